@@ -0,0 +1,237 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+    time::Duration,
+};
+
+use crate::error::Error;
+
+const DEFAULT_PING_INTERVAL: Duration = Duration::from_millis(1000);
+const DEFAULT_PROBE_INTERVAL: Duration = Duration::from_secs(2);
+const DEFAULT_REPLY_EXPIRY: Duration = Duration::from_secs(5);
+
+// Asked for when the name isn't part of the probe target
+pub const DEFAULT_DNS_QUERY_NAME: &str = "nymtech.net";
+
+// How the connection through the exit is checked against a target
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ProbeKind {
+    // Sent over the tunnel by the ICMP beacon
+    Icmp,
+    // The rest go through the operating system, which routes them through the tunnel
+    TcpConnect { port: u16 },
+    DnsQuery { port: u16, query_name: String },
+    HttpHead { port: u16, host: String },
+}
+
+// Something outside the exit gateway that we expect to answer us, if the exit routes our traffic
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ProbeTarget {
+    pub address: IpAddr,
+    pub kind: ProbeKind,
+}
+
+impl ProbeTarget {
+    pub fn icmp(address: impl Into<IpAddr>) -> Self {
+        ProbeTarget {
+            address: address.into(),
+            kind: ProbeKind::Icmp,
+        }
+    }
+
+    pub fn is_icmp(&self) -> bool {
+        self.kind == ProbeKind::Icmp
+    }
+}
+
+// Parsed from `icmp:IP`, `tcp:IP:PORT`, `dns:IP:PORT[/NAME]` or `http:IP:PORT[/HOST]`. IPv6
+// addresses with a port are written in brackets, as in `tcp:[2606:4700::1111]:443`.
+impl FromStr for ProbeTarget {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &'static str| Error::InvalidProbeTarget {
+            target: s.to_string(),
+            reason,
+        };
+        let (kind, rest) = s.split_once(':').ok_or(invalid("missing probe kind"))?;
+        if kind == "icmp" {
+            let address = rest.parse().map_err(|_| invalid("invalid ip address"))?;
+            return Ok(ProbeTarget::icmp(address));
+        }
+
+        let (socket_addr, name) = match rest.split_once('/') {
+            Some((socket_addr, name)) if !name.is_empty() => (socket_addr, Some(name.to_string())),
+            Some(_) => return Err(invalid("empty name after '/'")),
+            None => (rest, None),
+        };
+        let socket_addr: SocketAddr = socket_addr
+            .parse()
+            .map_err(|_| invalid("expected an ip address and a port"))?;
+        let port = socket_addr.port();
+        let kind = match (kind, name) {
+            ("tcp", None) => ProbeKind::TcpConnect { port },
+            ("tcp", Some(_)) => return Err(invalid("tcp probes don't take a name")),
+            ("dns", query_name) => ProbeKind::DnsQuery {
+                port,
+                query_name: query_name.unwrap_or_else(|| DEFAULT_DNS_QUERY_NAME.to_string()),
+            },
+            ("http", host) => ProbeKind::HttpHead {
+                port,
+                host: host.unwrap_or_else(|| socket_addr.ip().to_string()),
+            },
+            _ => return Err(invalid("unknown probe kind")),
+        };
+        Ok(ProbeTarget {
+            address: socket_addr.ip(),
+            kind,
+        })
+    }
+}
+
+impl fmt::Display for ProbeTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let socket_addr = |port| SocketAddr::new(self.address, port);
+        match &self.kind {
+            ProbeKind::Icmp => write!(f, "icmp:{}", self.address),
+            ProbeKind::TcpConnect { port } => write!(f, "tcp:{}", socket_addr(*port)),
+            ProbeKind::DnsQuery { port, query_name } => {
+                write!(f, "dns:{}/{query_name}", socket_addr(*port))
+            }
+            ProbeKind::HttpHead { port, host } => write!(f, "http:{}/{host}", socket_addr(*port)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConnectionMonitorConfig {
+    // The exit is considered to route our traffic as long as any of these answer
+    pub targets: Vec<ProbeTarget>,
+    // How often the ICMP targets, and the exit itself, are pinged
+    pub ping_interval: Duration,
+    // How often the other targets are probed
+    pub probe_interval: Duration,
+    // When the latest reply is older than this, the target is considered unreachable
+    pub reply_expiry: Duration,
+}
+
+impl ConnectionMonitorConfig {
+    // Each target has to be asked again before its previous reply expires, or the connection is
+    // reported down in between
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |reason| Err(Error::InvalidConfig { reason });
+        if self.ping_interval.is_zero() || self.probe_interval.is_zero() {
+            return invalid("the ping and probe intervals must be positive");
+        }
+        if self.ping_interval >= self.reply_expiry {
+            return invalid("the ping interval must be shorter than the reply expiry");
+        }
+        if self.probe_interval >= self.reply_expiry {
+            return invalid("the probe interval must be shorter than the reply expiry");
+        }
+        Ok(())
+    }
+
+    pub(crate) fn icmp_targets_v4(&self) -> Vec<Ipv4Addr> {
+        self.icmp_targets()
+            .filter_map(|address| match address {
+                IpAddr::V4(address) => Some(address),
+                IpAddr::V6(_) => None,
+            })
+            .collect()
+    }
+
+    pub(crate) fn icmp_targets_v6(&self) -> Vec<Ipv6Addr> {
+        self.icmp_targets()
+            .filter_map(|address| match address {
+                IpAddr::V4(_) => None,
+                IpAddr::V6(address) => Some(address),
+            })
+            .collect()
+    }
+
+    fn icmp_targets(&self) -> impl Iterator<Item = IpAddr> + '_ {
+        self.targets
+            .iter()
+            .filter(|target| target.is_icmp())
+            .map(|target| target.address)
+    }
+}
+
+impl Default for ConnectionMonitorConfig {
+    fn default() -> Self {
+        ConnectionMonitorConfig {
+            targets: vec![
+                ProbeTarget::icmp(Ipv4Addr::new(8, 8, 8, 8)),
+                ProbeTarget::icmp(Ipv4Addr::new(1, 1, 1, 1)),
+                ProbeTarget::icmp(Ipv6Addr::new(0x2001, 0x4860, 0x4860, 0, 0, 0, 0, 0x8888)),
+                ProbeTarget::icmp(Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1111)),
+            ],
+            ping_interval: DEFAULT_PING_INTERVAL,
+            probe_interval: DEFAULT_PROBE_INTERVAL,
+            reply_expiry: DEFAULT_REPLY_EXPIRY,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_probe_targets() {
+        for target in [
+            "icmp:8.8.8.8",
+            "icmp:2001:4860:4860::8888",
+            "tcp:1.1.1.1:443",
+            "tcp:[2606:4700::1111]:443",
+            "dns:9.9.9.9:53/example.com",
+            "http:1.1.1.1:80/one.one.one.one",
+        ] {
+            assert_eq!(target.parse::<ProbeTarget>().unwrap().to_string(), target);
+        }
+
+        assert_eq!(
+            "dns:9.9.9.9:53".parse::<ProbeTarget>().unwrap().kind,
+            ProbeKind::DnsQuery {
+                port: 53,
+                query_name: DEFAULT_DNS_QUERY_NAME.to_string(),
+            }
+        );
+        assert_eq!(
+            "http:1.1.1.1:80".parse::<ProbeTarget>().unwrap().kind,
+            ProbeKind::HttpHead {
+                port: 80,
+                host: "1.1.1.1".to_string(),
+            }
+        );
+
+        for target in [
+            "8.8.8.8",
+            "udp:8.8.8.8:53",
+            "tcp:1.1.1.1",
+            "tcp:1.1.1.1:443/x",
+        ] {
+            assert!(target.parse::<ProbeTarget>().is_err(), "{target}");
+        }
+    }
+
+    #[test]
+    fn targets_are_asked_again_before_their_replies_expire() {
+        let config = ConnectionMonitorConfig::default();
+        assert!(config.validate().is_ok());
+
+        for (ping_interval, probe_interval) in [(5, 2), (1, 5), (0, 2)] {
+            let config = ConnectionMonitorConfig {
+                ping_interval: Duration::from_secs(ping_interval),
+                probe_interval: Duration::from_secs(probe_interval),
+                ..ConnectionMonitorConfig::default()
+            };
+            assert!(config.validate().is_err());
+        }
+    }
+}
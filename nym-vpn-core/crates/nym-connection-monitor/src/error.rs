@@ -22,6 +22,18 @@ pub enum Error {
 
     #[error("failed to create ipv4 packet")]
     Ipv4PacketCreationFailure,
+
+    #[error("icmp socket error: {0}")]
+    IcmpSocketError(#[source] std::io::Error),
+
+    #[error("invalid connection monitor config: {reason}")]
+    InvalidConfig { reason: &'static str },
+
+    #[error("invalid probe target {target}: {reason}")]
+    InvalidProbeTarget {
+        target: String,
+        reason: &'static str,
+    },
 }
 
 // Result type based on our error type
@@ -7,6 +7,7 @@ use std::{
 };
 
 use bytes::Bytes;
+use futures::channel::mpsc;
 use nym_ip_packet_requests::{codec::MultiIpPacketCodec, request::IpPacketRequest, IpPair};
 use nym_sdk::{
     mixnet::{InputMessage, MixnetClientSender, MixnetMessageSender, Recipient},
@@ -14,51 +15,58 @@ use nym_sdk::{
 };
 use nym_task::connections::TransmissionLane;
 use pnet_packet::Packet;
-use tokio::task::JoinHandle;
+use tokio::{sync::watch, task::JoinHandle};
 use tracing::{debug, error, trace};
 
 use crate::{
+    config::ConnectionMonitorConfig,
     error::Result,
     packet_helpers::{
         create_icmpv4_echo_request, create_icmpv6_echo_request, is_icmp_echo_reply,
         is_icmp_v6_echo_reply, wrap_icmp_in_ipv4, wrap_icmp_in_ipv6,
     },
+    quality::PingId,
+    ConnectionStatusEvent,
 };
 
-const ICMP_BEACON_PING_INTERVAL: Duration = Duration::from_millis(1000);
-
 // TODO: extract these from the ip-packet-router crate
-const ICMP_IPR_TUN_IP_V4: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+pub const ICMP_IPR_TUN_IP_V4: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
 // 2001:db8:a160::1
-const ICMP_IPR_TUN_IP_V6: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0xa160, 0, 0, 0, 0, 0x1);
-
-// This can be anything really, we just want to check if the exit IPR can reach the internet
-// TODO: have a pool of IPs to ping
-const ICMP_IPR_TUN_EXTERNAL_PING_V4: Ipv4Addr = Ipv4Addr::new(8, 8, 8, 8);
-const ICMP_IPR_TUN_EXTERNAL_PING_V6: Ipv6Addr =
-    Ipv6Addr::new(0x2001, 0x4860, 0x4860, 0, 0, 0, 0, 0x8888);
+pub const ICMP_IPR_TUN_IP_V6: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0xa160, 0, 0, 0, 0, 0x1);
 
 struct IcmpConnectionBeacon {
     mixnet_client_sender: MixnetClientSender,
     our_ips: IpPair,
-    ipr_address: Recipient,
+    // Follows the exit we are connected to, which can change while the beacon is running
+    ipr_address: watch::Receiver<Recipient>,
+    connection_event_tx: mpsc::UnboundedSender<ConnectionStatusEvent>,
     sequence_number: u16,
     icmp_identifier: u16,
+    // Pinged to check that the exit IPR can reach the internet
+    external_targets_v4: Vec<Ipv4Addr>,
+    external_targets_v6: Vec<Ipv6Addr>,
+    ping_interval: Duration,
 }
 
 impl IcmpConnectionBeacon {
     fn new(
         mixnet_client_sender: MixnetClientSender,
         our_ips: IpPair,
-        ipr_address: Recipient,
+        ipr_address: watch::Receiver<Recipient>,
+        connection_event_tx: mpsc::UnboundedSender<ConnectionStatusEvent>,
         icmp_identifier: u16,
+        config: &ConnectionMonitorConfig,
     ) -> Self {
         IcmpConnectionBeacon {
             mixnet_client_sender,
             our_ips,
             ipr_address,
+            connection_event_tx,
             sequence_number: 0,
             icmp_identifier,
+            external_targets_v4: config.icmp_targets_v4(),
+            external_targets_v6: config.icmp_targets_v6(),
+            ping_interval: config.ping_interval,
         }
     }
 
@@ -68,7 +76,7 @@ impl IcmpConnectionBeacon {
         sequence_number
     }
 
-    async fn send_icmp_v4_ping(&mut self, destination: Ipv4Addr) -> Result<()> {
+    async fn send_icmp_v4_ping(&mut self, destination: Ipv4Addr) -> Result<u16> {
         // Create ICMP/IPv4 echo request packet
         let sequence_number = self.get_next_sequence_number();
         let identifier = self.icmp_identifier;
@@ -80,16 +88,14 @@ impl IcmpConnectionBeacon {
             MultiIpPacketCodec::bundle_one_packet(ipv4_packet.packet().to_vec().into());
 
         // Wrap into a mixnet input message addressed to the IPR
-        let mixnet_message = create_input_message(self.ipr_address, bundled_packet)?;
+        let mixnet_message = create_input_message(*self.ipr_address.borrow(), bundled_packet)?;
 
         // Send across the mixnet
-        self.mixnet_client_sender
-            .send(mixnet_message)
-            .await
-            .map_err(|err| err.into())
+        self.mixnet_client_sender.send(mixnet_message).await?;
+        Ok(sequence_number)
     }
 
-    async fn send_icmp_v6_ping(&mut self, destination: Ipv6Addr) -> Result<()> {
+    async fn send_icmp_v6_ping(&mut self, destination: Ipv6Addr) -> Result<u16> {
         // Create ICMP/IPv6 echo request packet
         let sequence_number = self.get_next_sequence_number();
         let identifier = self.icmp_identifier;
@@ -106,36 +112,50 @@ impl IcmpConnectionBeacon {
             MultiIpPacketCodec::bundle_one_packet(ipv6_packet.packet().to_vec().into());
 
         // Wrap into a mixnet input message addressed to the IPR
-        let mixnet_message = create_input_message(self.ipr_address, bundled_packet)?;
+        let mixnet_message = create_input_message(*self.ipr_address.borrow(), bundled_packet)?;
 
         // Send across the mixnet
-        self.mixnet_client_sender
-            .send(mixnet_message)
-            .await
-            .map_err(|err| err.into())
+        self.mixnet_client_sender.send(mixnet_message).await?;
+        Ok(sequence_number)
+    }
+
+    // The round trip to the IPR is timed by the connection monitor
+    fn report_ping_sent(&self, sequence_number: u16) {
+        let event = ConnectionStatusEvent::PingSent(PingId::IcmpBeacon(sequence_number));
+        if self.connection_event_tx.unbounded_send(event).is_err() {
+            error!("Failed to send connection event to connection monitor");
+        }
     }
 
     async fn ping_v4_ipr_tun_device_over_the_mixnet(&mut self) -> Result<()> {
-        self.send_icmp_v4_ping(ICMP_IPR_TUN_IP_V4).await
+        let sequence_number = self.send_icmp_v4_ping(ICMP_IPR_TUN_IP_V4).await?;
+        self.report_ping_sent(sequence_number);
+        Ok(())
     }
 
     async fn ping_v6_ipr_tun_device_over_the_mixnet(&mut self) -> Result<()> {
-        self.send_icmp_v6_ping(ICMP_IPR_TUN_IP_V6).await
+        let sequence_number = self.send_icmp_v6_ping(ICMP_IPR_TUN_IP_V6).await?;
+        self.report_ping_sent(sequence_number);
+        Ok(())
     }
 
-    async fn ping_v4_some_external_ip_over_the_mixnet(&mut self) -> Result<()> {
-        // TODO: ramdon external IP from a pool
-        self.send_icmp_v4_ping(ICMP_IPR_TUN_EXTERNAL_PING_V4).await
+    async fn ping_v4_external_ips_over_the_mixnet(&mut self) -> Result<()> {
+        for destination in self.external_targets_v4.clone() {
+            self.send_icmp_v4_ping(destination).await?;
+        }
+        Ok(())
     }
 
-    async fn ping_v6_some_external_ip_over_the_mixnet(&mut self) -> Result<()> {
-        // TODO: ramdon external IP from a pool
-        self.send_icmp_v6_ping(ICMP_IPR_TUN_EXTERNAL_PING_V6).await
+    async fn ping_v6_external_ips_over_the_mixnet(&mut self) -> Result<()> {
+        for destination in self.external_targets_v6.clone() {
+            self.send_icmp_v6_ping(destination).await?;
+        }
+        Ok(())
     }
 
     pub async fn run(mut self, mut shutdown: TaskClient) -> Result<()> {
         debug!("Icmp connection beacon is running");
-        let mut ping_interval = tokio::time::interval(ICMP_BEACON_PING_INTERVAL);
+        let mut ping_interval = tokio::time::interval(self.ping_interval);
         loop {
             tokio::select! {
                 _ = shutdown.recv() => {
@@ -149,10 +169,10 @@ impl IcmpConnectionBeacon {
                     if let Err(err) = self.ping_v6_ipr_tun_device_over_the_mixnet().await {
                         error!("Failed to send ICMPv6 ping: {err}");
                     }
-                    if let Err(err) = self.ping_v4_some_external_ip_over_the_mixnet().await {
+                    if let Err(err) = self.ping_v4_external_ips_over_the_mixnet().await {
                         error!("Failed to send ICMP ping: {err}");
                     }
-                    if let Err(err) = self.ping_v6_some_external_ip_over_the_mixnet().await {
+                    if let Err(err) = self.ping_v6_external_ips_over_the_mixnet().await {
                         error!("Failed to send ICMPv6 ping: {err}");
                     }
                 }
@@ -177,12 +197,12 @@ fn create_input_message(recipient: Recipient, bundled_packets: Bytes) -> Result<
 }
 
 pub enum IcmpBeaconReply {
-    TunDeviceReply,
+    TunDeviceReply { sequence_number: u16 },
     ExternalPingReply(Ipv4Addr),
 }
 
 pub enum Icmpv6BeaconReply {
-    TunDeviceReply,
+    TunDeviceReply { sequence_number: u16 },
     ExternalPingReply(Ipv6Addr),
 }
 
@@ -191,11 +211,15 @@ pub fn is_icmp_beacon_reply(
     identifier: u16,
     destination: Ipv4Addr,
 ) -> Option<IcmpBeaconReply> {
-    if let Some((reply_identifier, reply_source, reply_destination)) = is_icmp_echo_reply(packet) {
+    if let Some((reply_identifier, sequence_number, reply_source, reply_destination)) =
+        is_icmp_echo_reply(packet)
+    {
         if reply_identifier == identifier && reply_destination == destination {
+            // Anything but the IPR is an external reply, which the connection monitor only counts
+            // when it's from one of the probe targets
             if reply_source == ICMP_IPR_TUN_IP_V4 {
-                return Some(IcmpBeaconReply::TunDeviceReply);
-            } else if reply_source == ICMP_IPR_TUN_EXTERNAL_PING_V4 {
+                return Some(IcmpBeaconReply::TunDeviceReply { sequence_number });
+            } else {
                 return Some(IcmpBeaconReply::ExternalPingReply(reply_source));
             }
         }
@@ -208,12 +232,13 @@ pub fn is_icmp_v6_beacon_reply(
     identifier: u16,
     destination: Ipv6Addr,
 ) -> Option<Icmpv6BeaconReply> {
-    if let Some((reply_identifier, reply_source, reply_destination)) = is_icmp_v6_echo_reply(packet)
+    if let Some((reply_identifier, sequence_number, reply_source, reply_destination)) =
+        is_icmp_v6_echo_reply(packet)
     {
         if reply_identifier == identifier && reply_destination == destination {
             if reply_source == ICMP_IPR_TUN_IP_V6 {
-                return Some(Icmpv6BeaconReply::TunDeviceReply);
-            } else if reply_source == ICMP_IPR_TUN_EXTERNAL_PING_V6 {
+                return Some(Icmpv6BeaconReply::TunDeviceReply { sequence_number });
+            } else {
                 return Some(Icmpv6BeaconReply::ExternalPingReply(reply_source));
             }
         }
@@ -224,13 +249,21 @@ pub fn is_icmp_v6_beacon_reply(
 pub fn start_icmp_connection_beacon(
     mixnet_client_sender: MixnetClientSender,
     our_ips: IpPair,
-    ipr_address: Recipient,
+    ipr_address: watch::Receiver<Recipient>,
+    connection_event_tx: mpsc::UnboundedSender<ConnectionStatusEvent>,
     icmp_identifier: u16,
+    config: &ConnectionMonitorConfig,
     shutdown_listener: TaskClient,
 ) -> JoinHandle<Result<()>> {
     debug!("Creating icmp connection beacon");
-    let beacon =
-        IcmpConnectionBeacon::new(mixnet_client_sender, our_ips, ipr_address, icmp_identifier);
+    let beacon = IcmpConnectionBeacon::new(
+        mixnet_client_sender,
+        our_ips,
+        ipr_address,
+        connection_event_tx,
+        icmp_identifier,
+        config,
+    );
     tokio::spawn(async move {
         beacon.run(shutdown_listener).await.inspect_err(|err| {
             error!("Icmp connection beacon error: {err}");
@@ -5,21 +5,30 @@ use futures::channel::mpsc;
 use nym_ip_packet_requests::IpPair;
 use nym_sdk::mixnet::{MixnetClientSender, Recipient};
 use nym_task::TaskManager;
+use tokio::sync::watch;
 use tracing::info;
 
+mod config;
 mod error;
 mod icmp_beacon;
 mod mixnet_beacon;
 mod monitor;
+mod probe;
+mod quality;
 mod sync_self_ping;
+mod wireguard_beacon;
 
 pub mod packet_helpers;
+pub use config::{ConnectionMonitorConfig, ProbeKind, ProbeTarget, DEFAULT_DNS_QUERY_NAME};
 pub use error::Error;
 pub use icmp_beacon::{
     is_icmp_beacon_reply, is_icmp_v6_beacon_reply, IcmpBeaconReply, Icmpv6BeaconReply,
+    ICMP_IPR_TUN_IP_V4, ICMP_IPR_TUN_IP_V6,
 };
 pub use monitor::{ConnectionMonitorStatus, ConnectionStatusEvent};
-pub use sync_self_ping::self_ping_and_wait;
+pub use quality::{ConnectionQuality, PingId, PingStats};
+pub use sync_self_ping::{self_ping_and_wait, self_ping_round_trips};
+pub use wireguard_beacon::WireguardExit;
 
 fn create_icmp_beacon_identifier() -> u16 {
     // TODO: use something that is more unique than just process id
@@ -30,16 +39,18 @@ fn create_icmp_beacon_identifier() -> u16 {
 // as well as provides the channel to send connection status events read from the mixnet, to the
 // monitor.
 pub struct ConnectionMonitorTask {
+    config: ConnectionMonitorConfig,
     icmp_beacon_identifier: u16,
     connection_event_tx: mpsc::UnboundedSender<monitor::ConnectionStatusEvent>,
     connection_event_rx: mpsc::UnboundedReceiver<monitor::ConnectionStatusEvent>,
 }
 
 impl ConnectionMonitorTask {
-    pub fn setup() -> ConnectionMonitorTask {
+    pub fn setup(config: ConnectionMonitorConfig) -> ConnectionMonitorTask {
         let (connection_event_tx, connection_event_rx) = mpsc::unbounded();
         let icmp_beacon_identifier = create_icmp_beacon_identifier();
         ConnectionMonitorTask {
+            config,
             icmp_beacon_identifier,
             connection_event_tx,
             connection_event_rx,
@@ -59,13 +70,14 @@ impl ConnectionMonitorTask {
         mixnet_client_sender: MixnetClientSender,
         our_nym_address: Recipient,
         our_ips: IpPair,
-        exit_router_address: Recipient,
+        exit_router_address: watch::Receiver<Recipient>,
         task_manager: &TaskManager,
     ) {
         info!("Setting up mixnet connection beacon");
         mixnet_beacon::start_mixnet_connection_beacon(
             mixnet_client_sender.clone(),
             our_nym_address,
+            self.connection_event_tx.clone(),
             task_manager.subscribe_named("mixnet_beacon"),
         );
 
@@ -74,13 +86,60 @@ impl ConnectionMonitorTask {
             mixnet_client_sender,
             our_ips,
             exit_router_address,
+            self.connection_event_tx.clone(),
             self.icmp_beacon_identifier,
+            &self.config,
             task_manager.subscribe_named("icmp_beacon"),
         );
 
+        self.start_prober_and_monitor(true, task_manager);
+    }
+
+    // With wireguard there is no mixnet to ping ourselves through. Instead the pings go through
+    // the exit tunnel, and whoever owns the tunnels reports `EntryTunnelAlive` on the event sender.
+    pub fn start_wireguard(self, exit: watch::Receiver<WireguardExit>, task_manager: &TaskManager) {
+        info!("Setting up wireguard ICMP connection beacon");
+        wireguard_beacon::start_wireguard_icmp_beacon(
+            exit,
+            self.connection_event_tx.clone(),
+            self.icmp_beacon_identifier,
+            &self.config,
+            task_manager.subscribe_named("wireguard_icmp_beacon"),
+        );
+
+        // Ipv6 is routed into the tunnels too, so report whether it makes it through
+        self.start_prober_and_monitor(true, task_manager);
+    }
+
+    fn start_prober_and_monitor(self, monitor_ipv6: bool, task_manager: &TaskManager) {
+        let targets: Vec<_> = self
+            .config
+            .targets
+            .into_iter()
+            .filter(|target| monitor_ipv6 || target.address.is_ipv4())
+            .collect();
+        // The ICMP targets are pinged by the beacons
+        let probe_targets: Vec<_> = targets
+            .iter()
+            .filter(|target| !target.is_icmp())
+            .cloned()
+            .collect();
+        if !probe_targets.is_empty() {
+            info!("Setting up connection prober");
+            probe::start_connection_prober(
+                probe_targets,
+                self.config.probe_interval,
+                self.connection_event_tx,
+                task_manager.subscribe_named("connection_prober"),
+            );
+        }
+
         info!("Setting up connection monitor");
         monitor::start_connection_monitor(
             self.connection_event_rx,
+            targets,
+            self.config.reply_expiry,
+            monitor_ipv6,
             task_manager.subscribe_named("connection_monitor"),
         );
     }
@@ -3,6 +3,7 @@
 
 use std::time::Duration;
 
+use futures::channel::mpsc;
 use nym_ip_packet_requests::request::IpPacketRequest;
 use nym_sdk::{
     mixnet::{InputMessage, MixnetClientSender, MixnetMessageSender, Recipient},
@@ -12,20 +13,26 @@ use nym_task::connections::TransmissionLane;
 use tokio::task::JoinHandle;
 use tracing::{debug, error, trace};
 
-use crate::error::Result;
+use crate::{error::Result, quality::PingId, ConnectionStatusEvent};
 
 const MIXNET_SELF_PING_INTERVAL: Duration = Duration::from_millis(1000);
 
 struct MixnetConnectionBeacon {
     mixnet_client_sender: MixnetClientSender,
     our_address: Recipient,
+    connection_event_tx: mpsc::UnboundedSender<ConnectionStatusEvent>,
 }
 
 impl MixnetConnectionBeacon {
-    fn new(mixnet_client_sender: MixnetClientSender, our_address: Recipient) -> Self {
+    fn new(
+        mixnet_client_sender: MixnetClientSender,
+        our_address: Recipient,
+        connection_event_tx: mpsc::UnboundedSender<ConnectionStatusEvent>,
+    ) -> Self {
         MixnetConnectionBeacon {
             mixnet_client_sender,
             our_address,
+            connection_event_tx,
         }
     }
 
@@ -46,14 +53,18 @@ impl MixnetConnectionBeacon {
                     break;
                 }
                 _ = ping_interval.tick() => {
-                    let _ping_id = match self.send_mixnet_self_ping().await {
+                    let ping_id = match self.send_mixnet_self_ping().await {
                         Ok(id) => id,
                         Err(err) => {
                             error!("Failed to send mixnet self ping: {err}");
                             continue;
                         }
                     };
-                    // TODO: store ping_id to be able to monitor or ping timeouts
+                    // The connection monitor times the round trip from here
+                    let event = ConnectionStatusEvent::PingSent(PingId::MixnetSelfPing(ping_id));
+                    if self.connection_event_tx.unbounded_send(event).is_err() {
+                        error!("Failed to send connection event to connection monitor");
+                    }
                 }
             }
         }
@@ -78,10 +89,12 @@ pub fn create_self_ping(our_address: Recipient) -> (InputMessage, u64) {
 pub fn start_mixnet_connection_beacon(
     mixnet_client_sender: MixnetClientSender,
     our_address: Recipient,
+    connection_event_tx: mpsc::UnboundedSender<ConnectionStatusEvent>,
     shutdown_listener: TaskClient,
 ) -> JoinHandle<Result<()>> {
     debug!("Creating mixnet connection beacon");
-    let beacon = MixnetConnectionBeacon::new(mixnet_client_sender, our_address);
+    let beacon =
+        MixnetConnectionBeacon::new(mixnet_client_sender, our_address, connection_event_tx);
     tokio::spawn(async move {
         beacon.run(shutdown_listener).await.inspect_err(|err| {
             error!("Mixnet connection beacon error: {err}");
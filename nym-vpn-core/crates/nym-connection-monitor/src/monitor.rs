@@ -1,49 +1,99 @@
 // Copyright 2023 - Nym Technologies SA <contact@nymtech.net>
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::time::{Duration, Instant};
+use std::{
+    collections::{HashMap, HashSet},
+    net::{Ipv4Addr, Ipv6Addr},
+    time::{Duration, Instant},
+};
 
 use futures::{channel::mpsc, StreamExt};
 use nym_sdk::TaskClient;
 use tokio::task::JoinHandle;
 use tracing::{debug, error, trace};
 
-use crate::error::Result;
+use crate::{
+    config::ProbeTarget,
+    error::Result,
+    quality::{ConnectionQuality, PingId, QualityTracker},
+};
 
 const CONNECTION_MONITOR_REPORT_INTERVAL: Duration = Duration::from_secs(5);
 
-// When the latest successful ping is older than this, we consider the connection to be down
-const PING_REPLY_EXPIRY: Duration = Duration::from_secs(5);
-
 // Events that are reported by other tasks to the connection monitor
 #[derive(Debug)]
 pub enum ConnectionStatusEvent {
-    MixnetSelfPing,
-    Icmpv4IprTunDevicePingReply,
-    Icmpv6IprTunDevicePingReply,
-    Icmpv4IprExternalPingReply,
-    Icmpv6IprExternalPingReply,
+    MixnetSelfPing { request_id: Option<u64> },
+    // With wireguard, the tunnels up to the exit gateway are passing traffic
+    EntryTunnelAlive,
+    Icmpv4IprTunDevicePingReply { sequence_number: u16 },
+    Icmpv6IprTunDevicePingReply { sequence_number: u16 },
+    // From one of the ICMP probe targets
+    Icmpv4IprExternalPingReply(Ipv4Addr),
+    Icmpv6IprExternalPingReply(Ipv6Addr),
+    // One of the other probe targets answered
+    ProbeReply(ProbeTarget),
+    // A ping was sent that we measure the round trip time of
+    PingSent(PingId),
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 struct ConnectionStats {
     // TODO: extend with all sorts of good stuff
-    latest_self_ping: Option<Instant>,
+    // Either a mixnet self ping, or the wireguard tunnels up to the exit being alive
+    latest_entry_alive: Option<Instant>,
     latest_ipr_tun_device_ping_v4_reply: Option<Instant>,
     latest_ipr_tun_device_ping_v6_reply: Option<Instant>,
-    latest_ipr_external_ping_v4_reply: Option<Instant>,
-    latest_ipr_external_ping_v6_reply: Option<Instant>,
+    latest_probe_replies: HashMap<ProbeTarget, Instant>,
+    // Replies from anywhere else don't count, even when they carry our ICMP identifier
+    targets: HashSet<ProbeTarget>,
+    // When the latest reply is older than this, we consider the connection to be down
+    reply_expiry: Duration,
 }
 
 impl ConnectionStats {
+    fn new(targets: Vec<ProbeTarget>, reply_expiry: Duration) -> Self {
+        ConnectionStats {
+            latest_entry_alive: None,
+            latest_ipr_tun_device_ping_v4_reply: None,
+            latest_ipr_tun_device_ping_v6_reply: None,
+            latest_probe_replies: HashMap::new(),
+            targets: targets.into_iter().collect(),
+            reply_expiry,
+        }
+    }
+
+    fn status(&self, reply: Option<Instant>) -> ConnectivityStatus {
+        match reply {
+            Some(when) if when.elapsed() < self.reply_expiry => ConnectivityStatus::Ok,
+            Some(_) => ConnectivityStatus::Fail,
+            None => ConnectivityStatus::Fail,
+        }
+    }
+
+    // A single target can be blocked or down, so the exit is routing our traffic as long as any
+    // of them answers
+    fn probe_status(&self, is_ipv4: bool) -> ConnectivityStatus {
+        let any_reply = self
+            .latest_probe_replies
+            .iter()
+            .filter(|(target, _)| target.address.is_ipv4() == is_ipv4)
+            .any(|(_, when)| self.status(Some(*when)) == ConnectivityStatus::Ok);
+        if any_reply {
+            ConnectivityStatus::Ok
+        } else {
+            ConnectivityStatus::Fail
+        }
+    }
+
     fn evaluate_connectivity(&self) -> ConnectivityState {
-        let entry = ConnectivityStatus::from(&self.latest_self_ping);
+        let entry = self.status(self.latest_entry_alive);
 
-        let exit_ipv4 = ConnectivityStatus::from(&self.latest_ipr_tun_device_ping_v4_reply);
-        let exit_ipv6 = ConnectivityStatus::from(&self.latest_ipr_tun_device_ping_v6_reply);
+        let exit_ipv4 = self.status(self.latest_ipr_tun_device_ping_v4_reply);
+        let exit_ipv6 = self.status(self.latest_ipr_tun_device_ping_v6_reply);
 
-        let exit_routing_ipv4 = ConnectivityStatus::from(&self.latest_ipr_external_ping_v4_reply);
-        let exit_routing_ipv6 = ConnectivityStatus::from(&self.latest_ipr_external_ping_v6_reply);
+        let exit_routing_ipv4 = self.probe_status(true);
+        let exit_routing_ipv6 = self.probe_status(false);
 
         ConnectivityState {
             entry,
@@ -60,8 +110,8 @@ impl ConnectionStats {
 
     fn log_status(&self) {
         debug!(
-            "Time since latest received self ping: {}ms",
-            self.latest_self_ping
+            "Time since the entry was last seen alive: {}ms",
+            self.latest_entry_alive
                 .map(|t| t.elapsed().as_millis())
                 .unwrap_or(0)
         );
@@ -77,24 +127,22 @@ impl ConnectionStats {
                 .map(|t| t.elapsed().as_millis())
                 .unwrap_or(0)
         );
-        debug!(
-            "Time since latest received ipr external ping v4 reply: {}ms",
-            self.latest_ipr_external_ping_v4_reply
-                .map(|t| t.elapsed().as_millis())
-                .unwrap_or(0)
-        );
-        debug!(
-            "Time since latest received ipr external ping v6 reply: {}ms",
-            self.latest_ipr_external_ping_v6_reply
-                .map(|t| t.elapsed().as_millis())
-                .unwrap_or(0)
-        );
+        for (target, when) in &self.latest_probe_replies {
+            debug!(
+                "Time since latest received reply from {target}: {}ms",
+                when.elapsed().as_millis()
+            );
+        }
     }
 }
 
 struct ConnectionMonitor {
     connection_event_rx: mpsc::UnboundedReceiver<ConnectionStatusEvent>,
     stats: ConnectionStats,
+    mixnet_quality: QualityTracker,
+    exit_quality: QualityTracker,
+    // Without ipv6 in the tunnel there is nothing to report about it
+    monitor_ipv6: bool,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -103,16 +151,6 @@ enum ConnectivityStatus {
     Fail,
 }
 
-impl From<&Option<Instant>> for ConnectivityStatus {
-    fn from(reply: &Option<Instant>) -> Self {
-        match reply {
-            Some(when) if when.elapsed() < PING_REPLY_EXPIRY => ConnectivityStatus::Ok,
-            Some(_) => ConnectivityStatus::Fail,
-            None => ConnectivityStatus::Fail,
-        }
-    }
-}
-
 struct IpConnectivity {
     ipv4: ConnectivityStatus,
     ipv6: ConnectivityStatus,
@@ -125,38 +163,90 @@ struct ConnectivityState {
 }
 
 impl ConnectionMonitor {
-    fn new(connection_event_rx: mpsc::UnboundedReceiver<ConnectionStatusEvent>) -> Self {
+    fn new(
+        connection_event_rx: mpsc::UnboundedReceiver<ConnectionStatusEvent>,
+        targets: Vec<ProbeTarget>,
+        reply_expiry: Duration,
+        monitor_ipv6: bool,
+    ) -> Self {
         ConnectionMonitor {
             connection_event_rx,
-            stats: ConnectionStats::default(),
+            stats: ConnectionStats::new(targets, reply_expiry),
+            mixnet_quality: QualityTracker::default(),
+            exit_quality: QualityTracker::default(),
+            monitor_ipv6,
         }
     }
 
     fn record_event(&mut self, event: &ConnectionStatusEvent) {
         match event {
-            ConnectionStatusEvent::MixnetSelfPing => {
+            ConnectionStatusEvent::MixnetSelfPing { request_id } => {
                 trace!("Received self ping event");
-                self.stats.latest_self_ping = Some(Instant::now());
+                self.stats.latest_entry_alive = Some(Instant::now());
+                if let Some(request_id) = request_id {
+                    self.mixnet_quality
+                        .ping_reply(PingId::MixnetSelfPing(*request_id), Instant::now());
+                }
+            }
+            ConnectionStatusEvent::EntryTunnelAlive => {
+                trace!("Received entry tunnel alive event");
+                self.stats.latest_entry_alive = Some(Instant::now());
             }
-            ConnectionStatusEvent::Icmpv4IprTunDevicePingReply => {
+            ConnectionStatusEvent::Icmpv4IprTunDevicePingReply { sequence_number } => {
                 trace!("Received IPR tun device ping reply event");
                 self.stats.latest_ipr_tun_device_ping_v4_reply = Some(Instant::now());
+                self.exit_quality
+                    .ping_reply(PingId::IcmpBeacon(*sequence_number), Instant::now());
             }
-            ConnectionStatusEvent::Icmpv6IprTunDevicePingReply => {
+            ConnectionStatusEvent::Icmpv6IprTunDevicePingReply { sequence_number } => {
                 trace!("Received IPR tun device ping v6 reply event");
                 self.stats.latest_ipr_tun_device_ping_v6_reply = Some(Instant::now());
+                self.exit_quality
+                    .ping_reply(PingId::IcmpBeacon(*sequence_number), Instant::now());
             }
-            ConnectionStatusEvent::Icmpv4IprExternalPingReply => {
-                trace!("Received IPR external ping reply event");
-                self.stats.latest_ipr_external_ping_v4_reply = Some(Instant::now());
+            ConnectionStatusEvent::Icmpv4IprExternalPingReply(source) => {
+                trace!("Received IPR external ping reply event from {source}");
+                self.record_probe_reply(ProbeTarget::icmp(*source));
             }
-            ConnectionStatusEvent::Icmpv6IprExternalPingReply => {
-                trace!("Received IPR external ping v6 reply event");
-                self.stats.latest_ipr_external_ping_v6_reply = Some(Instant::now());
+            ConnectionStatusEvent::Icmpv6IprExternalPingReply(source) => {
+                trace!("Received IPR external ping v6 reply event from {source}");
+                self.record_probe_reply(ProbeTarget::icmp(*source));
+            }
+            ConnectionStatusEvent::ProbeReply(target) => {
+                trace!("Received probe reply event from {target}");
+                self.record_probe_reply(target.clone());
+            }
+            ConnectionStatusEvent::PingSent(id) => {
+                trace!("Received ping sent event for {id:?}");
+                match id {
+                    PingId::MixnetSelfPing(_) => self.mixnet_quality.ping_sent(*id, Instant::now()),
+                    PingId::IcmpBeacon(_) => self.exit_quality.ping_sent(*id, Instant::now()),
+                }
             }
         }
     }
 
+    // Only the pings to our own gateway and to the exit are measured, since the external targets
+    // can be slow or blocked without it saying anything about the tunnel
+    fn evaluate_quality(&mut self) -> Option<ConnectionQuality> {
+        let now = Instant::now();
+        let quality = ConnectionQuality {
+            mixnet: self.mixnet_quality.stats(now),
+            exit: self.exit_quality.stats(now),
+        };
+        (quality.mixnet.is_some() || quality.exit.is_some()).then_some(quality)
+    }
+
+    fn record_probe_reply(&mut self, target: ProbeTarget) {
+        if !self.stats.targets.contains(&target) {
+            trace!("Ignoring reply from {target}, which is not one of our probe targets");
+            return;
+        }
+        self.stats
+            .latest_probe_replies
+            .insert(target, Instant::now());
+    }
+
     async fn run(mut self, mut task_client: TaskClient) -> Result<()> {
         debug!("Connection monitor is running");
         let mut report_interval = tokio::time::interval(CONNECTION_MONITOR_REPORT_INTERVAL);
@@ -176,7 +266,13 @@ impl ConnectionMonitor {
                 _ = report_interval.tick() => {
                     self.stats.log_status();
                     let connectivity = self.stats.evaluate_connectivity();
-                    report_connectivity(&connectivity, &mut task_client);
+                    report_connectivity(&connectivity, self.monitor_ipv6, &mut task_client);
+                    if let Some(quality) = self.evaluate_quality() {
+                        debug!("ConnectionMonitor: {quality}");
+                        task_client.send_status_msg(Box::new(
+                            ConnectionMonitorStatus::ConnectionQuality(quality),
+                        ));
+                    }
                 }
             }
         }
@@ -185,9 +281,13 @@ impl ConnectionMonitor {
     }
 }
 
-fn report_connectivity(connectivity: &ConnectivityState, task_client: &mut TaskClient) {
+fn report_connectivity(
+    connectivity: &ConnectivityState,
+    monitor_ipv6: bool,
+    task_client: &mut TaskClient,
+) {
     if connectivity.entry == ConnectivityStatus::Fail {
-        error!("Entry gateway not routing our mixnet traffic");
+        error!("Entry gateway not routing our traffic");
         task_client.send_status_msg(Box::new(ConnectionMonitorStatus::EntryGatewayDown));
         return;
     }
@@ -208,6 +308,10 @@ fn report_connectivity(connectivity: &ConnectivityState, task_client: &mut TaskC
         error!("Unexpected connectivity state - exit gateway ipv4 connectivity is ok, but routing is not?");
     }
 
+    if !monitor_ipv6 {
+        return;
+    }
+
     if connectivity.exit_routing.ipv6 == ConnectivityStatus::Ok {
         debug!("ConnectionMonitor: connection success over ipv6");
         task_client.send_status_msg(Box::new(ConnectionMonitorStatus::ConnectedIpv6));
@@ -230,7 +334,7 @@ fn report_connectivity(connectivity: &ConnectivityState, task_client: &mut TaskC
 // to create a separate trait in nym_task::TaskManager
 #[derive(Clone, thiserror::Error, Debug)]
 pub enum ConnectionMonitorStatus {
-    #[error("entry gateway appears down - it's not routing our mixnet traffic")]
+    #[error("entry gateway appears down - it's not routing our traffic")]
     EntryGatewayDown,
 
     #[error("exit gateway (or ipr) appears down - it's not responding to IPv4 traffic")]
@@ -250,17 +354,68 @@ pub enum ConnectionMonitorStatus {
 
     #[error("connected with ipv6")]
     ConnectedIpv6,
+
+    #[error("{0}")]
+    ConnectionQuality(ConnectionQuality),
 }
 
 pub fn start_connection_monitor(
     connection_event_rx: futures::channel::mpsc::UnboundedReceiver<ConnectionStatusEvent>,
+    targets: Vec<ProbeTarget>,
+    reply_expiry: Duration,
+    monitor_ipv6: bool,
     shutdown_listener: TaskClient,
 ) -> JoinHandle<Result<()>> {
     debug!("Creating connection monitor");
-    let monitor = ConnectionMonitor::new(connection_event_rx);
+    let monitor = ConnectionMonitor::new(connection_event_rx, targets, reply_expiry, monitor_ipv6);
     tokio::spawn(async move {
         monitor.run(shutdown_listener).await.inspect_err(|err| {
             error!("Connection monitor error: {err}");
         })
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_blocked_target_is_not_a_routing_error() {
+        let blocked = ProbeTarget::icmp(Ipv4Addr::new(8, 8, 8, 8));
+        let answering: ProbeTarget = "tcp:1.1.1.1:443".parse().unwrap();
+        let mut stats = ConnectionStats::new(
+            vec![blocked.clone(), answering.clone()],
+            Duration::from_secs(5),
+        );
+        assert_eq!(stats.probe_status(true), ConnectivityStatus::Fail);
+
+        stats
+            .latest_probe_replies
+            .insert(blocked, Instant::now() - Duration::from_secs(60));
+        assert_eq!(stats.probe_status(true), ConnectivityStatus::Fail);
+
+        stats.latest_probe_replies.insert(answering, Instant::now());
+        assert_eq!(stats.probe_status(true), ConnectivityStatus::Ok);
+        assert_eq!(stats.probe_status(false), ConnectivityStatus::Fail);
+    }
+
+    #[test]
+    fn only_replies_from_our_targets_count() {
+        let (_connection_event_tx, connection_event_rx) = mpsc::unbounded();
+        let target = ProbeTarget::icmp(Ipv4Addr::new(8, 8, 8, 8));
+        let mut monitor = ConnectionMonitor::new(
+            connection_event_rx,
+            vec![target],
+            Duration::from_secs(5),
+            false,
+        );
+
+        let stranger = Ipv4Addr::new(192, 0, 2, 1);
+        monitor.record_event(&ConnectionStatusEvent::Icmpv4IprExternalPingReply(stranger));
+        assert_eq!(monitor.stats.probe_status(true), ConnectivityStatus::Fail);
+
+        let target = Ipv4Addr::new(8, 8, 8, 8);
+        monitor.record_event(&ConnectionStatusEvent::Icmpv4IprExternalPingReply(target));
+        assert_eq!(monitor.stats.probe_status(true), ConnectivityStatus::Ok);
+    }
+}
@@ -136,12 +136,14 @@ pub(crate) fn compute_ipv4_checksum(header: &Ipv4Packet) -> u16 {
     !sum as u16
 }
 
-pub(crate) fn is_icmp_echo_reply(packet: &Bytes) -> Option<(u16, Ipv4Addr, Ipv4Addr)> {
+// Returns the identifier, sequence number, source and destination of an ICMP echo reply
+pub(crate) fn is_icmp_echo_reply(packet: &Bytes) -> Option<(u16, u16, Ipv4Addr, Ipv4Addr)> {
     if let Some(ipv4_packet) = Ipv4Packet::new(packet) {
         if let Some(icmp_packet) = IcmpPacket::new(ipv4_packet.payload()) {
             if let Some(echo_reply) = EchoReplyPacket::new(icmp_packet.packet()) {
                 return Some((
                     echo_reply.get_identifier(),
+                    echo_reply.get_sequence_number(),
                     ipv4_packet.get_source(),
                     ipv4_packet.get_destination(),
                 ));
@@ -151,7 +153,7 @@ pub(crate) fn is_icmp_echo_reply(packet: &Bytes) -> Option<(u16, Ipv4Addr, Ipv4A
     None
 }
 
-pub(crate) fn is_icmp_v6_echo_reply(packet: &Bytes) -> Option<(u16, Ipv6Addr, Ipv6Addr)> {
+pub(crate) fn is_icmp_v6_echo_reply(packet: &Bytes) -> Option<(u16, u16, Ipv6Addr, Ipv6Addr)> {
     if let Some(ipv6_packet) = Ipv6Packet::new(packet) {
         if let Some(icmp_packet) = IcmpPacket::new(ipv6_packet.payload()) {
             if let Some(echo_reply) =
@@ -159,6 +161,7 @@ pub(crate) fn is_icmp_v6_echo_reply(packet: &Bytes) -> Option<(u16, Ipv6Addr, Ip
             {
                 return Some((
                     echo_reply.get_identifier(),
+                    echo_reply.get_sequence_number(),
                     ipv6_packet.get_source(),
                     ipv6_packet.get_destination(),
                 ));
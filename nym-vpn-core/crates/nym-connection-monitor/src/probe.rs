@@ -0,0 +1,207 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{io, net::SocketAddr, time::Duration};
+
+use futures::{channel::mpsc, future::join_all};
+use nym_sdk::TaskClient;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+    task::JoinHandle,
+    time::MissedTickBehavior,
+};
+use tracing::{debug, error, trace};
+
+use crate::{
+    config::{ProbeKind, ProbeTarget},
+    error::Result,
+    ConnectionStatusEvent,
+};
+
+// A probe that takes longer than this has failed, regardless of how often we probe
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Probes the targets that aren't pinged by the ICMP beacon. These use regular sockets, and rely on
+// the operating system routing them through the tunnel.
+struct ConnectionProber {
+    targets: Vec<ProbeTarget>,
+    probe_interval: Duration,
+    connection_event_tx: mpsc::UnboundedSender<ConnectionStatusEvent>,
+    query_id: u16,
+}
+
+impl ConnectionProber {
+    fn new(
+        targets: Vec<ProbeTarget>,
+        probe_interval: Duration,
+        connection_event_tx: mpsc::UnboundedSender<ConnectionStatusEvent>,
+    ) -> Self {
+        ConnectionProber {
+            targets,
+            probe_interval,
+            connection_event_tx,
+            query_id: 0,
+        }
+    }
+
+    async fn probe_all(&mut self) {
+        self.query_id = self.query_id.wrapping_add(1);
+        let query_id = self.query_id;
+        let results = join_all(self.targets.iter().map(|target| async move {
+            let result = tokio::time::timeout(PROBE_TIMEOUT, probe(target, query_id))
+                .await
+                .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()));
+            (target, result)
+        }))
+        .await;
+
+        for (target, result) in results {
+            match result {
+                Ok(()) => {
+                    trace!("Probe {target} succeeded");
+                    let event = ConnectionStatusEvent::ProbeReply(target.clone());
+                    if self.connection_event_tx.unbounded_send(event).is_err() {
+                        error!("Failed to send connection event to connection monitor");
+                    }
+                }
+                Err(err) => debug!("Probe {target} failed: {err}"),
+            }
+        }
+    }
+
+    async fn run(mut self, mut shutdown: TaskClient) -> Result<()> {
+        debug!("Connection prober is running");
+        let mut probe_interval = tokio::time::interval(self.probe_interval);
+        probe_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => {
+                    trace!("ConnectionProber: Received shutdown");
+                    break;
+                }
+                _ = probe_interval.tick() => self.probe_all().await,
+            }
+        }
+        debug!("ConnectionProber: Exiting");
+        Ok(())
+    }
+}
+
+async fn probe(target: &ProbeTarget, query_id: u16) -> io::Result<()> {
+    let socket_addr = |port| SocketAddr::new(target.address, port);
+    match &target.kind {
+        ProbeKind::Icmp => Err(io::Error::other("pinged by the icmp beacon instead")),
+        ProbeKind::TcpConnect { port } => tcp_connect(socket_addr(*port)).await,
+        ProbeKind::DnsQuery { port, query_name } => {
+            dns_query(socket_addr(*port), query_name, query_id).await
+        }
+        ProbeKind::HttpHead { port, host } => http_head(socket_addr(*port), host).await,
+    }
+}
+
+// Only a completed connection counts. A refusal can come from our own firewall or from a gateway
+// on the way, which says nothing about the exit routing our traffic.
+async fn tcp_connect(socket_addr: SocketAddr) -> io::Result<()> {
+    TcpStream::connect(socket_addr).await.map(|_| ())
+}
+
+async fn dns_query(socket_addr: SocketAddr, query_name: &str, query_id: u16) -> io::Result<()> {
+    let bind_addr: SocketAddr = if socket_addr.is_ipv4() {
+        (std::net::Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    socket.connect(socket_addr).await?;
+    socket
+        .send(&dns_query_packet(query_name, query_id)?)
+        .await?;
+
+    let mut buffer = [0u8; 512];
+    loop {
+        let len = socket.recv(&mut buffer).await?;
+        // Any answer will do, even an error, as long as it's for our query
+        if is_dns_response(&buffer[..len], query_id) {
+            return Ok(());
+        }
+    }
+}
+
+// A query for the A record of the name, with recursion desired
+fn dns_query_packet(query_name: &str, query_id: u16) -> io::Result<Vec<u8>> {
+    let mut packet = Vec::with_capacity(12 + query_name.len() + 6);
+    packet.extend_from_slice(&query_id.to_be_bytes());
+    packet.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in query_name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid dns name {query_name}"),
+            ));
+        }
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.extend_from_slice(&[0, 0, 1, 0, 1]);
+    Ok(packet)
+}
+
+fn is_dns_response(packet: &[u8], query_id: u16) -> bool {
+    packet.len() >= 12 && packet[..2] == query_id.to_be_bytes() && packet[2] & 0x80 != 0
+}
+
+async fn http_head(socket_addr: SocketAddr, host: &str) -> io::Result<()> {
+    let mut stream = TcpStream::connect(socket_addr).await?;
+    let request = format!("HEAD / HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut status_line = [0u8; 5];
+    stream.read_exact(&mut status_line).await?;
+    if status_line == *b"HTTP/" {
+        Ok(())
+    } else {
+        Err(io::Error::other("not an http response"))
+    }
+}
+
+pub fn start_connection_prober(
+    targets: Vec<ProbeTarget>,
+    probe_interval: Duration,
+    connection_event_tx: mpsc::UnboundedSender<ConnectionStatusEvent>,
+    shutdown_listener: TaskClient,
+) -> JoinHandle<Result<()>> {
+    debug!("Creating connection prober");
+    let prober = ConnectionProber::new(targets, probe_interval, connection_event_tx);
+    tokio::spawn(async move {
+        prober.run(shutdown_listener).await.inspect_err(|err| {
+            error!("Connection prober error: {err}");
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_dns_query() {
+        let packet = dns_query_packet("nymtech.net.", 0x1234).unwrap();
+        assert_eq!(
+            packet,
+            [
+                &[0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0][..],
+                &b"\x07nymtech\x03net\x00"[..],
+                &[0, 1, 0, 1][..],
+            ]
+            .concat()
+        );
+        assert!(dns_query_packet("nym..net", 1).is_err());
+
+        let mut response = packet;
+        assert!(!is_dns_response(&response, 0x1234));
+        response[2] |= 0x80;
+        assert!(is_dns_response(&response, 0x1234));
+        assert!(!is_dns_response(&response, 0x4321));
+    }
+}
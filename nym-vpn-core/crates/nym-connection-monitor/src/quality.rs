@@ -0,0 +1,211 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    time::{Duration, Instant},
+};
+
+// Only the pings sent within this window count towards the connection quality
+const QUALITY_WINDOW: Duration = Duration::from_secs(60);
+
+// A ping that hasn't been answered within this time is considered lost. Mixnet round trips can
+// take a few seconds, so this is generous.
+const PING_LOSS_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Identifies a ping that we wait for the reply of
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PingId {
+    // The request id of a self ping through the mixnet
+    MixnetSelfPing(u64),
+    // The sequence number of an ICMP ping to the exit
+    IcmpBeacon(u16),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PingStats {
+    pub rtt_p50: Duration,
+    pub rtt_p90: Duration,
+    pub rtt_p99: Duration,
+    // Mean difference between consecutive round trip times
+    pub jitter: Duration,
+    // Fraction of the pings in the window that didn't come back
+    pub packet_loss: f64,
+    pub samples: usize,
+}
+
+impl fmt::Display for PingStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rtt p50/p90/p99 {}/{}/{}ms, jitter {}ms, loss {:.1}%",
+            self.rtt_p50.as_millis(),
+            self.rtt_p90.as_millis(),
+            self.rtt_p99.as_millis(),
+            self.jitter.as_millis(),
+            self.packet_loss * 100.0,
+        )
+    }
+}
+
+// The quality of the connection over the last minute, as seen by the pings of the connection
+// monitor
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConnectionQuality {
+    // Self pings through the mixnet, that go out to our gateway and back
+    pub mixnet: Option<PingStats>,
+    // Pings to the exit, through the mixnet or the wireguard tunnels
+    pub exit: Option<PingStats>,
+}
+
+impl fmt::Display for ConnectionQuality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut paths = Vec::new();
+        if let Some(mixnet) = &self.mixnet {
+            paths.push(format!("mixnet {mixnet}"));
+        }
+        if let Some(exit) = &self.exit {
+            paths.push(format!("exit {exit}"));
+        }
+        write!(f, "connection quality: {}", paths.join(", "))
+    }
+}
+
+#[derive(Debug)]
+struct PingSample {
+    sent_at: Instant,
+    rtt: Option<Duration>,
+}
+
+// Matches ping replies with the pings that were sent, and keeps the outcome over a sliding window
+#[derive(Debug, Default)]
+pub(crate) struct QualityTracker {
+    outstanding: HashMap<PingId, Instant>,
+    samples: VecDeque<PingSample>,
+}
+
+impl QualityTracker {
+    pub(crate) fn ping_sent(&mut self, id: PingId, now: Instant) {
+        self.outstanding.insert(id, now);
+    }
+
+    pub(crate) fn ping_reply(&mut self, id: PingId, now: Instant) {
+        // Replies to pings we never sent, or that are already counted as lost, are ignored
+        if let Some(sent_at) = self.outstanding.remove(&id) {
+            self.samples.push_back(PingSample {
+                sent_at,
+                rtt: Some(now.duration_since(sent_at)),
+            });
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let mut lost = Vec::new();
+        self.outstanding.retain(|_, sent_at| {
+            let expired = now.duration_since(*sent_at) >= PING_LOSS_TIMEOUT;
+            if expired {
+                lost.push(*sent_at);
+            }
+            !expired
+        });
+        self.samples.extend(
+            lost.into_iter()
+                .map(|sent_at| PingSample { sent_at, rtt: None }),
+        );
+        self.samples
+            .make_contiguous()
+            .sort_by_key(|sample| sample.sent_at);
+        while self
+            .samples
+            .front()
+            .is_some_and(|sample| now.duration_since(sample.sent_at) > QUALITY_WINDOW)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    pub(crate) fn stats(&mut self, now: Instant) -> Option<PingStats> {
+        self.expire(now);
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        let rtts: Vec<_> = self
+            .samples
+            .iter()
+            .filter_map(|sample| sample.rtt)
+            .collect();
+        let lost = self.samples.len() - rtts.len();
+        let jitter = if rtts.len() > 1 {
+            let total: Duration = rtts
+                .windows(2)
+                .map(|pair| pair[0].max(pair[1]) - pair[0].min(pair[1]))
+                .sum();
+            total / (rtts.len() - 1) as u32
+        } else {
+            Duration::ZERO
+        };
+
+        let mut sorted = rtts;
+        sorted.sort();
+        Some(PingStats {
+            rtt_p50: percentile(&sorted, 50),
+            rtt_p90: percentile(&sorted, 90),
+            rtt_p99: percentile(&sorted, 99),
+            jitter,
+            packet_loss: lost as f64 / self.samples.len() as f64,
+            samples: self.samples.len(),
+        })
+    }
+}
+
+// Nearest rank percentile of sorted values
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (percent * sorted.len()).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rtt_percentiles_jitter_and_loss() {
+        let start = Instant::now();
+        let mut tracker = QualityTracker::default();
+        assert_eq!(tracker.stats(start), None);
+
+        // Ten pings, one second apart, with round trips of 100ms to 1000ms, and one that is lost
+        for i in 0..10u16 {
+            let sent_at = start + Duration::from_secs(i.into());
+            tracker.ping_sent(PingId::IcmpBeacon(i), sent_at);
+            if i != 4 {
+                let rtt = Duration::from_millis(100 * (u64::from(i) + 1));
+                tracker.ping_reply(PingId::IcmpBeacon(i), sent_at + rtt);
+            }
+        }
+        // Replies we aren't waiting for don't count
+        tracker.ping_reply(PingId::MixnetSelfPing(1), start);
+
+        // The lost ping isn't counted until it has timed out
+        let stats = tracker.stats(start + Duration::from_secs(11)).unwrap();
+        assert_eq!(stats.samples, 9);
+        assert_eq!(stats.packet_loss, 0.0);
+
+        let stats = tracker.stats(start + Duration::from_secs(15)).unwrap();
+        assert_eq!(stats.samples, 10);
+        assert_eq!(stats.packet_loss, 0.1);
+        assert_eq!(stats.rtt_p50, Duration::from_millis(600));
+        assert_eq!(stats.rtt_p90, Duration::from_millis(1000));
+        assert_eq!(stats.rtt_p99, Duration::from_millis(1000));
+        // Seven steps of 100ms, and one of 200ms around the lost ping
+        assert_eq!(stats.jitter, Duration::from_micros(112_500));
+
+        // Everything eventually falls out of the window
+        assert_eq!(tracker.stats(start + Duration::from_secs(120)), None);
+    }
+}
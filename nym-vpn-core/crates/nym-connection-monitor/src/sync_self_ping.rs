@@ -1,7 +1,10 @@
 // Copyright 2023 - Nym Technologies SA <contact@nymtech.net>
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use futures::StreamExt;
 use nym_ip_packet_requests::request::IpPacketRequest;
@@ -24,6 +27,44 @@ pub async fn self_ping_and_wait(
     wait_for_self_ping_return(&mixnet_client, &request_ids).await
 }
 
+// Send a number of mixnet self pings, one after the other, and return the round trip times of the
+// ones that came back within the timeout
+pub async fn self_ping_round_trips(
+    our_address: Recipient,
+    mixnet_client: SharedMixnetClient,
+    count: usize,
+    timeout: Duration,
+) -> Result<Vec<Duration>> {
+    let mut mixnet_client_handle = mixnet_client.lock().await;
+    let mixnet_client = mixnet_client_handle.as_mut().unwrap();
+
+    let mut round_trips = Vec::with_capacity(count);
+    for _ in 0..count {
+        let (input_message, request_id) = create_self_ping(our_address);
+        let sent_at = Instant::now();
+        mixnet_client.send(input_message).await?;
+
+        let wait_for_reply = async {
+            while let Some(msgs) = mixnet_client.wait_for_messages().await {
+                let returned = msgs.iter().any(|msg| {
+                    IpPacketRequest::from_reconstructed_message(msg)
+                        .is_ok_and(|response| response.id() == Some(request_id))
+                });
+                if returned {
+                    return true;
+                }
+            }
+            false
+        };
+        match tokio::time::timeout(timeout, wait_for_reply).await {
+            Ok(true) => round_trips.push(sent_at.elapsed()),
+            Ok(false) => break,
+            Err(_) => debug!("Mixnet self ping {request_id} didn't return in time"),
+        }
+    }
+    Ok(round_trips)
+}
+
 async fn send_self_pings(
     our_address: Recipient,
     mixnet_client: &SharedMixnetClient,
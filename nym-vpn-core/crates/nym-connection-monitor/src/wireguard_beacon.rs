@@ -0,0 +1,357 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use futures::channel::mpsc;
+use nym_sdk::TaskClient;
+use pnet_packet::{icmpv6, Packet};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::{net::UdpSocket, sync::watch, task::JoinHandle};
+use tracing::{debug, error, trace};
+
+use crate::{
+    config::ConnectionMonitorConfig,
+    error::{Error, Result},
+    packet_helpers::{create_icmpv4_echo_request, create_icmpv6_echo_request, is_icmp_echo_reply},
+    quality::PingId,
+    ConnectionStatusEvent,
+};
+
+// The innermost wireguard tunnel, that the pings are sent through
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WireguardExit {
+    pub interface: String,
+    // The addresses of the exit gateway inside the tunnel
+    pub gateway_ipv4: Ipv4Addr,
+    pub gateway_ipv6: Option<Ipv6Addr>,
+}
+
+// Sends ICMP pings through the exit wireguard tunnel, to the exit gateway and to external
+// addresses, and reports the replies to the connection monitor. This is the wireguard counterpart
+// of the ICMP beacon that pings through the mixnet, except that here the operating system does the
+// routing, so we use raw sockets.
+struct WireguardIcmpBeacon {
+    // Follows the exit we are connected to, which can change while the beacon is running
+    exit: watch::Receiver<WireguardExit>,
+    connection_event_tx: mpsc::UnboundedSender<ConnectionStatusEvent>,
+    sequence_number: u16,
+    icmp_identifier: u16,
+    // Pinged to check that the exit gateway can reach the internet
+    external_targets_v4: Vec<Ipv4Addr>,
+    external_targets_v6: Vec<Ipv6Addr>,
+    ping_interval: Duration,
+}
+
+// The raw sockets bound to the exit tunnel
+struct IcmpSockets {
+    v4: UdpSocket,
+    v6: UdpSocket,
+}
+
+impl WireguardIcmpBeacon {
+    fn new(
+        exit: watch::Receiver<WireguardExit>,
+        connection_event_tx: mpsc::UnboundedSender<ConnectionStatusEvent>,
+        icmp_identifier: u16,
+        config: &ConnectionMonitorConfig,
+    ) -> Self {
+        WireguardIcmpBeacon {
+            exit,
+            connection_event_tx,
+            sequence_number: 0,
+            icmp_identifier,
+            external_targets_v4: config.icmp_targets_v4(),
+            external_targets_v6: config.icmp_targets_v6(),
+            ping_interval: config.ping_interval,
+        }
+    }
+
+    fn get_next_sequence_number(&mut self) -> u16 {
+        let sequence_number = self.sequence_number;
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+        sequence_number
+    }
+
+    async fn send_icmp_ping(
+        &mut self,
+        sockets: &IcmpSockets,
+        destination: IpAddr,
+        exit: &WireguardExit,
+    ) -> Result<()> {
+        let sequence_number = self.get_next_sequence_number();
+        let (socket, icmp_echo_request) = match destination {
+            IpAddr::V4(_) => (
+                &sockets.v4,
+                create_icmpv4_echo_request(sequence_number, self.icmp_identifier)?
+                    .packet()
+                    .to_vec(),
+            ),
+            // The kernel fills in the checksum of ICMPv6 packets sent on raw sockets, since it
+            // covers the source address that it picks
+            IpAddr::V6(destination) => (
+                &sockets.v6,
+                create_icmpv6_echo_request(
+                    sequence_number,
+                    self.icmp_identifier,
+                    &Ipv6Addr::UNSPECIFIED,
+                    &destination,
+                )?
+                .packet()
+                .to_vec(),
+            ),
+        };
+        socket
+            .send_to(&icmp_echo_request, SocketAddr::new(destination, 0))
+            .await
+            .map_err(Error::IcmpSocketError)?;
+
+        // The round trip to the exit gateway is timed by the connection monitor
+        if is_exit_gateway(exit, destination) {
+            let event = ConnectionStatusEvent::PingSent(PingId::IcmpBeacon(sequence_number));
+            if self.connection_event_tx.unbounded_send(event).is_err() {
+                error!("Failed to send connection event to connection monitor");
+            }
+        }
+        Ok(())
+    }
+
+    async fn send_pings(&mut self, sockets: &IcmpSockets, exit: &WireguardExit) {
+        let targets_v4 = [exit.gateway_ipv4]
+            .into_iter()
+            .chain(self.external_targets_v4.clone())
+            .map(IpAddr::from);
+        for destination in targets_v4 {
+            if let Err(err) = self.send_icmp_ping(sockets, destination, exit).await {
+                error!("Failed to send ICMP ping to {destination}: {err}");
+            }
+        }
+
+        // Without an ipv6 address on the tunnel these fail, and the connection monitor reports
+        // that ipv6 is not routed
+        let targets_v6 = exit
+            .gateway_ipv6
+            .into_iter()
+            .chain(self.external_targets_v6.clone())
+            .map(IpAddr::from);
+        for destination in targets_v6 {
+            if let Err(err) = self.send_icmp_ping(sockets, destination, exit).await {
+                debug!("Failed to send ICMP ping to {destination}: {err}");
+            }
+        }
+    }
+
+    fn handle_reply_v4(&self, packet: Bytes, exit: &WireguardExit) {
+        let Some((identifier, sequence_number, source, _)) = is_icmp_echo_reply(&packet) else {
+            return;
+        };
+        if identifier != self.icmp_identifier {
+            return;
+        }
+        let event = if source == exit.gateway_ipv4 {
+            debug!("Received ping response from the exit gateway");
+            ConnectionStatusEvent::Icmpv4IprTunDevicePingReply { sequence_number }
+        } else if self.external_targets_v4.contains(&source) {
+            debug!("Received ping response from an external ip through the exit gateway");
+            ConnectionStatusEvent::Icmpv4IprExternalPingReply(source)
+        } else {
+            return;
+        };
+        self.report_reply(event);
+    }
+
+    // Unlike ICMPv4, raw ICMPv6 sockets receive the packet without the IP header
+    fn handle_reply_v6(&self, packet: &[u8], source: Ipv6Addr, exit: &WireguardExit) {
+        let Some(reply) = icmpv6::echo_reply::EchoReplyPacket::new(packet) else {
+            return;
+        };
+        if reply.get_icmpv6_type() != icmpv6::Icmpv6Types::EchoReply
+            || reply.get_identifier() != self.icmp_identifier
+        {
+            return;
+        }
+        let event = if exit.gateway_ipv6 == Some(source) {
+            debug!("Received ping v6 response from the exit gateway");
+            ConnectionStatusEvent::Icmpv6IprTunDevicePingReply {
+                sequence_number: reply.get_sequence_number(),
+            }
+        } else if self.external_targets_v6.contains(&source) {
+            debug!("Received ping v6 response from an external ip through the exit gateway");
+            ConnectionStatusEvent::Icmpv6IprExternalPingReply(source)
+        } else {
+            return;
+        };
+        self.report_reply(event);
+    }
+
+    fn report_reply(&self, event: ConnectionStatusEvent) {
+        // The replies came back through every tunnel, so the ones before the exit are alive too
+        for event in [ConnectionStatusEvent::EntryTunnelAlive, event] {
+            if self.connection_event_tx.unbounded_send(event).is_err() {
+                error!("Failed to send connection event to connection monitor");
+            }
+        }
+    }
+
+    pub async fn run(mut self, mut shutdown: TaskClient) -> Result<()> {
+        debug!("Wireguard icmp connection beacon is running");
+        loop {
+            let exit = self.exit.borrow_and_update().clone();
+            let sockets = match open_icmp_sockets(&exit.interface) {
+                Ok(sockets) => sockets,
+                Err(err) => {
+                    error!("Failed to open icmp sockets on {}: {err}", exit.interface);
+                    tokio::select! {
+                        _ = shutdown.recv() => break,
+                        Ok(_) = self.exit.changed() => continue,
+                    }
+                }
+            };
+
+            let mut ping_interval = tokio::time::interval(self.ping_interval);
+            let mut buffer_v4 = vec![0u8; 1500];
+            let mut buffer_v6 = vec![0u8; 1500];
+            loop {
+                tokio::select! {
+                    _ = shutdown.recv() => {
+                        trace!("WireguardIcmpBeacon: Received shutdown");
+                        debug!("WireguardIcmpBeacon: Exiting");
+                        return Ok(());
+                    }
+                    Ok(_) = self.exit.changed() => {
+                        debug!("Exit tunnel changed, reopening the icmp sockets");
+                        break;
+                    }
+                    _ = ping_interval.tick() => self.send_pings(&sockets, &exit).await,
+                    received = sockets.v4.recv_from(&mut buffer_v4) => match received {
+                        Ok((len, _)) => {
+                            self.handle_reply_v4(Bytes::copy_from_slice(&buffer_v4[..len]), &exit)
+                        }
+                        Err(err) => error!("Failed to receive ICMP reply: {err}"),
+                    },
+                    received = sockets.v6.recv_from(&mut buffer_v6) => match received {
+                        Ok((len, SocketAddr::V6(source))) => {
+                            self.handle_reply_v6(&buffer_v6[..len], *source.ip(), &exit)
+                        }
+                        Ok(_) => {}
+                        Err(err) => error!("Failed to receive ICMPv6 reply: {err}"),
+                    },
+                }
+            }
+        }
+        debug!("WireguardIcmpBeacon: Exiting");
+        Ok(())
+    }
+}
+
+fn is_exit_gateway(exit: &WireguardExit, address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => address == exit.gateway_ipv4,
+        IpAddr::V6(address) => exit.gateway_ipv6 == Some(address),
+    }
+}
+
+fn open_icmp_sockets(interface: &str) -> Result<IcmpSockets> {
+    Ok(IcmpSockets {
+        v4: open_icmp_socket(interface, Domain::IPV4, Protocol::ICMPV4)?,
+        v6: open_icmp_socket(interface, Domain::IPV6, Protocol::ICMPV6)?,
+    })
+}
+
+// Raw sockets are datagram sockets as far as sending and receiving goes, which lets tokio drive
+// them on every platform
+fn open_icmp_socket(interface: &str, domain: Domain, protocol: Protocol) -> Result<UdpSocket> {
+    let socket = Socket::new(domain, Type::RAW, Some(protocol)).map_err(Error::IcmpSocketError)?;
+    // The exit gateways all use the same private addresses, so make sure the pings go through the
+    // exit tunnel and not through one of the tunnels before it
+    bind_to_interface(&socket, interface, domain).map_err(Error::IcmpSocketError)?;
+    socket
+        .set_nonblocking(true)
+        .map_err(Error::IcmpSocketError)?;
+    UdpSocket::from_std(socket.into()).map_err(Error::IcmpSocketError)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn bind_to_interface(socket: &Socket, interface: &str, _domain: Domain) -> std::io::Result<()> {
+    socket.bind_device(Some(interface.as_bytes()))
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn bind_to_interface(socket: &Socket, interface: &str, domain: Domain) -> std::io::Result<()> {
+    // IP_BOUND_IF and IPV6_BOUND_IF
+    let index = std::num::NonZeroU32::new(nix::net::if_::if_nametoindex(interface)?);
+    if domain == Domain::IPV6 {
+        socket.bind_device_by_index_v6(index)
+    } else {
+        socket.bind_device_by_index_v4(index)
+    }
+}
+
+#[cfg(windows)]
+fn bind_to_interface(socket: &Socket, interface: &str, domain: Domain) -> std::io::Result<()> {
+    use std::os::windows::io::AsRawSocket;
+    use windows_sys::Win32::{
+        NetworkManagement::{
+            IpHelper::{ConvertInterfaceAliasToLuid, ConvertInterfaceLuidToIndex},
+            Ndis::NET_LUID_LH,
+        },
+        Networking::WinSock::{
+            setsockopt, IPPROTO_IP, IPPROTO_IPV6, IPV6_UNICAST_IF, IP_UNICAST_IF, SOCKET_ERROR,
+        },
+    };
+
+    let alias: Vec<u16> = interface.encode_utf16().chain(Some(0)).collect();
+    let mut luid = NET_LUID_LH { Value: 0 };
+    let mut index = 0u32;
+    // SAFETY: the alias is nul terminated, and the outputs are valid for writes
+    let status = unsafe { ConvertInterfaceAliasToLuid(alias.as_ptr(), &mut luid) };
+    if status != 0 {
+        return Err(std::io::Error::from_raw_os_error(status as i32));
+    }
+    // SAFETY: as above
+    let status = unsafe { ConvertInterfaceLuidToIndex(&luid, &mut index) };
+    if status != 0 {
+        return Err(std::io::Error::from_raw_os_error(status as i32));
+    }
+
+    // IP_UNICAST_IF takes the index in network byte order, IPV6_UNICAST_IF in host byte order
+    let (level, name, index) = if domain == Domain::IPV6 {
+        (IPPROTO_IPV6, IPV6_UNICAST_IF, index)
+    } else {
+        (IPPROTO_IP, IP_UNICAST_IF, index.to_be())
+    };
+    // SAFETY: the option value is a valid u32 for the duration of the call
+    let result = unsafe {
+        setsockopt(
+            socket.as_raw_socket() as _,
+            level,
+            name,
+            &index as *const u32 as *const u8,
+            std::mem::size_of::<u32>() as i32,
+        )
+    };
+    if result == SOCKET_ERROR {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub fn start_wireguard_icmp_beacon(
+    exit: watch::Receiver<WireguardExit>,
+    connection_event_tx: mpsc::UnboundedSender<ConnectionStatusEvent>,
+    icmp_identifier: u16,
+    config: &ConnectionMonitorConfig,
+    shutdown_listener: TaskClient,
+) -> JoinHandle<Result<()>> {
+    debug!("Creating wireguard icmp connection beacon");
+    let beacon = WireguardIcmpBeacon::new(exit, connection_event_tx, icmp_identifier, config);
+    tokio::spawn(async move {
+        beacon.run(shutdown_listener).await.inspect_err(|err| {
+            error!("Wireguard icmp connection beacon error: {err}");
+        })
+    })
+}
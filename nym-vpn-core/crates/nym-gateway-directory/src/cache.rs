@@ -0,0 +1,381 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+use url::Url;
+
+const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+
+// Past this age we don't serve the cached list unless the directory can't be reached
+const DEFAULT_CACHE_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+// After failing to reach the directory, serve the cached list without asking again for this long
+const FETCH_FAILURE_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CacheConfig {
+    pub dir: PathBuf,
+    pub ttl: Duration,
+    pub max_age: Duration,
+    // The lists of each network are kept apart
+    pub network_name: Option<String>,
+    // Only use the cache when the directory can't be reached
+    pub fallback_only: bool,
+}
+
+impl CacheConfig {
+    pub fn new(dir: PathBuf) -> Self {
+        CacheConfig {
+            dir,
+            ttl: DEFAULT_CACHE_TTL,
+            max_age: DEFAULT_CACHE_MAX_AGE,
+            network_name: None,
+            fallback_only: false,
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn with_network_name(mut self, network_name: String) -> Self {
+        self.network_name = Some(network_name);
+        self
+    }
+
+    // Always ask the directory first, such as when picking the gateways to connect to
+    pub fn fallback_only(mut self) -> Self {
+        self.fallback_only = true;
+        self
+    }
+}
+
+// A list returned from the directory, together with when it was fetched
+#[derive(Clone, Debug)]
+pub struct Cached<T> {
+    pub value: T,
+    pub fetched_at: SystemTime,
+    // Older than the cache TTL, and being refreshed in the background
+    pub stale: bool,
+}
+
+impl<T> Cached<T> {
+    pub(crate) fn fresh(value: T) -> Self {
+        Cached {
+            value,
+            fetched_at: SystemTime::now(),
+            stale: false,
+        }
+    }
+
+    pub fn age(&self) -> Duration {
+        self.fetched_at.elapsed().unwrap_or_default()
+    }
+
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Cached<U> {
+        Cached {
+            value: f(self.value),
+            fetched_at: self.fetched_at,
+            stale: self.stale,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum CacheKey {
+    AllGateways,
+    EntryGateways,
+    ExitGateways,
+    EntryCountries,
+    ExitCountries,
+}
+
+impl CacheKey {
+    fn name(&self) -> &'static str {
+        match self {
+            CacheKey::AllGateways => "all_gateways",
+            CacheKey::EntryGateways => "entry_gateways",
+            CacheKey::ExitGateways => "exit_gateways",
+            CacheKey::EntryCountries => "entry_countries",
+            CacheKey::ExitCountries => "exit_countries",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile<T> {
+    fetched_at_unix_secs: u64,
+    items: Vec<T>,
+}
+
+pub(crate) enum CacheLookup<T> {
+    Fresh(Cached<Vec<T>>),
+    Stale(Cached<Vec<T>>),
+    // Too old to use, unless there is nothing better
+    Expired(Cached<Vec<T>>),
+    Missing,
+}
+
+// On-disk cache of the lists we get from the directory, one json file per list
+#[derive(Clone, Debug)]
+pub(crate) struct GatewayCache {
+    config: CacheConfig,
+    // Where the lists come from, so that switching networks or APIs doesn't serve the wrong ones
+    source: String,
+    // Shared between the clones, so that all lookups see the same failures and refreshes
+    state: Arc<Mutex<FetchState>>,
+}
+
+type ListId = (CacheKey, Option<u8>);
+
+#[derive(Debug, Default)]
+struct FetchState {
+    last_failure: HashMap<ListId, Instant>,
+    refreshing: HashSet<ListId>,
+}
+
+impl GatewayCache {
+    pub(crate) fn new(config: CacheConfig, api_urls: &[&Url]) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(config.network_name.as_deref().unwrap_or_default());
+        for api_url in api_urls {
+            hasher.update([0u8]);
+            hasher.update(api_url.as_str());
+        }
+        let source = hex::encode(&hasher.finalize()[..8]);
+        GatewayCache {
+            config,
+            source,
+            state: Default::default(),
+        }
+    }
+
+    pub(crate) fn fallback_only(&self) -> bool {
+        self.config.fallback_only
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, FetchState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Whether the directory failed us so recently that it's not worth waiting for it again
+    pub(crate) fn recently_failed(
+        &self,
+        key: CacheKey,
+        min_gateway_performance: Option<u8>,
+    ) -> bool {
+        self.state()
+            .last_failure
+            .get(&(key, min_gateway_performance))
+            .is_some_and(|failed_at| failed_at.elapsed() < FETCH_FAILURE_BACKOFF)
+    }
+
+    pub(crate) fn record_failure(&self, key: CacheKey, min_gateway_performance: Option<u8>) {
+        self.state()
+            .last_failure
+            .insert((key, min_gateway_performance), Instant::now());
+    }
+
+    // Returns false if the list is already being refreshed
+    pub(crate) fn start_refresh(&self, key: CacheKey, min_gateway_performance: Option<u8>) -> bool {
+        self.state()
+            .refreshing
+            .insert((key, min_gateway_performance))
+    }
+
+    pub(crate) fn finish_refresh(&self, key: CacheKey, min_gateway_performance: Option<u8>) {
+        self.state()
+            .refreshing
+            .remove(&(key, min_gateway_performance));
+    }
+
+    // The lists depend on the performance threshold, so each threshold gets its own file
+    fn path(&self, key: CacheKey, min_gateway_performance: Option<u8>) -> PathBuf {
+        let file_name = match min_gateway_performance {
+            Some(min_performance) => format!("{}_min_performance_{min_performance}", key.name()),
+            None => key.name().to_string(),
+        };
+        self.config
+            .dir
+            .join(&self.source)
+            .join(file_name)
+            .with_extension("json")
+    }
+
+    pub(crate) fn read<T: DeserializeOwned>(
+        &self,
+        key: CacheKey,
+        min_gateway_performance: Option<u8>,
+    ) -> CacheLookup<T> {
+        let path = self.path(key, min_gateway_performance);
+        let Ok(contents) = fs::read(&path) else {
+            return CacheLookup::Missing;
+        };
+        let file: CacheFile<T> = match serde_json::from_slice(&contents) {
+            Ok(file) => file,
+            Err(err) => {
+                warn!("Ignoring corrupt gateway cache {}: {err}", path.display());
+                return CacheLookup::Missing;
+            }
+        };
+
+        let fetched_at = UNIX_EPOCH + Duration::from_secs(file.fetched_at_unix_secs);
+        let cached = Cached {
+            value: file.items,
+            fetched_at,
+            stale: false,
+        };
+        let age = cached.age();
+        if age < self.config.ttl {
+            CacheLookup::Fresh(cached)
+        } else if age < self.config.max_age {
+            CacheLookup::Stale(Cached {
+                stale: true,
+                ..cached
+            })
+        } else {
+            CacheLookup::Expired(Cached {
+                stale: true,
+                ..cached
+            })
+        }
+    }
+
+    pub(crate) fn write<T: Serialize>(
+        &self,
+        key: CacheKey,
+        min_gateway_performance: Option<u8>,
+        items: &[T],
+    ) {
+        let path = self.path(key, min_gateway_performance);
+        let file = CacheFile {
+            fetched_at_unix_secs: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            items,
+        };
+        // Write to a temporary file first, so that readers never see a partial file
+        let tmp_path = path.with_extension("json.tmp");
+        let result = fs::create_dir_all(self.config.dir.join(&self.source))
+            .and_then(|_| {
+                serde_json::to_vec(&file)
+                    .map_err(std::io::Error::from)
+                    .and_then(|contents| fs::write(&tmp_path, contents))
+            })
+            .and_then(|_| fs::rename(&tmp_path, &path));
+        self.state()
+            .last_failure
+            .remove(&(key, min_gateway_performance));
+        match result {
+            Ok(()) => debug!("Updated gateway cache: {}", path.display()),
+            Err(err) => warn!("Failed to write gateway cache {}: {err}", path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_back_what_was_written() {
+        let dir = std::env::temp_dir().join(format!("nym-gateway-cache-{}", std::process::id()));
+        let api_url = Url::parse("https://validator.nymtech.net/api/").unwrap();
+        let cache = GatewayCache::new(CacheConfig::new(dir.clone()), &[&api_url]);
+        cache.write(CacheKey::EntryCountries, Some(70), &["DE", "NO"]);
+
+        assert!(matches!(
+            cache.read::<String>(CacheKey::EntryCountries, None),
+            CacheLookup::Missing
+        ));
+        match cache.read::<String>(CacheKey::EntryCountries, Some(70)) {
+            CacheLookup::Fresh(cached) => assert_eq!(cached.value, vec!["DE", "NO"]),
+            _ => panic!("expected a fresh cache entry"),
+        }
+
+        let cache = GatewayCache::new(
+            CacheConfig::new(dir.clone()).with_ttl(Duration::ZERO),
+            &[&api_url],
+        );
+        assert!(matches!(
+            cache.read::<String>(CacheKey::EntryCountries, Some(70)),
+            CacheLookup::Stale(_)
+        ));
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn other_networks_and_apis_dont_share_lists() {
+        let dir =
+            std::env::temp_dir().join(format!("nym-gateway-cache-sources-{}", std::process::id()));
+        let mainnet = Url::parse("https://validator.nymtech.net/api/").unwrap();
+        let sandbox = Url::parse("https://sandbox-nym-api1.nymtech.net/api/").unwrap();
+        let config = CacheConfig::new(dir.clone());
+        let cache = GatewayCache::new(
+            config.clone().with_network_name("mainnet".into()),
+            &[&mainnet],
+        );
+        cache.write(CacheKey::ExitCountries, None, &["DE"]);
+
+        for other in [
+            GatewayCache::new(
+                config.clone().with_network_name("mainnet".into()),
+                &[&sandbox],
+            ),
+            GatewayCache::new(
+                config.clone().with_network_name("sandbox".into()),
+                &[&mainnet],
+            ),
+        ] {
+            assert!(matches!(
+                other.read::<String>(CacheKey::ExitCountries, None),
+                CacheLookup::Missing
+            ));
+        }
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn one_refresh_at_a_time() {
+        let api_url = Url::parse("https://validator.nymtech.net/api/").unwrap();
+        let cache = GatewayCache::new(CacheConfig::new(std::env::temp_dir()), &[&api_url]);
+        let clone = cache.clone();
+
+        assert!(cache.start_refresh(CacheKey::EntryGateways, None));
+        assert!(!clone.start_refresh(CacheKey::EntryGateways, None));
+        assert!(clone.start_refresh(CacheKey::EntryGateways, Some(70)));
+        cache.finish_refresh(CacheKey::EntryGateways, None);
+        assert!(clone.start_refresh(CacheKey::EntryGateways, None));
+    }
+
+    #[test]
+    fn failures_are_remembered_until_the_next_write() {
+        let dir =
+            std::env::temp_dir().join(format!("nym-gateway-cache-failures-{}", std::process::id()));
+        let api_url = Url::parse("https://validator.nymtech.net/api/").unwrap();
+        let cache = GatewayCache::new(CacheConfig::new(dir.clone()), &[&api_url]);
+
+        assert!(!cache.recently_failed(CacheKey::ExitGateways, None));
+        cache.record_failure(CacheKey::ExitGateways, None);
+        assert!(cache.clone().recently_failed(CacheKey::ExitGateways, None));
+        assert!(!cache.recently_failed(CacheKey::ExitGateways, Some(70)));
+
+        cache.write(CacheKey::ExitGateways, None, &["gateway"]);
+        assert!(!cache.recently_failed(CacheKey::ExitGateways, None));
+        fs::remove_dir_all(dir).ok();
+    }
+}
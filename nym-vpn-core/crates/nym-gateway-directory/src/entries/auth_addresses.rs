@@ -4,11 +4,12 @@
 use std::fmt::Display;
 
 use nym_sdk::mixnet::Recipient;
+use serde::{Deserialize, Serialize};
 
 use crate::{error::Result, Error};
 
 // optional, until we remove the wireguard feature flag
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct AuthAddress(pub Option<Recipient>);
 
 impl AuthAddress {
@@ -23,9 +24,10 @@ impl AuthAddress {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub struct AuthAddresses {
     entry_addr: AuthAddress,
+    middle_addrs: Vec<AuthAddress>,
     exit_addr: AuthAddress,
 }
 
@@ -33,25 +35,44 @@ impl AuthAddresses {
     pub fn new(entry_addr: AuthAddress, exit_addr: AuthAddress) -> Self {
         AuthAddresses {
             entry_addr,
+            middle_addrs: Vec::new(),
             exit_addr,
         }
     }
 
+    // The hops in between the entry and the exit, in order, for multi-hop wireguard
+    pub fn with_middle_addrs(mut self, middle_addrs: Vec<AuthAddress>) -> Self {
+        self.middle_addrs = middle_addrs;
+        self
+    }
+
     pub fn entry(&self) -> AuthAddress {
         self.entry_addr
     }
 
+    pub fn middles(&self) -> &[AuthAddress] {
+        &self.middle_addrs
+    }
+
     pub fn exit(&self) -> AuthAddress {
         self.exit_addr
     }
+
+    // All hops, from the entry to the exit
+    pub fn hops(&self) -> Vec<AuthAddress> {
+        std::iter::once(self.entry_addr)
+            .chain(self.middle_addrs.iter().copied())
+            .chain(std::iter::once(self.exit_addr))
+            .collect()
+    }
 }
 
 impl Display for AuthAddresses {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "entry: {:?} exit: {:?}",
-            self.entry_addr.0, self.exit_addr.0
-        )
+        write!(f, "entry: {:?}", self.entry_addr.0)?;
+        for middle_addr in &self.middle_addrs {
+            write!(f, " middle: {:?}", middle_addr.0)?;
+        }
+        write!(f, " exit: {:?}", self.exit_addr.0)
     }
 }
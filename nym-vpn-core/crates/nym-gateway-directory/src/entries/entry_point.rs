@@ -8,7 +8,11 @@ use serde::{Deserialize, Serialize};
 use tracing::debug;
 
 use super::gateway::{Gateway, GatewayList};
-use crate::{error::Result, Error};
+use crate::{
+    error::Result,
+    selector::{GatewayRole, GatewaySelector, ScoringGatewaySelector},
+    Error,
+};
 
 // The entry point is always a gateway identity, or some other entry that can be resolved to a
 // gateway identity.
@@ -23,6 +27,8 @@ pub enum EntryPoint {
     RandomLowLatency,
     // Select an entry gateway at random.
     Random,
+    // Select one of the best entry gateways, scored by latency, performance and probe outcome.
+    Best,
 }
 
 impl Display for EntryPoint {
@@ -32,6 +38,7 @@ impl Display for EntryPoint {
             EntryPoint::Location { location } => write!(f, "Location: {}", location),
             EntryPoint::RandomLowLatency => write!(f, "Random low latency"),
             EntryPoint::Random => write!(f, "Random"),
+            EntryPoint::Best => write!(f, "Best"),
         }
     }
 }
@@ -52,6 +59,16 @@ impl EntryPoint {
     }
 
     pub async fn lookup_gateway(&self, gateways: &GatewayList) -> Result<Gateway> {
+        self.lookup_gateway_with_selector(gateways, &ScoringGatewaySelector::new())
+            .await
+    }
+
+    // Same as `lookup_gateway`, but with the selector used for `EntryPoint::Best`
+    pub async fn lookup_gateway_with_selector(
+        &self,
+        gateways: &GatewayList,
+        selector: &dyn GatewaySelector,
+    ) -> Result<Gateway> {
         match &self {
             EntryPoint::Gateway { identity } => {
                 debug!("Selecting gateway by identity: {}", identity);
@@ -81,6 +98,10 @@ impl EntryPoint {
                     .random_gateway()
                     .ok_or_else(|| Error::FailedToSelectGatewayRandomly)
             }
+            EntryPoint::Best => {
+                debug!("Selecting the best gateway");
+                selector.select(gateways, GatewayRole::Entry).await
+            }
         }
     }
 }
@@ -8,7 +8,11 @@ use serde::{Deserialize, Serialize};
 use tracing::{debug, info};
 
 use super::gateway::{Gateway, GatewayList};
-use crate::{error::Result, Error, IpPacketRouterAddress};
+use crate::{
+    error::Result,
+    selector::{GatewayRole, GatewaySelector, ScoringGatewaySelector},
+    Error, IpPacketRouterAddress,
+};
 
 // The exit point is a nym-address, but if the exit ip-packet-router is running embedded on a
 // gateway, we can refer to it by the gateway identity.
@@ -26,6 +30,8 @@ pub enum ExitPoint {
     Location { location: String },
     // Select an exit gateway at random.
     Random,
+    // Select one of the best exit gateways, scored by latency, performance and probe outcome.
+    Best,
 }
 
 impl Display for ExitPoint {
@@ -35,6 +41,7 @@ impl Display for ExitPoint {
             ExitPoint::Gateway { identity } => write!(f, "Gateway: {}", identity),
             ExitPoint::Location { location } => write!(f, "Location: {}", location),
             ExitPoint::Random => write!(f, "Random"),
+            ExitPoint::Best => write!(f, "Best"),
         }
     }
 }
@@ -44,7 +51,17 @@ impl ExitPoint {
         matches!(self, ExitPoint::Location { .. })
     }
 
-    pub fn lookup_gateway(&self, gateways: &GatewayList) -> Result<Gateway> {
+    pub async fn lookup_gateway(&self, gateways: &GatewayList) -> Result<Gateway> {
+        self.lookup_gateway_with_selector(gateways, &ScoringGatewaySelector::new())
+            .await
+    }
+
+    // Same as `lookup_gateway`, but with the selector used for `ExitPoint::Best`
+    pub async fn lookup_gateway_with_selector(
+        &self,
+        gateways: &GatewayList,
+        selector: &dyn GatewaySelector,
+    ) -> Result<Gateway> {
         match &self {
             ExitPoint::Address { address } => {
                 debug!("Selecting gateway by address: {}", address);
@@ -87,6 +104,10 @@ impl ExitPoint {
                     .random_gateway()
                     .ok_or_else(|| Error::FailedToSelectGatewayRandomly)
             }
+            ExitPoint::Best => {
+                info!("Selecting the best exit gateway");
+                selector.select(gateways, GatewayRole::Exit).await
+            }
         }
     }
 }
@@ -5,6 +5,7 @@ use itertools::Itertools;
 use nym_sdk::mixnet::NodeIdentity;
 use nym_topology::IntoGatewayNode;
 use rand::seq::IteratorRandom;
+use serde::{Deserialize, Serialize};
 use tracing::error;
 
 use crate::{error::Result, AuthAddress, Country, Error, IpPacketRouterAddress};
@@ -12,13 +13,14 @@ use crate::{error::Result, AuthAddress, Country, Error, IpPacketRouterAddress};
 // Decimal between 0 and 1 representing the performance of a gateway, measured over 24h.
 type Performance = u8;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Gateway {
     pub identity: NodeIdentity,
     pub location: Option<Location>,
     pub ipr_address: Option<IpPacketRouterAddress>,
     pub authenticator_address: Option<AuthAddress>,
     pub last_probe: Option<Probe>,
+    #[serde(with = "network_address")]
     pub host: Option<nym_topology::NetworkAddress>,
     pub clients_ws_port: Option<u16>,
     pub clients_wss_port: Option<u16>,
@@ -60,32 +62,32 @@ impl Gateway {
     }
 }
 
-#[derive(Debug, Default, Clone, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Location {
     pub two_letter_iso_country_code: String,
     pub latitude: f64,
     pub longitude: f64,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Probe {
     pub last_updated_utc: String,
     pub outcome: ProbeOutcome,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ProbeOutcome {
     pub as_entry: Entry,
     pub as_exit: Option<Exit>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Entry {
     pub can_connect: bool,
     pub can_route: bool,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Exit {
     pub can_connect: bool,
     pub can_route_ip_v4: bool,
@@ -222,6 +224,32 @@ impl TryFrom<nym_validator_client::models::DescribedGateway> for Gateway {
     }
 }
 
+// nym_topology::NetworkAddress isn't serializable, so we store it as a string
+mod network_address {
+    use std::net::IpAddr;
+
+    use nym_topology::NetworkAddress;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(
+        host: &Option<NetworkAddress>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        host.as_ref().map(ToString::to_string).serialize(serializer)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<NetworkAddress>, D::Error> {
+        Ok(
+            Option::<String>::deserialize(deserializer)?.map(|host| match host.parse::<IpAddr>() {
+                Ok(ip) => NetworkAddress::IpAddr(ip),
+                Err(_) => NetworkAddress::Hostname(host),
+            }),
+        )
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct GatewayList {
     gateways: Vec<Gateway>,
@@ -3,10 +3,11 @@
 
 use nym_sdk::mixnet::{NodeIdentity, Recipient};
 use nym_validator_client::models::DescribedGateway;
+use serde::{Deserialize, Serialize};
 
 use crate::{error::Result, Error};
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct IpPacketRouterAddress(pub Recipient);
 
 impl IpPacketRouterAddress {
@@ -68,6 +68,9 @@ pub enum Error {
     #[error("failed to select gateway randomly")]
     FailedToSelectGatewayRandomly,
 
+    #[error("failed to select the best gateway, no gateways available")]
+    FailedToSelectBestGateway,
+
     #[error("gateway {0} doesn't have a description available")]
     NoGatewayDescriptionAvailable(String),
 }
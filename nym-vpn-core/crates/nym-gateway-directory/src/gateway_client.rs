@@ -1,15 +1,17 @@
 // Copyright 2023-2024 - Nym Technologies SA <contact@nymtech.net>
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::{fmt, net::IpAddr};
+use std::{fmt, future::Future, net::IpAddr, sync::Arc};
 
 use nym_sdk::{mixnet::Recipient, UserAgent};
 use nym_topology::IntoGatewayNode;
 use nym_validator_client::{models::DescribedGateway, nym_nodes::SkimmedNode, NymApiClient};
-use tracing::{debug, error, info};
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::{debug, error, info, warn};
 use url::Url;
 
 use crate::{
+    cache::{CacheConfig, CacheKey, CacheLookup, Cached, GatewayCache},
     entries::{
         country::Country,
         gateway::{Gateway, GatewayList},
@@ -24,6 +26,7 @@ pub struct Config {
     pub api_url: Url,
     pub nym_vpn_api_url: Option<Url>,
     pub min_gateway_performance: Option<u8>,
+    pub cache: Option<CacheConfig>,
 }
 
 impl Default for Config {
@@ -68,6 +71,7 @@ impl Config {
             api_url: default_api_url,
             nym_vpn_api_url: Some(default_nym_vpn_api_url),
             min_gateway_performance: None,
+            cache: None,
         }
     }
 
@@ -87,6 +91,7 @@ impl Config {
             api_url,
             nym_vpn_api_url,
             min_gateway_performance,
+            cache: None,
         }
     }
 
@@ -116,29 +121,118 @@ impl Config {
         self.min_gateway_performance = Some(min_gateway_performance);
         self
     }
+
+    // Keep the gateway and country lists on disk, so that we can do without the directory when
+    // it's slow or down
+    pub fn with_cache(mut self, cache: CacheConfig) -> Self {
+        self.cache = Some(cache);
+        self
+    }
 }
 
+#[derive(Clone)]
 pub struct GatewayClient {
     api_client: NymApiClient,
-    nym_vpn_api_client: Option<nym_vpn_api_client::VpnApiClient>,
+    nym_vpn_api_client: Option<Arc<nym_vpn_api_client::VpnApiClient>>,
     min_gateway_performance: Option<u8>,
+    cache: Option<GatewayCache>,
 }
 
 impl GatewayClient {
     pub fn new(config: Config, user_agent: UserAgent) -> Result<Self> {
+        let cache = config.cache.map(|cache| {
+            let api_urls: Vec<_> = std::iter::once(&config.api_url)
+                .chain(config.nym_vpn_api_url.as_ref())
+                .collect();
+            GatewayCache::new(cache, &api_urls)
+        });
         let api_client = NymApiClient::new_with_user_agent(config.api_url, user_agent.clone());
         let nym_vpn_api_client = config
             .nym_vpn_api_url
             .map(|url| nym_vpn_api_client::VpnApiClient::new(url, user_agent.clone()))
-            .transpose()?;
+            .transpose()?
+            .map(Arc::new);
 
         Ok(GatewayClient {
             api_client,
             nym_vpn_api_client,
             min_gateway_performance: config.min_gateway_performance,
+            cache,
         })
     }
 
+    // Stale-while-revalidate: a fresh cached list is returned as is, a stale one is returned while
+    // it's refreshed in the background. If there is no usable list in the cache we wait for the
+    // directory, and only fall back to an expired list if that fails. When the cache is only a
+    // fallback, we always wait for the directory and use any cached list if that fails. After a
+    // failed fetch, any cached list is served right away for a short while instead of waiting for
+    // the directory again.
+    async fn cached<T, F, Fut>(&self, key: CacheKey, fetch: F) -> Result<Cached<Vec<T>>>
+    where
+        T: Serialize + DeserializeOwned + Send + 'static,
+        F: FnOnce(GatewayClient) -> Fut,
+        Fut: Future<Output = Result<Vec<T>>> + Send + 'static,
+    {
+        let Some(ref cache) = self.cache else {
+            return fetch(self.clone()).await.map(Cached::fresh);
+        };
+        let min_gateway_performance = self.min_gateway_performance;
+
+        let fallback = match cache.read(key, min_gateway_performance) {
+            CacheLookup::Fresh(cached) if !cache.fallback_only() => return Ok(cached),
+            CacheLookup::Stale(cached) if !cache.fallback_only() => {
+                if cache.start_refresh(key, min_gateway_performance) {
+                    debug!("Refreshing stale gateway cache: {key:?}");
+                    let cache = cache.clone();
+                    let refresh = fetch(self.clone());
+                    tokio::spawn(async move {
+                        match refresh.await {
+                            Ok(items) => cache.write(key, min_gateway_performance, &items),
+                            Err(err) => {
+                                warn!("Failed to refresh gateway cache {key:?}: {err}");
+                                cache.record_failure(key, min_gateway_performance);
+                            }
+                        }
+                        cache.finish_refresh(key, min_gateway_performance);
+                    });
+                }
+                return Ok(cached);
+            }
+            CacheLookup::Fresh(cached)
+            | CacheLookup::Stale(cached)
+            | CacheLookup::Expired(cached) => Some(Cached {
+                stale: true,
+                ..cached
+            }),
+            CacheLookup::Missing => None,
+        };
+
+        let fallback = match fallback {
+            Some(cached) if cache.recently_failed(key, min_gateway_performance) => {
+                debug!("The directory failed recently, using the cached {key:?}");
+                return Ok(cached);
+            }
+            fallback => fallback,
+        };
+
+        match fetch(self.clone()).await {
+            Ok(items) => {
+                cache.write(key, min_gateway_performance, &items);
+                Ok(Cached::fresh(items))
+            }
+            Err(err) => {
+                cache.record_failure(key, min_gateway_performance);
+                match fallback {
+                    Some(cached) => {
+                        warn!("Failed to fetch {key:?} ({err}), using the cached list");
+                        Ok(cached)
+                    }
+                    None => Err(err),
+                }
+            }
+        }
+    }
+
     async fn lookup_described_gateways(&self) -> Result<Vec<DescribedGateway>> {
         info!("Fetching described gateways from nym-api...");
         self.api_client
@@ -217,6 +311,23 @@ impl GatewayClient {
     }
 
     pub async fn lookup_all_gateways(&self) -> Result<GatewayList> {
+        self.lookup_all_gateways_cached()
+            .await
+            .map(|cached| cached.value)
+    }
+
+    pub async fn lookup_all_gateways_cached(&self) -> Result<Cached<GatewayList>> {
+        self.cached(CacheKey::AllGateways, |client| async move {
+            client
+                .fetch_all_gateways()
+                .await
+                .map(GatewayList::into_inner)
+        })
+        .await
+        .map(|cached| cached.map(GatewayList::new))
+    }
+
+    async fn fetch_all_gateways(&self) -> Result<GatewayList> {
         if let Some(nym_vpn_api_client) = &self.nym_vpn_api_client {
             info!("Fetching all gateways from nym-vpn-api...");
             let mut gateways: Vec<_> = nym_vpn_api_client
@@ -244,6 +355,23 @@ impl GatewayClient {
     }
 
     pub async fn lookup_entry_gateways(&self) -> Result<GatewayList> {
+        self.lookup_entry_gateways_cached()
+            .await
+            .map(|cached| cached.value)
+    }
+
+    pub async fn lookup_entry_gateways_cached(&self) -> Result<Cached<GatewayList>> {
+        self.cached(CacheKey::EntryGateways, |client| async move {
+            client
+                .fetch_entry_gateways()
+                .await
+                .map(GatewayList::into_inner)
+        })
+        .await
+        .map(|cached| cached.map(GatewayList::new))
+    }
+
+    async fn fetch_entry_gateways(&self) -> Result<GatewayList> {
         if let Some(nym_vpn_api_client) = &self.nym_vpn_api_client {
             info!("Fetching entry gateways from nym-vpn-api...");
             let mut entry_gateways: Vec<_> = nym_vpn_api_client
@@ -271,6 +399,23 @@ impl GatewayClient {
     }
 
     pub async fn lookup_exit_gateways(&self) -> Result<GatewayList> {
+        self.lookup_exit_gateways_cached()
+            .await
+            .map(|cached| cached.value)
+    }
+
+    pub async fn lookup_exit_gateways_cached(&self) -> Result<Cached<GatewayList>> {
+        self.cached(CacheKey::ExitGateways, |client| async move {
+            client
+                .fetch_exit_gateways()
+                .await
+                .map(GatewayList::into_inner)
+        })
+        .await
+        .map(|cached| cached.map(GatewayList::new))
+    }
+
+    async fn fetch_exit_gateways(&self) -> Result<GatewayList> {
         if let Some(nym_vpn_api_client) = &self.nym_vpn_api_client {
             info!("Fetching exit gateways from nym-vpn-api...");
             let mut exit_gateways: Vec<_> = nym_vpn_api_client
@@ -298,6 +443,19 @@ impl GatewayClient {
     }
 
     pub async fn lookup_entry_countries(&self) -> Result<Vec<Country>> {
+        self.lookup_entry_countries_cached()
+            .await
+            .map(|cached| cached.value)
+    }
+
+    pub async fn lookup_entry_countries_cached(&self) -> Result<Cached<Vec<Country>>> {
+        self.cached(CacheKey::EntryCountries, |client| async move {
+            client.fetch_entry_countries().await
+        })
+        .await
+    }
+
+    async fn fetch_entry_countries(&self) -> Result<Vec<Country>> {
         // Workaround until we can pass a threshold parameter directly to the nym-vpn-api.
         // Get the full list, which is filtered, and get the countries from that.
         if let Some(min_gateway_performance) = self.min_gateway_performance {
@@ -325,6 +483,19 @@ impl GatewayClient {
     }
 
     pub async fn lookup_exit_countries(&self) -> Result<Vec<Country>> {
+        self.lookup_exit_countries_cached()
+            .await
+            .map(|cached| cached.value)
+    }
+
+    pub async fn lookup_exit_countries_cached(&self) -> Result<Cached<Vec<Country>>> {
+        self.cached(CacheKey::ExitCountries, |client| async move {
+            client.fetch_exit_countries().await
+        })
+        .await
+    }
+
+    async fn fetch_exit_countries(&self) -> Result<Vec<Country>> {
         // Workaround until we can pass a threshold parameter directly to the nym-vpn-api.
         // Get the full list, which is filtered, and get the countries from that.
         if let Some(min_gateway_performance) = self.min_gateway_performance {
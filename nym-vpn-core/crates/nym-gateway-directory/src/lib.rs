@@ -1,15 +1,18 @@
 // Copyright 2023 - Nym Technologies SA <contact@nymtech.net>
 // SPDX-License-Identifier: GPL-3.0-only
 
+mod cache;
 mod entries;
 mod error;
 mod gateway_client;
 mod helpers;
+mod selector;
 
 pub use nym_sdk::mixnet::{NodeIdentity, Recipient};
 pub use nym_validator_client::models::DescribedGateway;
 
 pub use crate::{
+    cache::{CacheConfig, Cached},
     entries::{
         auth_addresses::{AuthAddress, AuthAddresses},
         country::Country,
@@ -20,4 +23,5 @@ pub use crate::{
     },
     error::Error,
     gateway_client::{Config, GatewayClient},
+    selector::{GatewayRole, GatewaySelector, ScoreWeights, ScoringGatewaySelector},
 };
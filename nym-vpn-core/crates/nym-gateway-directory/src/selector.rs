@@ -0,0 +1,385 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use futures::{stream, StreamExt};
+use rand::{seq::SliceRandom, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use tokio::net::TcpStream;
+use tracing::{debug, info};
+
+use crate::{
+    entries::gateway::{Gateway, GatewayList, Location},
+    error::Result,
+    Error,
+};
+
+const LATENCY_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+const LATENCY_PROBE_CONCURRENCY: usize = 32;
+
+// The latency and distance at which the respective scores drop to one half
+const HALF_SCORE_LATENCY_MS: f64 = 100.0;
+const HALF_SCORE_DISTANCE_KM: f64 = 1000.0;
+
+// We pick randomly among the top scoring gateways, to avoid everyone piling onto the same one
+const TOP_CANDIDATES: usize = 5;
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+// Used when a gateway has not been probed, so that it's ranked in between the ones that passed
+// and the ones that failed
+const UNKNOWN_PROBE_SCORE: f64 = 0.5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GatewayRole {
+    Entry,
+    Exit,
+}
+
+// Picks a gateway from a list. Implement this to plug in a different selection strategy.
+#[async_trait::async_trait]
+pub trait GatewaySelector: Send + Sync {
+    async fn select(&self, gateways: &GatewayList, role: GatewayRole) -> Result<Gateway>;
+}
+
+// The relative weight of each part of the score. They don't need to add up to one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScoreWeights {
+    pub latency: f64,
+    pub performance: f64,
+    pub probe: f64,
+    pub distance: f64,
+}
+
+impl Default for ScoreWeights {
+    fn default() -> Self {
+        ScoreWeights {
+            latency: 0.4,
+            performance: 0.3,
+            probe: 0.2,
+            distance: 0.1,
+        }
+    }
+}
+
+// Scores the gateways by measured latency, performance, the outcome of the last probe and the
+// distance from a reference location, and picks one of the best.
+#[derive(Clone, Debug, Default)]
+pub struct ScoringGatewaySelector {
+    weights: ScoreWeights,
+    reference_location: Option<Location>,
+    measure_latency: bool,
+    seed: Option<u64>,
+}
+
+impl ScoringGatewaySelector {
+    pub fn new() -> Self {
+        ScoringGatewaySelector {
+            measure_latency: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_weights(mut self, weights: ScoreWeights) -> Self {
+        self.weights = weights;
+        self
+    }
+
+    // Prefer gateways close to this location
+    pub fn with_reference_location(mut self, location: Option<Location>) -> Self {
+        self.reference_location = location;
+        self
+    }
+
+    pub fn with_latency_measurement(mut self, measure_latency: bool) -> Self {
+        self.measure_latency = measure_latency;
+        self
+    }
+
+    // Make the selection deterministic, for a given set of scores
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn score(&self, gateway: &Gateway, latency: Option<Duration>, role: GatewayRole) -> f64 {
+        let mut parts = vec![
+            (self.weights.performance, performance_score(gateway)),
+            (self.weights.probe, probe_score(gateway, role)),
+        ];
+        if self.measure_latency {
+            parts.push((self.weights.latency, latency_score(latency)));
+        }
+        if let Some(ref reference) = self.reference_location {
+            parts.push((self.weights.distance, distance_score(gateway, reference)));
+        }
+
+        let total_weight: f64 = parts.iter().map(|(weight, _)| weight).sum();
+        if total_weight <= 0.0 {
+            return 0.0;
+        }
+        parts
+            .iter()
+            .map(|(weight, score)| weight * score)
+            .sum::<f64>()
+            / total_weight
+    }
+
+    fn choose(&self, mut scored: Vec<(Gateway, f64)>) -> Option<Gateway> {
+        scored.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        scored.truncate(TOP_CANDIDATES);
+
+        let mut rng = match self.seed {
+            Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+            None => ChaCha8Rng::from_entropy(),
+        };
+        scored
+            .choose_weighted(&mut rng, |(_, score)| score.max(f64::EPSILON))
+            .ok()
+            .map(|(gateway, _)| gateway.clone())
+    }
+}
+
+#[async_trait::async_trait]
+impl GatewaySelector for ScoringGatewaySelector {
+    async fn select(&self, gateways: &GatewayList, role: GatewayRole) -> Result<Gateway> {
+        let gateways = gateways.clone().into_inner();
+        let latencies = if self.measure_latency {
+            measure_latencies(&gateways).await
+        } else {
+            vec![None; gateways.len()]
+        };
+
+        // If none of the gateways could be reached, e.g. because a firewall is blocking us, the
+        // latencies tell us nothing and are left out of the score
+        let selector = if self.measure_latency && latencies.iter().all(Option::is_none) {
+            debug!("No gateway could be reached, ignoring latency");
+            self.clone().with_latency_measurement(false)
+        } else {
+            self.clone()
+        };
+
+        let scored: Vec<_> = gateways
+            .into_iter()
+            .zip(latencies)
+            .map(|(gateway, latency)| {
+                let score = selector.score(&gateway, latency, role);
+                debug!(
+                    "Gateway {} scored {score:.3} (latency: {latency:?})",
+                    gateway.identity()
+                );
+                (gateway, score)
+            })
+            .collect();
+
+        let gateway = selector
+            .choose(scored)
+            .ok_or(Error::FailedToSelectBestGateway)?;
+        info!("Selected best gateway: {}", gateway.identity());
+        Ok(gateway)
+    }
+}
+
+fn performance_score(gateway: &Gateway) -> f64 {
+    f64::from(gateway.performance.unwrap_or(0).min(100)) / 100.0
+}
+
+fn probe_score(gateway: &Gateway, role: GatewayRole) -> f64 {
+    let Some(ref probe) = gateway.last_probe else {
+        return UNKNOWN_PROBE_SCORE;
+    };
+    let checks = match role {
+        GatewayRole::Entry => vec![
+            probe.outcome.as_entry.can_connect,
+            probe.outcome.as_entry.can_route,
+        ],
+        GatewayRole::Exit => match probe.outcome.as_exit {
+            Some(ref exit) => vec![
+                exit.can_connect,
+                exit.can_route_ip_v4,
+                exit.can_route_ip_external_v4,
+                exit.can_route_ip_v6,
+                exit.can_route_ip_external_v6,
+            ],
+            None => return 0.0,
+        },
+    };
+    checks.iter().filter(|passed| **passed).count() as f64 / checks.len() as f64
+}
+
+fn latency_score(latency: Option<Duration>) -> f64 {
+    // Gateways we couldn't reach get nothing
+    latency.map_or(0.0, |latency| {
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        1.0 / (1.0 + latency_ms / HALF_SCORE_LATENCY_MS)
+    })
+}
+
+fn distance_score(gateway: &Gateway, reference: &Location) -> f64 {
+    match gateway.location.as_ref().filter(|l| has_coordinates(l)) {
+        Some(location) if has_coordinates(reference) => {
+            1.0 / (1.0 + distance_km(location, reference) / HALF_SCORE_DISTANCE_KM)
+        }
+        _ => 0.5,
+    }
+}
+
+// Locations from the nym-api only have the country set
+fn has_coordinates(location: &Location) -> bool {
+    location.latitude != 0.0 || location.longitude != 0.0
+}
+
+// Great-circle distance using the haversine formula
+fn distance_km(a: &Location, b: &Location) -> f64 {
+    let (lat_a, lat_b) = (a.latitude.to_radians(), b.latitude.to_radians());
+    let d_lat = lat_b - lat_a;
+    let d_lon = (b.longitude - a.longitude).to_radians();
+    let h = (d_lat / 2.0).sin().powi(2) + lat_a.cos() * lat_b.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * h.sqrt().asin()
+}
+
+// The time it takes to open a TCP connection to the client port of each gateway, in order. This
+// is the connection the mixnet client makes to its entry gateway. Wireguard runs over UDP to a
+// different port, but on the same host, so the TCP handshake still tells the round trip time to it.
+async fn measure_latencies(gateways: &[Gateway]) -> Vec<Option<Duration>> {
+    stream::iter(gateways)
+        .map(measure_latency)
+        .buffered(LATENCY_PROBE_CONCURRENCY)
+        .collect()
+        .await
+}
+
+async fn measure_latency(gateway: &Gateway) -> Option<Duration> {
+    let host = gateway.host.as_ref()?;
+    let port = gateway.clients_ws_port.or(gateway.clients_wss_port)?;
+    let start = Instant::now();
+    // Joining an IPv6 address and the port into a string would need brackets to parse
+    let connect = async {
+        match host {
+            nym_topology::NetworkAddress::IpAddr(ip) => {
+                TcpStream::connect(SocketAddr::new(*ip, port)).await
+            }
+            nym_topology::NetworkAddress::Hostname(hostname) => {
+                TcpStream::connect((hostname.as_str(), port)).await
+            }
+        }
+    };
+    match tokio::time::timeout(LATENCY_PROBE_TIMEOUT, connect).await {
+        Ok(Ok(_)) => Some(start.elapsed()),
+        _ => {
+            debug!(
+                "Failed to measure latency to gateway {}",
+                gateway.identity()
+            );
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nym_sdk::mixnet::ed25519;
+
+    use super::*;
+    use crate::{Entry, Exit, Probe, ProbeOutcome};
+
+    fn gateway(performance: u8, can_route_ip_v6: bool) -> Gateway {
+        Gateway {
+            identity: *ed25519::KeyPair::new(&mut rand::rngs::OsRng).public_key(),
+            location: None,
+            ipr_address: None,
+            authenticator_address: None,
+            last_probe: Some(Probe {
+                last_updated_utc: String::new(),
+                outcome: ProbeOutcome {
+                    as_entry: Entry {
+                        can_connect: true,
+                        can_route: true,
+                    },
+                    as_exit: Some(Exit {
+                        can_connect: true,
+                        can_route_ip_v4: true,
+                        can_route_ip_external_v4: true,
+                        can_route_ip_v6,
+                        can_route_ip_external_v6: can_route_ip_v6,
+                    }),
+                },
+            }),
+            host: None,
+            clients_ws_port: None,
+            clients_wss_port: None,
+            performance: Some(performance),
+        }
+    }
+
+    #[test]
+    fn failed_exit_probe_lowers_score() {
+        let selector = ScoringGatewaySelector::new();
+        let latency = Some(Duration::from_millis(50));
+        let good = selector.score(&gateway(90, true), latency, GatewayRole::Exit);
+        let no_ipv6 = selector.score(&gateway(90, false), latency, GatewayRole::Exit);
+        assert!(good > no_ipv6);
+
+        // The exit probe doesn't matter for entry gateways
+        let good = selector.score(&gateway(90, true), latency, GatewayRole::Entry);
+        let no_ipv6 = selector.score(&gateway(90, false), latency, GatewayRole::Entry);
+        assert_eq!(good, no_ipv6);
+    }
+
+    #[test]
+    fn closer_gateway_scores_higher() {
+        let oslo = Location {
+            two_letter_iso_country_code: "NO".to_string(),
+            latitude: 59.91,
+            longitude: 10.75,
+        };
+        let tokyo = Location {
+            two_letter_iso_country_code: "JP".to_string(),
+            latitude: 35.68,
+            longitude: 139.69,
+        };
+        let selector = ScoringGatewaySelector::new()
+            .with_latency_measurement(false)
+            .with_reference_location(Some(oslo.clone()));
+        let mut near = gateway(80, true);
+        near.location = Some(oslo);
+        let mut far = gateway(80, true);
+        far.location = Some(tokyo);
+        assert!(
+            selector.score(&near, None, GatewayRole::Entry)
+                > selector.score(&far, None, GatewayRole::Entry)
+        );
+    }
+
+    #[tokio::test]
+    async fn latency_is_measured_over_ipv6() {
+        let listener = tokio::net::TcpListener::bind("[::1]:0").await.unwrap();
+        let mut gateway = gateway(90, true);
+        gateway.host = Some(nym_topology::NetworkAddress::IpAddr(
+            std::net::Ipv6Addr::LOCALHOST.into(),
+        ));
+        gateway.clients_ws_port = Some(listener.local_addr().unwrap().port());
+        assert!(measure_latency(&gateway).await.is_some());
+
+        // Nothing listens there anymore
+        drop(listener);
+        assert!(measure_latency(&gateway).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn seeded_selection_is_deterministic() {
+        let gateways = GatewayList::new(vec![gateway(90, true), gateway(85, true)]);
+        let selector = ScoringGatewaySelector::new()
+            .with_latency_measurement(false)
+            .with_seed(42);
+        let first = selector.select(&gateways, GatewayRole::Exit).await.unwrap();
+        for _ in 0..10 {
+            let gateway = selector.select(&gateways, GatewayRole::Exit).await.unwrap();
+            assert_eq!(gateway.identity(), first.identity());
+        }
+    }
+}
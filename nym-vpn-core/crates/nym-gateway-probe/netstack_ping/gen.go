@@ -25,6 +25,10 @@ typedef struct NetstackResponseRef {
   uint16_t sent_hosts;
   uint16_t received_hosts;
   bool can_resolve_dns;
+  uint64_t handshake_ms;
+  uint64_t throughput_bytes;
+  uint64_t throughput_duration_ms;
+  struct StringRef throughput_error;
 } NetstackResponseRef;
 
 typedef struct NetstackRequestRef {
@@ -38,6 +42,9 @@ typedef struct NetstackRequestRef {
   uint8_t num_ping;
   uint64_t send_timeout_sec;
   uint64_t recv_timeout_sec;
+  struct StringRef throughput_url;
+  uint64_t throughput_timeout_sec;
+  uint64_t throughput_max_bytes;
 } NetstackRequestRef;
 
 // hack from: https://stackoverflow.com/a/69904977
@@ -223,22 +230,28 @@ type NetstackRequest struct {
 	ping_hosts       []string
 	ping_ips         []string
 	num_ping         uint8
-	send_timeout_sec uint64
-	recv_timeout_sec uint64
+	send_timeout_sec       uint64
+	recv_timeout_sec       uint64
+	throughput_url         string
+	throughput_timeout_sec uint64
+	throughput_max_bytes   uint64
 }
 
 func newNetstackRequest(p C.NetstackRequestRef) NetstackRequest {
 	return NetstackRequest{
-		wg_ip:            newString(p.wg_ip),
-		private_key:      newString(p.private_key),
-		public_key:       newString(p.public_key),
-		endpoint:         newString(p.endpoint),
-		dns:              newString(p.dns),
-		ping_hosts:       new_list_mapper(newString)(p.ping_hosts),
-		ping_ips:         new_list_mapper(newString)(p.ping_ips),
-		num_ping:         newC_uint8_t(p.num_ping),
-		send_timeout_sec: newC_uint64_t(p.send_timeout_sec),
-		recv_timeout_sec: newC_uint64_t(p.recv_timeout_sec),
+		wg_ip:                  newString(p.wg_ip),
+		private_key:            newString(p.private_key),
+		public_key:             newString(p.public_key),
+		endpoint:               newString(p.endpoint),
+		dns:                    newString(p.dns),
+		ping_hosts:             new_list_mapper(newString)(p.ping_hosts),
+		ping_ips:               new_list_mapper(newString)(p.ping_ips),
+		num_ping:               newC_uint8_t(p.num_ping),
+		send_timeout_sec:       newC_uint64_t(p.send_timeout_sec),
+		recv_timeout_sec:       newC_uint64_t(p.recv_timeout_sec),
+		throughput_url:         newString(p.throughput_url),
+		throughput_timeout_sec: newC_uint64_t(p.throughput_timeout_sec),
+		throughput_max_bytes:   newC_uint64_t(p.throughput_max_bytes),
 	}
 }
 func cntNetstackRequest(s *NetstackRequest, cnt *uint) [0]C.NetstackRequestRef {
@@ -248,36 +261,47 @@ func cntNetstackRequest(s *NetstackRequest, cnt *uint) [0]C.NetstackRequestRef {
 }
 func refNetstackRequest(p *NetstackRequest, buffer *[]byte) C.NetstackRequestRef {
 	return C.NetstackRequestRef{
-		wg_ip:            refString(&p.wg_ip, buffer),
-		private_key:      refString(&p.private_key, buffer),
-		public_key:       refString(&p.public_key, buffer),
-		endpoint:         refString(&p.endpoint, buffer),
-		dns:              refString(&p.dns, buffer),
-		ping_hosts:       ref_list_mapper(refString)(&p.ping_hosts, buffer),
-		ping_ips:         ref_list_mapper(refString)(&p.ping_ips, buffer),
-		num_ping:         refC_uint8_t(&p.num_ping, buffer),
-		send_timeout_sec: refC_uint64_t(&p.send_timeout_sec, buffer),
-		recv_timeout_sec: refC_uint64_t(&p.recv_timeout_sec, buffer),
+		wg_ip:                  refString(&p.wg_ip, buffer),
+		private_key:            refString(&p.private_key, buffer),
+		public_key:             refString(&p.public_key, buffer),
+		endpoint:               refString(&p.endpoint, buffer),
+		dns:                    refString(&p.dns, buffer),
+		ping_hosts:             ref_list_mapper(refString)(&p.ping_hosts, buffer),
+		ping_ips:               ref_list_mapper(refString)(&p.ping_ips, buffer),
+		num_ping:               refC_uint8_t(&p.num_ping, buffer),
+		send_timeout_sec:       refC_uint64_t(&p.send_timeout_sec, buffer),
+		recv_timeout_sec:       refC_uint64_t(&p.recv_timeout_sec, buffer),
+		throughput_url:         refString(&p.throughput_url, buffer),
+		throughput_timeout_sec: refC_uint64_t(&p.throughput_timeout_sec, buffer),
+		throughput_max_bytes:   refC_uint64_t(&p.throughput_max_bytes, buffer),
 	}
 }
 
 type NetstackResponse struct {
-	can_handshake   bool
-	sent_ips        uint16
-	received_ips    uint16
-	sent_hosts      uint16
-	received_hosts  uint16
-	can_resolve_dns bool
+	can_handshake          bool
+	sent_ips               uint16
+	received_ips           uint16
+	sent_hosts             uint16
+	received_hosts         uint16
+	can_resolve_dns        bool
+	handshake_ms           uint64
+	throughput_bytes       uint64
+	throughput_duration_ms uint64
+	throughput_error       string
 }
 
 func newNetstackResponse(p C.NetstackResponseRef) NetstackResponse {
 	return NetstackResponse{
-		can_handshake:   newC_bool(p.can_handshake),
-		sent_ips:        newC_uint16_t(p.sent_ips),
-		received_ips:    newC_uint16_t(p.received_ips),
-		sent_hosts:      newC_uint16_t(p.sent_hosts),
-		received_hosts:  newC_uint16_t(p.received_hosts),
-		can_resolve_dns: newC_bool(p.can_resolve_dns),
+		can_handshake:          newC_bool(p.can_handshake),
+		sent_ips:               newC_uint16_t(p.sent_ips),
+		received_ips:           newC_uint16_t(p.received_ips),
+		sent_hosts:             newC_uint16_t(p.sent_hosts),
+		received_hosts:         newC_uint16_t(p.received_hosts),
+		can_resolve_dns:        newC_bool(p.can_resolve_dns),
+		handshake_ms:           newC_uint64_t(p.handshake_ms),
+		throughput_bytes:       newC_uint64_t(p.throughput_bytes),
+		throughput_duration_ms: newC_uint64_t(p.throughput_duration_ms),
+		throughput_error:       newString(p.throughput_error),
 	}
 }
 func cntNetstackResponse(s *NetstackResponse, cnt *uint) [0]C.NetstackResponseRef {
@@ -285,12 +309,16 @@ func cntNetstackResponse(s *NetstackResponse, cnt *uint) [0]C.NetstackResponseRe
 }
 func refNetstackResponse(p *NetstackResponse, buffer *[]byte) C.NetstackResponseRef {
 	return C.NetstackResponseRef{
-		can_handshake:   refC_bool(&p.can_handshake, buffer),
-		sent_ips:        refC_uint16_t(&p.sent_ips, buffer),
-		received_ips:    refC_uint16_t(&p.received_ips, buffer),
-		sent_hosts:      refC_uint16_t(&p.sent_hosts, buffer),
-		received_hosts:  refC_uint16_t(&p.received_hosts, buffer),
-		can_resolve_dns: refC_bool(&p.can_resolve_dns, buffer),
+		can_handshake:          refC_bool(&p.can_handshake, buffer),
+		sent_ips:               refC_uint16_t(&p.sent_ips, buffer),
+		received_ips:           refC_uint16_t(&p.received_ips, buffer),
+		sent_hosts:             refC_uint16_t(&p.sent_hosts, buffer),
+		received_hosts:         refC_uint16_t(&p.received_hosts, buffer),
+		can_resolve_dns:        refC_bool(&p.can_resolve_dns, buffer),
+		handshake_ms:           refC_uint64_t(&p.handshake_ms, buffer),
+		throughput_bytes:       refC_uint64_t(&p.throughput_bytes, buffer),
+		throughput_duration_ms: refC_uint64_t(&p.throughput_duration_ms, buffer),
+		throughput_error:       refString(&p.throughput_error, buffer),
 	}
 }
 func main() {}
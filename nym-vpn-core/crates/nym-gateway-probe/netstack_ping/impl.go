@@ -3,8 +3,11 @@ package main
 import (
 	"bytes"
 	"fmt"
+	"io"
 	"log"
+	"net/http"
 	"net/netip"
+	"strconv"
 	"strings"
 	"time"
 
@@ -42,15 +45,15 @@ func (Netstack) ping(req NetstackRequest) NetstackResponse {
 	ipc.WriteString(req.endpoint)
 	ipc.WriteString("\nallowed_ip=0.0.0.0/0\n")
 
-	response := NetstackResponse{false, 0, 0, 0, 0, false}
+	response := NetstackResponse{}
 
 	dev.IpcSet(ipc.String())
 	err = dev.Up()
 	if err != nil {
 		log.Panic(err)
 	}
-
-	response.can_handshake = true
+	// The handshake is started by the first ping
+	deviceUp := time.Now()
 
 	for _, host := range req.ping_hosts {
 		for i := uint8(0); i < req.num_ping; i++ {
@@ -81,9 +84,86 @@ func (Netstack) ping(req NetstackRequest) NetstackResponse {
 		}
 	}
 
+	if handshake, ok := lastHandshake(dev); ok {
+		response.can_handshake = true
+		response.handshake_ms = uint64(handshake.Sub(deviceUp).Milliseconds())
+		log.Printf("Handshake completed after %dms\n", response.handshake_ms)
+	}
+
+	if req.throughput_url != "" {
+		log.Printf("Downloading %s", req.throughput_url)
+		received, duration, err := measureThroughput(req.throughput_url, req.throughput_timeout_sec, req.throughput_max_bytes, tnet)
+		response.throughput_bytes = received
+		response.throughput_duration_ms = uint64(duration.Milliseconds())
+		if err != nil {
+			log.Printf("Failed to download: %v\n", err)
+			response.throughput_error = err.Error()
+		} else {
+			log.Printf("Downloaded %d bytes in %v\n", received, duration)
+		}
+	}
+
+	dev.Close()
 	return response
 }
 
+// The time of the latest handshake with the peer, if there has been one
+func lastHandshake(dev *device.Device) (time.Time, bool) {
+	config, err := dev.IpcGet()
+	if err != nil {
+		return time.Time{}, false
+	}
+	var sec, nsec int64
+	for _, line := range strings.Split(config, "\n") {
+		key, value, found := strings.Cut(line, "=")
+		if !found {
+			continue
+		}
+		switch key {
+		case "last_handshake_time_sec":
+			sec, _ = strconv.ParseInt(value, 10, 64)
+		case "last_handshake_time_nsec":
+			nsec, _ = strconv.ParseInt(value, 10, 64)
+		}
+	}
+	if sec == 0 && nsec == 0 {
+		return time.Time{}, false
+	}
+	return time.Unix(sec, nsec), true
+}
+
+// Used when the request doesn't set a timeout, since the download must never be unbounded
+const defaultThroughputTimeoutSec = 10
+
+// Downloads the url through the tunnel, up to maxBytes when it isn't zero, and returns how much was
+// received and how long it took. What was received before a failure is still returned.
+func measureThroughput(url string, timeoutSec uint64, maxBytes uint64, tnet *netstack.Net) (uint64, time.Duration, error) {
+	if timeoutSec == 0 {
+		timeoutSec = defaultThroughputTimeoutSec
+	}
+	client := http.Client{
+		Transport: &http.Transport{DialContext: tnet.DialContext},
+		Timeout:   time.Second * time.Duration(timeoutSec),
+	}
+
+	start := time.Now()
+	resp, err := client.Get(url)
+	if err != nil {
+		return 0, time.Since(start), err
+	}
+	defer resp.Body.Close()
+	if resp.StatusCode != http.StatusOK {
+		return 0, time.Since(start), fmt.Errorf("unexpected status: %s", resp.Status)
+	}
+
+	var body io.Reader = resp.Body
+	if maxBytes > 0 {
+		body = io.LimitReader(resp.Body, int64(maxBytes))
+	}
+	received, err := io.Copy(io.Discard, body)
+	return uint64(received), time.Since(start), err
+}
+
 func sendPing(address string, seq uint8, send_timeout_secs uint64, recieve_timout_secs uint64, tnet *netstack.Net) (time.Duration, error) {
 	socket, err := tnet.Dial("ping4", address)
 	if err != nil {
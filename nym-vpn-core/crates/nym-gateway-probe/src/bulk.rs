@@ -0,0 +1,282 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    fmt::Write as _,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::{stream, Stream, StreamExt};
+use nym_gateway_directory::{EntryPoint, GatewayList, NodeIdentity};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+use tracing::*;
+
+use crate::{types::ProbeOutcome, ProbeConfig};
+
+const DEFAULT_CONCURRENCY: usize = 4;
+const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Clone, Debug)]
+pub struct BulkProbeConfig {
+    // How many gateways are probed at the same time
+    pub concurrency: usize,
+    // A probe that takes longer than this is reported as failed
+    pub timeout: Duration,
+    // How each of the gateways is probed
+    pub probe: ProbeConfig,
+}
+
+impl Default for BulkProbeConfig {
+    fn default() -> Self {
+        BulkProbeConfig {
+            concurrency: DEFAULT_CONCURRENCY,
+            timeout: DEFAULT_PROBE_TIMEOUT,
+            probe: ProbeConfig::default(),
+        }
+    }
+}
+
+// Which gateways out of the directory are probed
+#[derive(Clone, Debug)]
+pub enum GatewaySelection {
+    All,
+    // By two letter ISO country code
+    Country(String),
+    Identities(Vec<NodeIdentity>),
+}
+
+impl GatewaySelection {
+    // Identities that aren't in the directory are kept, so that they are reported as failing
+    pub fn select(&self, gateways: &GatewayList) -> Vec<NodeIdentity> {
+        match self {
+            GatewaySelection::All => gateways
+                .clone()
+                .into_iter()
+                .map(|gateway| gateway.identity)
+                .collect(),
+            GatewaySelection::Country(country) => gateways
+                .gateways_located_at(country.to_uppercase())
+                .map(|gateway| *gateway.identity())
+                .collect(),
+            GatewaySelection::Identities(identities) => identities.clone(),
+        }
+    }
+}
+
+// The outcome of probing one gateway out of many. The outcome is missing when the probe itself
+// failed, in which case the failure says why.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GatewayProbeReport {
+    pub gateway: String,
+    pub country: Option<String>,
+    pub duration_ms: u64,
+    pub outcome: Option<ProbeOutcome>,
+    pub failure: Option<String>,
+}
+
+const CSV_HEADER: &str = "gateway,country,duration_ms,entry_can_connect,entry_can_route,\
+exit_can_connect,exit_can_route_ip_v4,exit_can_route_ip_external_v4,exit_can_route_ip_v6,\
+exit_can_route_ip_external_v6,wg_can_register,wg_can_handshake,wg_can_resolve_dns,\
+wg_ping_hosts_performance,wg_ping_ips_performance,mixnet_rtt_p50_ms,exit_icmp_rtt_p50_ms,\
+wg_handshake_ms,wg_throughput_mbit_per_sec,failure";
+
+impl GatewayProbeReport {
+    pub fn csv_header() -> &'static str {
+        CSV_HEADER
+    }
+
+    pub fn to_csv_row(&self) -> String {
+        let outcome = self.outcome.as_ref();
+        let exit = outcome.and_then(|outcome| outcome.as_exit.as_ref());
+        let wg = outcome.and_then(|outcome| outcome.wg.as_ref());
+        // Values that weren't probed are left empty
+        let field = |value: Option<String>| value.unwrap_or_default();
+        [
+            self.gateway.clone(),
+            field(self.country.clone()),
+            self.duration_ms.to_string(),
+            field(outcome.map(|outcome| outcome.as_entry.can_connect.to_string())),
+            field(outcome.map(|outcome| outcome.as_entry.can_route.to_string())),
+            field(exit.map(|exit| exit.can_connect.to_string())),
+            field(exit.map(|exit| exit.can_route_ip_v4.to_string())),
+            field(exit.map(|exit| exit.can_route_ip_external_v4.to_string())),
+            field(exit.map(|exit| exit.can_route_ip_v6.to_string())),
+            field(exit.map(|exit| exit.can_route_ip_external_v6.to_string())),
+            field(wg.map(|wg| wg.can_register.to_string())),
+            field(wg.map(|wg| wg.can_handshake.to_string())),
+            field(wg.map(|wg| wg.can_resolve_dns.to_string())),
+            field(wg.map(|wg| wg.ping_hosts_performance.to_string())),
+            field(wg.map(|wg| wg.ping_ips_performance.to_string())),
+            field(
+                outcome
+                    .and_then(|outcome| outcome.as_entry.mixnet_rtt.as_ref())
+                    .map(|rtt| format!("{:.1}", rtt.p50_ms)),
+            ),
+            field(
+                exit.and_then(|exit| exit.icmp_rtt.as_ref())
+                    .map(|rtt| format!("{:.1}", rtt.p50_ms)),
+            ),
+            field(
+                wg.and_then(|wg| wg.handshake_ms)
+                    .map(|handshake_ms| handshake_ms.to_string()),
+            ),
+            field(
+                wg.and_then(|wg| wg.throughput.as_ref())
+                    .map(|throughput| format!("{:.2}", throughput.mbit_per_sec)),
+            ),
+            field(self.failure.clone()),
+        ]
+        .iter()
+        .map(|value| csv_escape(value))
+        .collect::<Vec<_>>()
+        .join(",")
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// A table with a line per gateway, followed by the totals
+pub fn summary_table(reports: &[GatewayProbeReport]) -> String {
+    let yes_no = |value: Option<bool>| match value {
+        Some(true) => "yes",
+        Some(false) => "no",
+        None => "-",
+    };
+
+    let mut table = String::new();
+    writeln!(
+        table,
+        "{:<44} {:<7} {:>9} {:<5} {:<4} {:<4} {:<4} {:<3}  failure",
+        "gateway", "country", "time (s)", "entry", "exit", "ipv4", "ipv6", "wg"
+    )
+    .ok();
+    for report in reports {
+        let outcome = report.outcome.as_ref();
+        let exit = outcome.and_then(|outcome| outcome.as_exit.as_ref());
+        let wg = outcome.and_then(|outcome| outcome.wg.as_ref());
+        writeln!(
+            table,
+            "{:<44} {:<7} {:>9.1} {:<5} {:<4} {:<4} {:<4} {:<3}  {}",
+            report.gateway,
+            report.country.as_deref().unwrap_or("-"),
+            report.duration_ms as f64 / 1000.0,
+            yes_no(outcome.map(|outcome| outcome.as_entry.can_route)),
+            yes_no(exit.map(|exit| exit.can_connect)),
+            yes_no(exit.map(|exit| exit.can_route_ip_external_v4)),
+            yes_no(exit.map(|exit| exit.can_route_ip_external_v6)),
+            yes_no(wg.map(|wg| wg.can_handshake)),
+            report.failure.as_deref().unwrap_or(""),
+        )
+        .ok();
+    }
+    let succeeded = reports.iter().filter(|report| report.succeeded()).count();
+    writeln!(
+        table,
+        "\n{succeeded} of {} gateways passed, {} failed",
+        reports.len(),
+        reports.len() - succeeded
+    )
+    .ok();
+    table
+}
+
+async fn probe_with_timeout(
+    identity: NodeIdentity,
+    gateways: &GatewayList,
+    timeout: Duration,
+    config: &ProbeConfig,
+) -> GatewayProbeReport {
+    let started = Instant::now();
+    let entry_point = EntryPoint::Gateway { identity };
+    let probe = crate::probe_from_list(entry_point, gateways, config);
+    let result = tokio::time::timeout(timeout, probe).await;
+    let duration_ms = started.elapsed().as_millis() as u64;
+
+    let (outcome, failure) = match result {
+        Ok(Ok(result)) => {
+            let failure = result.outcome.failure_reason().map(ToString::to_string);
+            (Some(result.outcome), failure)
+        }
+        Ok(Err(err)) => (None, Some(format!("{err:#}"))),
+        Err(_) => (
+            None,
+            Some(format!("timed out after {}s", timeout.as_secs())),
+        ),
+    };
+    if let Some(failure) = &failure {
+        warn!("Probe of gateway {identity} failed: {failure}");
+    }
+    GatewayProbeReport {
+        gateway: identity.to_base58_string(),
+        country: gateways
+            .gateway_with_identity(&identity)
+            .and_then(|gateway| gateway.two_letter_iso_country_code())
+            .map(ToString::to_string),
+        duration_ms,
+        outcome,
+        failure,
+    }
+}
+
+// Probes the gateways, a few at a time, and yields the reports in the order the probes finish
+pub fn probe_gateways(
+    identities: Vec<NodeIdentity>,
+    gateways: &GatewayList,
+    config: BulkProbeConfig,
+) -> impl Stream<Item = GatewayProbeReport> + '_ {
+    info!(
+        "Probing {} gateways, {} at a time",
+        identities.len(),
+        config.concurrency
+    );
+    let concurrency = config.concurrency.max(1);
+    // A probe that timed out can leave its netstack ping running, which keeps the permit
+    let permits = Arc::new(Semaphore::new(concurrency));
+    stream::iter(identities)
+        .map(move |identity| {
+            let mut config = config.clone();
+            let permits = permits.clone();
+            async move {
+                config.probe.concurrency_permit = permits.acquire_owned().await.ok().map(Arc::new);
+                probe_with_timeout(identity, gateways, config.timeout, &config.probe).await
+            }
+        })
+        .buffer_unordered(concurrency)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_row_of_failed_probe() {
+        let report = GatewayProbeReport {
+            gateway: "gw".to_string(),
+            country: None,
+            duration_ms: 1500,
+            outcome: None,
+            failure: Some("failed to look up gateway, \"gw\"".to_string()),
+        };
+        let row = report.to_csv_row();
+        assert_eq!(
+            row,
+            "gw,,1500,,,,,,,,,,,,,,,,,\"failed to look up gateway, \"\"gw\"\"\""
+        );
+        assert_eq!(
+            row.matches(',').count() - 1,
+            GatewayProbeReport::csv_header().matches(',').count()
+        );
+    }
+}
@@ -93,25 +93,25 @@ pub fn check_for_icmp_beacon_reply(
     our_ips: IpPair,
 ) -> Option<ConnectionStatusEvent> {
     match is_icmp_beacon_reply(packet, icmp_beacon_identifier, our_ips.ipv4) {
-        Some(IcmpBeaconReply::TunDeviceReply) => {
+        Some(IcmpBeaconReply::TunDeviceReply { sequence_number }) => {
             log::debug!("Received ping response from ipr tun device");
-            return Some(ConnectionStatusEvent::Icmpv4IprTunDevicePingReply);
+            return Some(ConnectionStatusEvent::Icmpv4IprTunDevicePingReply { sequence_number });
         }
-        Some(IcmpBeaconReply::ExternalPingReply(_source)) => {
+        Some(IcmpBeaconReply::ExternalPingReply(source)) => {
             log::debug!("Received ping response from an external ip through the ipr");
-            return Some(ConnectionStatusEvent::Icmpv4IprExternalPingReply);
+            return Some(ConnectionStatusEvent::Icmpv4IprExternalPingReply(source));
         }
         None => {}
     }
 
     match is_icmp_v6_beacon_reply(packet, icmp_beacon_identifier, our_ips.ipv6) {
-        Some(Icmpv6BeaconReply::TunDeviceReply) => {
+        Some(Icmpv6BeaconReply::TunDeviceReply { sequence_number }) => {
             log::debug!("Received ping v6 response from ipr tun device");
-            return Some(ConnectionStatusEvent::Icmpv6IprTunDevicePingReply);
+            return Some(ConnectionStatusEvent::Icmpv6IprTunDevicePingReply { sequence_number });
         }
-        Some(Icmpv6BeaconReply::ExternalPingReply(_source)) => {
+        Some(Icmpv6BeaconReply::ExternalPingReply(source)) => {
             log::debug!("Received ping v6 response from an external ip through the ipr");
-            return Some(ConnectionStatusEvent::Icmpv6IprExternalPingReply);
+            return Some(ConnectionStatusEvent::Icmpv6IprExternalPingReply(source));
         }
         None => {}
     }
@@ -1,10 +1,11 @@
 use std::{
+    collections::HashMap,
     net::{Ipv4Addr, Ipv6Addr},
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use anyhow::bail;
+use anyhow::{anyhow, bail};
 use base64::{engine::general_purpose, Engine as _};
 use bytes::BytesMut;
 use dns_lookup::lookup_host;
@@ -14,10 +15,10 @@ use nym_authenticator_requests::v1::response::{
     AuthenticatorResponseData, PendingRegistrationResponse, RegisteredResponse,
 };
 use nym_config::defaults::NymNetworkDetails;
-use nym_connection_monitor::self_ping_and_wait;
+use nym_connection_monitor::{self_ping_and_wait, self_ping_round_trips, ConnectionStatusEvent};
 use nym_gateway_directory::{
-    AuthAddress, Config as GatewayDirectoryConfig, EntryPoint,
-    GatewayClient as GatewayDirectoryClient, GatewayList, IpPacketRouterAddress,
+    AuthAddress, Config as GatewayDirectoryConfig, EntryPoint, Gateway,
+    GatewayClient as GatewayDirectoryClient, GatewayList, IpPacketRouterAddress, Recipient,
 };
 use nym_ip_packet_client::{IprClientConnect, SharedMixnetClient};
 use nym_ip_packet_requests::{
@@ -29,23 +30,51 @@ use nym_sdk::mixnet::{MixnetClient, MixnetClientBuilder, ReconstructedMessage};
 use nym_wireguard_types::{
     registration::RegistrationData, ClientMessage, GatewayClient, InitMessage, PeerPublicKey,
 };
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, OwnedSemaphorePermit};
 use tokio_util::codec::Decoder;
 use tracing::*;
 use types::WgProbeResults;
 
 use crate::{
     icmp::{check_for_icmp_beacon_reply, icmp_identifier, send_ping_v4, send_ping_v6},
-    types::{Entry, Exit},
+    types::{Entry, Exit, RttStats, Throughput},
 };
 
+mod bulk;
 mod error;
 mod icmp;
 mod netstack;
+mod serve;
 mod types;
 
+pub use bulk::{
+    probe_gateways, summary_table, BulkProbeConfig, GatewayProbeReport, GatewaySelection,
+};
 pub use error::{Error, Result};
-pub use types::{IpPingReplies, ProbeOutcome, ProbeResult};
+pub use serve::{serve, ServeConfig};
+pub use types::{IpPingReplies, ProbeOutcome, ProbeResult, RttStats, Throughput};
+
+const MIXNET_SELF_PINGS: usize = 10;
+const MIXNET_SELF_PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, Default)]
+pub struct ProbeConfig {
+    // Downloaded through the wireguard tunnel to measure its throughput, when set
+    pub throughput_url: Option<String>,
+    // How long the download can take, and how much of it is downloaded, when set
+    pub throughput_timeout: Option<Duration>,
+    pub throughput_max_bytes: Option<u64>,
+    // The slot of a bulk probe, that the netstack ping holds on to until it returns, even when the
+    // probe has timed out by then
+    pub(crate) concurrency_permit: Option<Arc<OwnedSemaphorePermit>>,
+}
+
+// The ICMP pings to the tun device of the ip packet router, that we measure the round trip time of
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum TunDevicePing {
+    V4(u16),
+    V6(u16),
+}
 
 pub async fn fetch_gateways() -> anyhow::Result<GatewayList> {
     lookup_gateways().await
@@ -55,16 +84,94 @@ pub async fn fetch_gateways_with_ipr() -> anyhow::Result<GatewayList> {
     Ok(lookup_gateways().await?.into_exit_gateways())
 }
 
-pub async fn probe(entry_point: EntryPoint) -> anyhow::Result<ProbeResult> {
-    // Setup the entry gateways
+pub async fn probe(entry_point: EntryPoint, config: &ProbeConfig) -> anyhow::Result<ProbeResult> {
     let gateways = lookup_gateways().await?;
-    let entry_gateway = entry_point.lookup_gateway(&gateways).await?;
-    let exit_router_address = entry_gateway.ipr_address;
-    let authenticator = entry_gateway.authenticator_address;
-    let gateway_host = entry_gateway.host.clone().unwrap();
+    probe_from_list(entry_point, &gateways, config).await
+}
+
+// Probe a gateway out of a list that was already fetched from the directory
+pub async fn probe_from_list(
+    entry_point: EntryPoint,
+    gateways: &GatewayList,
+    config: &ProbeConfig,
+) -> anyhow::Result<ProbeResult> {
+    let entry_gateway = entry_point.lookup_gateway(gateways).await?;
+    probe_gateway(&entry_gateway, None, config).await
+}
+
+// Probe an ip packet router given by its address, such as a standalone one that isn't in the
+// directory, as exit through the entry gateway out of a list that was already fetched from the
+// directory. The wireguard registration with the entry gateway is not probed.
+pub async fn probe_exit(
+    entry_point: EntryPoint,
+    exit_address: Recipient,
+    gateways: &GatewayList,
+    config: &ProbeConfig,
+) -> anyhow::Result<ProbeResult> {
+    let entry_gateway = entry_point.lookup_gateway(gateways).await?;
+    probe_gateway(&entry_gateway, Some(exit_address), config).await
+}
+
+// What is probed through the entry gateway
+struct ProbePlan {
+    exit_router_address: Option<IpPacketRouterAddress>,
+    // The authenticator and host of the entry gateway, to register with over wireguard
+    wg_gateway: Option<(Option<AuthAddress>, nym_topology::NetworkAddress)>,
+    // Set when the exit isn't the ip packet router of the entry gateway
+    exit: Option<String>,
+}
+
+impl ProbePlan {
+    fn new(entry_gateway: &Gateway, exit_address: Option<Recipient>) -> anyhow::Result<Self> {
+        // Without an explicit exit, the entry gateway is probed as exit as well
+        let exit_router_address = match exit_address {
+            Some(exit_address) => Some(IpPacketRouterAddress(exit_address)),
+            None => entry_gateway.ipr_address,
+        };
+        // The wireguard registration with the entry gateway is only probed when it is probed as
+        // exit too
+        let wg_gateway = match exit_address {
+            Some(_) => None,
+            None => {
+                let gateway_host = entry_gateway
+                    .host
+                    .clone()
+                    .ok_or_else(|| anyhow!("gateway has no host"))?;
+                Some((entry_gateway.authenticator_address, gateway_host))
+            }
+        };
+        Ok(ProbePlan {
+            exit_router_address,
+            wg_gateway,
+            exit: exit_address.map(|exit_address| exit_address.to_string()),
+        })
+    }
+
+    fn failed_to_connect(self, entry_gateway: &Gateway) -> ProbeResult {
+        ProbeResult {
+            gateway: entry_gateway.identity().to_string(),
+            exit: self.exit,
+            outcome: ProbeOutcome {
+                as_entry: Entry::fail_to_connect(),
+                as_exit: None,
+                wg: None,
+            },
+        }
+    }
+}
+
+async fn probe_gateway(
+    entry_gateway: &Gateway,
+    exit_address: Option<Recipient>,
+    config: &ProbeConfig,
+) -> anyhow::Result<ProbeResult> {
+    let plan = ProbePlan::new(entry_gateway, exit_address)?;
     let entry_gateway_id = entry_gateway.identity();
 
     info!("Probing gateway: {entry_gateway:?}");
+    if let Some(exit) = &plan.exit {
+        info!("Probing exit: {exit}");
+    }
 
     // Connect to the mixnet
     let mixnet_client = MixnetClientBuilder::new_ephemeral()
@@ -79,14 +186,7 @@ pub async fn probe(entry_point: EntryPoint) -> anyhow::Result<ProbeResult> {
         Ok(mixnet_client) => mixnet_client,
         Err(err) => {
             error!("Failed to connect to mixnet: {err}");
-            return Ok(ProbeResult {
-                gateway: entry_gateway_id.to_string(),
-                outcome: ProbeOutcome {
-                    as_entry: Entry::fail_to_connect(),
-                    as_exit: None,
-                    wg: None,
-                },
-            });
+            return Ok(plan.failed_to_connect(entry_gateway));
         }
     };
 
@@ -100,14 +200,16 @@ pub async fn probe(entry_point: EntryPoint) -> anyhow::Result<ProbeResult> {
 
     // Now that we have a connected mixnet client, we can start pinging
     let shared_mixnet_client = SharedMixnetClient::from_shared(&shared_client);
-    let outcome = do_ping(shared_mixnet_client.clone(), exit_router_address).await;
-
-    let wg_outcome = if let Some(authenticator) = authenticator {
-        wg_probe(authenticator, shared_client, &gateway_host)
-            .await
-            .unwrap_or_default()
-    } else {
-        WgProbeResults::default()
+    let outcome = do_ping(shared_mixnet_client.clone(), plan.exit_router_address).await;
+
+    let wg_outcome = match plan.wg_gateway {
+        Some((Some(authenticator), gateway_host)) => Some(
+            wg_probe(authenticator, shared_client, &gateway_host, config)
+                .await
+                .unwrap_or_default(),
+        ),
+        Some((None, _)) => Some(WgProbeResults::default()),
+        None => None,
     };
 
     let mixnet_client = shared_mixnet_client.lock().await.take().unwrap();
@@ -115,9 +217,10 @@ pub async fn probe(entry_point: EntryPoint) -> anyhow::Result<ProbeResult> {
 
     // Disconnect the mixnet client gracefully
     outcome.map(|mut outcome| {
-        outcome.wg = Some(wg_outcome);
+        outcome.wg = wg_outcome;
         ProbeResult {
             gateway: entry_gateway.clone(),
+            exit: plan.exit,
             outcome,
         }
     })
@@ -127,6 +230,7 @@ async fn wg_probe(
     authenticator: AuthAddress,
     shared_mixnet_client: Arc<Mutex<Option<MixnetClient>>>,
     gateway_host: &nym_topology::NetworkAddress,
+    config: &ProbeConfig,
 ) -> anyhow::Result<WgProbeResults> {
     let auth_shared_client =
         nym_authenticator_client::SharedMixnetClient::from_shared(&shared_mixnet_client);
@@ -213,15 +317,31 @@ async fn wg_probe(
         wg_outcome.can_register = true;
 
         if wg_outcome.can_register {
-            let netstack_request = netstack::NetstackRequest {
+            let mut netstack_request = netstack::NetstackRequest {
                 wg_ip: registered_data.private_ip.to_string(),
                 private_key: private_key_hex,
                 public_key: public_key_hex,
                 endpoint: wg_endpoint.clone(),
+                throughput_url: config.throughput_url.clone().unwrap_or_default(),
                 ..Default::default()
             };
+            if let Some(timeout) = config.throughput_timeout {
+                // Zero would leave the download without a timeout, so round up to a second
+                netstack_request.throughput_timeout_sec = timeout.as_secs().max(1);
+            }
+            if let Some(max_bytes) = config.throughput_max_bytes {
+                netstack_request.throughput_max_bytes = max_bytes;
+            }
 
-            let netstack_response = NetstackCallImpl::ping(&netstack_request);
+            // The netstack ping blocks for as long as it runs, which would stall the other probes
+            // on this task and keep a timeout around the probe from firing. It can't be cancelled,
+            // so it keeps the concurrency permit until it's done.
+            let concurrency_permit = config.concurrency_permit.clone();
+            let netstack_response = tokio::task::spawn_blocking(move || {
+                let _concurrency_permit = concurrency_permit;
+                NetstackCallImpl::ping(&netstack_request)
+            })
+            .await?;
 
             info!("Wireguard probe response: {:?}", netstack_response);
             wg_outcome.can_handshake = netstack_response.can_handshake;
@@ -230,6 +350,16 @@ async fn wg_probe(
                 netstack_response.received_hosts as f32 / netstack_response.sent_hosts as f32;
             wg_outcome.ping_ips_performance =
                 netstack_response.received_ips as f32 / netstack_response.sent_ips as f32;
+            wg_outcome.handshake_ms =
+                Some(netstack_response.handshake_ms).filter(|_| netstack_response.can_handshake);
+            wg_outcome.throughput = config.throughput_url.clone().map(|url| {
+                Throughput::new(
+                    url,
+                    netstack_response.throughput_bytes,
+                    netstack_response.throughput_duration_ms,
+                    Some(netstack_response.throughput_error).filter(|error| !error.is_empty()),
+                )
+            });
         }
     }
 
@@ -283,9 +413,29 @@ async fn do_ping(
     }
     info!("Successfully mixnet pinged ourselves");
 
+    // Now that we know the pings come back, measure how long they take
+    let mixnet_rtt = match self_ping_round_trips(
+        shared_mixnet_client.nym_address().await,
+        shared_mixnet_client.inner(),
+        MIXNET_SELF_PINGS,
+        MIXNET_SELF_PING_TIMEOUT,
+    )
+    .await
+    {
+        Ok(round_trips) => RttStats::from_round_trips(MIXNET_SELF_PINGS, &round_trips),
+        Err(err) => {
+            warn!("Failed to measure the mixnet round trip time: {err}");
+            None
+        }
+    };
+    let entry = Entry {
+        mixnet_rtt,
+        ..Entry::success()
+    };
+
     let Some(exit_router_address) = exit_router_address else {
         return Ok(ProbeOutcome {
-            as_entry: Entry::success(),
+            as_entry: entry,
             as_exit: None,
             wg: None,
         });
@@ -299,7 +449,7 @@ async fn do_ping(
     let mut ipr_client = IprClientConnect::new(shared_mixnet_client.clone()).await;
     let Ok(our_ips) = ipr_client.connect(exit_router_address.0, None).await else {
         return Ok(ProbeOutcome {
-            as_entry: Entry::success(),
+            as_entry: entry,
             as_exit: Some(Exit::fail_to_connect()),
             wg: None,
         });
@@ -308,15 +458,24 @@ async fn do_ping(
     info!("Using mixnet VPN IP addresses: {our_ips}");
 
     // Step 3: perform ICMP connectivity checks for the exit gateway
-    send_icmp_pings(shared_mixnet_client.clone(), our_ips, exit_router_address).await?;
-    listen_for_icmp_ping_replies(shared_mixnet_client.clone(), our_ips).await
+    let tun_device_pings =
+        send_icmp_pings(shared_mixnet_client.clone(), our_ips, exit_router_address).await?;
+    let exit =
+        listen_for_icmp_ping_replies(shared_mixnet_client.clone(), our_ips, tun_device_pings)
+            .await?;
+    Ok(ProbeOutcome {
+        as_entry: entry,
+        as_exit: Some(exit),
+        wg: None,
+    })
 }
 
 async fn send_icmp_pings(
     shared_mixnet_client: SharedMixnetClient,
     our_ips: IpPair,
     exit_router_address: IpPacketRouterAddress,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<HashMap<TunDevicePing, Instant>> {
+    let mut tun_device_pings = HashMap::new();
     let ipr_tun_ip_v4 = Ipv4Addr::new(10, 0, 0, 1);
     let ipr_tun_ip_v6 = Ipv6Addr::new(0x2001, 0xdb8, 0xa160, 0, 0, 0, 0, 0x1);
     let external_ip_v4 = Ipv4Addr::new(8, 8, 8, 8);
@@ -333,6 +492,7 @@ async fn send_icmp_pings(
             exit_router_address,
         )
         .await?;
+        tun_device_pings.insert(TunDevicePing::V4(ii), Instant::now());
         send_ping_v4(
             shared_mixnet_client.clone(),
             our_ips,
@@ -349,6 +509,7 @@ async fn send_icmp_pings(
             exit_router_address,
         )
         .await?;
+        tun_device_pings.insert(TunDevicePing::V6(ii), Instant::now());
         send_ping_v6(
             shared_mixnet_client.clone(),
             our_ips,
@@ -358,18 +519,20 @@ async fn send_icmp_pings(
         )
         .await?;
     }
-    Ok(())
+    Ok(tun_device_pings)
 }
 
 async fn listen_for_icmp_ping_replies(
     shared_mixnet_client: SharedMixnetClient,
     our_ips: IpPair,
-) -> anyhow::Result<ProbeOutcome> {
+    tun_device_pings: HashMap<TunDevicePing, Instant>,
+) -> anyhow::Result<Exit> {
     // HACK: take it out of the shared mixnet client
     let mut mixnet_client = shared_mixnet_client.inner().lock().await.take().unwrap();
     let mut multi_ip_packet_decoder =
         MultiIpPacketCodec::new(nym_ip_packet_requests::codec::BUFFER_TIMEOUT);
     let mut registered_replies = IpPingReplies::new();
+    let mut round_trips = Vec::new();
 
     loop {
         tokio::select! {
@@ -389,6 +552,11 @@ async fn listen_for_icmp_ping_replies(
                         info!("Received ICMP echo reply from exit gateway");
                         info!("Connection event: {:?}", event);
                         registered_replies.register_event(&event);
+                        let sent_at = tun_device_ping(&event)
+                            .and_then(|ping| tun_device_pings.get(&ping));
+                        if let Some(sent_at) = sent_at {
+                            round_trips.push(sent_at.elapsed());
+                        }
                     }
                 }
             }
@@ -402,19 +570,24 @@ async fn listen_for_icmp_ping_replies(
         .await
         .replace(mixnet_client);
 
-    Ok(ProbeOutcome {
-        as_entry: Entry::success(),
-        as_exit: Some(Exit {
-            can_connect: true,
-            can_route_ip_v4: registered_replies.ipr_tun_ip_v4,
-            can_route_ip_external_v4: registered_replies.external_ip_v4,
-            can_route_ip_v6: registered_replies.ipr_tun_ip_v6,
-            can_route_ip_external_v6: registered_replies.external_ip_v6,
-        }),
-        wg: None,
+    Ok(Exit {
+        icmp_rtt: RttStats::from_round_trips(tun_device_pings.len(), &round_trips),
+        ..Exit::from_ping_replies(&registered_replies)
     })
 }
 
+fn tun_device_ping(event: &ConnectionStatusEvent) -> Option<TunDevicePing> {
+    match event {
+        ConnectionStatusEvent::Icmpv4IprTunDevicePingReply { sequence_number } => {
+            Some(TunDevicePing::V4(*sequence_number))
+        }
+        ConnectionStatusEvent::Icmpv6IprTunDevicePingReply { sequence_number } => {
+            Some(TunDevicePing::V6(*sequence_number))
+        }
+        _ => None,
+    }
+}
+
 fn unpack_data_response(reconstructed_message: &ReconstructedMessage) -> Option<DataResponse> {
     match IpPacketResponse::from_reconstructed_message(reconstructed_message) {
         Ok(response) => match response.data {
@@ -439,3 +612,54 @@ fn unpack_data_response(reconstructed_message: &ReconstructedMessage) -> Option<
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nym_crypto::asymmetric::{encryption, identity};
+
+    use super::*;
+
+    fn gateway() -> Gateway {
+        let mut rng = rand::thread_rng();
+        Gateway {
+            identity: *identity::KeyPair::new(&mut rng).public_key(),
+            location: None,
+            ipr_address: None,
+            authenticator_address: None,
+            last_probe: None,
+            host: Some(nym_topology::NetworkAddress::IpAddr(
+                Ipv4Addr::new(192, 0, 2, 1).into(),
+            )),
+            clients_ws_port: None,
+            clients_wss_port: None,
+            performance: None,
+        }
+    }
+
+    #[test]
+    fn an_exit_given_by_address_is_probed_without_wireguard() {
+        let mut rng = rand::thread_rng();
+        let entry_gateway = gateway();
+        let exit_address = Recipient::new(
+            *identity::KeyPair::new(&mut rng).public_key(),
+            *encryption::KeyPair::new(&mut rng).public_key(),
+            *gateway().identity(),
+        );
+
+        let plan = ProbePlan::new(&entry_gateway, Some(exit_address)).unwrap();
+        assert_eq!(
+            plan.exit_router_address.map(|address| address.0),
+            Some(exit_address)
+        );
+        assert!(plan.wg_gateway.is_none());
+
+        let result = plan.failed_to_connect(&entry_gateway);
+        assert_eq!(result.exit, Some(exit_address.to_string()));
+        assert!(result.outcome.wg.is_none());
+
+        // Without one, the entry gateway is probed as exit, and over wireguard
+        let plan = ProbePlan::new(&entry_gateway, None).unwrap();
+        assert!(plan.exit.is_none());
+        assert!(plan.wg_gateway.is_some());
+    }
+}
@@ -1,37 +1,152 @@
-use std::path::PathBuf;
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
 
-use anyhow::anyhow;
-use clap::Parser;
+use anyhow::{anyhow, bail};
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use futures::StreamExt;
 use nym_config::defaults::setup_env;
-use nym_gateway_directory::EntryPoint;
-use nym_gateway_probe::ProbeResult;
+use nym_gateway_directory::{EntryPoint, GatewayList, NodeIdentity, Recipient};
+use nym_gateway_probe::{
+    BulkProbeConfig, GatewayProbeReport, GatewaySelection, ProbeConfig, ServeConfig,
+};
 use tracing::*;
 
 #[derive(Parser)]
 #[clap(author, version, about)]
+#[command(args_conflicts_with_subcommands = true)]
+// The serve command probes all gateways by default, but here they have to be selected
+#[command(group(
+    ArgGroup::new("bulk_options")
+        .args(["exit_only", "concurrency", "timeout_secs"])
+        .multiple(true)
+        .requires("bulk")
+))]
 struct CliArgs {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Path pointing to an env file describing the network.
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     config_env_file: Option<PathBuf>,
 
-    #[arg(long, short)]
+    #[arg(long, short, conflicts_with = "bulk")]
     gateway: Option<String>,
 
-    #[arg(long, short)]
+    /// Probe the ip packet router with this nym address as exit, through the gateway given by
+    /// --gateway, or a random one. Meant for standalone ip packet routers that are not in the
+    /// directory.
+    #[arg(long, conflicts_with = "bulk")]
+    exit_address: Option<String>,
+
+    #[command(flatten)]
+    selection: SelectionArgs,
+
+    #[command(flatten)]
+    bulk: BulkArgs,
+
+    #[command(flatten)]
+    throughput: ThroughputArgs,
+
+    /// How the reports of the probed gateways are written.
+    #[arg(long, value_enum, default_value_t, requires = "bulk")]
+    format: ReportFormat,
+
+    #[arg(long, short, global = true)]
     no_log: bool,
 }
 
+#[derive(Subcommand)]
+enum Command {
+    /// Keep probing the gateways, and serve the results as Prometheus metrics and JSON.
+    Serve(ServeArgs),
+}
+
+#[derive(Args)]
+#[group(id = "bulk", multiple = false)]
+struct SelectionArgs {
+    /// Probe all gateways in the directory.
+    #[arg(long)]
+    all: bool,
+
+    /// Probe all gateways in the country, given by its two letter ISO code.
+    #[arg(long)]
+    country: Option<String>,
+
+    /// Probe the gateways listed in the file, one identity per line.
+    #[arg(long)]
+    from_file: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct BulkArgs {
+    /// Only probe gateways that run an ip packet router.
+    #[arg(long)]
+    exit_only: bool,
+
+    /// How many gateways are probed at the same time.
+    #[arg(long)]
+    concurrency: Option<usize>,
+
+    /// How long a single gateway probe can take, in seconds.
+    #[arg(long)]
+    timeout_secs: Option<u64>,
+}
+
+#[derive(Args)]
+struct ThroughputArgs {
+    /// Measure the throughput of the wireguard tunnel by downloading this url through it.
+    #[arg(long)]
+    throughput_url: Option<String>,
+
+    /// How long the throughput download can take, in seconds.
+    #[arg(long, requires = "throughput_url", value_parser = clap::value_parser!(u64).range(1..))]
+    throughput_timeout_secs: Option<u64>,
+
+    /// Stop the throughput download after this many bytes.
+    #[arg(long, requires = "throughput_url")]
+    throughput_max_bytes: Option<u64>,
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    /// Where the metrics and the JSON API are served.
+    #[arg(long, default_value = "127.0.0.1:9465")]
+    listen: SocketAddr,
+
+    /// How often the gateways are probed, in seconds.
+    #[arg(long)]
+    interval_secs: Option<u64>,
+
+    /// How many probe results are kept per gateway.
+    #[arg(long)]
+    history: Option<usize>,
+
+    /// The gateways to probe. Without any, all gateways in the directory are probed.
+    #[command(flatten)]
+    selection: SelectionArgs,
+
+    #[command(flatten)]
+    bulk: BulkArgs,
+
+    #[command(flatten)]
+    throughput: ThroughputArgs,
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+enum ReportFormat {
+    /// A JSON object per gateway, on a line each, written as the probes finish.
+    #[default]
+    JsonLines,
+    /// A CSV row per gateway, written as the probes finish.
+    Csv,
+    /// A table of all gateways, written once all probes have finished.
+    Summary,
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    match run().await {
-        Ok(ref result) => {
-            let json = serde_json::to_string_pretty(result)?;
-            println!("{}", json);
-        }
-        Err(err) => {
-            eprintln!("An error occurred: {err}");
-            std::process::exit(1)
-        }
+    if let Err(err) = run().await {
+        eprintln!("An error occurred: {err}");
+        std::process::exit(1)
     }
     Ok(())
 }
@@ -44,13 +159,15 @@ fn setup_logging() {
         .add_directive("hyper::proto=info".parse().unwrap())
         .add_directive("netlink_proto=info".parse().unwrap());
 
+    // The reports go to stdout, so the logs are kept out of their way
     tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
         .with_env_filter(filter)
         .compact()
         .init();
 }
 
-async fn run() -> anyhow::Result<ProbeResult> {
+async fn run() -> anyhow::Result<()> {
     let args = CliArgs::parse();
     if !args.no_log {
         setup_logging();
@@ -58,13 +175,139 @@ async fn run() -> anyhow::Result<ProbeResult> {
     debug!("{:?}", nym_bin_common::bin_info_local_vergen!());
     setup_env(args.config_env_file.as_ref());
 
-    let gateway = if let Some(gateway) = args.gateway {
-        EntryPoint::from_base58_string(&gateway)?
+    if let Some(Command::Serve(serve_args)) = &args.command {
+        return run_serve(serve_args).await;
+    }
+    if let Some(selection) = gateway_selection(&args.selection)? {
+        return run_bulk(&args, selection).await;
+    }
+
+    let config = probe_config(&args.throughput);
+    let result = if let Some(exit_address) = &args.exit_address {
+        let exit_address = Recipient::try_from_base58_string(exit_address)
+            .map_err(|err| anyhow!("invalid exit address {exit_address}: {err}"))?;
+        // Any gateway will do as entry
+        let gateways = nym_gateway_probe::fetch_gateways().await?;
+        let gateway = if let Some(gateway) = &args.gateway {
+            EntryPoint::from_base58_string(gateway)?
+        } else {
+            random_gateway(&gateways)?
+        };
+        nym_gateway_probe::probe_exit(gateway, exit_address, &gateways, &config).await?
+    } else {
+        let gateway = if let Some(gateway) = &args.gateway {
+            EntryPoint::from_base58_string(gateway)?
+        } else {
+            fetch_random_gateway_with_ipr().await?
+        };
+        nym_gateway_probe::probe(gateway, &config).await?
+    };
+    println!("{}", serde_json::to_string_pretty(&result)?);
+    Ok(())
+}
+
+async fn run_bulk(args: &CliArgs, selection: GatewaySelection) -> anyhow::Result<()> {
+    let gateways = if args.bulk.exit_only {
+        nym_gateway_probe::fetch_gateways_with_ipr().await?
     } else {
-        fetch_random_gateway_with_ipr().await?
+        nym_gateway_probe::fetch_gateways().await?
     };
 
-    nym_gateway_probe::probe(gateway).await
+    let identities = selection.select(&gateways);
+    if identities.is_empty() {
+        bail!("No gateways to probe");
+    }
+
+    let config = bulk_probe_config(&args.bulk, &args.throughput);
+
+    if let ReportFormat::Csv = args.format {
+        println!("{}", GatewayProbeReport::csv_header());
+    }
+    let mut reports = Vec::new();
+    let mut probes = std::pin::pin!(nym_gateway_probe::probe_gateways(
+        identities, &gateways, config
+    ));
+    while let Some(report) = probes.next().await {
+        match args.format {
+            ReportFormat::JsonLines => println!("{}", serde_json::to_string(&report)?),
+            ReportFormat::Csv => println!("{}", report.to_csv_row()),
+            ReportFormat::Summary => reports.push(report),
+        }
+    }
+    if let ReportFormat::Summary = args.format {
+        reports.sort_by(|a, b| (&a.country, &a.gateway).cmp(&(&b.country, &b.gateway)));
+        print!("{}", nym_gateway_probe::summary_table(&reports));
+    }
+    Ok(())
+}
+
+async fn run_serve(args: &ServeArgs) -> anyhow::Result<()> {
+    let selection = gateway_selection(&args.selection)?.unwrap_or(GatewaySelection::All);
+    let mut config = ServeConfig {
+        listen_address: args.listen,
+        exit_only: args.bulk.exit_only,
+        bulk: bulk_probe_config(&args.bulk, &args.throughput),
+        ..Default::default()
+    };
+    if let Some(interval_secs) = args.interval_secs {
+        config.interval = Duration::from_secs(interval_secs);
+    }
+    if let Some(history) = args.history {
+        config.history_len = history;
+    }
+    nym_gateway_probe::serve(selection, config).await
+}
+
+// None when a single gateway is probed
+fn gateway_selection(args: &SelectionArgs) -> anyhow::Result<Option<GatewaySelection>> {
+    let selection = if let Some(path) = &args.from_file {
+        GatewaySelection::Identities(read_gateway_identities(path)?)
+    } else if let Some(country) = &args.country {
+        GatewaySelection::Country(country.clone())
+    } else if args.all {
+        GatewaySelection::All
+    } else {
+        return Ok(None);
+    };
+    Ok(Some(selection))
+}
+
+fn bulk_probe_config(args: &BulkArgs, throughput: &ThroughputArgs) -> BulkProbeConfig {
+    let mut config = BulkProbeConfig {
+        probe: probe_config(throughput),
+        ..Default::default()
+    };
+    if let Some(concurrency) = args.concurrency {
+        config.concurrency = concurrency;
+    }
+    if let Some(timeout_secs) = args.timeout_secs {
+        config.timeout = Duration::from_secs(timeout_secs);
+    }
+    config
+}
+
+fn probe_config(args: &ThroughputArgs) -> ProbeConfig {
+    ProbeConfig {
+        throughput_url: args.throughput_url.clone(),
+        throughput_timeout: args.throughput_timeout_secs.map(Duration::from_secs),
+        throughput_max_bytes: args.throughput_max_bytes,
+        ..Default::default()
+    }
+}
+
+// One gateway identity per line. Empty lines and lines starting with '#' are skipped.
+fn read_gateway_identities(path: &PathBuf) -> anyhow::Result<Vec<NodeIdentity>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| anyhow!("failed to read {}: {err}", path.display()))?;
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            NodeIdentity::from_base58_string(line)
+                .map_err(|err| anyhow!("invalid gateway identity {line}: {err}"))
+        })
+        .collect()
 }
 
 async fn fetch_random_gateway_with_ipr() -> anyhow::Result<EntryPoint> {
@@ -78,3 +321,12 @@ async fn fetch_random_gateway_with_ipr() -> anyhow::Result<EntryPoint> {
         identity: *gateway.identity(),
     })
 }
+
+fn random_gateway(gateways: &GatewayList) -> anyhow::Result<EntryPoint> {
+    let gateway = gateways
+        .random_gateway()
+        .ok_or(anyhow!("No gateways returned by nym-api"))?;
+    Ok(EntryPoint::Gateway {
+        identity: *gateway.identity(),
+    })
+}
@@ -15,6 +15,11 @@ pub struct NetstackRequest {
     pub num_ping: u8,
     pub send_timeout_sec: u64,
     pub recv_timeout_sec: u64,
+    // Downloaded through the tunnel to measure its throughput, unless empty
+    pub throughput_url: String,
+    pub throughput_timeout_sec: u64,
+    // The download stops after this many bytes, unless zero
+    pub throughput_max_bytes: u64,
 }
 
 impl Default for NetstackRequest {
@@ -30,6 +35,9 @@ impl Default for NetstackRequest {
             num_ping: 3,
             send_timeout_sec: 1,
             recv_timeout_sec: 2,
+            throughput_url: Default::default(),
+            throughput_timeout_sec: 10,
+            throughput_max_bytes: 10 * 1024 * 1024,
         }
     }
 }
@@ -42,6 +50,12 @@ pub struct NetstackResponse {
     pub sent_hosts: u16,
     pub received_hosts: u16,
     pub can_resolve_dns: bool,
+    // From bringing the device up to the first handshake, zero when there was none
+    pub handshake_ms: u64,
+    pub throughput_bytes: u64,
+    pub throughput_duration_ms: u64,
+    // Empty when the download succeeded
+    pub throughput_error: String,
 }
 
 #[rust2go::r2g]
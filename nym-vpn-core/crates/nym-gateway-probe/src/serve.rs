@@ -0,0 +1,492 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    fmt::Write as _,
+    future::IntoFuture,
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use futures::StreamExt;
+use nym_gateway_directory::NodeIdentity;
+use serde::Serialize;
+use tokio::{net::TcpListener, sync::RwLock, time::MissedTickBehavior};
+use tracing::*;
+
+use crate::{
+    bulk::{probe_gateways, BulkProbeConfig, GatewayProbeReport, GatewaySelection},
+    types::{Exit, ProbeOutcome, WgProbeResults},
+};
+
+const DEFAULT_LISTEN_PORT: u16 = 9465;
+const DEFAULT_PROBE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+const DEFAULT_HISTORY_LEN: usize = 96;
+
+#[derive(Clone, Debug)]
+pub struct ServeConfig {
+    // Where the metrics and the JSON API are served
+    pub listen_address: SocketAddr,
+    // How often a round of probes is started. A round that takes longer is cut short, and the
+    // gateways it didn't get to are probed first in the next one.
+    pub interval: Duration,
+    // How many probe results are kept per gateway
+    pub history_len: usize,
+    // Only probe gateways that run an ip packet router
+    pub exit_only: bool,
+    pub bulk: BulkProbeConfig,
+}
+
+impl Default for ServeConfig {
+    fn default() -> Self {
+        ServeConfig {
+            listen_address: (Ipv4Addr::LOCALHOST, DEFAULT_LISTEN_PORT).into(),
+            interval: DEFAULT_PROBE_INTERVAL,
+            history_len: DEFAULT_HISTORY_LEN,
+            exit_only: false,
+            bulk: BulkProbeConfig::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct ProbeRecord {
+    // Seconds since the unix epoch
+    probed_at: u64,
+    #[serde(flatten)]
+    report: GatewayProbeReport,
+}
+
+#[derive(Debug, Serialize)]
+struct GatewayStatus {
+    gateway: String,
+    country: Option<String>,
+    success_ratio: f64,
+    probes: usize,
+    latest: ProbeRecord,
+}
+
+#[derive(Debug, Serialize)]
+struct GatewayHistory {
+    gateway: String,
+    country: Option<String>,
+    success_ratio: f64,
+    probes: Vec<ProbeRecord>,
+}
+
+// The latest probe results of each gateway, oldest first. A gateway is only added once it has a
+// result, so the history of a gateway is never empty.
+#[derive(Debug)]
+struct ProbeHistory {
+    gateways: BTreeMap<String, VecDeque<ProbeRecord>>,
+    history_len: usize,
+    rounds: u64,
+}
+
+type SharedProbeHistory = Arc<RwLock<ProbeHistory>>;
+
+impl ProbeHistory {
+    fn new(history_len: usize) -> Self {
+        ProbeHistory {
+            gateways: BTreeMap::new(),
+            history_len: history_len.max(1),
+            rounds: 0,
+        }
+    }
+
+    // Forget the gateways that are no longer probed, such as the ones that left the directory
+    fn retain_gateways(&mut self, identities: &[NodeIdentity]) {
+        let identities: HashSet<_> = identities
+            .iter()
+            .map(NodeIdentity::to_base58_string)
+            .collect();
+        self.gateways
+            .retain(|gateway, _| identities.contains(gateway));
+    }
+
+    fn last_probed_at(&self, gateway: &NodeIdentity) -> Option<u64> {
+        let history = self.gateways.get(&gateway.to_base58_string())?;
+        Some(history.back()?.probed_at)
+    }
+
+    fn record(&mut self, report: GatewayProbeReport, probed_at: u64) {
+        let history = self.gateways.entry(report.gateway.clone()).or_default();
+        history.push_back(ProbeRecord { probed_at, report });
+        while history.len() > self.history_len {
+            history.pop_front();
+        }
+    }
+
+    fn statuses(&self) -> Vec<GatewayStatus> {
+        self.gateways
+            .iter()
+            .filter_map(|(gateway, history)| {
+                let latest = history.back()?.clone();
+                Some(GatewayStatus {
+                    gateway: gateway.clone(),
+                    country: latest.report.country.clone(),
+                    success_ratio: success_ratio(history),
+                    probes: history.len(),
+                    latest,
+                })
+            })
+            .collect()
+    }
+
+    fn history(&self, gateway: &str) -> Option<GatewayHistory> {
+        let history = self.gateways.get(gateway)?;
+        Some(GatewayHistory {
+            gateway: gateway.to_string(),
+            country: history.back()?.report.country.clone(),
+            success_ratio: success_ratio(history),
+            probes: history.iter().cloned().collect(),
+        })
+    }
+
+    // In the Prometheus text format
+    fn metrics(&self) -> String {
+        let mut metrics = String::new();
+        writeln!(
+            metrics,
+            "# HELP nym_gateway_probe_rounds_total Probe rounds that have finished\n\
+             # TYPE nym_gateway_probe_rounds_total counter\n\
+             nym_gateway_probe_rounds_total {}",
+            self.rounds
+        )
+        .ok();
+        for (name, help, value) in GATEWAY_METRICS {
+            writeln!(metrics, "# HELP {name} {help}\n# TYPE {name} gauge").ok();
+            for history in self.gateways.values() {
+                let Some(latest) = history.back() else {
+                    continue;
+                };
+                if let Some(value) = value(history) {
+                    writeln!(
+                        metrics,
+                        "{name}{{gateway=\"{}\",country=\"{}\"}} {value}",
+                        escape_label(&latest.report.gateway),
+                        escape_label(latest.report.country.as_deref().unwrap_or_default()),
+                    )
+                    .ok();
+                }
+            }
+        }
+        metrics
+    }
+}
+
+type GatewayMetric = (
+    &'static str,
+    &'static str,
+    fn(&VecDeque<ProbeRecord>) -> Option<f64>,
+);
+
+// The gauges exported for each gateway, mostly from its latest probe. Metrics that weren't measured
+// in the latest probe are left out for the gateway.
+const GATEWAY_METRICS: &[GatewayMetric] = &[
+    (
+        "nym_gateway_probe_success",
+        "Whether the latest probe of the gateway passed",
+        |history| Some(gauge(latest(history)?.succeeded())),
+    ),
+    (
+        "nym_gateway_probe_success_ratio",
+        "The fraction of the probes in the history of the gateway that passed",
+        |history| Some(success_ratio(history)),
+    ),
+    (
+        "nym_gateway_probe_last_probe_timestamp_seconds",
+        "When the gateway was last probed, in seconds since the unix epoch",
+        |history| Some(history.back()?.probed_at as f64),
+    ),
+    (
+        "nym_gateway_probe_duration_seconds",
+        "How long the latest probe of the gateway took",
+        |history| Some(latest(history)?.duration_ms as f64 / 1000.0),
+    ),
+    (
+        "nym_gateway_probe_entry_can_route",
+        "Whether the gateway routed mixnet traffic as an entry",
+        |history| Some(gauge(outcome(history)?.as_entry.can_route)),
+    ),
+    (
+        "nym_gateway_probe_exit_can_connect",
+        "Whether the ip packet router of the gateway accepted our connection",
+        |history| Some(gauge(exit(history)?.can_connect)),
+    ),
+    (
+        "nym_gateway_probe_exit_can_route_ipv4",
+        "Whether the gateway routed IPv4 traffic to the internet as an exit",
+        |history| Some(gauge(exit(history)?.can_route_ip_external_v4)),
+    ),
+    (
+        "nym_gateway_probe_exit_can_route_ipv6",
+        "Whether the gateway routed IPv6 traffic to the internet as an exit",
+        |history| Some(gauge(exit(history)?.can_route_ip_external_v6)),
+    ),
+    (
+        "nym_gateway_probe_wg_can_handshake",
+        "Whether a wireguard handshake with the gateway completed",
+        |history| Some(gauge(wg(history)?.can_handshake)),
+    ),
+    (
+        "nym_gateway_probe_mixnet_rtt_p50_seconds",
+        "Median round trip time of mixnet self pings through the gateway",
+        |history| Some(outcome(history)?.as_entry.mixnet_rtt.as_ref()?.p50_ms / 1000.0),
+    ),
+    (
+        "nym_gateway_probe_exit_icmp_rtt_p50_seconds",
+        "Median round trip time of ICMP pings to the ip packet router of the gateway",
+        |history| Some(exit(history)?.icmp_rtt.as_ref()?.p50_ms / 1000.0),
+    ),
+    (
+        "nym_gateway_probe_wg_handshake_seconds",
+        "How long the wireguard handshake with the gateway took",
+        |history| Some(wg(history)?.handshake_ms? as f64 / 1000.0),
+    ),
+    (
+        "nym_gateway_probe_wg_throughput_bits_per_second",
+        "Throughput of a download through the wireguard tunnel to the gateway",
+        |history| Some(wg(history)?.throughput.as_ref()?.mbit_per_sec * 1_000_000.0),
+    ),
+];
+
+fn latest(history: &VecDeque<ProbeRecord>) -> Option<&GatewayProbeReport> {
+    history.back().map(|record| &record.report)
+}
+
+fn outcome(history: &VecDeque<ProbeRecord>) -> Option<&ProbeOutcome> {
+    latest(history)?.outcome.as_ref()
+}
+
+fn exit(history: &VecDeque<ProbeRecord>) -> Option<&Exit> {
+    outcome(history)?.as_exit.as_ref()
+}
+
+fn wg(history: &VecDeque<ProbeRecord>) -> Option<&WgProbeResults> {
+    outcome(history)?.wg.as_ref()
+}
+
+fn gauge(value: bool) -> f64 {
+    if value {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+fn success_ratio(history: &VecDeque<ProbeRecord>) -> f64 {
+    if history.is_empty() {
+        return 0.0;
+    }
+    let succeeded = history
+        .iter()
+        .filter(|record| record.report.succeeded())
+        .count();
+    succeeded as f64 / history.len() as f64
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn unix_time_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+async fn get_metrics(State(history): State<SharedProbeHistory>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        history.read().await.metrics(),
+    )
+}
+
+async fn get_gateways(State(history): State<SharedProbeHistory>) -> Json<Vec<GatewayStatus>> {
+    Json(history.read().await.statuses())
+}
+
+async fn get_gateway(
+    State(history): State<SharedProbeHistory>,
+    Path(gateway): Path<String>,
+) -> Result<Json<GatewayHistory>, StatusCode> {
+    history
+        .read()
+        .await
+        .history(&gateway)
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn probe_round(
+    selection: &GatewaySelection,
+    config: &ServeConfig,
+    history: &SharedProbeHistory,
+) -> anyhow::Result<()> {
+    // The directory is fetched for every round, to pick up gateways that come and go
+    let gateways = if config.exit_only {
+        crate::fetch_gateways_with_ipr().await?
+    } else {
+        crate::fetch_gateways().await?
+    };
+    let mut identities = selection.select(&gateways);
+    {
+        let mut history = history.write().await;
+        history.retain_gateways(&identities);
+        // Gateways that haven't been probed in the longest time go first, which are the ones that
+        // a previous round didn't get to
+        identities.sort_by_key(|identity| history.last_probed_at(identity));
+    }
+    let total = identities.len();
+    info!("Starting a probe round of {total} gateways");
+
+    // Cut the round short instead of delaying the next one
+    let deadline = tokio::time::sleep(config.interval);
+    let mut deadline = std::pin::pin!(deadline);
+    let mut probes = std::pin::pin!(probe_gateways(identities, &gateways, config.bulk.clone()));
+    let mut probed = 0;
+    loop {
+        tokio::select! {
+            report = probes.next() => {
+                let Some(report) = report else {
+                    break;
+                };
+                history.write().await.record(report, unix_time_now());
+                probed += 1;
+            }
+            _ = &mut deadline => {
+                warn!(
+                    "The probe round ran out of time, {} gateways are left for the next round",
+                    total - probed
+                );
+                break;
+            }
+        }
+    }
+    history.write().await.rounds += 1;
+    info!("Finished the probe round");
+    Ok(())
+}
+
+async fn probe_rounds(
+    selection: GatewaySelection,
+    config: ServeConfig,
+    history: SharedProbeHistory,
+) {
+    let mut interval = tokio::time::interval(config.interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        if let Err(err) = probe_round(&selection, &config, &history).await {
+            error!("Probe round failed: {err:#}");
+        }
+    }
+}
+
+// Probe the selected gateways periodically, and serve the results as Prometheus metrics on
+// /metrics and as JSON on /api/v1/gateways and /api/v1/gateways/{identity}
+pub async fn serve(selection: GatewaySelection, config: ServeConfig) -> anyhow::Result<()> {
+    let history = Arc::new(RwLock::new(ProbeHistory::new(config.history_len)));
+    let app = Router::new()
+        .route("/metrics", get(get_metrics))
+        .route("/api/v1/gateways", get(get_gateways))
+        .route("/api/v1/gateways/:gateway", get(get_gateway))
+        .with_state(history.clone());
+
+    let listener = TcpListener::bind(config.listen_address).await?;
+    info!("Serving probe results on http://{}", config.listen_address);
+
+    // The server runs on a task of its own, so that it keeps responding while the probes run on
+    // this task, since they aren't Send
+    let mut server = tokio::spawn(axum::serve(listener, app).into_future());
+    tokio::select! {
+        result = &mut server => result??,
+        _ = probe_rounds(selection, config, history) => {}
+    }
+    server.abort();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Entry;
+
+    fn report(gateway: &str, outcome: Option<ProbeOutcome>) -> GatewayProbeReport {
+        GatewayProbeReport {
+            gateway: gateway.to_string(),
+            country: Some("DE".to_string()),
+            duration_ms: 2500,
+            failure: outcome
+                .as_ref()
+                .map_or(Some("timed out after 120s".to_string()), |outcome| {
+                    outcome.failure_reason().map(ToString::to_string)
+                }),
+            outcome,
+        }
+    }
+
+    #[test]
+    fn history_and_metrics() {
+        let mut history = ProbeHistory::new(2);
+        let entry_only = ProbeOutcome {
+            as_entry: Entry::success(),
+            as_exit: None,
+            wg: None,
+        };
+        history.record(report("gw1", None), 100);
+        history.record(report("gw1", Some(entry_only.clone())), 200);
+        history.record(report("gw1", Some(entry_only)), 300);
+        history.rounds = 3;
+
+        let gw1 = history.history("gw1").unwrap();
+        assert_eq!(gw1.probes.len(), 2);
+        assert_eq!(gw1.probes[0].probed_at, 200);
+        assert_eq!(gw1.success_ratio, 1.0);
+        assert!(history.history("gw2").is_none());
+
+        let metrics = history.metrics();
+        for line in [
+            "nym_gateway_probe_rounds_total 3",
+            "nym_gateway_probe_success{gateway=\"gw1\",country=\"DE\"} 1",
+            "nym_gateway_probe_last_probe_timestamp_seconds{gateway=\"gw1\",country=\"DE\"} 300",
+            "nym_gateway_probe_duration_seconds{gateway=\"gw1\",country=\"DE\"} 2.5",
+            "nym_gateway_probe_entry_can_route{gateway=\"gw1\",country=\"DE\"} 1",
+            "# TYPE nym_gateway_probe_exit_can_connect gauge",
+        ] {
+            assert!(metrics.lines().any(|metric| metric == line), "{line}");
+        }
+        // Not probed, so not exported
+        assert!(!metrics.contains("nym_gateway_probe_exit_can_connect{"));
+    }
+
+    #[test]
+    fn gateways_that_are_no_longer_probed_are_forgotten() {
+        let identity =
+            || *nym_sdk::mixnet::ed25519::KeyPair::new(&mut rand::rngs::OsRng).public_key();
+        let (stays, leaves, joins) = (identity(), identity(), identity());
+        let mut history = ProbeHistory::new(2);
+        history.record(report(&stays.to_base58_string(), None), 100);
+        history.record(report(&leaves.to_base58_string(), None), 200);
+
+        history.retain_gateways(&[stays, joins]);
+        assert!(history.history(&leaves.to_base58_string()).is_none());
+        assert_eq!(history.last_probed_at(&stays), Some(100));
+        // Never probed, so it goes first in the next round
+        assert_eq!(history.last_probed_at(&joins), None);
+    }
+}
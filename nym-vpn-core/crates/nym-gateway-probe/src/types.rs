@@ -1,9 +1,14 @@
+use std::time::Duration;
+
 use nym_connection_monitor::ConnectionStatusEvent;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProbeResult {
     pub gateway: String,
+    // The ip packet router that was probed as exit, when it isn't the one of the gateway
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit: Option<String>,
     pub outcome: ProbeOutcome,
 }
 
@@ -14,6 +19,27 @@ pub struct ProbeOutcome {
     pub wg: Option<WgProbeResults>,
 }
 
+impl ProbeOutcome {
+    // The first thing that didn't work, if any. Exits that route only one of IPv4 and IPv6 are
+    // still considered to work.
+    pub fn failure_reason(&self) -> Option<&'static str> {
+        if !self.as_entry.can_connect {
+            return Some("failed to connect to the entry gateway");
+        }
+        if !self.as_entry.can_route {
+            return Some("the entry gateway is not routing mixnet traffic");
+        }
+        let exit = self.as_exit.as_ref()?;
+        if !exit.can_connect {
+            return Some("failed to connect to the ip packet router");
+        }
+        if !exit.can_route_ip_external_v4 && !exit.can_route_ip_external_v6 {
+            return Some("the exit gateway is not routing traffic to the internet");
+        }
+        None
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(rename = "wg")]
 pub struct WgProbeResults {
@@ -22,12 +48,85 @@ pub struct WgProbeResults {
     pub can_resolve_dns: bool,
     pub ping_hosts_performance: f32,
     pub ping_ips_performance: f32,
+    // From bringing the tunnel up until the first handshake with the gateway completed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handshake_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub throughput: Option<Throughput>,
+}
+
+// A timed download through the wireguard tunnel
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Throughput {
+    pub url: String,
+    pub bytes: u64,
+    pub duration_ms: u64,
+    pub mbit_per_sec: f64,
+    // The download can fail part way, in which case the numbers are for what was received
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Throughput {
+    pub fn new(url: String, bytes: u64, duration_ms: u64, error: Option<String>) -> Self {
+        let mbit_per_sec = if duration_ms > 0 {
+            (bytes * 8) as f64 / (duration_ms as f64 * 1000.0)
+        } else {
+            0.0
+        };
+        Self {
+            url,
+            bytes,
+            duration_ms,
+            mbit_per_sec,
+            error,
+        }
+    }
+}
+
+// The round trip times of a series of pings
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RttStats {
+    pub sent: usize,
+    pub received: usize,
+    pub min_ms: f64,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub max_ms: f64,
+}
+
+impl RttStats {
+    // None when nothing came back
+    pub fn from_round_trips(sent: usize, round_trips: &[Duration]) -> Option<Self> {
+        if round_trips.is_empty() {
+            return None;
+        }
+        let mut sorted = round_trips.to_vec();
+        sorted.sort();
+        let millis = |rtt: Duration| rtt.as_secs_f64() * 1000.0;
+        // Nearest rank
+        let percentile =
+            |percent: usize| millis(sorted[(percent * sorted.len()).div_ceil(100).max(1) - 1]);
+        Some(Self {
+            sent,
+            received: sorted.len(),
+            min_ms: millis(sorted[0]),
+            mean_ms: millis(sorted.iter().sum::<Duration>() / sorted.len() as u32),
+            p50_ms: percentile(50),
+            p90_ms: percentile(90),
+            max_ms: millis(sorted[sorted.len() - 1]),
+        })
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Entry {
     pub can_connect: bool,
     pub can_route: bool,
+    // Mixnet self pings, through the entry gateway and back
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mixnet_rtt: Option<RttStats>,
 }
 
 impl Entry {
@@ -35,6 +134,7 @@ impl Entry {
         Self {
             can_connect: false,
             can_route: false,
+            mixnet_rtt: None,
         }
     }
 
@@ -42,6 +142,7 @@ impl Entry {
         Self {
             can_connect: true,
             can_route: false,
+            mixnet_rtt: None,
         }
     }
 
@@ -49,6 +150,7 @@ impl Entry {
         Self {
             can_connect: true,
             can_route: true,
+            mixnet_rtt: None,
         }
     }
 }
@@ -60,6 +162,9 @@ pub struct Exit {
     pub can_route_ip_external_v4: bool,
     pub can_route_ip_v6: bool,
     pub can_route_ip_external_v6: bool,
+    // ICMP pings through the mixnet to the tun device of the ip packet router
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icmp_rtt: Option<RttStats>,
 }
 
 impl Exit {
@@ -70,6 +175,7 @@ impl Exit {
             can_route_ip_external_v4: false,
             can_route_ip_v6: false,
             can_route_ip_external_v6: false,
+            icmp_rtt: None,
         }
     }
 
@@ -80,6 +186,7 @@ impl Exit {
             can_route_ip_external_v4: replies.external_ip_v4,
             can_route_ip_v6: replies.ipr_tun_ip_v6,
             can_route_ip_external_v6: replies.external_ip_v6,
+            icmp_rtt: None,
         }
     }
 }
@@ -99,11 +206,40 @@ impl IpPingReplies {
 
     pub fn register_event(&mut self, event: &ConnectionStatusEvent) {
         match event {
-            ConnectionStatusEvent::MixnetSelfPing => {}
-            ConnectionStatusEvent::Icmpv4IprTunDevicePingReply => self.ipr_tun_ip_v4 = true,
-            ConnectionStatusEvent::Icmpv6IprTunDevicePingReply => self.ipr_tun_ip_v6 = true,
-            ConnectionStatusEvent::Icmpv4IprExternalPingReply => self.external_ip_v4 = true,
-            ConnectionStatusEvent::Icmpv6IprExternalPingReply => self.external_ip_v6 = true,
+            ConnectionStatusEvent::MixnetSelfPing { .. }
+            | ConnectionStatusEvent::EntryTunnelAlive
+            | ConnectionStatusEvent::ProbeReply(_)
+            | ConnectionStatusEvent::PingSent(_) => {}
+            ConnectionStatusEvent::Icmpv4IprTunDevicePingReply { .. } => self.ipr_tun_ip_v4 = true,
+            ConnectionStatusEvent::Icmpv6IprTunDevicePingReply { .. } => self.ipr_tun_ip_v6 = true,
+            ConnectionStatusEvent::Icmpv4IprExternalPingReply(_) => self.external_ip_v4 = true,
+            ConnectionStatusEvent::Icmpv6IprExternalPingReply(_) => self.external_ip_v6 = true,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rtt_stats_from_round_trips() {
+        assert_eq!(RttStats::from_round_trips(3, &[]), None);
+
+        let round_trips: Vec<_> = [400, 100, 300, 200]
+            .into_iter()
+            .map(Duration::from_millis)
+            .collect();
+        let stats = RttStats::from_round_trips(5, &round_trips).unwrap();
+        assert_eq!(stats.sent, 5);
+        assert_eq!(stats.received, 4);
+        assert_eq!(stats.min_ms, 100.0);
+        assert_eq!(stats.mean_ms, 250.0);
+        assert_eq!(stats.p50_ms, 200.0);
+        assert_eq!(stats.p90_ms, 400.0);
+        assert_eq!(stats.max_ms, 400.0);
+
+        let throughput = Throughput::new("http://example.com".to_string(), 1_250_000, 1000, None);
+        assert_eq!(throughput.mbit_per_sec, 10.0);
+    }
+}
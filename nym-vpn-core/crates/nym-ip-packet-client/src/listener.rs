@@ -15,7 +15,10 @@ use crate::helpers::check_ipr_message_version;
 
 pub enum MixnetMessageOutcome {
     IpPackets(Vec<Bytes>),
-    MixnetSelfPing,
+    // Carries the request id of the ping, to match it with when it was sent
+    MixnetSelfPing(Option<u64>),
+    // The IPR dropped us, nothing will be routed anymore
+    Disconnect,
 }
 
 pub struct IprListener {
@@ -71,7 +74,8 @@ impl IprListener {
                     info!("Received disconnect response, ignoring for now");
                 }
                 IpPacketResponseData::UnrequestedDisconnect(_) => {
-                    info!("Received unrequested disconnect response, ignoring for now");
+                    info!("Received unrequested disconnect response");
+                    return Ok(Some(MixnetMessageOutcome::Disconnect));
                 }
                 IpPacketResponseData::Data(data_response) => {
                     // Un-bundle the mixnet message and send the individual IP packets
@@ -103,7 +107,7 @@ impl IprListener {
                 // are sending a ping to ourselves.
                 if let Ok(request) = IpPacketRequest::from_reconstructed_message(&message) {
                     if self.is_mix_self_ping(&request) {
-                        return Ok(Some(MixnetMessageOutcome::MixnetSelfPing));
+                        return Ok(Some(MixnetMessageOutcome::MixnetSelfPing(request.id())));
                     }
                 } else {
                     warn!("Failed to deserialize reconstructed message: {err}");
@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use std::{
-    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     path::PathBuf,
     str::FromStr,
     sync::OnceLock,
@@ -10,6 +10,10 @@ use std::{
 
 use clap::{Args, Parser, Subcommand};
 use ipnetwork::{Ipv4Network, Ipv6Network};
+use nym_vpn_lib::{
+    connection_monitor::ProbeTarget, Blocklist, BlocklistFormat, BlocklistSource,
+    EncryptedDnsUpstream, SplitDnsRule, TrafficProfile,
+};
 
 const TUN_IP4_SUBNET: &str = "10.0.0.0/16";
 const TUN_IP6_SUBNET: &str = "2001:db8:a160::0/112";
@@ -42,6 +46,10 @@ pub(crate) enum Commands {
 
     /// Import credential
     ImportCredential(ImportCredentialArgs),
+
+    /// Run a local SOCKS5 proxy that forwards through the mixnet, for the applications pointed at
+    /// it. No TUN device is created and the routing is left untouched, so no root is needed.
+    Proxy(ProxyArgs),
 }
 
 #[derive(Args)]
@@ -56,6 +64,11 @@ pub(crate) struct RunArgs {
     #[arg(long, default_value_t = false)]
     pub(crate) wireguard_mode: bool,
 
+    /// The number of stacked wireguard tunnels, including the entry and the exit. Hops in between
+    /// are picked in countries not used by any other hop.
+    #[arg(long, requires = "wireguard_mode", value_parser = clap::value_parser!(u8).range(2..=3))]
+    pub(crate) wireguard_hops: Option<u8>,
+
     /// The IPv4 address of the nym TUN device that wraps IP packets in sphinx packets.
     #[arg(long, alias = "ipv4", value_parser = validate_ipv4, requires = "nym_ipv6")]
     pub(crate) nym_ipv4: Option<Ipv4Addr>,
@@ -68,9 +81,44 @@ pub(crate) struct RunArgs {
     #[arg(long, alias = "mtu")]
     pub(crate) nym_mtu: Option<u16>,
 
-    /// The DNS server to use
+    /// The DNS server to use. Can be given more than once, in order of preference.
     #[arg(long)]
-    pub(crate) dns: Option<IpAddr>,
+    pub(crate) dns: Vec<IpAddr>,
+
+    /// Resolve a domain, and all names below it, through other DNS servers, such as internal ones
+    /// on the LAN. Given as DOMAIN=IP[,IP...], e.g. *.corp.example=192.168.1.53. Can be given more
+    /// than once. Only supported in mixnet mode.
+    #[arg(long = "split-dns", conflicts_with = "wireguard_mode")]
+    pub(crate) split_dns_rules: Vec<SplitDnsRule>,
+
+    /// Resolve DNS through a local resolver that sends the queries over DNS over HTTPS through
+    /// the tunnel, so that the exit doesn't see them. Only supported in mixnet mode.
+    #[arg(long, group = "encrypted_dns", conflicts_with_all = ["dns", "wireguard_mode"])]
+    pub(crate) dns_over_https: bool,
+
+    /// Like --dns-over-https, but using DNS over TLS.
+    #[arg(long, group = "encrypted_dns", conflicts_with_all = ["dns", "wireguard_mode"])]
+    pub(crate) dns_over_tls: bool,
+
+    /// The resolvers the encrypted DNS queries are sent to, given as IP[@PORT]#TLS_NAME. Defaults
+    /// to Cloudflare.
+    #[arg(long = "encrypted-dns-upstream", requires = "encrypted_dns")]
+    pub(crate) encrypted_dns_upstreams: Vec<EncryptedDnsUpstream>,
+
+    /// Answer the DNS queries for common advertising and tracking domains locally, so that they
+    /// are never resolved.
+    #[arg(long)]
+    pub(crate) block_trackers: bool,
+
+    /// Also block the domains on this list, given as FORMAT:PATH where the format is hosts or
+    /// adblock. Can be given more than once.
+    #[arg(long = "dns-blocklist", value_parser = parse_blocklist)]
+    pub(crate) dns_blocklists: Vec<Blocklist>,
+
+    /// Never block this domain, or the names below it, even when it is on a blocklist. Can be
+    /// given more than once.
+    #[arg(long = "dns-allow")]
+    pub(crate) dns_allowlist: Vec<String>,
 
     /// Disable routing all traffic through the nym TUN device. When the flag is set, the nym TUN
     /// device will be created, but to route traffic through it you will need to do it manually,
@@ -78,6 +126,106 @@ pub(crate) struct RunArgs {
     #[arg(long)]
     pub(crate) disable_routing: bool,
 
+    /// Shape the mixnet traffic with a profile: max-anonymity, balanced, low-latency or
+    /// bandwidth-saver. The flags below take precedence over it.
+    #[arg(long, value_parser = parse_traffic_profile)]
+    pub(crate) traffic_profile: Option<TrafficProfile>,
+
+    /// Enable Poisson process rate limiting of outbound traffic.
+    #[arg(long)]
+    pub(crate) enable_poisson_rate: bool,
+
+    /// Disable constant rate background loop cover traffic.
+    #[arg(long)]
+    pub(crate) disable_background_cover_traffic: bool,
+
+    /// Enable credentials mode.
+    #[arg(long)]
+    pub(crate) enable_credentials_mode: bool,
+
+    /// Set the minimum performance level for mixnodes.
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100), hide = true)]
+    pub(crate) min_mixnode_performance: Option<u8>,
+
+    // Set the minimum performance level for gateways.
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub(crate) min_gateway_performance: Option<u8>,
+
+    /// Check the connection through the exit against these targets instead of the defaults. The
+    /// exit is considered to route our traffic as long as one of them answers. Formats are
+    /// icmp:IP, tcp:IP:PORT, dns:IP:PORT[/NAME] and http:IP:PORT[/HOST].
+    #[arg(long = "probe-target")]
+    pub(crate) probe_targets: Vec<ProbeTarget>,
+
+    /// How often the ICMP probe targets are pinged, in milliseconds.
+    #[arg(long)]
+    pub(crate) ping_interval_ms: Option<u64>,
+
+    /// How often the other probe targets are probed, in milliseconds.
+    #[arg(long)]
+    pub(crate) probe_interval_ms: Option<u64>,
+
+    /// How long a probe reply counts as the connection being up, in milliseconds.
+    #[arg(long)]
+    pub(crate) probe_reply_expiry_ms: Option<u64>,
+
+    /// Switch to another exit gateway when the current one stops routing our traffic. An exit
+    /// given by identity or address is always kept.
+    #[arg(long)]
+    pub(crate) exit_failover: bool,
+
+    /// How long the exit gateway has to keep failing before switching to another one, in seconds.
+    #[arg(long, requires = "exit_failover")]
+    pub(crate) exit_failover_grace_period_secs: Option<u64>,
+
+    /// When failing over, pick the best exit anywhere instead of one in the same country.
+    #[arg(long, requires = "exit_failover")]
+    pub(crate) exit_failover_any_location: bool,
+}
+
+#[derive(Args)]
+pub(crate) struct ProxyArgs {
+    /// The address the SOCKS5 proxy listens on.
+    #[arg(long, default_value = "127.0.0.1:1080")]
+    pub(crate) listen: SocketAddr,
+
+    /// Allow listening on an address that isn't loopback. The proxy has no authentication, so
+    /// anyone who can reach it can use the tunnel.
+    #[arg(long)]
+    pub(crate) allow_remote_clients: bool,
+
+    #[command(flatten)]
+    pub(crate) entry: CliEntry,
+
+    #[command(flatten)]
+    pub(crate) exit: CliExit,
+
+    /// Go through two WireGuard hops run in userspace instead of the mixnet. Linux only.
+    #[arg(long)]
+    pub(crate) wireguard: bool,
+
+    /// The IPv4 address to ask the exit for.
+    #[arg(long, alias = "ipv4", value_parser = validate_ipv4, requires = "nym_ipv6")]
+    pub(crate) nym_ipv4: Option<Ipv4Addr>,
+
+    /// The IPv6 address to ask the exit for.
+    #[arg(long, alias = "ipv6", value_parser = validate_ipv6, requires = "nym_ipv4")]
+    pub(crate) nym_ipv6: Option<Ipv6Addr>,
+
+    /// The MTU of the IP packets sent through the mixnet.
+    #[arg(long, alias = "mtu")]
+    pub(crate) nym_mtu: Option<u16>,
+
+    /// The DNS server the domain names of the requests are resolved with, through the mixnet. Can
+    /// be given more than once, in order of preference.
+    #[arg(long)]
+    pub(crate) dns: Vec<IpAddr>,
+
+    /// Shape the mixnet traffic with a profile: max-anonymity, balanced, low-latency or
+    /// bandwidth-saver. The flags below take precedence over it.
+    #[arg(long, value_parser = parse_traffic_profile)]
+    pub(crate) traffic_profile: Option<TrafficProfile>,
+
     /// Enable Poisson process rate limiting of outbound traffic.
     #[arg(long)]
     pub(crate) enable_poisson_rate: bool,
@@ -113,6 +261,10 @@ pub(crate) struct CliEntry {
     /// Auto-select entry gateway by latency
     #[clap(long, alias = "entry-fastest")]
     pub(crate) entry_gateway_low_latency: bool,
+
+    /// Auto-select the best entry gateway, scored by latency, performance and probe outcome.
+    #[clap(long, alias = "entry-best")]
+    pub(crate) entry_gateway_best: bool,
 }
 
 #[derive(Args)]
@@ -129,6 +281,10 @@ pub(crate) struct CliExit {
     /// Auto-select exit gateway by country ISO.
     #[clap(long, alias = "exit-country")]
     pub(crate) exit_gateway_country: Option<String>,
+
+    /// Auto-select the best exit gateway, scored by latency, performance and probe outcome.
+    #[clap(long, alias = "exit-best")]
+    pub(crate) exit_gateway_best: bool,
 }
 
 #[derive(Args)]
@@ -188,6 +344,41 @@ fn check_path(path: &str) -> Result<PathBuf, String> {
     Ok(path)
 }
 
+// The path doubles as the name of the list
+fn parse_blocklist(blocklist: &str) -> Result<Blocklist, String> {
+    let (format, path) = blocklist
+        .split_once(':')
+        .ok_or_else(|| format!("expected FORMAT:PATH, got {blocklist}"))?;
+    let format = match format {
+        "hosts" => BlocklistFormat::Hosts,
+        "adblock" => BlocklistFormat::Adblock,
+        _ => {
+            return Err(format!(
+                "unknown blocklist format {format}, expected hosts or adblock"
+            ))
+        }
+    };
+    let path = check_path(path)?;
+    Ok(Blocklist {
+        name: path.display().to_string(),
+        enabled: true,
+        source: BlocklistSource::File { path, format },
+    })
+}
+
+fn parse_traffic_profile(name: &str) -> Result<TrafficProfile, String> {
+    TrafficProfile::builtin(name).ok_or_else(|| {
+        let names = TrafficProfile::builtins()
+            .into_iter()
+            .map(|profile| profile.name)
+            .collect::<Vec<_>>();
+        format!(
+            "unknown traffic profile {name}, expected one of {}",
+            names.join(", ")
+        )
+    })
+}
+
 // Workaround until clap supports enums for ArgGroups
 pub enum ImportCredentialTypeEnum {
     Path(PathBuf),
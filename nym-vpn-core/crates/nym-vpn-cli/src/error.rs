@@ -6,6 +6,9 @@ pub(crate) enum Error {
     #[error(transparent)]
     VpnLib(#[from] nym_vpn_lib::Error),
 
+    #[error(transparent)]
+    Proxy(#[from] nym_vpn_lib::ProxyError),
+
     #[error(transparent)]
     ImportCredential(#[from] nym_vpn_lib::credentials::ImportCredentialError),
 
@@ -21,6 +24,12 @@ pub(crate) enum Error {
     #[error("recipient is not formatted correctly")]
     RecipientFormatting,
 
+    #[error("invalid dns filter: {0}")]
+    InvalidDnsFilter(String),
+
+    #[error(transparent)]
+    InvalidConnectionMonitorConfig(nym_vpn_lib::connection_monitor::Error),
+
     #[error("config path not set")]
     ConfigPathNotSet,
 
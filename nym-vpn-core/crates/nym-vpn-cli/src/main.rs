@@ -1,15 +1,18 @@
 // Copyright 2023 - Nym Technologies SA <contact@nymtech.net>
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::{fs, path::PathBuf};
+use std::{fs, path::PathBuf, time::Duration};
 
 use clap::Parser;
 use commands::{CliArgs, ImportCredentialTypeEnum};
 use futures::channel::mpsc;
 use nym_vpn_lib::{
+    connection_monitor::ConnectionMonitorConfig,
     gateway_directory::{Config as GatewayConfig, EntryPoint, ExitPoint},
     nym_config::defaults::{setup_env, var_names},
-    GenericNymVpnConfig, IpPair, MixnetClientConfig, NodeIdentity, NymVpn, Recipient, SpecificVpn,
+    DnsConfig, DnsFilterConfig, EncryptedDnsConfig, EncryptedDnsProtocol, ExitFailoverConfig,
+    ExitFailoverScope, GenericNymVpnConfig, IpPair, MixnetClientConfig, NodeIdentity, NymVpn,
+    ProxyConfig, Recipient, SpecificVpn,
 };
 use time::OffsetDateTime;
 use tracing::{debug, error, info};
@@ -45,48 +48,117 @@ pub(crate) fn setup_logging(args: &CliArgs) {
         .init();
 }
 
-fn parse_entry_point(args: &commands::RunArgs) -> Result<EntryPoint> {
-    if let Some(ref entry_gateway_id) = args.entry.entry_gateway_id {
+fn parse_entry_point(args: &commands::CliEntry) -> Result<EntryPoint> {
+    if let Some(ref entry_gateway_id) = args.entry_gateway_id {
         Ok(EntryPoint::Gateway {
             identity: NodeIdentity::from_base58_string(entry_gateway_id.clone())
                 .map_err(|_| Error::NodeIdentityFormatting)?,
         })
-    } else if let Some(ref entry_gateway_country) = args.entry.entry_gateway_country {
+    } else if let Some(ref entry_gateway_country) = args.entry_gateway_country {
         Ok(EntryPoint::Location {
             location: entry_gateway_country.clone(),
         })
-    } else if args.entry.entry_gateway_low_latency {
+    } else if args.entry_gateway_low_latency {
         Ok(EntryPoint::RandomLowLatency)
+    } else if args.entry_gateway_best {
+        Ok(EntryPoint::Best)
     } else {
         Ok(EntryPoint::Random)
     }
 }
 
-fn parse_exit_point(args: &commands::RunArgs) -> Result<ExitPoint> {
-    if let Some(ref exit_router_address) = args.exit.exit_router_address {
+fn parse_exit_point(args: &commands::CliExit) -> Result<ExitPoint> {
+    if let Some(ref exit_router_address) = args.exit_router_address {
         Ok(ExitPoint::Address {
             address: Recipient::try_from_base58_string(exit_router_address.clone())
                 .map_err(|_| Error::RecipientFormatting)?,
         })
-    } else if let Some(ref exit_router_id) = args.exit.exit_gateway_id {
+    } else if let Some(ref exit_router_id) = args.exit_gateway_id {
         Ok(ExitPoint::Gateway {
             identity: NodeIdentity::from_base58_string(exit_router_id.clone())
                 .map_err(|_| Error::NodeIdentityFormatting)?,
         })
-    } else if let Some(ref exit_gateway_country) = args.exit.exit_gateway_country {
+    } else if let Some(ref exit_gateway_country) = args.exit_gateway_country {
         Ok(ExitPoint::Location {
             location: exit_gateway_country.clone(),
         })
+    } else if args.exit_gateway_best {
+        Ok(ExitPoint::Best)
     } else {
         Ok(ExitPoint::Random)
     }
 }
 
 #[allow(unreachable_code)]
+fn connection_monitor_config(args: &commands::RunArgs) -> Result<ConnectionMonitorConfig> {
+    let mut config = ConnectionMonitorConfig::default();
+    if !args.probe_targets.is_empty() {
+        config.targets = args.probe_targets.clone();
+    }
+    if let Some(ping_interval_ms) = args.ping_interval_ms {
+        config.ping_interval = Duration::from_millis(ping_interval_ms);
+    }
+    if let Some(probe_interval_ms) = args.probe_interval_ms {
+        config.probe_interval = Duration::from_millis(probe_interval_ms);
+    }
+    if let Some(reply_expiry_ms) = args.probe_reply_expiry_ms {
+        config.reply_expiry = Duration::from_millis(reply_expiry_ms);
+    }
+    config
+        .validate()
+        .map_err(Error::InvalidConnectionMonitorConfig)?;
+    Ok(config)
+}
+
+fn exit_failover_config(args: &commands::RunArgs) -> ExitFailoverConfig {
+    let mut config = ExitFailoverConfig {
+        enabled: args.exit_failover,
+        ..Default::default()
+    };
+    if let Some(grace_period_secs) = args.exit_failover_grace_period_secs {
+        config.grace_period = Duration::from_secs(grace_period_secs);
+    }
+    if args.exit_failover_any_location {
+        config.scope = ExitFailoverScope::AnyLocation;
+    }
+    config
+}
+
+fn encrypted_dns_config(args: &commands::RunArgs) -> Option<EncryptedDnsConfig> {
+    let protocol = if args.dns_over_https {
+        EncryptedDnsProtocol::Https
+    } else if args.dns_over_tls {
+        EncryptedDnsProtocol::Tls
+    } else {
+        return None;
+    };
+    let mut config = EncryptedDnsConfig::new(protocol);
+    if !args.encrypted_dns_upstreams.is_empty() {
+        config.upstreams = args.encrypted_dns_upstreams.clone();
+    }
+    Some(config)
+}
+
+fn dns_filter_config(args: &commands::RunArgs) -> Result<Option<DnsFilterConfig>> {
+    if !args.block_trackers && args.dns_blocklists.is_empty() {
+        return Ok(None);
+    }
+    // The default config has just the bundled tracker list
+    let mut config = DnsFilterConfig::default();
+    config.blocklists.retain(|_| args.block_trackers);
+    config
+        .blocklists
+        .extend(args.dns_blocklists.iter().cloned());
+    config.allowlist = args.dns_allowlist.clone();
+    config.validate().map_err(Error::InvalidDnsFilter)?;
+    Ok(Some(config))
+}
+
 fn check_root_privileges(args: &commands::CliArgs) -> Result<()> {
     let needs_root = match &args.command {
         Commands::Run(run_args) => !run_args.disable_routing,
         Commands::ImportCredential(_) => true,
+        Commands::Proxy(_) => false,
     };
 
     if !needs_root {
@@ -153,6 +225,7 @@ async fn run() -> Result<()> {
                 }
             })
         }
+        Commands::Proxy(args) => run_proxy(args, data_path).await,
     }
 }
 
@@ -168,8 +241,8 @@ async fn run_vpn(args: commands::RunArgs, data_path: Option<PathBuf>) -> Result<
             .unwrap_or("unavailable".to_string())
     );
 
-    let entry_point = parse_entry_point(&args)?;
-    let exit_point = parse_exit_point(&args)?;
+    let entry_point = parse_entry_point(&args.entry)?;
+    let exit_point = parse_exit_point(&args.exit)?;
     let nym_ips = if let (Some(ipv4), Some(ipv6)) = (args.nym_ipv4, args.nym_ipv6) {
         Some(IpPair::new(ipv4, ipv6))
     } else {
@@ -177,6 +250,7 @@ async fn run_vpn(args: commands::RunArgs, data_path: Option<PathBuf>) -> Result<
     };
     let generic_config = GenericNymVpnConfig {
         mixnet_client_config: MixnetClientConfig {
+            traffic_profile: args.traffic_profile.clone().unwrap_or_default(),
             enable_poisson_rate: args.enable_poisson_rate,
             disable_background_cover_traffic: args.disable_background_cover_traffic,
             enable_credentials_mode: args.enable_credentials_mode,
@@ -189,14 +263,26 @@ async fn run_vpn(args: commands::RunArgs, data_path: Option<PathBuf>) -> Result<
         exit_point: exit_point.clone(),
         nym_ips,
         nym_mtu: args.nym_mtu,
-        dns: args.dns,
+        dns: DnsConfig {
+            servers: args.dns.clone(),
+            split_rules: args.split_dns_rules.clone(),
+        },
+        encrypted_dns: encrypted_dns_config(&args),
+        dns_filter: dns_filter_config(&args)?,
         disable_routing: args.disable_routing,
         user_agent: Some(nym_bin_common::bin_info_local_vergen!().into()),
+        kill_switch: Default::default(),
+        split_tunnel: Default::default(),
+        connection_monitor: connection_monitor_config(&args)?,
+        exit_failover: exit_failover_config(&args),
     };
 
     let nym_vpn: SpecificVpn = if args.wireguard_mode {
         let mut nym_vpn = NymVpn::new_wireguard_vpn(entry_point, exit_point);
         nym_vpn.generic_config = generic_config;
+        if let Some(hop_count) = args.wireguard_hops {
+            nym_vpn.vpn_config.hop_count = hop_count.into();
+        }
         nym_vpn.into()
     } else {
         let mut nym_vpn = NymVpn::new_mixnet_vpn(entry_point, exit_point);
@@ -211,40 +297,71 @@ async fn run_vpn(args: commands::RunArgs, data_path: Option<PathBuf>) -> Result<
     handle.wait_until_stopped().await.map_err(Error::VpnLib)
 }
 
+async fn run_proxy(args: commands::ProxyArgs, data_path: Option<PathBuf>) -> Result<()> {
+    let gateway_config = GatewayConfig::new_from_env(args.min_gateway_performance);
+    info!("nym-api: {}", gateway_config.api_url());
+
+    let nym_ips = if let (Some(ipv4), Some(ipv6)) = (args.nym_ipv4, args.nym_ipv6) {
+        Some(IpPair::new(ipv4, ipv6))
+    } else {
+        None
+    };
+    let config = ProxyConfig {
+        listen: args.listen,
+        allow_remote_clients: args.allow_remote_clients,
+        mixnet_client_config: MixnetClientConfig {
+            traffic_profile: args.traffic_profile.clone().unwrap_or_default(),
+            enable_poisson_rate: args.enable_poisson_rate,
+            disable_background_cover_traffic: args.disable_background_cover_traffic,
+            enable_credentials_mode: args.enable_credentials_mode,
+            min_mixnode_performance: args.min_mixnode_performance,
+            min_gateway_performance: args.min_gateway_performance,
+        },
+        data_path,
+        gateway_config,
+        entry_point: parse_entry_point(&args.entry)?,
+        exit_point: parse_exit_point(&args.exit)?,
+        nym_ips,
+        nym_mtu: args.nym_mtu,
+        dns: DnsConfig::new(args.dns.clone()),
+        user_agent: Some(nym_bin_common::bin_info_local_vergen!().into()),
+        wireguard: args.wireguard,
+    };
+
+    nym_vpn_lib::run_proxy(config, wait_for_signal())
+        .await
+        .map_err(Error::Proxy)
+}
+
 #[cfg(unix)]
-fn register_signal_handler(vpn_ctrl_tx: mpsc::UnboundedSender<nym_vpn_lib::NymVpnCtrlMessage>) {
+async fn wait_for_signal() {
     use tokio::signal::unix::{signal, SignalKind};
 
-    tokio::spawn(async move {
-        let mut sigterm = signal(SignalKind::terminate()).expect("Failed to setup SIGTERM channel");
-        let mut sigquit = signal(SignalKind::quit()).expect("Failed to setup SIGQUIT channel");
-
-        tokio::select! {
-            _ = tokio::signal::ctrl_c() => {
-                info!("Received Ctrl-C signal");
-            }
-            _ = sigterm.recv() => {
-                info!("Received SIGTERM signal");
-            }
-            _ = sigquit.recv() => {
-                info!("Received SIGQUIT signal");
-            }
+    let mut sigterm = signal(SignalKind::terminate()).expect("Failed to setup SIGTERM channel");
+    let mut sigquit = signal(SignalKind::quit()).expect("Failed to setup SIGQUIT channel");
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {
+            info!("Received Ctrl-C signal");
         }
-        info!("Sending stop message to VPN");
-        vpn_ctrl_tx
-            .unbounded_send(nym_vpn_lib::NymVpnCtrlMessage::Stop)
-            .unwrap();
-    });
+        _ = sigterm.recv() => {
+            info!("Received SIGTERM signal");
+        }
+        _ = sigquit.recv() => {
+            info!("Received SIGQUIT signal");
+        }
+    }
 }
 
 #[cfg(not(unix))]
+async fn wait_for_signal() {
+    tokio::signal::ctrl_c().await.ok();
+    info!("Received Ctrl-C signal");
+}
+
 fn register_signal_handler(vpn_ctrl_tx: mpsc::UnboundedSender<nym_vpn_lib::NymVpnCtrlMessage>) {
     tokio::spawn(async move {
-        tokio::select! {
-            _ = tokio::signal::ctrl_c() => {
-                info!("Received Ctrl-C signal");
-            }
-        }
+        wait_for_signal().await;
         info!("Sending stop message to VPN");
         vpn_ctrl_tx
             .unbounded_send(nym_vpn_lib::NymVpnCtrlMessage::Stop)
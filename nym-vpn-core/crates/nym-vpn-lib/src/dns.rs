@@ -0,0 +1,559 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use hickory_resolver::{
+    config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+    error::ResolveErrorKind,
+    proto::{
+        op::{Edns, Message, MessageType, OpCode, ResponseCode},
+        rr::Name,
+    },
+    TokioAsyncResolver,
+};
+use nym_task::TaskClient;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+};
+use tracing::{debug, error, info, trace};
+
+use crate::{dns_filter::DnsFilter, encrypted_dns::EncryptedDnsConfig};
+
+const DNS_PORT: u16 = 53;
+pub(crate) const DEFAULT_CACHE_SIZE: usize = 1024;
+
+// Plain DNS over UDP, without EDNS, is limited to this size
+const MIN_UDP_PAYLOAD: u16 = 512;
+const MAX_UDP_PAYLOAD: u16 = 4096;
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DnsConfig {
+    /// The resolvers used through the tunnel, in order of preference. The default ones are used
+    /// when empty.
+    pub servers: Vec<IpAddr>,
+
+    /// Domains that are resolved by other resolvers than the tunnel ones, such as internal
+    /// resolvers on the LAN.
+    pub split_rules: Vec<SplitDnsRule>,
+}
+
+impl DnsConfig {
+    pub fn new(servers: Vec<IpAddr>) -> Self {
+        DnsConfig {
+            servers,
+            split_rules: Vec::new(),
+        }
+    }
+
+    pub(crate) fn servers_or_default(&self) -> Vec<IpAddr> {
+        if self.servers.is_empty() {
+            crate::DEFAULT_DNS_SERVERS.to_vec()
+        } else {
+            self.servers.clone()
+        }
+    }
+
+    // The resolvers of the split rules, which the kill switch has to let through
+    pub(crate) fn split_servers(&self) -> impl Iterator<Item = IpAddr> + '_ {
+        self.split_rules
+            .iter()
+            .flat_map(|rule| rule.servers.iter().copied())
+    }
+}
+
+// Queries for the domain, and all names below it, go to the servers of the rule
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SplitDnsRule {
+    /// The domain, without a leading `*.` or trailing dot, e.g. `corp.example`.
+    pub domain: String,
+
+    /// The resolvers for the domain, in order of preference.
+    pub servers: Vec<IpAddr>,
+}
+
+impl SplitDnsRule {
+    // Accepts `corp.example`, `*.corp.example` and `corp.example.` alike
+    pub fn new(domain: &str, servers: Vec<IpAddr>) -> Result<Self, String> {
+        let domain = domain
+            .trim()
+            .trim_start_matches("*.")
+            .trim_end_matches('.')
+            .to_ascii_lowercase();
+        if domain.is_empty() {
+            return Err("empty split dns domain".to_string());
+        }
+        if servers.is_empty() {
+            return Err(format!("no resolvers given for {domain}"));
+        }
+        Ok(SplitDnsRule { domain, servers })
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        name == self.domain
+            || name
+                .strip_suffix(&self.domain)
+                .is_some_and(|prefix| prefix.ends_with('.'))
+    }
+}
+
+// Written as `DOMAIN=IP[,IP...]`, e.g. `*.corp.example=192.168.1.53`
+impl FromStr for SplitDnsRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (domain, servers) = s
+            .split_once('=')
+            .ok_or_else(|| format!("invalid split dns rule {s}, expected DOMAIN=IP[,IP...]"))?;
+        let servers = servers
+            .split(',')
+            .map(|ip| {
+                ip.trim()
+                    .parse()
+                    .map_err(|err| format!("invalid ip {ip} in {s}: {err}"))
+            })
+            .collect::<Result<_, _>>()?;
+        SplitDnsRule::new(domain, servers)
+    }
+}
+
+impl fmt::Display for SplitDnsRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let servers = self
+            .servers
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        write!(f, "{}={}", self.domain, servers.join(","))
+    }
+}
+
+// What goes with the rule for the most specific domain that the name is in
+fn most_specific_rule<'a, T>(rules: &'a [(SplitDnsRule, T)], name: &str) -> Option<&'a T> {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    rules
+        .iter()
+        .filter(|(rule, _)| rule.matches(&name))
+        .max_by_key(|(rule, _)| rule.domain.len())
+        .map(|(_, value)| value)
+}
+
+pub(crate) fn stub_resolver_opts(cache_size: usize) -> ResolverOpts {
+    let mut opts = ResolverOpts::default();
+    opts.cache_size = cache_size;
+    // We answer for the system resolver, which expects the CNAME chain along with the answer
+    opts.preserve_intermediates = true;
+    opts.use_hosts_file = false;
+    opts
+}
+
+fn plain_resolver(servers: &[IpAddr]) -> TokioAsyncResolver {
+    let name_servers = NameServerConfigGroup::from_ips_clear(servers, DNS_PORT, true);
+    TokioAsyncResolver::tokio(
+        ResolverConfig::from_parts(None, vec![], name_servers),
+        stub_resolver_opts(DEFAULT_CACHE_SIZE),
+    )
+}
+
+struct Resolvers {
+    // For everything that no split rule matches, through the tunnel
+    tunnel: TokioAsyncResolver,
+    split: Vec<(SplitDnsRule, TokioAsyncResolver)>,
+    // Answers the queries for blocked names before any of the resolvers see them
+    filter: Option<DnsFilter>,
+}
+
+impl Resolvers {
+    fn new(
+        config: &DnsConfig,
+        encrypted_dns: Option<&EncryptedDnsConfig>,
+        filter: Option<DnsFilter>,
+    ) -> Self {
+        let tunnel = match encrypted_dns {
+            Some(encrypted_dns) => encrypted_dns.resolver(),
+            None => plain_resolver(&config.servers_or_default()),
+        };
+        let split = config
+            .split_rules
+            .iter()
+            .map(|rule| (rule.clone(), plain_resolver(&rule.servers)))
+            .collect();
+        Resolvers {
+            tunnel,
+            split,
+            filter,
+        }
+    }
+
+    fn for_name(&self, name: &Name) -> &TokioAsyncResolver {
+        most_specific_rule(&self.split, &name.to_ascii()).unwrap_or(&self.tunnel)
+    }
+}
+
+// A local DNS server, listening on the tunnel address, that the system resolver is pointed at. It
+// forwards the queries for the split domains to their resolvers, and everything else to the tunnel
+// resolvers, encrypted when configured so that the exit doesn't see them. Names on the blocklists
+// are answered right away.
+pub(crate) struct DnsStub {
+    udp_socket: Arc<UdpSocket>,
+    tcp_listener: TcpListener,
+    resolvers: Arc<Resolvers>,
+}
+
+impl DnsStub {
+    pub(crate) async fn bind(
+        listen_ip: IpAddr,
+        config: &DnsConfig,
+        encrypted_dns: Option<&EncryptedDnsConfig>,
+        filter: Option<DnsFilter>,
+    ) -> std::io::Result<Self> {
+        let listen_address = SocketAddr::new(listen_ip, DNS_PORT);
+        let udp_socket = UdpSocket::bind(listen_address).await?;
+        let tcp_listener = TcpListener::bind(listen_address).await?;
+        match encrypted_dns {
+            Some(encrypted_dns) => info!(
+                "Resolving DNS on {listen_address} over {:?} through: {}",
+                encrypted_dns.protocol,
+                encrypted_dns
+                    .upstreams
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            None => info!(
+                "Resolving DNS on {listen_address} through: {:?}",
+                config.servers_or_default()
+            ),
+        }
+        for rule in &config.split_rules {
+            info!(
+                "Resolving {} and below through: {:?}",
+                rule.domain, rule.servers
+            );
+        }
+        Ok(DnsStub {
+            udp_socket: Arc::new(udp_socket),
+            tcp_listener,
+            resolvers: Arc::new(Resolvers::new(config, encrypted_dns, filter)),
+        })
+    }
+
+    pub(crate) async fn run(self, mut shutdown: TaskClient) {
+        debug!("DNS stub is running");
+        let mut buf = vec![0u8; MAX_UDP_PAYLOAD as usize];
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => {
+                    trace!("DnsStub: Received shutdown");
+                    break;
+                }
+                received = self.udp_socket.recv_from(&mut buf) => match received {
+                    Ok((len, peer)) => self.answer_udp(&buf[..len], peer),
+                    Err(err) => error!("Failed to receive DNS query: {err}"),
+                },
+                accepted = self.tcp_listener.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        tokio::spawn(answer_tcp(stream, self.resolvers.clone()));
+                    }
+                    Err(err) => error!("Failed to accept DNS connection: {err}"),
+                },
+            }
+        }
+        debug!("DnsStub: Exiting");
+    }
+
+    // Each query is answered on its own task, so that a slow upstream doesn't hold up the others
+    fn answer_udp(&self, query: &[u8], peer: SocketAddr) {
+        let Ok(query) = Message::from_vec(query) else {
+            debug!("Dropping malformed DNS query from {peer}");
+            return;
+        };
+        let resolvers = self.resolvers.clone();
+        let udp_socket = self.udp_socket.clone();
+        tokio::spawn(async move {
+            let max_payload = query
+                .extensions()
+                .as_ref()
+                .map_or(MIN_UDP_PAYLOAD, Edns::max_payload)
+                .clamp(MIN_UDP_PAYLOAD, MAX_UDP_PAYLOAD);
+            let response = resolve(&resolvers, &query).await;
+            let Some(response) = encode_udp_response(response, max_payload) else {
+                return;
+            };
+            if let Err(err) = udp_socket.send_to(&response, peer).await {
+                debug!("Failed to send DNS response to {peer}: {err}");
+            }
+        });
+    }
+}
+
+async fn resolve(resolvers: &Resolvers, query: &Message) -> Message {
+    let mut response = Message::new();
+    response
+        .set_id(query.id())
+        .set_message_type(MessageType::Response)
+        .set_op_code(query.op_code())
+        .set_recursion_desired(query.recursion_desired())
+        .set_recursion_available(true)
+        .add_queries(query.queries().to_vec());
+    if query.extensions().is_some() {
+        let mut edns = Edns::new();
+        edns.set_max_payload(MAX_UDP_PAYLOAD);
+        response.set_edns(edns);
+    }
+
+    if query.op_code() != OpCode::Query {
+        response.set_response_code(ResponseCode::NotImp);
+        return response;
+    }
+    // Like most resolvers, we only answer queries with a single question
+    let [question] = query.queries() else {
+        response.set_response_code(ResponseCode::FormErr);
+        return response;
+    };
+    if resolvers
+        .filter
+        .as_ref()
+        .is_some_and(|filter| filter.check(question, &mut response))
+    {
+        return response;
+    }
+
+    match resolvers
+        .for_name(question.name())
+        .lookup(question.name().clone(), question.query_type())
+        .await
+    {
+        Ok(lookup) => {
+            response.add_answers(lookup.records().iter().cloned());
+        }
+        Err(err) => {
+            let response_code = match err.kind() {
+                ResolveErrorKind::NoRecordsFound { response_code, .. } => *response_code,
+                _ => {
+                    debug!("Failed to resolve {question}: {err}");
+                    ResponseCode::ServFail
+                }
+            };
+            response.set_response_code(response_code);
+        }
+    }
+    response
+}
+
+// Responses that don't fit are truncated, so that the client retries over TCP
+fn encode_udp_response(mut response: Message, max_payload: u16) -> Option<Vec<u8>> {
+    let encoded = response
+        .to_vec()
+        .inspect_err(|err| error!("Failed to encode DNS response: {err}"))
+        .ok()?;
+    if encoded.len() <= max_payload as usize {
+        return Some(encoded);
+    }
+    response.take_answers();
+    response.take_name_servers();
+    response.take_additionals();
+    response.set_truncated(true);
+    response
+        .to_vec()
+        .inspect_err(|err| error!("Failed to encode DNS response: {err}"))
+        .ok()
+}
+
+// DNS over TCP prefixes each message with its length. The client can send several queries on the
+// same connection, which are answered in order.
+async fn answer_tcp(mut stream: TcpStream, resolvers: Arc<Resolvers>) {
+    loop {
+        let len = match tokio::time::timeout(TCP_IDLE_TIMEOUT, stream.read_u16()).await {
+            Ok(Ok(len)) => len,
+            // Closed by the client, or idle for too long
+            Ok(Err(_)) | Err(_) => return,
+        };
+        let mut query = vec![0u8; len as usize];
+        if stream.read_exact(&mut query).await.is_err() {
+            return;
+        }
+        let Ok(query) = Message::from_vec(&query) else {
+            debug!("Dropping malformed DNS query over TCP");
+            return;
+        };
+        let response = match resolve(&resolvers, &query).await.to_vec() {
+            Ok(response) => response,
+            Err(err) => {
+                error!("Failed to encode DNS response: {err}");
+                return;
+            }
+        };
+        let mut framed = Vec::with_capacity(response.len() + 2);
+        framed.extend_from_slice(&(response.len() as u16).to_be_bytes());
+        framed.extend_from_slice(&response);
+        if stream.write_all(&framed).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use hickory_resolver::{
+        config::{NameServerConfig, Protocol},
+        proto::{
+            op::Query,
+            rr::{rdata::A, RData, Record, RecordType},
+        },
+    };
+
+    use super::*;
+
+    const LARGE_ANSWER_COUNT: u8 = 64;
+
+    // An upstream over TCP that answers large.test with more records than fit in a plain UDP
+    // response, and everything else with NXDOMAIN
+    async fn mock_upstream() -> SocketAddr {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    while let Ok(len) = stream.read_u16().await {
+                        let mut query = vec![0u8; len as usize];
+                        stream.read_exact(&mut query).await.unwrap();
+                        let query = Message::from_vec(&query).unwrap();
+                        let response = mock_answer(&query).to_vec().unwrap();
+                        stream.write_u16(response.len() as u16).await.unwrap();
+                        stream.write_all(&response).await.unwrap();
+                    }
+                });
+            }
+        });
+        address
+    }
+
+    fn mock_answer(query: &Message) -> Message {
+        let mut response = Message::new();
+        response
+            .set_id(query.id())
+            .set_message_type(MessageType::Response)
+            .set_recursion_available(true)
+            .add_queries(query.queries().to_vec());
+        let name = query.queries()[0].name().clone();
+        if name == Name::from_ascii("large.test.").unwrap() {
+            for i in 0..LARGE_ANSWER_COUNT {
+                let rdata = RData::A(A(Ipv4Addr::new(10, 0, 0, i)));
+                response.add_answer(Record::from_rdata(name.clone(), 300, rdata));
+            }
+        } else {
+            response.set_response_code(ResponseCode::NXDomain);
+        }
+        response
+    }
+
+    fn mock_resolvers(upstream: SocketAddr) -> Resolvers {
+        let mut name_server = NameServerConfig::new(upstream, Protocol::Tcp);
+        name_server.trust_negative_responses = true;
+        let tunnel = TokioAsyncResolver::tokio(
+            ResolverConfig::from_parts(None, vec![], vec![name_server]),
+            stub_resolver_opts(DEFAULT_CACHE_SIZE),
+        );
+        Resolvers {
+            tunnel,
+            split: Vec::new(),
+            filter: None,
+        }
+    }
+
+    fn query(name: &str) -> Message {
+        let mut query = Message::new();
+        query
+            .set_id(4242)
+            .set_recursion_desired(true)
+            .add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+        query
+    }
+
+    #[tokio::test]
+    async fn large_answers_are_truncated_over_udp_and_complete_over_tcp() {
+        let resolvers = Arc::new(mock_resolvers(mock_upstream().await));
+
+        let response = resolve(&resolvers, &query("large.test.")).await;
+        assert_eq!(response.answers().len(), usize::from(LARGE_ANSWER_COUNT));
+        let udp_response = encode_udp_response(response, MIN_UDP_PAYLOAD).unwrap();
+        assert!(udp_response.len() <= usize::from(MIN_UDP_PAYLOAD));
+        let udp_response = Message::from_vec(&udp_response).unwrap();
+        assert!(udp_response.truncated());
+        assert!(udp_response.answers().is_empty());
+        assert_eq!(udp_response.id(), 4242);
+
+        // The client retries over TCP, where the whole answer fits
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        tokio::spawn(answer_tcp(stream, resolvers));
+
+        let query = query("large.test.").to_vec().unwrap();
+        client.write_u16(query.len() as u16).await.unwrap();
+        client.write_all(&query).await.unwrap();
+        let mut tcp_response = vec![0u8; client.read_u16().await.unwrap() as usize];
+        client.read_exact(&mut tcp_response).await.unwrap();
+        let tcp_response = Message::from_vec(&tcp_response).unwrap();
+        assert!(!tcp_response.truncated());
+        assert_eq!(tcp_response.id(), 4242);
+        assert_eq!(
+            tcp_response.answers().len(),
+            usize::from(LARGE_ANSWER_COUNT)
+        );
+    }
+
+    #[tokio::test]
+    async fn nxdomain_is_passed_through() {
+        let resolvers = mock_resolvers(mock_upstream().await);
+
+        let response = resolve(&resolvers, &query("missing.test.")).await;
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        assert!(response.answers().is_empty());
+        assert_eq!(response.queries(), query("missing.test.").queries());
+    }
+
+    #[test]
+    fn split_rule_for_most_specific_domain() {
+        let rules = [
+            ("*.corp.example=192.168.1.53".parse().unwrap(), "corp"),
+            (
+                "lab.corp.example.=10.1.0.53,10.1.0.54".parse().unwrap(),
+                "lab",
+            ),
+        ];
+        assert_eq!(most_specific_rule(&rules, "corp.example."), Some(&"corp"));
+        assert_eq!(
+            most_specific_rule(&rules, "Wiki.Corp.Example."),
+            Some(&"corp")
+        );
+        assert_eq!(
+            most_specific_rule(&rules, "ci.lab.corp.example."),
+            Some(&"lab")
+        );
+        assert_eq!(most_specific_rule(&rules, "notcorp.example."), None);
+        assert_eq!(most_specific_rule(&rules, "example."), None);
+
+        let (lab, _) = &rules[1];
+        assert_eq!(lab.to_string(), "lab.corp.example=10.1.0.53,10.1.0.54");
+        assert!("corp.example".parse::<SplitDnsRule>().is_err());
+        assert!("*.=192.168.1.53".parse::<SplitDnsRule>().is_err());
+    }
+}
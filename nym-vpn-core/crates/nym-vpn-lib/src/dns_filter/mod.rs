@@ -0,0 +1,587 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use hickory_resolver::proto::{
+    op::{Message, Query, ResponseCode},
+    rr::{
+        rdata::{A, AAAA},
+        RData, Record, RecordType,
+    },
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+const TRACKERS: &str = include_str!("trackers.txt");
+
+// How long clients may cache the answers to blocked queries
+const BLOCKED_TTL: u32 = 60;
+
+// Names found in hosts files that point at the machine itself, rather than at something to block
+const HOSTS_LOCAL_NAMES: &[&str] = &[
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlocklistFormat {
+    /// `0.0.0.0 ads.example` lines, as in a hosts file. Only the listed names are blocked.
+    Hosts,
+
+    /// `||ads.example^` lines, or just `ads.example`. The names below them are blocked too, and
+    /// `@@||ads.example^` exceptions are not blocked by the same list.
+    Adblock,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BundledBlocklist {
+    /// Common advertising, analytics and tracking domains.
+    Trackers,
+}
+
+impl BundledBlocklist {
+    fn content(self) -> &'static str {
+        match self {
+            BundledBlocklist::Trackers => TRACKERS,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BlocklistSource {
+    /// A list that ships with the library.
+    Bundled { list: BundledBlocklist },
+
+    /// A list on disk, e.g. one downloaded from a blocklist project.
+    File {
+        path: PathBuf,
+        format: BlocklistFormat,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Blocklist {
+    /// Identifies the list in the settings and in the hit counters.
+    pub name: String,
+
+    /// Lists that are not enabled are kept in the settings, but not loaded.
+    pub enabled: bool,
+
+    pub source: BlocklistSource,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockResponse {
+    /// Answer that the name doesn't exist.
+    #[default]
+    NxDomain,
+
+    /// Answer A and AAAA queries with 0.0.0.0 and ::, and other queries with no records.
+    #[serde(alias = "unspecified")]
+    NullAddress,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DnsFilterConfig {
+    /// The lists of domains to block, checked in order.
+    pub blocklists: Vec<Blocklist>,
+
+    /// Domains, and the names below them, that are never blocked, even when on a blocklist.
+    pub allowlist: Vec<String>,
+
+    /// How the queries for blocked names are answered.
+    pub block_response: BlockResponse,
+}
+
+impl Default for DnsFilterConfig {
+    // The bundled tracker list, until the user adds their own
+    fn default() -> Self {
+        DnsFilterConfig {
+            blocklists: vec![Blocklist {
+                name: "trackers".to_string(),
+                enabled: true,
+                source: BlocklistSource::Bundled {
+                    list: BundledBlocklist::Trackers,
+                },
+            }],
+            allowlist: Vec::new(),
+            block_response: BlockResponse::default(),
+        }
+    }
+}
+
+impl DnsFilterConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        let mut names = HashSet::new();
+        for blocklist in &self.blocklists {
+            if blocklist.name.trim().is_empty() {
+                return Err("blocklists must have a name".to_string());
+            }
+            if !names.insert(blocklist.name.as_str()) {
+                return Err(format!("duplicate blocklist name {}", blocklist.name));
+            }
+        }
+        if let Some(domain) = self
+            .allowlist
+            .iter()
+            .find(|domain| normalize_domain(domain).is_none())
+        {
+            return Err(format!("invalid allowlist domain {domain}"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("failed to load the dns blocklist {name} from {}: {source}", path.display())]
+pub struct LoadBlocklistError {
+    name: String,
+    path: PathBuf,
+    source: std::io::Error,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlocklistStats {
+    pub name: String,
+    // The number of domains loaded from the list
+    pub domains: usize,
+    pub hits: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DnsFilterStats {
+    // The queries seen by the filter since the tunnel was set up
+    pub queries: u64,
+    pub blocked: u64,
+    // Queries for names on a blocklist that the allowlist let through
+    pub allowed: u64,
+    // The enabled blocklists, in the order they are checked
+    pub blocklists: Vec<BlocklistStats>,
+}
+
+// Counts what the DNS filter of a running tunnel does. Cheap to clone, all clones share the same
+// counters.
+#[derive(Clone, Default)]
+pub struct DnsFilterStatsHandle {
+    inner: Arc<Mutex<DnsFilterStats>>,
+}
+
+impl DnsFilterStatsHandle {
+    // The counters start over whenever the filter is loaded, e.g. when reconnecting
+    fn reset(&self, blocklists: Vec<BlocklistStats>) {
+        *self.inner.lock().unwrap() = DnsFilterStats {
+            blocklists,
+            ..Default::default()
+        };
+    }
+
+    fn record(&self, blocked_by: Option<usize>, allowed: bool) {
+        let mut stats = self.inner.lock().unwrap();
+        stats.queries += 1;
+        let Some(list) = blocked_by else {
+            return;
+        };
+        if allowed {
+            stats.allowed += 1;
+        } else {
+            stats.blocked += 1;
+            if let Some(blocklist) = stats.blocklists.get_mut(list) {
+                blocklist.hits += 1;
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> DnsFilterStats {
+        self.inner.lock().unwrap().clone()
+    }
+}
+
+// A domain on a blocklist, and whether the names below it are blocked too
+#[derive(Debug, PartialEq, Eq)]
+struct Entry {
+    domain: String,
+    subdomains: bool,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ParsedBlocklist {
+    blocked: Vec<Entry>,
+    exceptions: Vec<String>,
+}
+
+// Lines that aren't entries we understand, such as comments or cosmetic adblock rules, are skipped
+fn parse_blocklist(content: &str, format: BlocklistFormat) -> ParsedBlocklist {
+    let mut parsed = ParsedBlocklist::default();
+    for line in content.lines() {
+        match format {
+            BlocklistFormat::Hosts => parse_hosts_line(line, &mut parsed),
+            BlocklistFormat::Adblock => parse_adblock_line(line, &mut parsed),
+        }
+    }
+    parsed
+}
+
+fn parse_hosts_line(line: &str, parsed: &mut ParsedBlocklist) {
+    let line = line.split('#').next().unwrap_or_default();
+    let mut fields = line.split_whitespace();
+    if !fields.next().is_some_and(|ip| ip.parse::<IpAddr>().is_ok()) {
+        return;
+    }
+    parsed.blocked.extend(
+        fields
+            .filter(|name| !HOSTS_LOCAL_NAMES.contains(name))
+            .filter_map(normalize_domain)
+            .map(|domain| Entry {
+                domain,
+                subdomains: false,
+            }),
+    );
+}
+
+// Rules with options or paths can't be applied to DNS queries, so only plain domain rules are used
+fn parse_adblock_line(line: &str, parsed: &mut ParsedBlocklist) {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with(['!', '#', '[']) {
+        return;
+    }
+    let (rule, exception) = match line.strip_prefix("@@") {
+        Some(rule) => (rule, true),
+        None => (line, false),
+    };
+    let domain = match rule.strip_prefix("||") {
+        Some(rule) => match rule.strip_suffix("^|").or_else(|| rule.strip_suffix('^')) {
+            Some(domain) => domain,
+            None => return,
+        },
+        None => rule,
+    };
+    let Some(domain) = normalize_domain(domain) else {
+        return;
+    };
+    if exception {
+        parsed.exceptions.push(domain);
+    } else {
+        parsed.blocked.push(Entry {
+            domain,
+            subdomains: true,
+        });
+    }
+}
+
+// The name itself, then the domains it is in, up to the top level one
+fn domain_suffixes(name: &str) -> impl Iterator<Item = &str> {
+    std::iter::successors(Some(name), |suffix| {
+        suffix.split_once('.').map(|(_, parent)| parent)
+    })
+}
+
+// Whether the name is one of the domains, or below one of them
+fn in_domains(domains: &HashSet<String>, name: &str) -> bool {
+    domain_suffixes(name).any(|suffix| domains.contains(suffix))
+}
+
+// Lowercased, without a leading `*.` or trailing dot. None when it isn't a domain name.
+fn normalize_domain(domain: &str) -> Option<String> {
+    let domain = domain
+        .trim()
+        .trim_start_matches("*.")
+        .trim_end_matches('.')
+        .to_ascii_lowercase();
+    let valid = !domain.is_empty()
+        && domain.parse::<IpAddr>().is_err()
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        });
+    valid.then_some(domain)
+}
+
+// A domain on one of the enabled blocklists, by the index of the list
+struct BlockedDomain {
+    list: usize,
+    subdomains: bool,
+}
+
+// Answers the queries for names on the enabled blocklists, before they are sent anywhere
+pub(crate) struct DnsFilter {
+    // In the order of the lists
+    blocked: HashMap<String, Vec<BlockedDomain>>,
+    // The exceptions of each list only apply to the domains on that list
+    exceptions: Vec<HashSet<String>>,
+    allowed: HashSet<String>,
+    block_response: BlockResponse,
+    stats: DnsFilterStatsHandle,
+}
+
+impl DnsFilter {
+    pub(crate) async fn load(
+        config: &DnsFilterConfig,
+        stats: DnsFilterStatsHandle,
+    ) -> Result<Self, LoadBlocklistError> {
+        let mut lists = Vec::new();
+        for blocklist in config
+            .blocklists
+            .iter()
+            .filter(|blocklist| blocklist.enabled)
+        {
+            let parsed = match &blocklist.source {
+                BlocklistSource::Bundled { list } => {
+                    parse_blocklist(list.content(), BlocklistFormat::Adblock)
+                }
+                BlocklistSource::File { path, format } => {
+                    let content = tokio::fs::read_to_string(path).await.map_err(|source| {
+                        LoadBlocklistError {
+                            name: blocklist.name.clone(),
+                            path: path.clone(),
+                            source,
+                        }
+                    })?;
+                    parse_blocklist(&content, *format)
+                }
+            };
+            info!(
+                "Loaded {} domains from DNS blocklist {}",
+                parsed.blocked.len(),
+                blocklist.name
+            );
+            lists.push((blocklist.name.clone(), parsed));
+        }
+        Ok(Self::new(config, lists, stats))
+    }
+
+    fn new(
+        config: &DnsFilterConfig,
+        lists: Vec<(String, ParsedBlocklist)>,
+        stats: DnsFilterStatsHandle,
+    ) -> Self {
+        let mut blocked = HashMap::<String, Vec<BlockedDomain>>::new();
+        let mut exceptions = Vec::with_capacity(lists.len());
+        let allowed = config
+            .allowlist
+            .iter()
+            .filter_map(|domain| normalize_domain(domain))
+            .collect();
+        let mut list_stats = Vec::with_capacity(lists.len());
+        for (list, (name, parsed)) in lists.into_iter().enumerate() {
+            list_stats.push(BlocklistStats {
+                name,
+                domains: parsed.blocked.len(),
+                hits: 0,
+            });
+            for entry in parsed.blocked {
+                blocked
+                    .entry(entry.domain)
+                    .or_default()
+                    .push(BlockedDomain {
+                        list,
+                        subdomains: entry.subdomains,
+                    });
+            }
+            exceptions.push(parsed.exceptions.into_iter().collect());
+        }
+        stats.reset(list_stats);
+        DnsFilter {
+            blocked,
+            exceptions,
+            allowed,
+            block_response: config.block_response,
+            stats,
+        }
+    }
+
+    // The first list that blocks the name without an exception for it, or else the first one
+    // that lists it along with an exception. The flag tells whether the exception applies.
+    fn blocked_by(&self, name: &str) -> Option<(usize, bool)> {
+        let mut lists: Vec<usize> = domain_suffixes(name)
+            .enumerate()
+            .filter_map(|(depth, suffix)| Some((depth == 0, self.blocked.get(suffix)?)))
+            .flat_map(|(exact, domains)| {
+                domains
+                    .iter()
+                    .filter(move |domain| exact || domain.subdomains)
+                    .map(|domain| domain.list)
+            })
+            .collect();
+        lists.sort_unstable();
+        let excepted = |list: usize| in_domains(&self.exceptions[list], name);
+        lists
+            .iter()
+            .find(|list| !excepted(**list))
+            .map(|list| (*list, false))
+            .or_else(|| lists.first().map(|list| (*list, true)))
+    }
+
+    fn is_allowed(&self, name: &str) -> bool {
+        in_domains(&self.allowed, name)
+    }
+
+    // Answers the query when the name is blocked. Every query is counted, blocked or not.
+    pub(crate) fn check(&self, query: &Query, response: &mut Message) -> bool {
+        let name = query
+            .name()
+            .to_ascii()
+            .trim_end_matches('.')
+            .to_ascii_lowercase();
+        let blocked_by = self.blocked_by(&name);
+        let allowed = blocked_by.is_some_and(|(_, excepted)| excepted || self.is_allowed(&name));
+        let blocked_by = blocked_by.map(|(list, _)| list);
+        self.stats.record(blocked_by, allowed);
+        if blocked_by.is_none() || allowed {
+            return false;
+        }
+
+        debug!("Blocking DNS query for {name}");
+        let rdata = match (self.block_response, query.query_type()) {
+            (BlockResponse::NxDomain, _) => {
+                response.set_response_code(ResponseCode::NXDomain);
+                return true;
+            }
+            (BlockResponse::NullAddress, RecordType::A) => RData::A(A(Ipv4Addr::UNSPECIFIED)),
+            (BlockResponse::NullAddress, RecordType::AAAA) => {
+                RData::AAAA(AAAA(Ipv6Addr::UNSPECIFIED))
+            }
+            (BlockResponse::NullAddress, _) => return true,
+        };
+        response.add_answer(Record::from_rdata(query.name().clone(), BLOCKED_TTL, rdata));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_hosts_and_adblock_lists() {
+        let hosts =
+            "# comment\n127.0.0.1 localhost\n0.0.0.0 ads.example Tracker.Example. # inline\n\
+                     not-an-ip skipped.example\n";
+        assert_eq!(
+            parse_blocklist(hosts, BlocklistFormat::Hosts).blocked,
+            vec![
+                Entry {
+                    domain: "ads.example".to_string(),
+                    subdomains: false,
+                },
+                Entry {
+                    domain: "tracker.example".to_string(),
+                    subdomains: false,
+                },
+            ]
+        );
+
+        let adblock = "! comment\n[Adblock Plus 2.0]\n||ads.example^\n||pixel.example^|\n\
+                       @@||ok.ads.example^\n||cosmetic.example^$third-party\nplain.example\n\
+                       example.com##.banner\n";
+        let parsed = parse_blocklist(adblock, BlocklistFormat::Adblock);
+        let domains: Vec<_> = parsed
+            .blocked
+            .iter()
+            .map(|entry| entry.domain.as_str())
+            .collect();
+        assert_eq!(domains, ["ads.example", "pixel.example", "plain.example"]);
+        assert!(parsed.blocked.iter().all(|entry| entry.subdomains));
+        assert_eq!(parsed.exceptions, ["ok.ads.example"]);
+
+        assert!(!parse_blocklist(TRACKERS, BlocklistFormat::Adblock)
+            .blocked
+            .is_empty());
+    }
+
+    #[test]
+    fn block_listed_names_unless_allowed() {
+        let config = DnsFilterConfig {
+            allowlist: vec!["*.cdn.ads.example".to_string()],
+            ..Default::default()
+        };
+        let lists = vec![
+            (
+                "hosts".to_string(),
+                parse_blocklist("0.0.0.0 tracker.example", BlocklistFormat::Hosts),
+            ),
+            (
+                "adblock".to_string(),
+                parse_blocklist(
+                    "||ads.example^\n@@||ok.ads.example^",
+                    BlocklistFormat::Adblock,
+                ),
+            ),
+        ];
+        let stats = DnsFilterStatsHandle::default();
+        let filter = DnsFilter::new(&config, lists, stats.clone());
+
+        let check = |name: &str| {
+            let query = Query::query(name.parse().unwrap(), RecordType::A);
+            filter.check(&query, &mut Message::new())
+        };
+        assert!(check("tracker.example."));
+        assert!(!check("www.tracker.example."));
+        assert!(check("ADS.example."));
+        assert!(check("banner.ads.example."));
+        assert!(!check("ok.ads.example."));
+        assert!(!check("img.cdn.ads.example."));
+        assert!(!check("example."));
+
+        let stats = stats.snapshot();
+        assert_eq!(stats.queries, 7);
+        assert_eq!(stats.blocked, 3);
+        assert_eq!(stats.allowed, 2);
+        assert_eq!(stats.blocklists[0].hits, 1);
+        assert_eq!(stats.blocklists[1].hits, 2);
+    }
+
+    #[test]
+    fn lists_only_except_and_count_their_own_domains() {
+        let lists = vec![
+            (
+                "hosts".to_string(),
+                parse_blocklist("0.0.0.0 ads.example", BlocklistFormat::Hosts),
+            ),
+            (
+                "adblock".to_string(),
+                parse_blocklist(
+                    "||ads.example^\n@@||ok.tracker.example^",
+                    BlocklistFormat::Adblock,
+                ),
+            ),
+            (
+                "trackers".to_string(),
+                parse_blocklist("||tracker.example^", BlocklistFormat::Adblock),
+            ),
+        ];
+        let stats = DnsFilterStatsHandle::default();
+        let filter = DnsFilter::new(&DnsFilterConfig::default(), lists, stats.clone());
+
+        let check = |name: &str| {
+            let query = Query::query(name.parse().unwrap(), RecordType::A);
+            filter.check(&query, &mut Message::new())
+        };
+        assert!(check("ads.example."));
+        assert!(check("www.ads.example."));
+        assert!(check("ok.tracker.example."));
+
+        let stats = stats.snapshot();
+        assert_eq!(stats.blocked, 3);
+        assert_eq!(stats.allowed, 0);
+        let hits: Vec<_> = stats.blocklists.iter().map(|list| list.hits).collect();
+        assert_eq!(hits, [1, 1, 1]);
+    }
+}
@@ -0,0 +1,189 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use hickory_resolver::{
+    config::{NameServerConfig, Protocol, ResolverConfig},
+    TokioAsyncResolver,
+};
+use serde::{Deserialize, Serialize};
+
+const CLOUDFLARE_TLS_NAME: &str = "cloudflare-dns.com";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EncryptedDnsProtocol {
+    /// DNS over HTTPS.
+    #[default]
+    Https,
+
+    /// DNS over TLS.
+    Tls,
+}
+
+impl EncryptedDnsProtocol {
+    fn default_port(self) -> u16 {
+        match self {
+            EncryptedDnsProtocol::Https => 443,
+            EncryptedDnsProtocol::Tls => 853,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct EncryptedDnsUpstream {
+    /// The address of the resolver.
+    pub ip: IpAddr,
+
+    /// The port of the resolver, when it isn't the default one of the protocol.
+    pub port: Option<u16>,
+
+    /// The name the certificate of the resolver is verified against.
+    pub tls_name: String,
+}
+
+// Written as `IP[@PORT]#TLS_NAME`, the way unbound and stubby write forwarders
+impl FromStr for EncryptedDnsUpstream {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, tls_name) = s
+            .split_once('#')
+            .ok_or_else(|| format!("missing tls name in {s}, expected IP[@PORT]#TLS_NAME"))?;
+        if tls_name.is_empty() {
+            return Err(format!("empty tls name in {s}"));
+        }
+        let (ip, port) = match address.split_once('@') {
+            Some((ip, port)) => {
+                let port = port
+                    .parse()
+                    .map_err(|err| format!("invalid port in {s}: {err}"))?;
+                (ip, Some(port))
+            }
+            None => (address, None),
+        };
+        let ip = ip
+            .parse()
+            .map_err(|err| format!("invalid ip in {s}: {err}"))?;
+        Ok(EncryptedDnsUpstream {
+            ip,
+            port,
+            tls_name: tls_name.to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for EncryptedDnsUpstream {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<EncryptedDnsUpstream> for String {
+    fn from(upstream: EncryptedDnsUpstream) -> Self {
+        upstream.to_string()
+    }
+}
+
+impl fmt::Display for EncryptedDnsUpstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.ip)?;
+        if let Some(port) = self.port {
+            write!(f, "@{port}")?;
+        }
+        write!(f, "#{}", self.tls_name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncryptedDnsConfig {
+    /// How the queries are sent to the upstream resolvers.
+    pub protocol: EncryptedDnsProtocol,
+
+    /// The resolvers the queries are forwarded to, through the tunnel.
+    pub upstreams: Vec<EncryptedDnsUpstream>,
+
+    /// How many answers are cached.
+    pub cache_size: usize,
+}
+
+impl EncryptedDnsConfig {
+    // Forwards to the same Cloudflare resolvers we use for plain DNS
+    pub fn new(protocol: EncryptedDnsProtocol) -> Self {
+        EncryptedDnsConfig {
+            protocol,
+            upstreams: crate::DEFAULT_DNS_SERVERS
+                .iter()
+                .map(|ip| EncryptedDnsUpstream {
+                    ip: *ip,
+                    port: None,
+                    tls_name: CLOUDFLARE_TLS_NAME.to_string(),
+                })
+                .collect(),
+            cache_size: crate::dns::DEFAULT_CACHE_SIZE,
+        }
+    }
+
+    pub(crate) fn resolver(&self) -> TokioAsyncResolver {
+        let protocol = match self.protocol {
+            EncryptedDnsProtocol::Https => Protocol::Https,
+            EncryptedDnsProtocol::Tls => Protocol::Tls,
+        };
+        let name_servers = self
+            .upstreams
+            .iter()
+            .map(|upstream| {
+                let port = upstream
+                    .port
+                    .unwrap_or_else(|| self.protocol.default_port());
+                let mut name_server =
+                    NameServerConfig::new(SocketAddr::new(upstream.ip, port), protocol);
+                name_server.tls_dns_name = Some(upstream.tls_name.clone());
+                name_server.trust_negative_responses = true;
+                name_server
+            })
+            .collect::<Vec<_>>();
+
+        TokioAsyncResolver::tokio(
+            ResolverConfig::from_parts(None, vec![], name_servers),
+            crate::dns::stub_resolver_opts(self.cache_size),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_upstream() {
+        let upstream: EncryptedDnsUpstream = "9.9.9.9@8853#dns.quad9.net".parse().unwrap();
+        assert_eq!(
+            upstream,
+            EncryptedDnsUpstream {
+                ip: "9.9.9.9".parse().unwrap(),
+                port: Some(8853),
+                tls_name: "dns.quad9.net".to_string(),
+            }
+        );
+        assert_eq!(upstream.to_string(), "9.9.9.9@8853#dns.quad9.net");
+
+        let upstream: EncryptedDnsUpstream =
+            "2606:4700:4700::1111#cloudflare-dns.com".parse().unwrap();
+        assert_eq!(upstream.port, None);
+
+        assert!("1.1.1.1".parse::<EncryptedDnsUpstream>().is_err());
+        assert!("1.1.1.1#".parse::<EncryptedDnsUpstream>().is_err());
+        assert!("one.one#cloudflare-dns.com"
+            .parse::<EncryptedDnsUpstream>()
+            .is_err());
+    }
+}
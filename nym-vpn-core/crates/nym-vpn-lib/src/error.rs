@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use nym_gateway_directory::NodeIdentity;
+use nym_ip_packet_requests::IpPair;
 
 use crate::{tunnel_setup::WaitInterfaceUpError, MixnetError};
 
@@ -19,6 +20,21 @@ pub enum Error {
     #[error("failed to reset firewall policy: {reason}")]
     FailedToResetFirewallPolicy { reason: String },
 
+    #[error("the kill switch is not supported in wireguard mode")]
+    KillSwitchNotSupportedWithWireguard,
+
+    #[error("encrypted dns is not supported in wireguard mode")]
+    EncryptedDnsNotSupportedWithWireguard,
+
+    #[error("split dns is not supported in wireguard mode")]
+    SplitDnsNotSupportedWithWireguard,
+
+    #[error("excluding networks from the tunnel is not supported in mixnet mode on windows")]
+    ExcludeNetworksNotSupportedWithMixnet,
+
+    #[error("split dns is not supported in mixnet mode on windows")]
+    SplitDnsNotSupportedWithMixnet,
+
     #[error("{0}")]
     CanceledError(#[from] futures::channel::oneshot::Canceled),
 
@@ -105,6 +121,14 @@ pub enum GatewayDirectoryError {
 
     #[error("unable to use same entry and exit gateway for location: {requested_location}")]
     SameEntryAndExitGatewayFromCountry { requested_location: String },
+
+    #[error("entry gateway {gateway_id} is the exit gateway, or runs on the same host")]
+    SameEntryAndExitGateway { gateway_id: String },
+
+    #[error(
+        "not enough gateways in distinct countries for {requested} middle hops, found {found}"
+    )]
+    NotEnoughMiddleGateways { requested: usize, found: usize },
 }
 
 // Errors specific to the mixnet. This often comes from the nym-sdk crate, but not necessarily.
@@ -134,6 +158,15 @@ pub enum SetupMixTunnelError {
     #[error("{0}")]
     DNSError(#[from] talpid_core::dns::Error),
 
+    #[error("failed to apply kill switch policy: {0}")]
+    KillSwitchError(String),
+
+    #[error("failed to start the dns resolver: {0}")]
+    FailedToStartDnsStub(#[source] std::io::Error),
+
+    #[error(transparent)]
+    FailedToLoadDnsBlocklist(#[from] crate::dns_filter::LoadBlocklistError),
+
     #[cfg(target_os = "android")]
     #[error("vpn errored on stop")]
     StopError,
@@ -158,6 +191,12 @@ pub enum SetupWgTunnelError {
     #[error("not enough bandwidth to setup tunnel")]
     NotEnoughBandwidthToSetupTunnel,
 
+    #[error("unsupported number of wireguard hops: {hop_count}, must be between 2 and {max}")]
+    UnsupportedHopCount { hop_count: usize, max: usize },
+
+    #[error("the path MTU {wire_mtu} is too small to carry {hop_count} wireguard hops")]
+    PathMtuTooSmall { wire_mtu: u16, hop_count: usize },
+
     #[error("failed to lookup gateway ip: {gateway_id}: {source}")]
     FailedToLookupGatewayIp {
         gateway_id: String,
@@ -173,6 +212,9 @@ pub enum SetupWgTunnelError {
     #[error("{0}")]
     WireguardConfigError(#[from] talpid_wireguard::config::Error),
 
+    #[error("{0}")]
+    DNSError(#[from] talpid_core::dns::Error),
+
     #[error("failed to parse entry gateway ipv4: {0}")]
     FailedToParseEntryGatewayIpv4(#[source] std::net::AddrParseError),
 
@@ -182,6 +224,43 @@ pub enum SetupWgTunnelError {
         public_key: String,
         source: WaitInterfaceUpError,
     },
+
+    #[error("failed to start the dns resolver: {0}")]
+    FailedToStartDnsStub(#[source] std::io::Error),
+
+    #[error(transparent)]
+    FailedToLoadDnsBlocklist(#[from] crate::dns_filter::LoadBlocklistError),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SwitchGatewayError {
+    #[error("switching the entry gateway is only supported in wireguard mode")]
+    EntrySwitchNotSupportedWithMixnet,
+
+    #[error(transparent)]
+    GatewayDirectoryError(#[from] GatewayDirectoryError),
+
+    #[error("exit gateway {gateway_id} has no ip packet router")]
+    MissingIpPacketRouter { gateway_id: String },
+
+    #[error("the mixnet processor is not running")]
+    MixnetProcessorNotRunning,
+
+    #[error("the exit gateway was chosen explicitly, so there is no other exit to fail over to")]
+    ExitPinned,
+
+    #[error("failed to connect to ip packet router: {0}")]
+    FailedToConnectToIpPacketRouter(#[source] nym_ip_packet_client::Error),
+
+    #[error("the exit gateway assigned us other ips: {ips}")]
+    ExitAssignedOtherIps { ips: IpPair },
+
+    #[error("failed to setup wireguard tunnels: {0}")]
+    SetupWgTunnelError(#[from] SetupWgTunnelError),
+
+    // The old tunnel was already torn down, so we are no longer connected
+    #[error("lost the tunnel while switching gateways: {0}")]
+    TunnelLost(#[source] Box<dyn std::error::Error + Send + Sync>),
 }
 
 // Result type based on our error type
@@ -0,0 +1,216 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::time::{Duration, Instant};
+
+use nym_connection_monitor::ConnectionMonitorStatus;
+use nym_gateway_directory::{ExitPoint, NodeIdentity};
+use tracing::{debug, info};
+
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(30);
+const DEFAULT_EXCLUSION_PERIOD: Duration = Duration::from_secs(10 * 60);
+
+// Where to look for a new exit when the current one fails
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExitFailoverScope {
+    /// Another exit in the country of the one that failed.
+    #[default]
+    SameLocation,
+
+    /// The best exit anywhere.
+    AnyLocation,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExitFailoverConfig {
+    /// Switch to another exit when the connection monitor keeps reporting that the exit isn't
+    /// routing our traffic. Off unless asked for, since it moves the traffic to another exit
+    /// without the user choosing it.
+    pub enabled: bool,
+
+    /// How long the exit has to keep failing before we switch away from it.
+    pub grace_period: Duration,
+
+    /// Where to look for a new exit.
+    pub scope: ExitFailoverScope,
+
+    /// How long a failed exit is left out when picking a new one.
+    pub exclusion_period: Duration,
+}
+
+impl Default for ExitFailoverConfig {
+    fn default() -> Self {
+        ExitFailoverConfig {
+            enabled: false,
+            grace_period: DEFAULT_GRACE_PERIOD,
+            scope: ExitFailoverScope::default(),
+            exclusion_period: DEFAULT_EXCLUSION_PERIOD,
+        }
+    }
+}
+
+// Follows the status reports of the connection monitor, and decides when the exit has failed for
+// long enough that we should move to another one
+pub(crate) struct ExitFailover {
+    config: ExitFailoverConfig,
+    // Since when the monitor has been reporting the exit as failing, without it recovering
+    failing_since: Option<Instant>,
+    // Exits that failed us, and when they did
+    excluded: Vec<(NodeIdentity, Instant)>,
+}
+
+impl ExitFailover {
+    pub(crate) fn new(config: ExitFailoverConfig) -> Self {
+        ExitFailover {
+            config,
+            failing_since: None,
+            excluded: Vec::new(),
+        }
+    }
+
+    // Returns true when it's time to fail over
+    pub(crate) fn observe(&mut self, status: &ConnectionMonitorStatus, now: Instant) -> bool {
+        if !self.config.enabled {
+            return false;
+        }
+        match status {
+            ConnectionMonitorStatus::ExitGatewayDownIpv4
+            | ConnectionMonitorStatus::ExitGatewayRoutingErrorIpv4 => {
+                let failing_since = *self.failing_since.get_or_insert(now);
+                now.duration_since(failing_since) >= self.config.grace_period
+            }
+            ConnectionMonitorStatus::ConnectedIpv4 => {
+                if self.failing_since.take().is_some() {
+                    debug!("The exit recovered before failing over");
+                }
+                false
+            }
+            // Without a working entry nothing reaches the exit, so there is nothing to say about
+            // it. IPv6 is not required for the connection to be usable.
+            ConnectionMonitorStatus::EntryGatewayDown
+            | ConnectionMonitorStatus::ExitGatewayDownIpv6
+            | ConnectionMonitorStatus::ExitGatewayRoutingErrorIpv6
+            | ConnectionMonitorStatus::ConnectedIpv6
+            | ConnectionMonitorStatus::ConnectionQuality(_) => false,
+        }
+    }
+
+    // Where to look for the exit to switch to, after the one in `failed_exit_country` failed. An
+    // exit given by address or by identity is an explicit choice, so we stay with it.
+    pub(crate) fn failover_exit_point(
+        &self,
+        exit_point: &ExitPoint,
+        failed_exit_country: Option<&str>,
+    ) -> Option<ExitPoint> {
+        if let ExitPoint::Address { .. } | ExitPoint::Gateway { .. } = exit_point {
+            return None;
+        }
+        let location = match exit_point {
+            ExitPoint::Location { location } => Some(location.clone()),
+            _ => failed_exit_country.map(ToString::to_string),
+        };
+        match (self.config.scope, location) {
+            (ExitFailoverScope::SameLocation, Some(location)) => {
+                Some(ExitPoint::Location { location })
+            }
+            (ExitFailoverScope::SameLocation, None) | (ExitFailoverScope::AnyLocation, _) => {
+                Some(ExitPoint::Best)
+            }
+        }
+    }
+
+    pub(crate) fn exclude(&mut self, failed_exit: NodeIdentity, now: Instant) {
+        info!(
+            "Leaving out exit gateway {failed_exit} for {}s",
+            self.config.exclusion_period.as_secs()
+        );
+        self.excluded.retain(|(exit, _)| *exit != failed_exit);
+        self.excluded.push((failed_exit, now));
+    }
+
+    // After switching exits, whether it worked or not, the exit we are on gets a fresh grace
+    // period
+    pub(crate) fn reset(&mut self) {
+        self.failing_since = None;
+    }
+
+    pub(crate) fn excluded_exits(&mut self, now: Instant) -> Vec<NodeIdentity> {
+        let exclusion_period = self.config.exclusion_period;
+        self.excluded
+            .retain(|(_, excluded_at)| now.duration_since(*excluded_at) < exclusion_period);
+        self.excluded.iter().map(|(exit, _)| *exit).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fails_over_after_grace_period() {
+        let start = Instant::now();
+        let mut failover = ExitFailover::new(ExitFailoverConfig {
+            enabled: true,
+            ..Default::default()
+        });
+        let routing_error = ConnectionMonitorStatus::ExitGatewayRoutingErrorIpv4;
+
+        assert!(!failover.observe(&routing_error, start));
+        assert!(!failover.observe(&routing_error, start + Duration::from_secs(20)));
+        // Recovering in between starts the grace period over
+        assert!(!failover.observe(&ConnectionMonitorStatus::ConnectedIpv4, start));
+        assert!(!failover.observe(&routing_error, start + Duration::from_secs(30)));
+        assert!(!failover.observe(
+            &ConnectionMonitorStatus::ExitGatewayDownIpv6,
+            start + Duration::from_secs(45)
+        ));
+        assert!(failover.observe(
+            &ConnectionMonitorStatus::ExitGatewayDownIpv4,
+            start + Duration::from_secs(60)
+        ));
+
+        // Disabled unless asked for
+        let mut failover = ExitFailover::new(ExitFailoverConfig::default());
+        assert!(!failover.observe(&routing_error, start));
+        assert!(!failover.observe(&routing_error, start + Duration::from_secs(120)));
+    }
+
+    #[test]
+    fn failover_exit_point_follows_scope() {
+        let same_location = ExitFailover::new(ExitFailoverConfig::default());
+        assert!(matches!(
+            same_location.failover_exit_point(&ExitPoint::Best, Some("DE")),
+            Some(ExitPoint::Location { location }) if location == "DE"
+        ));
+        assert!(matches!(
+            same_location.failover_exit_point(&ExitPoint::Random, None),
+            Some(ExitPoint::Best)
+        ));
+
+        let any_location = ExitFailover::new(ExitFailoverConfig {
+            scope: ExitFailoverScope::AnyLocation,
+            ..Default::default()
+        });
+        let location = ExitPoint::Location {
+            location: "DE".to_string(),
+        };
+        assert!(matches!(
+            any_location.failover_exit_point(&location, Some("DE")),
+            Some(ExitPoint::Best)
+        ));
+    }
+
+    #[test]
+    fn explicitly_chosen_exits_are_kept() {
+        let any_location = ExitFailover::new(ExitFailoverConfig {
+            scope: ExitFailoverScope::AnyLocation,
+            ..Default::default()
+        });
+        let gateway = ExitPoint::Gateway {
+            identity: *nym_sdk::mixnet::ed25519::KeyPair::new(&mut rand::rngs::OsRng).public_key(),
+        };
+        assert!(any_location
+            .failover_exit_point(&gateway, Some("DE"))
+            .is_none());
+    }
+}
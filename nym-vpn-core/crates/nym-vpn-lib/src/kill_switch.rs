@@ -0,0 +1,516 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use nym_connection_monitor::{ICMP_IPR_TUN_IP_V4, ICMP_IPR_TUN_IP_V6};
+use nym_ip_packet_requests::IpPair;
+use talpid_core::firewall::{Firewall, FirewallPolicy};
+use talpid_tunnel::TunnelMetadata;
+use talpid_types::net::{
+    AllowedClients, AllowedEndpoint, AllowedTunnelTraffic, Endpoint, TransportProtocol,
+};
+use tracing::{debug, error, info};
+
+use crate::error::{Error, Result};
+
+// The port gateways listen on for websocket connections from clients, if not advertised otherwise
+pub(crate) const DEFAULT_GATEWAY_CLIENTS_WS_PORT: u16 = 9000;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KillSwitchConfig {
+    /// Block all traffic that does not go through the tunnel, from the moment the mixnet client
+    /// is connected to the entry gateway until the user disconnects. Only supported in mixnet
+    /// mode, since the nested wireguard tunnels of two-hop mode need more than the one tunnel
+    /// interface the firewall policies let through.
+    pub enabled: bool,
+
+    /// Allow traffic to and from the local network while blocking.
+    pub allow_lan: bool,
+}
+
+// The firewall the kill switch applies its policies with
+pub(crate) trait PolicyFirewall {
+    fn apply_policy(&mut self, policy: FirewallPolicy) -> std::result::Result<(), String>;
+    fn reset_policy(&mut self) -> std::result::Result<(), String>;
+}
+
+impl PolicyFirewall for Firewall {
+    fn apply_policy(&mut self, policy: FirewallPolicy) -> std::result::Result<(), String> {
+        Firewall::apply_policy(self, policy).map_err(|err| err.to_string())
+    }
+
+    fn reset_policy(&mut self) -> std::result::Result<(), String> {
+        Firewall::reset_policy(self).map_err(|err| err.to_string())
+    }
+}
+
+// Wraps the firewall and applies the policies needed for the kill switch as the tunnel is being
+// set up. When the kill switch is disabled we only ever reset the firewall policy.
+//
+// The block starts once the mixnet client is connected to the entry gateway. Selecting the
+// gateways and starting the mixnet client needs DNS and the directory services, for the topology,
+// the gateway details and the credentials, which the firewall policies can't allow alongside the
+// entry gateway. When a block is already in place, e.g. when reconnecting after a failure, the
+// selection is served from the gateway cache, and the block is only lifted while the mixnet client
+// starts.
+pub(crate) struct KillSwitch<F: PolicyFirewall = Firewall> {
+    firewall: F,
+    config: KillSwitchConfig,
+
+    // The endpoint of the entry gateway, once it has been selected
+    entry_gateway: Option<SocketAddr>,
+
+    // What the policy in place lets through, not set when the firewall isn't filtering
+    allowed_traffic: Option<AllowedTraffic>,
+}
+
+impl<F: PolicyFirewall> KillSwitch<F> {
+    pub(crate) fn new(firewall: F, config: KillSwitchConfig) -> Self {
+        Self {
+            firewall,
+            config,
+            entry_gateway: None,
+            allowed_traffic: None,
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    pub(crate) fn entry_gateway(&self) -> Option<SocketAddr> {
+        self.entry_gateway
+    }
+
+    pub(crate) fn allowed_traffic(&self) -> Option<AllowedTraffic> {
+        self.allowed_traffic.clone()
+    }
+
+    // The mixnet client fetches what it needs from the directory services while it starts, so a
+    // block left in place by a failure is lifted until it is connected.
+    pub(crate) fn allow_mixnet_bootstrap(&mut self) -> Result<()> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        info!("Kill switch: lifting the block while the mixnet client starts");
+        self.reset()
+    }
+
+    // Once the mixnet client is connected to the entry gateway, block everything except the
+    // connection to it.
+    pub(crate) fn allow_entry_gateway(&mut self, entry_gateway: SocketAddr) -> Result<()> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        self.entry_gateway = Some(entry_gateway);
+
+        info!("Kill switch: blocking all traffic except to entry gateway {entry_gateway}");
+        self.apply_policy(connecting_policy(entry_gateway, self.config.allow_lan))
+    }
+
+    // The tunnel device is up, from now on only the tunnel and the entry gateway are reachable.
+    pub(crate) fn allow_mixnet_tunnel(
+        &mut self,
+        tun_device: &tun2::AsyncDevice,
+        tun_ips: IpPair,
+        dns_servers: Vec<IpAddr>,
+    ) -> Result<()> {
+        use tun2::AbstractDevice;
+
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let Some(entry_gateway) = self.entry_gateway else {
+            error!("Kill switch: entry gateway not set when allowing the tunnel");
+            return self.block();
+        };
+        let interface = tun_device
+            .as_ref()
+            .tun_name()
+            .map_err(|err| Error::FirewallError(err.to_string()))?;
+
+        info!("Kill switch: allowing traffic through the tunnel only");
+        self.apply_policy(connected_policy(
+            entry_gateway,
+            interface,
+            tun_ips,
+            dns_servers,
+            self.config.allow_lan,
+        ))
+    }
+
+    // Block everything, used when the tunnel went down without the user asking for it.
+    pub(crate) fn block(&mut self) -> Result<()> {
+        info!("Kill switch: blocking all traffic");
+        self.apply_policy(blocked_policy(self.config.allow_lan))
+    }
+
+    pub(crate) fn reset(&mut self) -> Result<()> {
+        self.firewall.reset_policy().map_err(|reason| {
+            error!("Failed to reset firewall policy: {reason}");
+            Error::FailedToResetFirewallPolicy { reason }
+        })?;
+        self.allowed_traffic = None;
+        Ok(())
+    }
+
+    // Called when the vpn exits. If the user asked us to stop we lift the block, otherwise we keep
+    // blocking until the user explicitly disconnects.
+    pub(crate) fn close(&mut self, stopped_by_user: bool) -> Result<()> {
+        if self.is_enabled() && !stopped_by_user {
+            self.block()
+        } else {
+            self.reset()
+        }
+    }
+
+    fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<()> {
+        debug!("Applying firewall policy: {policy}");
+        let allowed_traffic = AllowedTraffic::from_policy(&policy);
+        self.firewall
+            .apply_policy(policy)
+            .map_err(Error::FirewallError)?;
+        self.allowed_traffic = Some(allowed_traffic);
+        Ok(())
+    }
+}
+
+// The traffic a firewall policy lets out, used by the leak test to tell whether traffic routed
+// outside the tunnel is dropped
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct AllowedTraffic {
+    tunnel_interface: Option<String>,
+    endpoints: Vec<SocketAddr>,
+    dns_servers: Vec<IpAddr>,
+    allow_lan: bool,
+}
+
+impl AllowedTraffic {
+    fn from_policy(policy: &FirewallPolicy) -> Self {
+        match policy {
+            FirewallPolicy::Connecting {
+                peer_endpoint,
+                allowed_endpoint,
+                allow_lan,
+                ..
+            } => AllowedTraffic {
+                endpoints: vec![
+                    peer_endpoint.endpoint.address,
+                    allowed_endpoint.endpoint.address,
+                ],
+                allow_lan: *allow_lan,
+                ..Default::default()
+            },
+            FirewallPolicy::Connected {
+                peer_endpoint,
+                tunnel,
+                allow_lan,
+                #[cfg(not(target_os = "android"))]
+                dns_servers,
+                ..
+            } => AllowedTraffic {
+                tunnel_interface: Some(tunnel.interface.clone()),
+                endpoints: vec![peer_endpoint.endpoint.address],
+                #[cfg(not(target_os = "android"))]
+                dns_servers: dns_servers.clone(),
+                allow_lan: *allow_lan,
+                ..Default::default()
+            },
+            FirewallPolicy::Blocked {
+                allow_lan,
+                allowed_endpoint,
+                ..
+            } => AllowedTraffic {
+                endpoints: allowed_endpoint
+                    .iter()
+                    .map(|allowed| allowed.endpoint.address)
+                    .collect(),
+                allow_lan: *allow_lan,
+                ..Default::default()
+            },
+        }
+    }
+
+    // Whether traffic to the destination, leaving on the interface, gets through
+    pub(crate) fn allows(&self, destination: SocketAddr, interface: Option<&str>) -> bool {
+        interface.is_some_and(|interface| self.tunnel_interface.as_deref() == Some(interface))
+            || self.endpoints.contains(&destination)
+            || self.dns_servers.contains(&destination.ip())
+            || (self.allow_lan && is_lan(destination.ip()))
+    }
+}
+
+// The ranges the firewall treats as the local network
+fn is_lan(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => {
+            address.is_private()
+                || address.is_link_local()
+                || address.is_multicast()
+                || address == Ipv4Addr::BROADCAST
+        }
+        IpAddr::V6(address) => {
+            let first_segment = address.segments()[0];
+            // Unique local fc00::/7 and link local fe80::/10
+            (first_segment & 0xfe00) == 0xfc00
+                || (first_segment & 0xffc0) == 0xfe80
+                || address.is_multicast()
+                || address == Ipv6Addr::UNSPECIFIED
+        }
+    }
+}
+
+fn connecting_policy(entry_gateway: SocketAddr, allow_lan: bool) -> FirewallPolicy {
+    FirewallPolicy::Connecting {
+        peer_endpoint: allowed_tcp_endpoint(entry_gateway),
+        tunnel: None,
+        allow_lan,
+        allowed_endpoint: allowed_tcp_endpoint(entry_gateway),
+        allowed_tunnel_traffic: AllowedTunnelTraffic::None,
+    }
+}
+
+fn connected_policy(
+    entry_gateway: SocketAddr,
+    interface: String,
+    tun_ips: IpPair,
+    dns_servers: Vec<IpAddr>,
+    allow_lan: bool,
+) -> FirewallPolicy {
+    // The gateways on the far side of the tunnel are the tun device of the ip packet router
+    let tunnel = TunnelMetadata {
+        interface,
+        ips: vec![tun_ips.ipv4.into(), tun_ips.ipv6.into()],
+        ipv4_gateway: ICMP_IPR_TUN_IP_V4,
+        ipv6_gateway: Some(ICMP_IPR_TUN_IP_V6),
+    };
+    debug!("Kill switch: allowing traffic through tunnel: {tunnel:?}");
+    FirewallPolicy::Connected {
+        peer_endpoint: allowed_tcp_endpoint(entry_gateway),
+        tunnel,
+        allow_lan,
+        #[cfg(not(target_os = "android"))]
+        dns_servers,
+    }
+}
+
+fn blocked_policy(allow_lan: bool) -> FirewallPolicy {
+    FirewallPolicy::Blocked {
+        allow_lan,
+        allowed_endpoint: None,
+    }
+}
+
+pub(crate) async fn init_firewall() -> Result<Firewall> {
+    tracing::debug!("Starting firewall");
+    tokio::task::spawn_blocking(move || {
+        #[cfg(target_os = "linux")]
+        let firewall = Firewall::new(crate::wireguard_config::TUNNEL_FWMARK);
+        #[cfg(not(target_os = "linux"))]
+        let firewall = Firewall::new();
+        firewall.map_err(|err| Error::FirewallError(err.to_string()))
+    })
+    .await
+    .map_err(|err| Error::FirewallError(err.to_string()))?
+}
+
+/// Lift the kill switch block that is kept in place after the vpn failed. This is needed since the
+/// block outlives the vpn, until the user explicitly disconnects.
+pub async fn disable_kill_switch() -> Result<()> {
+    let mut firewall = init_firewall().await?;
+    firewall.reset_policy().map_err(|err| {
+        error!("Failed to reset firewall policy: {err}");
+        Error::FailedToResetFirewallPolicy {
+            reason: err.to_string(),
+        }
+    })
+}
+
+fn allowed_tcp_endpoint(address: SocketAddr) -> AllowedEndpoint {
+    AllowedEndpoint {
+        endpoint: Endpoint::from_socket_address(address, TransportProtocol::Tcp),
+        // We run as a privileged daemon, so restrict the exceptions to our own traffic
+        #[cfg(unix)]
+        clients: AllowedClients::Root,
+        #[cfg(windows)]
+        clients: AllowedClients::all(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    fn entry_gateway() -> SocketAddr {
+        SocketAddr::new(Ipv4Addr::new(192, 0, 2, 1).into(), 9000)
+    }
+
+    fn is_entry_gateway(allowed: &AllowedEndpoint) -> bool {
+        allowed.endpoint == Endpoint::from_socket_address(entry_gateway(), TransportProtocol::Tcp)
+    }
+
+    #[test]
+    fn connecting_only_allows_the_entry_gateway() {
+        let policy = connecting_policy(entry_gateway(), false);
+        let FirewallPolicy::Connecting {
+            peer_endpoint,
+            tunnel,
+            allow_lan,
+            allowed_endpoint,
+            ..
+        } = policy
+        else {
+            panic!("expected a connecting policy");
+        };
+        assert!(is_entry_gateway(&peer_endpoint));
+        assert!(is_entry_gateway(&allowed_endpoint));
+        assert!(tunnel.is_none());
+        assert!(!allow_lan);
+    }
+
+    #[test]
+    fn connected_allows_the_tunnel_and_its_dns_servers() {
+        let tun_ips = IpPair {
+            ipv4: Ipv4Addr::new(10, 0, 0, 2),
+            ipv6: Ipv6Addr::LOCALHOST,
+        };
+        let dns = vec![IpAddr::from(Ipv4Addr::new(10, 0, 0, 1))];
+        let policy = connected_policy(
+            entry_gateway(),
+            "nymtun0".into(),
+            tun_ips,
+            dns.clone(),
+            true,
+        );
+        let FirewallPolicy::Connected {
+            peer_endpoint,
+            tunnel,
+            allow_lan,
+            #[cfg(not(target_os = "android"))]
+            dns_servers,
+            ..
+        } = policy
+        else {
+            panic!("expected a connected policy");
+        };
+        assert!(is_entry_gateway(&peer_endpoint));
+        assert_eq!(tunnel.interface, "nymtun0");
+        assert_eq!(
+            tunnel.ips,
+            vec![IpAddr::from(tun_ips.ipv4), tun_ips.ipv6.into()]
+        );
+        assert_eq!(tunnel.ipv4_gateway, ICMP_IPR_TUN_IP_V4);
+        assert_eq!(tunnel.ipv6_gateway, Some(ICMP_IPR_TUN_IP_V6));
+        assert!(allow_lan);
+        #[cfg(not(target_os = "android"))]
+        assert_eq!(dns_servers, dns);
+    }
+
+    #[test]
+    fn allowed_traffic_follows_the_policy() {
+        let public_ipv6 = "[2606:4700:4700::1111]:53".parse().unwrap();
+        let lan_ipv6 = "[fd00::1]:53".parse().unwrap();
+
+        let connecting = AllowedTraffic::from_policy(&connecting_policy(entry_gateway(), false));
+        assert!(connecting.allows(entry_gateway(), Some("eth0")));
+        assert!(!connecting.allows(public_ipv6, Some("eth0")));
+        assert!(!connecting.allows(lan_ipv6, Some("eth0")));
+
+        let tun_ips = IpPair {
+            ipv4: Ipv4Addr::new(10, 0, 0, 2),
+            ipv6: Ipv6Addr::LOCALHOST,
+        };
+        let connected = AllowedTraffic::from_policy(&connected_policy(
+            entry_gateway(),
+            "nymtun0".into(),
+            tun_ips,
+            Vec::new(),
+            true,
+        ));
+        assert!(connected.allows(public_ipv6, Some("nymtun0")));
+        assert!(!connected.allows(public_ipv6, Some("eth0")));
+        assert!(connected.allows(lan_ipv6, Some("eth0")));
+    }
+
+    // Records the kinds of policies applied, with None for a reset
+    #[derive(Default)]
+    struct RecordingFirewall {
+        policies: Vec<Option<&'static str>>,
+    }
+
+    impl PolicyFirewall for RecordingFirewall {
+        fn apply_policy(&mut self, policy: FirewallPolicy) -> std::result::Result<(), String> {
+            self.policies.push(Some(match policy {
+                FirewallPolicy::Connecting { .. } => "connecting",
+                FirewallPolicy::Connected { .. } => "connected",
+                FirewallPolicy::Blocked { .. } => "blocked",
+            }));
+            Ok(())
+        }
+
+        fn reset_policy(&mut self) -> std::result::Result<(), String> {
+            self.policies.push(None);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn the_block_is_lifted_while_the_mixnet_client_starts() {
+        let config = KillSwitchConfig {
+            enabled: true,
+            allow_lan: false,
+        };
+        let nym_api = "[2001:db8::443]:443".parse().unwrap();
+        let mut kill_switch = KillSwitch::new(RecordingFirewall::default(), config);
+
+        // Left in place by the failure we are reconnecting after
+        kill_switch.block().unwrap();
+        assert!(!kill_switch
+            .allowed_traffic()
+            .unwrap()
+            .allows(nym_api, Some("eth0")));
+
+        // The mixnet client can reach the directory services while it starts
+        kill_switch.allow_mixnet_bootstrap().unwrap();
+        assert!(kill_switch.allowed_traffic().is_none());
+
+        // And once it is connected, only the entry gateway is reachable
+        kill_switch.allow_entry_gateway(entry_gateway()).unwrap();
+        let allowed_traffic = kill_switch.allowed_traffic().unwrap();
+        assert!(allowed_traffic.allows(entry_gateway(), Some("eth0")));
+        assert!(!allowed_traffic.allows(nym_api, Some("eth0")));
+        assert_eq!(kill_switch.entry_gateway(), Some(entry_gateway()));
+
+        assert_eq!(
+            kill_switch.firewall.policies,
+            vec![Some("blocked"), None, Some("connecting")]
+        );
+    }
+
+    #[test]
+    fn the_mixnet_client_starts_unhindered_without_the_kill_switch() {
+        let mut kill_switch =
+            KillSwitch::new(RecordingFirewall::default(), KillSwitchConfig::default());
+        kill_switch.allow_mixnet_bootstrap().unwrap();
+        kill_switch.allow_entry_gateway(entry_gateway()).unwrap();
+        assert!(kill_switch.allowed_traffic().is_none());
+        assert!(kill_switch.firewall.policies.is_empty());
+    }
+
+    #[test]
+    fn blocked_allows_nothing_but_the_lan() {
+        let FirewallPolicy::Blocked {
+            allow_lan,
+            allowed_endpoint,
+            ..
+        } = blocked_policy(true)
+        else {
+            panic!("expected a blocked policy");
+        };
+        assert!(allow_lan);
+        assert!(allowed_endpoint.is_none());
+    }
+}
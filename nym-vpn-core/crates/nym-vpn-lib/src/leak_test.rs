@@ -0,0 +1,708 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use hickory_resolver::proto::{
+    op::{Message, MessageType, OpCode, Query},
+    rr::{Name, RecordType},
+};
+use ipnetwork::IpNetwork;
+use talpid_routing::RouteManager;
+use tokio::net::UdpSocket;
+use tracing::{debug, info};
+
+use crate::{
+    kill_switch::AllowedTraffic, routing::replace_default_prefixes, tunnel_setup::AllTunnelsSetup,
+    vpn::SpecificVpn,
+};
+
+const DNS_PORT: u16 = 53;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+// The test queries are for random names under this reserved domain, so they can't be told apart
+// from anyone else's
+const TEST_QUERY_DOMAIN: &str = "example.";
+
+// The local stubs systemd-resolved sets the system up with
+#[cfg(target_os = "linux")]
+const RESOLVED_STUBS: [Ipv4Addr; 2] = [Ipv4Addr::new(127, 0, 0, 53), Ipv4Addr::new(127, 0, 0, 54)];
+
+// Public resolvers, used to probe where traffic for the internet is routed
+const PROBE_IPV4: Ipv4Addr = Ipv4Addr::new(1, 1, 1, 1);
+const PROBE_IPV6: Ipv6Addr = Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1111);
+
+// Ordered from best to worst, so that the outcome of a report is the worst of its checks
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LeakTestOutcome {
+    Pass,
+    Inconclusive,
+    Fail,
+}
+
+impl fmt::Display for LeakTestOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LeakTestOutcome::Pass => write!(f, "pass"),
+            LeakTestOutcome::Inconclusive => write!(f, "inconclusive"),
+            LeakTestOutcome::Fail => write!(f, "fail"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LeakTestReport {
+    pub tunnel_interfaces: Vec<String>,
+    pub dns: DnsLeakReport,
+    pub ipv6: Ipv6LeakReport,
+    pub routes: RouteLeakReport,
+}
+
+impl LeakTestReport {
+    pub fn outcome(&self) -> LeakTestOutcome {
+        self.dns
+            .outcome
+            .max(self.ipv6.outcome)
+            .max(self.routes.outcome)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnsLeakReport {
+    pub outcome: LeakTestOutcome,
+    pub detail: String,
+    // The resolvers we set up the system with, empty when the system DNS was left alone
+    pub expected_servers: Vec<IpAddr>,
+    pub resolvers: Vec<ResolverReport>,
+}
+
+// A resolver of the system configuration, and what happened to the test query we sent it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResolverReport {
+    pub address: IpAddr,
+    // The link systemd-resolved has the resolver set on, when it is one of its upstreams
+    pub link: Option<String>,
+    pub configured_by_vpn: bool,
+    // The interface the query left on
+    pub interface: Option<String>,
+    pub through_tunnel: bool,
+    pub answered: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ipv6Status {
+    Tunneled,
+    Blocked,
+    Leaking,
+    Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ipv6LeakReport {
+    pub outcome: LeakTestOutcome,
+    pub detail: String,
+    pub status: Ipv6Status,
+    pub interface: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RouteLeakReport {
+    pub outcome: LeakTestOutcome,
+    pub detail: String,
+    pub routes: Vec<RouteReport>,
+}
+
+// Where the traffic for one of the default prefixes is routed. There's no interface when there is
+// no route at all.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RouteReport {
+    pub prefix: IpNetwork,
+    pub interface: Option<String>,
+    pub gateway: Option<IpAddr>,
+    pub through_tunnel: bool,
+}
+
+pub(crate) struct LeakTestContext {
+    tunnel_interfaces: Vec<String>,
+    dns_servers: Vec<IpAddr>,
+    // What the kill switch lets out, not set when it isn't blocking anything
+    firewall: Option<AllowedTraffic>,
+    #[cfg(target_os = "linux")]
+    route_manager: Option<talpid_routing::RouteManagerHandle>,
+}
+
+impl LeakTestContext {
+    pub(crate) fn new(
+        vpn: &SpecificVpn,
+        tunnels: &AllTunnelsSetup,
+        firewall: Option<AllowedTraffic>,
+        #[cfg_attr(not(target_os = "linux"), allow(unused_variables))] route_manager: &RouteManager,
+    ) -> Self {
+        let (tunnel_interfaces, dns_servers) = match (vpn, tunnels) {
+            (SpecificVpn::Mix(vpn), AllTunnelsSetup::Mix(setup)) => {
+                let ips = setup.specific_setup.exit_connection_info.ips;
+                let dns_servers = match vpn.dns_stub_ip(ips) {
+                    Some(dns_stub_ip) => vec![dns_stub_ip],
+                    None => vpn.generic_config.dns.servers_or_default(),
+                };
+                let tun_interface = interface_with_address(IpAddr::V4(ips.ipv4));
+                (tun_interface.into_iter().collect(), dns_servers)
+            }
+            // The system DNS isn't touched in wireguard mode
+            _ => (tunnels.wireguard_interfaces(), Vec::new()),
+        };
+        LeakTestContext {
+            tunnel_interfaces,
+            dns_servers,
+            firewall,
+            #[cfg(target_os = "linux")]
+            route_manager: route_manager.handle().ok(),
+        }
+    }
+
+    fn is_tunnel(&self, interface: Option<&str>) -> bool {
+        interface.is_some_and(|interface| {
+            self.tunnel_interfaces
+                .iter()
+                .any(|tunnel_interface| tunnel_interface == interface)
+        })
+    }
+}
+
+pub(crate) async fn run(context: LeakTestContext) -> LeakTestReport {
+    info!("Running leak test");
+    let (dns, ipv6, routes) = tokio::join!(
+        check_dns(&context),
+        check_ipv6(&context),
+        check_routes(&context)
+    );
+    let report = LeakTestReport {
+        tunnel_interfaces: context.tunnel_interfaces,
+        dns,
+        ipv6,
+        routes,
+    };
+    info!("Leak test finished: {}", report.outcome());
+    debug!("Leak test report: {report:#?}");
+    report
+}
+
+async fn check_dns(context: &LeakTestContext) -> DnsLeakReport {
+    let report = |outcome, detail: String, resolvers| DnsLeakReport {
+        outcome,
+        detail,
+        expected_servers: context.dns_servers.clone(),
+        resolvers,
+    };
+    let system_resolvers = match system_resolvers().await {
+        Ok(resolvers) if resolvers.is_empty() => {
+            return report(
+                LeakTestOutcome::Inconclusive,
+                "no system resolvers are configured".to_string(),
+                Vec::new(),
+            );
+        }
+        Ok(resolvers) => resolvers,
+        Err(err) => {
+            return report(
+                LeakTestOutcome::Inconclusive,
+                format!("failed to read the system resolver configuration: {err}"),
+                Vec::new(),
+            );
+        }
+    };
+
+    let resolvers = futures::future::join_all(system_resolvers.into_iter().map(
+        |(address, link)| async move {
+            let (interface, answered) = match Egress::to(address).await {
+                Ok(egress) => (egress.interface.clone(), egress.query().await),
+                Err(err) => {
+                    debug!("No route to resolver {address}: {err}");
+                    (None, false)
+                }
+            };
+            ResolverReport {
+                address,
+                link,
+                configured_by_vpn: context.dns_servers.contains(&address),
+                through_tunnel: context.is_tunnel(interface.as_deref()),
+                interface,
+                answered,
+            }
+        },
+    ))
+    .await;
+
+    // A local resolver forwards the queries on its own, so we can't tell where they end up
+    let leaking = resolvers
+        .iter()
+        .filter(|resolver| {
+            !resolver.through_tunnel
+                && !resolver.address.is_loopback()
+                && resolver.interface.is_some()
+        })
+        .map(|resolver| resolver.address.to_string())
+        .collect::<Vec<_>>();
+    let local = resolvers
+        .iter()
+        .filter(|resolver| resolver.address.is_loopback() && !resolver.configured_by_vpn)
+        .map(|resolver| resolver.address.to_string())
+        .collect::<Vec<_>>();
+    let (outcome, detail) = if !leaking.is_empty() {
+        (
+            LeakTestOutcome::Fail,
+            format!("queries to {} leave outside the tunnel", leaking.join(", ")),
+        )
+    } else if !local.is_empty() {
+        (
+            LeakTestOutcome::Inconclusive,
+            format!(
+                "the local resolvers {} forward queries on their own",
+                local.join(", ")
+            ),
+        )
+    } else {
+        (
+            LeakTestOutcome::Pass,
+            "all resolvers are reached through the tunnel".to_string(),
+        )
+    };
+    report(outcome, detail, resolvers)
+}
+
+async fn check_ipv6(context: &LeakTestContext) -> Ipv6LeakReport {
+    let report = |outcome, detail: &str, status, interface| Ipv6LeakReport {
+        outcome,
+        detail: detail.to_string(),
+        status,
+        interface,
+    };
+    let egress = match Egress::to(IpAddr::V6(PROBE_IPV6)).await {
+        Ok(egress) => egress,
+        Err(err) => {
+            debug!("No IPv6 route: {err}");
+            return report(
+                LeakTestOutcome::Pass,
+                "there is no IPv6 route",
+                Ipv6Status::Blocked,
+                None,
+            );
+        }
+    };
+    if context.is_tunnel(egress.interface.as_deref()) {
+        return report(
+            LeakTestOutcome::Pass,
+            "IPv6 is routed through the tunnel",
+            Ipv6Status::Tunneled,
+            egress.interface,
+        );
+    }
+
+    // Routed outside the tunnel, see if anything actually gets through
+    let destination = SocketAddr::new(IpAddr::V6(PROBE_IPV6), DNS_PORT);
+    let dropped_by_firewall = context
+        .firewall
+        .as_ref()
+        .is_some_and(|firewall| !firewall.allows(destination, egress.interface.as_deref()));
+    if egress.query().await {
+        report(
+            LeakTestOutcome::Fail,
+            "IPv6 traffic leaves outside the tunnel",
+            Ipv6Status::Leaking,
+            egress.interface,
+        )
+    } else if dropped_by_firewall {
+        report(
+            LeakTestOutcome::Pass,
+            "IPv6 is routed outside the tunnel, but the kill switch firewall policy drops it",
+            Ipv6Status::Blocked,
+            egress.interface,
+        )
+    } else {
+        report(
+            LeakTestOutcome::Inconclusive,
+            "IPv6 is routed outside the tunnel, but got no answer",
+            Ipv6Status::Unknown,
+            egress.interface,
+        )
+    }
+}
+
+async fn check_routes(context: &LeakTestContext) -> RouteLeakReport {
+    let prefixes = [
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    ]
+    .into_iter()
+    .map(|ip| IpNetwork::new(ip, 0).expect("zero is a valid prefix"))
+    .flat_map(replace_default_prefixes);
+
+    let mut routes = Vec::new();
+    let mut failed = Vec::new();
+    for prefix in prefixes {
+        match lookup_route(context, probe_address(prefix)).await {
+            Ok(route) => {
+                let (interface, gateway) = route.unwrap_or_default();
+                routes.push(RouteReport {
+                    prefix,
+                    through_tunnel: context.is_tunnel(interface.as_deref()),
+                    interface,
+                    gateway,
+                });
+            }
+            Err(err) => failed.push(format!("{prefix}: {err}")),
+        }
+    }
+
+    // Having no route at all is fine, nothing can leak that way
+    let leaking = routes
+        .iter()
+        .filter(|route| route.interface.is_some() && !route.through_tunnel)
+        .map(|route| route.prefix.to_string())
+        .collect::<Vec<_>>();
+    let (outcome, detail) = if !leaking.is_empty() {
+        (
+            LeakTestOutcome::Fail,
+            format!("{} routed outside the tunnel", leaking.join(", ")),
+        )
+    } else if !failed.is_empty() {
+        (
+            LeakTestOutcome::Inconclusive,
+            format!("failed to look up routes: {}", failed.join(", ")),
+        )
+    } else {
+        (
+            LeakTestOutcome::Pass,
+            "the default prefixes are routed through the tunnel".to_string(),
+        )
+    };
+    RouteLeakReport {
+        outcome,
+        detail,
+        routes,
+    }
+}
+
+// The interface and gateway of the route to the destination
+#[cfg(target_os = "linux")]
+async fn lookup_route(
+    context: &LeakTestContext,
+    destination: IpAddr,
+) -> Result<Option<(Option<String>, Option<IpAddr>)>, String> {
+    let Some(ref route_manager) = context.route_manager else {
+        return Err("the route manager is not available".to_string());
+    };
+    let route = route_manager
+        .get_destination_route(destination, None)
+        .await
+        .map_err(|err| err.to_string())?;
+    Ok(route.map(|route| {
+        let node = route.get_node();
+        (node.get_device().map(str::to_string), node.get_address())
+    }))
+}
+
+// The route manager can't be asked for routes here, instead we see which interface the system
+// picks for the destination
+#[cfg(not(target_os = "linux"))]
+async fn lookup_route(
+    _context: &LeakTestContext,
+    destination: IpAddr,
+) -> Result<Option<(Option<String>, Option<IpAddr>)>, String> {
+    match Egress::to(destination).await {
+        Ok(egress) => Ok(Some((egress.interface, None))),
+        Err(err) if err.kind() == std::io::ErrorKind::NetworkUnreachable => Ok(None),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+// An address inside the prefix, preferring a public resolver over the unspecified address
+fn probe_address(prefix: IpNetwork) -> IpAddr {
+    [IpAddr::V4(PROBE_IPV4), IpAddr::V6(PROBE_IPV6)]
+        .into_iter()
+        .find(|probe| prefix.contains(*probe))
+        .unwrap_or(prefix.network())
+}
+
+// The system resolvers, along with the systemd-resolved link they are set on
+async fn system_resolvers() -> Result<Vec<(IpAddr, Option<String>)>, String> {
+    let (config, _) =
+        hickory_resolver::system_conf::read_system_conf().map_err(|err| err.to_string())?;
+    let mut resolvers = Vec::new();
+    // Each resolver is listed once for UDP and once for TCP
+    for name_server in config.name_servers() {
+        let address = name_server.socket_addr.ip();
+        if !resolvers.iter().any(|(resolver, _)| *resolver == address) {
+            resolvers.push((address, None));
+        }
+    }
+
+    // The local stub of systemd-resolved forwards to the servers set on each link, which are the
+    // ones to check
+    #[cfg(target_os = "linux")]
+    if resolvers
+        .iter()
+        .any(|(address, _)| RESOLVED_STUBS.iter().any(|stub| *address == *stub))
+    {
+        match resolved_upstreams().await {
+            Ok(upstreams) => {
+                resolvers
+                    .retain(|(address, _)| !RESOLVED_STUBS.iter().any(|stub| *address == *stub));
+                for (address, link) in upstreams {
+                    if !resolvers.iter().any(|(resolver, _)| *resolver == address) {
+                        resolvers.push((address, link));
+                    }
+                }
+            }
+            Err(err) => debug!("Failed to read the systemd-resolved upstreams: {err}"),
+        }
+    }
+    Ok(resolvers)
+}
+
+// The servers systemd-resolved sends general queries to: the global ones, and those of the links
+// that are a default route for DNS. Servers on other links only get queries for their own domains.
+#[cfg(target_os = "linux")]
+async fn resolved_upstreams() -> Result<Vec<(IpAddr, Option<String>)>, String> {
+    let dns = resolvectl("dns").await?;
+    let default_route = resolvectl("default-route").await?;
+    let default_route_links = parse_resolvectl(&default_route)
+        .into_iter()
+        .filter(|(_, value)| value.trim() == "yes")
+        .filter_map(|(link, _)| link)
+        .collect::<Vec<_>>();
+
+    let mut upstreams = Vec::new();
+    for (link, servers) in parse_resolvectl(&dns) {
+        if link
+            .as_ref()
+            .is_some_and(|link| !default_route_links.contains(link))
+        {
+            continue;
+        }
+        upstreams.extend(
+            servers
+                .split_whitespace()
+                .filter_map(parse_resolved_server)
+                .map(|address| (address, link.clone())),
+        );
+    }
+    Ok(upstreams)
+}
+
+#[cfg(target_os = "linux")]
+async fn resolvectl(command: &str) -> Result<String, String> {
+    let output = tokio::process::Command::new("resolvectl")
+        .arg(command)
+        .output()
+        .await
+        .map_err(|err| err.to_string())?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    String::from_utf8(output.stdout).map_err(|err| err.to_string())
+}
+
+// Splits the per link output of resolvectl, lines like `Link 2 (eth0): 192.0.2.1`, into the link
+// name and the value. The global settings have no link.
+#[cfg(target_os = "linux")]
+fn parse_resolvectl(output: &str) -> Vec<(Option<String>, String)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (scope, value) = line.split_once(':')?;
+            let link = match scope.trim() {
+                "Global" => None,
+                scope => Some(
+                    scope
+                        .strip_prefix("Link ")?
+                        .split_once('(')?
+                        .1
+                        .strip_suffix(')')?
+                        .to_string(),
+                ),
+            };
+            Some((link, value.to_string()))
+        })
+        .collect()
+}
+
+// Servers can come with a port, an interface scope and a TLS server name: `192.0.2.1:853#name`
+#[cfg(target_os = "linux")]
+fn parse_resolved_server(server: &str) -> Option<IpAddr> {
+    let server = server.split('#').next()?;
+    server
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| {
+            server
+                .parse::<SocketAddr>()
+                .ok()
+                .map(|address| address.ip())
+        })
+        .or_else(|| server.split('%').next()?.parse().ok())
+}
+
+fn interface_with_address(address: IpAddr) -> Option<String> {
+    netdev::get_interfaces()
+        .into_iter()
+        .find(|interface| match address {
+            IpAddr::V4(address) => interface.ipv4.iter().any(|net| net.addr() == address),
+            IpAddr::V6(address) => interface.ipv6.iter().any(|net| net.addr() == address),
+        })
+        .map(|interface| interface.name)
+}
+
+// A UDP socket connected to the DNS port of the destination. Connecting doesn't send anything, but
+// has the system pick the route, and with it the source address of the interface the traffic
+// leaves on.
+struct Egress {
+    socket: UdpSocket,
+    interface: Option<String>,
+}
+
+impl Egress {
+    async fn to(destination: IpAddr) -> std::io::Result<Self> {
+        let bind_address = match destination {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        let socket = UdpSocket::bind(SocketAddr::new(bind_address, 0)).await?;
+        socket
+            .connect(SocketAddr::new(destination, DNS_PORT))
+            .await?;
+        let interface = interface_with_address(socket.local_addr()?.ip());
+        Ok(Egress { socket, interface })
+    }
+
+    // Whether the destination answered a test query
+    async fn query(&self) -> bool {
+        let id = rand::random();
+        let mut query = Message::new();
+        query
+            .set_id(id)
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(true)
+            .add_query(Query::query(test_query_name(), RecordType::A));
+        let Ok(query) = query.to_vec() else {
+            return false;
+        };
+        if self.socket.send(&query).await.is_err() {
+            return false;
+        }
+
+        let mut buf = [0u8; 512];
+        let response = tokio::time::timeout(QUERY_TIMEOUT, async {
+            loop {
+                let len = self.socket.recv(&mut buf).await?;
+                if Message::from_vec(&buf[..len]).is_ok_and(|response| response.id() == id) {
+                    return Ok::<_, std::io::Error>(());
+                }
+            }
+        })
+        .await;
+        matches!(response, Ok(Ok(())))
+    }
+}
+
+fn test_query_name() -> Name {
+    let label = format!("{:016x}", rand::random::<u64>());
+    Name::from_ascii(format!("{label}.{TEST_QUERY_DOMAIN}")).expect("the test name is valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probes_public_resolvers_in_the_default_prefixes() {
+        assert_eq!(
+            probe_address("0.0.0.0/0".parse().unwrap()),
+            IpAddr::V4(PROBE_IPV4)
+        );
+        assert_eq!(
+            probe_address("0.0.0.0/1".parse().unwrap()),
+            IpAddr::V4(PROBE_IPV4)
+        );
+        assert_eq!(
+            probe_address("128.0.0.0/1".parse().unwrap()),
+            "128.0.0.0".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            probe_address("::/1".parse().unwrap()),
+            IpAddr::V6(PROBE_IPV6)
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn reads_the_resolved_servers_per_link() {
+        let output = "Global: 192.0.2.1#dns.example\n\
+            Link 2 (eth0): 192.168.1.1 fe80::1%2 [2001:db8::1]:853\n\
+            Link 5 (nymtun0):\n";
+        let links = parse_resolvectl(output);
+        assert_eq!(links.len(), 3);
+        assert_eq!(links[0].0, None);
+        assert_eq!(links[1].0.as_deref(), Some("eth0"));
+        assert_eq!(links[2].0.as_deref(), Some("nymtun0"));
+
+        let servers = |value: &str| {
+            value
+                .split_whitespace()
+                .filter_map(parse_resolved_server)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            servers(&links[0].1),
+            vec!["192.0.2.1".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(
+            servers(&links[1].1),
+            ["192.168.1.1", "fe80::1", "2001:db8::1"]
+                .map(|server| server.parse::<IpAddr>().unwrap())
+        );
+        assert!(servers(&links[2].1).is_empty());
+    }
+
+    #[test]
+    fn test_queries_are_for_random_names() {
+        let name = test_query_name();
+        assert!(name.is_fqdn());
+        assert_eq!(
+            name.base_name(),
+            Name::from_ascii(TEST_QUERY_DOMAIN).unwrap()
+        );
+        assert_ne!(name, test_query_name());
+    }
+
+    #[test]
+    fn outcome_is_the_worst_check() {
+        let mut report = LeakTestReport {
+            tunnel_interfaces: vec!["nymtun0".to_string()],
+            dns: DnsLeakReport {
+                outcome: LeakTestOutcome::Pass,
+                detail: String::new(),
+                expected_servers: Vec::new(),
+                resolvers: Vec::new(),
+            },
+            ipv6: Ipv6LeakReport {
+                outcome: LeakTestOutcome::Inconclusive,
+                detail: String::new(),
+                status: Ipv6Status::Unknown,
+                interface: None,
+            },
+            routes: RouteLeakReport {
+                outcome: LeakTestOutcome::Pass,
+                detail: String::new(),
+                routes: Vec::new(),
+            },
+        };
+        assert_eq!(report.outcome(), LeakTestOutcome::Inconclusive);
+        report.routes.outcome = LeakTestOutcome::Fail;
+        assert_eq!(report.outcome(), LeakTestOutcome::Fail);
+    }
+}
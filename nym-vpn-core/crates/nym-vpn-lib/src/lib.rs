@@ -8,16 +8,27 @@ pub mod storage;
 pub mod util;
 
 mod bandwidth_controller;
+mod dns;
+mod dns_filter;
+mod encrypted_dns;
 mod error;
+mod exit_failover;
+mod kill_switch;
+mod leak_test;
 mod mixnet;
-#[cfg(any(target_os = "ios", target_os = "android"))]
+#[cfg(any(target_os = "ios", target_os = "android", target_os = "linux"))]
 mod mobile;
 mod platform;
+mod pmtu;
+mod proxy;
 mod routing;
+mod split_tunnel;
+mod traffic_stats;
 mod tunnel;
 mod tunnel_setup;
 mod uniffi_custom_impls;
 mod vpn;
+mod wg_health;
 mod wireguard_config;
 mod wireguard_setup;
 
@@ -43,12 +54,35 @@ pub use nym_wg_gateway_client as wg_gateway_client;
 #[cfg(any(target_os = "ios", target_os = "macos"))]
 pub use crate::platform::swift;
 pub use crate::{
-    error::{Error, GatewayDirectoryError, SetupMixTunnelError, SetupWgTunnelError},
-    mixnet::MixnetError,
+    dns::{DnsConfig, SplitDnsRule},
+    dns_filter::{
+        BlockResponse, Blocklist, BlocklistFormat, BlocklistSource, BlocklistStats,
+        BundledBlocklist, DnsFilterConfig, DnsFilterStats, DnsFilterStatsHandle,
+        LoadBlocklistError,
+    },
+    encrypted_dns::{EncryptedDnsConfig, EncryptedDnsProtocol, EncryptedDnsUpstream},
+    error::{
+        Error, GatewayDirectoryError, SetupMixTunnelError, SetupWgTunnelError, SwitchGatewayError,
+    },
+    exit_failover::{ExitFailoverConfig, ExitFailoverScope},
+    kill_switch::{disable_kill_switch, KillSwitchConfig},
+    leak_test::{
+        DnsLeakReport, Ipv6LeakReport, Ipv6Status, LeakTestOutcome, LeakTestReport, ResolverReport,
+        RouteLeakReport, RouteReport,
+    },
+    mixnet::{
+        MixnetError, TrafficProfile, BALANCED_PROFILE, BANDWIDTH_SAVER_PROFILE,
+        LOW_LATENCY_PROFILE, MAX_ANONYMITY_PROFILE,
+    },
+    proxy::{run_proxy, ProxyConfig, ProxyError},
+    split_tunnel::SplitTunnelConfig,
+    traffic_stats::{
+        ThroughputSampler, TrafficCounters, TrafficStats, TrafficStatsHandle, WgHopStats,
+    },
     vpn::{
         spawn_nym_vpn, spawn_nym_vpn_with_new_runtime, GenericNymVpnConfig, MixnetClientConfig,
         NymVpn, NymVpnCtrlMessage, NymVpnExitStatusMessage, NymVpnHandle, NymVpnStatusMessage,
-        SpecificVpn,
+        SpecificVpn, DEFAULT_WG_HOP_COUNT, MAX_WG_HOP_COUNT,
     },
 };
 
@@ -31,28 +31,40 @@ fn apply_mixnet_client_config(
     debug_config: &mut nym_client_core::config::DebugConfig,
 ) {
     let MixnetClientConfig {
+        traffic_profile,
         enable_poisson_rate,
         disable_background_cover_traffic,
         enable_credentials_mode: _enable_credentials_mode,
         min_mixnode_performance,
         min_gateway_performance,
     } = mixnet_client_config;
+    info!("mixnet client traffic profile: {}", traffic_profile.name);
 
-    // Disable Poisson rate limiter by default
+    // The flags take precedence over the profile
+    let poisson_rate = traffic_profile.poisson_rate || *enable_poisson_rate;
     info!(
         "mixnet client poisson rate limiting: {}",
-        true_to_enabled(*enable_poisson_rate)
+        true_to_enabled(poisson_rate)
     );
     debug_config
         .traffic
-        .disable_main_poisson_packet_distribution = !enable_poisson_rate;
+        .disable_main_poisson_packet_distribution = !poisson_rate;
+    debug_config.traffic.message_sending_average_delay = traffic_profile.average_send_delay();
+    debug_config.traffic.average_packet_delay = traffic_profile.average_packet_delay();
+    info!(
+        "mixnet client average packet delay: {:?}",
+        debug_config.traffic.average_packet_delay
+    );
 
+    let disable_loop_cover_traffic =
+        !traffic_profile.loop_cover_traffic || *disable_background_cover_traffic;
     info!(
         "mixnet client background loop cover traffic stream: {}",
-        true_to_disabled(*disable_background_cover_traffic)
+        true_to_disabled(disable_loop_cover_traffic)
     );
-    debug_config.cover_traffic.disable_loop_cover_traffic_stream =
-        *disable_background_cover_traffic;
+    debug_config.cover_traffic.disable_loop_cover_traffic_stream = disable_loop_cover_traffic;
+    debug_config.cover_traffic.loop_cover_traffic_average_delay =
+        traffic_profile.loop_cover_traffic_delay();
 
     if let Some(min_mixnode_performance) = min_mixnode_performance {
         debug_config.topology.minimum_mixnode_performance = *min_mixnode_performance;
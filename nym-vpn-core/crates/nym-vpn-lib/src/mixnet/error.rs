@@ -38,4 +38,7 @@ pub enum MixnetError {
 
     #[error("{0}")]
     ConnectionMonitorError(#[from] nym_connection_monitor::Error),
+
+    #[error("mixnet processor task failed: {0}")]
+    ProcessorTaskFailed(#[from] tokio::task::JoinError),
 }
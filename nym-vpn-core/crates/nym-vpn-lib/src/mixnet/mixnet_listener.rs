@@ -8,11 +8,12 @@ use nym_ip_packet_client::{IprListener, MixnetMessageOutcome};
 use nym_ip_packet_requests::IpPair;
 use nym_task::TaskClient;
 use tokio::task::JoinHandle;
-use tokio_util::codec::Framed;
+use tokio_util::{codec::Framed, sync::CancellationToken};
 use tracing::{debug, error, trace};
 use tun2::{AsyncDevice, TunPacketCodec};
 
 use super::SharedMixnetClient;
+use crate::traffic_stats::TrafficStatsHandle;
 
 // The mixnet listener is responsible for listening for incoming mixnet messages from the mixnet
 // client, and if they contain IP packets, forward them to the tun device.
@@ -26,6 +27,9 @@ pub(super) struct MixnetListener {
     // Task client for receiving shutdown signals
     task_client: TaskClient,
 
+    // For stopping the listener without shutting down the rest of the tunnel
+    stop_token: CancellationToken,
+
     // Sink for sending packets to the tun device
     tun_device_sink: SplitSink<Framed<AsyncDevice, TunPacketCodec>, Vec<u8>>,
 
@@ -37,16 +41,21 @@ pub(super) struct MixnetListener {
 
     // Connection event sender
     connection_event_tx: mpsc::UnboundedSender<ConnectionStatusEvent>,
+
+    // Counts the packets we write to the tun device
+    traffic_stats: TrafficStatsHandle,
 }
 
 impl MixnetListener {
     pub(super) async fn new(
         mixnet_client: SharedMixnetClient,
         task_client: TaskClient,
+        stop_token: CancellationToken,
         tun_device_sink: SplitSink<Framed<AsyncDevice, TunPacketCodec>, Vec<u8>>,
         icmp_beacon_identifier: u16,
         our_ips: IpPair,
         connection_event_tx: mpsc::UnboundedSender<ConnectionStatusEvent>,
+        traffic_stats: TrafficStatsHandle,
     ) -> Self {
         let our_address = mixnet_client.nym_address().await;
         let ipr_client = IprListener::new(our_address);
@@ -55,10 +64,12 @@ impl MixnetListener {
             mixnet_client,
             ipr_listener: ipr_client,
             task_client,
+            stop_token,
             tun_device_sink,
             icmp_beacon_identifier,
             our_ips,
             connection_event_tx,
+            traffic_stats,
         }
     }
 
@@ -88,12 +99,18 @@ impl MixnetListener {
                     trace!("Mixnet listener: Received shutdown");
                     break;
                 }
+                _ = self.stop_token.cancelled() => {
+                    trace!("Mixnet listener: Stopped");
+                    self.task_client.disarm();
+                    break;
+                }
                 Some(reconstructed_message) = mixnet_client.next() => {
                     // We're just going to assume that all incoming messags are IPR messages
                     match self.ipr_listener.handle_reconstructed_message(reconstructed_message).await {
                         Ok(Some(MixnetMessageOutcome::IpPackets(packets))) => {
                             for packet in packets {
                                 self.check_for_icmp_beacon_reply(&packet);
+                                self.traffic_stats.record_in(packet.len());
 
                                 // Consider not including packets that are ICMP ping replies to our beacon
                                 // in the responses. We are defensive here just in case we incorrectly
@@ -103,8 +120,14 @@ impl MixnetListener {
                                 }
                             }
                         }
-                        Ok(Some(MixnetMessageOutcome::MixnetSelfPing)) => {
-                            self.send_connection_event(ConnectionStatusEvent::MixnetSelfPing);
+                        Ok(Some(MixnetMessageOutcome::MixnetSelfPing(request_id))) => {
+                            self.send_connection_event(
+                                ConnectionStatusEvent::MixnetSelfPing { request_id },
+                            );
+                        }
+                        Ok(Some(MixnetMessageOutcome::Disconnect)) => {
+                            error!("Mixnet listener: the exit gateway disconnected us");
+                            break;
                         }
                         Ok(None) => {}
                         Err(err) => {
@@ -130,20 +153,20 @@ impl MixnetListener {
     }
 }
 
-fn check_for_icmp_beacon_reply(
+pub(crate) fn check_for_icmp_beacon_reply(
     packet: &Bytes,
     icmp_beacon_identifier: u16,
     our_ips: IpPair,
 ) -> Option<ConnectionStatusEvent> {
     match nym_connection_monitor::is_icmp_beacon_reply(packet, icmp_beacon_identifier, our_ips.ipv4)
     {
-        Some(IcmpBeaconReply::TunDeviceReply) => {
+        Some(IcmpBeaconReply::TunDeviceReply { sequence_number }) => {
             debug!("Received ping response from ipr tun device");
-            return Some(ConnectionStatusEvent::Icmpv4IprTunDevicePingReply);
+            return Some(ConnectionStatusEvent::Icmpv4IprTunDevicePingReply { sequence_number });
         }
-        Some(IcmpBeaconReply::ExternalPingReply(_source)) => {
+        Some(IcmpBeaconReply::ExternalPingReply(source)) => {
             debug!("Received ping response from an external ip through the ipr");
-            return Some(ConnectionStatusEvent::Icmpv4IprExternalPingReply);
+            return Some(ConnectionStatusEvent::Icmpv4IprExternalPingReply(source));
         }
         None => {}
     }
@@ -153,13 +176,13 @@ fn check_for_icmp_beacon_reply(
         icmp_beacon_identifier,
         our_ips.ipv6,
     ) {
-        Some(Icmpv6BeaconReply::TunDeviceReply) => {
+        Some(Icmpv6BeaconReply::TunDeviceReply { sequence_number }) => {
             debug!("Received ping v6 response from ipr tun device");
-            return Some(ConnectionStatusEvent::Icmpv6IprTunDevicePingReply);
+            return Some(ConnectionStatusEvent::Icmpv6IprTunDevicePingReply { sequence_number });
         }
-        Some(Icmpv6BeaconReply::ExternalPingReply(_source)) => {
+        Some(Icmpv6BeaconReply::ExternalPingReply(source)) => {
             debug!("Received ping v6 response from an external ip through the ipr");
-            return Some(ConnectionStatusEvent::Icmpv6IprExternalPingReply);
+            return Some(ConnectionStatusEvent::Icmpv6IprExternalPingReply(source));
         }
         None => {}
     }
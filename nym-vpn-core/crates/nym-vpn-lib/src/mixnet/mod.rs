@@ -6,9 +6,15 @@ mod error;
 mod mixnet_listener;
 mod processor;
 mod shared_mixnet_client;
+mod traffic_profile;
 
 pub(crate) use connect::setup_mixnet_client;
-pub(crate) use processor::{start_processor, Config};
+pub(crate) use mixnet_listener::check_for_icmp_beacon_reply;
+pub(crate) use processor::{start_processor, Config, MessageCreator, MixnetProcessorHandle};
 pub(crate) use shared_mixnet_client::SharedMixnetClient;
 
 pub use error::MixnetError;
+pub use traffic_profile::{
+    TrafficProfile, BALANCED_PROFILE, BANDWIDTH_SAVER_PROFILE, LOW_LATENCY_PROFILE,
+    MAX_ANONYMITY_PROFILE,
+};
@@ -1,43 +1,53 @@
 // Copyright 2023 - Nym Technologies SA <contact@nymtech.net>
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::result::Result;
+use std::{result::Result, time::Duration};
 
 use bytes::Bytes;
 use futures::{channel::mpsc, StreamExt};
 use nym_connection_monitor::{ConnectionMonitorTask, ConnectionStatusEvent};
-use nym_ip_packet_requests::{codec::MultiIpPacketCodec, request::IpPacketRequest};
+use nym_ip_packet_requests::{codec::MultiIpPacketCodec, request::IpPacketRequest, IpPair};
 use nym_sdk::mixnet::{InputMessage, MixnetMessageSender, Recipient};
 use nym_task::{connections::TransmissionLane, TaskClient, TaskManager};
 use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, trace};
 use tun2::{AbstractDevice, AsyncDevice};
 
 use super::{MixnetError, SharedMixnetClient};
+use crate::traffic_stats::TrafficStatsHandle;
 
 #[derive(Debug)]
 pub(crate) struct Config {
     pub(crate) ip_packet_router_address: Recipient,
+    pub(crate) packet_buffer_timeout: Duration,
 }
 
 impl Config {
-    pub(crate) fn new(ip_packet_router_address: Recipient) -> Self {
+    pub(crate) fn new(
+        ip_packet_router_address: Recipient,
+        packet_buffer_timeout: Duration,
+    ) -> Self {
         Config {
             ip_packet_router_address,
+            packet_buffer_timeout,
         }
     }
 }
 
-struct MessageCreator {
+pub(crate) struct MessageCreator {
     recipient: Recipient,
 }
 
 impl MessageCreator {
-    fn new(recipient: Recipient) -> Self {
+    pub(crate) fn new(recipient: Recipient) -> Self {
         Self { recipient }
     }
 
-    fn create_input_message(&self, bundled_packets: Bytes) -> Result<InputMessage, MixnetError> {
+    pub(crate) fn create_input_message(
+        &self,
+        bundled_packets: Bytes,
+    ) -> Result<InputMessage, MixnetError> {
         let packet = IpPacketRequest::new_data_request(bundled_packets).to_bytes()?;
 
         let lane = TransmissionLane::General;
@@ -52,32 +62,18 @@ struct MixnetProcessor {
     mixnet_client: SharedMixnetClient,
     connection_event_tx: mpsc::UnboundedSender<ConnectionStatusEvent>,
     ip_packet_router_address: Recipient,
-    our_ips: nym_ip_packet_requests::IpPair,
+    packet_buffer_timeout: Duration,
+    our_ips: IpPair,
     icmp_beacon_identifier: u16,
+    traffic_stats: TrafficStatsHandle,
 }
 
 impl MixnetProcessor {
-    fn new(
-        device: AsyncDevice,
-        mixnet_client: SharedMixnetClient,
-        connection_monitor: &ConnectionMonitorTask,
-        ip_packet_router_address: Recipient,
-        our_ips: nym_ip_packet_requests::IpPair,
-    ) -> Self {
-        MixnetProcessor {
-            device,
-            mixnet_client,
-            connection_event_tx: connection_monitor.event_sender(),
-            ip_packet_router_address,
-            our_ips,
-            icmp_beacon_identifier: connection_monitor.icmp_beacon_identifier(),
-        }
-    }
-
     async fn run(
         self,
         mut task_client_mix_processor: TaskClient,
         task_client_mix_listener: TaskClient,
+        stop_token: CancellationToken,
     ) -> Result<AsyncDevice, MixnetError> {
         info!(
             "Opened mixnet processor on tun device {}",
@@ -91,8 +87,7 @@ impl MixnetProcessor {
         let sender = self.mixnet_client.split_sender().await;
         let recipient = self.ip_packet_router_address;
 
-        let mut multi_ip_packet_encoder =
-            MultiIpPacketCodec::new(nym_ip_packet_requests::codec::BUFFER_TIMEOUT);
+        let mut multi_ip_packet_encoder = MultiIpPacketCodec::new(self.packet_buffer_timeout);
 
         let message_creator = MessageCreator::new(recipient);
 
@@ -104,10 +99,12 @@ impl MixnetProcessor {
         let mixnet_listener = super::mixnet_listener::MixnetListener::new(
             self.mixnet_client,
             task_client_mix_listener,
+            stop_token.clone(),
             tun_device_sink,
             self.icmp_beacon_identifier,
             self.our_ips,
             self.connection_event_tx.clone(),
+            self.traffic_stats.clone(),
         )
         .await;
         let mixnet_listener_handle = mixnet_listener.start();
@@ -119,6 +116,12 @@ impl MixnetProcessor {
                     trace!("MixnetProcessor: Received shutdown");
                     break;
                 }
+                _ = stop_token.cancelled() => {
+                    trace!("MixnetProcessor: Stopped");
+                    // The rest of the tunnel is still up, so this is not an unexpected halt
+                    task_client_mix_processor.disarm();
+                    break;
+                }
                 // To make sure we don't wait too long before filling up the buffer, which destroys
                 // latency, cap the time waiting for the buffer to fill
                 Some(bundled_packets) = multi_ip_packet_encoder.buffer_timeout() => {
@@ -137,6 +140,7 @@ impl MixnetProcessor {
                     };
                 }
                 Some(Ok(packet)) = tun_device_stream.next() => {
+                    self.traffic_stats.record_out(packet.len());
                     // Bundle up IP packets into a single mixnet message
                     if let Some(input_message) = multi_ip_packet_encoder
                         .append_packet(packet.into())
@@ -172,37 +176,109 @@ impl MixnetProcessor {
     }
 }
 
+// Handle to a running mixnet processor, that can be used to stop it while keeping the rest of the
+// tunnel up, for example to switch to a different exit.
+pub(crate) struct MixnetProcessorHandle {
+    handle: JoinHandle<Result<AsyncDevice, MixnetError>>,
+    stop_token: CancellationToken,
+    mixnet_client: SharedMixnetClient,
+    our_ips: IpPair,
+    connection_event_tx: mpsc::UnboundedSender<ConnectionStatusEvent>,
+    icmp_beacon_identifier: u16,
+    traffic_stats: TrafficStatsHandle,
+}
+
+impl MixnetProcessorHandle {
+    pub(crate) async fn stop(self) -> Result<StoppedMixnetProcessor, MixnetError> {
+        debug!("Stopping mixnet processor");
+        self.stop_token.cancel();
+        let device = self.handle.await??;
+        Ok(StoppedMixnetProcessor {
+            device,
+            mixnet_client: self.mixnet_client,
+            our_ips: self.our_ips,
+            connection_event_tx: self.connection_event_tx,
+            icmp_beacon_identifier: self.icmp_beacon_identifier,
+            traffic_stats: self.traffic_stats,
+        })
+    }
+}
+
+// Everything needed to start the mixnet processor again on the same tun device
+pub(crate) struct StoppedMixnetProcessor {
+    device: AsyncDevice,
+    mixnet_client: SharedMixnetClient,
+    our_ips: IpPair,
+    connection_event_tx: mpsc::UnboundedSender<ConnectionStatusEvent>,
+    icmp_beacon_identifier: u16,
+    traffic_stats: TrafficStatsHandle,
+}
+
+impl StoppedMixnetProcessor {
+    pub(crate) fn start(self, config: Config, task_manager: &TaskManager) -> MixnetProcessorHandle {
+        info!("Creating mixnet processor");
+        let processor = MixnetProcessor {
+            device: self.device,
+            mixnet_client: self.mixnet_client.clone(),
+            connection_event_tx: self.connection_event_tx.clone(),
+            ip_packet_router_address: config.ip_packet_router_address,
+            packet_buffer_timeout: config.packet_buffer_timeout,
+            our_ips: self.our_ips,
+            icmp_beacon_identifier: self.icmp_beacon_identifier,
+            traffic_stats: self.traffic_stats.clone(),
+        };
+
+        // This is an unfortunate limitation of the TaskManager/TaskClient. Would be better if we could
+        // have child clients like with tokio::CancellationToken, that can be crated from the parent
+        let task_client_mix_processor = task_manager.subscribe_named("mixnet_processor");
+        let task_client_mix_listener = task_manager.subscribe_named("mixnet_listener");
+        let stop_token = CancellationToken::new();
+
+        let processor_stop_token = stop_token.clone();
+        let handle = tokio::spawn(async move {
+            let ret = processor
+                .run(
+                    task_client_mix_processor,
+                    task_client_mix_listener,
+                    processor_stop_token,
+                )
+                .await;
+            if let Err(err) = ret {
+                error!("Mixnet processor error: {err}");
+                Err(err)
+            } else {
+                ret
+            }
+        });
+
+        MixnetProcessorHandle {
+            handle,
+            stop_token,
+            mixnet_client: self.mixnet_client,
+            our_ips: self.our_ips,
+            connection_event_tx: self.connection_event_tx,
+            icmp_beacon_identifier: self.icmp_beacon_identifier,
+            traffic_stats: self.traffic_stats,
+        }
+    }
+}
+
 pub(crate) async fn start_processor(
     config: Config,
     dev: AsyncDevice,
     mixnet_client: SharedMixnetClient,
     task_manager: &TaskManager,
-    our_ips: nym_ip_packet_requests::IpPair,
+    our_ips: IpPair,
     connection_monitor: &ConnectionMonitorTask,
-) -> JoinHandle<Result<AsyncDevice, MixnetError>> {
-    info!("Creating mixnet processor");
-    let processor = MixnetProcessor::new(
-        dev,
+    traffic_stats: TrafficStatsHandle,
+) -> MixnetProcessorHandle {
+    StoppedMixnetProcessor {
+        device: dev,
         mixnet_client,
-        connection_monitor,
-        config.ip_packet_router_address,
         our_ips,
-    );
-
-    // This is an unfortunate limitation of the TaskManager/TaskClient. Would be better if we could
-    // have child clients like with tokio::CancellationToken, that can be crated from the parent
-    let task_client_mix_processor = task_manager.subscribe_named("mixnet_processor");
-    let task_client_mix_listener = task_manager.subscribe_named("mixnet_listener");
-
-    tokio::spawn(async move {
-        let ret = processor
-            .run(task_client_mix_processor, task_client_mix_listener)
-            .await;
-        if let Err(err) = ret {
-            error!("Mixnet processor error: {err}");
-            Err(err)
-        } else {
-            ret
-        }
-    })
+        connection_event_tx: connection_monitor.event_sender(),
+        icmp_beacon_identifier: connection_monitor.icmp_beacon_identifier(),
+        traffic_stats,
+    }
+    .start(config, task_manager)
 }
@@ -0,0 +1,165 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::time::Duration;
+
+use nym_ip_packet_requests::codec::BUFFER_TIMEOUT;
+use serde::{Deserialize, Serialize};
+
+pub const MAX_ANONYMITY_PROFILE: &str = "max-anonymity";
+pub const BALANCED_PROFILE: &str = "balanced";
+pub const LOW_LATENCY_PROFILE: &str = "low-latency";
+pub const BANDWIDTH_SAVER_PROFILE: &str = "bandwidth-saver";
+
+// How the traffic through the mixnet is shaped, trading anonymity for latency and bandwidth
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrafficProfile {
+    /// The name the profile is selected by.
+    pub name: String,
+
+    /// Send packets following a Poisson process, filling the gaps with cover traffic.
+    pub poisson_rate: bool,
+
+    /// The average delay between the packets sent by the Poisson process, in milliseconds.
+    pub average_send_delay_ms: u64,
+
+    /// The average delay a packet is held at each mix node, in milliseconds.
+    pub average_packet_delay_ms: u64,
+
+    /// Send a constant rate background stream of loop cover traffic.
+    pub loop_cover_traffic: bool,
+
+    /// The average delay between the loop cover packets, in milliseconds.
+    pub loop_cover_traffic_delay_ms: u64,
+
+    /// How long to wait for more IP packets to bundle into the same mixnet message, in
+    /// milliseconds.
+    pub packet_buffer_timeout_ms: u64,
+}
+
+impl TrafficProfile {
+    pub fn builtin(name: &str) -> Option<Self> {
+        Self::builtins()
+            .into_iter()
+            .find(|profile| profile.name == name)
+    }
+
+    pub fn builtins() -> Vec<Self> {
+        let balanced = TrafficProfile::default();
+        vec![
+            // Every packet looks the same to an observer, at the cost of both latency and
+            // bandwidth
+            TrafficProfile {
+                name: MAX_ANONYMITY_PROFILE.to_string(),
+                poisson_rate: true,
+                average_packet_delay_ms: 100,
+                packet_buffer_timeout_ms: 50,
+                ..balanced.clone()
+            },
+            balanced.clone(),
+            TrafficProfile {
+                name: LOW_LATENCY_PROFILE.to_string(),
+                average_packet_delay_ms: 10,
+                packet_buffer_timeout_ms: 5,
+                ..balanced.clone()
+            },
+            // No cover traffic, and fuller mixnet messages
+            TrafficProfile {
+                name: BANDWIDTH_SAVER_PROFILE.to_string(),
+                loop_cover_traffic: false,
+                packet_buffer_timeout_ms: 50,
+                ..balanced
+            },
+        ]
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.is_empty() {
+            return Err("traffic profiles must have a name".to_string());
+        }
+        // The delays are the means of exponential distributions, which can't be zero
+        if self.average_send_delay_ms == 0 || self.loop_cover_traffic_delay_ms == 0 {
+            return Err(format!(
+                "the send and cover traffic delays of traffic profile {} must be positive",
+                self.name
+            ));
+        }
+        // The codec flushes the bundled packets on an interval of this period, which can't be zero
+        if self.packet_buffer_timeout_ms == 0 {
+            return Err(format!(
+                "the packet buffer timeout of traffic profile {} must be positive",
+                self.name
+            ));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn average_send_delay(&self) -> Duration {
+        Duration::from_millis(self.average_send_delay_ms)
+    }
+
+    pub(crate) fn average_packet_delay(&self) -> Duration {
+        Duration::from_millis(self.average_packet_delay_ms)
+    }
+
+    pub(crate) fn loop_cover_traffic_delay(&self) -> Duration {
+        Duration::from_millis(self.loop_cover_traffic_delay_ms)
+    }
+
+    pub(crate) fn packet_buffer_timeout(&self) -> Duration {
+        Duration::from_millis(self.packet_buffer_timeout_ms)
+    }
+}
+
+// The defaults of the mixnet client and of the IP packet bundling, which is what we connected with
+// before there were profiles
+impl Default for TrafficProfile {
+    fn default() -> Self {
+        let debug_config = nym_client_core::config::DebugConfig::default();
+        let millis = |delay: Duration| delay.as_millis() as u64;
+        TrafficProfile {
+            name: BALANCED_PROFILE.to_string(),
+            poisson_rate: false,
+            average_send_delay_ms: millis(debug_config.traffic.message_sending_average_delay),
+            average_packet_delay_ms: millis(debug_config.traffic.average_packet_delay),
+            loop_cover_traffic: true,
+            loop_cover_traffic_delay_ms: millis(
+                debug_config.cover_traffic.loop_cover_traffic_average_delay,
+            ),
+            packet_buffer_timeout_ms: millis(BUFFER_TIMEOUT),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_profiles_are_valid() {
+        let names = [
+            MAX_ANONYMITY_PROFILE,
+            BALANCED_PROFILE,
+            LOW_LATENCY_PROFILE,
+            BANDWIDTH_SAVER_PROFILE,
+        ];
+        for name in names {
+            let profile = TrafficProfile::builtin(name).unwrap();
+            assert_eq!(profile.name, name);
+            assert_eq!(profile.validate(), Ok(()));
+        }
+        assert_eq!(TrafficProfile::builtins().len(), names.len());
+        assert_eq!(TrafficProfile::builtin("unknown"), None);
+
+        let zero_send_delay = TrafficProfile {
+            average_send_delay_ms: 0,
+            ..TrafficProfile::default()
+        };
+        assert!(zero_send_delay.validate().is_err());
+        let zero_buffer_timeout = TrafficProfile {
+            packet_buffer_timeout_ms: 0,
+            ..TrafficProfile::default()
+        };
+        assert!(zero_buffer_timeout.validate().is_err());
+    }
+}
@@ -1,21 +1,24 @@
 // Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
 // SPDX-License-Identifier: Apache-2.0
 
-//! WireGuard tunnel creation and management on Android and iOS
+//! WireGuard tunnel creation and management on Android and iOS, and fully in userspace behind a
+//! SOCKS5 proxy on Linux
 
 #[cfg(target_os = "ios")]
 pub mod ios;
-#[cfg(any(target_os = "ios", target_os = "android"))]
 pub mod runner;
+#[cfg(any(target_os = "ios", target_os = "android"))]
 pub mod tunnel_settings;
 pub mod two_hop_config;
 pub mod two_hop_tunnel;
 pub mod wg_config;
 
+#[cfg(any(target_os = "ios", target_os = "android"))]
 use crate::platform::error::FFIError;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
+    #[cfg(any(target_os = "ios", target_os = "android"))]
     #[error("failed to locate tun fd")]
     CannotLocateTunFd,
 
@@ -30,9 +33,22 @@ pub enum Error {
     #[error("DNS resolution failure")]
     DnsResolution(#[from] ios::dns64::Error),
 
+    #[cfg(any(target_os = "ios", target_os = "android"))]
     #[error("failed to set network settings")]
     SetNetworkSettings(#[source] FFIError),
 
+    #[cfg(target_os = "linux")]
+    #[error("failed to set up a local socket: {0}")]
+    LocalSocket(#[source] std::io::Error),
+
+    #[cfg(target_os = "linux")]
+    #[error("no response through the exit tunnel")]
+    ExitUnreachable,
+
+    #[cfg(target_os = "linux")]
+    #[error("the exit tunnel stopped")]
+    ExitTunnelStopped,
+
     #[cfg(target_os = "ios")]
     #[error("failed to set default path observer")]
     SetDefaultPathObserver(#[source] FFIError),
@@ -1,14 +1,18 @@
-use std::{net::IpAddr, sync::Arc, time::Duration};
+#[cfg(any(target_os = "ios", target_os = "android"))]
+use std::sync::Arc;
+use std::{net::IpAddr, time::Duration};
 
 use ipnetwork::{IpNetwork, Ipv4Network};
 use nym_authenticator_client::AuthClient;
-use nym_gateway_directory::{
-    AuthAddresses, EntryPoint, ExitPoint, Gateway, GatewayClient, Recipient,
-};
+use nym_gateway_directory::{AuthAddresses, Gateway, GatewayClient, Recipient};
+#[cfg(any(target_os = "ios", target_os = "android"))]
+use nym_gateway_directory::{EntryPoint, ExitPoint};
 use nym_sdk::UserAgent;
 use nym_task::TaskManager;
 use nym_wg_gateway_client::{GatewayData, WgGatewayClient};
 use nym_wg_go::{PrivateKey, PublicKey};
+#[cfg(target_os = "linux")]
+use tokio::net::TcpListener;
 use tokio::task::JoinSet;
 use tokio_util::sync::CancellationToken;
 
@@ -17,14 +21,18 @@ use super::wg_config::{WgInterface, WgNodeConfig, WgPeer};
 use crate::mobile::ios::tun_provider::OSTunProvider;
 #[cfg(target_os = "android")]
 use crate::platform::android::AndroidTunProvider;
+#[cfg(target_os = "linux")]
+use crate::proxy::ProxyConfig;
 use crate::{
     bandwidth_controller::BandwidthController,
     mixnet::SharedMixnetClient,
     mobile::two_hop_tunnel,
-    platform::{uniffi_set_listener_status, VPNConfig},
+    platform::uniffi_set_listener_status,
     uniffi_custom_impls::{StatusEvent, TunStatus},
-    GatewayDirectoryError, GenericNymVpnConfig, MixnetClientConfig,
+    GatewayDirectoryError, GenericNymVpnConfig,
 };
+#[cfg(any(target_os = "ios", target_os = "android"))]
+use crate::{mixnet::TrafficProfile, platform::VPNConfig, MixnetClientConfig};
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -72,16 +80,21 @@ pub struct WgTunnelRunner {
     tun_provider: Arc<dyn AndroidTunProvider>,
     #[cfg(target_os = "ios")]
     tun_provider: Arc<dyn OSTunProvider>,
+    // Where the proxy served through the tunnel listens, on linux where there's no tun device
+    #[cfg(target_os = "linux")]
+    socks5_listener: Option<TcpListener>,
     shutdown_token: CancellationToken,
 }
 
 impl WgTunnelRunner {
+    #[cfg(any(target_os = "ios", target_os = "android"))]
     pub fn new(config: VPNConfig, shutdown_token: CancellationToken) -> Result<Self> {
         let user_agent = UserAgent::from(nym_bin_common::bin_info_local_vergen!());
         tracing::info!("User agent: {user_agent}");
 
         let generic_config = GenericNymVpnConfig {
             mixnet_client_config: MixnetClientConfig {
+                traffic_profile: TrafficProfile::default(),
                 enable_poisson_rate: false,
                 disable_background_cover_traffic: false,
                 enable_credentials_mode: false,
@@ -93,24 +106,27 @@ impl WgTunnelRunner {
                 api_url: config.api_url,
                 nym_vpn_api_url: config.vpn_api_url,
                 min_gateway_performance: None,
+                cache: None,
             },
             entry_point: EntryPoint::from(config.entry_gateway),
             exit_point: ExitPoint::from(config.exit_router),
             nym_ips: None,
             nym_mtu: None,
-            dns: None,
+            dns: Default::default(),
+            // The mobile platforms can't bind the DNS stub, so encrypted and filtered DNS are
+            // not offered there
+            encrypted_dns: None,
+            dns_filter: None,
             disable_routing: false,
             user_agent: Some(user_agent.clone()),
+            kill_switch: Default::default(),
+            split_tunnel: Default::default(),
+            connection_monitor: Default::default(),
+            exit_failover: Default::default(),
         };
 
         let task_manager = TaskManager::new(TASK_MANAGER_SHUTDOWN_TIMER_SECS).named("nym_vpn_lib");
-        let gateway_directory_client =
-            GatewayClient::new(generic_config.gateway_config.clone(), user_agent.clone()).map_err(
-                |err| GatewayDirectoryError::FailedtoSetupGatewayDirectoryClient {
-                    config: Box::new(generic_config.gateway_config.clone()),
-                    source: err,
-                },
-            )?;
+        let gateway_directory_client = new_gateway_directory_client(&generic_config, user_agent)?;
 
         Ok(Self {
             gateway_directory_client,
@@ -124,6 +140,50 @@ impl WgTunnelRunner {
         })
     }
 
+    /// Create a runner serving the SOCKS5 proxy of `config` on `socks5_listener`, through two
+    /// WireGuard hops run in userspace.
+    #[cfg(target_os = "linux")]
+    pub fn new_proxy(
+        config: ProxyConfig,
+        socks5_listener: TcpListener,
+        shutdown_token: CancellationToken,
+    ) -> Result<Self> {
+        let user_agent = config
+            .user_agent
+            .unwrap_or_else(|| UserAgent::from(nym_bin_common::bin_info_local_vergen!()));
+        tracing::info!("User agent: {user_agent}");
+
+        let generic_config = GenericNymVpnConfig {
+            mixnet_client_config: config.mixnet_client_config,
+            data_path: config.data_path,
+            gateway_config: config.gateway_config,
+            entry_point: config.entry_point,
+            exit_point: config.exit_point,
+            nym_ips: None,
+            nym_mtu: None,
+            dns: config.dns,
+            encrypted_dns: None,
+            dns_filter: None,
+            disable_routing: false,
+            user_agent: Some(user_agent.clone()),
+            kill_switch: Default::default(),
+            split_tunnel: Default::default(),
+            connection_monitor: Default::default(),
+            exit_failover: Default::default(),
+        };
+
+        let task_manager = TaskManager::new(TASK_MANAGER_SHUTDOWN_TIMER_SECS).named("nym_vpn_lib");
+        let gateway_directory_client = new_gateway_directory_client(&generic_config, user_agent)?;
+
+        Ok(Self {
+            gateway_directory_client,
+            task_manager,
+            generic_config,
+            socks5_listener: Some(socks5_listener),
+            shutdown_token,
+        })
+    }
+
     pub async fn start(mut self) -> Result<()> {
         let SelectedGateways { entry, exit } = self.select_gateways().await?;
         let mixnet_client = self.start_mixnet_client(&entry).await?;
@@ -216,17 +276,23 @@ impl WgTunnelRunner {
             .lookup_all_gateways()
             .await
             .map_err(|source| GatewayDirectoryError::FailedToLookupGateways { source })?;
-        let mut entry_gateways = all_gateways.clone();
+        let entry_gateways = all_gateways.clone();
         let exit_gateways = all_gateways;
 
         let exit_gateway = self
             .generic_config
             .exit_point
             .lookup_gateway(&exit_gateways)
+            .await
             .map_err(|source| GatewayDirectoryError::FailedToSelectExitGateway { source })?;
 
-        // Exclude the exit gateway from the list of entry gateways for privacy reasons
-        entry_gateways.remove_gateway(&exit_gateway);
+        // Exclude the exit gateway, and any other on its host, from the list of entry gateways for
+        // privacy reasons
+        let entry_gateways = crate::tunnel_setup::entry_gateways_apart_from_exit(
+            entry_gateways,
+            &self.generic_config.entry_point,
+            &exit_gateway,
+        )?;
 
         let entry_gateway = self
             .generic_config
@@ -300,7 +366,7 @@ impl WgTunnelRunner {
     }
 
     async fn start_wireguard(
-        &self,
+        &mut self,
         mixnet_client: SharedMixnetClient,
         auth_addresses: AuthAddresses,
     ) -> Result<()> {
@@ -314,16 +380,28 @@ impl WgTunnelRunner {
         let wg_entry_config = self
             .start_wg_entry_client(auth_client.clone(), entry_auth_recipient)
             .await?;
-        let wg_exit_config = self
+        #[cfg_attr(not(target_os = "linux"), allow(unused_mut))]
+        let mut wg_exit_config = self
             .start_wg_exit_client(auth_client.clone(), exit_auth_recipient)
             .await?;
 
         tracing::info!("Created wg gateway clients");
 
+        // The proxy resolves the names in the requests with the servers it was given
+        #[cfg(target_os = "linux")]
+        {
+            wg_exit_config.interface.dns = self.generic_config.dns.servers_or_default();
+        }
+
         two_hop_tunnel::start(
             wg_entry_config,
             wg_exit_config,
+            #[cfg(any(target_os = "ios", target_os = "android"))]
             self.tun_provider.clone(),
+            #[cfg(target_os = "linux")]
+            self.socks5_listener
+                .take()
+                .expect("the wireguard tunnel is only started once"),
             self.shutdown_token.clone(),
         )
         .await
@@ -406,6 +484,19 @@ impl WgTunnelRunner {
     }
 }
 
+fn new_gateway_directory_client(
+    generic_config: &GenericNymVpnConfig,
+    user_agent: UserAgent,
+) -> Result<GatewayClient> {
+    GatewayClient::new(generic_config.gateway_config.clone(), user_agent).map_err(|err| {
+        GatewayDirectoryError::FailedtoSetupGatewayDirectoryClient {
+            config: Box::new(generic_config.gateway_config.clone()),
+            source: err,
+        }
+        .into()
+    })
+}
+
 struct SelectedGateways {
     entry: nym_gateway_directory::Gateway,
     exit: nym_gateway_directory::Gateway,
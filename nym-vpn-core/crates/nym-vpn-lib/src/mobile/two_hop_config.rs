@@ -11,6 +11,7 @@ const MIN_IPV6_MTU: u16 = 1280;
 const WG_TUNNEL_OVERHEAD: u16 = 80;
 
 /// Local port used for accepting exit traffic.
+#[cfg(any(target_os = "ios", target_os = "android"))]
 const UDP_FORWARDER_PORT: u16 = 34001;
 
 /// Local port used by exit tunnel when sending traffic to the udp forwarder.
@@ -22,7 +23,8 @@ pub struct TwoHopConfig {
     /// Entry configuration applied to netstack based WireGuard tunnel.
     pub entry: WgNodeConfig,
 
-    /// Exit configuration applied to wireguard-go attached to tun device.
+    /// Exit configuration applied to wireguard-go attached to tun device, or to the packet tunnel
+    /// on linux.
     pub exit: WgNodeConfig,
 
     /// Configuration for UDP forwader that's used for wrapping tunnel in tunnel.
@@ -34,7 +36,17 @@ pub struct TwoHopConfig {
 
 impl TwoHopConfig {
     /// Create new two-hop configuration given two individual WireGuard configurations.
+    #[cfg(any(target_os = "ios", target_os = "android"))]
     pub fn new(entry: WgNodeConfig, exit: WgNodeConfig) -> Self {
+        Self::with_forwarder_port(entry, exit, UDP_FORWARDER_PORT)
+    }
+
+    /// Create new two-hop configuration, with the udp forwarder listening on `forwarder_port`.
+    pub fn with_forwarder_port(
+        entry: WgNodeConfig,
+        exit: WgNodeConfig,
+        forwarder_port: u16,
+    ) -> Self {
         // Ensure that exit instance of wg attached on tun interface, uses a fixed port number
         // to initiate connection to the udp forwarder, because it ignores traffic from other ports.
         let client_port = exit.interface.listen_port.unwrap_or(EXIT_WG_CLIENT_PORT);
@@ -47,7 +59,7 @@ impl TwoHopConfig {
                 } else {
                     IpAddr::V6(Ipv6Addr::LOCALHOST)
                 },
-                UDP_FORWARDER_PORT,
+                forwarder_port,
             ),
             exit_endpoint: exit.peer.endpoint,
             client_port,
@@ -101,6 +113,8 @@ pub struct WgForwarderConfig {
     pub client_port: u16,
 }
 
+// The userspace tunnel on linux has no tun device and only looks at the DNS servers and the MTU
+#[cfg_attr(target_os = "linux", allow(dead_code))]
 #[derive(Debug)]
 pub struct TunConfig {
     pub addresses: Vec<IpNetwork>,
@@ -0,0 +1,374 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    os::fd::OwnedFd,
+    time::Duration,
+};
+
+use bytes::Bytes;
+use hickory_resolver::proto::{
+    op::{Message, MessageType, OpCode, Query},
+    rr::{Name, RecordType},
+};
+use nym_task::TaskManager;
+use nym_wg_go::{netstack, packet_tunnel};
+use tokio::{
+    net::{TcpListener, UnixDatagram},
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    mobile::{two_hop_config::TwoHopConfig, wg_config::WgNodeConfig, Error, Result},
+    platform::uniffi_set_listener_status,
+    proxy::{
+        resolver::TunnelResolver,
+        socks5::Socks5Server,
+        stack::{NetStack, PacketPipe, StackHandle},
+    },
+    uniffi_custom_impls::{StatusEvent, TunStatus},
+};
+
+/// Number of times the probe is sent before giving up on the exit.
+const PROBE_ATTEMPTS: u32 = 5;
+
+/// Time to wait for the answer to each probe.
+const PROBE_INTERVAL: Duration = Duration::from_secs(2);
+
+const DNS_PORT: u16 = 53;
+
+const TASK_MANAGER_SHUTDOWN_TIMER_SECS: u64 = 10;
+
+pub struct TwoHopTunnelImp {
+    /// Entry node tunnel
+    /// Retained inside struct on purpose
+    _entry: netstack::Tunnel,
+
+    /// Exit node tunnel, running on top of the entry tunnel.
+    /// Retained inside struct on purpose
+    _exit: packet_tunnel::Tunnel,
+
+    /// UDP connection over the entry tunnel, towards exit node.
+    /// Retained inside struct on purpose
+    _exit_connection: netstack::TunnelConnection,
+
+    /// Tasks of the network stack and the SOCKS5 proxy served over the exit tunnel.
+    task_manager: TaskManager,
+
+    /// Cancellation token.
+    shutdown_token: CancellationToken,
+}
+
+impl TwoHopTunnelImp {
+    /// Start two-hop wg tunnel given entry and exit nodes, and serve a SOCKS5 proxy through it on
+    /// `socks5_listener`.
+    pub async fn start(
+        entry_node_config: WgNodeConfig,
+        mut exit_node_config: WgNodeConfig,
+        socks5_listener: TcpListener,
+        shutdown_token: CancellationToken,
+    ) -> Result<()> {
+        // Several instances may run side by side, so the local ports are picked among the free ones
+        let forwarder_ip = if exit_node_config.peer.endpoint.is_ipv4() {
+            IpAddr::V4(Ipv4Addr::LOCALHOST)
+        } else {
+            IpAddr::V6(Ipv6Addr::LOCALHOST)
+        };
+        let forwarder_port = free_udp_port(forwarder_ip)?;
+        // wireguard-go binds its port on all the interfaces
+        exit_node_config.interface.listen_port =
+            Some(free_udp_port(IpAddr::V4(Ipv4Addr::UNSPECIFIED))?);
+        let two_hop_config =
+            TwoHopConfig::with_forwarder_port(entry_node_config, exit_node_config, forwarder_port);
+
+        tracing::info!("Two-hop entry: {:#?}", two_hop_config.entry);
+        tracing::info!("Two-hop exit: {:#?}", two_hop_config.exit);
+        tracing::info!("Two-hop forwarder: {:#?}", two_hop_config.forwarder);
+
+        let dns_servers = two_hop_config.tun.dns.clone();
+        let mtu = two_hop_config.tun.mtu;
+        let our_ips = two_hop_config
+            .exit
+            .interface
+            .addresses
+            .iter()
+            .map(|network| network.ip())
+            .collect::<Vec<_>>();
+
+        let entry_wg_config = two_hop_config.entry.into_netstack_config();
+        let exit_wg_config = two_hop_config.exit.into_packet_tunnel_config();
+
+        // Create netstack wg connected to the entry node.
+        let mut entry_tunnel = netstack::Tunnel::start(entry_wg_config, |s| {
+            tracing::debug!(name = "wg-netstack-entry", "{}", s);
+        })?;
+
+        // Open connection to the exit node via entry node.
+        let exit_connection = entry_tunnel.open_connection(
+            two_hop_config.forwarder.listen_endpoint.port(),
+            two_hop_config.forwarder.client_port,
+            two_hop_config.forwarder.exit_endpoint,
+        )?;
+
+        // Create wg connected to the exit node through the local udp forwarder. Its packets come
+        // and go over a socket instead of a tun device, and our own network stack handles them.
+        let exit_tunnel = packet_tunnel::Tunnel::start(exit_wg_config, |s| {
+            tracing::debug!(name = "wg-packet-exit", "{}", s);
+        })?;
+        let pipe = WireguardPipe::new(exit_tunnel.packet_socket()?).map_err(Error::LocalSocket)?;
+
+        let task_manager = TaskManager::new(TASK_MANAGER_SHUTDOWN_TIMER_SECS).named("nym_vpn_wg");
+        let stack = NetStack::new(&our_ips, mtu);
+        let stack_handle = stack.handle();
+        let stack_task = stack.start(pipe, task_manager.subscribe_named("wg_network_stack"));
+
+        let mut two_hop_tunnel = Self {
+            _entry: entry_tunnel,
+            _exit: exit_tunnel,
+            _exit_connection: exit_connection,
+            task_manager,
+            shutdown_token,
+        };
+
+        let result = two_hop_tunnel
+            .run(stack_handle, stack_task, socks5_listener, dns_servers)
+            .await;
+
+        two_hop_tunnel.task_manager.signal_shutdown().ok();
+        two_hop_tunnel.task_manager.wait_for_shutdown().await;
+
+        result
+    }
+
+    async fn run(
+        &self,
+        stack_handle: StackHandle,
+        mut stack_task: JoinHandle<()>,
+        socks5_listener: TcpListener,
+        dns_servers: Vec<IpAddr>,
+    ) -> Result<()> {
+        // Only report the tunnel as up once traffic made it through both hops.
+        match dns_servers.first().copied() {
+            Some(dns_server) => {
+                tokio::select! {
+                    _ = self.shutdown_token.cancelled() => {
+                        tracing::debug!("Received shutdown while probing the exit.");
+                        return Ok(());
+                    }
+                    result = probe_exit(&stack_handle, dns_server) => result?,
+                }
+            }
+            None => tracing::warn!("No DNS server to probe the exit tunnel with"),
+        }
+
+        let resolver = TunnelResolver::new(stack_handle.clone(), dns_servers);
+        let server = Socks5Server::new(socks5_listener, stack_handle, resolver);
+        tokio::spawn(server.run(self.task_manager.subscribe_named("socks5_server")));
+
+        uniffi_set_listener_status(StatusEvent::Tun(TunStatus::Up));
+
+        tokio::select! {
+            _ = self.shutdown_token.cancelled() => {
+                tracing::debug!("Received shutdown.");
+                Ok(())
+            }
+            _ = &mut stack_task => Err(Error::ExitTunnelStopped),
+        }
+    }
+}
+
+// A port nobody is using right now. The tunnels bind it themselves a moment later.
+fn free_udp_port(ip: IpAddr) -> Result<u16> {
+    std::net::UdpSocket::bind((ip, 0))
+        .and_then(|socket| socket.local_addr())
+        .map(|addr| addr.port())
+        .map_err(Error::LocalSocket)
+}
+
+// Exchanges the packets of our network stack with the exit tunnel, one packet per datagram
+struct WireguardPipe {
+    socket: UnixDatagram,
+    buf: Vec<u8>,
+}
+
+impl WireguardPipe {
+    fn new(packet_socket: OwnedFd) -> std::io::Result<Self> {
+        let socket = std::os::unix::net::UnixDatagram::from(packet_socket);
+        socket.set_nonblocking(true)?;
+        Ok(WireguardPipe {
+            socket: UnixDatagram::from_std(socket)?,
+            buf: vec![0; usize::from(u16::MAX)],
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl PacketPipe for WireguardPipe {
+    async fn send_packet(&mut self, packet: Vec<u8>) {
+        if let Err(err) = self.socket.send(&packet).await {
+            tracing::debug!("Failed to send a packet to the exit tunnel: {err}");
+        }
+    }
+
+    async fn recv_packets(&mut self) -> Option<Vec<Bytes>> {
+        match self.socket.recv(&mut self.buf).await {
+            // Empty datagrams aren't packets, the tunnel closed its end
+            Ok(0) => None,
+            Ok(len) => Some(vec![Bytes::copy_from_slice(&self.buf[..len])]),
+            Err(err) => {
+                tracing::error!("Failed to receive from the exit tunnel: {err}");
+                None
+            }
+        }
+    }
+}
+
+/// Send a DNS query through both hops and wait for the answer.
+async fn probe_exit(stack: &StackHandle, dns_server: IpAddr) -> Result<()> {
+    let mut connection = stack.udp_bind().await.map_err(|err| {
+        tracing::error!("Failed to open the probe connection: {err}");
+        Error::ExitUnreachable
+    })?;
+    let dns_server = SocketAddr::new(dns_server, DNS_PORT);
+
+    let id = rand::random();
+    let query = Bytes::from(probe_query(id));
+    for attempt in 1..=PROBE_ATTEMPTS {
+        tracing::debug!("Probing the exit tunnel via {dns_server} (attempt {attempt})");
+        if let Err(err) = connection.send_to(query.clone(), dns_server).await {
+            tracing::error!("Failed to send the probe: {err}");
+            return Err(Error::ExitUnreachable);
+        }
+
+        let answered = tokio::time::timeout(PROBE_INTERVAL, async {
+            while let Some((source, response)) = connection.recv_from().await {
+                if source == dns_server
+                    && Message::from_vec(&response).is_ok_and(|response| response.id() == id)
+                {
+                    return true;
+                }
+            }
+            false
+        })
+        .await;
+        match answered {
+            Ok(true) => {
+                tracing::info!("Two-hop tunnel is passing traffic");
+                return Ok(());
+            }
+            // The network stack stopped
+            Ok(false) => break,
+            Err(_) => continue,
+        }
+    }
+
+    Err(Error::ExitUnreachable)
+}
+
+// Any recursive resolver answers the root NS query, whatever it's configured to block
+fn probe_query(id: u16) -> Vec<u8> {
+    let mut query = Message::new();
+    query
+        .set_id(id)
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .set_recursion_desired(true)
+        .add_query(Query::query(Name::root(), RecordType::NS));
+    query.to_vec().expect("failed to encode the probe query")
+}
+
+#[cfg(test)]
+mod tests {
+    use ipnetwork::IpNetwork;
+    use nym_wg_go::{PeerConfig, PrivateKey};
+
+    use super::*;
+    use crate::proxy::stack::tests::{socks5_echo, EchoPeer, PEER_IP};
+
+    const OUR_IP: Ipv4Addr = Ipv4Addr::new(10, 64, 0, 2);
+
+    fn tunnel(
+        private_key: &PrivateKey,
+        listen_port: u16,
+        peer: PeerConfig,
+    ) -> packet_tunnel::Tunnel {
+        packet_tunnel::Tunnel::start(
+            packet_tunnel::Config {
+                interface: packet_tunnel::InterfaceConfig {
+                    listen_port: Some(listen_port),
+                    private_key: private_key.clone(),
+                    mtu: 1420,
+                },
+                peers: vec![peer],
+            },
+            |_| {},
+        )
+        .unwrap()
+    }
+
+    // The proxy over a packet tunnel, with another packet tunnel on loopback in place of the exit
+    // gateway. Everything runs in userspace, so it needs no privileges.
+    #[tokio::test]
+    async fn socks5_connections_go_through_the_packet_tunnel() {
+        let our_key = PrivateKey::from([1u8; 32]);
+        let peer_key = PrivateKey::from([2u8; 32]);
+        let our_port = free_udp_port(IpAddr::V4(Ipv4Addr::UNSPECIFIED)).unwrap();
+        let peer_port = free_udp_port(IpAddr::V4(Ipv4Addr::UNSPECIFIED)).unwrap();
+
+        let our_tunnel = tunnel(
+            &our_key,
+            our_port,
+            PeerConfig {
+                public_key: peer_key.public_key(),
+                preshared_key: None,
+                endpoint: SocketAddr::from((Ipv4Addr::LOCALHOST, peer_port)),
+                allowed_ips: vec!["0.0.0.0/0".parse().unwrap()],
+            },
+        );
+        let peer_tunnel = tunnel(
+            &peer_key,
+            peer_port,
+            PeerConfig {
+                public_key: our_key.public_key(),
+                preshared_key: None,
+                endpoint: SocketAddr::from((Ipv4Addr::LOCALHOST, our_port)),
+                allowed_ips: vec![IpNetwork::V4(OUR_IP.into())],
+            },
+        );
+
+        // The echo peer answers on the far side of the tunnel
+        let mut peer_pipe = WireguardPipe::new(peer_tunnel.packet_socket().unwrap()).unwrap();
+        let mut echo = EchoPeer::new();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    Some(packets) = peer_pipe.recv_packets() => {
+                        for packet in packets {
+                            echo.send_packet(packet.to_vec()).await;
+                        }
+                    }
+                    Some(packets) = echo.recv_packets() => {
+                        for packet in packets {
+                            peer_pipe.send_packet(packet.to_vec()).await;
+                        }
+                    }
+                }
+            }
+        });
+
+        let task_manager = TaskManager::new(1);
+        let stack = NetStack::new(&[IpAddr::V4(OUR_IP)], 1420);
+        let stack_handle = stack.handle();
+        let _stack_task = stack.start(
+            WireguardPipe::new(our_tunnel.packet_socket().unwrap()).unwrap(),
+            task_manager.subscribe_named("wg_network_stack"),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap();
+        let resolver = TunnelResolver::new(stack_handle.clone(), Vec::new());
+        let server = Socks5Server::new(listener, stack_handle, resolver);
+        tokio::spawn(server.run(task_manager.subscribe_named("socks5_server")));
+
+        socks5_echo(proxy, PEER_IP).await;
+    }
+}
@@ -6,8 +6,15 @@ mod imp;
 #[path = "ios.rs"]
 mod imp;
 
+#[cfg(target_os = "linux")]
+#[path = "linux.rs"]
+mod imp;
+
+#[cfg(any(target_os = "ios", target_os = "android"))]
 use std::sync::Arc;
 
+#[cfg(target_os = "linux")]
+use tokio::net::TcpListener;
 use tokio_util::sync::CancellationToken;
 
 #[cfg(target_os = "ios")]
@@ -26,17 +33,25 @@ use crate::platform::android::AndroidTunProvider;
 /// * The UDP connection to the exit node is established over the entry tunnel.
 /// * The exit traffic is captured on tun interface and directed towards local UDP forwarding proxy.
 /// * The local UDP forwarding proxy injects all received UDP datagrams into the UDP connection to the exit node.
+///
+/// On Linux there is no tun interface: the exit tunnel exchanges its packets over a local socket with
+/// a userspace network stack, which serves a SOCKS5 proxy on `socks5_listener`. The whole two-hop
+/// tunnel runs in userspace without privileges.
 pub async fn start(
     entry_node_config: WgNodeConfig,
     exit_node_config: WgNodeConfig,
     #[cfg(target_os = "android")] tun_provider: Arc<dyn AndroidTunProvider>,
     #[cfg(target_os = "ios")] tun_provider: Arc<dyn OSTunProvider>,
+    #[cfg(target_os = "linux")] socks5_listener: TcpListener,
     shutdown_token: CancellationToken,
 ) -> Result<()> {
     imp::TwoHopTunnelImp::start(
         entry_node_config,
         exit_node_config,
+        #[cfg(any(target_os = "ios", target_os = "android"))]
         tun_provider,
+        #[cfg(target_os = "linux")]
+        socks5_listener,
         shutdown_token,
     )
     .await
@@ -4,9 +4,13 @@ use std::{
 };
 
 use ipnetwork::IpNetwork;
+#[cfg(target_os = "linux")]
+use nym_wg_go::packet_tunnel;
+#[cfg(any(target_os = "ios", target_os = "android"))]
+use nym_wg_go::wireguard_go;
 #[cfg(target_os = "ios")]
 use nym_wg_go::PeerEndpointUpdate;
-use nym_wg_go::{netstack, wireguard_go, PeerConfig, PrivateKey, PublicKey};
+use nym_wg_go::{netstack, PeerConfig, PrivateKey, PublicKey};
 
 #[derive(Debug)]
 pub struct WgNodeConfig {
@@ -69,6 +73,7 @@ impl WgNodeConfig {
     pub fn into_netstack_config(self) -> netstack::Config {
         netstack::Config {
             interface: netstack::InterfaceConfig {
+                listen_port: self.interface.listen_port,
                 private_key: self.interface.private_key,
                 local_addrs: self
                     .interface
@@ -89,6 +94,7 @@ impl WgNodeConfig {
         }
     }
 
+    #[cfg(any(target_os = "ios", target_os = "android"))]
     pub fn into_wireguard_config(self) -> wireguard_go::Config {
         wireguard_go::Config {
             interface: wireguard_go::InterfaceConfig {
@@ -104,4 +110,21 @@ impl WgNodeConfig {
             }],
         }
     }
+
+    #[cfg(target_os = "linux")]
+    pub fn into_packet_tunnel_config(self) -> packet_tunnel::Config {
+        packet_tunnel::Config {
+            interface: packet_tunnel::InterfaceConfig {
+                listen_port: self.interface.listen_port,
+                private_key: self.interface.private_key,
+                mtu: self.interface.mtu,
+            },
+            peers: vec![PeerConfig {
+                public_key: self.peer.public_key,
+                preshared_key: None,
+                endpoint: self.peer.endpoint,
+                allowed_ips: vec!["0.0.0.0/0".parse().unwrap(), "::/0".parse().unwrap()],
+            }],
+        }
+    }
 }
@@ -150,6 +150,7 @@ async fn wait_for_shutdown(
         vpn_ctrl_tx,
         vpn_status_rx,
         vpn_exit_rx,
+        ..
     } = handle;
 
     RUNTIME.spawn(async move {
@@ -405,6 +406,7 @@ async fn get_gateway_countries(
         api_url,
         nym_vpn_api_url,
         min_gateway_performance: None,
+        cache: None,
     };
     let directory_client = GatewayClient::new(directory_config, user_agent)?;
     let locations = if !exit_only {
@@ -456,6 +458,7 @@ async fn get_low_latency_entry_country(
         api_url,
         nym_vpn_api_url: vpn_api_url,
         min_gateway_performance: None,
+        cache: None,
     };
     let user_agent = user_agent
         .map(nym_sdk::UserAgent::from)
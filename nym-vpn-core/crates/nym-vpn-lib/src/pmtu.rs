@@ -0,0 +1,311 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use tokio::{sync::Notify, time::Interval};
+use tracing::{debug, info, warn};
+
+#[cfg(target_os = "linux")]
+use linux::probe;
+
+// Assumed when the path can't be probed. We also never go above it, since the gateways might not
+// forward anything larger.
+pub(crate) const DEFAULT_WIRE_MTU: u16 = 1500;
+
+// IPv6 requires every link to carry at least this much, so no tunnel goes below it
+pub(crate) const MIN_TUNNEL_MTU: u16 = 1280;
+
+// The path to the entry gateway can change while we're connected, e.g. when switching networks
+const REPROBE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+// Common path MTUs below Ethernet, from PPPoE down to what is left after a couple of tunnels
+const PLATEAUS: [u16; 8] = [1492, 1480, 1460, 1420, 1400, 1380, 1360, 1340];
+
+// The WireGuard overhead is the outer IP header, the UDP header and the WireGuard metadata
+const UDP_HEADER_LEN: u16 = 8;
+const WG_METADATA_LEN: u16 = 32;
+
+const fn ip_header_len(ipv6: bool) -> u16 {
+    if ipv6 {
+        40
+    } else {
+        20
+    }
+}
+
+const fn wg_overhead(ipv6: bool) -> u16 {
+    ip_header_len(ipv6) + UDP_HEADER_LEN + WG_METADATA_LEN
+}
+
+// How many tunnels can be stacked over a path of the given MTU before the innermost one goes below
+// the minimum
+pub(crate) const fn max_hop_count(wire_mtu: u16, entry_endpoint_ipv6: bool) -> usize {
+    let entry_mtu = wire_mtu.saturating_sub(wg_overhead(entry_endpoint_ipv6));
+    if entry_mtu < MIN_TUNNEL_MTU {
+        return 0;
+    }
+    ((entry_mtu - MIN_TUNNEL_MTU) / wg_overhead(true)) as usize + 1
+}
+
+// The MTU of each tunnel, from the entry to the exit. The entry tunnel runs over the wire to the
+// entry endpoint, and each tunnel after it runs inside the one before and carries both IPv4 and
+// IPv6, so it has to make room for the larger header. None when the path can't fit that many
+// tunnels without the innermost one going below the minimum.
+pub(crate) fn tunnel_mtus(
+    wire_mtu: u16,
+    entry_endpoint_ipv6: bool,
+    hop_count: usize,
+) -> Option<Vec<u16>> {
+    if hop_count > max_hop_count(wire_mtu, entry_endpoint_ipv6) {
+        return None;
+    }
+    let mut mtu = wire_mtu.saturating_sub(wg_overhead(entry_endpoint_ipv6));
+    let mut mtus = Vec::with_capacity(hop_count);
+    for _ in 0..hop_count {
+        mtus.push(mtu);
+        mtu = mtu.saturating_sub(wg_overhead(true));
+    }
+    Some(mtus)
+}
+
+// The next plateau below the given path MTU, used when large packets go missing without any ICMP
+// telling us why
+pub(crate) fn lower_plateau(wire_mtu: u16) -> Option<u16> {
+    PLATEAUS.into_iter().find(|plateau| *plateau < wire_mtu)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Reprobe {
+    Periodic,
+    // The exit tunnel stopped carrying traffic, which might be a path dropping large packets
+    Stalled,
+}
+
+// When to probe the path to the entry gateway again: every few minutes, and right away when the
+// tunnel health check reports the exit stalled
+pub(crate) struct PathMtuReprobe {
+    interval: Interval,
+    stalled: Arc<Notify>,
+}
+
+impl PathMtuReprobe {
+    pub(crate) fn new() -> Self {
+        Self {
+            interval: tokio::time::interval_at(
+                tokio::time::Instant::now() + REPROBE_INTERVAL,
+                REPROBE_INTERVAL,
+            ),
+            stalled: Arc::new(Notify::new()),
+        }
+    }
+
+    // Handed to the health check, which notifies it when the exit stalls
+    pub(crate) fn stalled(&self) -> Arc<Notify> {
+        self.stalled.clone()
+    }
+
+    pub(crate) async fn wait(&mut self) -> Reprobe {
+        tokio::select! {
+            _ = self.interval.tick() => Reprobe::Periodic,
+            _ = self.stalled.notified() => {
+                info!("The exit tunnel stalled, probing the path MTU again");
+                self.interval.reset();
+                Reprobe::Stalled
+            }
+        }
+    }
+}
+
+// Find the MTU of the path to the endpoint by sending UDP probes with the don't fragment bit set.
+// A router that can't forward a probe replies with ICMP fragmentation needed, which lowers the
+// path MTU the kernel keeps for the destination, and we probe again at the lower size until it
+// stops changing. Paths that silently drop the probes go unnoticed here, they are caught when the
+// exit tunnel stalls and the MTU steps down through the plateaus.
+pub(crate) async fn probe_path_mtu(endpoint: SocketAddr) -> Option<u16> {
+    match probe(endpoint).await {
+        Ok(mtu) => {
+            let mtu = mtu.min(DEFAULT_WIRE_MTU);
+            info!("Path MTU to {endpoint}: {mtu}");
+            Some(mtu)
+        }
+        Err(err) => {
+            warn!("Failed to probe the path MTU to {endpoint}: {err}");
+            None
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn probe(_endpoint: SocketAddr) -> std::io::Result<u16> {
+    Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+}
+
+// Change the MTU of a tunnel interface that is already up
+#[cfg(target_os = "linux")]
+pub(crate) async fn set_interface_mtu(interface: &str, mtu: u16) -> std::io::Result<()> {
+    debug!("Setting the MTU of {interface} to {mtu}");
+    let output = tokio::process::Command::new("ip")
+        .args(["link", "set", "dev", interface, "mtu", &mtu.to_string()])
+        .output()
+        .await?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ))
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) async fn set_interface_mtu(interface: &str, mtu: u16) -> std::io::Result<()> {
+    debug!("Not setting the MTU of {interface} to {mtu}, not supported on this platform");
+    Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{
+        io,
+        net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+        os::fd::AsRawFd,
+        time::Duration,
+    };
+
+    use nix::libc;
+    use tokio::net::UdpSocket;
+
+    const PROBE_ROUNDS: usize = 4;
+
+    // How long we give the routers along the path to send back ICMP fragmentation needed
+    const ICMP_WAIT: Duration = Duration::from_millis(300);
+
+    pub(super) async fn probe(endpoint: SocketAddr) -> io::Result<u16> {
+        let ipv6 = endpoint.is_ipv6();
+        let bind_addr: SocketAddr = if ipv6 {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = UdpSocket::bind(bind_addr).await?;
+        socket.connect(endpoint).await?;
+        set_dont_fragment(&socket, ipv6)?;
+
+        let header_len = super::ip_header_len(ipv6) + super::UDP_HEADER_LEN;
+        // Starts out as the MTU of the route to the endpoint
+        let mut mtu = path_mtu(&socket, ipv6)?;
+        for _ in 0..PROBE_ROUNDS {
+            let probe = vec![0u8; usize::from(mtu.saturating_sub(header_len))];
+            match socket.send(&probe).await {
+                // The kernel already knows the probe is too large
+                Err(err) if err.raw_os_error() == Some(libc::EMSGSIZE) => {}
+                Err(err) => return Err(err),
+                Ok(_) => tokio::time::sleep(ICMP_WAIT).await,
+            }
+            let probed = path_mtu(&socket, ipv6)?;
+            if probed >= mtu {
+                break;
+            }
+            mtu = probed;
+        }
+        Ok(mtu)
+    }
+
+    fn set_dont_fragment(socket: &UdpSocket, ipv6: bool) -> io::Result<()> {
+        let (level, name, value) = if ipv6 {
+            (
+                libc::IPPROTO_IPV6,
+                libc::IPV6_MTU_DISCOVER,
+                libc::IPV6_PMTUDISC_DO,
+            )
+        } else {
+            (
+                libc::IPPROTO_IP,
+                libc::IP_MTU_DISCOVER,
+                libc::IP_PMTUDISC_DO,
+            )
+        };
+        // SAFETY: the value is a c_int that outlives the call, and its size is passed along
+        let ret = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                level,
+                name,
+                &value as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    fn path_mtu(socket: &UdpSocket, ipv6: bool) -> io::Result<u16> {
+        let (level, name) = if ipv6 {
+            (libc::IPPROTO_IPV6, libc::IPV6_MTU)
+        } else {
+            (libc::IPPROTO_IP, libc::IP_MTU)
+        };
+        let mut value: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        // SAFETY: the value is a c_int that outlives the call, and len holds its size
+        let ret = unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                level,
+                name,
+                &mut value as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret == 0 {
+            Ok(u16::try_from(value).unwrap_or(u16::MAX))
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_tunnels_make_room_for_each_layer() {
+        assert_eq!(
+            tunnel_mtus(DEFAULT_WIRE_MTU, false, 2),
+            Some(vec![1440, 1360])
+        );
+        assert_eq!(
+            tunnel_mtus(DEFAULT_WIRE_MTU, true, 2),
+            Some(vec![1420, 1340])
+        );
+        // PPPoE
+        assert_eq!(tunnel_mtus(1492, false, 2), Some(vec![1432, 1352]));
+        assert_eq!(tunnel_mtus(1420, false, 2), Some(vec![1360, 1280]));
+    }
+
+    #[test]
+    fn paths_too_small_for_the_hops_are_refused() {
+        assert_eq!(tunnel_mtus(DEFAULT_WIRE_MTU, true, 3), None);
+        assert_eq!(tunnel_mtus(1492, false, 3), None);
+        assert_eq!(tunnel_mtus(1400, false, 2), None);
+    }
+
+    #[test]
+    fn black_holes_step_down_through_the_plateaus() {
+        assert_eq!(lower_plateau(DEFAULT_WIRE_MTU), Some(1492));
+        assert_eq!(lower_plateau(1492), Some(1480));
+        assert_eq!(lower_plateau(1340), None);
+    }
+
+    #[test]
+    fn hop_count_is_bounded_by_the_ipv6_minimum() {
+        assert_eq!(max_hop_count(DEFAULT_WIRE_MTU, false), 3);
+        assert_eq!(max_hop_count(DEFAULT_WIRE_MTU, true), 2);
+        assert_eq!(max_hop_count(1280, false), 0);
+    }
+}
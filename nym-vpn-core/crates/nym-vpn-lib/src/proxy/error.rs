@@ -0,0 +1,66 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::net::SocketAddr;
+
+use crate::{error::GatewayDirectoryError, mixnet::MixnetError};
+
+#[derive(thiserror::Error, Debug)]
+pub enum ProxyError {
+    #[error("{0}")]
+    GatewayDirectory(#[from] GatewayDirectoryError),
+
+    #[error("exit gateway {gateway_id} has no ip packet router")]
+    MissingIpPacketRouter { gateway_id: String },
+
+    #[error("timeout after waiting {0}s for mixnet client to start")]
+    StartMixnetClientTimeout(u64),
+
+    #[error("{0}")]
+    Mixnet(#[from] MixnetError),
+
+    #[error("failed to connect to ip packet router: {0}")]
+    FailedToConnectToIpPacketRouter(#[source] nym_ip_packet_client::Error),
+
+    #[error("failed to listen on {address}: {source}")]
+    FailedToListen {
+        address: SocketAddr,
+        source: std::io::Error,
+    },
+
+    #[error("refusing to listen on {address} without authentication, since it isn't loopback")]
+    RemoteClientsNotAllowed { address: SocketAddr },
+
+    #[error("the tunnel through the mixnet stopped")]
+    TunnelStopped,
+
+    #[error("the connection through the mixnet is lost")]
+    ConnectionLost,
+
+    #[cfg(target_os = "linux")]
+    #[error("{0}")]
+    Wireguard(#[from] crate::mobile::runner::Error),
+
+    #[cfg(not(target_os = "linux"))]
+    #[error("the wireguard proxy is only supported on linux")]
+    WireguardNotSupported,
+}
+
+// Errors of the connections opened through the tunnel
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum StackError {
+    #[error("the network stack stopped")]
+    Stopped,
+
+    #[error("failed to connect: {0}")]
+    Connect(#[from] smoltcp::socket::tcp::ConnectError),
+
+    #[error("failed to bind: {0}")]
+    Bind(#[from] smoltcp::socket::udp::BindError),
+
+    #[error("connection refused")]
+    ConnectionRefused,
+
+    #[error("timeout connecting through the tunnel")]
+    ConnectTimeout,
+}
@@ -0,0 +1,333 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+// The proxy mode connects to the exit through the mixnet like the mixnet mode does, but instead
+// of routing the system traffic through a tun device it serves a local SOCKS5 proxy. Routes, DNS
+// and the firewall are left alone, so it needs no privileges, and only the applications that are
+// pointed at the proxy use the mixnet. On linux the proxy can go through two WireGuard hops run in
+// userspace instead.
+
+mod error;
+pub(crate) mod resolver;
+pub(crate) mod socks5;
+pub(crate) mod stack;
+
+use std::{
+    future::Future,
+    net::SocketAddr,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use futures::{channel::mpsc, StreamExt};
+use nym_connection_monitor::{
+    ConnectionMonitorConfig, ConnectionMonitorStatus, ConnectionMonitorTask, ProbeTarget,
+};
+use nym_gateway_directory::{EntryPoint, ExitPoint, GatewayClient};
+use nym_ip_packet_client::IprClientConnect;
+use nym_ip_packet_requests::IpPair;
+use nym_sdk::UserAgent;
+use nym_task::{manager::TaskStatus, TaskManager};
+use tokio::{net::TcpListener, sync::watch, time::timeout};
+#[cfg(target_os = "linux")]
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+pub use error::ProxyError;
+
+use self::{
+    resolver::TunnelResolver,
+    socks5::Socks5Server,
+    stack::{MixnetPipe, NetStack},
+};
+#[cfg(target_os = "linux")]
+use crate::mobile::runner::WgTunnelRunner;
+use crate::{
+    dns::DnsConfig,
+    error::GatewayDirectoryError,
+    mixnet::{self, SharedMixnetClient},
+    routing::DEFAULT_TUN_MTU,
+    vpn::{MixnetClientConfig, MIXNET_CLIENT_STARTUP_TIMEOUT_SECS},
+};
+
+const SHUTDOWN_TIMER_SECS: u64 = 10;
+// How long the connection monitor has to keep reporting the gateways as down before we give up
+const CONNECTION_LOST_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug)]
+pub struct ProxyConfig {
+    /// The address the SOCKS5 proxy listens on.
+    pub listen: SocketAddr,
+
+    /// Allow listening on an address that isn't loopback. The proxy has no authentication, so
+    /// anyone who can reach it can use the tunnel.
+    pub allow_remote_clients: bool,
+
+    pub mixnet_client_config: MixnetClientConfig,
+
+    /// Path to the data directory, where keys reside.
+    pub data_path: Option<PathBuf>,
+
+    /// Gateway configuration
+    pub gateway_config: nym_gateway_directory::Config,
+
+    /// Mixnet public ID of the entry gateway.
+    pub entry_point: EntryPoint,
+
+    /// Mixnet recipient address.
+    pub exit_point: ExitPoint,
+
+    /// The IP addresses to ask the exit for.
+    pub nym_ips: Option<IpPair>,
+
+    /// The MTU of the packets sent to the exit.
+    pub nym_mtu: Option<u16>,
+
+    /// The DNS servers the domain names in the requests are resolved with, through the mixnet.
+    pub dns: DnsConfig,
+
+    /// The user agent to use for HTTP requests. This includes client name, version, platform and
+    /// git commit hash.
+    pub user_agent: Option<UserAgent>,
+
+    /// Go through two WireGuard hops run in userspace instead of the mixnet. Only supported on
+    /// linux.
+    pub wireguard: bool,
+}
+
+// Run the proxy until `shutdown` completes, or the connection through the mixnet is lost
+pub async fn run_proxy(
+    config: ProxyConfig,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), ProxyError> {
+    if config.wireguard {
+        return run_wireguard_proxy(config, shutdown).await;
+    }
+
+    let user_agent = config.user_agent.clone().unwrap_or_else(|| {
+        warn!("No user agent provided, using default");
+        nym_bin_common::bin_info_local_vergen!().into()
+    });
+    let gateway_directory_client = GatewayClient::new(config.gateway_config.clone(), user_agent)
+        .map_err(
+            |err| GatewayDirectoryError::FailedtoSetupGatewayDirectoryClient {
+                config: Box::new(config.gateway_config.clone()),
+                source: err,
+            },
+        )?;
+    let selected_gateways = crate::tunnel_setup::select_gateways_for_mode(
+        &gateway_directory_client,
+        true,
+        0,
+        &config.entry_point,
+        &config.exit_point,
+        &[],
+    )
+    .await?;
+    let exit_ipr =
+        selected_gateways
+            .exit
+            .ipr_address
+            .ok_or_else(|| ProxyError::MissingIpPacketRouter {
+                gateway_id: selected_gateways.exit.identity().to_base58_string(),
+            })?;
+
+    // Fail early, before connecting, when the address is taken
+    let listener = bind_listener(config.listen, config.allow_remote_clients).await?;
+
+    let mut task_manager = TaskManager::new(SHUTDOWN_TIMER_SECS).named("nym_vpn_proxy");
+    info!(
+        "Connecting to mixnet gateway: {}",
+        selected_gateways.entry.identity()
+    );
+    let mixnet_client = timeout(
+        Duration::from_secs(MIXNET_CLIENT_STARTUP_TIMEOUT_SECS),
+        mixnet::setup_mixnet_client(
+            selected_gateways.entry.identity(),
+            &config.data_path,
+            task_manager.subscribe_named("mixnet_client_main"),
+            config.mixnet_client_config.clone(),
+        ),
+    )
+    .await
+    .map_err(|_| ProxyError::StartMixnetClientTimeout(MIXNET_CLIENT_STARTUP_TIMEOUT_SECS))??;
+
+    let our_ips = match connect_to_exit(&mixnet_client, &config, exit_ipr.0).await {
+        Ok(our_ips) => our_ips,
+        Err(err) => {
+            mixnet_client.disconnect().await;
+            return Err(err);
+        }
+    };
+
+    // The other probes use the sockets of the system, which don't go through the proxy
+    let mut connection_monitor_config = ConnectionMonitorConfig::default();
+    connection_monitor_config
+        .targets
+        .retain(ProbeTarget::is_icmp);
+    let connection_monitor = ConnectionMonitorTask::setup(connection_monitor_config);
+    let mixnet_client_sender = mixnet_client.split_sender().await;
+    let nym_address = mixnet_client.nym_address().await;
+
+    let stack = NetStack::new(
+        &[our_ips.ipv4.into(), our_ips.ipv6.into()],
+        config.nym_mtu.unwrap_or(DEFAULT_TUN_MTU),
+    );
+    let stack_handle = stack.handle();
+    let pipe = MixnetPipe::new(
+        mixnet_client,
+        exit_ipr.0,
+        config
+            .mixnet_client_config
+            .traffic_profile
+            .packet_buffer_timeout(),
+        our_ips,
+        &connection_monitor,
+    )
+    .await;
+    let mut stack_task = stack.start(pipe, task_manager.subscribe_named("proxy_network_stack"));
+    let resolver = TunnelResolver::new(stack_handle.clone(), config.dns.servers_or_default());
+    let server = Socks5Server::new(listener, stack_handle, resolver);
+    tokio::spawn(server.run(task_manager.subscribe_named("socks5_server")));
+
+    let (_exit_ipr_tx, exit_ipr_rx) = watch::channel(exit_ipr.0);
+    connection_monitor.start(
+        mixnet_client_sender,
+        nym_address,
+        our_ips,
+        exit_ipr_rx,
+        &task_manager,
+    );
+    let (task_status_tx, mut task_status_rx) = mpsc::channel(128);
+    task_manager
+        .start_status_listener(task_status_tx, TaskStatus::Ready)
+        .await;
+
+    tokio::pin!(shutdown);
+    // Since when the connection monitor has been reporting the gateways as down
+    let mut failing_since = None;
+    let result = loop {
+        tokio::select! {
+            _ = &mut shutdown => break Ok(()),
+            _ = &mut stack_task => {
+                error!("The proxy network stack stopped unexpectedly");
+                break Err(ProxyError::TunnelStopped);
+            }
+            Some(status) = task_status_rx.next() => {
+                let Some(status) = status.downcast_ref::<ConnectionMonitorStatus>() else {
+                    continue;
+                };
+                match status {
+                    ConnectionMonitorStatus::EntryGatewayDown
+                    | ConnectionMonitorStatus::ExitGatewayDownIpv4
+                    | ConnectionMonitorStatus::ExitGatewayRoutingErrorIpv4 => {
+                        let failing_since = *failing_since.get_or_insert_with(Instant::now);
+                        if failing_since.elapsed() >= CONNECTION_LOST_TIMEOUT {
+                            error!("The connection through the mixnet is lost: {status}");
+                            break Err(ProxyError::ConnectionLost);
+                        }
+                    }
+                    ConnectionMonitorStatus::ConnectedIpv4 => failing_since = None,
+                    _ => {}
+                }
+            }
+        }
+    };
+
+    info!("Sending shutdown signal");
+    task_manager.signal_shutdown().ok();
+    info!("Waiting for tasks to finish... (Press ctrl-c to force)");
+    task_manager.wait_for_shutdown().await;
+    result
+}
+
+#[cfg(target_os = "linux")]
+async fn run_wireguard_proxy(
+    config: ProxyConfig,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), ProxyError> {
+    let listener = bind_listener(config.listen, config.allow_remote_clients).await?;
+
+    let shutdown_token = CancellationToken::new();
+    let runner = WgTunnelRunner::new_proxy(config, listener, shutdown_token.clone())?.start();
+    tokio::pin!(runner, shutdown);
+    tokio::select! {
+        result = &mut runner => return Ok(result?),
+        _ = &mut shutdown => {}
+    }
+    shutdown_token.cancel();
+    Ok(runner.await?)
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn run_wireguard_proxy(
+    _config: ProxyConfig,
+    _shutdown: impl Future<Output = ()>,
+) -> Result<(), ProxyError> {
+    Err(ProxyError::WireguardNotSupported)
+}
+
+async fn bind_listener(
+    address: SocketAddr,
+    allow_remote_clients: bool,
+) -> Result<TcpListener, ProxyError> {
+    if !address.ip().to_canonical().is_loopback() {
+        if !allow_remote_clients {
+            return Err(ProxyError::RemoteClientsNotAllowed { address });
+        }
+        warn!("The SOCKS5 proxy has no authentication, anyone who can reach {address} can use it");
+    }
+    TcpListener::bind(address)
+        .await
+        .map_err(|source| ProxyError::FailedToListen { address, source })
+}
+
+async fn connect_to_exit(
+    mixnet_client: &SharedMixnetClient,
+    config: &ProxyConfig,
+    exit_ipr: nym_gateway_directory::Recipient,
+) -> Result<IpPair, ProxyError> {
+    let nym_address = mixnet_client.nym_address().await;
+    info!(
+        "Successfully connected to entry gateway: {}",
+        nym_address.gateway()
+    );
+
+    info!("Sending mixnet ping to ourselves to verify mixnet connection");
+    nym_connection_monitor::self_ping_and_wait(nym_address, mixnet_client.inner())
+        .await
+        .map_err(mixnet::MixnetError::from)?;
+    info!("Successfully mixnet pinged ourselves");
+
+    let mut ipr_client = IprClientConnect::new_from_inner(mixnet_client.inner()).await;
+    let our_ips = ipr_client
+        .connect(exit_ipr, config.nym_ips)
+        .await
+        .map_err(ProxyError::FailedToConnectToIpPacketRouter)?;
+    info!("Successfully connected to exit gateway");
+    info!("Using proxy IP addresses: {our_ips}");
+    Ok(our_ips)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn only_listen_beyond_loopback_when_allowed() {
+        let any: SocketAddr = "0.0.0.0:0".parse().unwrap();
+        assert!(matches!(
+            bind_listener(any, false).await,
+            Err(ProxyError::RemoteClientsNotAllowed { .. })
+        ));
+        assert!(bind_listener(any, true).await.is_ok());
+
+        for loopback in ["127.0.0.1:0", "[::ffff:127.0.0.1]:0"] {
+            let loopback: SocketAddr = loopback.parse().unwrap();
+            assert!(!matches!(
+                bind_listener(loopback, false).await,
+                Err(ProxyError::RemoteClientsNotAllowed { .. })
+            ));
+        }
+    }
+}
@@ -0,0 +1,180 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use hickory_resolver::proto::{
+    op::{Message, MessageType, OpCode, Query},
+    rr::{Name, RData, RecordType},
+};
+use tracing::debug;
+
+use super::stack::{StackHandle, UdpConnection};
+
+const DNS_PORT: u16 = 53;
+// Generous, since the queries go through the mixnet
+const QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+// The answers are kept for their TTL, within these bounds
+const MIN_CACHE_TTL: Duration = Duration::from_secs(30);
+const MAX_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+const MAX_CACHE_ENTRIES: usize = 1024;
+
+// Looks up the domain names in the requests through the tunnel, so that the queries don't leak
+// to the local resolver
+#[derive(Clone)]
+pub(crate) struct TunnelResolver {
+    stack: StackHandle,
+    servers: Vec<IpAddr>,
+    // Every lookup is a round trip through the mixnet, so the answers are shared by all the
+    // connections
+    cache: Arc<Mutex<DnsCache>>,
+}
+
+impl TunnelResolver {
+    pub(crate) fn new(stack: StackHandle, servers: Vec<IpAddr>) -> Self {
+        TunnelResolver {
+            stack,
+            servers,
+            cache: Default::default(),
+        }
+    }
+
+    pub(crate) async fn lookup(&self, domain: &str) -> Option<IpAddr> {
+        if let Ok(ip) = domain.parse() {
+            return Some(ip);
+        }
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        if let Some(ip) = self.cache.lock().unwrap().get(&domain, Instant::now()) {
+            return Some(ip);
+        }
+        let (ip, ttl) = self.resolve(&domain).await?;
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(domain, ip, ttl, Instant::now());
+        Some(ip)
+    }
+
+    async fn resolve(&self, domain: &str) -> Option<(IpAddr, Duration)> {
+        let name = Name::from_ascii(domain).ok()?;
+        let mut connection = self.stack.udp_bind().await.ok()?;
+
+        // The first server that answers is the one we go with, even if the name doesn't exist
+        for server in &self.servers {
+            let server = SocketAddr::new(*server, DNS_PORT);
+            let Some(response) = query(&mut connection, server, &name, RecordType::A).await else {
+                debug!("No answer from {server} for {domain}");
+                continue;
+            };
+            if let Some(ip) = first_address(&response) {
+                return Some(ip);
+            }
+            let response = query(&mut connection, server, &name, RecordType::AAAA).await?;
+            return first_address(&response);
+        }
+        None
+    }
+}
+
+async fn query(
+    connection: &mut UdpConnection,
+    server: SocketAddr,
+    name: &Name,
+    record_type: RecordType,
+) -> Option<Message> {
+    let id = rand::random();
+    let mut query = Message::new();
+    query
+        .set_id(id)
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .set_recursion_desired(true)
+        .add_query(Query::query(name.clone(), record_type));
+    let query = query.to_vec().ok()?;
+    connection.send_to(Bytes::from(query), server).await.ok()?;
+
+    tokio::time::timeout(QUERY_TIMEOUT, async {
+        while let Some((source, datagram)) = connection.recv_from().await {
+            if source != server {
+                continue;
+            }
+            match Message::from_vec(&datagram) {
+                Ok(response) if response.id() == id => return Some(response),
+                _ => {}
+            }
+        }
+        None
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+// The first address of the answer, and how long it is valid for
+fn first_address(response: &Message) -> Option<(IpAddr, Duration)> {
+    response.answers().iter().find_map(|record| {
+        let ip = match record.data() {
+            Some(RData::A(a)) => IpAddr::V4(a.0),
+            Some(RData::AAAA(aaaa)) => IpAddr::V6(aaaa.0),
+            _ => return None,
+        };
+        Some((ip, Duration::from_secs(u64::from(record.ttl()))))
+    })
+}
+
+// Addresses by domain, along with when they expire. Names that don't resolve aren't kept, so that
+// a failed lookup is retried on the next connection.
+#[derive(Default)]
+struct DnsCache {
+    entries: HashMap<String, (IpAddr, Instant)>,
+}
+
+impl DnsCache {
+    fn get(&self, domain: &str, now: Instant) -> Option<IpAddr> {
+        self.entries
+            .get(domain)
+            .filter(|(_, expires)| *expires > now)
+            .map(|(ip, _)| *ip)
+    }
+
+    fn insert(&mut self, domain: String, ip: IpAddr, ttl: Duration, now: Instant) {
+        if self.entries.len() >= MAX_CACHE_ENTRIES {
+            self.entries.retain(|_, (_, expires)| *expires > now);
+        }
+        if self.entries.len() >= MAX_CACHE_ENTRIES && !self.entries.contains_key(&domain) {
+            return;
+        }
+        let ttl = ttl.clamp(MIN_CACHE_TTL, MAX_CACHE_TTL);
+        self.entries.insert(domain, (ip, now + ttl));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cached_addresses_expire_within_bounds() {
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let now = Instant::now();
+        let mut cache = DnsCache::default();
+
+        // A short TTL is stretched to the minimum
+        cache.insert("short.test".to_string(), ip, Duration::ZERO, now);
+        assert_eq!(cache.get("short.test", now + MIN_CACHE_TTL / 2), Some(ip));
+        assert_eq!(cache.get("short.test", now + MIN_CACHE_TTL), None);
+
+        // A long one is cut to the maximum
+        cache.insert("long.test".to_string(), ip, Duration::from_secs(86400), now);
+        assert_eq!(cache.get("long.test", now + MAX_CACHE_TTL / 2), Some(ip));
+        assert_eq!(cache.get("long.test", now + MAX_CACHE_TTL), None);
+
+        assert_eq!(cache.get("other.test", now), None);
+    }
+}
@@ -0,0 +1,454 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+// A SOCKS5 server (RFC 1928) without authentication, supporting CONNECT and UDP ASSOCIATE
+
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use bytes::Bytes;
+use futures::{stream::FuturesUnordered, StreamExt};
+use nym_task::TaskClient;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+};
+use tracing::{debug, error, info, trace};
+
+use super::{
+    error::StackError,
+    resolver::TunnelResolver,
+    stack::{StackHandle, UdpConnection},
+};
+
+const SOCKS_VERSION: u8 = 0x05;
+const NO_AUTHENTICATION: u8 = 0x00;
+const NO_ACCEPTABLE_METHODS: u8 = 0xff;
+
+const CMD_CONNECT: u8 = 0x01;
+const CMD_UDP_ASSOCIATE: u8 = 0x03;
+
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+const MAX_DATAGRAM_SIZE: usize = 65535;
+
+#[derive(Clone, Copy, Debug)]
+enum Reply {
+    Succeeded = 0x00,
+    GeneralFailure = 0x01,
+    HostUnreachable = 0x04,
+    ConnectionRefused = 0x05,
+    TtlExpired = 0x06,
+    CommandNotSupported = 0x07,
+    AddressTypeNotSupported = 0x08,
+}
+
+impl From<&StackError> for Reply {
+    fn from(err: &StackError) -> Self {
+        match err {
+            StackError::ConnectionRefused => Reply::ConnectionRefused,
+            StackError::ConnectTimeout => Reply::TtlExpired,
+            StackError::Connect(_) => Reply::HostUnreachable,
+            StackError::Stopped | StackError::Bind(_) => Reply::GeneralFailure,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+enum SocksError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+
+    #[error("unsupported SOCKS version {0}")]
+    Version(u8),
+
+    #[error("the client doesn't support connecting without authentication")]
+    NoAcceptableMethods,
+
+    #[error("unsupported command {0}")]
+    Command(u8),
+
+    #[error("unsupported address type {0}")]
+    AddressType(u8),
+
+    #[error("failed to resolve {0}")]
+    Resolve(String),
+
+    #[error("{0}")]
+    Stack(#[from] StackError),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum TargetAddr {
+    Ip(SocketAddr),
+    Domain(String, u16),
+}
+
+impl TargetAddr {
+    async fn resolve(&self, resolver: &TunnelResolver) -> Result<SocketAddr, SocksError> {
+        match self {
+            TargetAddr::Ip(address) => Ok(*address),
+            TargetAddr::Domain(domain, port) => resolver
+                .lookup(domain)
+                .await
+                .map(|ip| SocketAddr::new(ip, *port))
+                .ok_or_else(|| SocksError::Resolve(domain.clone())),
+        }
+    }
+}
+
+pub(crate) struct Socks5Server {
+    listener: TcpListener,
+    stack: StackHandle,
+    resolver: TunnelResolver,
+}
+
+impl Socks5Server {
+    pub(crate) fn new(listener: TcpListener, stack: StackHandle, resolver: TunnelResolver) -> Self {
+        Socks5Server {
+            listener,
+            stack,
+            resolver,
+        }
+    }
+
+    pub(crate) async fn run(self, mut task_client: TaskClient) {
+        if let Ok(address) = self.listener.local_addr() {
+            info!("SOCKS5 proxy listening on {address}");
+        }
+        while !task_client.is_shutdown() {
+            tokio::select! {
+                _ = task_client.recv() => {
+                    trace!("SOCKS5 server: Received shutdown");
+                    break;
+                }
+                accepted = self.listener.accept() => {
+                    let (stream, peer) = match accepted {
+                        Ok(accepted) => accepted,
+                        Err(err) => {
+                            error!("Failed to accept SOCKS5 connection: {err}");
+                            continue;
+                        }
+                    };
+                    let stack = self.stack.clone();
+                    let resolver = self.resolver.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = handle_client(stream, peer, stack, resolver).await {
+                            debug!("SOCKS5 connection from {peer}: {err}");
+                        }
+                    });
+                }
+            }
+        }
+        debug!("SOCKS5 server: Exiting");
+    }
+}
+
+async fn handle_client(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    stack: StackHandle,
+    resolver: TunnelResolver,
+) -> Result<(), SocksError> {
+    let version = stream.read_u8().await?;
+    if version != SOCKS_VERSION {
+        return Err(SocksError::Version(version));
+    }
+    let mut methods = vec![0; usize::from(stream.read_u8().await?)];
+    stream.read_exact(&mut methods).await?;
+    if !methods.contains(&NO_AUTHENTICATION) {
+        stream
+            .write_all(&[SOCKS_VERSION, NO_ACCEPTABLE_METHODS])
+            .await?;
+        return Err(SocksError::NoAcceptableMethods);
+    }
+    stream
+        .write_all(&[SOCKS_VERSION, NO_AUTHENTICATION])
+        .await?;
+
+    let mut header = [0u8; 3];
+    stream.read_exact(&mut header).await?;
+    let [version, command, _reserved] = header;
+    if version != SOCKS_VERSION {
+        return Err(SocksError::Version(version));
+    }
+    let target = match read_target(&mut stream).await {
+        Ok(target) => target,
+        Err(err @ SocksError::AddressType(_)) => {
+            send_reply(&mut stream, Reply::AddressTypeNotSupported, None).await?;
+            return Err(err);
+        }
+        Err(err) => return Err(err),
+    };
+
+    match command {
+        CMD_CONNECT => connect(stream, target, stack, resolver).await,
+        CMD_UDP_ASSOCIATE => udp_associate(stream, peer, stack, resolver).await,
+        _ => {
+            send_reply(&mut stream, Reply::CommandNotSupported, None).await?;
+            Err(SocksError::Command(command))
+        }
+    }
+}
+
+async fn connect(
+    mut stream: TcpStream,
+    target: TargetAddr,
+    stack: StackHandle,
+    resolver: TunnelResolver,
+) -> Result<(), SocksError> {
+    let remote = match target.resolve(&resolver).await {
+        Ok(remote) => remote,
+        Err(err) => {
+            send_reply(&mut stream, Reply::HostUnreachable, None).await?;
+            return Err(err);
+        }
+    };
+    let connection = match stack.tcp_connect(remote).await {
+        Ok(connection) => connection,
+        Err(err) => {
+            send_reply(&mut stream, Reply::from(&err), None).await?;
+            return Err(err.into());
+        }
+    };
+    debug!("Connected to {remote} through the tunnel");
+    send_reply(&mut stream, Reply::Succeeded, None).await?;
+    connection.splice(stream).await;
+    Ok(())
+}
+
+async fn udp_associate(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    stack: StackHandle,
+    resolver: TunnelResolver,
+) -> Result<(), SocksError> {
+    // The client sends its datagrams to a socket next to the one it reached us on
+    let local = UdpSocket::bind(SocketAddr::new(stream.local_addr()?.ip(), 0)).await?;
+    let mut tunnel = match stack.udp_bind().await {
+        Ok(tunnel) => tunnel,
+        Err(err) => {
+            send_reply(&mut stream, Reply::from(&err), None).await?;
+            return Err(err.into());
+        }
+    };
+    send_reply(&mut stream, Reply::Succeeded, Some(local.local_addr()?)).await?;
+
+    let mut client = None;
+    let mut resolved = HashMap::new();
+    // Domains are looked up next to the loop, so that one slow lookup doesn't hold up the other
+    // datagrams. Datagrams to a domain that is still being looked up are dropped.
+    let mut lookups = FuturesUnordered::new();
+    let mut pending = HashSet::new();
+    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+    let mut control = [0u8; 1];
+    loop {
+        tokio::select! {
+            // The association lasts as long as the connection it was asked for on
+            read = stream.read(&mut control) => {
+                if matches!(read, Ok(0) | Err(_)) {
+                    break;
+                }
+            }
+            received = local.recv_from(&mut buf) => {
+                // Errors here are about single datagrams, e.g. an ICMP error for an earlier one
+                // being reported on the next receive, which isn't a reason to drop the association
+                let (len, source) = match received {
+                    Ok(received) => received,
+                    Err(err) => {
+                        debug!("Failed to receive SOCKS5 datagram: {err}");
+                        continue;
+                    }
+                };
+                // Only the client that asked for the association gets to use it
+                if source.ip() != peer.ip() {
+                    continue;
+                }
+                client = Some(source);
+                let Some((target, payload)) = parse_udp_datagram(&buf[..len]) else {
+                    trace!("Dropping malformed or fragmented SOCKS5 datagram");
+                    continue;
+                };
+                let remote = match target {
+                    TargetAddr::Ip(remote) => remote,
+                    _ => match resolved.get(&target) {
+                        Some(remote) => *remote,
+                        None => {
+                            if pending.insert(target.clone()) {
+                                let payload = Bytes::copy_from_slice(payload);
+                                let resolver = &resolver;
+                                lookups.push(async move {
+                                    let remote = target.resolve(resolver).await;
+                                    (target, remote, payload)
+                                });
+                            } else {
+                                trace!("Dropping datagram to {target:?} while it's looked up");
+                            }
+                            continue;
+                        }
+                    },
+                };
+                tunnel.send_to(Bytes::copy_from_slice(payload), remote).await?;
+            }
+            Some(lookup) = lookups.next(), if !lookups.is_empty() => {
+                let (target, remote, payload) = lookup;
+                pending.remove(&target);
+                match remote {
+                    Ok(remote) => {
+                        resolved.insert(target, remote);
+                        tunnel.send_to(payload, remote).await?;
+                    }
+                    Err(err) => debug!("Dropping datagram: {err}"),
+                }
+            }
+            received = tunnel.recv_from() => {
+                let Some((source, payload)) = received else {
+                    return Err(StackError::Stopped.into());
+                };
+                if let Some(client) = client {
+                    let datagram = encode_udp_datagram(source, &payload);
+                    if let Err(err) = local.send_to(&datagram, client).await {
+                        debug!("Failed to send SOCKS5 datagram to {client}: {err}");
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn read_target(stream: &mut TcpStream) -> Result<TargetAddr, SocksError> {
+    let address_type = stream.read_u8().await?;
+    let target = match address_type {
+        ATYP_IPV4 => {
+            let mut octets = [0u8; 4];
+            stream.read_exact(&mut octets).await?;
+            let port = stream.read_u16().await?;
+            TargetAddr::Ip(SocketAddr::new(Ipv4Addr::from(octets).into(), port))
+        }
+        ATYP_IPV6 => {
+            let mut octets = [0u8; 16];
+            stream.read_exact(&mut octets).await?;
+            let port = stream.read_u16().await?;
+            TargetAddr::Ip(SocketAddr::new(Ipv6Addr::from(octets).into(), port))
+        }
+        ATYP_DOMAIN => {
+            let mut domain = vec![0; usize::from(stream.read_u8().await?)];
+            stream.read_exact(&mut domain).await?;
+            let port = stream.read_u16().await?;
+            TargetAddr::Domain(String::from_utf8_lossy(&domain).into_owned(), port)
+        }
+        _ => return Err(SocksError::AddressType(address_type)),
+    };
+    Ok(target)
+}
+
+async fn send_reply(
+    stream: &mut TcpStream,
+    reply: Reply,
+    bound: Option<SocketAddr>,
+) -> io::Result<()> {
+    let bound = bound.unwrap_or_else(|| SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0));
+    let mut message = vec![SOCKS_VERSION, reply as u8, 0x00];
+    encode_address(bound, &mut message);
+    stream.write_all(&message).await
+}
+
+fn encode_address(address: SocketAddr, buf: &mut Vec<u8>) {
+    match address.ip() {
+        IpAddr::V4(ip) => {
+            buf.push(ATYP_IPV4);
+            buf.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.push(ATYP_IPV6);
+            buf.extend_from_slice(&ip.octets());
+        }
+    }
+    buf.extend_from_slice(&address.port().to_be_bytes());
+}
+
+// The datagrams are prefixed with RSV(2) FRAG(1) ATYP DST.ADDR DST.PORT. Fragments are not
+// supported, as allowed by the RFC.
+fn parse_udp_datagram(datagram: &[u8]) -> Option<(TargetAddr, &[u8])> {
+    let (header, rest) = split_at(datagram, 4)?;
+    let [_, _, fragment, address_type] = header.try_into().ok()?;
+    if fragment != 0 {
+        return None;
+    }
+    let (target, rest) = match address_type {
+        ATYP_IPV4 => {
+            let (octets, rest) = split_at(rest, 4)?;
+            let octets: [u8; 4] = octets.try_into().ok()?;
+            (IpAddr::from(octets), rest)
+        }
+        ATYP_IPV6 => {
+            let (octets, rest) = split_at(rest, 16)?;
+            let octets: [u8; 16] = octets.try_into().ok()?;
+            (IpAddr::from(octets), rest)
+        }
+        ATYP_DOMAIN => {
+            let (len, rest) = rest.split_first()?;
+            let (domain, rest) = split_at(rest, usize::from(*len))?;
+            let (port, payload) = split_at(rest, 2)?;
+            let port = u16::from_be_bytes(port.try_into().ok()?);
+            let domain = String::from_utf8_lossy(domain).into_owned();
+            return Some((TargetAddr::Domain(domain, port), payload));
+        }
+        _ => return None,
+    };
+    let (port, payload) = split_at(rest, 2)?;
+    let port = u16::from_be_bytes(port.try_into().ok()?);
+    Some((TargetAddr::Ip(SocketAddr::new(target, port)), payload))
+}
+
+fn split_at(buf: &[u8], mid: usize) -> Option<(&[u8], &[u8])> {
+    (buf.len() >= mid).then(|| buf.split_at(mid))
+}
+
+fn encode_udp_datagram(source: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let mut datagram = vec![0x00, 0x00, 0x00];
+    encode_address(source, &mut datagram);
+    datagram.extend_from_slice(payload);
+    datagram
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn udp_datagrams_round_trip() {
+        let source: SocketAddr = "[2001:db8::1]:53".parse().unwrap();
+        let datagram = encode_udp_datagram(source, b"payload");
+        assert_eq!(
+            parse_udp_datagram(&datagram),
+            Some((TargetAddr::Ip(source), &b"payload"[..]))
+        );
+
+        let mut datagram = vec![0x00, 0x00, 0x00, ATYP_DOMAIN, 11];
+        datagram.extend_from_slice(b"nymtech.net");
+        datagram.extend_from_slice(&443u16.to_be_bytes());
+        datagram.extend_from_slice(b"payload");
+        assert_eq!(
+            parse_udp_datagram(&datagram),
+            Some((
+                TargetAddr::Domain("nymtech.net".to_string(), 443),
+                &b"payload"[..]
+            ))
+        );
+
+        // Fragments and truncated headers are dropped
+        datagram[2] = 1;
+        assert_eq!(parse_udp_datagram(&datagram), None);
+        assert_eq!(
+            parse_udp_datagram(&[0x00, 0x00, 0x00, ATYP_IPV4, 1, 1]),
+            None
+        );
+    }
+}
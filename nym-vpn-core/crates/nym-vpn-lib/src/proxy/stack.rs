@@ -0,0 +1,849 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::VecDeque,
+    net::{IpAddr, SocketAddr},
+    ops::RangeInclusive,
+    sync::Arc,
+    time::Duration,
+};
+
+use bytes::{Buf, Bytes};
+use futures::{channel::mpsc::UnboundedSender, StreamExt};
+use nym_connection_monitor::{ConnectionMonitorTask, ConnectionStatusEvent};
+use nym_ip_packet_client::{IprListener, MixnetMessageOutcome};
+use nym_ip_packet_requests::{codec::MultiIpPacketCodec, IpPair};
+use nym_sdk::mixnet::{MixnetClient, MixnetClientSender, MixnetMessageSender, Recipient};
+use nym_task::TaskClient;
+use smoltcp::{
+    iface::{Config as InterfaceConfig, Interface, SocketHandle, SocketSet},
+    phy::{self, Device, DeviceCapabilities, Medium},
+    socket::{tcp, udp},
+    time::Instant,
+    wire::{HardwareAddress, IpAddress, IpCidr, IpEndpoint},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::{
+        mpsc::{self, error::TryRecvError, error::TrySendError},
+        oneshot, Notify, OwnedMutexGuard,
+    },
+};
+use tracing::{debug, error, info, trace};
+
+use super::error::StackError;
+use crate::mixnet::{check_for_icmp_beacon_reply, MessageCreator, SharedMixnetClient};
+
+const TCP_BUFFER_SIZE: usize = 64 * 1024;
+const UDP_PACKET_SLOTS: usize = 64;
+const UDP_BUFFER_SIZE: usize = 64 * 1024;
+const CHANNEL_SIZE: usize = 32;
+const COMMAND_CHANNEL_SIZE: usize = 64;
+const TCP_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+// The stack is polled at least this often, even when it doesn't ask for it
+const MAX_POLL_DELAY: Duration = Duration::from_secs(1);
+const EPHEMERAL_PORTS: RangeInclusive<u16> = 49152..=65535;
+
+enum StackCommand {
+    TcpConnect {
+        remote: SocketAddr,
+        reply: oneshot::Sender<Result<TcpConnection, StackError>>,
+    },
+    UdpBind {
+        reply: oneshot::Sender<Result<UdpConnection, StackError>>,
+    },
+}
+
+// For opening connections through the stack, from any task
+#[derive(Clone)]
+pub(crate) struct StackHandle {
+    commands: mpsc::Sender<StackCommand>,
+}
+
+impl StackHandle {
+    pub(crate) async fn tcp_connect(
+        &self,
+        remote: SocketAddr,
+    ) -> Result<TcpConnection, StackError> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.commands
+            .send(StackCommand::TcpConnect { remote, reply })
+            .await
+            .map_err(|_| StackError::Stopped)?;
+        // Dropping the reply receiver on timeout aborts the connection attempt
+        tokio::time::timeout(TCP_CONNECT_TIMEOUT, reply_rx)
+            .await
+            .map_err(|_| StackError::ConnectTimeout)?
+            .map_err(|_| StackError::Stopped)?
+    }
+
+    pub(crate) async fn udp_bind(&self) -> Result<UdpConnection, StackError> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.commands
+            .send(StackCommand::UdpBind { reply })
+            .await
+            .map_err(|_| StackError::Stopped)?;
+        reply_rx.await.map_err(|_| StackError::Stopped)?
+    }
+}
+
+// Our end of a TCP connection through the tunnel
+pub(crate) struct TcpConnection {
+    tx: mpsc::Sender<Bytes>,
+    rx: mpsc::Receiver<Bytes>,
+    wake: Arc<Notify>,
+}
+
+impl TcpConnection {
+    // Copy data both ways between a local stream and the connection, until both directions are
+    // closed
+    pub(crate) async fn splice(self, stream: TcpStream) {
+        let TcpConnection { tx, mut rx, wake } = self;
+        let (mut reader, mut writer) = stream.into_split();
+
+        let upload_wake = wake.clone();
+        let upload = async move {
+            let mut buf = vec![0; TCP_BUFFER_SIZE];
+            while let Ok(len) = reader.read(&mut buf).await {
+                if len == 0 || tx.send(Bytes::copy_from_slice(&buf[..len])).await.is_err() {
+                    break;
+                }
+                upload_wake.notify_one();
+            }
+            // Dropping the sender closes our side of the connection
+            drop(tx);
+            upload_wake.notify_one();
+        };
+        let download = async move {
+            while let Some(data) = rx.recv().await {
+                // There is room in the channel again
+                wake.notify_one();
+                if writer.write_all(&data).await.is_err() {
+                    break;
+                }
+            }
+            writer.shutdown().await.ok();
+        };
+        tokio::join!(upload, download);
+    }
+}
+
+// Our end of a UDP socket through the tunnel
+pub(crate) struct UdpConnection {
+    tx: mpsc::Sender<(SocketAddr, Bytes)>,
+    rx: mpsc::Receiver<(SocketAddr, Bytes)>,
+    wake: Arc<Notify>,
+}
+
+impl UdpConnection {
+    pub(crate) async fn send_to(&self, data: Bytes, remote: SocketAddr) -> Result<(), StackError> {
+        self.tx
+            .send((remote, data))
+            .await
+            .map_err(|_| StackError::Stopped)?;
+        self.wake.notify_one();
+        Ok(())
+    }
+
+    pub(crate) async fn recv_from(&mut self) -> Option<(SocketAddr, Bytes)> {
+        let datagram = self.rx.recv().await;
+        self.wake.notify_one();
+        datagram
+    }
+}
+
+// The device of the stack is a pair of queues, the other ends of which are the mixnet
+struct PacketQueues {
+    // Packets from the exit
+    rx: VecDeque<Bytes>,
+    // Packets for the exit
+    tx: VecDeque<Vec<u8>>,
+    mtu: usize,
+}
+
+struct RxToken(Vec<u8>);
+
+impl phy::RxToken for RxToken {
+    fn consume<R, F>(mut self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        f(&mut self.0)
+    }
+}
+
+struct TxToken<'a>(&'a mut VecDeque<Vec<u8>>);
+
+impl phy::TxToken for TxToken<'_> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let mut packet = vec![0; len];
+        let result = f(&mut packet);
+        self.0.push_back(packet);
+        result
+    }
+}
+
+impl Device for PacketQueues {
+    type RxToken<'a>
+        = RxToken
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = TxToken<'a>
+    where
+        Self: 'a;
+
+    fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let packet = self.rx.pop_front()?;
+        Some((RxToken(packet.to_vec()), TxToken(&mut self.tx)))
+    }
+
+    fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        Some(TxToken(&mut self.tx))
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut capabilities = DeviceCapabilities::default();
+        capabilities.medium = Medium::Ip;
+        capabilities.max_transmission_unit = self.mtu;
+        capabilities
+    }
+}
+
+struct TcpEntry {
+    handle: SocketHandle,
+    // Handed over once the handshake completes
+    connecting: Option<(
+        oneshot::Sender<Result<TcpConnection, StackError>>,
+        TcpConnection,
+    )>,
+    // Dropped when the remote closes its side
+    to_app: Option<mpsc::Sender<Bytes>>,
+    from_app: mpsc::Receiver<Bytes>,
+    // What the socket didn't take yet of the last chunk from the app
+    pending: Option<Bytes>,
+    closing: bool,
+}
+
+struct UdpEntry {
+    handle: SocketHandle,
+    to_app: mpsc::Sender<(SocketAddr, Bytes)>,
+    from_app: mpsc::Receiver<(SocketAddr, Bytes)>,
+}
+
+// Where the packets of the stack go to, and come from
+#[async_trait::async_trait]
+pub(crate) trait PacketPipe: Send {
+    async fn send_packet(&mut self, packet: Vec<u8>);
+
+    // The next packets for the stack, or None once the other end is gone
+    async fn recv_packets(&mut self) -> Option<Vec<Bytes>>;
+}
+
+// A userspace TCP/IP stack with the addresses the exit gave us, whose packets are exchanged with
+// the exit over the mixnet or a wireguard tunnel. This is what lets us proxy connections without
+// a tun device.
+pub(crate) struct NetStack {
+    iface: Interface,
+    device: PacketQueues,
+    sockets: SocketSet<'static>,
+    tcp_connections: Vec<TcpEntry>,
+    udp_connections: Vec<UdpEntry>,
+    next_port: u16,
+    wake: Arc<Notify>,
+    commands_tx: mpsc::Sender<StackCommand>,
+    commands: mpsc::Receiver<StackCommand>,
+}
+
+impl NetStack {
+    pub(crate) fn new(our_ips: &[IpAddr], mtu: u16) -> Self {
+        let mut device = PacketQueues {
+            rx: VecDeque::new(),
+            tx: VecDeque::new(),
+            mtu: usize::from(mtu),
+        };
+        let mut iface = Interface::new(
+            InterfaceConfig::new(HardwareAddress::Ip),
+            &mut device,
+            Instant::now(),
+        );
+        iface.update_ip_addrs(|addrs| {
+            for ip in our_ips {
+                let prefix_len = if ip.is_ipv4() { 32 } else { 128 };
+                addrs
+                    .push(IpCidr::new(IpAddress::from(*ip), prefix_len))
+                    .ok();
+            }
+        });
+        // Everything goes to the exit. The link is point to point, so the gateway is only there
+        // to have a route at all.
+        for ip in our_ips {
+            match ip {
+                IpAddr::V4(ipv4) => iface.routes_mut().add_default_ipv4_route(*ipv4).ok(),
+                IpAddr::V6(ipv6) => iface.routes_mut().add_default_ipv6_route(*ipv6).ok(),
+            };
+        }
+
+        let (commands_tx, commands) = mpsc::channel(COMMAND_CHANNEL_SIZE);
+        NetStack {
+            iface,
+            device,
+            sockets: SocketSet::new(Vec::new()),
+            tcp_connections: Vec::new(),
+            udp_connections: Vec::new(),
+            next_port: rand::random::<u16>() % (EPHEMERAL_PORTS.end() - EPHEMERAL_PORTS.start())
+                + EPHEMERAL_PORTS.start(),
+            wake: Arc::new(Notify::new()),
+            commands_tx,
+            commands,
+        }
+    }
+
+    pub(crate) fn handle(&self) -> StackHandle {
+        StackHandle {
+            commands: self.commands_tx.clone(),
+        }
+    }
+
+    pub(crate) fn start<P: PacketPipe + 'static>(
+        self,
+        pipe: P,
+        task_client: TaskClient,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(self.run(pipe, task_client))
+    }
+
+    async fn run<P: PacketPipe>(mut self, mut pipe: P, mut task_client: TaskClient) {
+        let wake = self.wake.clone();
+
+        info!("Proxy network stack is running");
+        while !task_client.is_shutdown() {
+            self.poll();
+            while let Some(packet) = self.device.tx.pop_front() {
+                pipe.send_packet(packet).await;
+            }
+            let delay = self
+                .iface
+                .poll_delay(Instant::now(), &self.sockets)
+                .map(Duration::from)
+                .unwrap_or(MAX_POLL_DELAY)
+                .min(MAX_POLL_DELAY);
+
+            tokio::select! {
+                _ = task_client.recv_with_delay() => {
+                    trace!("Proxy network stack: Received shutdown");
+                    break;
+                }
+                Some(command) = self.commands.recv() => self.handle_command(command),
+                _ = wake.notified() => {}
+                _ = tokio::time::sleep(delay) => {}
+                packets = pipe.recv_packets() => {
+                    let Some(packets) = packets else {
+                        error!("Proxy network stack: the tunnel to the exit is gone");
+                        break;
+                    };
+                    self.device.rx.extend(packets);
+                }
+            }
+        }
+        debug!("Proxy network stack: Exiting");
+    }
+
+    fn poll(&mut self) {
+        self.iface
+            .poll(Instant::now(), &mut self.device, &mut self.sockets);
+        self.process_tcp_connections();
+        self.process_udp_connections();
+        // Send what the connections handed to their sockets right away
+        self.iface
+            .poll(Instant::now(), &mut self.device, &mut self.sockets);
+    }
+
+    fn handle_command(&mut self, command: StackCommand) {
+        match command {
+            StackCommand::TcpConnect { remote, reply } => {
+                if let Err(err) = self.tcp_connect(remote, reply) {
+                    debug!("Failed to connect to {remote}: {err}");
+                }
+            }
+            StackCommand::UdpBind { reply } => {
+                let connection = self.udp_bind();
+                reply.send(connection).ok();
+            }
+        }
+    }
+
+    fn tcp_connect(
+        &mut self,
+        remote: SocketAddr,
+        reply: oneshot::Sender<Result<TcpConnection, StackError>>,
+    ) -> Result<(), StackError> {
+        let mut socket = tcp::Socket::new(
+            tcp::SocketBuffer::new(vec![0; TCP_BUFFER_SIZE]),
+            tcp::SocketBuffer::new(vec![0; TCP_BUFFER_SIZE]),
+        );
+        // The mixnet adds plenty of latency already
+        socket.set_nagle_enabled(false);
+        let local_port = self.next_port();
+        if let Err(err) = socket.connect(self.iface.context(), IpEndpoint::from(remote), local_port)
+        {
+            reply.send(Err(err.into())).ok();
+            return Err(err.into());
+        }
+        let handle = self.sockets.add(socket);
+
+        let (app_tx, from_app) = mpsc::channel(CHANNEL_SIZE);
+        let (to_app, app_rx) = mpsc::channel(CHANNEL_SIZE);
+        let connection = TcpConnection {
+            tx: app_tx,
+            rx: app_rx,
+            wake: self.wake.clone(),
+        };
+        self.tcp_connections.push(TcpEntry {
+            handle,
+            connecting: Some((reply, connection)),
+            to_app: Some(to_app),
+            from_app,
+            pending: None,
+            closing: false,
+        });
+        Ok(())
+    }
+
+    fn udp_bind(&mut self) -> Result<UdpConnection, StackError> {
+        let mut socket = udp::Socket::new(
+            udp::PacketBuffer::new(
+                vec![udp::PacketMetadata::EMPTY; UDP_PACKET_SLOTS],
+                vec![0; UDP_BUFFER_SIZE],
+            ),
+            udp::PacketBuffer::new(
+                vec![udp::PacketMetadata::EMPTY; UDP_PACKET_SLOTS],
+                vec![0; UDP_BUFFER_SIZE],
+            ),
+        );
+        socket.bind(self.next_port())?;
+        let handle = self.sockets.add(socket);
+
+        let (app_tx, from_app) = mpsc::channel(CHANNEL_SIZE);
+        let (to_app, app_rx) = mpsc::channel(CHANNEL_SIZE);
+        self.udp_connections.push(UdpEntry {
+            handle,
+            to_app,
+            from_app,
+        });
+        Ok(UdpConnection {
+            tx: app_tx,
+            rx: app_rx,
+            wake: self.wake.clone(),
+        })
+    }
+
+    fn next_port(&mut self) -> u16 {
+        let port = self.next_port;
+        self.next_port = if port == *EPHEMERAL_PORTS.end() {
+            *EPHEMERAL_PORTS.start()
+        } else {
+            port + 1
+        };
+        port
+    }
+
+    fn process_tcp_connections(&mut self) {
+        let sockets = &mut self.sockets;
+        self.tcp_connections.retain_mut(|entry| {
+            let socket = sockets.get_mut::<tcp::Socket>(entry.handle);
+            let keep = process_tcp_connection(entry, socket);
+            if !keep {
+                sockets.remove(entry.handle);
+            }
+            keep
+        });
+    }
+
+    fn process_udp_connections(&mut self) {
+        let sockets = &mut self.sockets;
+        self.udp_connections.retain_mut(|entry| {
+            let socket = sockets.get_mut::<udp::Socket>(entry.handle);
+            let keep = process_udp_connection(entry, socket);
+            if !keep {
+                sockets.remove(entry.handle);
+            }
+            keep
+        });
+    }
+}
+
+// Moves data between the socket and the app, returns whether the connection is still alive
+fn process_tcp_connection(entry: &mut TcpEntry, socket: &mut tcp::Socket<'_>) -> bool {
+    if let Some((reply, _)) = &entry.connecting {
+        match socket.state() {
+            tcp::State::SynSent | tcp::State::SynReceived => {
+                if reply.is_closed() {
+                    socket.abort();
+                    return false;
+                }
+                return true;
+            }
+            tcp::State::Established => {
+                let (reply, connection) = entry.connecting.take().unwrap();
+                if reply.send(Ok(connection)).is_err() {
+                    socket.abort();
+                    return false;
+                }
+            }
+            _ => {
+                let (reply, _) = entry.connecting.take().unwrap();
+                reply.send(Err(StackError::ConnectionRefused)).ok();
+                return false;
+            }
+        }
+    }
+
+    // From the tunnel to the app
+    if let Some(to_app) = &entry.to_app {
+        while socket.can_recv() {
+            let permit = match to_app.try_reserve() {
+                Ok(permit) => permit,
+                Err(TrySendError::Full(_)) => break,
+                Err(TrySendError::Closed(_)) => {
+                    socket.abort();
+                    return false;
+                }
+            };
+            match socket.recv(|buf| (buf.len(), Bytes::copy_from_slice(buf))) {
+                Ok(data) => permit.send(data),
+                Err(_) => break,
+            }
+        }
+        if !socket.may_recv() && !socket.can_recv() {
+            entry.to_app = None;
+        }
+    }
+
+    // From the app to the tunnel
+    while !entry.closing {
+        if entry.pending.is_none() {
+            match entry.from_app.try_recv() {
+                Ok(data) => entry.pending = Some(data),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    socket.close();
+                    entry.closing = true;
+                    break;
+                }
+            }
+        }
+        let Some(data) = entry.pending.as_mut() else {
+            break;
+        };
+        if !socket.can_send() {
+            break;
+        }
+        match socket.send_slice(data) {
+            Ok(len) => {
+                data.advance(len);
+                if data.is_empty() {
+                    entry.pending = None;
+                }
+            }
+            Err(_) => break,
+        }
+    }
+
+    socket.state() != tcp::State::Closed
+}
+
+fn process_udp_connection(entry: &mut UdpEntry, socket: &mut udp::Socket<'_>) -> bool {
+    // From the tunnel to the app
+    while socket.can_recv() {
+        let permit = match entry.to_app.try_reserve() {
+            Ok(permit) => permit,
+            Err(TrySendError::Full(_)) => break,
+            Err(TrySendError::Closed(_)) => {
+                socket.close();
+                return false;
+            }
+        };
+        match socket.recv() {
+            Ok((data, meta)) => {
+                let source = SocketAddr::new(meta.endpoint.addr.into(), meta.endpoint.port);
+                permit.send((source, Bytes::copy_from_slice(data)));
+            }
+            Err(_) => break,
+        }
+    }
+
+    // From the app to the tunnel
+    while socket.can_send() {
+        match entry.from_app.try_recv() {
+            Ok((remote, data)) => {
+                if let Err(err) = socket.send_slice(&data, IpEndpoint::from(remote)) {
+                    trace!("Dropping datagram to {remote}: {err}");
+                }
+            }
+            Err(TryRecvError::Empty) => break,
+            Err(TryRecvError::Disconnected) => {
+                socket.close();
+                return false;
+            }
+        }
+    }
+    true
+}
+
+// Bundles the packets of the stack into mixnet messages to the exit, and unpacks its replies.
+// Like the mixnet listener of the mixnet mode, it hands the replies to the beacons of the
+// connection monitor over.
+pub(crate) struct MixnetPipe {
+    // We are the only one listening for mixnet messages from now on
+    mixnet_client: OwnedMutexGuard<Option<MixnetClient>>,
+    sender: MixnetClientSender,
+    message_creator: MessageCreator,
+    encoder: MultiIpPacketCodec,
+    ipr_listener: IprListener,
+    our_ips: IpPair,
+    connection_event_tx: UnboundedSender<ConnectionStatusEvent>,
+    icmp_beacon_identifier: u16,
+}
+
+impl MixnetPipe {
+    pub(crate) async fn new(
+        mixnet_client: SharedMixnetClient,
+        exit_ipr: Recipient,
+        packet_buffer_timeout: Duration,
+        our_ips: IpPair,
+        connection_monitor: &ConnectionMonitorTask,
+    ) -> Self {
+        let sender = mixnet_client.split_sender().await;
+        let ipr_listener = IprListener::new(mixnet_client.nym_address().await);
+        MixnetPipe {
+            mixnet_client: mixnet_client.inner().lock_owned().await,
+            sender,
+            message_creator: MessageCreator::new(exit_ipr),
+            encoder: MultiIpPacketCodec::new(packet_buffer_timeout),
+            ipr_listener,
+            our_ips,
+            connection_event_tx: connection_monitor.event_sender(),
+            icmp_beacon_identifier: connection_monitor.icmp_beacon_identifier(),
+        }
+    }
+
+    fn send_connection_event(&self, event: ConnectionStatusEvent) {
+        if self.connection_event_tx.unbounded_send(event).is_err() {
+            debug!("Failed to send connection event to connection monitor");
+        }
+    }
+
+    async fn send_bundle(&self, bundled_packets: Bytes) {
+        match self.message_creator.create_input_message(bundled_packets) {
+            Ok(input_message) => {
+                if self.sender.send(input_message).await.is_err() {
+                    error!(
+                        "Could not forward IP packet to the mixnet. The packet(s) will be dropped."
+                    );
+                }
+            }
+            Err(err) => {
+                error!("Failed to create input message, the packet(s) will be dropped: {err}");
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl PacketPipe for MixnetPipe {
+    async fn send_packet(&mut self, packet: Vec<u8>) {
+        if let Some(bundled_packets) = self.encoder.append_packet(packet.into()) {
+            self.send_bundle(bundled_packets).await;
+        }
+    }
+
+    async fn recv_packets(&mut self) -> Option<Vec<Bytes>> {
+        loop {
+            tokio::select! {
+                // Don't hold back packets too long while waiting for more to bundle them with
+                Some(bundled_packets) = self.encoder.buffer_timeout() => {
+                    self.send_bundle(bundled_packets).await;
+                }
+                message = self.mixnet_client.as_mut()?.next() => {
+                    let Some(message) = message else {
+                        error!("Proxy network stack: mixnet stream ended");
+                        return None;
+                    };
+                    match self.ipr_listener.handle_reconstructed_message(message).await {
+                        Ok(Some(MixnetMessageOutcome::IpPackets(packets))) => {
+                            for packet in &packets {
+                                if let Some(event) = check_for_icmp_beacon_reply(
+                                    packet,
+                                    self.icmp_beacon_identifier,
+                                    self.our_ips,
+                                ) {
+                                    self.send_connection_event(event);
+                                }
+                            }
+                            return Some(packets);
+                        }
+                        Ok(Some(MixnetMessageOutcome::MixnetSelfPing(request_id))) => {
+                            self.send_connection_event(ConnectionStatusEvent::MixnetSelfPing {
+                                request_id,
+                            });
+                        }
+                        Ok(Some(MixnetMessageOutcome::Disconnect)) => {
+                            error!("Proxy network stack: the exit gateway disconnected us");
+                            return None;
+                        }
+                        Ok(None) => {}
+                        Err(err) => error!("Proxy network stack: {err}"),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::net::Ipv4Addr;
+
+    use nym_task::TaskManager;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::proxy::{resolver::TunnelResolver, socks5::Socks5Server};
+
+    pub(crate) const PEER_IP: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+    const ECHO_PORT: u16 = 7;
+    const TEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+    // The other end of the tunnel, in place of the exit: a second stack echoing what reaches its
+    // TCP port
+    pub(crate) struct EchoPeer {
+        iface: Interface,
+        device: PacketQueues,
+        sockets: SocketSet<'static>,
+        echo: SocketHandle,
+    }
+
+    impl EchoPeer {
+        pub(crate) fn new() -> Self {
+            let mut device = PacketQueues {
+                rx: VecDeque::new(),
+                tx: VecDeque::new(),
+                mtu: 1500,
+            };
+            let mut iface = Interface::new(
+                InterfaceConfig::new(HardwareAddress::Ip),
+                &mut device,
+                Instant::now(),
+            );
+            iface.update_ip_addrs(|addrs| {
+                addrs
+                    .push(IpCidr::new(IpAddress::from(IpAddr::V4(PEER_IP)), 32))
+                    .ok();
+            });
+            iface.routes_mut().add_default_ipv4_route(PEER_IP).ok();
+
+            let mut socket = tcp::Socket::new(
+                tcp::SocketBuffer::new(vec![0; TCP_BUFFER_SIZE]),
+                tcp::SocketBuffer::new(vec![0; TCP_BUFFER_SIZE]),
+            );
+            socket.listen(ECHO_PORT).unwrap();
+            let mut sockets = SocketSet::new(Vec::new());
+            let echo = sockets.add(socket);
+            EchoPeer {
+                iface,
+                device,
+                sockets,
+                echo,
+            }
+        }
+
+        fn poll(&mut self) {
+            self.iface
+                .poll(Instant::now(), &mut self.device, &mut self.sockets);
+            let socket = self.sockets.get_mut::<tcp::Socket>(self.echo);
+            if socket.can_recv() {
+                let data = socket.recv(|buf| (buf.len(), buf.to_vec())).unwrap();
+                socket.send_slice(&data).unwrap();
+            }
+            self.iface
+                .poll(Instant::now(), &mut self.device, &mut self.sockets);
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl PacketPipe for EchoPeer {
+        async fn send_packet(&mut self, packet: Vec<u8>) {
+            self.device.rx.push_back(packet.into());
+            self.poll();
+        }
+
+        async fn recv_packets(&mut self) -> Option<Vec<Bytes>> {
+            loop {
+                // Polling on a timer too, for the delayed acks
+                self.poll();
+                if !self.device.tx.is_empty() {
+                    return Some(self.device.tx.drain(..).map(Bytes::from).collect());
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn socks5_connections_go_through_the_stack() {
+        let our_ips = [
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            IpAddr::V6("fd00::2".parse().unwrap()),
+        ];
+        let task_manager = TaskManager::new(1);
+        let stack = NetStack::new(&our_ips, 1500);
+        let stack_handle = stack.handle();
+        let _stack_task = stack.start(
+            EchoPeer::new(),
+            task_manager.subscribe_named("proxy_network_stack"),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap();
+        let resolver = TunnelResolver::new(stack_handle.clone(), Vec::new());
+        let server = Socks5Server::new(listener, stack_handle, resolver);
+        tokio::spawn(server.run(task_manager.subscribe_named("socks5_server")));
+
+        socks5_echo(proxy, PEER_IP).await;
+    }
+
+    // Connects to the echo port of `peer` through the SOCKS5 proxy, and checks what comes back
+    pub(crate) async fn socks5_echo(proxy: SocketAddr, peer: Ipv4Addr) {
+        let mut client = TcpStream::connect(proxy).await.unwrap();
+        client.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+        let mut method = [0u8; 2];
+        client.read_exact(&mut method).await.unwrap();
+        assert_eq!(method, [0x05, 0x00]);
+
+        let mut request = vec![0x05, 0x01, 0x00, 0x01];
+        request.extend_from_slice(&peer.octets());
+        request.extend_from_slice(&ECHO_PORT.to_be_bytes());
+        client.write_all(&request).await.unwrap();
+        let mut reply = [0u8; 10];
+        tokio::time::timeout(TEST_TIMEOUT, client.read_exact(&mut reply))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(reply[..2], [0x05, 0x00]);
+
+        let message = b"through the tunnel";
+        client.write_all(message).await.unwrap();
+        let mut echoed = vec![0u8; message.len()];
+        tokio::time::timeout(TEST_TIMEOUT, client.read_exact(&mut echoed))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(echoed, message);
+    }
+}
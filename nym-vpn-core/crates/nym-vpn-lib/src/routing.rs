@@ -23,11 +23,12 @@ use tun2::AbstractDevice;
 
 use crate::{
     error::Result,
+    split_tunnel::SplitTunnelConfig,
     vpn::{MixnetVpn, NymVpn},
     SetupMixTunnelError,
 };
 
-const DEFAULT_TUN_MTU: u16 = 1500;
+pub(crate) const DEFAULT_TUN_MTU: u16 = 1500;
 
 #[derive(Clone)]
 pub(crate) struct RoutingConfig {
@@ -39,19 +40,24 @@ pub(crate) struct RoutingConfig {
     pub(crate) entry_mixnet_gateway_ip: IpAddr,
     pub(crate) lan_gateway_ip: LanGatewayIp,
     pub(crate) disable_routing: bool,
+    pub(crate) split_tunnel: SplitTunnelConfig,
+    // The resolvers of the split DNS domains, reached outside the tunnel
+    pub(crate) split_dns_servers: Vec<IpAddr>,
 }
 
 impl Display for RoutingConfig {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "mixnet_tun_config: {:?}\ntun_ips: {:?}\nmtu: {}\nentry_mixnet_gateway_ip: {:?}\nlan_gateway_ip: {:?}\ndisable_routing: {:?}",
+            "mixnet_tun_config: {:?}\ntun_ips: {:?}\nmtu: {}\nentry_mixnet_gateway_ip: {:?}\nlan_gateway_ip: {:?}\ndisable_routing: {:?}\nsplit_tunnel: {}\nsplit_dns_servers: {:?}",
             self.mixnet_tun_config,
             self.tun_ips,
             self.mtu,
             self.entry_mixnet_gateway_ip,
             self.lan_gateway_ip,
-            self.disable_routing
+            self.disable_routing,
+            self.split_tunnel,
+            self.split_dns_servers
         )
     }
 }
@@ -60,12 +66,12 @@ impl RoutingConfig {
     pub(crate) fn new(
         vpn: &NymVpn<MixnetVpn>,
         tun_ips: IpPair,
+        mtu: u16,
         entry_mixnet_gateway_ip: IpAddr,
         lan_gateway_ip: LanGatewayIp,
     ) -> Self {
         debug!("TUN device IPs: {}", tun_ips);
         let mut mixnet_tun_config = tun2::Configuration::default();
-        let mtu = vpn.generic_config.nym_mtu.unwrap_or(DEFAULT_TUN_MTU);
         // only IPv4 is supported by tun2 for now
         mixnet_tun_config.address(tun_ips.ipv4);
         mixnet_tun_config.mtu(mtu);
@@ -83,6 +89,8 @@ impl RoutingConfig {
             entry_mixnet_gateway_ip,
             lan_gateway_ip,
             disable_routing: vpn.generic_config.disable_routing,
+            split_tunnel: vpn.generic_config.split_tunnel.clone(),
+            split_dns_servers: vpn.generic_config.dns.split_servers().collect(),
         }
     }
 
@@ -112,6 +120,24 @@ impl LanGatewayIp {
         debug!("Default network interface: {:?}", default_interface);
         Ok(LanGatewayIp(default_interface))
     }
+
+    // The node that routes the destination outside the tunnel, through the gateway of the same
+    // address family, or straight out the interface when there is no such gateway
+    #[cfg(not(any(target_os = "ios", target_os = "android")))]
+    pub(crate) fn node_for(&self, destination: IpAddr) -> Node {
+        let gateway = self
+            .0
+            .gateway
+            .as_ref()
+            .and_then(|gateway| match destination {
+                IpAddr::V4(_) => gateway.ipv4.first().copied().map(IpAddr::from),
+                IpAddr::V6(_) => gateway.ipv6.first().copied().map(IpAddr::from),
+            });
+        match gateway {
+            Some(gateway) => Node::new(gateway, self.0.name.clone()),
+            None => Node::device(self.0.name.clone()),
+        }
+    }
 }
 
 impl std::fmt::Display for LanGatewayIp {
@@ -174,7 +200,7 @@ pub(crate) async fn setup_mixnet_routing(
         dyn crate::mobile::ios::tun_provider::OSTunProvider,
     >,
     _dns_monitor: &mut DnsMonitor,
-    dns: Option<IpAddr>,
+    dns_servers: Vec<IpAddr>,
 ) -> std::result::Result<tun2::AsyncDevice, SetupMixTunnelError> {
     let mut tun_config = tun2::Configuration::default();
 
@@ -193,9 +219,7 @@ pub(crate) async fn setup_mixnet_routing(
             IpNetwork::new(IpAddr::V6(interface_addresses.ipv6), 128)
                 .expect("ipnetwork from v6/128 addr"),
         ],
-        dns_servers: dns
-            .map(|dns| vec![dns])
-            .unwrap_or(crate::DEFAULT_DNS_SERVERS.to_vec()),
+        dns_servers,
         remote_addresses: vec![config.entry_mixnet_gateway_ip],
         mtu: config.mtu(),
     };
@@ -246,7 +270,7 @@ pub async fn setup_mixnet_routing(
     route_manager: &mut RouteManager,
     config: RoutingConfig,
     dns_monitor: &mut DnsMonitor,
-    dns: Option<IpAddr>,
+    dns_servers: Vec<IpAddr>,
 ) -> std::result::Result<tun2::AsyncDevice, SetupMixTunnelError> {
     debug!("Creating tun device");
     let mixnet_tun_config = config.mixnet_tun_config.clone();
@@ -294,11 +318,19 @@ pub async fn setup_mixnet_routing(
     debug!("Using node_v4: {:?}", node_v4);
     debug!("Using node_v6: {:?}", node_v6);
 
-    let mut routes = [
-        (catch_all_ipv4().to_string(), node_v4),
-        (catch_all_ipv6().to_string(), node_v6),
-    ]
-    .to_vec();
+    let mut routes = config
+        .split_tunnel
+        .tunneled_networks()
+        .into_iter()
+        .map(|network| {
+            let node = if network.is_ipv4() {
+                node_v4.clone()
+            } else {
+                node_v6.clone()
+            };
+            (network, node)
+        })
+        .collect::<Vec<_>>();
 
     // If wireguard is not enabled, and we are not tunneling the connection to the gateway through
     // it, we need to add an exception route for the gateway to the routing table.
@@ -309,27 +341,44 @@ pub async fn setup_mixnet_routing(
     // correct one should be something along the lines of "Ethernet" or "Wi-Fi". Check the name
     // with `netsh interface show interfaces`
     if cfg!(not(target_os = "windows")) || cfg!(target_os = "linux") {
-        let entry_mixnet_gateway_ip = config.entry_mixnet_gateway_ip.to_string();
-        let default_node = if let Some(addr) = config.lan_gateway_ip.0.gateway.and_then(|g| {
-            g.ipv4
-                .first()
-                .map(|a| IpAddr::from(*a))
-                .or(g.ipv6.first().map(|a| IpAddr::from(*a)))
-        }) {
-            Node::new(addr, config.lan_gateway_ip.0.name)
-        } else {
-            Node::device(config.lan_gateway_ip.0.name)
-        };
+        let entry_mixnet_gateway_ip = IpNetwork::from(config.entry_mixnet_gateway_ip);
+        let entry_node = config
+            .lan_gateway_ip
+            .node_for(config.entry_mixnet_gateway_ip);
         info!(
             "Add extra route: [{:?}, {:?}]",
             entry_mixnet_gateway_ip,
-            default_node.clone()
+            entry_node.clone()
+        );
+        routes.extend([(entry_mixnet_gateway_ip, entry_node)]);
+
+        // Split tunnel exclusions are routed the same way as the gateway exception
+        if !config.split_tunnel.exclude_networks.is_empty() {
+            info!(
+                "Excluding networks from the tunnel: {:?}",
+                config.split_tunnel.exclude_networks
+            );
+        }
+        routes.extend(
+            config
+                .split_tunnel
+                .exclude_networks
+                .iter()
+                .map(|network| (*network, config.lan_gateway_ip.node_for(network.ip()))),
         );
-        routes.extend([(entry_mixnet_gateway_ip, default_node.clone())]);
+
+        // The split domains are resolved by the stub, which would otherwise reach their resolvers
+        // through the tunnel, and the exit can't reach resolvers on internal networks
+        routes.extend(config.split_dns_servers.iter().map(|server| {
+            (
+                IpNetwork::from(*server),
+                config.lan_gateway_ip.node_for(*server),
+            )
+        }));
     };
 
     let routes = routes.into_iter().flat_map(|(network, node)| {
-        replace_default_prefixes(network.parse().unwrap())
+        replace_default_prefixes(network)
             .into_iter()
             .map(move |ip| RequiredRoute::new(ip, node.clone()))
     });
@@ -340,10 +389,7 @@ pub async fn setup_mixnet_routing(
     debug!("Routes: {:#?}", routes.clone().collect::<HashSet<_>>());
     route_manager.add_routes(routes.collect()).await?;
 
-    // Set the DNS server
-    let dns_servers = dns
-        .map(|dns| vec![dns])
-        .unwrap_or(crate::DEFAULT_DNS_SERVERS.to_vec());
+    // Set the DNS servers
     tokio::task::block_in_place(move || dns_monitor.set(&device_name, &dns_servers))?;
 
     Ok(dev)
@@ -0,0 +1,290 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    path::{Component, Path},
+    process::Stdio,
+};
+
+use tokio::{io::AsyncWriteExt, process::Command};
+use tracing::{debug, error, info, warn};
+
+const CGROUP2_ROOT: &str = "/sys/fs/cgroup";
+
+// Traffic from excluded processes is marked with this, and the marked traffic is routed using the
+// main routing table instead of the tunnel.
+const EXCLUDED_FWMARK: u32 = 0x6e796d;
+const EXCLUDED_RULE_PRIORITY: u32 = 100;
+const NFT_TABLE: &str = "nym-split-tunnel";
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum SplitTunnelError {
+    #[error("failed to run {command}: {source}")]
+    RunCommand {
+        command: String,
+        source: std::io::Error,
+    },
+
+    #[error("{command} failed: {stderr}")]
+    CommandFailed { command: String, stderr: String },
+
+    #[error("invalid cgroup path: {cgroup}")]
+    InvalidCgroup { cgroup: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ExcludedCgroup {
+    // Relative to the cgroup root
+    path: String,
+    level: usize,
+}
+
+// Routes the traffic of all processes in the given cgroups outside the tunnel, until removed.
+pub(crate) struct ExcludedCgroups {
+    cgroups: Vec<ExcludedCgroup>,
+    removed: bool,
+}
+
+impl ExcludedCgroups {
+    async fn new(cgroups: &[String]) -> Result<Option<Self>, SplitTunnelError> {
+        let mut excluded = Vec::new();
+        for cgroup in cgroups {
+            match resolve_cgroup(cgroup).await? {
+                Some(cgroup) => excluded.push(cgroup),
+                None => warn!("Not excluding cgroup {cgroup} from the tunnel, it does not exist"),
+            }
+        }
+        if excluded.is_empty() {
+            return Ok(None);
+        }
+
+        // Clean up anything left behind in case we didn't shut down cleanly last time
+        remove_rules().await;
+
+        debug!("Adding split tunnel rules for cgroups: {excluded:?}");
+        run_nft(&nft_ruleset(&excluded)).await?;
+        for family in ["-4", "-6"] {
+            run_ip(&rule_args(family, "add")).await?;
+        }
+        let cgroups = Self {
+            cgroups: excluded,
+            removed: false,
+        };
+        info!("Excluded cgroups from the tunnel: {}", cgroups.paths());
+
+        Ok(Some(cgroups))
+    }
+
+    fn paths(&self) -> String {
+        self.cgroups
+            .iter()
+            .map(|cgroup| cgroup.path.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    pub(crate) async fn remove(mut self) {
+        info!("Removing split tunnel rules for cgroups: {}", self.paths());
+        remove_rules().await;
+        self.removed = true;
+    }
+}
+
+impl Drop for ExcludedCgroups {
+    fn drop(&mut self) {
+        if self.removed {
+            return;
+        }
+        // We didn't get to remove the rules on the way out, do it in the background
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                info!("Removing split tunnel rules for cgroups: {}", self.paths());
+                handle.spawn(remove_rules());
+            }
+            Err(_) => error!("Failed to remove the split tunnel rules, no runtime"),
+        }
+    }
+}
+
+// The cgroup ends up in the nft ruleset, so only plain paths that stay within the cgroup root are
+// accepted. Returns None if the cgroup doesn't exist.
+async fn resolve_cgroup(cgroup: &str) -> Result<Option<ExcludedCgroup>, SplitTunnelError> {
+    let invalid = || SplitTunnelError::InvalidCgroup {
+        cgroup: cgroup.to_string(),
+    };
+    if !is_plain_cgroup_path(cgroup) {
+        return Err(invalid());
+    }
+
+    let root = tokio::fs::canonicalize(CGROUP2_ROOT)
+        .await
+        .map_err(|_| invalid())?;
+    let canonical = match tokio::fs::canonicalize(root.join(cgroup)).await {
+        Ok(canonical) if canonical.is_dir() => canonical,
+        _ => return Ok(None),
+    };
+    // Symlinks could lead anywhere, so check the path we actually resolved to as well
+    let path = canonical
+        .strip_prefix(&root)
+        .ok()
+        .and_then(Path::to_str)
+        .filter(|path| is_plain_cgroup_path(path))
+        .ok_or_else(invalid)?;
+
+    Ok(Some(ExcludedCgroup {
+        path: path.to_string(),
+        level: Path::new(path).components().count(),
+    }))
+}
+
+fn is_plain_cgroup_path(cgroup: &str) -> bool {
+    !cgroup.is_empty()
+        && !cgroup
+            .chars()
+            .any(|c| c == '"' || c == '\\' || c.is_control())
+        && Path::new(cgroup)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+async fn remove_rules() {
+    for family in ["-4", "-6"] {
+        // Delete any duplicates too
+        while run_ip(&rule_args(family, "del")).await.is_ok() {}
+    }
+    run_nft(&format!("delete table inet {NFT_TABLE}\n"))
+        .await
+        .ok();
+}
+
+fn rule_args(family: &str, action: &str) -> Vec<String> {
+    [
+        family,
+        "rule",
+        action,
+        "fwmark",
+        &format!("{EXCLUDED_FWMARK:#x}"),
+        "lookup",
+        "main",
+        "priority",
+        &EXCLUDED_RULE_PRIORITY.to_string(),
+    ]
+    .map(str::to_string)
+    .to_vec()
+}
+
+// Marking the packets in a route hook makes the kernel redo the routing decision, and since the
+// source address was picked for the tunnel we also need to masquerade them.
+fn nft_ruleset(cgroups: &[ExcludedCgroup]) -> String {
+    let mut ruleset = format!(
+        "table inet {NFT_TABLE} {{\n  \
+           chain output {{\n    \
+             type route hook output priority mangle; policy accept;\n"
+    );
+    for ExcludedCgroup { path, level } in cgroups {
+        ruleset.push_str(&format!(
+            "    socket cgroupv2 level {level} \"{path}\" meta mark set {EXCLUDED_FWMARK:#x}\n"
+        ));
+    }
+    ruleset.push_str(&format!(
+        "  }}\n  \
+           chain postrouting {{\n    \
+             type nat hook postrouting priority srcnat; policy accept;\n    \
+             meta mark {EXCLUDED_FWMARK:#x} masquerade\n  \
+           }}\n\
+         }}\n"
+    ));
+    ruleset
+}
+
+async fn run_nft(ruleset: &str) -> Result<(), SplitTunnelError> {
+    let command = "nft -f -".to_string();
+    let run_error = |source| SplitTunnelError::RunCommand {
+        command: command.clone(),
+        source,
+    };
+    let mut child = Command::new("nft")
+        .args(["-f", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(run_error)?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(ruleset.as_bytes())
+            .await
+            .map_err(run_error)?;
+    }
+    let output = child.wait_with_output().await.map_err(run_error)?;
+    check_output(command, output)
+}
+
+async fn run_ip(args: &[String]) -> Result<(), SplitTunnelError> {
+    let command = format!("ip {}", args.join(" "));
+    let output = Command::new("ip")
+        .args(args)
+        .output()
+        .await
+        .map_err(|source| SplitTunnelError::RunCommand {
+            command: command.clone(),
+            source,
+        })?;
+    check_output(command, output)
+}
+
+fn check_output(command: String, output: std::process::Output) -> Result<(), SplitTunnelError> {
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        debug!("{command} failed: {stderr}");
+        Err(SplitTunnelError::CommandFailed { command, stderr })
+    }
+}
+
+pub(crate) async fn exclude_cgroups(cgroups: &[String]) -> Option<ExcludedCgroups> {
+    ExcludedCgroups::new(cgroups)
+        .await
+        .inspect_err(|err| error!("Failed to exclude cgroups from the tunnel: {err}"))
+        .ok()
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cgroup_level_matches_path_depth() {
+        let ruleset = nft_ruleset(&[
+            ExcludedCgroup {
+                path: "system.slice/gitlab-runner.service".to_string(),
+                level: 2,
+            },
+            ExcludedCgroup {
+                path: "ci".to_string(),
+                level: 1,
+            },
+        ]);
+        assert!(ruleset.contains("socket cgroupv2 level 2 \"system.slice/gitlab-runner.service\""));
+        assert!(ruleset.contains("socket cgroupv2 level 1 \"ci\""));
+    }
+
+    #[test]
+    fn only_plain_cgroup_paths_are_accepted() {
+        assert!(is_plain_cgroup_path("system.slice/gitlab-runner.service"));
+        for cgroup in [
+            "",
+            "/system.slice",
+            "../etc",
+            "system.slice/../..",
+            "./ci",
+            "ci\" meta mark set 1",
+            "ci\\",
+            "ci\nflush ruleset",
+        ] {
+            assert!(!is_plain_cgroup_path(cgroup), "{cgroup:?}");
+        }
+    }
+}
@@ -0,0 +1,83 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+#[cfg(target_os = "linux")]
+mod linux;
+
+use ipnetwork::IpNetwork;
+use serde::{Deserialize, Serialize};
+
+#[cfg(target_os = "linux")]
+pub(crate) use linux::exclude_cgroups;
+
+use crate::routing::{catch_all_ipv4, catch_all_ipv6};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SplitTunnelConfig {
+    /// Only route these networks through the tunnel. If empty, all traffic is routed through the
+    /// tunnel.
+    pub include_networks: Vec<IpNetwork>,
+
+    /// Route these networks outside the tunnel, even if they are covered by the included networks.
+    /// Not supported in mixnet mode on Windows.
+    pub exclude_networks: Vec<IpNetwork>,
+
+    /// Linux only: the cgroup v2 paths, relative to the cgroup root, of processes whose traffic
+    /// should bypass the tunnel. For example "system.slice/gitlab-runner.service".
+    pub excluded_cgroups: Vec<String>,
+}
+
+impl SplitTunnelConfig {
+    // The networks routed through the tunnel
+    pub(crate) fn tunneled_networks(&self) -> Vec<IpNetwork> {
+        if self.include_networks.is_empty() {
+            vec![catch_all_ipv4(), catch_all_ipv6()]
+        } else {
+            self.include_networks.clone()
+        }
+    }
+}
+
+impl std::fmt::Display for SplitTunnelConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn join<T: ToString>(items: &[T]) -> String {
+            items
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+        write!(
+            f,
+            "include: [{}], exclude: [{}], excluded cgroups: [{}]",
+            join(&self.include_networks),
+            join(&self.exclude_networks),
+            join(&self.excluded_cgroups),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn everything_is_tunneled_by_default() {
+        let config = SplitTunnelConfig::default();
+        assert_eq!(
+            config.tunneled_networks(),
+            vec![catch_all_ipv4(), catch_all_ipv6()]
+        );
+    }
+
+    #[test]
+    fn only_included_networks_are_tunneled() {
+        let network: IpNetwork = "10.0.0.0/8".parse().unwrap();
+        let config = SplitTunnelConfig {
+            include_networks: vec![network],
+            ..Default::default()
+        };
+        assert_eq!(config.tunneled_networks(), vec![network]);
+    }
+}
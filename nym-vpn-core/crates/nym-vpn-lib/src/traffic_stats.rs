@@ -0,0 +1,386 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use tracing::debug;
+
+// Throughput is averaged over at least this long, so that frequent readers don't see it jump
+// around
+const MIN_THROUGHPUT_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TrafficCounters {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub packets_in: u64,
+    pub packets_out: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WgHopStats {
+    pub interface: String,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    // Unknown when the peer stats can't be read, or before the first handshake
+    pub last_handshake: Option<SystemTime>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrafficStats {
+    // The traffic through the tunnel since it was set up
+    pub total: TrafficCounters,
+    // In bytes per second
+    pub throughput_in: f64,
+    pub throughput_out: f64,
+    // For each wireguard tunnel, from the entry to the exit
+    pub wireguard_hops: Vec<WgHopStats>,
+}
+
+// Throughput is measured between two snapshots, so each reader that wants its own rate keeps one
+// of these. Readers that don't share the one in the handle.
+#[derive(Default)]
+pub struct ThroughputSampler {
+    sampled_at: Option<Instant>,
+    bytes_in: u64,
+    bytes_out: u64,
+    rate_in: f64,
+    rate_out: f64,
+}
+
+impl ThroughputSampler {
+    fn sample(&mut self, total: &TrafficCounters) -> (f64, f64) {
+        let now = Instant::now();
+        let elapsed = self.sampled_at.map(|sampled_at| now - sampled_at);
+        if elapsed.map_or(true, |elapsed| elapsed >= MIN_THROUGHPUT_WINDOW) {
+            if let Some(elapsed) = elapsed {
+                let secs = elapsed.as_secs_f64();
+                // The counters start over when the wireguard tunnels are replaced
+                self.rate_in = total.bytes_in.saturating_sub(self.bytes_in) as f64 / secs;
+                self.rate_out = total.bytes_out.saturating_sub(self.bytes_out) as f64 / secs;
+            }
+            self.sampled_at = Some(now);
+            self.bytes_in = total.bytes_in;
+            self.bytes_out = total.bytes_out;
+        }
+        (self.rate_in, self.rate_out)
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    // Counted by the mixnet processor
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    packets_in: AtomicU64,
+    packets_out: AtomicU64,
+
+    // The wireguard tunnels keep their own counters, that we read on demand
+    wireguard_interfaces: Mutex<Vec<String>>,
+
+    throughput: Mutex<ThroughputSampler>,
+}
+
+// Collects the traffic statistics of a running tunnel. Cheap to clone, all clones share the same
+// counters.
+#[derive(Clone, Default)]
+pub struct TrafficStatsHandle {
+    inner: Arc<Inner>,
+}
+
+impl TrafficStatsHandle {
+    pub(crate) fn record_in(&self, bytes: usize) {
+        self.inner
+            .bytes_in
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        self.inner.packets_in.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_out(&self, bytes: usize) {
+        self.inner
+            .bytes_out
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        self.inner.packets_out.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // The wireguard interfaces, from the entry to the exit. Empty when not using wireguard.
+    pub(crate) fn set_wireguard_interfaces(&self, interfaces: Vec<String>) {
+        *self.inner.wireguard_interfaces.lock().unwrap() = interfaces;
+    }
+
+    // The throughput is measured since the previous call, by any caller
+    pub async fn snapshot(&self) -> TrafficStats {
+        let (total, wireguard_hops) = self.read_counters().await;
+        let (throughput_in, throughput_out) = self.inner.throughput.lock().unwrap().sample(&total);
+        TrafficStats {
+            total,
+            throughput_in,
+            throughput_out,
+            wireguard_hops,
+        }
+    }
+
+    // The throughput is measured since the previous sample taken with the same sampler
+    pub async fn sample(&self, throughput: &mut ThroughputSampler) -> TrafficStats {
+        let (total, wireguard_hops) = self.read_counters().await;
+        let (throughput_in, throughput_out) = throughput.sample(&total);
+        TrafficStats {
+            total,
+            throughput_in,
+            throughput_out,
+            wireguard_hops,
+        }
+    }
+
+    // Reads only the wireguard counters, leaving the throughput sampling alone
+    pub(crate) async fn wireguard_hops(&self) -> Vec<WgHopStats> {
+        let interfaces = self.inner.wireguard_interfaces.lock().unwrap().clone();
+        read_wireguard_hops(interfaces).await
+    }
+
+    async fn read_counters(&self) -> (TrafficCounters, Vec<WgHopStats>) {
+        let interfaces = self.inner.wireguard_interfaces.lock().unwrap().clone();
+        if interfaces.is_empty() {
+            let total = TrafficCounters {
+                bytes_in: self.inner.bytes_in.load(Ordering::Relaxed),
+                bytes_out: self.inner.bytes_out.load(Ordering::Relaxed),
+                packets_in: self.inner.packets_in.load(Ordering::Relaxed),
+                packets_out: self.inner.packets_out.load(Ordering::Relaxed),
+            };
+            return (total, Vec::new());
+        }
+        let hops = read_hops(interfaces).await;
+        // With wireguard, the traffic through the tunnel is what goes through the innermost one
+        let total = hops.last().map(|(_, exit)| *exit).unwrap_or_default();
+        (total, hops.into_iter().map(|(hop, _)| hop).collect())
+    }
+}
+
+async fn read_wireguard_hops(interfaces: Vec<String>) -> Vec<WgHopStats> {
+    read_hops(interfaces)
+        .await
+        .into_iter()
+        .map(|(hop, _)| hop)
+        .collect()
+}
+
+// The peer stats come from the tunnels themselves, over the wireguard UAPI. The system counters of
+// the interfaces are what went through the tunnel, and stand in for the peer stats where the
+// tunnel can't be asked. Both are read off the async runtime.
+async fn read_hops(interfaces: Vec<String>) -> Vec<(WgHopStats, TrafficCounters)> {
+    tokio::task::spawn_blocking(move || {
+        interfaces
+            .into_iter()
+            .filter_map(|interface| {
+                let peer = wireguard::peer_stats(&interface);
+                let counters = wireguard::interface_counters(&interface);
+                let counters = match (counters, &peer) {
+                    (Some(counters), _) => counters,
+                    (None, Some(peer)) => TrafficCounters {
+                        bytes_in: peer.rx_bytes,
+                        bytes_out: peer.tx_bytes,
+                        ..Default::default()
+                    },
+                    (None, None) => return None,
+                };
+                let hop = WgHopStats {
+                    interface,
+                    rx_bytes: peer
+                        .as_ref()
+                        .map_or(counters.bytes_in, |peer| peer.rx_bytes),
+                    tx_bytes: peer
+                        .as_ref()
+                        .map_or(counters.bytes_out, |peer| peer.tx_bytes),
+                    last_handshake: peer.and_then(|peer| peer.last_handshake),
+                };
+                Some((hop, counters))
+            })
+            .collect()
+    })
+    .await
+    .inspect_err(|err| debug!("Failed to read the wireguard counters: {err}"))
+    .unwrap_or_default()
+}
+
+mod wireguard {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    use super::TrafficCounters;
+
+    #[derive(Debug, Default, PartialEq, Eq)]
+    pub(super) struct PeerStats {
+        pub(super) rx_bytes: u64,
+        pub(super) tx_bytes: u64,
+        pub(super) last_handshake: Option<SystemTime>,
+    }
+
+    // Asks the tunnel behind the interface for its peer stats, summed over its peers
+    pub(super) fn peer_stats(interface: &str) -> Option<PeerStats> {
+        let mut uapi = uapi::connect(interface)?;
+        uapi.write_all(b"get=1\n\n").ok()?;
+        // The connection is kept open for more requests, so the reply ends at the first empty line
+        let mut reply = String::new();
+        for line in BufReader::new(uapi).lines() {
+            let line = line.ok()?;
+            if line.is_empty() {
+                break;
+            }
+            reply.push_str(&line);
+            reply.push('\n');
+        }
+        parse_peer_stats(&reply)
+    }
+
+    // The reply is a list of key=value lines, ending with the error code of the request
+    pub(super) fn parse_peer_stats(reply: &str) -> Option<PeerStats> {
+        let mut stats = PeerStats::default();
+        let mut handshake_sec = 0;
+        for (key, value) in reply.lines().filter_map(|line| line.split_once('=')) {
+            match key {
+                "rx_bytes" => stats.rx_bytes += value.parse::<u64>().ok()?,
+                "tx_bytes" => stats.tx_bytes += value.parse::<u64>().ok()?,
+                "last_handshake_time_sec" => handshake_sec = value.parse().ok()?,
+                "last_handshake_time_nsec" => {
+                    let handshake_nsec = value.parse().ok()?;
+                    // Zero until the first handshake
+                    if handshake_sec != 0 || handshake_nsec != 0 {
+                        let handshake = UNIX_EPOCH + Duration::new(handshake_sec, handshake_nsec);
+                        stats.last_handshake = stats.last_handshake.max(Some(handshake));
+                    }
+                }
+                "errno" if value != "0" => return None,
+                _ => {}
+            }
+        }
+        Some(stats)
+    }
+
+    #[cfg(unix)]
+    mod uapi {
+        use std::{os::unix::net::UnixStream, time::Duration};
+
+        const TIMEOUT: Duration = Duration::from_secs(1);
+
+        pub(super) fn connect(interface: &str) -> Option<UnixStream> {
+            let uapi = UnixStream::connect(format!("/var/run/wireguard/{interface}.sock")).ok()?;
+            uapi.set_read_timeout(Some(TIMEOUT)).ok()?;
+            uapi.set_write_timeout(Some(TIMEOUT)).ok()?;
+            Some(uapi)
+        }
+    }
+
+    #[cfg(windows)]
+    mod uapi {
+        use std::fs::{File, OpenOptions};
+
+        pub(super) fn connect(interface: &str) -> Option<File> {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(format!(
+                    r"\\.\pipe\ProtectedPrefix\Administrators\WireGuard\{interface}"
+                ))
+                .ok()
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(super) fn interface_counters(interface: &str) -> Option<TrafficCounters> {
+        let read = |name: &str| -> Option<u64> {
+            let path = format!("/sys/class/net/{interface}/statistics/{name}");
+            std::fs::read_to_string(path).ok()?.trim().parse().ok()
+        };
+        Some(TrafficCounters {
+            bytes_in: read("rx_bytes")?,
+            bytes_out: read("tx_bytes")?,
+            packets_in: read("rx_packets")?,
+            packets_out: read("tx_packets")?,
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(super) fn interface_counters(_interface: &str) -> Option<TrafficCounters> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn mixnet_traffic_is_counted() {
+        let stats = TrafficStatsHandle::default();
+        stats.record_out(100);
+        stats.record_out(50);
+        stats.record_in(1200);
+        assert_eq!(
+            stats.snapshot().await.total,
+            TrafficCounters {
+                bytes_in: 1200,
+                bytes_out: 150,
+                packets_in: 1,
+                packets_out: 2,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn each_sampler_measures_its_own_throughput() {
+        let stats = TrafficStatsHandle::default();
+        let mut sampler = ThroughputSampler::default();
+        stats.record_in(1000);
+        stats.sample(&mut sampler).await;
+        // Nothing is known until a sampler has taken two samples
+        assert_eq!(stats.snapshot().await.throughput_in, 0.0);
+
+        stats.record_in(3000);
+        tokio::time::sleep(MIN_THROUGHPUT_WINDOW).await;
+        let sampled = stats.sample(&mut sampler).await;
+        assert!(sampled.throughput_in > 0.0 && sampled.throughput_in <= 3000.0);
+        // Sampling with our own sampler didn't start a new window for the shared one
+        let snapshot = stats.snapshot().await;
+        assert!(snapshot.throughput_in > 0.0 && snapshot.throughput_in <= 3000.0);
+    }
+
+    #[test]
+    fn peer_stats_from_the_uapi() {
+        let reply = "private_key=e84b5a6d2717c1003a13b431570353dbaca9146cf150c5f8575680feba52027a
+listen_port=51820
+public_key=b85996fecc9c7f1fc6d2572a76eda11d59bcd20be8e543b15ce4bd85a8e75a33
+endpoint=[abcd:23::33%2]:51820
+last_handshake_time_sec=1700000000
+last_handshake_time_nsec=500
+tx_bytes=38333
+rx_bytes=2224
+persistent_keepalive_interval=0
+errno=0
+";
+        assert_eq!(
+            wireguard::parse_peer_stats(reply),
+            Some(wireguard::PeerStats {
+                rx_bytes: 2224,
+                tx_bytes: 38333,
+                last_handshake: Some(UNIX_EPOCH + Duration::new(1_700_000_000, 500)),
+            })
+        );
+
+        let no_handshake = "last_handshake_time_sec=0\nlast_handshake_time_nsec=0\nerrno=0\n";
+        assert_eq!(
+            wireguard::parse_peer_stats(no_handshake),
+            Some(wireguard::PeerStats::default())
+        );
+        assert_eq!(wireguard::parse_peer_stats("errno=1\n"), None);
+    }
+}
@@ -1,7 +1,10 @@
 // Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::{net::IpAddr, time::Duration};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::{Duration, Instant},
+};
 
 use futures::{
     channel::{mpsc, oneshot},
@@ -10,25 +13,37 @@ use futures::{
 use ipnetwork::IpNetwork;
 use log::*;
 use nym_authenticator_client::AuthClient;
-use nym_gateway_directory::{AuthAddresses, GatewayClient, IpPacketRouterAddress};
+use nym_gateway_directory::{
+    AuthAddresses, EntryPoint, ExitPoint, GatewayClient, GatewayList, GatewayRole, GatewaySelector,
+    IpPacketRouterAddress, NodeIdentity, Recipient, ScoringGatewaySelector,
+};
 use nym_task::TaskManager;
 use nym_wg_gateway_client::WgGatewayClient;
 use talpid_core::dns::DnsMonitor;
-use talpid_routing::{Node, RequiredRoute, RouteManager};
+use talpid_routing::{RequiredRoute, RouteManager};
 use talpid_tunnel::{TunnelEvent, TunnelMetadata};
-use tokio::time::timeout;
+use tokio::{sync::watch, task::JoinHandle, time::timeout};
 
 use crate::{
     bandwidth_controller::BandwidthController,
-    error::{Error, GatewayDirectoryError, Result, SetupMixTunnelError, SetupWgTunnelError},
-    mixnet, platform,
-    routing::{self, catch_all_ipv4, catch_all_ipv6, replace_default_prefixes},
+    dns::DnsStub,
+    dns_filter::DnsFilter,
+    error::{
+        Error, GatewayDirectoryError, Result, SetupMixTunnelError, SetupWgTunnelError,
+        SwitchGatewayError,
+    },
+    exit_failover::ExitFailover,
+    kill_switch::{KillSwitch, DEFAULT_GATEWAY_CLIENTS_WS_PORT},
+    mixnet, platform, pmtu,
+    routing::{self, replace_default_prefixes},
+    split_tunnel::SplitTunnelConfig,
     uniffi_custom_impls::{StatusEvent, TunStatus},
     vpn::{
-        MixnetConnectionInfo, MixnetExitConnectionInfo, MixnetVpn, NymVpn, SpecificVpn,
-        WireguardConnectionInfo, WireguardVpn, MIXNET_CLIENT_STARTUP_TIMEOUT_SECS,
+        MixnetConnectionInfo, MixnetExitConnectionInfo, MixnetVpn, NymVpn, NymVpnStatusMessage,
+        SpecificVpn, WireguardConnectionInfo, WireguardVpn, MAX_WG_HOP_COUNT,
+        MIXNET_CLIENT_STARTUP_TIMEOUT_SECS,
     },
-    wireguard_config,
+    wireguard_config::{self, WireguardConfig},
     wireguard_setup::create_wireguard_tunnel,
 };
 
@@ -41,16 +56,24 @@ pub(crate) trait TunnelSpecifcSetup {}
 pub(crate) struct MixTunnelSetup {
     pub(crate) mixnet_connection_info: MixnetConnectionInfo,
     pub(crate) exit_connection_info: MixnetExitConnectionInfo,
+    pub(crate) exit_ipr_tx: watch::Sender<Recipient>,
 }
 
 impl TunnelSpecifcSetup for MixTunnelSetup {}
 
 pub(crate) struct WgTunnelSetup {
     pub(crate) connection_info: WireguardConnectionInfo,
+    pub(crate) endpoint: SocketAddr,
+    // Set once the interface is up
+    pub(crate) interface: Option<String>,
+    // The address of the gateway inside the tunnel
+    pub(crate) tunnel_gateway: Ipv4Addr,
 
     pub(crate) receiver: oneshot::Receiver<()>,
     pub(crate) handle: tokio::task::JoinHandle<()>,
     pub(crate) tunnel_close_tx: oneshot::Sender<()>,
+    // What the tunnel was started with, so that it can be restarted with another next hop
+    pub(crate) config: WireguardConfig,
 }
 
 impl TunnelSpecifcSetup for WgTunnelSetup {}
@@ -58,12 +81,121 @@ impl TunnelSpecifcSetup for WgTunnelSetup {}
 #[allow(clippy::large_enum_variant)]
 pub(crate) enum AllTunnelsSetup {
     Mix(TunnelSetup<MixTunnelSetup>),
+    // The wireguard tunnels, from the entry to the exit
     Wg {
-        entry: TunnelSetup<WgTunnelSetup>,
-        exit: TunnelSetup<WgTunnelSetup>,
+        hops: Vec<TunnelSetup<WgTunnelSetup>>,
+        bandwidth_clients: Vec<JoinHandle<()>>,
+        // The path MTU to the entry gateway the tunnel MTUs were derived from
+        wire_mtu: u16,
+        // Lowered when the path turns out to drop larger packets without telling us, since the
+        // probes can't see that
+        max_wire_mtu: u16,
+        // Answers the DNS queries on the exit hop address when the DNS filter is enabled
+        dns_stub: Option<JoinHandle<()>>,
     },
 }
 
+// The entry and exit hops of a wireguard chain, which has at least those two
+fn wg_entry_and_exit(
+    hops: &[TunnelSetup<WgTunnelSetup>],
+) -> std::result::Result<(&WgTunnelSetup, &WgTunnelSetup), SetupWgTunnelError> {
+    match hops {
+        [entry, .., exit] => Ok((&entry.specific_setup, &exit.specific_setup)),
+        _ => Err(SetupWgTunnelError::UnsupportedHopCount {
+            hop_count: hops.len(),
+            max: MAX_WG_HOP_COUNT,
+        }),
+    }
+}
+
+impl AllTunnelsSetup {
+    pub(crate) fn status_message(
+        &self,
+    ) -> std::result::Result<NymVpnStatusMessage, SetupWgTunnelError> {
+        match self {
+            AllTunnelsSetup::Mix(TunnelSetup { specific_setup }) => {
+                Ok(NymVpnStatusMessage::MixConnectionInfo {
+                    mixnet_connection_info: specific_setup.mixnet_connection_info,
+                    mixnet_exit_connection_info: Box::new(specific_setup.exit_connection_info),
+                })
+            }
+            AllTunnelsSetup::Wg { hops, .. } => {
+                let (entry, exit) = wg_entry_and_exit(hops)?;
+                Ok(NymVpnStatusMessage::WgConnectionInfo {
+                    entry_connection_info: entry.connection_info.clone(),
+                    middle_connection_info: hops[1..hops.len() - 1]
+                        .iter()
+                        .map(|hop| hop.specific_setup.connection_info.clone())
+                        .collect(),
+                    exit_connection_info: exit.connection_info.clone(),
+                })
+            }
+        }
+    }
+
+    pub(crate) fn entry_gateway(&self) -> std::result::Result<NodeIdentity, SetupWgTunnelError> {
+        match self {
+            AllTunnelsSetup::Mix(TunnelSetup { specific_setup }) => {
+                Ok(specific_setup.mixnet_connection_info.entry_gateway)
+            }
+            AllTunnelsSetup::Wg { hops, .. } => {
+                wg_entry_and_exit(hops).map(|(entry, _)| entry.connection_info.gateway_id)
+            }
+        }
+    }
+
+    // The wireguard interfaces, from the entry to the exit
+    pub(crate) fn wireguard_interfaces(&self) -> Vec<String> {
+        match self {
+            AllTunnelsSetup::Mix(_) => Vec::new(),
+            AllTunnelsSetup::Wg { hops, .. } => hops
+                .iter()
+                .filter_map(|hop| hop.specific_setup.interface.clone())
+                .collect(),
+        }
+    }
+
+    // The innermost tunnel, that the connection monitor pings through
+    pub(crate) fn wireguard_exit(&self) -> Option<nym_connection_monitor::WireguardExit> {
+        let AllTunnelsSetup::Wg { hops, .. } = self else {
+            return None;
+        };
+        let exit = &hops.last()?.specific_setup;
+        Some(nym_connection_monitor::WireguardExit {
+            interface: exit.interface.clone()?,
+            gateway_ipv4: exit.tunnel_gateway,
+            gateway_ipv6: exit.config.talpid_config.ipv6_gateway,
+        })
+    }
+
+    pub(crate) fn exit_gateway(&self) -> std::result::Result<NodeIdentity, SetupWgTunnelError> {
+        match self {
+            AllTunnelsSetup::Mix(TunnelSetup { specific_setup }) => {
+                Ok(specific_setup.exit_connection_info.exit_gateway)
+            }
+            AllTunnelsSetup::Wg { hops, .. } => {
+                wg_entry_and_exit(hops).map(|(_, exit)| exit.connection_info.gateway_id)
+            }
+        }
+    }
+
+    pub(crate) fn into_wireguard_waiting(self) -> Option<Vec<WgTunnelSetup>> {
+        match self {
+            AllTunnelsSetup::Mix(_) => None,
+            AllTunnelsSetup::Wg { hops, .. } => {
+                Some(hops.into_iter().map(|hop| hop.specific_setup).collect())
+            }
+        }
+    }
+}
+
+// Kept for as long as the tunnels are up, so that we can switch gateways without a full teardown
+pub(crate) struct SwitchGatewayContext {
+    gateway_directory_client: GatewayClient,
+    mixnet_client: mixnet::SharedMixnetClient,
+    default_lan_gateway_ip: routing::LanGatewayIp,
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum WaitInterfaceUpError {
     #[error("auth failed")]
@@ -103,31 +235,79 @@ async fn wait_interface_up(
     }
 }
 
+fn validate_hop_count(hop_count: usize) -> std::result::Result<(), SetupWgTunnelError> {
+    if (2..=MAX_WG_HOP_COUNT).contains(&hop_count) {
+        Ok(())
+    } else {
+        Err(SetupWgTunnelError::UnsupportedHopCount {
+            hop_count,
+            max: MAX_WG_HOP_COUNT,
+        })
+    }
+}
+
+// Used to name the tasks and in the logs
+fn hop_names(hop_count: usize) -> Vec<String> {
+    (0..hop_count)
+        .map(|index| match index {
+            0 => "entry".to_string(),
+            index if index == hop_count - 1 => "exit".to_string(),
+            index => format!("middle_{index}"),
+        })
+        .collect()
+}
+
+// One entry per hop, from the entry to the exit
+struct WgGatewaysRegistration {
+    wireguard_configs: Vec<WireguardConfig>,
+    entry_gateway_ip: IpAddr,
+    wg_gateway_clients: Vec<WgGatewayClient>,
+}
+
 async fn setup_wg_tunnel(
     nym_vpn: &mut NymVpn<WireguardVpn>,
     mixnet_client: mixnet::SharedMixnetClient,
     task_manager: &mut TaskManager,
     route_manager: &mut RouteManager,
-    gateway_directory_client: GatewayClient,
+    gateway_directory_client: &GatewayClient,
     auth_addresses: AuthAddresses,
     default_lan_gateway_ip: routing::LanGatewayIp,
+    dns_monitor: &mut DnsMonitor,
 ) -> std::result::Result<AllTunnelsSetup, SetupWgTunnelError> {
-    // MTU is computed as (MTU of wire interface) - ((IP header size) + (UDP header size) + (WireGuard metadata size))
-    // The IP header size is 20 for IPv4 and 40 for IPv6
-    // The UDP header size is 8
-    // The Wireguard metadata size is 32
-    // Entry tunnel will only deal with IPv4 => 1500 - (20 + 8 + 32)
-    let entry_mtu = 1440;
-    // Exit tunnel will deal with both v4 and v6, and it's "wire" interface is entry tunnel's MTU
-    // 1440 - (40 + 8 + 32)
-    let exit_mtu = 1360;
-
     let bandwidth_controller =
         BandwidthController::new(mixnet_client.clone(), task_manager.subscribe());
     tokio::spawn(bandwidth_controller.run());
 
-    let (Some(entry_auth_recipient), Some(exit_auth_recipient)) =
-        (auth_addresses.entry().0, auth_addresses.exit().0)
+    let registration = register_wg_gateways(
+        nym_vpn,
+        &mixnet_client,
+        gateway_directory_client,
+        auth_addresses,
+    )
+    .await?;
+    start_wg_tunnels(
+        nym_vpn,
+        registration,
+        task_manager,
+        route_manager,
+        default_lan_gateway_ip,
+        dns_monitor,
+    )
+    .await
+}
+
+async fn register_wg_gateways(
+    nym_vpn: &NymVpn<WireguardVpn>,
+    mixnet_client: &mixnet::SharedMixnetClient,
+    gateway_directory_client: &GatewayClient,
+    auth_addresses: AuthAddresses,
+) -> std::result::Result<WgGatewaysRegistration, SetupWgTunnelError> {
+    validate_hop_count(nym_vpn.vpn_config.hop_count)?;
+    let Some(auth_recipients) = auth_addresses
+        .hops()
+        .iter()
+        .map(|auth_address| auth_address.0)
+        .collect::<Option<Vec<_>>>()
     else {
         return Err(SetupWgTunnelError::AuthenticationNotPossible(
             auth_addresses.to_string(),
@@ -135,149 +315,495 @@ async fn setup_wg_tunnel(
     };
     let auth_client = AuthClient::new_from_inner(mixnet_client.inner()).await;
     log::info!("Created wg gateway clients");
-    let mut wg_entry_gateway_client = WgGatewayClient::new_entry(
-        &nym_vpn.generic_config.data_path,
-        auth_client.clone(),
-        entry_auth_recipient,
-    );
-    let mut wg_exit_gateway_client = WgGatewayClient::new_exit(
-        &nym_vpn.generic_config.data_path,
-        auth_client.clone(),
-        exit_auth_recipient,
-    );
+    let data_path = &nym_vpn.generic_config.data_path;
+    let hop_count = auth_recipients.len();
+    let mut wg_gateway_clients: Vec<_> = auth_recipients
+        .into_iter()
+        .enumerate()
+        .map(|(index, auth_recipient)| {
+            new_wg_gateway_client(data_path, &auth_client, auth_recipient, index, hop_count)
+        })
+        .collect();
 
-    let (mut exit_wireguard_config, _) = wireguard_config::init_wireguard_config(
-        &gateway_directory_client,
-        &mut wg_exit_gateway_client,
-        None,
-        exit_mtu,
-    )
-    .await?;
-    let wg_gateway = exit_wireguard_config
-        .talpid_config
-        .peers
-        .first()
-        .map(|config| config.endpoint.ip());
-    let (mut entry_wireguard_config, entry_gateway_ip) = wireguard_config::init_wireguard_config(
-        &gateway_directory_client,
-        &mut wg_entry_gateway_client,
+    // These are replaced by the ones derived from the path MTU before the tunnels are brought up
+    let default_mtus = pmtu::tunnel_mtus(pmtu::DEFAULT_WIRE_MTU, false, wg_gateway_clients.len());
+
+    // Register starting from the exit, since each hop needs the endpoint of the hop after it
+    let mut wireguard_configs = Vec::with_capacity(wg_gateway_clients.len());
+    let mut wg_gateway = None;
+    for (index, wg_gateway_client) in wg_gateway_clients.iter_mut().enumerate().skip(1).rev() {
+        let (wireguard_config, _) = wireguard_config::init_wireguard_config(
+            gateway_directory_client,
+            wg_gateway_client,
+            wg_gateway,
+            default_mtus[index],
+        )
+        .await?;
+        wg_gateway = wireguard_config
+            .talpid_config
+            .peers
+            .first()
+            .map(|config| config.endpoint.ip());
+        wireguard_configs.push(wireguard_config);
+    }
+    let (entry_wireguard_config, entry_gateway_ip) = wireguard_config::init_wireguard_config(
+        gateway_directory_client,
+        &mut wg_gateway_clients[0],
         wg_gateway,
-        entry_mtu,
+        default_mtus[0],
     )
     .await?;
+    wireguard_configs.push(entry_wireguard_config);
+    wireguard_configs.reverse();
 
-    if wg_entry_gateway_client.suspended().await? || wg_exit_gateway_client.suspended().await? {
-        return Err(SetupWgTunnelError::NotEnoughBandwidthToSetupTunnel);
+    for wg_gateway_client in &wg_gateway_clients {
+        if wg_gateway_client.suspended().await? {
+            return Err(SetupWgTunnelError::NotEnoughBandwidthToSetupTunnel);
+        }
     }
-    tokio::spawn(
-        wg_entry_gateway_client.run(task_manager.subscribe_named("bandwidth_entry_client")),
-    );
-    tokio::spawn(wg_exit_gateway_client.run(task_manager.subscribe_named("bandwidth_exit_client")));
-    entry_wireguard_config
-        .talpid_config
-        .peers
-        .iter_mut()
-        .for_each(|peer| {
-            peer.allowed_ips.append(
-                &mut exit_wireguard_config
-                    .talpid_config
-                    .peers
-                    .iter()
-                    .map(|peer| IpNetwork::from(peer.endpoint.ip()))
-                    .collect::<Vec<_>>(),
-            );
+
+    Ok(WgGatewaysRegistration {
+        wireguard_configs,
+        entry_gateway_ip,
+        wg_gateway_clients,
+    })
+}
+
+fn new_wg_gateway_client(
+    data_path: &Option<std::path::PathBuf>,
+    auth_client: &AuthClient,
+    auth_recipient: Recipient,
+    index: usize,
+    hop_count: usize,
+) -> WgGatewayClient {
+    match index {
+        0 => WgGatewayClient::new_entry(data_path, auth_client.clone(), auth_recipient),
+        index if index == hop_count - 1 => {
+            WgGatewayClient::new_exit(data_path, auth_client.clone(), auth_recipient)
+        }
+        index => WgGatewayClient::new_middle(data_path, auth_client.clone(), auth_recipient, index),
+    }
+}
+
+// The hops whose gateway, or whose next hop, changed when switching gateways, registered anew.
+// The other hops keep running as they are and are None.
+struct WgGatewaysSwitch {
+    registrations: Vec<Option<(WireguardConfig, WgGatewayClient)>>,
+    // Set if the entry changed
+    entry_gateway_ip: Option<IpAddr>,
+}
+
+async fn register_switched_wg_gateways(
+    nym_vpn: &NymVpn<WireguardVpn>,
+    hops: &[TunnelSetup<WgTunnelSetup>],
+    mixnet_client: &mixnet::SharedMixnetClient,
+    gateway_directory_client: &GatewayClient,
+    auth_addresses: AuthAddresses,
+) -> std::result::Result<WgGatewaysSwitch, SetupWgTunnelError> {
+    let Some(auth_recipients) = auth_addresses
+        .hops()
+        .iter()
+        .map(|auth_address| auth_address.0)
+        .collect::<Option<Vec<_>>>()
+    else {
+        return Err(SetupWgTunnelError::AuthenticationNotPossible(
+            auth_addresses.to_string(),
+        ));
+    };
+    let hop_count = hops.len();
+    if auth_recipients.len() != hop_count {
+        return Err(SetupWgTunnelError::UnsupportedHopCount {
+            hop_count: auth_recipients.len(),
+            max: MAX_WG_HOP_COUNT,
         });
-    // If routing is disabled, we don't append the catch all routing rules
-    if !nym_vpn.generic_config.disable_routing {
-        exit_wireguard_config
+    }
+    let changed: Vec<bool> = hops
+        .iter()
+        .zip(&auth_recipients)
+        .map(|(hop, auth_recipient)| {
+            hop.specific_setup.connection_info.gateway_id != *auth_recipient.gateway()
+        })
+        .collect();
+
+    let auth_client = AuthClient::new_from_inner(mixnet_client.inner()).await;
+    let data_path = &nym_vpn.generic_config.data_path;
+    let mut registrations: Vec<Option<(WireguardConfig, WgGatewayClient)>> =
+        (0..hop_count).map(|_| None).collect();
+    let mut entry_gateway_ip = None;
+
+    // Register starting from the exit, since each hop needs the endpoint of the hop after it
+    let mut next_hop_changed = false;
+    let mut wg_gateway = None;
+    for (index, auth_recipient) in auth_recipients.into_iter().enumerate().rev() {
+        let hop = &hops[index].specific_setup;
+        if !changed[index] && !next_hop_changed {
+            wg_gateway = Some(hop.endpoint.ip());
+            next_hop_changed = false;
+            continue;
+        }
+        let mut wg_gateway_client =
+            new_wg_gateway_client(data_path, &auth_client, auth_recipient, index, hop_count);
+        let (wireguard_config, gateway_ip) = wireguard_config::init_wireguard_config(
+            gateway_directory_client,
+            &mut wg_gateway_client,
+            wg_gateway,
+            hop.connection_info.mtu,
+        )
+        .await?;
+        if wg_gateway_client.suspended().await? {
+            return Err(SetupWgTunnelError::NotEnoughBandwidthToSetupTunnel);
+        }
+        if index == 0 && changed[index] {
+            entry_gateway_ip = Some(gateway_ip);
+        }
+        wg_gateway = wireguard_config
+            .talpid_config
+            .peers
+            .first()
+            .map(|config| config.endpoint.ip());
+        next_hop_changed = changed[index];
+        registrations[index] = Some((wireguard_config, wg_gateway_client));
+    }
+
+    Ok(WgGatewaysSwitch {
+        registrations,
+        entry_gateway_ip,
+    })
+}
+
+// Restart the hops that were registered anew, while the others keep running along with their
+// routes. The old exception route to a replaced entry stays until we disconnect.
+#[allow(clippy::too_many_arguments)]
+async fn restart_wg_hops(
+    nym_vpn: &NymVpn<WireguardVpn>,
+    hops: &mut Vec<TunnelSetup<WgTunnelSetup>>,
+    bandwidth_clients: &mut [JoinHandle<()>],
+    dns_stub: &mut Option<JoinHandle<()>>,
+    wire_mtu: u16,
+    switch: WgGatewaysSwitch,
+    default_lan_gateway_ip: routing::LanGatewayIp,
+    task_manager: &mut TaskManager,
+    route_manager: &mut RouteManager,
+    dns_monitor: &mut DnsMonitor,
+) -> std::result::Result<(), SetupWgTunnelError> {
+    let hop_names = hop_names(hops.len());
+    let mut wireguard_configs: Vec<_> = hops
+        .iter()
+        .zip(&switch.registrations)
+        .map(|(hop, registration)| match registration {
+            Some((wireguard_config, _)) => wireguard_config.clone(),
+            None => hop.specific_setup.config.clone(),
+        })
+        .collect();
+    // The MTUs stay derived from the current path MTU, the periodic re-probe picks up any change
+    // that comes with a new entry
+    let entry_is_ipv6 = wireguard_configs[0].gateway_data.endpoint.is_ipv6();
+    chain_wireguard_configs(nym_vpn, &mut wireguard_configs, wire_mtu, entry_is_ipv6)?;
+
+    if let Some(entry_gateway_ip) = switch.entry_gateway_ip {
+        add_exception_routes(
+            route_manager,
+            entry_gateway_ip,
+            &nym_vpn.generic_config.split_tunnel,
+            default_lan_gateway_ip,
+        )
+        .await?;
+    }
+
+    // Close the replaced hops, starting from the exit side
+    let mut slots: Vec<_> = std::mem::take(hops).into_iter().map(Some).collect();
+    let replaced = slots
+        .iter_mut()
+        .zip(&switch.registrations)
+        .rev()
+        .filter(|(_, registration)| registration.is_some())
+        .filter_map(|(slot, _)| slot.take())
+        .map(|hop| hop.specific_setup)
+        .collect();
+    crate::util::close_wireguard_tunnels(replaced).await;
+
+    // A restarted exit comes up on a new interface, which the DNS servers have to follow
+    let exit_restarted = switch.registrations.last().is_some_and(Option::is_some);
+    let mut result = Ok(());
+    for (index, registration) in switch.registrations.into_iter().enumerate() {
+        let Some((_, wg_gateway_client)) = registration else {
+            continue;
+        };
+        bandwidth_clients[index].abort();
+        bandwidth_clients[index] =
+            spawn_bandwidth_client(task_manager, &hop_names[index], wg_gateway_client);
+        match start_wg_hop(
+            nym_vpn,
+            &hop_names[index],
+            wireguard_configs[index].clone(),
+            task_manager,
+            route_manager,
+        )
+        .await
+        {
+            Ok(hop) => slots[index] = Some(hop),
+            Err(err) => {
+                result = Err(err);
+                break;
+            }
+        }
+    }
+    // If a hop failed to come up, what is left is closed along with the rest on shutdown
+    *hops = slots.into_iter().flatten().collect();
+    result?;
+    if exit_restarted {
+        set_wg_dns(nym_vpn, hops, dns_stub, task_manager, dns_monitor).await?;
+    }
+    Ok(())
+}
+
+// The system resolves through the exit hop, the way it does through the tun device in mixnet mode.
+// With the DNS filter, it resolves through our stub on the address of the exit hop, which is
+// started anew along with the exit.
+async fn set_wg_dns(
+    nym_vpn: &NymVpn<WireguardVpn>,
+    hops: &[TunnelSetup<WgTunnelSetup>],
+    dns_stub: &mut Option<JoinHandle<()>>,
+    task_manager: &TaskManager,
+    dns_monitor: &mut DnsMonitor,
+) -> std::result::Result<(), SetupWgTunnelError> {
+    // The new exit may hand out the same address, so wait for the old stub to let go of it
+    if let Some(dns_stub) = dns_stub.take() {
+        dns_stub.abort();
+        dns_stub.await.ok();
+    }
+    if nym_vpn.generic_config.disable_routing {
+        return Ok(());
+    }
+    let Some(exit) = hops.last().map(|hop| &hop.specific_setup) else {
+        return Ok(());
+    };
+    let Some(interface) = exit.interface.as_deref() else {
+        return Ok(());
+    };
+    let dns_servers = match &nym_vpn.generic_config.dns_filter {
+        Some(config) => {
+            let filter = DnsFilter::load(config, nym_vpn.dns_filter_stats.clone()).await?;
+            let stub_ip = IpAddr::V4(exit.connection_info.private_ipv4);
+            let stub = DnsStub::bind(stub_ip, &nym_vpn.generic_config.dns, None, Some(filter))
+                .await
+                .map_err(SetupWgTunnelError::FailedToStartDnsStub)?;
+            // Stopped along with the exit, which is not a failure
+            let mut stub_task_client = task_manager.subscribe_named("dns_stub");
+            stub_task_client.disarm();
+            *dns_stub = Some(tokio::spawn(stub.run(stub_task_client)));
+            vec![stub_ip]
+        }
+        None => nym_vpn.generic_config.dns.servers_or_default(),
+    };
+    info!("Resolving DNS through {interface}: {dns_servers:?}");
+    tokio::task::block_in_place(|| dns_monitor.set(interface, &dns_servers))?;
+    Ok(())
+}
+
+// These are stopped when switching gateways, which is not a failure, so they are disarmed
+fn spawn_bandwidth_client(
+    task_manager: &mut TaskManager,
+    name: &str,
+    wg_gateway_client: WgGatewayClient,
+) -> JoinHandle<()> {
+    let mut bandwidth_task_client =
+        task_manager.subscribe_named(format!("bandwidth_{name}_client"));
+    bandwidth_task_client.disarm();
+    tokio::spawn(wg_gateway_client.run(bandwidth_task_client))
+}
+
+// Each hop carries the traffic to the endpoint of the hop after it, and the exit carries the
+// tunneled networks
+fn chain_wireguard_configs(
+    nym_vpn: &NymVpn<WireguardVpn>,
+    wireguard_configs: &mut [WireguardConfig],
+    wire_mtu: u16,
+    entry_is_ipv6: bool,
+) -> std::result::Result<(), SetupWgTunnelError> {
+    let hop_count = wireguard_configs.len();
+    let mtus = pmtu::tunnel_mtus(wire_mtu, entry_is_ipv6, hop_count).ok_or(
+        SetupWgTunnelError::PathMtuTooSmall {
+            wire_mtu,
+            hop_count,
+        },
+    )?;
+    for (wireguard_config, mtu) in wireguard_configs.iter_mut().zip(mtus) {
+        wireguard_config.talpid_config.mtu = mtu;
+        // Start over from what the gateway assigned, in case the config was chained before
+        let private_ipv4 = IpAddr::from(wireguard_config.gateway_data.private_ipv4);
+        for peer in &mut wireguard_config.talpid_config.peers {
+            peer.allowed_ips = vec![private_ipv4.into()];
+        }
+    }
+
+    for index in 1..wireguard_configs.len() {
+        let next_hop_endpoints = wireguard_configs[index]
+            .talpid_config
+            .peers
+            .iter()
+            .map(|peer| IpNetwork::from(peer.endpoint.ip()))
+            .collect::<Vec<_>>();
+        wireguard_configs[index - 1]
             .talpid_config
             .peers
             .iter_mut()
             .for_each(|peer| {
-                peer.allowed_ips
-                    .append(&mut replace_default_prefixes(catch_all_ipv4()));
-                peer.allowed_ips
-                    .append(&mut replace_default_prefixes(catch_all_ipv6()));
+                peer.allowed_ips.extend(next_hop_endpoints.iter().copied());
             });
+    }
+    // If routing is disabled, we don't append the routing rules for the tunneled networks
+    if !nym_vpn.generic_config.disable_routing {
+        let tunneled_networks = nym_vpn
+            .generic_config
+            .split_tunnel
+            .tunneled_networks()
+            .into_iter()
+            .flat_map(replace_default_prefixes)
+            .collect::<Vec<_>>();
+        if let Some(exit_wireguard_config) = wireguard_configs.last_mut() {
+            exit_wireguard_config
+                .talpid_config
+                .peers
+                .iter_mut()
+                .for_each(|peer| {
+                    peer.allowed_ips.extend(tunneled_networks.iter().copied());
+                });
+        }
     } else {
         info!("Routing is disabled, skipping adding routes");
     }
-    info!("Entry wireguard config: \n{entry_wireguard_config}");
-    info!("Exit wireguard config: \n{exit_wireguard_config}");
+}
 
-    let default_node = if let Some(addr) = default_lan_gateway_ip.0.gateway.and_then(|g| {
-        g.ipv4
-            .first()
-            .map(|a| IpAddr::from(*a))
-            .or(g.ipv6.first().map(|a| IpAddr::from(*a)))
-    }) {
-        Node::new(addr, default_lan_gateway_ip.0.name)
-    } else {
-        Node::device(default_lan_gateway_ip.0.name)
-    };
-    let _routes = replace_default_prefixes(entry_gateway_ip.into())
-        .into_iter()
-        .map(move |ip| RequiredRoute::new(ip, default_node.clone()));
+// The entry gateway, and any networks excluded from the tunnel, are routed outside the tunnels
+#[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+async fn add_exception_routes(
+    route_manager: &mut RouteManager,
+    entry_gateway_ip: IpAddr,
+    split_tunnel: &SplitTunnelConfig,
+    default_lan_gateway_ip: routing::LanGatewayIp,
+) -> std::result::Result<(), SetupWgTunnelError> {
+    if !split_tunnel.exclude_networks.is_empty() {
+        info!(
+            "Excluding networks from the tunnel: {:?}",
+            split_tunnel.exclude_networks
+        );
+    }
+    let routes = std::iter::once(IpNetwork::from(entry_gateway_ip))
+        .chain(split_tunnel.exclude_networks.iter().copied())
+        .flat_map(replace_default_prefixes)
+        .map(move |ip| RequiredRoute::new(ip, default_lan_gateway_ip.node_for(ip.ip())));
     #[cfg(target_os = "linux")]
     {
-        let _routes = _routes.map(|route| route.use_main_table(false));
-        route_manager.add_routes(_routes.collect()).await?;
+        let routes = routes.map(|route| route.use_main_table(false));
+        route_manager.add_routes(routes.collect()).await?;
     }
+    Ok(())
+}
 
-    std::env::set_var("TALPID_FORCE_USERSPACE_WIREGUARD", "1");
-    let (wireguard_waiting_entry, event_rx) = create_wireguard_tunnel(
+async fn start_wg_hop(
+    nym_vpn: &NymVpn<WireguardVpn>,
+    name: &str,
+    wireguard_config: WireguardConfig,
+    task_manager: &mut TaskManager,
+    route_manager: &mut RouteManager,
+) -> std::result::Result<TunnelSetup<WgTunnelSetup>, SetupWgTunnelError> {
+    info!("Wireguard config for {name} hop: \n{wireguard_config}");
+    let (mut wireguard_waiting, event_rx) = create_wireguard_tunnel(
         route_manager,
-        task_manager.subscribe_named("entry_wg_tunnel"),
+        task_manager.subscribe_named(format!("{name}_wg_tunnel")),
         nym_vpn.tun_provider.clone(),
-        entry_wireguard_config.clone(),
+        wireguard_config.clone(),
     )?;
 
-    // Wait for entry gateway routes to be finished before moving to exit gateway routes, as the two might race if
-    // started one after the other
-    debug!("Waiting for first interface up");
+    // Wait for the routes of each hop to be finished before moving to the next one, as they
+    // might race if started one after the other
+    debug!("Waiting for {name} interface up");
     let metadata = wait_interface_up(event_rx).await.map_err(|source| {
         SetupWgTunnelError::FailedToBringInterfaceUp {
-            gateway_id: Box::new(entry_wireguard_config.gateway_id),
-            public_key: entry_wireguard_config.gateway_data.public_key.to_base64(),
+            gateway_id: Box::new(wireguard_config.gateway_id),
+            public_key: wireguard_config.gateway_data.public_key.to_base64(),
             source,
         }
     })?;
 
     info!(
-        "Created entry tun device {device_name} with ip={device_ip:?}",
+        "Created {name} tun device {device_name} with ip={device_ip:?}",
         device_name = metadata.interface,
         device_ip = metadata.ips
     );
+    wireguard_waiting.interface = Some(metadata.interface);
+    Ok(TunnelSetup {
+        specific_setup: wireguard_waiting,
+    })
+}
 
-    let (wireguard_waiting_exit, event_rx) = create_wireguard_tunnel(
-        route_manager,
-        task_manager.subscribe_named("exit_wg_tunnel"),
-        nym_vpn.tun_provider.clone(),
-        exit_wireguard_config.clone(),
+async fn start_wg_tunnels(
+    nym_vpn: &NymVpn<WireguardVpn>,
+    registration: WgGatewaysRegistration,
+    task_manager: &mut TaskManager,
+    route_manager: &mut RouteManager,
+    default_lan_gateway_ip: routing::LanGatewayIp,
+    dns_monitor: &mut DnsMonitor,
+) -> std::result::Result<AllTunnelsSetup, SetupWgTunnelError> {
+    let WgGatewaysRegistration {
+        mut wireguard_configs,
+        entry_gateway_ip,
+        wg_gateway_clients,
+    } = registration;
+    let hop_names = hop_names(wireguard_configs.len());
+
+    let bandwidth_clients = wg_gateway_clients
+        .into_iter()
+        .zip(&hop_names)
+        .map(|(wg_gateway_client, name)| {
+            spawn_bandwidth_client(task_manager, name, wg_gateway_client)
+        })
+        .collect();
+
+    // Probe the path to the entry before bringing up the interfaces, so that the nested tunnels
+    // fit in what the path can carry
+    let entry_endpoint = wireguard_configs[0]
+        .talpid_config
+        .peers
+        .first()
+        .map(|peer| peer.endpoint)
+        .expect("the entry config always has a peer");
+    let wire_mtu = pmtu::probe_path_mtu(entry_endpoint)
+        .await
+        .unwrap_or(pmtu::DEFAULT_WIRE_MTU);
+    chain_wireguard_configs(
+        nym_vpn,
+        &mut wireguard_configs,
+        wire_mtu,
+        entry_endpoint.is_ipv6(),
     )?;
 
-    debug!("Waiting for second interface up");
-    let metadata = wait_interface_up(event_rx).await.map_err(|source| {
-        SetupWgTunnelError::FailedToBringInterfaceUp {
-            gateway_id: Box::new(exit_wireguard_config.gateway_id),
-            public_key: exit_wireguard_config.gateway_data.public_key.to_base64(),
-            source,
-        }
-    })?;
+    add_exception_routes(
+        route_manager,
+        entry_gateway_ip,
+        &nym_vpn.generic_config.split_tunnel,
+        default_lan_gateway_ip,
+    )
+    .await?;
 
-    info!(
-        "Created exit tun device {device_name} with ip={device_ip:?}",
-        device_name = metadata.interface,
-        device_ip = metadata.ips
-    );
-    let entry = TunnelSetup {
-        specific_setup: wireguard_waiting_entry,
-    };
-    let exit = TunnelSetup {
-        specific_setup: wireguard_waiting_exit,
-    };
+    std::env::set_var("TALPID_FORCE_USERSPACE_WIREGUARD", "1");
+    let mut hops = Vec::with_capacity(wireguard_configs.len());
+    for (name, wireguard_config) in hop_names.iter().zip(wireguard_configs) {
+        hops.push(
+            start_wg_hop(nym_vpn, name, wireguard_config, task_manager, route_manager).await?,
+        );
+    }
+    let mut dns_stub = None;
+    set_wg_dns(nym_vpn, &hops, &mut dns_stub, task_manager, dns_monitor).await?;
 
-    Ok(AllTunnelsSetup::Wg { entry, exit })
+    Ok(AllTunnelsSetup::Wg {
+        hops,
+        bandwidth_clients,
+        wire_mtu,
+        max_wire_mtu: pmtu::DEFAULT_WIRE_MTU,
+        dns_stub,
+    })
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -287,7 +813,8 @@ async fn setup_mix_tunnel(
     task_manager: &mut TaskManager,
     route_manager: &mut RouteManager,
     dns_monitor: &mut DnsMonitor,
-    gateway_directory_client: GatewayClient,
+    kill_switch: &mut KillSwitch,
+    gateway_directory_client: &GatewayClient,
     exit_mix_addresses: &IpPacketRouterAddress,
     default_lan_gateway_ip: routing::LanGatewayIp,
 ) -> std::result::Result<AllTunnelsSetup, SetupMixTunnelError> {
@@ -299,9 +826,10 @@ async fn setup_mix_tunnel(
             route_manager,
             exit_mix_addresses,
             task_manager,
-            &gateway_directory_client,
+            gateway_directory_client,
             default_lan_gateway_ip,
             dns_monitor,
+            kill_switch,
         )
         .await?;
 
@@ -309,6 +837,7 @@ async fn setup_mix_tunnel(
         specific_setup: MixTunnelSetup {
             mixnet_connection_info: connection_info.0,
             exit_connection_info: connection_info.1,
+            exit_ipr_tx: connection_info.2,
         },
     }))
 }
@@ -318,7 +847,8 @@ pub(crate) async fn setup_tunnel(
     task_manager: &mut TaskManager,
     route_manager: &mut RouteManager,
     dns_monitor: &mut DnsMonitor,
-) -> Result<AllTunnelsSetup> {
+    kill_switch: &mut KillSwitch,
+) -> Result<(AllTunnelsSetup, SwitchGatewayContext)> {
     // The user agent is set on HTTP REST API calls, and ideally should identify the type of
     // client. This means it needs to be set way higher in the call stack, but set a default for
     // what we know here if we don't have anything.
@@ -338,8 +868,31 @@ pub(crate) async fn setup_tunnel(
             },
         )?;
 
-    let SelectedGateways { entry, exit } =
-        select_gateways(&gateway_directory_client, nym_vpn).await?;
+    if let SpecificVpn::Wg(vpn) = nym_vpn {
+        validate_hop_count(vpn.vpn_config.hop_count).map_err(Error::from)?;
+    }
+
+    let selected_gateways = select_gateways(
+        &gateway_directory_client,
+        nym_vpn,
+        &nym_vpn.entry_point(),
+        &nym_vpn.exit_point(),
+        &[],
+    )
+    .await?;
+    let SelectedGateways { entry, exit, .. } = &selected_gateways;
+
+    // The block starts again once the mixnet client is connected to the entry gateway
+    kill_switch.allow_mixnet_bootstrap()?;
+    let kill_switch_entry_gateway = if kill_switch.is_enabled() {
+        Some(
+            lookup_entry_gateway_endpoint(&gateway_directory_client, entry)
+                .await
+                .map_err(Error::from)?,
+        )
+    } else {
+        None
+    };
 
     // Get the IP address of the local LAN gateway
     let default_lan_gateway_ip = routing::LanGatewayIp::get_default_interface()?;
@@ -362,9 +915,16 @@ pub(crate) async fn setup_tunnel(
     .map_err(|_| Error::StartMixnetClientTimeout(MIXNET_CLIENT_STARTUP_TIMEOUT_SECS))?
     .map_err(Error::FailedToSetupMixnetClient)?;
 
+    if let Some(entry_gateway) = kill_switch_entry_gateway {
+        if let Err(err) = kill_switch.allow_entry_gateway(entry_gateway) {
+            mixnet_client.disconnect().await;
+            return Err(err);
+        }
+    }
+
     let tunnels_setup = match nym_vpn {
         SpecificVpn::Wg(vpn) => {
-            let auth_addresses = match setup_auth_addresses(&entry, &exit) {
+            let auth_addresses = match setup_auth_addresses(&selected_gateways) {
                 Ok(auth_addr) => auth_addr,
                 Err(err) => {
                     // Put in some manual error handling, the correct long-term solution is that handling
@@ -379,61 +939,345 @@ pub(crate) async fn setup_tunnel(
             // happens!
             setup_wg_tunnel(
                 vpn,
-                mixnet_client,
+                mixnet_client.clone(),
                 task_manager,
                 route_manager,
-                gateway_directory_client,
+                &gateway_directory_client,
                 auth_addresses,
-                default_lan_gateway_ip,
+                default_lan_gateway_ip.clone(),
+                dns_monitor,
             )
             .await
             .map_err(Error::from)
         }
         SpecificVpn::Mix(vpn) => setup_mix_tunnel(
             vpn,
-            mixnet_client,
+            mixnet_client.clone(),
             task_manager,
             route_manager,
             dns_monitor,
-            gateway_directory_client,
+            kill_switch,
+            &gateway_directory_client,
             &exit.ipr_address.unwrap(),
-            default_lan_gateway_ip,
+            default_lan_gateway_ip.clone(),
         )
         .await
         .map_err(Error::from),
     }?;
-    Ok(tunnels_setup)
+    let switch_context = SwitchGatewayContext {
+        gateway_directory_client,
+        mixnet_client,
+        default_lan_gateway_ip,
+    };
+    Ok((tunnels_setup, switch_context))
 }
 
-fn setup_auth_addresses(
+// Switch the entry or exit gateway of the running tunnels. In mixnet mode only the exit can be
+// switched, since the entry gateway is the one the mixnet client is connected to. In wireguard
+// mode we register with the new gateways and restart only the hops whose gateway or next hop
+// changed, while keeping the mixnet client, the other hops and the routes. If the tunnels are
+// lost in the process, what is left of them is kept in `tunnels` for the shutdown.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn switch_gateway(
+    nym_vpn: &mut SpecificVpn,
+    tunnels: &mut Option<AllTunnelsSetup>,
+    switch_context: &SwitchGatewayContext,
+    entry_point: Option<EntryPoint>,
+    exit_point: Option<ExitPoint>,
+    excluded_exits: &[NodeIdentity],
+    task_manager: &mut TaskManager,
+    route_manager: &mut RouteManager,
+    dns_monitor: &mut DnsMonitor,
+) -> std::result::Result<NymVpnStatusMessage, SwitchGatewayError> {
+    // Whichever gateway is not being switched stays the same
+    let Some(current_tunnels) = tunnels.as_ref() else {
+        return Err(SwitchGatewayError::TunnelLost(
+            "no tunnels to switch gateways for".into(),
+        ));
+    };
+    if matches!(current_tunnels, AllTunnelsSetup::Mix(_)) && entry_point.is_some() {
+        return Err(SwitchGatewayError::EntrySwitchNotSupportedWithMixnet);
+    }
+    let current_entry = current_tunnels.entry_gateway()?;
+    let current_exit = current_tunnels.exit_gateway()?;
+    let entry_point = entry_point.unwrap_or(EntryPoint::Gateway {
+        identity: current_entry,
+    });
+    let exit_point = exit_point.unwrap_or(ExitPoint::Gateway {
+        identity: current_exit,
+    });
+    info!("Switching gateways, entry: {entry_point}, exit: {exit_point}");
+
+    // Any middle hops are picked anew, so that they stay distinct from the new entry and exit
+    let selected_gateways = select_gateways(
+        &switch_context.gateway_directory_client,
+        nym_vpn,
+        &entry_point,
+        &exit_point,
+        excluded_exits,
+    )
+    .await?;
+    let SelectedGateways { entry, exit, .. } = &selected_gateways;
+
+    match nym_vpn {
+        SpecificVpn::Mix(vpn) => {
+            let Some(AllTunnelsSetup::Mix(TunnelSetup { specific_setup })) = tunnels.as_mut()
+            else {
+                unreachable!("mixnet mode always has a mixnet tunnel");
+            };
+            specific_setup.exit_connection_info = vpn
+                .switch_exit(
+                    &switch_context.mixnet_client,
+                    exit,
+                    specific_setup.exit_connection_info,
+                    &specific_setup.exit_ipr_tx,
+                    task_manager,
+                )
+                .await?;
+        }
+        SpecificVpn::Wg(vpn) => {
+            let Some(AllTunnelsSetup::Wg {
+                hops,
+                bandwidth_clients,
+                wire_mtu,
+                max_wire_mtu,
+                dns_stub,
+            }) = tunnels.as_mut()
+            else {
+                unreachable!("wireguard mode always has wireguard tunnels");
+            };
+            let auth_addresses = setup_auth_addresses(&selected_gateways)?;
+            // Register before touching anything, so that we keep the current tunnels if the new
+            // gateways can't be used
+            let switch = register_switched_wg_gateways(
+                vpn,
+                hops,
+                &switch_context.mixnet_client,
+                &switch_context.gateway_directory_client,
+                auth_addresses,
+            )
+            .await?;
+            // A new entry comes with a new path, that might not drop what the old one did
+            if switch.entry_gateway_ip.is_some() {
+                *max_wire_mtu = pmtu::DEFAULT_WIRE_MTU;
+            }
+            restart_wg_hops(
+                vpn,
+                hops,
+                bandwidth_clients,
+                dns_stub,
+                *wire_mtu,
+                switch,
+                switch_context.default_lan_gateway_ip.clone(),
+                task_manager,
+                route_manager,
+                dns_monitor,
+            )
+            .await
+            .map_err(|err| SwitchGatewayError::TunnelLost(Box::new(err)))?;
+        }
+    }
+    info!(
+        "Switched gateways, entry: {}, exit: {}",
+        entry.identity(),
+        exit.identity()
+    );
+
+    let tunnels = tunnels
+        .as_ref()
+        .ok_or_else(|| SwitchGatewayError::TunnelLost("no tunnels after switching".into()))?;
+    Ok(tunnels.status_message()?)
+}
+
+// Switch away from an exit that keeps failing, to one picked by the failover policy. The failed
+// exit is left out of the selection for a while.
+pub(crate) async fn failover_exit(
+    nym_vpn: &mut SpecificVpn,
+    tunnels: &mut Option<AllTunnelsSetup>,
+    switch_context: &SwitchGatewayContext,
+    exit_failover: &mut ExitFailover,
+    task_manager: &mut TaskManager,
+    route_manager: &mut RouteManager,
+    dns_monitor: &mut DnsMonitor,
+) -> std::result::Result<NymVpnStatusMessage, SwitchGatewayError> {
+    let failed_exit = tunnels
+        .as_ref()
+        .ok_or_else(|| SwitchGatewayError::TunnelLost("no tunnels to fail over".into()))?
+        .exit_gateway()?;
+    let now = Instant::now();
+    exit_failover.exclude(failed_exit, now);
+
+    let gateways = switch_context
+        .gateway_directory_client
+        .lookup_all_gateways()
+        .await
+        .map_err(|source| GatewayDirectoryError::FailedToLookupGateways { source })?;
+    let failed_exit_country = gateways
+        .gateway_with_identity(&failed_exit)
+        .and_then(|gateway| gateway.two_letter_iso_country_code());
+    let exit_point = exit_failover
+        .failover_exit_point(&nym_vpn.exit_point(), failed_exit_country)
+        .ok_or(SwitchGatewayError::ExitPinned)?;
+    info!("Failing over from exit gateway {failed_exit} to {exit_point}");
+
+    switch_gateway(
+        nym_vpn,
+        tunnels,
+        switch_context,
+        None,
+        Some(exit_point),
+        &exit_failover.excluded_exits(now),
+        task_manager,
+        route_manager,
+        dns_monitor,
+    )
+    .await
+}
+
+// Probe the path to the entry gateway again, and update the MTU of the running tunnels if it
+// changed. Returns the new status when it did.
+pub(crate) async fn reprobe_path_mtu(
+    tunnels: &mut AllTunnelsSetup,
+    reprobe: pmtu::Reprobe,
+) -> Option<NymVpnStatusMessage> {
+    let AllTunnelsSetup::Wg {
+        hops,
+        wire_mtu,
+        max_wire_mtu,
+        ..
+    } = tunnels
+    else {
+        return None;
+    };
+    let entry_endpoint = hops.first()?.specific_setup.endpoint;
+    let probed_mtu = pmtu::probe_path_mtu(entry_endpoint).await;
+    let new_wire_mtu = match (probed_mtu, reprobe) {
+        // A router on the path told us about a smaller MTU
+        (Some(probed_mtu), _) if probed_mtu < *wire_mtu => probed_mtu,
+        // Nothing told us why the exit stalled, so the path might be dropping the larger packets
+        // silently. Step down, and stay down until the entry changes.
+        (_, pmtu::Reprobe::Stalled) => {
+            let lowered = pmtu::lower_plateau(*wire_mtu).filter(|lowered| {
+                pmtu::max_hop_count(*lowered, entry_endpoint.is_ipv6()) >= hops.len()
+            })?;
+            info!("No smaller path MTU was reported, trying {lowered} in case of a black hole");
+            *max_wire_mtu = lowered;
+            lowered
+        }
+        (Some(probed_mtu), pmtu::Reprobe::Periodic) => probed_mtu.min(*max_wire_mtu),
+        (None, pmtu::Reprobe::Periodic) => return None,
+    };
+    if new_wire_mtu == *wire_mtu {
+        return None;
+    }
+    let Some(mtus) = pmtu::tunnel_mtus(new_wire_mtu, entry_endpoint.is_ipv6(), hops.len()) else {
+        warn!(
+            "Path MTU {new_wire_mtu} is too small for {} wireguard hops, keeping {wire_mtu}",
+            hops.len()
+        );
+        return None;
+    };
+    info!("Path MTU to the entry gateway changed from {wire_mtu} to {new_wire_mtu}");
+
+    for (hop, mtu) in hops.iter_mut().zip(mtus) {
+        let Some(ref interface) = hop.specific_setup.interface else {
+            continue;
+        };
+        match pmtu::set_interface_mtu(interface, mtu).await {
+            Ok(()) => hop.specific_setup.connection_info.mtu = mtu,
+            Err(err) => warn!("Failed to set the MTU of {interface} to {mtu}: {err}"),
+        }
+    }
+    *wire_mtu = new_wire_mtu;
+    tunnels.status_message().ok()
+}
+
+async fn lookup_entry_gateway_endpoint(
+    gateway_directory_client: &GatewayClient,
     entry: &nym_gateway_directory::Gateway,
-    exit: &nym_gateway_directory::Gateway,
+) -> std::result::Result<SocketAddr, SetupMixTunnelError> {
+    // Prefer the address from the gateway list, which may come from the cache, since the
+    // directory might not be reachable while the kill switch is blocking
+    let entry_gateway_ip = match entry.host {
+        Some(nym_topology::NetworkAddress::IpAddr(ip)) => ip,
+        _ => {
+            let gateway_id = entry.identity().to_base58_string();
+            gateway_directory_client
+                .lookup_gateway_ip(&gateway_id)
+                .await
+                .map_err(|source| SetupMixTunnelError::FailedToLookupGatewayIp {
+                    gateway_id,
+                    source,
+                })?
+        }
+    };
+    let port = entry
+        .clients_ws_port
+        .unwrap_or(DEFAULT_GATEWAY_CLIENTS_WS_PORT);
+    Ok(SocketAddr::new(entry_gateway_ip, port))
+}
+
+fn setup_auth_addresses(
+    selected_gateways: &SelectedGateways,
 ) -> std::result::Result<AuthAddresses, SetupWgTunnelError> {
-    let entry_authenticator_address = entry
-        .authenticator_address
-        .ok_or(SetupWgTunnelError::AuthenticatorAddressNotFound)?;
-    let exit_authenticator_address = exit
-        .authenticator_address
-        .ok_or(SetupWgTunnelError::AuthenticatorAddressNotFound)?;
+    let authenticator_address = |gateway: &nym_gateway_directory::Gateway| {
+        gateway
+            .authenticator_address
+            .ok_or(SetupWgTunnelError::AuthenticatorAddressNotFound)
+    };
+    let middle_authenticator_addresses = selected_gateways
+        .middles
+        .iter()
+        .map(authenticator_address)
+        .collect::<std::result::Result<Vec<_>, _>>()?;
     Ok(AuthAddresses::new(
-        entry_authenticator_address,
-        exit_authenticator_address,
-    ))
+        authenticator_address(&selected_gateways.entry)?,
+        authenticator_address(&selected_gateways.exit)?,
+    )
+    .with_middle_addrs(middle_authenticator_addresses))
 }
 
-struct SelectedGateways {
-    entry: nym_gateway_directory::Gateway,
-    exit: nym_gateway_directory::Gateway,
+pub(crate) struct SelectedGateways {
+    pub(crate) entry: nym_gateway_directory::Gateway,
+    // Only used for wireguard chains longer than two hops
+    pub(crate) middles: Vec<nym_gateway_directory::Gateway>,
+    pub(crate) exit: nym_gateway_directory::Gateway,
 }
 
 async fn select_gateways(
     gateway_directory_client: &GatewayClient,
     nym_vpn: &SpecificVpn,
+    entry_point: &EntryPoint,
+    exit_point: &ExitPoint,
+    excluded_exits: &[NodeIdentity],
+) -> std::result::Result<SelectedGateways, GatewayDirectoryError> {
+    let middle_hops = match nym_vpn {
+        SpecificVpn::Wg(vpn) => vpn.vpn_config.hop_count.saturating_sub(2),
+        SpecificVpn::Mix(_) => 0,
+    };
+    select_gateways_for_mode(
+        gateway_directory_client,
+        matches!(nym_vpn, SpecificVpn::Mix(_)),
+        middle_hops,
+        entry_point,
+        exit_point,
+        excluded_exits,
+    )
+    .await
+}
+
+pub(crate) async fn select_gateways_for_mode(
+    gateway_directory_client: &GatewayClient,
+    mixnet_mode: bool,
+    middle_hops: usize,
+    entry_point: &EntryPoint,
+    exit_point: &ExitPoint,
+    excluded_exits: &[NodeIdentity],
 ) -> std::result::Result<SelectedGateways, GatewayDirectoryError> {
     // The set of exit gateways is smaller than the set of entry gateways, so we start by selecting
     // the exit gateway and then filter out the exit gateway from the set of entry gateways.
 
-    let (mut entry_gateways, exit_gateways) = if let SpecificVpn::Mix(_) = nym_vpn {
+    let (entry_gateways, exit_gateways) = if mixnet_mode {
         // Setup the gateway that we will use as the exit point
         let exit_gateways = gateway_directory_client
             .lookup_exit_gateways()
@@ -453,17 +1297,33 @@ async fn select_gateways(
         (all_gateways.clone(), all_gateways)
     };
 
-    let exit_gateway = nym_vpn
-        .exit_point()
+    // Exits that recently failed us are left out, until they have had time to recover
+    let exit_gateways = if excluded_exits.is_empty() {
+        exit_gateways
+    } else {
+        GatewayList::new(
+            exit_gateways
+                .into_inner()
+                .into_iter()
+                .filter(|gateway| !excluded_exits.contains(gateway.identity()))
+                .collect(),
+        )
+    };
+    let exit_gateway = exit_point
         .lookup_gateway(&exit_gateways)
+        .await
         .map_err(|source| GatewayDirectoryError::FailedToSelectExitGateway { source })?;
 
-    // Exclude the exit gateway from the list of entry gateways for privacy reasons
-    entry_gateways.remove_gateway(&exit_gateway);
+    // Exclude the exit gateway, and any other on its host, from the list of entry gateways for
+    // privacy reasons
+    let entry_gateways =
+        entry_gateways_apart_from_exit(entry_gateways, entry_point, &exit_gateway)?;
 
-    let entry_gateway = nym_vpn
-        .entry_point()
-        .lookup_gateway(&entry_gateways)
+    // We don't know where the user is, so the best entry is not scored by distance. The latency
+    // we measure to it already favours the ones close to the user.
+    let entry_selector = ScoringGatewaySelector::new();
+    let entry_gateway = entry_point
+        .lookup_gateway_with_selector(&entry_gateways, &entry_selector)
         .await
         .map_err(|source| match source {
             nym_gateway_directory::Error::NoMatchingEntryGatewayForLocation {
@@ -508,8 +1368,133 @@ async fn select_gateways(
             .map_or_else(|| "none".to_string(), |ipr| ipr.to_string())
     );
 
+    let middle_gateways = if middle_hops > 0 {
+        select_middle_gateways(&entry_gateways, &entry_gateway, &exit_gateway, middle_hops).await?
+    } else {
+        Vec::new()
+    };
+    for middle_gateway in &middle_gateways {
+        info!(
+            "Using middle gateway: {}, location: {}",
+            *middle_gateway.identity(),
+            middle_gateway
+                .two_letter_iso_country_code()
+                .map_or_else(|| "unknown".to_string(), |code| code.to_string()),
+        );
+    }
+
     Ok(SelectedGateways {
         entry: entry_gateway,
+        middles: middle_gateways,
         exit: exit_gateway,
     })
 }
+
+// Leave out the entry gateways that would end up on the same host as the exit, and refuse an entry
+// that was asked for by identity if it does
+pub(crate) fn entry_gateways_apart_from_exit(
+    entry_gateways: GatewayList,
+    entry_point: &EntryPoint,
+    exit_gateway: &nym_gateway_directory::Gateway,
+) -> std::result::Result<GatewayList, GatewayDirectoryError> {
+    if let EntryPoint::Gateway { identity } = entry_point {
+        if identity == exit_gateway.identity()
+            || entry_gateways
+                .gateway_with_identity(identity)
+                .is_some_and(|entry| same_host(entry, exit_gateway))
+        {
+            return Err(GatewayDirectoryError::SameEntryAndExitGateway {
+                gateway_id: identity.to_base58_string(),
+            });
+        }
+    }
+    Ok(GatewayList::new(
+        entry_gateways
+            .into_inner()
+            .into_iter()
+            .filter(|gateway| {
+                gateway.identity() != exit_gateway.identity() && !same_host(gateway, exit_gateway)
+            })
+            .collect(),
+    ))
+}
+
+fn gateway_host(gateway: &nym_gateway_directory::Gateway) -> Option<String> {
+    gateway.host.as_ref().map(ToString::to_string)
+}
+
+fn same_host(a: &nym_gateway_directory::Gateway, b: &nym_gateway_directory::Gateway) -> bool {
+    gateway_host(a).is_some_and(|host| Some(host) == gateway_host(b))
+}
+
+// Pick the hops in between the entry and the exit. Each one is in a country, and on a host, that
+// no other hop uses, so that no single jurisdiction or operator sees more than one hop.
+async fn select_middle_gateways(
+    gateways: &GatewayList,
+    entry: &nym_gateway_directory::Gateway,
+    exit: &nym_gateway_directory::Gateway,
+    count: usize,
+) -> std::result::Result<Vec<nym_gateway_directory::Gateway>, GatewayDirectoryError> {
+    let mut used = vec![entry.clone(), exit.clone()];
+    let mut middles = Vec::with_capacity(count);
+    let selector = ScoringGatewaySelector::new();
+    while middles.len() < count {
+        let candidates = GatewayList::new(
+            gateways
+                .clone()
+                .into_inner()
+                .into_iter()
+                .filter(|gateway| gateway.authenticator_address.is_some())
+                .filter(|gateway| is_distinct_hop(gateway, &used))
+                .collect(),
+        );
+        if candidates.is_empty() {
+            return Err(GatewayDirectoryError::NotEnoughMiddleGateways {
+                requested: count,
+                found: middles.len(),
+            });
+        }
+        let middle = selector
+            .select(&candidates, GatewayRole::Entry)
+            .await
+            .map_err(|source| GatewayDirectoryError::FailedToSelectEntryGateway { source })?;
+        used.push(middle.clone());
+        middles.push(middle);
+    }
+    Ok(middles)
+}
+
+// Gateways without a known country or host can't be told apart from the others, so they are not
+// used as middle hops
+fn is_distinct_hop(
+    gateway: &nym_gateway_directory::Gateway,
+    used: &[nym_gateway_directory::Gateway],
+) -> bool {
+    let (Some(country), Some(_)) = (gateway.two_letter_iso_country_code(), gateway_host(gateway))
+    else {
+        return false;
+    };
+    used.iter().all(|other| {
+        other.identity() != gateway.identity()
+            && other.two_letter_iso_country_code() != Some(country)
+            && !same_host(other, gateway)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hop_count_is_validated() {
+        assert!(validate_hop_count(1).is_err());
+        assert!(validate_hop_count(MAX_WG_HOP_COUNT).is_ok());
+        assert!(validate_hop_count(MAX_WG_HOP_COUNT + 1).is_err());
+    }
+
+    #[test]
+    fn hops_are_named_from_entry_to_exit() {
+        assert_eq!(hop_names(2), ["entry", "exit"]);
+        assert_eq!(hop_names(3), ["entry", "middle_1", "exit"]);
+    }
+}
@@ -9,7 +9,7 @@ use std::{
 
 use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
 use nym_bandwidth_controller_pre_ecash::BandwidthStatusMessage;
-use nym_connection_monitor::ConnectionMonitorStatus;
+use nym_connection_monitor::{ConnectionMonitorStatus, PingStats};
 use nym_gateway_directory::{EntryPoint as GwEntryPoint, ExitPoint as GwExitPoint};
 use nym_ip_packet_requests::IpPair;
 use nym_sdk::UserAgent as NymUserAgent;
@@ -316,6 +316,7 @@ pub enum EntryPoint {
     Location { location: String },
     RandomLowLatency,
     Random,
+    Best,
 }
 
 impl From<EntryPoint> for GwEntryPoint {
@@ -325,6 +326,7 @@ impl From<EntryPoint> for GwEntryPoint {
             EntryPoint::Location { location } => GwEntryPoint::Location { location },
             EntryPoint::RandomLowLatency => GwEntryPoint::RandomLowLatency,
             EntryPoint::Random => GwEntryPoint::Random,
+            EntryPoint::Best => GwEntryPoint::Best,
         }
     }
 }
@@ -335,6 +337,7 @@ pub enum ExitPoint {
     Address { address: Recipient },
     Gateway { identity: NodeIdentity },
     Location { location: String },
+    Best,
 }
 
 impl From<ExitPoint> for GwExitPoint {
@@ -343,6 +346,7 @@ impl From<ExitPoint> for GwExitPoint {
             ExitPoint::Address { address } => GwExitPoint::Address { address },
             ExitPoint::Gateway { identity } => GwExitPoint::Gateway { identity },
             ExitPoint::Location { location } => GwExitPoint::Location { location },
+            ExitPoint::Best => GwExitPoint::Best,
         }
     }
 }
@@ -385,6 +389,10 @@ pub enum NymVpnStatus {
         entry_connection_info: WireguardConnectionInfo,
         exit_connection_info: WireguardConnectionInfo,
     },
+    ExitFailover {
+        failed_exit_gateway: NodeIdentity,
+        exit_gateway: NodeIdentity,
+    },
 }
 
 impl From<NymVpnStatusMessage> for NymVpnStatus {
@@ -400,10 +408,18 @@ impl From<NymVpnStatusMessage> for NymVpnStatus {
             NymVpnStatusMessage::WgConnectionInfo {
                 entry_connection_info,
                 exit_connection_info,
+                ..
             } => NymVpnStatus::WgConnectInfo {
                 entry_connection_info: entry_connection_info.into(),
                 exit_connection_info: exit_connection_info.into(),
             },
+            NymVpnStatusMessage::ExitFailover {
+                failed_exit_gateway,
+                exit_gateway,
+            } => NymVpnStatus::ExitFailover {
+                failed_exit_gateway,
+                exit_gateway,
+            },
         }
     }
 }
@@ -438,6 +454,32 @@ pub enum ConnectionStatus {
     ExitGatewayRoutingErrorIpv6,
     ConnectedIpv4,
     ConnectedIpv6,
+    ConnectionQuality {
+        mixnet: Option<PingQuality>,
+        exit: Option<PingQuality>,
+    },
+}
+
+#[derive(Debug, PartialEq, uniffi::Record, Clone)]
+pub struct PingQuality {
+    pub rtt_p50_ms: u64,
+    pub rtt_p90_ms: u64,
+    pub rtt_p99_ms: u64,
+    pub jitter_ms: u64,
+    // Fraction of the pings that were lost, between 0 and 1
+    pub packet_loss: f64,
+}
+
+impl From<PingStats> for PingQuality {
+    fn from(value: PingStats) -> Self {
+        PingQuality {
+            rtt_p50_ms: value.rtt_p50.as_millis() as u64,
+            rtt_p90_ms: value.rtt_p90.as_millis() as u64,
+            rtt_p99_ms: value.rtt_p99.as_millis() as u64,
+            jitter_ms: value.jitter.as_millis() as u64,
+            packet_loss: value.packet_loss,
+        }
+    }
 }
 
 impl From<ConnectionMonitorStatus> for ConnectionStatus {
@@ -454,6 +496,12 @@ impl From<ConnectionMonitorStatus> for ConnectionStatus {
             }
             ConnectionMonitorStatus::ConnectedIpv4 => ConnectionStatus::ConnectedIpv4,
             ConnectionMonitorStatus::ConnectedIpv6 => ConnectionStatus::ConnectedIpv6,
+            ConnectionMonitorStatus::ConnectionQuality(quality) => {
+                ConnectionStatus::ConnectionQuality {
+                    mixnet: quality.mixnet.map(Into::into),
+                    exit: quality.exit.map(Into::into),
+                }
+            }
         }
     }
 }
@@ -7,87 +7,69 @@ use tracing::{error, info};
 
 use crate::{tunnel_setup::WgTunnelSetup, vpn::NymVpnCtrlMessage};
 
-pub(crate) async fn wait_for_interrupt(
-    mut task_manager: Option<nym_task::TaskManager>,
-    mut vpn_ctrl_rx: Option<mpsc::UnboundedReceiver<NymVpnCtrlMessage>>,
-    route_manager: RouteManager,
-    wireguard_waiting: Option<[WgTunnelSetup; 2]>,
-) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
-    let task_manager_wait = async {
-        if let Some(task_manager) = &mut task_manager {
-            task_manager.wait_for_error().await
-        } else {
-            std::future::pending().await
-        }
-    };
-
-    let vpn_ctrl_rx_wait = async {
-        if let Some(vpn_ctrl_rx) = &mut vpn_ctrl_rx {
-            vpn_ctrl_rx.next().await
-        } else {
-            std::future::pending().await
-        }
-    };
-
-    let res = tokio::select! {
+// Wait until we are told to do something, or one of the tasks fails
+pub(crate) async fn wait_for_ctrl_message(
+    task_manager: &mut nym_task::TaskManager,
+    vpn_ctrl_rx: &mut mpsc::UnboundedReceiver<NymVpnCtrlMessage>,
+) -> std::result::Result<NymVpnCtrlMessage, Box<dyn std::error::Error + Send + Sync + 'static>> {
+    tokio::select! {
         biased;
-        Some(message) = vpn_ctrl_rx_wait => match message {
-            NymVpnCtrlMessage::Stop => {
-                log::info!("Received stop message");
-                Ok(())
+        message = vpn_ctrl_rx.next() => match message {
+            Some(message) => {
+                log::info!("Received {message:?} message");
+                Ok(message)
+            }
+            None => {
+                log::error!("Unexpected channel close when waiting for interrupt");
+                Ok(NymVpnCtrlMessage::Stop)
             }
         },
-        Some(msg) = task_manager_wait => {
+        Some(msg) = task_manager.wait_for_error() => {
             log::info!("Task error: {:?}", msg);
             Err(msg)
         }
-        else => {
-            log::error!("Unexpected channel close when waiting for interrupt");
-            Ok(())
-        }
-    };
+    }
+}
 
-    if let Some(mut task_manager) = task_manager {
-        info!("Sending shutdown signal");
-        task_manager.signal_shutdown().ok();
+pub(crate) async fn shutdown(
+    mut task_manager: nym_task::TaskManager,
+    route_manager: RouteManager,
+    wireguard_waiting: Option<Vec<WgTunnelSetup>>,
+) {
+    info!("Sending shutdown signal");
+    task_manager.signal_shutdown().ok();
 
-        handle_interrupt(route_manager, wireguard_waiting).await;
+    handle_interrupt(route_manager, wireguard_waiting).await;
 
-        info!("Waiting for tasks to finish... (Press ctrl-c to force)");
-        // TODO: this contains another signal handler that needs to be moved out.
-        task_manager.wait_for_shutdown().await;
+    info!("Waiting for tasks to finish... (Press ctrl-c to force)");
+    // TODO: this contains another signal handler that needs to be moved out.
+    task_manager.wait_for_shutdown().await;
 
-        info!("Stopping mixnet client");
-    }
-    res
+    info!("Stopping mixnet client");
 }
 
 #[cfg_attr(target_os = "windows", allow(unused_mut))]
 pub(crate) async fn handle_interrupt(
     route_manager: RouteManager,
-    wireguard_waiting: Option<[WgTunnelSetup; 2]>,
+    wireguard_waiting: Option<Vec<WgTunnelSetup>>,
 ) {
     tokio::task::spawn_blocking(|| drop(route_manager))
         .await
         .ok();
-    let Some(wireguard_waiting) = wireguard_waiting else {
-        return;
-    };
-    let [entry, exit] = wireguard_waiting;
-
-    entry.tunnel_close_tx.send(()).ok();
-    if let Err(err) = entry.receiver.await {
-        error!("Error on entry signal handle {}", err);
-    }
-    if let Err(err) = entry.handle.await {
-        error!("Error on entry tunnel handle {}", err);
+    if let Some(wireguard_waiting) = wireguard_waiting {
+        close_wireguard_tunnels(wireguard_waiting).await;
     }
+}
 
-    exit.tunnel_close_tx.send(()).ok();
-    if let Err(err) = exit.receiver.await {
-        error!("Error on exit signal handle {}", err);
-    }
-    if let Err(err) = exit.handle.await {
-        error!("Error on exit tunnel handle {}", err);
+// Close the tunnels in order, starting with the entry
+pub(crate) async fn close_wireguard_tunnels(wireguard_waiting: Vec<WgTunnelSetup>) {
+    for hop in wireguard_waiting {
+        hop.tunnel_close_tx.send(()).ok();
+        if let Err(err) = hop.receiver.await {
+            error!("Error on wireguard signal handle {}", err);
+        }
+        if let Err(err) = hop.handle.await {
+            error!("Error on wireguard tunnel handle {}", err);
+        }
     }
 }
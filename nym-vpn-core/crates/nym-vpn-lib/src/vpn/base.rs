@@ -2,35 +2,44 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use std::{
-    net::IpAddr,
     path::PathBuf,
     sync::{Arc, Mutex},
+    time::Instant,
 };
 
-use futures::{channel::mpsc, SinkExt};
+use futures::{channel::mpsc, SinkExt, StreamExt};
+use nym_connection_monitor::{ConnectionMonitorConfig, ConnectionMonitorStatus};
 use nym_gateway_directory::{Config as GatewayDirectoryConfig, EntryPoint, ExitPoint};
 use nym_ip_packet_requests::IpPair;
 use nym_sdk::UserAgent;
 use nym_task::{manager::TaskStatus, TaskManager};
 use talpid_core::{dns::DnsMonitor, firewall::Firewall};
 use talpid_tunnel::tun_provider::TunProvider;
-use tokio::task::JoinHandle;
+use tokio::sync::oneshot;
 use tracing::{error, info};
-use tun2::AsyncDevice;
 
 use super::{
-    messages::NymVpnStatusMessage,
     mixnet::{MixnetClientConfig, MixnetVpn},
     wireguard::WireguardVpn,
+    NymVpnCtrlMessage, NymVpnStatusMessage,
 };
 #[cfg(target_os = "ios")]
 use crate::mobile::ios::tun_provider::OSTunProvider;
 #[cfg(target_os = "android")]
 use crate::platform::android::AndroidTunProvider;
 use crate::{
-    error::{Error, Result},
-    tunnel_setup::{AllTunnelsSetup, TunnelSetup},
-    MixnetError,
+    dns::DnsConfig,
+    dns_filter::{DnsFilterConfig, DnsFilterStatsHandle},
+    encrypted_dns::EncryptedDnsConfig,
+    error::{Error, Result, SwitchGatewayError},
+    exit_failover::{ExitFailover, ExitFailoverConfig},
+    kill_switch::{self, KillSwitch, KillSwitchConfig},
+    leak_test::{LeakTestContext, LeakTestReport},
+    mixnet::MixnetProcessorHandle,
+    pmtu::{PathMtuReprobe, Reprobe},
+    split_tunnel::SplitTunnelConfig,
+    traffic_stats::TrafficStatsHandle,
+    tunnel_setup::AllTunnelsSetup,
 };
 
 pub(crate) const MIXNET_CLIENT_STARTUP_TIMEOUT_SECS: u64 = 30;
@@ -57,8 +66,16 @@ pub struct GenericNymVpnConfig {
     /// The MTU of the TUN device.
     pub nym_mtu: Option<u16>,
 
-    /// The DNS server to use
-    pub dns: Option<IpAddr>,
+    /// The DNS servers to use, and the domains resolved by other servers.
+    pub dns: DnsConfig,
+
+    /// Resolve DNS through a local stub that forwards the queries encrypted, instead of sending
+    /// them in plaintext to the DNS server. Takes precedence over `dns`.
+    pub encrypted_dns: Option<EncryptedDnsConfig>,
+
+    /// Answer the queries for names on these blocklists locally, instead of resolving them. Only
+    /// used in mixnet mode on desktop.
+    pub dns_filter: Option<DnsFilterConfig>,
 
     /// Disable routing all traffic through the VPN TUN device.
     pub disable_routing: bool,
@@ -66,10 +83,52 @@ pub struct GenericNymVpnConfig {
     /// The user agent to use for HTTP requests. This includes client name, version, platform and
     /// git commit hash.
     pub user_agent: Option<UserAgent>,
+
+    /// Block all traffic outside the tunnel.
+    pub kill_switch: KillSwitchConfig,
+
+    /// Which traffic to route outside the tunnel.
+    pub split_tunnel: SplitTunnelConfig,
+
+    /// How the connection through the exit is probed.
+    pub connection_monitor: ConnectionMonitorConfig,
+
+    /// When to switch away from an exit that isn't routing our traffic.
+    pub exit_failover: ExitFailoverConfig,
 }
 
 pub trait Vpn {}
 
+// What the running tunnels are asked to do, either by the user or by the exit failover policy
+enum TunnelAction {
+    Stop,
+    SwitchGateways {
+        entry_point: Option<EntryPoint>,
+        exit_point: Option<ExitPoint>,
+        result_tx: oneshot::Sender<std::result::Result<(), SwitchGatewayError>>,
+    },
+    FailoverExit,
+    RunLeakTest(oneshot::Sender<LeakTestReport>),
+}
+
+impl From<NymVpnCtrlMessage> for TunnelAction {
+    fn from(ctrl_message: NymVpnCtrlMessage) -> Self {
+        match ctrl_message {
+            NymVpnCtrlMessage::Stop => TunnelAction::Stop,
+            NymVpnCtrlMessage::SwitchGateways {
+                entry_point,
+                exit_point,
+                result_tx,
+            } => TunnelAction::SwitchGateways {
+                entry_point,
+                exit_point,
+                result_tx,
+            },
+            NymVpnCtrlMessage::RunLeakTest(reply_tx) => TunnelAction::RunLeakTest(reply_tx),
+        }
+    }
+}
+
 pub struct NymVpn<T: Vpn> {
     /// VPN configuration, independent of the type used
     pub generic_config: GenericNymVpnConfig,
@@ -85,24 +144,29 @@ pub struct NymVpn<T: Vpn> {
     #[cfg(target_os = "ios")]
     pub(super) ios_tun_provider: Arc<dyn OSTunProvider>,
 
+    pub(super) traffic_stats: TrafficStatsHandle,
+
+    pub(crate) dns_filter_stats: DnsFilterStatsHandle,
+
     // Necessary so that the device doesn't get closed before cleanup has taken place
     // Observation: this seems only used for mixnet mode? If so, can we move it to MixnetVpn?
     pub(super) shadow_handle: ShadowHandle,
 }
 
 pub(super) struct ShadowHandle {
-    pub(super) _inner: Option<JoinHandle<std::result::Result<AsyncDevice, MixnetError>>>,
+    pub(super) inner: Option<MixnetProcessorHandle>,
 }
 
 impl<T: Vpn> NymVpn<T> {
-    pub(crate) fn set_shadow_handle(
-        &mut self,
-        shadow_handle: JoinHandle<std::result::Result<AsyncDevice, MixnetError>>,
-    ) {
+    pub(crate) fn set_shadow_handle(&mut self, shadow_handle: MixnetProcessorHandle) {
         self.shadow_handle = ShadowHandle {
-            _inner: Some(shadow_handle),
+            inner: Some(shadow_handle),
         }
     }
+
+    pub(crate) fn take_shadow_handle(&mut self) -> Option<MixnetProcessorHandle> {
+        self.shadow_handle.inner.take()
+    }
 }
 
 pub enum SpecificVpn {
@@ -165,31 +229,114 @@ impl SpecificVpn {
         }
     }
 
+    pub fn kill_switch(&self) -> KillSwitchConfig {
+        match self {
+            SpecificVpn::Wg(vpn) => vpn.generic_config.kill_switch,
+            SpecificVpn::Mix(vpn) => vpn.generic_config.kill_switch,
+        }
+    }
+
+    pub fn split_tunnel(&self) -> SplitTunnelConfig {
+        match self {
+            SpecificVpn::Wg(vpn) => vpn.generic_config.split_tunnel.clone(),
+            SpecificVpn::Mix(vpn) => vpn.generic_config.split_tunnel.clone(),
+        }
+    }
+
+    pub fn connection_monitor(&self) -> ConnectionMonitorConfig {
+        match self {
+            SpecificVpn::Wg(vpn) => vpn.generic_config.connection_monitor.clone(),
+            SpecificVpn::Mix(vpn) => vpn.generic_config.connection_monitor.clone(),
+        }
+    }
+
+    pub fn exit_failover(&self) -> ExitFailoverConfig {
+        match self {
+            SpecificVpn::Wg(vpn) => vpn.generic_config.exit_failover,
+            SpecificVpn::Mix(vpn) => vpn.generic_config.exit_failover,
+        }
+    }
+
+    pub fn traffic_stats(&self) -> TrafficStatsHandle {
+        match self {
+            SpecificVpn::Wg(vpn) => vpn.traffic_stats.clone(),
+            SpecificVpn::Mix(vpn) => vpn.traffic_stats.clone(),
+        }
+    }
+
+    pub fn dns_filter_stats(&self) -> DnsFilterStatsHandle {
+        match self {
+            SpecificVpn::Wg(vpn) => vpn.dns_filter_stats.clone(),
+            SpecificVpn::Mix(vpn) => vpn.dns_filter_stats.clone(),
+        }
+    }
+
     // Start the Nym VPN client, but also listen for external messages to e.g. disconnect as well
     // as reporting it's status on the provided channel.
     pub async fn run(
         &mut self,
         mut vpn_status_tx: nym_task::StatusSender,
-        vpn_ctrl_rx: mpsc::UnboundedReceiver<super::NymVpnCtrlMessage>,
+        mut vpn_ctrl_rx: mpsc::UnboundedReceiver<NymVpnCtrlMessage>,
     ) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
         let mut task_manager = TaskManager::new(SHUTDOWN_TIMER_SECS).named("nym_vpn_lib");
         info!("Setting up route manager");
         let mut route_manager = crate::tunnel::setup_route_manager().await?;
-        let (mut firewall, mut dns_monitor) = init_firewall_dns(
+        let (firewall, mut dns_monitor) = init_firewall_dns(
             #[cfg(target_os = "linux")]
             route_manager.handle()?,
         )
         .await?;
 
-        let tunnels = match crate::tunnel_setup::setup_tunnel(
+        let mut kill_switch = KillSwitch::new(firewall, self.kill_switch());
+        if kill_switch.is_enabled() {
+            // The wireguard tunnels are nested, which the firewall policies can't express
+            if let SpecificVpn::Wg(_) = self {
+                return Err(Box::new(Error::KillSwitchNotSupportedWithWireguard));
+            }
+        }
+
+        // In wireguard mode the DNS stub only filters. The upstreams are reached through the exit
+        // hop, without encryption, and without routes for the split DNS resolvers.
+        if let SpecificVpn::Wg(vpn) = self {
+            if vpn.generic_config.encrypted_dns.is_some() {
+                return Err(Box::new(Error::EncryptedDnsNotSupportedWithWireguard));
+            }
+            if !vpn.generic_config.dns.split_rules.is_empty() {
+                return Err(Box::new(Error::SplitDnsNotSupportedWithWireguard));
+            }
+        }
+
+        // The exception routes for the excluded networks are not added on windows in mixnet mode,
+        // see the note in the routing setup
+        #[cfg(windows)]
+        if let SpecificVpn::Mix(_) = self {
+            if !self.split_tunnel().exclude_networks.is_empty() {
+                return Err(Box::new(Error::ExcludeNetworksNotSupportedWithMixnet));
+            }
+        }
+        // Nor are the ones for the resolvers of the split DNS domains
+        #[cfg(windows)]
+        if let SpecificVpn::Mix(vpn) = self {
+            if !vpn.generic_config.dns.split_rules.is_empty() {
+                return Err(Box::new(Error::SplitDnsNotSupportedWithMixnet));
+            }
+        }
+
+        let setup = crate::tunnel_setup::setup_tunnel(
             self,
             &mut task_manager,
             &mut route_manager,
             &mut dns_monitor,
+            &mut kill_switch,
         )
         .await
-        {
-            Ok(tunnels) => tunnels,
+        .and_then(|(tunnels, switch_context)| {
+            let entry_gateway = tunnels.entry_gateway()?;
+            let status_message = tunnels.status_message()?;
+            Ok((tunnels, switch_context, entry_gateway, status_message))
+        });
+        let (tunnels, switch_context, entry_gateway, status_message) = match setup {
+            Ok(setup) => setup,
             Err(e) => {
                 tokio::task::spawn_blocking(move || {
                     dns_monitor
@@ -198,12 +345,8 @@ impl SpecificVpn {
                             error!("Failed to reset dns monitor: {err}");
                         })
                         .ok();
-                    firewall
-                        .reset_policy()
-                        .inspect_err(|err| {
-                            error!("Failed to reset firewall policy: {err}");
-                        })
-                        .ok();
+                    // With the kill switch enabled we keep blocking until the user disconnects
+                    kill_switch.close(false).ok();
                     drop(route_manager);
                 })
                 .await?;
@@ -211,95 +354,198 @@ impl SpecificVpn {
             }
         };
 
+        // Kept until we exit, at which point the rules are removed
+        #[cfg(target_os = "linux")]
+        let excluded_cgroups =
+            crate::split_tunnel::exclude_cgroups(&self.split_tunnel().excluded_cgroups).await;
+
         info!("Nym VPN is now running");
 
-        // Finished starting everything, now wait for mixnet client shutdown
-        match tunnels {
-            AllTunnelsSetup::Mix(TunnelSetup { specific_setup, .. }) => {
-                // Signal back that mixnet is ready and up with all cylinders firing
-                // TODO: this should actually be sent much earlier, when the mixnet client is
-                // connected. However that would also require starting the status listener earlier.
-                // This means that for now, we basically just ignore the status message and use the
-                // NymVpnStatusMessage sent below instead.
-                let start_status = TaskStatus::ReadyWithGateway(
-                    specific_setup
-                        .mixnet_connection_info
-                        .entry_gateway
-                        .to_base58_string(),
-                );
-                task_manager
-                    .start_status_listener(vpn_status_tx.clone(), start_status)
+        // Signal back that the tunnels are ready and up with all cylinders firing
+        // TODO: this should actually be sent much earlier, when the mixnet client is
+        // connected. However that would also require starting the status listener earlier.
+        // This means that for now, we basically just ignore the status message and use the
+        // NymVpnStatusMessage sent below instead.
+        let start_status = TaskStatus::ReadyWithGateway(entry_gateway.to_base58_string());
+        // The task status messages pass through us, so that we can follow the connection monitor
+        let (task_status_tx, mut task_status_rx) = mpsc::channel(128);
+        task_manager
+            .start_status_listener(task_status_tx, start_status)
+            .await;
+
+        vpn_status_tx.send(Box::new(status_message)).await.unwrap();
+
+        let traffic_stats = self.traffic_stats();
+        traffic_stats.set_wireguard_interfaces(tunnels.wireguard_interfaces());
+
+        // Notified by the wireguard health check when the exit stalls, in mixnet mode the connection
+        // monitor is started along with the mixnet client
+        let mut mtu_reprobe = PathMtuReprobe::new();
+        let wireguard_exit_tx = tunnels.wireguard_exit().map(|exit| {
+            crate::wg_health::start_wireguard_health_monitor(
+                exit,
+                self.connection_monitor(),
+                traffic_stats.clone(),
+                mtu_reprobe.stalled(),
+                &task_manager,
+            )
+        });
+
+        // We are operational, wait for exit while switching gateways when asked to
+        let mut tunnels = Some(tunnels);
+        let mut exit_failover = ExitFailover::new(self.exit_failover());
+        let result = loop {
+            let action = tokio::select! {
+                ctrl_message = crate::util::wait_for_ctrl_message(
+                    &mut task_manager,
+                    &mut vpn_ctrl_rx,
+                ) => match ctrl_message {
+                    Ok(ctrl_message) => TunnelAction::from(ctrl_message),
+                    Err(err) => break Err(err),
+                },
+                Some(status) = task_status_rx.next() => {
+                    let exit_failed = status
+                        .downcast_ref::<ConnectionMonitorStatus>()
+                        .is_some_and(|status| exit_failover.observe(status, Instant::now()));
+                    vpn_status_tx.send(status).await.ok();
+                    if !exit_failed {
+                        continue;
+                    }
+                    TunnelAction::FailoverExit
+                }
+                reprobe = mtu_reprobe.wait() => {
+                    reprobe_path_mtu(tunnels.as_mut(), reprobe, &mut vpn_status_tx).await;
+                    continue;
+                }
+            };
+            let (switch_result, failed_exit, result_tx) = match action {
+                TunnelAction::Stop => break Ok(()),
+                TunnelAction::SwitchGateways {
+                    entry_point,
+                    exit_point,
+                    result_tx,
+                } => {
+                    let switch_result = crate::tunnel_setup::switch_gateway(
+                        self,
+                        &mut tunnels,
+                        &switch_context,
+                        entry_point,
+                        exit_point,
+                        &[],
+                        &mut task_manager,
+                        &mut route_manager,
+                        &mut dns_monitor,
+                    )
                     .await;
-
-                vpn_status_tx
-                    .send(Box::new(NymVpnStatusMessage::MixConnectionInfo {
-                        mixnet_connection_info: specific_setup.mixnet_connection_info,
-                        mixnet_exit_connection_info: Box::new(specific_setup.exit_connection_info),
-                    }))
-                    .await
-                    .unwrap();
-
-                // We are operational, wait for exit
-                let result = crate::util::wait_for_interrupt(
-                    Some(task_manager),
-                    Some(vpn_ctrl_rx),
-                    route_manager,
-                    None,
-                )
-                .await;
-
-                tokio::task::spawn_blocking(move || {
-                    dns_monitor.reset().inspect_err(|err| {
-                        error!("Failed to reset dns monitor: {err}");
-                    })
-                })
-                .await??;
-                result
-            }
-            AllTunnelsSetup::Wg { entry, exit } => {
-                let start_status = TaskStatus::ReadyWithGateway(
-                    entry
-                        .specific_setup
-                        .connection_info
-                        .gateway_id
-                        .to_base58_string(),
-                );
-                task_manager
-                    .start_status_listener(vpn_status_tx.clone(), start_status)
+                    (switch_result, None, Some(result_tx))
+                }
+                TunnelAction::FailoverExit => {
+                    info!("The exit gateway keeps failing, switching to another one");
+                    let failed_exit = tunnels.as_ref().map(AllTunnelsSetup::exit_gateway);
+                    let switch_result = crate::tunnel_setup::failover_exit(
+                        self,
+                        &mut tunnels,
+                        &switch_context,
+                        &mut exit_failover,
+                        &mut task_manager,
+                        &mut route_manager,
+                        &mut dns_monitor,
+                    )
                     .await;
-
-                vpn_status_tx
-                    .send(Box::new(NymVpnStatusMessage::WgConnectionInfo {
-                        entry_connection_info: entry.specific_setup.connection_info.clone(),
-                        exit_connection_info: exit.specific_setup.connection_info.clone(),
-                    }))
-                    .await
-                    .unwrap();
-
-                // We are operational, wait for exit
-                let result = crate::util::wait_for_interrupt(
-                    Some(task_manager),
-                    Some(vpn_ctrl_rx),
-                    route_manager,
-                    Some([entry.specific_setup, exit.specific_setup]),
-                )
-                .await;
-
-                tokio::task::spawn_blocking(move || {
-                    dns_monitor.reset().inspect_err(|err| {
-                        error!("Failed to reset dns monitor: {err}");
-                    })
-                })
-                .await??;
-                firewall.reset_policy().map_err(|err| {
-                    error!("Failed to reset firewall policy: {err}");
-                    Error::FailedToResetFirewallPolicy {
-                        reason: err.to_string(),
+                    (switch_result, failed_exit, None)
+                }
+                TunnelAction::RunLeakTest(reply_tx) => {
+                    // The probes take a few seconds, which shouldn't hold up the tunnel
+                    if let Some(ref tunnels) = tunnels {
+                        let context = LeakTestContext::new(
+                            self,
+                            tunnels,
+                            kill_switch.allowed_traffic(),
+                            &route_manager,
+                        );
+                        tokio::spawn(async move {
+                            reply_tx.send(crate::leak_test::run(context).await).ok();
+                        });
+                    }
+                    continue;
+                }
+            };
+            exit_failover.reset();
+            match switch_result {
+                Ok(status_message) => {
+                    if let Some(ref tunnels) = tunnels {
+                        if let (Some(failed_exit_gateway), Ok(exit_gateway)) =
+                            (failed_exit, tunnels.exit_gateway())
+                        {
+                            let failover = NymVpnStatusMessage::ExitFailover {
+                                failed_exit_gateway,
+                                exit_gateway,
+                            };
+                            vpn_status_tx.send(Box::new(failover)).await.ok();
+                        }
+                        traffic_stats.set_wireguard_interfaces(tunnels.wireguard_interfaces());
+                        if let (Some(exit_tx), Some(exit)) =
+                            (&wireguard_exit_tx, tunnels.wireguard_exit())
+                        {
+                            exit_tx.send_replace(exit);
+                        }
+                    }
+                    vpn_status_tx.send(Box::new(status_message)).await.ok();
+                    if let Some(result_tx) = result_tx {
+                        result_tx.send(Ok(())).ok();
+                    }
+                }
+                Err(SwitchGatewayError::TunnelLost(err)) => {
+                    error!("Lost the tunnel while switching gateways: {err}");
+                    if let Some(result_tx) = result_tx {
+                        result_tx
+                            .send(Err(SwitchGatewayError::TunnelLost(err.to_string().into())))
+                            .ok();
+                    }
+                    break Err(err);
+                }
+                Err(err) => {
+                    error!("Failed to switch gateways: {err}");
+                    if let Some(result_tx) = result_tx {
+                        result_tx.send(Err(err)).ok();
                     }
-                })?;
-                result
+                }
             }
+        };
+
+        traffic_stats.set_wireguard_interfaces(Vec::new());
+        #[cfg(target_os = "linux")]
+        if let Some(excluded_cgroups) = excluded_cgroups {
+            excluded_cgroups.remove().await;
         }
+        crate::util::shutdown(
+            task_manager,
+            route_manager,
+            tunnels.and_then(AllTunnelsSetup::into_wireguard_waiting),
+        )
+        .await;
+
+        tokio::task::spawn_blocking(move || {
+            dns_monitor.reset().inspect_err(|err| {
+                error!("Failed to reset dns monitor: {err}");
+            })
+        })
+        .await??;
+        kill_switch.close(result.is_ok())?;
+        result
+    }
+}
+
+async fn reprobe_path_mtu(
+    tunnels: Option<&mut AllTunnelsSetup>,
+    reprobe: Reprobe,
+    vpn_status_tx: &mut nym_task::StatusSender,
+) {
+    let Some(tunnels) = tunnels else {
+        return;
+    };
+    if let Some(status_message) = crate::tunnel_setup::reprobe_path_mtu(tunnels, reprobe).await {
+        vpn_status_tx.send(Box::new(status_message)).await.ok();
     }
 }
 
@@ -312,12 +558,7 @@ async fn init_firewall_dns(
         let command_tx = std::sync::Arc::new(command_tx);
         let weak_command_tx = std::sync::Arc::downgrade(&command_tx);
 
-        tracing::debug!("Starting firewall");
-        let firewall = tokio::task::spawn_blocking(move || {
-            Firewall::new().map_err(|err| Error::FirewallError(err.to_string()))
-        })
-        .await
-        .map_err(|err| Error::FirewallError(err.to_string()))??;
+        let firewall = kill_switch::init_firewall().await?;
 
         tracing::debug!("Starting dns monitor");
         let dns_monitor = DnsMonitor::new(weak_command_tx)?;
@@ -327,14 +568,7 @@ async fn init_firewall_dns(
 
     #[cfg(target_os = "linux")]
     {
-        let fwmark = 0; // ?
-
-        tracing::debug!("Starting firewall");
-        let firewall = tokio::task::spawn_blocking(move || {
-            Firewall::new(fwmark).map_err(|err| Error::FirewallError(err.to_string()))
-        })
-        .await
-        .map_err(|err| Error::FirewallError(err.to_string()))??;
+        let firewall = kill_switch::init_firewall().await?;
 
         tracing::debug!("Starting dns monitor");
         let dns_monitor = DnsMonitor::new(
@@ -347,12 +581,7 @@ async fn init_firewall_dns(
 
     #[cfg(all(not(target_os = "macos"), not(target_os = "linux")))]
     {
-        tracing::debug!("Starting firewall");
-        let firewall = tokio::task::spawn_blocking(move || {
-            Firewall::new().map_err(|err| Error::FirewallError(err.to_string()))
-        })
-        .await
-        .map_err(|err| Error::FirewallError(err.to_string()))??;
+        let firewall = kill_switch::init_firewall().await?;
 
         tracing::debug!("Starting dns monitor");
         let dns_monitor = DnsMonitor::new()?;
@@ -1,9 +1,12 @@
 // Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
 // SPDX-License-Identifier: GPL-3.0-only
 
+use nym_gateway_directory::{EntryPoint, ExitPoint, NodeIdentity};
+use tokio::sync::oneshot;
 use tracing::error;
 
 use super::{MixnetConnectionInfo, MixnetExitConnectionInfo, WireguardConnectionInfo};
+use crate::{error::SwitchGatewayError, leak_test::LeakTestReport};
 
 #[derive(thiserror::Error, Clone, Debug)]
 pub enum NymVpnStatusMessage {
@@ -15,13 +18,30 @@ pub enum NymVpnStatusMessage {
     #[error("wireguard connection info")]
     WgConnectionInfo {
         entry_connection_info: WireguardConnectionInfo,
+        // Any hops in between the entry and the exit, in order
+        middle_connection_info: Vec<WireguardConnectionInfo>,
         exit_connection_info: WireguardConnectionInfo,
     },
+    // Sent before the connection info of the new exit
+    #[error("exit gateway {failed_exit_gateway} kept failing, switched to {exit_gateway}")]
+    ExitFailover {
+        failed_exit_gateway: NodeIdentity,
+        exit_gateway: NodeIdentity,
+    },
 }
 
 #[derive(Debug)]
 pub enum NymVpnCtrlMessage {
     Stop,
+    // Switch the entry and/or the exit gateway on the live connection, without a full teardown,
+    // and report back on the channel whether the switch went through
+    SwitchGateways {
+        entry_point: Option<EntryPoint>,
+        exit_point: Option<ExitPoint>,
+        result_tx: oneshot::Sender<Result<(), SwitchGatewayError>>,
+    },
+    // Check the live connection for DNS and IP leaks, and report back on the channel
+    RunLeakTest(oneshot::Sender<LeakTestReport>),
 }
 
 #[derive(Debug)]
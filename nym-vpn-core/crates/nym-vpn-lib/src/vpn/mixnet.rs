@@ -2,15 +2,15 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use std::{
-    net::IpAddr,
+    net::{IpAddr, SocketAddr},
     result::Result,
     sync::{Arc, Mutex},
 };
 
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use nym_connection_monitor::ConnectionMonitorTask;
 use nym_gateway_directory::{
-    EntryPoint, ExitPoint, GatewayClient, IpPacketRouterAddress, NodeIdentity, Recipient,
+    EntryPoint, ExitPoint, Gateway, GatewayClient, IpPacketRouterAddress, NodeIdentity, Recipient,
 };
 use nym_ip_packet_client::IprClientConnect;
 use nym_ip_packet_requests::IpPair;
@@ -18,16 +18,31 @@ use nym_task::TaskManager;
 use talpid_core::dns::DnsMonitor;
 use talpid_routing::RouteManager;
 use talpid_tunnel::tun_provider::TunProvider;
+use tokio::sync::watch;
 
 use super::base::{GenericNymVpnConfig, NymVpn, ShadowHandle, Vpn};
 #[cfg(target_os = "ios")]
 use crate::mobile::ios::tun_provider::OSTunProvider;
 #[cfg(target_os = "android")]
 use crate::platform::android::AndroidTunProvider;
-use crate::{error::SetupMixTunnelError, mixnet::SharedMixnetClient, routing};
+use crate::{
+    dns::DnsStub,
+    dns_filter::DnsFilter,
+    error::{SetupMixTunnelError, SwitchGatewayError},
+    kill_switch::KillSwitch,
+    mixnet::{SharedMixnetClient, TrafficProfile},
+    pmtu, routing,
+};
+
+// The path MTU doesn't depend on the port, so the probes go to the discard port. With the kill
+// switch on they are blocked, and the default MTU is used.
+const DISCARD_PORT: u16 = 9;
 
 #[derive(Clone, Debug)]
 pub struct MixnetClientConfig {
+    /// How the traffic is shaped, the flags below take precedence over it.
+    pub traffic_profile: TrafficProfile,
+
     /// Enable Poission process rate limiting of outbound traffic.
     pub enable_poisson_rate: bool,
 
@@ -55,6 +70,8 @@ pub struct MixnetExitConnectionInfo {
     pub exit_gateway: NodeIdentity,
     pub exit_ipr: Recipient,
     pub ips: IpPair,
+    // Of the mixnet tun device
+    pub mtu: u16,
 }
 
 pub struct MixnetVpn {}
@@ -72,6 +89,7 @@ impl NymVpn<MixnetVpn> {
         Self {
             generic_config: GenericNymVpnConfig {
                 mixnet_client_config: MixnetClientConfig {
+                    traffic_profile: TrafficProfile::default(),
                     enable_poisson_rate: false,
                     disable_background_cover_traffic: false,
                     enable_credentials_mode: false,
@@ -84,9 +102,15 @@ impl NymVpn<MixnetVpn> {
                 exit_point,
                 nym_ips: None,
                 nym_mtu: None,
-                dns: None,
+                dns: Default::default(),
+                encrypted_dns: None,
+                dns_filter: None,
                 disable_routing: false,
                 user_agent: None,
+                kill_switch: Default::default(),
+                split_tunnel: Default::default(),
+                connection_monitor: Default::default(),
+                exit_failover: Default::default(),
             },
             vpn_config: MixnetVpn {},
             tun_provider,
@@ -94,7 +118,9 @@ impl NymVpn<MixnetVpn> {
             android_tun_provider,
             #[cfg(target_os = "ios")]
             ios_tun_provider,
-            shadow_handle: ShadowHandle { _inner: None },
+            traffic_stats: Default::default(),
+            dns_filter_stats: Default::default(),
+            shadow_handle: ShadowHandle { inner: None },
         }
     }
 
@@ -108,7 +134,8 @@ impl NymVpn<MixnetVpn> {
         gateway_client: &GatewayClient,
         default_lan_gateway_ip: routing::LanGatewayIp,
         dns_monitor: &mut DnsMonitor,
-    ) -> Result<MixnetExitConnectionInfo, SetupMixTunnelError> {
+        kill_switch: &mut KillSwitch,
+    ) -> Result<(MixnetExitConnectionInfo, watch::Sender<Recipient>), SetupMixTunnelError> {
         let exit_gateway = *exit_mix_addresses.gateway();
         info!("Connecting to exit gateway: {exit_gateway}");
         // Currently the IPR client is only used to connect. The next step would be to use it to
@@ -125,23 +152,51 @@ impl NymVpn<MixnetVpn> {
         let mixnet_client_address = mixnet_client.nym_address().await;
         let gateway_used = mixnet_client_address.gateway().to_base58_string();
         debug!("Entry gateway used for setting up routing table: {gateway_used}");
-        let entry_mixnet_gateway_ip: IpAddr = gateway_client
-            .lookup_gateway_ip(&gateway_used)
-            .await
-            .map_err(|source| SetupMixTunnelError::FailedToLookupGatewayIp {
-                gateway_id: gateway_used,
-                source,
-            })?;
+        // With the kill switch we already looked it up, and the directory is blocked by now
+        let entry_mixnet_gateway_ip: IpAddr = match kill_switch.entry_gateway() {
+            Some(entry_gateway) => entry_gateway.ip(),
+            None => gateway_client
+                .lookup_gateway_ip(&gateway_used)
+                .await
+                .map_err(|source| SetupMixTunnelError::FailedToLookupGatewayIp {
+                    gateway_id: gateway_used,
+                    source,
+                })?,
+        };
         debug!("Gateway ip resolves to: {entry_mixnet_gateway_ip}");
 
+        // The packets leave the exit gateway as they are, and the path to the entry gateway is the
+        // only one we can probe. Without an MTU from the user, keep them within what it carries.
+        let mtu = match self.generic_config.nym_mtu {
+            Some(mtu) => mtu,
+            None => pmtu::probe_path_mtu(SocketAddr::new(entry_mixnet_gateway_ip, DISCARD_PORT))
+                .await
+                .unwrap_or(routing::DEFAULT_TUN_MTU)
+                .clamp(pmtu::MIN_TUNNEL_MTU, routing::DEFAULT_TUN_MTU),
+        };
+
         info!("Setting up routing");
         let routing_config = routing::RoutingConfig::new(
             self,
             our_ips,
+            mtu,
             entry_mixnet_gateway_ip,
             default_lan_gateway_ip,
         );
         debug!("Routing config: {}", routing_config);
+        let mtu = routing_config.mtu;
+        let dns_stub_ip = self.dns_stub_ip(our_ips);
+        // Load the blocklists before touching the system DNS, so that a missing list fails early
+        let dns_filter = match (dns_stub_ip, &self.generic_config.dns_filter) {
+            (Some(_), Some(config)) => {
+                Some(DnsFilter::load(config, self.dns_filter_stats.clone()).await?)
+            }
+            _ => None,
+        };
+        let dns_servers = match dns_stub_ip {
+            Some(dns_stub_ip) => vec![dns_stub_ip],
+            None => self.generic_config.dns.servers_or_default(),
+        };
         let mixnet_tun_dev = routing::setup_mixnet_routing(
             route_manager,
             routing_config,
@@ -150,19 +205,49 @@ impl NymVpn<MixnetVpn> {
             #[cfg(target_os = "ios")]
             self.ios_tun_provider.clone(),
             dns_monitor,
-            self.generic_config.dns,
+            dns_servers.clone(),
         )
         .await?;
 
+        // The stub listens on the tunnel address, so it can only be started once the tun device
+        // is up
+        if let Some(dns_stub_ip) = dns_stub_ip {
+            let stub = DnsStub::bind(
+                dns_stub_ip,
+                &self.generic_config.dns,
+                self.generic_config.encrypted_dns.as_ref(),
+                dns_filter,
+            )
+            .await
+            .map_err(SetupMixTunnelError::FailedToStartDnsStub)?;
+            tokio::spawn(stub.run(task_manager.subscribe_named("dns_stub")));
+        }
+
+        // The resolvers of the split domains are reached outside the tunnel, e.g. over the LAN
+        let allowed_dns_servers = dns_servers
+            .into_iter()
+            .chain(self.generic_config.dns.split_servers())
+            .collect();
+        kill_switch
+            .allow_mixnet_tunnel(&mixnet_tun_dev, our_ips, allowed_dns_servers)
+            .map_err(|err| SetupMixTunnelError::KillSwitchError(err.to_string()))?;
+
         info!("Setting up mixnet processor");
-        let processor_config = crate::mixnet::Config::new(exit_mix_addresses.0);
+        let processor_config = crate::mixnet::Config::new(
+            exit_mix_addresses.0,
+            self.generic_config
+                .mixnet_client_config
+                .traffic_profile
+                .packet_buffer_timeout(),
+        );
         debug!("Mixnet processor config: {:#?}", processor_config);
 
         // For other components that will want to send mixnet packets
         let mixnet_client_sender = mixnet_client.split_sender().await;
 
         // Setup connection monitor shared tag and channels
-        let connection_monitor = ConnectionMonitorTask::setup();
+        let connection_monitor =
+            ConnectionMonitorTask::setup(self.generic_config.connection_monitor.clone());
 
         let shadow_handle = crate::mixnet::start_processor(
             processor_config,
@@ -171,22 +256,124 @@ impl NymVpn<MixnetVpn> {
             task_manager,
             our_ips,
             &connection_monitor,
+            self.traffic_stats.clone(),
         )
         .await;
         self.set_shadow_handle(shadow_handle);
 
+        // The connection monitor follows the exit, in case we switch to another one later
+        let (exit_ipr_tx, exit_ipr_rx) = watch::channel(exit_mix_addresses.0);
         connection_monitor.start(
             mixnet_client_sender,
             mixnet_client_address,
             our_ips,
-            exit_mix_addresses.0,
+            exit_ipr_rx,
             task_manager,
         );
 
-        Ok(MixnetExitConnectionInfo {
+        let exit_connection_info = MixnetExitConnectionInfo {
             exit_gateway,
             exit_ipr: exit_mix_addresses.0,
             ips: our_ips,
+            mtu,
+        };
+        Ok((exit_connection_info, exit_ipr_tx))
+    }
+
+    // Where our DNS stub listens, when the system has to resolve through it. That's for encrypted
+    // DNS, for sending the split domains to their own resolvers, and for filtering. The mobile
+    // platforms don't let us bind the DNS port on the tunnel address.
+    pub(crate) fn dns_stub_ip(&self, our_ips: IpPair) -> Option<IpAddr> {
+        let config = &self.generic_config;
+        if config.encrypted_dns.is_none()
+            && config.dns.split_rules.is_empty()
+            && config.dns_filter.is_none()
+        {
+            return None;
+        }
+        if cfg!(any(target_os = "ios", target_os = "android")) {
+            warn!(
+                "Encrypted, split and filtered DNS are not supported on this platform, using plain DNS"
+            );
+            return None;
+        }
+        Some(IpAddr::V4(our_ips.ipv4))
+    }
+
+    // Switch to a different exit on the live connection. We stop the mixnet processor, connect to
+    // the new ip packet router asking for the IPs we already have, and start the processor again
+    // on the same tun device. That way routing, DNS and the kill switch are left untouched.
+    pub(crate) async fn switch_exit(
+        &mut self,
+        mixnet_client: &SharedMixnetClient,
+        exit_gateway: &Gateway,
+        exit_connection_info: MixnetExitConnectionInfo,
+        exit_ipr_tx: &watch::Sender<Recipient>,
+        task_manager: &TaskManager,
+    ) -> Result<MixnetExitConnectionInfo, SwitchGatewayError> {
+        let exit_ipr = exit_gateway
+            .ipr_address
+            .ok_or_else(|| SwitchGatewayError::MissingIpPacketRouter {
+                gateway_id: exit_gateway.identity().to_base58_string(),
+            })?
+            .0;
+        info!("Switching to exit gateway: {}", exit_gateway.identity());
+
+        let processor = self
+            .take_shadow_handle()
+            .ok_or(SwitchGatewayError::MixnetProcessorNotRunning)?
+            .stop()
+            .await
+            .map_err(|err| SwitchGatewayError::TunnelLost(Box::new(err)))?;
+
+        // The tunnel device keeps its addresses, so the new exit has to hand out the same ones
+        let old_ips = exit_connection_info.ips;
+        let mut ipr_client = IprClientConnect::new_from_inner(mixnet_client.inner()).await;
+        let connect_result = match ipr_client.connect(exit_ipr, Some(old_ips)).await {
+            Ok(ips) if ips == old_ips => Ok(()),
+            Ok(ips) => Err(SwitchGatewayError::ExitAssignedOtherIps { ips }),
+            Err(err) => Err(SwitchGatewayError::FailedToConnectToIpPacketRouter(err)),
+        };
+
+        let connected_ipr = if connect_result.is_ok() {
+            exit_ipr
+        } else {
+            // Keep using the exit we already had, asking it for our addresses again in case the
+            // attempt with the new exit made it let go of them
+            let old_ipr = exit_connection_info.exit_ipr;
+            let mut ipr_client = IprClientConnect::new_from_inner(mixnet_client.inner()).await;
+            match ipr_client.connect(old_ipr, Some(old_ips)).await {
+                Ok(ips) if ips == old_ips => {}
+                Ok(ips) => {
+                    return Err(SwitchGatewayError::TunnelLost(Box::new(
+                        SwitchGatewayError::ExitAssignedOtherIps { ips },
+                    )))
+                }
+                Err(err) => return Err(SwitchGatewayError::TunnelLost(Box::new(err))),
+            }
+            old_ipr
+        };
+        let processor_config = crate::mixnet::Config::new(
+            connected_ipr,
+            self.generic_config
+                .mixnet_client_config
+                .traffic_profile
+                .packet_buffer_timeout(),
+        );
+        self.set_shadow_handle(processor.start(processor_config, task_manager));
+
+        connect_result?;
+        exit_ipr_tx.send_replace(exit_ipr);
+        info!(
+            "Successfully switched to exit gateway: {}",
+            exit_gateway.identity()
+        );
+
+        Ok(MixnetExitConnectionInfo {
+            exit_gateway: *exit_gateway.identity(),
+            exit_ipr,
+            ips: exit_connection_info.ips,
+            mtu: exit_connection_info.mtu,
         })
     }
 
@@ -200,7 +387,15 @@ impl NymVpn<MixnetVpn> {
         gateway_client: &GatewayClient,
         default_lan_gateway_ip: routing::LanGatewayIp,
         dns_monitor: &mut DnsMonitor,
-    ) -> Result<(MixnetConnectionInfo, MixnetExitConnectionInfo), SetupMixTunnelError> {
+        kill_switch: &mut KillSwitch,
+    ) -> Result<
+        (
+            MixnetConnectionInfo,
+            MixnetExitConnectionInfo,
+            watch::Sender<Recipient>,
+        ),
+        SetupMixTunnelError,
+    > {
         // Now that we have a connection, collection some info about that and return
         let nym_address = mixnet_client.nym_address().await;
         let entry_gateway = *(nym_address.gateway());
@@ -225,6 +420,7 @@ impl NymVpn<MixnetVpn> {
                 gateway_client,
                 default_lan_gateway_ip,
                 dns_monitor,
+                kill_switch,
             )
             .await
         {
@@ -234,7 +430,9 @@ impl NymVpn<MixnetVpn> {
                 mixnet_client.disconnect().await;
                 Err(err)
             }
-            Ok(exit_connection_info) => Ok((our_mixnet_connection, exit_connection_info)),
+            Ok((exit_connection_info, exit_ipr_tx)) => {
+                Ok((our_mixnet_connection, exit_connection_info, exit_ipr_tx))
+            }
         }
     }
 }
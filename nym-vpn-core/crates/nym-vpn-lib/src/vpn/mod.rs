@@ -15,3 +15,4 @@ pub use base::{GenericNymVpnConfig, NymVpn, SpecificVpn};
 pub use messages::{NymVpnCtrlMessage, NymVpnExitStatusMessage, NymVpnStatusMessage};
 pub use mixnet::MixnetClientConfig;
 pub use start::{spawn_nym_vpn, spawn_nym_vpn_with_new_runtime, NymVpnHandle};
+pub use wireguard::{DEFAULT_WG_HOP_COUNT, MAX_WG_HOP_COUNT};
@@ -6,7 +6,9 @@ use tracing::{debug, error, info};
 
 use super::{NymVpnCtrlMessage, NymVpnExitStatusMessage, SpecificVpn};
 use crate::{
+    dns_filter::DnsFilterStatsHandle,
     error::Result,
+    traffic_stats::TrafficStatsHandle,
     uniffi_custom_impls::{ExitStatus, StatusEvent},
     Error,
 };
@@ -36,6 +38,8 @@ pub fn spawn_nym_vpn(nym_vpn: SpecificVpn) -> Result<NymVpnHandle> {
     let (vpn_ctrl_tx, vpn_ctrl_rx) = mpsc::unbounded();
     let (vpn_status_tx, vpn_status_rx) = mpsc::channel(128);
     let (vpn_exit_tx, vpn_exit_rx) = oneshot::channel();
+    let traffic_stats = nym_vpn.traffic_stats();
+    let dns_filter_stats = nym_vpn.dns_filter_stats();
 
     tokio::spawn(run_nym_vpn(
         nym_vpn,
@@ -48,6 +52,8 @@ pub fn spawn_nym_vpn(nym_vpn: SpecificVpn) -> Result<NymVpnHandle> {
         vpn_ctrl_tx,
         vpn_status_rx,
         vpn_exit_rx,
+        traffic_stats,
+        dns_filter_stats,
     })
 }
 
@@ -76,6 +82,8 @@ pub fn spawn_nym_vpn_with_new_runtime(nym_vpn: SpecificVpn) -> Result<NymVpnHand
     let (vpn_ctrl_tx, vpn_ctrl_rx) = mpsc::unbounded();
     let (vpn_status_tx, vpn_status_rx) = mpsc::channel(128);
     let (vpn_exit_tx, vpn_exit_rx) = oneshot::channel();
+    let traffic_stats = nym_vpn.traffic_stats();
+    let dns_filter_stats = nym_vpn.dns_filter_stats();
 
     std::thread::spawn(|| {
         let rt = tokio::runtime::Builder::new_multi_thread()
@@ -94,6 +102,8 @@ pub fn spawn_nym_vpn_with_new_runtime(nym_vpn: SpecificVpn) -> Result<NymVpnHand
         vpn_ctrl_tx,
         vpn_status_rx,
         vpn_exit_rx,
+        traffic_stats,
+        dns_filter_stats,
     })
 }
 
@@ -127,6 +137,8 @@ pub struct NymVpnHandle {
     pub vpn_ctrl_tx: mpsc::UnboundedSender<NymVpnCtrlMessage>,
     pub vpn_status_rx: nym_task::StatusReceiver,
     pub vpn_exit_rx: oneshot::Receiver<NymVpnExitStatusMessage>,
+    pub traffic_stats: TrafficStatsHandle,
+    pub dns_filter_stats: DnsFilterStatsHandle,
 }
 
 impl NymVpnHandle {
@@ -17,15 +17,32 @@ use super::{
 use crate::mobile::ios::tun_provider::OSTunProvider;
 #[cfg(target_os = "android")]
 use crate::platform::android::AndroidTunProvider;
+use crate::{
+    mixnet::TrafficProfile,
+    pmtu::{max_hop_count, DEFAULT_WIRE_MTU},
+};
 
 #[derive(Clone, Debug)]
 pub struct WireguardConnectionInfo {
     pub gateway_id: NodeIdentity,
     pub public_key: String,
     pub private_ipv4: Ipv4Addr,
+    pub mtu: u16,
 }
 
-pub struct WireguardVpn {}
+// The number of stacked wireguard tunnels, counting both the entry and the exit
+pub const DEFAULT_WG_HOP_COUNT: usize = 2;
+
+// Every hop inside another takes 80 bytes off the MTU. Over a 1500 bytes path to an IPv4 entry
+// that leaves room for three tunnels before the innermost one goes below the 1280 bytes IPv6
+// requires.
+pub const MAX_WG_HOP_COUNT: usize = max_hop_count(DEFAULT_WIRE_MTU, false);
+
+pub struct WireguardVpn {
+    // Hops in between the entry and the exit are picked from the directory, in countries not used
+    // by any other hop
+    pub hop_count: usize,
+}
 
 impl Vpn for WireguardVpn {}
 
@@ -41,6 +58,7 @@ impl NymVpn<WireguardVpn> {
         Self {
             generic_config: GenericNymVpnConfig {
                 mixnet_client_config: MixnetClientConfig {
+                    traffic_profile: TrafficProfile::default(),
                     enable_poisson_rate: false,
                     disable_background_cover_traffic: false,
                     enable_credentials_mode: false,
@@ -53,17 +71,29 @@ impl NymVpn<WireguardVpn> {
                 exit_point,
                 nym_ips: None,
                 nym_mtu: None,
-                dns: None,
+                dns: Default::default(),
+                // Rejected when the tunnel is set up, the queries would leave the exit in plain
+                // text
+                encrypted_dns: None,
+                dns_filter: None,
                 disable_routing: false,
                 user_agent: None,
+                kill_switch: Default::default(),
+                split_tunnel: Default::default(),
+                connection_monitor: Default::default(),
+                exit_failover: Default::default(),
+            },
+            vpn_config: WireguardVpn {
+                hop_count: DEFAULT_WG_HOP_COUNT,
             },
-            vpn_config: WireguardVpn {},
             tun_provider,
             #[cfg(target_os = "android")]
             android_tun_provider,
             #[cfg(target_os = "ios")]
             ios_tun_provider,
-            shadow_handle: ShadowHandle { _inner: None },
+            traffic_stats: Default::default(),
+            dns_filter_stats: Default::default(),
+            shadow_handle: ShadowHandle { inner: None },
         }
     }
 }
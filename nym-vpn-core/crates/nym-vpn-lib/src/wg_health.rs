@@ -0,0 +1,239 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{sync::Arc, time::Duration};
+
+use futures::channel::mpsc;
+use nym_connection_monitor::{
+    ConnectionMonitorConfig, ConnectionMonitorTask, ConnectionStatusEvent, WireguardExit,
+};
+use nym_sdk::TaskClient;
+use nym_task::TaskManager;
+use tokio::sync::{watch, Notify};
+use tracing::{debug, trace, warn};
+
+use crate::traffic_stats::{TrafficStatsHandle, WgHopStats};
+
+// Wireguard renews the session every two minutes while there is traffic, and gives up on it after
+// three
+const HANDSHAKE_EXPIRY: Duration = Duration::from_secs(180);
+
+// How long traffic can go into the exit tunnel without anything coming back, before we suspect
+// that the packets are too large for the path
+const STALLED_CHECKS_BEFORE_MTU_REPROBE: u32 = 3;
+
+// Checks that the wireguard tunnels up to the exit are passing traffic, which is what the mixnet
+// self ping tells the connection monitor in mixnet mode. The ping replies through the exit tell it
+// as well, this covers the case where the exit is down but the entry is not.
+struct WireguardHealthCheck {
+    check_interval: Duration,
+    traffic_stats: TrafficStatsHandle,
+    connection_event_tx: mpsc::UnboundedSender<ConnectionStatusEvent>,
+    exit_stalled: Arc<Notify>,
+    previous_hops: Vec<WgHopStats>,
+    stalled_checks: u32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct HealthCheckResult {
+    entry_alive: bool,
+    exit_stalled: bool,
+}
+
+impl WireguardHealthCheck {
+    fn new(
+        check_interval: Duration,
+        traffic_stats: TrafficStatsHandle,
+        connection_event_tx: mpsc::UnboundedSender<ConnectionStatusEvent>,
+        exit_stalled: Arc<Notify>,
+    ) -> Self {
+        WireguardHealthCheck {
+            check_interval,
+            traffic_stats,
+            connection_event_tx,
+            exit_stalled,
+            previous_hops: Vec::new(),
+            stalled_checks: 0,
+        }
+    }
+
+    fn evaluate(&mut self, hops: Vec<WgHopStats>) -> HealthCheckResult {
+        let previous_hops = std::mem::replace(&mut self.previous_hops, hops);
+        // The interfaces are replaced when switching gateways, and the counters start over
+        let previous = |hop: &WgHopStats| {
+            previous_hops
+                .iter()
+                .find(|previous| previous.interface == hop.interface)
+        };
+
+        let Some((exit, entry_hops)) = self.previous_hops.split_last() else {
+            return HealthCheckResult {
+                entry_alive: false,
+                exit_stalled: false,
+            };
+        };
+        let entry_alive = !entry_hops.is_empty()
+            && entry_hops
+                .iter()
+                .all(|hop| is_hop_alive(hop, previous(hop)));
+
+        // Traffic goes into the exit tunnel, but nothing comes back
+        let exit_stalling = previous(exit).is_some_and(|previous| {
+            exit.tx_bytes > previous.tx_bytes && exit.rx_bytes == previous.rx_bytes
+        });
+        self.stalled_checks = if exit_stalling {
+            self.stalled_checks + 1
+        } else {
+            0
+        };
+        let exit_stalled = self.stalled_checks >= STALLED_CHECKS_BEFORE_MTU_REPROBE;
+        if exit_stalled {
+            self.stalled_checks = 0;
+        }
+
+        HealthCheckResult {
+            entry_alive,
+            exit_stalled,
+        }
+    }
+
+    async fn check(&mut self) {
+        let hops = self.traffic_stats.wireguard_hops().await;
+        trace!("Wireguard hops: {hops:?}");
+        let result = self.evaluate(hops);
+        if result.entry_alive
+            && self
+                .connection_event_tx
+                .unbounded_send(ConnectionStatusEvent::EntryTunnelAlive)
+                .is_err()
+        {
+            warn!("Failed to send connection event to connection monitor");
+        }
+        if result.exit_stalled {
+            debug!("Nothing is coming back through the exit tunnel");
+            self.exit_stalled.notify_one();
+        }
+    }
+
+    async fn run(mut self, mut shutdown: TaskClient) {
+        debug!("Wireguard health check is running");
+        let mut check_interval = tokio::time::interval(self.check_interval);
+        while !shutdown.is_shutdown() {
+            tokio::select! {
+                _ = shutdown.recv() => {
+                    trace!("WireguardHealthCheck: Received shutdown");
+                }
+                _ = check_interval.tick() => self.check().await,
+            }
+        }
+        debug!("WireguardHealthCheck: Exiting");
+    }
+}
+
+fn is_hop_alive(hop: &WgHopStats, previous: Option<&WgHopStats>) -> bool {
+    // A recent handshake shows the peer is there even when nothing else is coming in. If the
+    // tunnel can't tell us about handshakes, receiving anything will do.
+    let recent_handshake = hop
+        .last_handshake
+        .and_then(|handshake| handshake.elapsed().ok())
+        .is_some_and(|age| age < HANDSHAKE_EXPIRY);
+    let received = previous.is_some_and(|previous| hop.rx_bytes > previous.rx_bytes);
+    recent_handshake || received
+}
+
+// Starts the connection monitor for the wireguard tunnels. The returned sender is used to point it
+// at the new exit tunnel after switching gateways. `exit_stalled` is notified when the exit tunnel
+// stops receiving, so that the path MTU can be probed again.
+pub(crate) fn start_wireguard_health_monitor(
+    exit: WireguardExit,
+    config: ConnectionMonitorConfig,
+    traffic_stats: TrafficStatsHandle,
+    exit_stalled: Arc<Notify>,
+    task_manager: &TaskManager,
+) -> watch::Sender<WireguardExit> {
+    let (exit_tx, exit_rx) = watch::channel(exit);
+    // Check often enough that a live entry is reported before the previous report expires
+    let check_interval = config.reply_expiry / 2;
+    let connection_monitor = ConnectionMonitorTask::setup(config);
+
+    let health_check = WireguardHealthCheck::new(
+        check_interval,
+        traffic_stats,
+        connection_monitor.event_sender(),
+        exit_stalled,
+    );
+    tokio::spawn(health_check.run(task_manager.subscribe_named("wireguard_health_check")));
+
+    connection_monitor.start_wireguard(exit_rx, task_manager);
+    exit_tx
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+
+    fn hop(interface: &str, rx_bytes: u64, tx_bytes: u64) -> WgHopStats {
+        WgHopStats {
+            interface: interface.to_string(),
+            rx_bytes,
+            tx_bytes,
+            last_handshake: None,
+        }
+    }
+
+    #[test]
+    fn entry_alive_and_exit_stalls() {
+        let (connection_event_tx, _connection_event_rx) = mpsc::unbounded();
+        let mut health_check = WireguardHealthCheck::new(
+            Duration::from_secs(1),
+            TrafficStatsHandle::default(),
+            connection_event_tx,
+            Arc::new(Notify::new()),
+        );
+
+        // Without a previous check there is nothing to compare with
+        let result = health_check.evaluate(vec![hop("wg0", 100, 100), hop("wg1", 50, 50)]);
+        assert!(!result.entry_alive);
+
+        let result = health_check.evaluate(vec![hop("wg0", 200, 200), hop("wg1", 50, 100)]);
+        assert!(result.entry_alive);
+        assert!(!result.exit_stalled);
+
+        let result = health_check.evaluate(vec![hop("wg0", 200, 300), hop("wg1", 50, 150)]);
+        assert!(!result.entry_alive);
+        assert!(!result.exit_stalled);
+
+        let result = health_check.evaluate(vec![hop("wg0", 200, 400), hop("wg1", 50, 200)]);
+        assert!(result.exit_stalled);
+
+        // Counting starts over once the exit has been reported as stalled
+        let result = health_check.evaluate(vec![hop("wg0", 200, 500), hop("wg1", 50, 250)]);
+        assert!(!result.exit_stalled);
+    }
+
+    #[test]
+    fn recent_handshake_keeps_an_idle_entry_alive() {
+        let (connection_event_tx, _connection_event_rx) = mpsc::unbounded();
+        let mut health_check = WireguardHealthCheck::new(
+            Duration::from_secs(1),
+            TrafficStatsHandle::default(),
+            connection_event_tx,
+            Arc::new(Notify::new()),
+        );
+        let entry = |handshake_age: Duration| WgHopStats {
+            last_handshake: Some(SystemTime::now() - handshake_age),
+            ..hop("wg0", 100, 100)
+        };
+
+        health_check.evaluate(vec![entry(Duration::from_secs(10)), hop("wg1", 50, 50)]);
+        let result =
+            health_check.evaluate(vec![entry(Duration::from_secs(10)), hop("wg1", 50, 50)]);
+        assert!(result.entry_alive);
+
+        // Nothing received and the session has expired
+        let result = health_check.evaluate(vec![entry(HANDSHAKE_EXPIRY), hop("wg1", 50, 50)]);
+        assert!(!result.entry_alive);
+    }
+}
@@ -44,10 +44,15 @@ pub(crate) fn create_wireguard_tunnel(
                 .public_key()
                 .to_string(),
             private_ipv4: wireguard_config.gateway_data.private_ipv4,
+            mtu: wireguard_config.talpid_config.mtu,
         },
+        endpoint: wireguard_config.gateway_data.endpoint,
+        interface: None,
+        tunnel_gateway: wireguard_config.talpid_config.ipv4_gateway,
         receiver: finished_shutdown_rx,
         handle: tunnel_handle,
         tunnel_close_tx,
+        config: wireguard_config,
     };
 
     Ok((wireguard_waiting, event_rx))
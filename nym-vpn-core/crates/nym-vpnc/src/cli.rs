@@ -4,7 +4,7 @@
 use std::{net::IpAddr, path::PathBuf};
 
 use anyhow::{anyhow, Result};
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use nym_gateway_directory::{EntryPoint, ExitPoint, NodeIdentity, Recipient};
 
 #[derive(Parser)]
@@ -22,7 +22,9 @@ pub(crate) struct CliArgs {
 pub(crate) enum Command {
     Connect(ConnectArgs),
     Disconnect,
+    Switch(SwitchArgs),
     Status,
+    Stats(StatsArgs),
     Info,
     ImportCredential(ImportCredentialArgs),
     StoreAccount(StoreAccountArgs),
@@ -32,8 +34,22 @@ pub(crate) enum Command {
     ListExitGateways(ListExitGatewaysArgs),
     ListEntryCountries(ListEntryCountriesArgs),
     ListExitCountries(ListExitCountriesArgs),
+    GetSplitTunnel,
+    SetSplitTunnel(SetSplitTunnelArgs),
+    GetDnsFilter,
+    AddDnsBlocklist(AddDnsBlocklistArgs),
+    RemoveDnsBlocklist(DnsBlocklistArgs),
+    EnableDnsBlocklist(DnsBlocklistArgs),
+    DisableDnsBlocklist(DnsBlocklistArgs),
+    SetDnsAllowlist(SetDnsAllowlistArgs),
+    DnsFilterStats,
+    LeakTest(LeakTestArgs),
+    GetSettings,
+    SetSettings(SetSettingsArgs),
+    ResetSettings,
 }
 
+/// Options that are not given fall back to the settings stored in the daemon.
 #[derive(Args)]
 pub(crate) struct ConnectArgs {
     #[command(flatten)]
@@ -42,33 +58,126 @@ pub(crate) struct ConnectArgs {
     #[command(flatten)]
     pub(crate) exit: CliExit,
 
-    /// Set the IP address of the DNS server to use.
+    #[command(flatten)]
+    pub(crate) options: CliConnectOptions,
+}
+
+/// Switch the entry and/or exit gateway of the current connection, without disconnecting.
+#[derive(Args)]
+pub(crate) struct SwitchArgs {
+    #[command(flatten)]
+    pub(crate) entry: CliEntry,
+
+    #[command(flatten)]
+    pub(crate) exit: CliExit,
+}
+
+/// Replace the settings the daemon connects with.
+#[derive(Args)]
+pub(crate) struct SetSettingsArgs {
+    #[command(flatten)]
+    pub(crate) options: CliConnectOptions,
+}
+
+#[derive(Args)]
+pub(crate) struct CliConnectOptions {
+    /// Set the IP address of the DNS server to use. Can be given more than once, in order of
+    /// preference.
     #[arg(long)]
-    pub(crate) dns: Option<IpAddr>,
+    pub(crate) dns: Vec<IpAddr>,
+
+    /// Resolve a domain, and all names below it, through other DNS servers, such as internal ones
+    /// on the LAN. Given as DOMAIN=IP[,IP...], e.g. *.corp.example=192.168.1.53. Can be given more
+    /// than once. Not supported in two-hop mode.
+    #[arg(
+        long = "split-dns",
+        value_parser = parse_split_dns_rule,
+        conflicts_with = "enable_two_hop"
+    )]
+    pub(crate) split_dns_rules: Vec<nym_vpn_proto::SplitDnsRule>,
+
+    /// Resolve DNS through a local resolver that sends the queries over DNS over HTTPS through
+    /// the tunnel, so that the exit doesn't see them. Not supported in two-hop mode.
+    #[arg(
+        long,
+        group = "encrypted_dns",
+        conflicts_with = "enable_two_hop",
+        overrides_with = "no_encrypted_dns"
+    )]
+    pub(crate) dns_over_https: bool,
+
+    /// Like --dns-over-https, but using DNS over TLS.
+    #[arg(
+        long,
+        group = "encrypted_dns",
+        conflicts_with = "enable_two_hop",
+        overrides_with = "no_encrypted_dns"
+    )]
+    pub(crate) dns_over_tls: bool,
+
+    /// The resolvers the encrypted DNS queries are sent to, given as IP[@PORT]#TLS_NAME. Defaults
+    /// to Cloudflare.
+    #[arg(long = "encrypted-dns-upstream", requires = "encrypted_dns")]
+    pub(crate) encrypted_dns_upstreams: Vec<String>,
+
+    /// Resolve DNS in plain text, overriding the stored setting.
+    #[arg(long, overrides_with_all = ["dns_over_https", "dns_over_tls"])]
+    pub(crate) no_encrypted_dns: bool,
 
     /// Disable routing all traffic through the nym TUN device. When the flag is set, the nym TUN
     /// device will be created, but to route traffic through it you will need to do it manually,
     /// e.g. ping -Itun0.
-    #[arg(long)]
+    #[arg(long, overrides_with = "no_disable_routing")]
     pub(crate) disable_routing: bool,
 
+    /// Route all traffic through the nym TUN device, overriding the stored setting.
+    #[arg(long, overrides_with = "disable_routing")]
+    pub(crate) no_disable_routing: bool,
+
     /// Enable two-hop wireguard traffic. This means that traffic jumps directly from entry gateway to
     /// exit gateway using Wireguard protocol.
-    #[arg(long)]
+    #[arg(long, overrides_with = "no_enable_two_hop")]
     pub(crate) enable_two_hop: bool,
 
-    /// Enable Poisson process rate limiting of outbound traffic.
+    /// Connect in mixnet mode, overriding the stored setting.
+    #[arg(long, overrides_with = "enable_two_hop")]
+    pub(crate) no_enable_two_hop: bool,
+
+    /// The number of stacked wireguard tunnels in two-hop mode, including the entry and the exit.
+    /// Hops in between are picked in countries not used by any other hop.
+    #[arg(long, requires = "enable_two_hop", value_parser = clap::value_parser!(u32).range(2..=3))]
+    pub(crate) wireguard_hops: Option<u32>,
+
+    /// Shape the mixnet traffic with a profile: max-anonymity, balanced, low-latency,
+    /// bandwidth-saver, or one defined in the daemon config. The flags below take precedence over
+    /// it.
     #[arg(long)]
+    pub(crate) traffic_profile: Option<String>,
+
+    /// Enable Poisson process rate limiting of outbound traffic.
+    #[arg(long, overrides_with = "no_enable_poisson_rate")]
     pub(crate) enable_poisson_rate: bool,
 
+    /// Disable Poisson process rate limiting, overriding the stored setting.
+    #[arg(long, overrides_with = "enable_poisson_rate")]
+    pub(crate) no_enable_poisson_rate: bool,
+
     /// Disable constant rate background loop cover traffic.
-    #[arg(long)]
+    #[arg(long, overrides_with = "no_disable_background_cover_traffic")]
     pub(crate) disable_background_cover_traffic: bool,
 
+    /// Send background cover traffic, overriding the stored setting.
+    #[arg(long, overrides_with = "disable_background_cover_traffic")]
+    pub(crate) no_disable_background_cover_traffic: bool,
+
     /// Enable credentials mode.
-    #[arg(long)]
+    #[arg(long, overrides_with = "no_enable_credentials_mode")]
     pub(crate) enable_credentials_mode: bool,
 
+    /// Disable credentials mode, overriding the stored setting.
+    #[arg(long, overrides_with = "enable_credentials_mode")]
+    pub(crate) no_enable_credentials_mode: bool,
+
     /// An integer between 0 and 100 representing the minimum mixnode performance required to
     /// consider a mixnode for routing traffic.
     #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100), hide = true)]
@@ -78,6 +187,43 @@ pub(crate) struct ConnectArgs {
     /// consider a gateway for routing traffic.
     #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
     pub(crate) min_gateway_performance: Option<u8>,
+
+    /// Block all traffic that does not go through the tunnel, from the moment the mixnet client is
+    /// connected to the entry gateway until you disconnect. This also keeps blocking if the
+    /// connection fails. Not supported in two-hop mode, since the firewall can't let its nested
+    /// tunnels through.
+    #[arg(
+        long,
+        conflicts_with = "enable_two_hop",
+        overrides_with = "no_kill_switch"
+    )]
+    pub(crate) kill_switch: bool,
+
+    /// Disable the kill switch, overriding the stored setting.
+    #[arg(long, overrides_with = "kill_switch")]
+    pub(crate) no_kill_switch: bool,
+
+    /// Allow traffic to and from the local network while the kill switch is blocking.
+    #[arg(
+        long,
+        requires = "kill_switch",
+        overrides_with = "no_kill_switch_allow_lan"
+    )]
+    pub(crate) kill_switch_allow_lan: bool,
+
+    /// Block the local network too while the kill switch is blocking, overriding the stored
+    /// setting.
+    #[arg(long, overrides_with = "kill_switch_allow_lan")]
+    pub(crate) no_kill_switch_allow_lan: bool,
+
+    /// Answer the DNS queries for names on the enabled blocklists locally, so that they are never
+    /// resolved.
+    #[arg(long, overrides_with = "no_dns_filter")]
+    pub(crate) dns_filter: bool,
+
+    /// Disable the DNS filter, overriding the stored setting.
+    #[arg(long, overrides_with = "dns_filter")]
+    pub(crate) no_dns_filter: bool,
 }
 
 #[derive(Args)]
@@ -98,6 +244,10 @@ pub(crate) struct CliEntry {
     /// Auto-select entry gateway randomly.
     #[arg(long, alias = "entry-random")]
     pub(crate) entry_gateway_random: bool,
+
+    /// Auto-select the best entry gateway, scored by latency, performance and probe outcome.
+    #[arg(long, alias = "entry-best")]
+    pub(crate) entry_gateway_best: bool,
 }
 
 #[derive(Args)]
@@ -118,6 +268,10 @@ pub(crate) struct CliExit {
     /// Auto-select exit gateway randomly.
     #[clap(long, alias = "exit-random")]
     pub(crate) exit_gateway_random: bool,
+
+    /// Auto-select the best exit gateway, scored by latency, performance and probe outcome.
+    #[clap(long, alias = "exit-best")]
+    pub(crate) exit_gateway_best: bool,
 }
 
 #[derive(Args)]
@@ -165,6 +319,26 @@ pub(crate) struct StoreAccountArgs {
     pub(crate) mnemonic: String,
 }
 
+/// Show the traffic through the tunnel.
+#[derive(Args)]
+pub(crate) struct StatsArgs {
+    /// Keep printing the stats until interrupted.
+    #[arg(long)]
+    pub(crate) watch: bool,
+
+    /// How often to print the stats when watching, in milliseconds.
+    #[arg(long, requires = "watch")]
+    pub(crate) interval_ms: Option<u32>,
+}
+
+/// Check the connection for DNS and IP leaks. Exits with an error unless all checks pass.
+#[derive(Args)]
+pub(crate) struct LeakTestArgs {
+    /// Don't fail when a check can't tell whether there is a leak.
+    #[arg(long)]
+    pub(crate) allow_inconclusive: bool,
+}
+
 #[derive(Args)]
 pub(crate) struct ListEntryGatewaysArgs {
     /// An integer between 0 and 100 representing the minimum gateway performance required to
@@ -197,42 +371,125 @@ pub(crate) struct ListExitCountriesArgs {
     pub(crate) min_gateway_performance: Option<u8>,
 }
 
-pub(crate) fn parse_entry_point(args: &ConnectArgs) -> Result<Option<EntryPoint>> {
-    if let Some(ref entry_gateway_id) = args.entry.entry_gateway_id {
+#[derive(Args)]
+pub(crate) struct SetSplitTunnelArgs {
+    /// Only route this network (CIDR) through the tunnel. Can be given multiple times. If not
+    /// given, all traffic is routed through the tunnel.
+    #[arg(long)]
+    pub(crate) include: Vec<String>,
+
+    /// Route this network (CIDR) outside the tunnel. Can be given multiple times.
+    #[arg(long)]
+    pub(crate) exclude: Vec<String>,
+
+    /// Linux only: route the traffic of processes in this cgroup v2, e.g.
+    /// "system.slice/gitlab-runner.service", outside the tunnel. Can be given multiple times.
+    #[arg(long)]
+    pub(crate) exclude_cgroup: Vec<String>,
+}
+
+/// Add a list of domains to block when the DNS filter is enabled.
+#[derive(Args)]
+pub(crate) struct AddDnsBlocklistArgs {
+    /// The name of the list, shown in the filter stats.
+    #[arg(long)]
+    pub(crate) name: String,
+
+    /// The file with the list, which the daemon reads when connecting. It has to be in the
+    /// dns_blocklists directory next to the daemon config file, e.g. /etc/nym/dns_blocklists.
+    #[arg(long)]
+    pub(crate) path: PathBuf,
+
+    #[arg(long, value_enum, default_value_t = CliBlocklistFormat::Hosts)]
+    pub(crate) format: CliBlocklistFormat,
+
+    /// Add the list without using it yet.
+    #[arg(long)]
+    pub(crate) disabled: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub(crate) enum CliBlocklistFormat {
+    /// 0.0.0.0 ads.example lines, blocking only the listed names.
+    Hosts,
+    /// ||ads.example^ lines, blocking the names below them too.
+    Adblock,
+}
+
+#[derive(Args)]
+pub(crate) struct DnsBlocklistArgs {
+    /// The name of the blocklist, e.g. trackers for the bundled one.
+    pub(crate) name: String,
+}
+
+/// Replace the domains that are never blocked by the DNS filter.
+#[derive(Args)]
+pub(crate) struct SetDnsAllowlistArgs {
+    /// A domain that is never blocked, along with the names below it. Can be given multiple
+    /// times. If not given, the allowlist is cleared.
+    pub(crate) domains: Vec<String>,
+}
+
+// The daemon normalizes the domain, here we only check the format
+fn parse_split_dns_rule(rule: &str) -> std::result::Result<nym_vpn_proto::SplitDnsRule, String> {
+    let (domain, servers) = rule
+        .split_once('=')
+        .ok_or_else(|| format!("expected DOMAIN=IP[,IP...], got {rule}"))?;
+    let servers = servers
+        .split(',')
+        .map(|ip| {
+            ip.trim()
+                .parse::<IpAddr>()
+                .map(|ip| ip.to_string())
+                .map_err(|err| format!("invalid ip {ip}: {err}"))
+        })
+        .collect::<std::result::Result<_, _>>()?;
+    Ok(nym_vpn_proto::SplitDnsRule {
+        domain: domain.to_string(),
+        servers,
+    })
+}
+
+pub(crate) fn parse_entry_point(entry: &CliEntry) -> Result<Option<EntryPoint>> {
+    if let Some(ref entry_gateway_id) = entry.entry_gateway_id {
         Ok(Some(EntryPoint::Gateway {
             identity: NodeIdentity::from_base58_string(entry_gateway_id.clone())
                 .map_err(|_| anyhow!("Failed to parse gateway id"))?,
         }))
-    } else if let Some(ref entry_gateway_country) = args.entry.entry_gateway_country {
+    } else if let Some(ref entry_gateway_country) = entry.entry_gateway_country {
         Ok(Some(EntryPoint::Location {
             location: entry_gateway_country.clone(),
         }))
-    } else if args.entry.entry_gateway_low_latency {
+    } else if entry.entry_gateway_low_latency {
         Ok(Some(EntryPoint::RandomLowLatency))
-    } else if args.entry.entry_gateway_random {
+    } else if entry.entry_gateway_random {
         Ok(Some(EntryPoint::Random))
+    } else if entry.entry_gateway_best {
+        Ok(Some(EntryPoint::Best))
     } else {
         Ok(None)
     }
 }
 
-pub(crate) fn parse_exit_point(args: &ConnectArgs) -> Result<Option<ExitPoint>> {
-    if let Some(ref exit_router_address) = args.exit.exit_router_address {
+pub(crate) fn parse_exit_point(exit: &CliExit) -> Result<Option<ExitPoint>> {
+    if let Some(ref exit_router_address) = exit.exit_router_address {
         Ok(Some(ExitPoint::Address {
             address: Recipient::try_from_base58_string(exit_router_address.clone())
                 .map_err(|_| anyhow!("Failed to parse exit node address"))?,
         }))
-    } else if let Some(ref exit_router_id) = args.exit.exit_gateway_id {
+    } else if let Some(ref exit_router_id) = exit.exit_gateway_id {
         Ok(Some(ExitPoint::Gateway {
             identity: NodeIdentity::from_base58_string(exit_router_id.clone())
                 .map_err(|_| anyhow!("Failed to parse gateway id"))?,
         }))
-    } else if let Some(ref exit_gateway_country) = args.exit.exit_gateway_country {
+    } else if let Some(ref exit_gateway_country) = exit.exit_gateway_country {
         Ok(Some(ExitPoint::Location {
             location: exit_gateway_country.clone(),
         }))
-    } else if args.exit.exit_gateway_random {
+    } else if exit.exit_gateway_random {
         Ok(Some(ExitPoint::Random))
+    } else if exit.exit_gateway_best {
+        Ok(Some(ExitPoint::Best))
     } else {
         Ok(None)
     }
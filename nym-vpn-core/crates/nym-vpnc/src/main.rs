@@ -4,9 +4,14 @@
 use anyhow::Result;
 use clap::Parser;
 use nym_vpn_proto::{
-    ConnectRequest, DisconnectRequest, Empty, ImportUserCredentialRequest, InfoRequest,
-    ListEntryCountriesRequest, ListEntryGatewaysRequest, ListExitCountriesRequest,
-    ListExitGatewaysRequest, StatusRequest, StoreAccountRequest,
+    dns_blocklist, dns_blocklist_file, leak_test_report, ConnectRequest, DisconnectRequest,
+    DnsBlocklist, DnsBlocklistFile, DnsFilterConfig, Empty, GetDnsFilterRequest,
+    GetDnsFilterStatsRequest, GetSettingsRequest, GetSplitTunnelRequest, GetTrafficStatsRequest,
+    ImportUserCredentialRequest, InfoRequest, ListEntryCountriesRequest, ListEntryGatewaysRequest,
+    ListExitCountriesRequest, ListExitGatewaysRequest, ResetSettingsRequest, RunLeakTestRequest,
+    SetDnsFilterRequest, SetSettingsRequest, SetSplitTunnelRequest, SplitTunnelConfig,
+    StatusRequest, StoreAccountRequest, SwitchGatewayRequest, VpnSettings,
+    WatchTrafficStatsRequest,
 };
 use protobuf_conversion::into_threshold;
 use vpnd_client::ClientType;
@@ -14,7 +19,7 @@ use vpnd_client::ClientType;
 use crate::{
     cli::{Command, ImportCredentialTypeEnum},
     protobuf_conversion::{
-        into_entry_point, into_exit_point, ipaddr_into_string, parse_offset_datetime,
+        into_dns, into_encrypted_dns, into_entry_point, into_exit_point, parse_offset_datetime,
     },
 };
 
@@ -34,7 +39,9 @@ async fn main() -> Result<()> {
     match args.command {
         Command::Connect(ref connect_args) => connect(client_type, connect_args).await?,
         Command::Disconnect => disconnect(client_type).await?,
+        Command::Switch(ref switch_args) => switch_gateway(client_type, switch_args).await?,
         Command::Status => status(client_type).await?,
+        Command::Stats(ref stats_args) => stats(client_type, stats_args).await?,
         Command::Info => info(client_type).await?,
         Command::ImportCredential(ref import_args) => {
             import_credential(client_type, import_args).await?
@@ -54,25 +61,68 @@ async fn main() -> Result<()> {
         Command::ListExitCountries(ref list_args) => {
             list_exit_countries(client_type, list_args).await?
         }
+        Command::GetSettings => get_settings(client_type).await?,
+        Command::SetSettings(ref settings_args) => set_settings(client_type, settings_args).await?,
+        Command::ResetSettings => reset_settings(client_type).await?,
+        Command::GetSplitTunnel => get_split_tunnel(client_type).await?,
+        Command::SetSplitTunnel(ref split_tunnel_args) => {
+            set_split_tunnel(client_type, split_tunnel_args).await?
+        }
+        Command::GetDnsFilter => get_dns_filter(client_type).await?,
+        Command::AddDnsBlocklist(ref blocklist_args) => {
+            add_dns_blocklist(client_type, blocklist_args).await?
+        }
+        Command::RemoveDnsBlocklist(ref blocklist_args) => {
+            remove_dns_blocklist(client_type, blocklist_args).await?
+        }
+        Command::EnableDnsBlocklist(ref blocklist_args) => {
+            enable_dns_blocklist(client_type, blocklist_args, true).await?
+        }
+        Command::DisableDnsBlocklist(ref blocklist_args) => {
+            enable_dns_blocklist(client_type, blocklist_args, false).await?
+        }
+        Command::SetDnsAllowlist(ref allowlist_args) => {
+            set_dns_allowlist(client_type, allowlist_args).await?
+        }
+        Command::DnsFilterStats => dns_filter_stats(client_type).await?,
+        Command::LeakTest(ref leak_test_args) => leak_test(client_type, leak_test_args).await?,
     }
     Ok(())
 }
 
 async fn connect(client_type: ClientType, connect_args: &cli::ConnectArgs) -> Result<()> {
-    let entry = cli::parse_entry_point(connect_args)?;
-    let exit = cli::parse_exit_point(connect_args)?;
+    let entry = cli::parse_entry_point(&connect_args.entry)?;
+    let exit = cli::parse_exit_point(&connect_args.exit)?;
+    let options = &connect_args.options;
 
     let request = tonic::Request::new(ConnectRequest {
         entry: entry.map(into_entry_point),
         exit: exit.map(into_exit_point),
-        dns: connect_args.dns.map(ipaddr_into_string),
-        disable_routing: connect_args.disable_routing,
-        enable_two_hop: connect_args.enable_two_hop,
-        enable_poisson_rate: connect_args.enable_poisson_rate,
-        disable_background_cover_traffic: connect_args.disable_background_cover_traffic,
-        enable_credentials_mode: connect_args.enable_credentials_mode,
-        min_mixnode_performance: connect_args.min_mixnode_performance.map(into_threshold),
-        min_gateway_performance: connect_args.min_gateway_performance.map(into_threshold),
+        dns: into_dns(&options.dns, &options.split_dns_rules),
+        // Only send the flags that are given, so that the daemon uses its stored settings for the
+        // rest
+        disable_routing: flag(options.disable_routing, options.no_disable_routing),
+        enable_two_hop: flag(options.enable_two_hop, options.no_enable_two_hop),
+        enable_poisson_rate: flag(options.enable_poisson_rate, options.no_enable_poisson_rate),
+        disable_background_cover_traffic: flag(
+            options.disable_background_cover_traffic,
+            options.no_disable_background_cover_traffic,
+        ),
+        enable_credentials_mode: flag(
+            options.enable_credentials_mode,
+            options.no_enable_credentials_mode,
+        ),
+        min_mixnode_performance: options.min_mixnode_performance.map(into_threshold),
+        min_gateway_performance: options.min_gateway_performance.map(into_threshold),
+        enable_kill_switch: flag(options.kill_switch, options.no_kill_switch),
+        kill_switch_allow_lan: flag(
+            options.kill_switch_allow_lan,
+            options.no_kill_switch_allow_lan,
+        ),
+        wireguard_hop_count: options.wireguard_hops,
+        enable_dns_filter: flag(options.dns_filter, options.no_dns_filter),
+        traffic_profile: options.traffic_profile.clone(),
+        encrypted_dns: into_encrypted_dns(options),
     });
 
     let mut client = vpnd_client::get_client(client_type).await?;
@@ -81,6 +131,15 @@ async fn connect(client_type: ClientType, connect_args: &cli::ConnectArgs) -> Re
     Ok(())
 }
 
+// A flag and its --no- counterpart, the last one given wins
+fn flag(set: bool, unset: bool) -> Option<bool> {
+    match (set, unset) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
+
 async fn disconnect(client_type: ClientType) -> Result<()> {
     let mut client = vpnd_client::get_client(client_type).await?;
     let request = tonic::Request::new(DisconnectRequest {});
@@ -89,6 +148,25 @@ async fn disconnect(client_type: ClientType) -> Result<()> {
     Ok(())
 }
 
+async fn switch_gateway(client_type: ClientType, switch_args: &cli::SwitchArgs) -> Result<()> {
+    let entry = cli::parse_entry_point(&switch_args.entry)?;
+    let exit = cli::parse_exit_point(&switch_args.exit)?;
+    if entry.is_none() && exit.is_none() {
+        return Err(anyhow::anyhow!(
+            "Specify an entry and/or exit gateway to switch to"
+        ));
+    }
+
+    let mut client = vpnd_client::get_client(client_type).await?;
+    let request = tonic::Request::new(SwitchGatewayRequest {
+        entry: entry.map(into_entry_point),
+        exit: exit.map(into_exit_point),
+    });
+    let response = client.switch_gateway(request).await?.into_inner();
+    println!("{:#?}", response);
+    Ok(())
+}
+
 async fn status(client_type: ClientType) -> Result<()> {
     let mut client = vpnd_client::get_client(client_type).await?;
     let request = tonic::Request::new(StatusRequest {});
@@ -107,6 +185,36 @@ async fn status(client_type: ClientType) -> Result<()> {
     Ok(())
 }
 
+async fn stats(client_type: ClientType, stats_args: &cli::StatsArgs) -> Result<()> {
+    let mut client = vpnd_client::get_client(client_type).await?;
+    if !stats_args.watch {
+        let request = tonic::Request::new(GetTrafficStatsRequest {});
+        let response = client.get_traffic_stats(request).await?.into_inner();
+        println!("{:#?}", response);
+        return Ok(());
+    }
+
+    let request = tonic::Request::new(WatchTrafficStatsRequest {
+        interval_ms: stats_args.interval_ms,
+    });
+    let mut stream = client.watch_traffic_stats(request).await?.into_inner();
+    while let Some(response) = stream.message().await? {
+        match response.stats {
+            Some(stats) => println!(
+                "in: {} bytes ({:.1} kB/s), out: {} bytes ({:.1} kB/s), packets in/out: {}/{}",
+                stats.bytes_in,
+                stats.throughput_in / 1000.0,
+                stats.bytes_out,
+                stats.throughput_out / 1000.0,
+                stats.packets_in,
+                stats.packets_out,
+            ),
+            None => println!("not connected"),
+        }
+    }
+    Ok(())
+}
+
 async fn info(client_type: ClientType) -> Result<()> {
     let mut client = vpnd_client::get_client(client_type).await?;
     let request = tonic::Request::new(InfoRequest {});
@@ -253,3 +361,211 @@ async fn list_exit_countries(
     println!("{:#?}", response);
     Ok(())
 }
+
+async fn get_settings(client_type: ClientType) -> Result<()> {
+    let mut client = vpnd_client::get_client(client_type).await?;
+    let request = tonic::Request::new(GetSettingsRequest {});
+    let response = client.get_settings(request).await?.into_inner();
+    println!("{:#?}", response);
+    Ok(())
+}
+
+async fn set_settings(client_type: ClientType, settings_args: &cli::SetSettingsArgs) -> Result<()> {
+    let options = &settings_args.options;
+    let mut client = vpnd_client::get_client(client_type).await?;
+    let request = tonic::Request::new(SetSettingsRequest {
+        settings: Some(VpnSettings {
+            dns: into_dns(&options.dns, &options.split_dns_rules),
+            disable_routing: options.disable_routing,
+            enable_two_hop: options.enable_two_hop,
+            enable_poisson_rate: options.enable_poisson_rate,
+            disable_background_cover_traffic: options.disable_background_cover_traffic,
+            enable_credentials_mode: options.enable_credentials_mode,
+            min_mixnode_performance: options.min_mixnode_performance.map(into_threshold),
+            min_gateway_performance: options.min_gateway_performance.map(into_threshold),
+            enable_kill_switch: options.kill_switch,
+            kill_switch_allow_lan: options.kill_switch_allow_lan,
+            wireguard_hop_count: options.wireguard_hops,
+            enable_dns_filter: options.dns_filter,
+            traffic_profile: options.traffic_profile.clone(),
+            encrypted_dns: into_encrypted_dns(options),
+        }),
+    });
+    let response = client.set_settings(request).await?.into_inner();
+    println!("{:#?}", response);
+    Ok(())
+}
+
+async fn reset_settings(client_type: ClientType) -> Result<()> {
+    let mut client = vpnd_client::get_client(client_type).await?;
+    let request = tonic::Request::new(ResetSettingsRequest {});
+    let response = client.reset_settings(request).await?.into_inner();
+    println!("{:#?}", response);
+    Ok(())
+}
+
+async fn get_split_tunnel(client_type: ClientType) -> Result<()> {
+    let mut client = vpnd_client::get_client(client_type).await?;
+    let request = tonic::Request::new(GetSplitTunnelRequest {});
+    let response = client.get_split_tunnel(request).await?.into_inner();
+    println!("{:#?}", response);
+    Ok(())
+}
+
+async fn set_split_tunnel(
+    client_type: ClientType,
+    split_tunnel_args: &cli::SetSplitTunnelArgs,
+) -> Result<()> {
+    let mut client = vpnd_client::get_client(client_type).await?;
+    let request = tonic::Request::new(SetSplitTunnelRequest {
+        config: Some(SplitTunnelConfig {
+            include_networks: split_tunnel_args.include.clone(),
+            exclude_networks: split_tunnel_args.exclude.clone(),
+            excluded_cgroups: split_tunnel_args.exclude_cgroup.clone(),
+        }),
+    });
+    let response = client.set_split_tunnel(request).await?.into_inner();
+    println!("{:#?}", response);
+    Ok(())
+}
+
+async fn get_dns_filter(client_type: ClientType) -> Result<()> {
+    let mut client = vpnd_client::get_client(client_type).await?;
+    let request = tonic::Request::new(GetDnsFilterRequest {});
+    let response = client.get_dns_filter(request).await?.into_inner();
+    println!("{:#?}", response);
+    Ok(())
+}
+
+// The daemon only stores the whole filter config, so the lists are edited here and sent back
+async fn update_dns_filter(
+    client_type: ClientType,
+    update: impl FnOnce(&mut DnsFilterConfig) -> Result<()>,
+) -> Result<()> {
+    let mut client = vpnd_client::get_client(client_type).await?;
+    let request = tonic::Request::new(GetDnsFilterRequest {});
+    let mut config = client
+        .get_dns_filter(request)
+        .await?
+        .into_inner()
+        .config
+        .unwrap_or_default();
+    update(&mut config)?;
+
+    let request = tonic::Request::new(SetDnsFilterRequest {
+        config: Some(config),
+    });
+    let response = client.set_dns_filter(request).await?.into_inner();
+    println!("{:#?}", response);
+    Ok(())
+}
+
+async fn add_dns_blocklist(
+    client_type: ClientType,
+    blocklist_args: &cli::AddDnsBlocklistArgs,
+) -> Result<()> {
+    // The daemon has its own working directory, so it needs the full path
+    let path = std::fs::canonicalize(&blocklist_args.path)?;
+    let format = match blocklist_args.format {
+        cli::CliBlocklistFormat::Hosts => dns_blocklist_file::Format::Hosts,
+        cli::CliBlocklistFormat::Adblock => dns_blocklist_file::Format::Adblock,
+    };
+    let blocklist = DnsBlocklist {
+        name: blocklist_args.name.clone(),
+        enabled: !blocklist_args.disabled,
+        source: Some(dns_blocklist::Source::File(DnsBlocklistFile {
+            path: path.display().to_string(),
+            format: format as i32,
+        })),
+    };
+    update_dns_filter(client_type, |config| {
+        if config
+            .blocklists
+            .iter()
+            .any(|existing| existing.name == blocklist.name)
+        {
+            return Err(anyhow::anyhow!(
+                "a blocklist named {} already exists",
+                blocklist.name
+            ));
+        }
+        config.blocklists.push(blocklist);
+        Ok(())
+    })
+    .await
+}
+
+async fn remove_dns_blocklist(
+    client_type: ClientType,
+    blocklist_args: &cli::DnsBlocklistArgs,
+) -> Result<()> {
+    update_dns_filter(client_type, |config| {
+        let count = config.blocklists.len();
+        config
+            .blocklists
+            .retain(|blocklist| blocklist.name != blocklist_args.name);
+        if config.blocklists.len() == count {
+            return Err(anyhow::anyhow!(
+                "no blocklist named {}",
+                blocklist_args.name
+            ));
+        }
+        Ok(())
+    })
+    .await
+}
+
+async fn enable_dns_blocklist(
+    client_type: ClientType,
+    blocklist_args: &cli::DnsBlocklistArgs,
+    enabled: bool,
+) -> Result<()> {
+    update_dns_filter(client_type, |config| {
+        let blocklist = config
+            .blocklists
+            .iter_mut()
+            .find(|blocklist| blocklist.name == blocklist_args.name)
+            .ok_or_else(|| anyhow::anyhow!("no blocklist named {}", blocklist_args.name))?;
+        blocklist.enabled = enabled;
+        Ok(())
+    })
+    .await
+}
+
+async fn set_dns_allowlist(
+    client_type: ClientType,
+    allowlist_args: &cli::SetDnsAllowlistArgs,
+) -> Result<()> {
+    update_dns_filter(client_type, |config| {
+        config.allowlist = allowlist_args.domains.clone();
+        Ok(())
+    })
+    .await
+}
+
+async fn dns_filter_stats(client_type: ClientType) -> Result<()> {
+    let mut client = vpnd_client::get_client(client_type).await?;
+    let request = tonic::Request::new(GetDnsFilterStatsRequest {});
+    let response = client.get_dns_filter_stats(request).await?.into_inner();
+    println!("{:#?}", response);
+    Ok(())
+}
+
+async fn leak_test(client_type: ClientType, leak_test_args: &cli::LeakTestArgs) -> Result<()> {
+    let mut client = vpnd_client::get_client(client_type).await?;
+    let request = tonic::Request::new(RunLeakTestRequest {});
+    let response = client.run_leak_test(request).await?.into_inner();
+    println!("{:#?}", response);
+
+    let report = response
+        .report
+        .ok_or_else(|| anyhow::anyhow!("the leak test needs an active connection"))?;
+    match report.outcome() {
+        leak_test_report::Outcome::Pass => Ok(()),
+        leak_test_report::Outcome::Inconclusive if leak_test_args.allow_inconclusive => Ok(()),
+        outcome => Err(anyhow::anyhow!(
+            "leak test outcome: {}",
+            outcome.as_str_name().to_lowercase()
+        )),
+    }
+}
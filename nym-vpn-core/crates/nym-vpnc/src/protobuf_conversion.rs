@@ -39,12 +39,21 @@ fn new_entry_node_random() -> nym_vpn_proto::EntryNode {
     }
 }
 
+fn new_entry_node_best() -> nym_vpn_proto::EntryNode {
+    nym_vpn_proto::EntryNode {
+        entry_node_enum: Some(nym_vpn_proto::entry_node::EntryNodeEnum::Best(
+            nym_vpn_proto::Empty {},
+        )),
+    }
+}
+
 pub(crate) fn into_entry_point(entry: EntryPoint) -> nym_vpn_proto::EntryNode {
     match entry {
         EntryPoint::Gateway { identity } => new_entry_node_gateway(&identity),
         EntryPoint::Location { location } => new_entry_node_location(&location),
         EntryPoint::RandomLowLatency => new_entry_node_random_low_latency(),
         EntryPoint::Random => new_entry_node_random(),
+        EntryPoint::Best => new_entry_node_best(),
     }
 }
 
@@ -86,17 +95,58 @@ fn new_exit_node_random() -> nym_vpn_proto::ExitNode {
     }
 }
 
+fn new_exit_node_best() -> nym_vpn_proto::ExitNode {
+    nym_vpn_proto::ExitNode {
+        exit_node_enum: Some(nym_vpn_proto::exit_node::ExitNodeEnum::Best(
+            nym_vpn_proto::Empty {},
+        )),
+    }
+}
+
 pub(crate) fn into_exit_point(exit: ExitPoint) -> nym_vpn_proto::ExitNode {
     match exit {
         ExitPoint::Address { address } => new_exit_node_address(&address),
         ExitPoint::Gateway { identity } => new_exit_node_gateway(&identity),
         ExitPoint::Location { location } => new_exit_node_location(&location),
         ExitPoint::Random => new_exit_node_random(),
+        ExitPoint::Best => new_exit_node_best(),
     }
 }
 
-pub(crate) fn ipaddr_into_string(ip: std::net::IpAddr) -> nym_vpn_proto::Dns {
-    nym_vpn_proto::Dns { ip: ip.to_string() }
+// Not set when empty, so that the daemon falls back to its stored settings
+pub(crate) fn into_dns(
+    servers: &[std::net::IpAddr],
+    split_rules: &[nym_vpn_proto::SplitDnsRule],
+) -> Option<nym_vpn_proto::Dns> {
+    if servers.is_empty() && split_rules.is_empty() {
+        return None;
+    }
+    Some(nym_vpn_proto::Dns {
+        ip: String::new(),
+        servers: servers.iter().map(ToString::to_string).collect(),
+        split_rules: split_rules.to_vec(),
+    })
+}
+
+// Only set when one of the flags is given, so that the daemon uses its stored setting otherwise
+pub(crate) fn into_encrypted_dns(
+    options: &crate::cli::CliConnectOptions,
+) -> Option<nym_vpn_proto::EncryptedDns> {
+    use nym_vpn_proto::encrypted_dns::Protocol;
+
+    let protocol = if options.dns_over_https {
+        Protocol::Https
+    } else if options.dns_over_tls {
+        Protocol::Tls
+    } else if options.no_encrypted_dns {
+        Protocol::Unspecified
+    } else {
+        return None;
+    };
+    Some(nym_vpn_proto::EncryptedDns {
+        protocol: protocol.into(),
+        upstreams: options.encrypted_dns_upstreams.clone(),
+    })
 }
 
 pub(crate) fn into_threshold(performance: u8) -> nym_vpn_proto::Threshold {
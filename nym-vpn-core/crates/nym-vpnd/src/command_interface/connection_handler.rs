@@ -4,16 +4,21 @@
 use nym_vpn_api_client::response::{
     NymVpnAccountSummaryResponse, NymVpnDevice, NymVpnZkNym, NymVpnZkNymResponse,
 };
-use nym_vpn_lib::gateway_directory::{EntryPoint, ExitPoint, GatewayClient};
+use nym_vpn_lib::{
+    gateway_directory::{Cached, EntryPoint, ExitPoint, GatewayClient},
+    DnsFilterConfig, DnsFilterStats, LeakTestReport, SplitTunnelConfig, ThroughputSampler,
+    TrafficStats,
+};
 use time::OffsetDateTime;
 use tokio::sync::{mpsc::UnboundedSender, oneshot};
 use tracing::{debug, info, warn};
 
 use crate::{
     service::{
-        AccountError, ConnectArgs, ConnectOptions, ImportCredentialError, VpnServiceCommand,
+        data_dir, gateway_cache_config, AccountError, ConnectArgs, ConnectOptions,
+        ImportCredentialError, SettingsError, SplitTunnelError, VpnServiceCommand,
         VpnServiceConnectResult, VpnServiceDisconnectResult, VpnServiceInfoResult,
-        VpnServiceStatusResult,
+        VpnServiceStatusResult, VpnServiceSwitchGatewayResult, VpnSettings,
     },
     types::gateway,
 };
@@ -97,6 +102,32 @@ impl CommandInterfaceConnectionHandler {
         result
     }
 
+    pub(crate) async fn handle_switch_gateway(
+        &self,
+        entry: Option<EntryPoint>,
+        exit: Option<ExitPoint>,
+    ) -> VpnServiceSwitchGatewayResult {
+        let (tx, rx) = oneshot::channel();
+        self.vpn_command_tx
+            .send(VpnServiceCommand::SwitchGateway(tx, entry, exit))
+            .unwrap();
+        debug!("Sent switch gateway command to VPN");
+        debug!("Waiting for response");
+        let result = rx.await.unwrap();
+        match result {
+            VpnServiceSwitchGatewayResult::Success => {
+                debug!("VPN switch gateway command sent successfully");
+            }
+            VpnServiceSwitchGatewayResult::NotConnected => {
+                info!("VPN can't switch gateway - it's not connected");
+            }
+            VpnServiceSwitchGatewayResult::EntrySwitchNotSupported => {
+                info!("VPN can't switch the entry gateway in mixnet mode");
+            }
+        };
+        result
+    }
+
     pub(crate) async fn handle_info(&self) -> VpnServiceInfoResult {
         let (tx, rx) = oneshot::channel();
         self.vpn_command_tx
@@ -121,6 +152,44 @@ impl CommandInterfaceConnectionHandler {
         status
     }
 
+    // Without a sampler, the throughput is measured since the previous request by any client
+    pub(crate) async fn handle_get_traffic_stats(
+        &self,
+        throughput: Option<&mut ThroughputSampler>,
+    ) -> Option<TrafficStats> {
+        let (tx, rx) = oneshot::channel();
+        self.vpn_command_tx
+            .send(VpnServiceCommand::GetTrafficStats(tx))
+            .unwrap();
+        debug!("Sent get traffic stats command to VPN");
+        debug!("Waiting for response");
+        let traffic_stats = rx.await.unwrap()?;
+        Some(match throughput {
+            Some(throughput) => traffic_stats.sample(throughput).await,
+            None => traffic_stats.snapshot().await,
+        })
+    }
+
+    pub(crate) async fn handle_get_dns_filter_stats(&self) -> Option<DnsFilterStats> {
+        let (tx, rx) = oneshot::channel();
+        self.vpn_command_tx
+            .send(VpnServiceCommand::GetDnsFilterStats(tx))
+            .unwrap();
+        debug!("Sent get dns filter stats command to VPN");
+        debug!("Waiting for response");
+        rx.await.unwrap()
+    }
+
+    pub(crate) async fn handle_run_leak_test(&self) -> Option<LeakTestReport> {
+        let (tx, rx) = oneshot::channel();
+        self.vpn_command_tx
+            .send(VpnServiceCommand::RunLeakTest(tx))
+            .unwrap();
+        debug!("Sent run leak test command to VPN");
+        debug!("Waiting for response");
+        rx.await.unwrap()
+    }
+
     pub(crate) async fn handle_import_credential(
         &self,
         credential: Vec<u8>,
@@ -139,25 +208,25 @@ impl CommandInterfaceConnectionHandler {
     pub(crate) async fn handle_list_entry_gateways(
         &self,
         min_gateway_performance: Option<u8>,
-    ) -> Result<Vec<gateway::Gateway>, ListGatewayError> {
+    ) -> Result<Cached<Vec<gateway::Gateway>>, ListGatewayError> {
         let gateways = directory_client(min_gateway_performance)?
-            .lookup_entry_gateways()
+            .lookup_entry_gateways_cached()
             .await
             .map_err(|error| ListGatewayError::GetEntryGateways { error })?;
 
-        Ok(gateways.into_iter().map(gateway::Gateway::from).collect())
+        Ok(gateways.map(|gateways| gateways.into_iter().map(gateway::Gateway::from).collect()))
     }
 
     pub(crate) async fn handle_list_exit_gateways(
         &self,
         min_gateway_performance: Option<u8>,
-    ) -> Result<Vec<gateway::Gateway>, ListGatewayError> {
+    ) -> Result<Cached<Vec<gateway::Gateway>>, ListGatewayError> {
         let gateways = directory_client(min_gateway_performance)?
-            .lookup_exit_gateways()
+            .lookup_exit_gateways_cached()
             .await
             .map_err(|error| ListGatewayError::GetExitGateways { error })?;
 
-        Ok(gateways.into_iter().map(gateway::Gateway::from).collect())
+        Ok(gateways.map(|gateways| gateways.into_iter().map(gateway::Gateway::from).collect()))
     }
 
     pub(crate) async fn handle_list_entry_countries(
@@ -237,6 +306,87 @@ impl CommandInterfaceConnectionHandler {
         debug!("VPN get device zk nyms result: {:?}", result);
         result
     }
+
+    pub(crate) async fn handle_get_settings(&self) -> Result<VpnSettings, SettingsError> {
+        let (tx, rx) = oneshot::channel();
+        self.vpn_command_tx
+            .send(VpnServiceCommand::GetSettings(tx))
+            .unwrap();
+        let result = rx.await.unwrap();
+        debug!("VPN get settings result: {:?}", result);
+        result
+    }
+
+    pub(crate) async fn handle_set_settings(
+        &self,
+        settings: VpnSettings,
+    ) -> Result<(), SettingsError> {
+        let (tx, rx) = oneshot::channel();
+        self.vpn_command_tx
+            .send(VpnServiceCommand::SetSettings(tx, settings))
+            .unwrap();
+        let result = rx.await.unwrap();
+        debug!("VPN set settings result: {:?}", result);
+        result
+    }
+
+    pub(crate) async fn handle_reset_settings(&self) -> Result<VpnSettings, SettingsError> {
+        let (tx, rx) = oneshot::channel();
+        self.vpn_command_tx
+            .send(VpnServiceCommand::ResetSettings(tx))
+            .unwrap();
+        let result = rx.await.unwrap();
+        debug!("VPN reset settings result: {:?}", result);
+        result
+    }
+
+    pub(crate) async fn handle_get_split_tunnel(
+        &self,
+    ) -> Result<SplitTunnelConfig, SplitTunnelError> {
+        let (tx, rx) = oneshot::channel();
+        self.vpn_command_tx
+            .send(VpnServiceCommand::GetSplitTunnel(tx))
+            .unwrap();
+        let result = rx.await.unwrap();
+        debug!("VPN get split tunnel result: {:?}", result);
+        result
+    }
+
+    pub(crate) async fn handle_set_split_tunnel(
+        &self,
+        split_tunnel: SplitTunnelConfig,
+    ) -> Result<(), SplitTunnelError> {
+        let (tx, rx) = oneshot::channel();
+        self.vpn_command_tx
+            .send(VpnServiceCommand::SetSplitTunnel(tx, split_tunnel))
+            .unwrap();
+        let result = rx.await.unwrap();
+        debug!("VPN set split tunnel result: {:?}", result);
+        result
+    }
+
+    pub(crate) async fn handle_get_dns_filter(&self) -> Result<DnsFilterConfig, SettingsError> {
+        let (tx, rx) = oneshot::channel();
+        self.vpn_command_tx
+            .send(VpnServiceCommand::GetDnsFilter(tx))
+            .unwrap();
+        let result = rx.await.unwrap();
+        debug!("VPN get dns filter result: {:?}", result);
+        result
+    }
+
+    pub(crate) async fn handle_set_dns_filter(
+        &self,
+        dns_filter: DnsFilterConfig,
+    ) -> Result<(), SettingsError> {
+        let (tx, rx) = oneshot::channel();
+        self.vpn_command_tx
+            .send(VpnServiceCommand::SetDnsFilter(tx, dns_filter))
+            .unwrap();
+        let result = rx.await.unwrap();
+        debug!("VPN set dns filter result: {:?}", result);
+        result
+    }
 }
 
 fn directory_client(
@@ -244,7 +394,8 @@ fn directory_client(
 ) -> Result<GatewayClient, ListGatewayError> {
     let user_agent = nym_bin_common::bin_info_local_vergen!().into();
     let directory_config =
-        nym_vpn_lib::gateway_directory::Config::new_from_env(min_gateway_performance);
+        nym_vpn_lib::gateway_directory::Config::new_from_env(min_gateway_performance)
+            .with_cache(gateway_cache_config(&data_dir()));
     GatewayClient::new(directory_config, user_agent)
         .map_err(|error| ListGatewayError::CreateGatewayDirectoryClient { error })
 }
@@ -5,4 +5,19 @@ pub(crate) enum CommandInterfaceError {
         ip: String,
         source: std::net::AddrParseError,
     },
+
+    #[error("invalid split DNS rule for {domain}: {reason}")]
+    InvalidSplitDnsRule { domain: String, reason: String },
+
+    #[error("invalid encrypted DNS upstream: {reason}")]
+    InvalidEncryptedDnsUpstream { reason: String },
+
+    #[error("invalid DNS filter config: {reason}")]
+    InvalidDnsFilter { reason: String },
+
+    #[error("failed to parse network: {network}")]
+    FailedToParseNetwork {
+        network: String,
+        source: ipnetwork::IpNetworkError,
+    },
 }
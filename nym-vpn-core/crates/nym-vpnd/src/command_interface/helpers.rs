@@ -36,6 +36,10 @@ pub(super) fn parse_entry_point(
             info!("Connecting to random entry node");
             EntryPoint::Random
         }
+        nym_vpn_proto::entry_node::EntryNodeEnum::Best(_) => {
+            info!("Connecting to best entry node");
+            EntryPoint::Best
+        }
     })
 }
 
@@ -76,9 +80,18 @@ pub(super) fn parse_exit_point(
             info!("Connecting to low latency exit node");
             ExitPoint::Random
         }
+        nym_vpn_proto::exit_node::ExitNodeEnum::Best(_) => {
+            info!("Connecting to best exit node");
+            ExitPoint::Best
+        }
     })
 }
 
 pub(super) fn threshold_into_u8(threshold: nym_vpn_proto::Threshold) -> u8 {
     threshold.min_performance.clamp(0, 100) as u8
 }
+
+// Out of range values are rejected when the settings are validated
+pub(super) fn hop_count_into_u8(hop_count: u32) -> u8 {
+    hop_count.min(u8::MAX.into()) as u8
+}
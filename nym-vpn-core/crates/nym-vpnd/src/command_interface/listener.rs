@@ -5,33 +5,46 @@ use std::{
     fs,
     net::SocketAddr,
     path::{Path, PathBuf},
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
 
 use futures::{stream::BoxStream, StreamExt};
+use nym_vpn_lib::{DnsConfig, ThroughputSampler};
 use nym_vpn_proto::{
     nym_vpnd_server::NymVpnd, AccountError, ConnectRequest, ConnectResponse, ConnectionStateChange,
     ConnectionStatusUpdate, DisconnectRequest, DisconnectResponse, Empty, GetAccountSummaryRequest,
-    GetAccountSummaryResponse, ImportUserCredentialRequest, ImportUserCredentialResponse,
-    InfoRequest, InfoResponse, ListEntryCountriesRequest, ListEntryCountriesResponse,
-    ListEntryGatewaysRequest, ListEntryGatewaysResponse, ListExitCountriesRequest,
-    ListExitCountriesResponse, ListExitGatewaysRequest, ListExitGatewaysResponse, StatusRequest,
-    StatusResponse, StoreAccountRequest, StoreAccountResponse,
+    GetAccountSummaryResponse, GetDnsFilterRequest, GetDnsFilterResponse, GetDnsFilterStatsRequest,
+    GetDnsFilterStatsResponse, GetSettingsRequest, GetSettingsResponse, GetSplitTunnelRequest,
+    GetSplitTunnelResponse, GetTrafficStatsRequest, GetTrafficStatsResponse,
+    ImportUserCredentialRequest, ImportUserCredentialResponse, InfoRequest, InfoResponse,
+    ListEntryCountriesRequest, ListEntryCountriesResponse, ListEntryGatewaysRequest,
+    ListEntryGatewaysResponse, ListExitCountriesRequest, ListExitCountriesResponse,
+    ListExitGatewaysRequest, ListExitGatewaysResponse, ResetSettingsRequest, ResetSettingsResponse,
+    RunLeakTestRequest, RunLeakTestResponse, SetDnsFilterRequest, SetDnsFilterResponse,
+    SetSettingsRequest, SetSettingsResponse, SetSplitTunnelRequest, SetSplitTunnelResponse,
+    StatusRequest, StatusResponse, StoreAccountRequest, StoreAccountResponse, SwitchGatewayRequest,
+    SwitchGatewayResponse, WatchTrafficStatsRequest,
 };
 use prost_types::Timestamp;
 use tokio::sync::{broadcast, mpsc::UnboundedSender};
-use tracing::{error, info};
+use tokio_stream::wrappers::IntervalStream;
+use tracing::{debug, error, info};
 
 use super::{
     connection_handler::CommandInterfaceConnectionHandler,
     error::CommandInterfaceError,
-    helpers::{parse_entry_point, parse_exit_point, threshold_into_u8},
+    helpers::{hop_count_into_u8, parse_entry_point, parse_exit_point, threshold_into_u8},
+    protobuf::{dns::encrypted_dns_from_proto, gateway::cache_info},
     status_broadcaster::ConnectionStatusBroadcaster,
 };
 use crate::service::{
-    ConnectOptions, VpnServiceCommand, VpnServiceConnectResult, VpnServiceStateChange,
+    ConnectOptions, SettingsError, VpnServiceCommand, VpnServiceConnectResult,
+    VpnServiceStateChange, VpnServiceSwitchGatewayResult,
 };
 
+const DEFAULT_TRAFFIC_STATS_INTERVAL: Duration = Duration::from_secs(1);
+const MIN_TRAFFIC_STATS_INTERVAL: Duration = Duration::from_millis(100);
+
 enum ListenerType {
     Path(PathBuf),
     Uri(#[allow(unused)] SocketAddr),
@@ -184,6 +197,48 @@ impl NymVpnd for CommandInterface {
         Ok(tonic::Response::new(response))
     }
 
+    async fn switch_gateway(
+        &self,
+        request: tonic::Request<SwitchGatewayRequest>,
+    ) -> Result<tonic::Response<SwitchGatewayResponse>, tonic::Status> {
+        info!("Got switch gateway request: {:?}", request);
+
+        let switch_request = request.into_inner();
+
+        let entry = switch_request
+            .entry
+            .and_then(|e| e.entry_node_enum)
+            .map(parse_entry_point)
+            .transpose()?;
+
+        let exit = switch_request
+            .exit
+            .and_then(|e| e.exit_node_enum)
+            .map(parse_exit_point)
+            .transpose()?;
+
+        if entry.is_none() && exit.is_none() {
+            return Err(tonic::Status::invalid_argument(
+                "Neither entry nor exit gateway specified",
+            ));
+        }
+
+        let status = CommandInterfaceConnectionHandler::new(self.vpn_command_tx.clone())
+            .handle_switch_gateway(entry, exit)
+            .await;
+        if let VpnServiceSwitchGatewayResult::EntrySwitchNotSupported = status {
+            return Err(tonic::Status::failed_precondition(
+                "Switching the entry gateway is only supported in two-hop mode",
+            ));
+        }
+
+        let response = SwitchGatewayResponse {
+            success: status.is_success(),
+        };
+        info!("Returning switch gateway response: {:?}", response);
+        Ok(tonic::Response::new(response))
+    }
+
     async fn vpn_status(
         &self,
         request: tonic::Request<StatusRequest>,
@@ -268,6 +323,94 @@ impl NymVpnd for CommandInterface {
         ))
     }
 
+    async fn get_traffic_stats(
+        &self,
+        request: tonic::Request<GetTrafficStatsRequest>,
+    ) -> Result<tonic::Response<GetTrafficStatsResponse>, tonic::Status> {
+        debug!("Got get traffic stats request: {:?}", request);
+
+        let stats = CommandInterfaceConnectionHandler::new(self.vpn_command_tx.clone())
+            .handle_get_traffic_stats(None)
+            .await;
+
+        let response = GetTrafficStatsResponse::from(stats);
+        debug!("Returning get traffic stats response: {:?}", response);
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn get_dns_filter_stats(
+        &self,
+        request: tonic::Request<GetDnsFilterStatsRequest>,
+    ) -> Result<tonic::Response<GetDnsFilterStatsResponse>, tonic::Status> {
+        info!("Got get dns filter stats request: {:?}", request);
+
+        let stats = CommandInterfaceConnectionHandler::new(self.vpn_command_tx.clone())
+            .handle_get_dns_filter_stats()
+            .await;
+
+        let response = GetDnsFilterStatsResponse::from(stats);
+        info!("Returning get dns filter stats response: {:?}", response);
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn run_leak_test(
+        &self,
+        request: tonic::Request<RunLeakTestRequest>,
+    ) -> Result<tonic::Response<RunLeakTestResponse>, tonic::Status> {
+        info!("Got run leak test request: {:?}", request);
+
+        let report = CommandInterfaceConnectionHandler::new(self.vpn_command_tx.clone())
+            .handle_run_leak_test()
+            .await;
+
+        let response = RunLeakTestResponse::from(report);
+        info!("Returning run leak test response: {:?}", response);
+        Ok(tonic::Response::new(response))
+    }
+
+    type WatchTrafficStatsStream =
+        BoxStream<'static, Result<GetTrafficStatsResponse, tonic::Status>>;
+
+    async fn watch_traffic_stats(
+        &self,
+        request: tonic::Request<WatchTrafficStatsRequest>,
+    ) -> Result<tonic::Response<Self::WatchTrafficStatsStream>, tonic::Status> {
+        info!("Got watch traffic stats request: {request:?}");
+        let interval = request
+            .into_inner()
+            .interval_ms
+            .map_or(DEFAULT_TRAFFIC_STATS_INTERVAL, |interval_ms| {
+                Duration::from_millis(interval_ms.into())
+            })
+            .max(MIN_TRAFFIC_STATS_INTERVAL);
+
+        let mut interval = tokio::time::interval(interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // Each watcher measures the throughput over its own interval
+        let vpn_command_tx = self.vpn_command_tx.clone();
+        let stream = futures::stream::unfold(
+            (IntervalStream::new(interval), ThroughputSampler::default()),
+            move |(mut ticks, mut throughput)| {
+                let connection_handler =
+                    CommandInterfaceConnectionHandler::new(vpn_command_tx.clone());
+                async move {
+                    ticks.next().await?;
+                    let stats = connection_handler
+                        .handle_get_traffic_stats(Some(&mut throughput))
+                        .await;
+                    debug!("Sending traffic stats: {stats:?}");
+                    Some((
+                        Ok(GetTrafficStatsResponse::from(stats)),
+                        (ticks, throughput),
+                    ))
+                }
+            },
+        );
+        Ok(tonic::Response::new(
+            Box::pin(stream) as Self::WatchTrafficStatsStream
+        ))
+    }
+
     async fn list_entry_gateways(
         &self,
         request: tonic::Request<ListEntryGatewaysRequest>,
@@ -289,7 +432,9 @@ impl NymVpnd for CommandInterface {
             })?;
 
         let response = ListEntryGatewaysResponse {
+            cache: Some(cache_info(&entry_gateways)),
             gateways: entry_gateways
+                .value
                 .into_iter()
                 .map(nym_vpn_proto::EntryGateway::from)
                 .collect(),
@@ -323,7 +468,9 @@ impl NymVpnd for CommandInterface {
             })?;
 
         let response = ListExitGatewaysResponse {
+            cache: Some(cache_info(&exit_gateways)),
             gateways: exit_gateways
+                .value
                 .into_iter()
                 .map(nym_vpn_proto::ExitGateway::from)
                 .collect(),
@@ -530,27 +677,189 @@ impl NymVpnd for CommandInterface {
         info!("Returning get device zk nyms response");
         Ok(tonic::Response::new(response))
     }
+
+    async fn get_settings(
+        &self,
+        _request: tonic::Request<GetSettingsRequest>,
+    ) -> Result<tonic::Response<GetSettingsResponse>, tonic::Status> {
+        info!("Got get settings request");
+
+        let settings = CommandInterfaceConnectionHandler::new(self.vpn_command_tx.clone())
+            .handle_get_settings()
+            .await
+            .map_err(settings_error_into_status)?;
+
+        let response = GetSettingsResponse {
+            settings: Some(settings.into()),
+        };
+
+        info!("Returning get settings response");
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn set_settings(
+        &self,
+        request: tonic::Request<SetSettingsRequest>,
+    ) -> Result<tonic::Response<SetSettingsResponse>, tonic::Status> {
+        info!("Got set settings request: {:?}", request);
+
+        let settings = request
+            .into_inner()
+            .settings
+            .unwrap_or_default()
+            .try_into()
+            .map_err(|err: CommandInterfaceError| {
+                error!("Failed to parse settings: {:?}", err);
+                tonic::Status::invalid_argument(err.to_string())
+            })?;
+
+        CommandInterfaceConnectionHandler::new(self.vpn_command_tx.clone())
+            .handle_set_settings(settings)
+            .await
+            .map_err(settings_error_into_status)?;
+
+        let response = SetSettingsResponse { success: true };
+
+        info!("Returning set settings response");
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn reset_settings(
+        &self,
+        _request: tonic::Request<ResetSettingsRequest>,
+    ) -> Result<tonic::Response<ResetSettingsResponse>, tonic::Status> {
+        info!("Got reset settings request");
+
+        let settings = CommandInterfaceConnectionHandler::new(self.vpn_command_tx.clone())
+            .handle_reset_settings()
+            .await
+            .map_err(settings_error_into_status)?;
+
+        let response = ResetSettingsResponse {
+            settings: Some(settings.into()),
+        };
+
+        info!("Returning reset settings response");
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn get_split_tunnel(
+        &self,
+        _request: tonic::Request<GetSplitTunnelRequest>,
+    ) -> Result<tonic::Response<GetSplitTunnelResponse>, tonic::Status> {
+        info!("Got get split tunnel request");
+
+        let config = CommandInterfaceConnectionHandler::new(self.vpn_command_tx.clone())
+            .handle_get_split_tunnel()
+            .await
+            .map_err(|err| {
+                let msg = format!("Failed to get split tunnel config: {:?}", err);
+                error!(msg);
+                tonic::Status::internal(msg)
+            })?;
+
+        let response = GetSplitTunnelResponse {
+            config: Some(config.into()),
+        };
+
+        info!("Returning get split tunnel response");
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn set_split_tunnel(
+        &self,
+        request: tonic::Request<SetSplitTunnelRequest>,
+    ) -> Result<tonic::Response<SetSplitTunnelResponse>, tonic::Status> {
+        info!("Got set split tunnel request: {:?}", request);
+
+        let config = request
+            .into_inner()
+            .config
+            .unwrap_or_default()
+            .try_into()
+            .map_err(|err: CommandInterfaceError| {
+                error!("Failed to parse split tunnel config: {:?}", err);
+                tonic::Status::invalid_argument(err.to_string())
+            })?;
+
+        CommandInterfaceConnectionHandler::new(self.vpn_command_tx.clone())
+            .handle_set_split_tunnel(config)
+            .await
+            .map_err(|err| {
+                let msg = format!("Failed to set split tunnel config: {:?}", err);
+                error!(msg);
+                tonic::Status::internal(msg)
+            })?;
+
+        let response = SetSplitTunnelResponse { success: true };
+
+        info!("Returning set split tunnel response");
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn get_dns_filter(
+        &self,
+        _request: tonic::Request<GetDnsFilterRequest>,
+    ) -> Result<tonic::Response<GetDnsFilterResponse>, tonic::Status> {
+        info!("Got get dns filter request");
+
+        let config = CommandInterfaceConnectionHandler::new(self.vpn_command_tx.clone())
+            .handle_get_dns_filter()
+            .await
+            .map_err(settings_error_into_status)?;
+
+        let response = GetDnsFilterResponse {
+            config: Some(config.into()),
+        };
+
+        info!("Returning get dns filter response");
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn set_dns_filter(
+        &self,
+        request: tonic::Request<SetDnsFilterRequest>,
+    ) -> Result<tonic::Response<SetDnsFilterResponse>, tonic::Status> {
+        info!("Got set dns filter request: {:?}", request);
+
+        let config = request
+            .into_inner()
+            .config
+            .unwrap_or_default()
+            .try_into()
+            .map_err(|err: CommandInterfaceError| {
+                error!("Failed to parse dns filter config: {:?}", err);
+                tonic::Status::invalid_argument(err.to_string())
+            })?;
+
+        CommandInterfaceConnectionHandler::new(self.vpn_command_tx.clone())
+            .handle_set_dns_filter(config)
+            .await
+            .map_err(settings_error_into_status)?;
+
+        let response = SetDnsFilterResponse { success: true };
+
+        info!("Returning set dns filter response");
+        Ok(tonic::Response::new(response))
+    }
+}
+
+fn settings_error_into_status(err: SettingsError) -> tonic::Status {
+    error!("Settings request failed: {:?}", err);
+    match err {
+        SettingsError::InvalidSettings { .. } => tonic::Status::invalid_argument(err.to_string()),
+        SettingsError::ReadConfig { .. } | SettingsError::WriteConfig { .. } => {
+            tonic::Status::internal(err.to_string())
+        }
+    }
 }
 
 impl TryFrom<ConnectRequest> for ConnectOptions {
     type Error = CommandInterfaceError;
 
     fn try_from(request: ConnectRequest) -> Result<Self, Self::Error> {
-        // Parse the inner DNS IP address if it exists, but make sure to keep the outer Option.
-        let dns = request
-            .dns
-            .map(|dns| {
-                dns.ip
-                    .parse()
-                    .map_err(|err| CommandInterfaceError::FailedToParseDnsIp {
-                        ip: dns.ip.clone(),
-                        source: err,
-                    })
-            })
-            .transpose()?;
-
         Ok(ConnectOptions {
-            dns,
+            dns: request.dns.map(DnsConfig::try_from).transpose()?,
             disable_routing: request.disable_routing,
             enable_two_hop: request.enable_two_hop,
             enable_poisson_rate: request.enable_poisson_rate,
@@ -558,6 +867,15 @@ impl TryFrom<ConnectRequest> for ConnectOptions {
             enable_credentials_mode: request.enable_credentials_mode,
             min_mixnode_performance: request.min_mixnode_performance.map(threshold_into_u8),
             min_gateway_performance: request.min_gateway_performance.map(threshold_into_u8),
+            enable_kill_switch: request.enable_kill_switch,
+            kill_switch_allow_lan: request.kill_switch_allow_lan,
+            wireguard_hop_count: request.wireguard_hop_count.map(hop_count_into_u8),
+            enable_dns_filter: request.enable_dns_filter,
+            traffic_profile: request.traffic_profile,
+            encrypted_dns: request
+                .encrypted_dns
+                .map(encrypted_dns_from_proto)
+                .transpose()?,
         })
     }
 }
@@ -1,13 +1,17 @@
 // Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
 // SPDX-License-Identifier: GPL-3.0-only
 
-use nym_vpn_proto::{ConnectionStateChange, ConnectionStatus, Error as ProtoError};
+use nym_vpn_proto::{
+    ConnectionStateChange, ConnectionStatus, Error as ProtoError, ReconnectingDetails,
+};
 
+use super::offset_datetime_to_timestamp;
 use crate::service::VpnServiceStateChange;
 
 impl From<VpnServiceStateChange> for ConnectionStateChange {
     fn from(status: VpnServiceStateChange) -> Self {
         let mut error = None;
+        let mut reconnecting = None;
         let status = match status {
             VpnServiceStateChange::NotConnected => ConnectionStatus::NotConnected,
             VpnServiceStateChange::Connecting => ConnectionStatus::Connecting,
@@ -17,8 +21,22 @@ impl From<VpnServiceStateChange> for ConnectionStateChange {
                 error = Some(ProtoError::from(reason));
                 ConnectionStatus::ConnectionFailed
             }
+            VpnServiceStateChange::Reconnecting {
+                attempt,
+                next_retry_at,
+            } => {
+                reconnecting = Some(ReconnectingDetails {
+                    attempt,
+                    next_retry_at: Some(offset_datetime_to_timestamp(next_retry_at)),
+                });
+                ConnectionStatus::Reconnecting
+            }
         } as i32;
 
-        ConnectionStateChange { status, error }
+        ConnectionStateChange {
+            status,
+            error,
+            reconnecting,
+        }
     }
 }
@@ -0,0 +1,106 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::net::IpAddr;
+
+use nym_vpn_lib::{DnsConfig, EncryptedDnsConfig, EncryptedDnsProtocol, SplitDnsRule};
+use nym_vpn_proto::encrypted_dns::Protocol;
+
+use crate::command_interface::error::CommandInterfaceError;
+
+impl From<DnsConfig> for nym_vpn_proto::Dns {
+    fn from(config: DnsConfig) -> Self {
+        nym_vpn_proto::Dns {
+            ip: String::new(),
+            servers: ips_to_strings(&config.servers),
+            split_rules: config
+                .split_rules
+                .into_iter()
+                .map(|rule| nym_vpn_proto::SplitDnsRule {
+                    servers: ips_to_strings(&rule.servers),
+                    domain: rule.domain,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<nym_vpn_proto::Dns> for DnsConfig {
+    type Error = CommandInterfaceError;
+
+    fn try_from(dns: nym_vpn_proto::Dns) -> Result<Self, Self::Error> {
+        // Older clients only set the single server
+        let servers = (!dns.ip.is_empty())
+            .then_some(dns.ip)
+            .into_iter()
+            .chain(dns.servers);
+        let split_rules = dns
+            .split_rules
+            .into_iter()
+            .map(|rule| {
+                SplitDnsRule::new(&rule.domain, parse_ips(rule.servers)?).map_err(|reason| {
+                    CommandInterfaceError::InvalidSplitDnsRule {
+                        domain: rule.domain,
+                        reason,
+                    }
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(DnsConfig {
+            servers: parse_ips(servers)?,
+            split_rules,
+        })
+    }
+}
+
+pub(crate) fn encrypted_dns_into_proto(
+    config: Option<EncryptedDnsConfig>,
+) -> Option<nym_vpn_proto::EncryptedDns> {
+    config.map(|config| {
+        let protocol = match config.protocol {
+            EncryptedDnsProtocol::Https => Protocol::Https,
+            EncryptedDnsProtocol::Tls => Protocol::Tls,
+        };
+        nym_vpn_proto::EncryptedDns {
+            protocol: protocol.into(),
+            upstreams: config.upstreams.iter().map(ToString::to_string).collect(),
+        }
+    })
+}
+
+// An unspecified protocol means that encrypted DNS is off
+pub(crate) fn encrypted_dns_from_proto(
+    dns: nym_vpn_proto::EncryptedDns,
+) -> Result<Option<EncryptedDnsConfig>, CommandInterfaceError> {
+    let protocol = match dns.protocol() {
+        Protocol::Unspecified => return Ok(None),
+        Protocol::Https => EncryptedDnsProtocol::Https,
+        Protocol::Tls => EncryptedDnsProtocol::Tls,
+    };
+    let mut config = EncryptedDnsConfig::new(protocol);
+    if !dns.upstreams.is_empty() {
+        config.upstreams = dns
+            .upstreams
+            .iter()
+            .map(|upstream| {
+                upstream
+                    .parse()
+                    .map_err(|reason| CommandInterfaceError::InvalidEncryptedDnsUpstream { reason })
+            })
+            .collect::<Result<_, _>>()?;
+    }
+    Ok(Some(config))
+}
+
+fn ips_to_strings(ips: &[IpAddr]) -> Vec<String> {
+    ips.iter().map(ToString::to_string).collect()
+}
+
+fn parse_ips(ips: impl IntoIterator<Item = String>) -> Result<Vec<IpAddr>, CommandInterfaceError> {
+    ips.into_iter()
+        .map(|ip| {
+            ip.parse()
+                .map_err(|source| CommandInterfaceError::FailedToParseDnsIp { ip, source })
+        })
+        .collect()
+}
@@ -0,0 +1,145 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use nym_vpn_lib::{
+    BlockResponse, Blocklist, BlocklistFormat, BlocklistSource, BlocklistStats, BundledBlocklist,
+    DnsFilterConfig, DnsFilterStats,
+};
+use nym_vpn_proto::{dns_blocklist, dns_blocklist_file, dns_filter_config};
+
+use crate::command_interface::error::CommandInterfaceError;
+
+impl From<DnsFilterConfig> for nym_vpn_proto::DnsFilterConfig {
+    fn from(config: DnsFilterConfig) -> Self {
+        let block_response = match config.block_response {
+            BlockResponse::NxDomain => dns_filter_config::BlockResponse::Nxdomain,
+            BlockResponse::NullAddress => dns_filter_config::BlockResponse::NullAddress,
+        };
+        nym_vpn_proto::DnsFilterConfig {
+            blocklists: config.blocklists.into_iter().map(Into::into).collect(),
+            allowlist: config.allowlist,
+            block_response: block_response as i32,
+        }
+    }
+}
+
+impl TryFrom<nym_vpn_proto::DnsFilterConfig> for DnsFilterConfig {
+    type Error = CommandInterfaceError;
+
+    fn try_from(config: nym_vpn_proto::DnsFilterConfig) -> Result<Self, Self::Error> {
+        let block_response = match dns_filter_config::BlockResponse::try_from(config.block_response)
+        {
+            Ok(dns_filter_config::BlockResponse::Unspecified) => BlockResponse::default(),
+            Ok(dns_filter_config::BlockResponse::Nxdomain) => BlockResponse::NxDomain,
+            Ok(dns_filter_config::BlockResponse::NullAddress) => BlockResponse::NullAddress,
+            Err(_) => {
+                return Err(CommandInterfaceError::InvalidDnsFilter {
+                    reason: format!("unknown block response {}", config.block_response),
+                })
+            }
+        };
+        Ok(DnsFilterConfig {
+            blocklists: config
+                .blocklists
+                .into_iter()
+                .map(Blocklist::try_from)
+                .collect::<Result<_, _>>()?,
+            allowlist: config.allowlist,
+            block_response,
+        })
+    }
+}
+
+impl From<Blocklist> for nym_vpn_proto::DnsBlocklist {
+    fn from(blocklist: Blocklist) -> Self {
+        let source = match blocklist.source {
+            BlocklistSource::Bundled { list } => {
+                let list = match list {
+                    BundledBlocklist::Trackers => dns_blocklist::Bundled::Trackers,
+                };
+                dns_blocklist::Source::Bundled(list as i32)
+            }
+            BlocklistSource::File { path, format } => {
+                let format = match format {
+                    BlocklistFormat::Hosts => dns_blocklist_file::Format::Hosts,
+                    BlocklistFormat::Adblock => dns_blocklist_file::Format::Adblock,
+                };
+                dns_blocklist::Source::File(nym_vpn_proto::DnsBlocklistFile {
+                    path: path.display().to_string(),
+                    format: format as i32,
+                })
+            }
+        };
+        nym_vpn_proto::DnsBlocklist {
+            name: blocklist.name,
+            enabled: blocklist.enabled,
+            source: Some(source),
+        }
+    }
+}
+
+impl TryFrom<nym_vpn_proto::DnsBlocklist> for Blocklist {
+    type Error = CommandInterfaceError;
+
+    fn try_from(blocklist: nym_vpn_proto::DnsBlocklist) -> Result<Self, Self::Error> {
+        let invalid = |reason: String| CommandInterfaceError::InvalidDnsFilter {
+            reason: format!("blocklist {}: {reason}", blocklist.name),
+        };
+        let source = match blocklist.source {
+            Some(dns_blocklist::Source::Bundled(list)) => {
+                match dns_blocklist::Bundled::try_from(list) {
+                    Ok(dns_blocklist::Bundled::Trackers) => BlocklistSource::Bundled {
+                        list: BundledBlocklist::Trackers,
+                    },
+                    Err(_) => return Err(invalid(format!("unknown bundled list {list}"))),
+                }
+            }
+            Some(dns_blocklist::Source::File(file)) => {
+                let format = match dns_blocklist_file::Format::try_from(file.format) {
+                    Ok(dns_blocklist_file::Format::Hosts) => BlocklistFormat::Hosts,
+                    Ok(dns_blocklist_file::Format::Adblock) => BlocklistFormat::Adblock,
+                    Err(_) => return Err(invalid(format!("unknown format {}", file.format))),
+                };
+                BlocklistSource::File {
+                    path: file.path.into(),
+                    format,
+                }
+            }
+            None => return Err(invalid("missing source".to_string())),
+        };
+        Ok(Blocklist {
+            name: blocklist.name,
+            enabled: blocklist.enabled,
+            source,
+        })
+    }
+}
+
+impl From<DnsFilterStats> for nym_vpn_proto::DnsFilterStats {
+    fn from(stats: DnsFilterStats) -> Self {
+        nym_vpn_proto::DnsFilterStats {
+            queries: stats.queries,
+            blocked: stats.blocked,
+            allowed: stats.allowed,
+            blocklists: stats.blocklists.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<BlocklistStats> for nym_vpn_proto::DnsBlocklistStats {
+    fn from(stats: BlocklistStats) -> Self {
+        nym_vpn_proto::DnsBlocklistStats {
+            name: stats.name,
+            domains: stats.domains as u64,
+            hits: stats.hits,
+        }
+    }
+}
+
+impl From<Option<DnsFilterStats>> for nym_vpn_proto::GetDnsFilterStatsResponse {
+    fn from(stats: Option<DnsFilterStats>) -> Self {
+        nym_vpn_proto::GetDnsFilterStatsResponse {
+            stats: stats.map(Into::into),
+        }
+    }
+}
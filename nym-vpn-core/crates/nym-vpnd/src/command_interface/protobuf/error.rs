@@ -233,6 +233,21 @@ impl From<ConnectionFailedError> for ProtoError {
                     "requested_location".to_string() => requested_location.clone(),
                 },
             },
+            ConnectionFailedError::SameEntryAndExitGateway { ref gateway_id } => ProtoError {
+                kind: ErrorType::GatewayDirectorySameEntryAndExitGw as i32,
+                message: err.to_string(),
+                details: hashmap! {
+                    "gateway_id".to_string() => gateway_id.clone(),
+                },
+            },
+            ConnectionFailedError::NotEnoughMiddleGateways { requested, found } => ProtoError {
+                kind: ErrorType::GatewayDirectory as i32,
+                message: err.to_string(),
+                details: hashmap! {
+                    "requested".to_string() => requested.to_string(),
+                    "found".to_string() => found.to_string(),
+                },
+            },
             ConnectionFailedError::OutOfBandwidth => ProtoError {
                 kind: ErrorType::OutOfBandwidth as i32,
                 message: err.to_string(),
@@ -1,10 +1,20 @@
 // Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
 // SPDX-License-Identifier: GPL-3.0-only
 
+use nym_vpn_lib::gateway_directory::Cached;
 use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 
+use super::offset_datetime_to_timestamp;
 use crate::types::gateway;
 
+pub(crate) fn cache_info<T>(cached: &Cached<T>) -> nym_vpn_proto::GatewayCacheInfo {
+    nym_vpn_proto::GatewayCacheInfo {
+        fetched_at: Some(prost_types::Timestamp::from(cached.fetched_at)),
+        age_seconds: cached.age().as_secs(),
+        stale: cached.stale,
+    }
+}
+
 impl From<gateway::Location> for nym_vpn_proto::Location {
     fn from(location: gateway::Location) -> Self {
         nym_vpn_proto::Location {
@@ -45,10 +55,7 @@ impl From<gateway::ProbeOutcome> for nym_vpn_proto::ProbeOutcome {
 impl From<gateway::Probe> for nym_vpn_proto::Probe {
     fn from(probe: gateway::Probe) -> Self {
         let last_updated = OffsetDateTime::parse(&probe.last_updated_utc, &Rfc3339).ok();
-        let last_updated_utc = last_updated.map(|timestamp| prost_types::Timestamp {
-            seconds: timestamp.unix_timestamp(),
-            nanos: timestamp.nanosecond() as i32,
-        });
+        let last_updated_utc = last_updated.map(offset_datetime_to_timestamp);
         let outcome = Some(nym_vpn_proto::ProbeOutcome::from(probe.outcome));
         nym_vpn_proto::Probe {
             last_updated_utc,
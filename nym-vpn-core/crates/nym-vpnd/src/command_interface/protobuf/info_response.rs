@@ -3,6 +3,7 @@
 
 use nym_vpn_proto::InfoResponse;
 
+use super::offset_datetime_to_timestamp;
 use crate::service::VpnServiceInfoResult;
 
 impl From<VpnServiceInfoResult> for InfoResponse {
@@ -29,13 +30,6 @@ impl From<VpnServiceInfoResult> for InfoResponse {
     }
 }
 
-fn offset_datetime_to_timestamp(datetime: time::OffsetDateTime) -> prost_types::Timestamp {
-    prost_types::Timestamp {
-        seconds: datetime.unix_timestamp(),
-        nanos: datetime.nanosecond() as i32,
-    }
-}
-
 fn validator_details_to_endpoints(
     validator_details: nym_vpn_lib::nym_config::defaults::ValidatorDetails,
 ) -> nym_vpn_proto::Endpoints {
@@ -0,0 +1,103 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use nym_vpn_lib::{
+    DnsLeakReport, Ipv6LeakReport, Ipv6Status, LeakTestOutcome, LeakTestReport, ResolverReport,
+    RouteLeakReport, RouteReport,
+};
+use nym_vpn_proto::{leak_test_ipv6, leak_test_report};
+
+fn outcome_to_proto(outcome: LeakTestOutcome) -> i32 {
+    match outcome {
+        LeakTestOutcome::Pass => leak_test_report::Outcome::Pass,
+        LeakTestOutcome::Inconclusive => leak_test_report::Outcome::Inconclusive,
+        LeakTestOutcome::Fail => leak_test_report::Outcome::Fail,
+    }
+    .into()
+}
+
+impl From<LeakTestReport> for nym_vpn_proto::LeakTestReport {
+    fn from(report: LeakTestReport) -> Self {
+        nym_vpn_proto::LeakTestReport {
+            outcome: outcome_to_proto(report.outcome()),
+            tunnel_interfaces: report.tunnel_interfaces,
+            dns: Some(report.dns.into()),
+            ipv6: Some(report.ipv6.into()),
+            routes: Some(report.routes.into()),
+        }
+    }
+}
+
+impl From<DnsLeakReport> for nym_vpn_proto::LeakTestDns {
+    fn from(dns: DnsLeakReport) -> Self {
+        nym_vpn_proto::LeakTestDns {
+            outcome: outcome_to_proto(dns.outcome),
+            detail: dns.detail,
+            expected_servers: dns
+                .expected_servers
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            resolvers: dns.resolvers.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<ResolverReport> for nym_vpn_proto::LeakTestResolver {
+    fn from(resolver: ResolverReport) -> Self {
+        nym_vpn_proto::LeakTestResolver {
+            address: resolver.address.to_string(),
+            configured_by_vpn: resolver.configured_by_vpn,
+            interface: resolver.interface,
+            through_tunnel: resolver.through_tunnel,
+            answered: resolver.answered,
+            link: resolver.link,
+        }
+    }
+}
+
+impl From<Ipv6LeakReport> for nym_vpn_proto::LeakTestIpv6 {
+    fn from(ipv6: Ipv6LeakReport) -> Self {
+        let status = match ipv6.status {
+            Ipv6Status::Tunneled => leak_test_ipv6::Status::Tunneled,
+            Ipv6Status::Blocked => leak_test_ipv6::Status::Blocked,
+            Ipv6Status::Leaking => leak_test_ipv6::Status::Leaking,
+            Ipv6Status::Unknown => leak_test_ipv6::Status::Unknown,
+        };
+        nym_vpn_proto::LeakTestIpv6 {
+            outcome: outcome_to_proto(ipv6.outcome),
+            detail: ipv6.detail,
+            status: status.into(),
+            interface: ipv6.interface,
+        }
+    }
+}
+
+impl From<RouteLeakReport> for nym_vpn_proto::LeakTestRoutes {
+    fn from(routes: RouteLeakReport) -> Self {
+        nym_vpn_proto::LeakTestRoutes {
+            outcome: outcome_to_proto(routes.outcome),
+            detail: routes.detail,
+            routes: routes.routes.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<RouteReport> for nym_vpn_proto::LeakTestRoute {
+    fn from(route: RouteReport) -> Self {
+        nym_vpn_proto::LeakTestRoute {
+            prefix: route.prefix.to_string(),
+            interface: route.interface,
+            gateway: route.gateway.map(|gateway| gateway.to_string()),
+            through_tunnel: route.through_tunnel,
+        }
+    }
+}
+
+impl From<Option<LeakTestReport>> for nym_vpn_proto::RunLeakTestResponse {
+    fn from(report: Option<LeakTestReport>) -> Self {
+        nym_vpn_proto::RunLeakTestResponse {
+            report: report.map(Into::into),
+        }
+    }
+}
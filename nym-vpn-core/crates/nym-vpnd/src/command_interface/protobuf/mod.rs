@@ -4,8 +4,23 @@
 // This module primarily handles conversions to protobuf types
 
 pub mod connection_state;
+pub mod dns;
+pub mod dns_filter;
 pub mod error;
 pub mod gateway;
 pub mod info_response;
+pub mod leak_test;
+pub mod settings;
+pub mod split_tunnel;
 pub mod state_response;
 pub mod status_update;
+pub mod traffic_stats;
+
+pub(crate) fn offset_datetime_to_timestamp(
+    datetime: time::OffsetDateTime,
+) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: datetime.unix_timestamp(),
+        nanos: datetime.nanosecond() as i32,
+    }
+}
@@ -0,0 +1,71 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use nym_vpn_lib::DnsConfig;
+
+use crate::{
+    command_interface::{
+        error::CommandInterfaceError,
+        helpers::{hop_count_into_u8, threshold_into_u8},
+        protobuf::dns::{encrypted_dns_from_proto, encrypted_dns_into_proto},
+    },
+    service::VpnSettings,
+};
+
+impl From<VpnSettings> for nym_vpn_proto::VpnSettings {
+    fn from(settings: VpnSettings) -> Self {
+        nym_vpn_proto::VpnSettings {
+            dns: Some(settings.dns.into()),
+            disable_routing: settings.disable_routing,
+            enable_two_hop: settings.enable_two_hop,
+            enable_poisson_rate: settings.enable_poisson_rate,
+            disable_background_cover_traffic: settings.disable_background_cover_traffic,
+            enable_credentials_mode: settings.enable_credentials_mode,
+            min_mixnode_performance: settings.min_mixnode_performance.map(u8_into_threshold),
+            min_gateway_performance: settings.min_gateway_performance.map(u8_into_threshold),
+            enable_kill_switch: settings.enable_kill_switch,
+            kill_switch_allow_lan: settings.kill_switch_allow_lan,
+            wireguard_hop_count: settings.wireguard_hop_count.map(u32::from),
+            enable_dns_filter: settings.enable_dns_filter,
+            traffic_profile: settings.traffic_profile,
+            encrypted_dns: encrypted_dns_into_proto(settings.encrypted_dns),
+        }
+    }
+}
+
+impl TryFrom<nym_vpn_proto::VpnSettings> for VpnSettings {
+    type Error = CommandInterfaceError;
+
+    fn try_from(settings: nym_vpn_proto::VpnSettings) -> Result<Self, Self::Error> {
+        Ok(VpnSettings {
+            dns: settings
+                .dns
+                .map(DnsConfig::try_from)
+                .transpose()?
+                .unwrap_or_default(),
+            disable_routing: settings.disable_routing,
+            enable_two_hop: settings.enable_two_hop,
+            enable_poisson_rate: settings.enable_poisson_rate,
+            disable_background_cover_traffic: settings.disable_background_cover_traffic,
+            enable_credentials_mode: settings.enable_credentials_mode,
+            min_mixnode_performance: settings.min_mixnode_performance.map(threshold_into_u8),
+            min_gateway_performance: settings.min_gateway_performance.map(threshold_into_u8),
+            enable_kill_switch: settings.enable_kill_switch,
+            kill_switch_allow_lan: settings.kill_switch_allow_lan,
+            wireguard_hop_count: settings.wireguard_hop_count.map(hop_count_into_u8),
+            enable_dns_filter: settings.enable_dns_filter,
+            traffic_profile: settings.traffic_profile,
+            encrypted_dns: settings
+                .encrypted_dns
+                .map(encrypted_dns_from_proto)
+                .transpose()?
+                .flatten(),
+        })
+    }
+}
+
+fn u8_into_threshold(min_performance: u8) -> nym_vpn_proto::Threshold {
+    nym_vpn_proto::Threshold {
+        min_performance: min_performance.into(),
+    }
+}
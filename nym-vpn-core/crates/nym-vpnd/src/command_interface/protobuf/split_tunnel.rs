@@ -0,0 +1,44 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use ipnetwork::IpNetwork;
+use nym_vpn_lib::SplitTunnelConfig;
+
+use crate::command_interface::error::CommandInterfaceError;
+
+impl From<SplitTunnelConfig> for nym_vpn_proto::SplitTunnelConfig {
+    fn from(config: SplitTunnelConfig) -> Self {
+        nym_vpn_proto::SplitTunnelConfig {
+            include_networks: networks_to_strings(config.include_networks),
+            exclude_networks: networks_to_strings(config.exclude_networks),
+            excluded_cgroups: config.excluded_cgroups,
+        }
+    }
+}
+
+impl TryFrom<nym_vpn_proto::SplitTunnelConfig> for SplitTunnelConfig {
+    type Error = CommandInterfaceError;
+
+    fn try_from(config: nym_vpn_proto::SplitTunnelConfig) -> Result<Self, Self::Error> {
+        Ok(SplitTunnelConfig {
+            include_networks: parse_networks(config.include_networks)?,
+            exclude_networks: parse_networks(config.exclude_networks)?,
+            excluded_cgroups: config.excluded_cgroups,
+        })
+    }
+}
+
+fn networks_to_strings(networks: Vec<IpNetwork>) -> Vec<String> {
+    networks.iter().map(ToString::to_string).collect()
+}
+
+fn parse_networks(networks: Vec<String>) -> Result<Vec<IpNetwork>, CommandInterfaceError> {
+    networks
+        .into_iter()
+        .map(|network| {
+            network
+                .parse()
+                .map_err(|source| CommandInterfaceError::FailedToParseNetwork { network, source })
+        })
+        .collect()
+}
@@ -3,9 +3,10 @@
 
 use nym_vpn_proto::{
     connected_state_details, ConnectionStatus, Error as ProtoError, MixConnectedStateDetails,
-    StatusResponse, WgConnectedStateDetails,
+    ReconnectingDetails, StatusResponse, WgConnectedStateDetails,
 };
 
+use super::offset_datetime_to_timestamp;
 use crate::service::{ConnectedStateDetails, VpnServiceStatusResult};
 
 impl From<ConnectedStateDetails> for connected_state_details::ConnectedStateDetails {
@@ -37,14 +38,12 @@ impl From<VpnServiceStatusResult> for StatusResponse {
     fn from(status: VpnServiceStatusResult) -> Self {
         let mut details = None;
         let mut error = None;
+        let mut reconnecting = None;
         let status = match status {
             VpnServiceStatusResult::NotConnected => ConnectionStatus::NotConnected,
             VpnServiceStatusResult::Connecting => ConnectionStatus::Connecting,
             VpnServiceStatusResult::Connected(conn_details) => {
-                let timestamp = prost_types::Timestamp {
-                    seconds: conn_details.since.unix_timestamp(),
-                    nanos: conn_details.since.nanosecond() as i32,
-                };
+                let timestamp = offset_datetime_to_timestamp(conn_details.since);
                 details = Some(nym_vpn_proto::ConnectionDetails {
                     entry_gateway: Some(nym_vpn_proto::Gateway {
                         id: conn_details.entry_gateway.to_string(),
@@ -60,6 +59,7 @@ impl From<VpnServiceStatusResult> for StatusResponse {
                         ),
                     }),
                     since: Some(timestamp),
+                    mtu: conn_details.mtu.into(),
                 });
                 ConnectionStatus::Connected
             }
@@ -68,12 +68,23 @@ impl From<VpnServiceStatusResult> for StatusResponse {
                 error = Some(ProtoError::from(reason));
                 ConnectionStatus::ConnectionFailed
             }
+            VpnServiceStatusResult::Reconnecting {
+                attempt,
+                next_retry_at,
+            } => {
+                reconnecting = Some(ReconnectingDetails {
+                    attempt,
+                    next_retry_at: Some(offset_datetime_to_timestamp(next_retry_at)),
+                });
+                ConnectionStatus::Reconnecting
+            }
         } as i32;
 
         StatusResponse {
             status,
             details,
             error,
+            reconnecting,
         }
     }
 }
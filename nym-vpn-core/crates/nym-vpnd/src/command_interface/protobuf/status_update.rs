@@ -2,7 +2,12 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use nym_bandwidth_controller_pre_ecash::BandwidthStatusMessage;
-use nym_vpn_lib::{connection_monitor::ConnectionMonitorStatus, NymVpnStatusMessage};
+use std::collections::HashMap;
+
+use nym_vpn_lib::{
+    connection_monitor::{ConnectionMonitorStatus, ConnectionQuality, PingStats},
+    NymVpnStatusMessage,
+};
 use nym_vpn_proto::{connection_status_update::StatusType, ConnectionStatusUpdate};
 
 pub(crate) fn status_update_from_status_message(
@@ -26,6 +31,7 @@ pub(crate) fn status_update_from_status_message(
         },
         NymVpnStatusMessage::WgConnectionInfo {
             entry_connection_info,
+            middle_connection_info,
             exit_connection_info,
         } => ConnectionStatusUpdate {
             kind: StatusType::TunnelEndToEndConnectionEstablished as i32,
@@ -37,6 +43,22 @@ pub(crate) fn status_update_from_status_message(
                 "exit_public_key".to_string() => exit_connection_info.public_key.clone(),
                 "entry_private_ipv4".to_string() => entry_connection_info.private_ipv4.to_string(),
                 "exit_private_ipv4".to_string() => exit_connection_info.private_ipv4.to_string(),
+                "middle_gateways".to_string() => middle_connection_info
+                    .iter()
+                    .map(|info| info.gateway_id.to_base58_string())
+                    .collect::<Vec<_>>()
+                    .join(","),
+            },
+        },
+        NymVpnStatusMessage::ExitFailover {
+            failed_exit_gateway,
+            exit_gateway,
+        } => ConnectionStatusUpdate {
+            kind: StatusType::ExitGatewayFailover as i32,
+            message: status.to_string(),
+            details: maplit::hashmap! {
+                "failed_exit_gateway".to_string() => failed_exit_gateway.to_base58_string(),
+                "exit_gateway".to_string() => exit_gateway.to_base58_string(),
             },
         },
     }
@@ -81,6 +103,38 @@ pub(crate) fn status_update_from_monitor_status(
             message: status.to_string(),
             details: Default::default(),
         },
+        ConnectionMonitorStatus::ConnectionQuality(quality) => ConnectionStatusUpdate {
+            kind: StatusType::ConnectionQuality as i32,
+            message: status.to_string(),
+            details: connection_quality_details(quality),
+        },
+    }
+}
+
+// Keyed by the path that was measured, as in `mixnet_rtt_p50_ms` and `exit_packet_loss`
+fn connection_quality_details(quality: &ConnectionQuality) -> HashMap<String, String> {
+    let mut details = HashMap::new();
+    for (path, stats) in [("mixnet", &quality.mixnet), ("exit", &quality.exit)] {
+        let Some(stats) = stats else {
+            continue;
+        };
+        details.extend(
+            ping_stats_details(stats)
+                .into_iter()
+                .map(|(key, value)| (format!("{path}_{key}"), value)),
+        );
+    }
+    details
+}
+
+fn ping_stats_details(stats: &PingStats) -> HashMap<&'static str, String> {
+    maplit::hashmap! {
+        "rtt_p50_ms" => stats.rtt_p50.as_millis().to_string(),
+        "rtt_p90_ms" => stats.rtt_p90.as_millis().to_string(),
+        "rtt_p99_ms" => stats.rtt_p99.as_millis().to_string(),
+        "jitter_ms" => stats.jitter.as_millis().to_string(),
+        "packet_loss" => format!("{:.3}", stats.packet_loss),
+        "samples" => stats.samples.to_string(),
     }
 }
 
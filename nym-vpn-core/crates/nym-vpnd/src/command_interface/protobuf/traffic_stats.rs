@@ -0,0 +1,37 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use nym_vpn_lib::{TrafficStats, WgHopStats};
+
+impl From<TrafficStats> for nym_vpn_proto::TrafficStats {
+    fn from(stats: TrafficStats) -> Self {
+        nym_vpn_proto::TrafficStats {
+            bytes_in: stats.total.bytes_in,
+            bytes_out: stats.total.bytes_out,
+            packets_in: stats.total.packets_in,
+            packets_out: stats.total.packets_out,
+            throughput_in: stats.throughput_in,
+            throughput_out: stats.throughput_out,
+            wireguard_hops: stats.wireguard_hops.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<WgHopStats> for nym_vpn_proto::WireguardHopStats {
+    fn from(hop: WgHopStats) -> Self {
+        nym_vpn_proto::WireguardHopStats {
+            interface: hop.interface,
+            rx_bytes: hop.rx_bytes,
+            tx_bytes: hop.tx_bytes,
+            last_handshake: hop.last_handshake.map(prost_types::Timestamp::from),
+        }
+    }
+}
+
+impl From<Option<TrafficStats>> for nym_vpn_proto::GetTrafficStatsResponse {
+    fn from(stats: Option<TrafficStats>) -> Self {
+        nym_vpn_proto::GetTrafficStatsResponse {
+            stats: stats.map(Into::into),
+        }
+    }
+}
@@ -3,10 +3,18 @@
 
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt as _;
-use std::{fmt, fs, path::PathBuf};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
 
-use nym_vpn_lib::gateway_directory;
-use tracing::info;
+use nym_vpn_lib::{
+    gateway_directory, nym_config::defaults::NymNetworkDetails, BlocklistSource, DnsConfig,
+    DnsFilterConfig, EncryptedDnsConfig, SplitTunnelConfig, TrafficProfile,
+};
+use tracing::{info, warn};
+
+use super::reconnect::ReconnectPolicy;
 
 #[cfg(not(windows))]
 const DEFAULT_DATA_DIR: &str = "/var/lib/nym-vpnd";
@@ -16,6 +24,16 @@ const DEFAULT_LOG_DIR: &str = "/var/log/nym-vpnd";
 const DEFAULT_CONFIG_DIR: &str = "/etc/nym";
 pub(super) const DEFAULT_CONFIG_FILE: &str = "nym-vpnd.toml";
 pub(crate) const DEFAULT_LOG_FILE: &str = "nym-vpnd.log";
+// Next to the config file. The daemon reads the blocklists as root, so it only reads the ones an
+// admin put in there.
+pub(super) const DNS_BLOCKLIST_DIR: &str = "dns_blocklists";
+
+// Bump this when making changes to the config file that need a migration, and add the migration
+// step to `migrate_config`.
+// - 1: entry and exit point only, unversioned
+// - 2: connection settings
+// - 3: multiple DNS servers and split DNS rules
+const CONFIG_VERSION: u32 = 3;
 
 #[cfg(windows)]
 pub(crate) fn program_data_path() -> PathBuf {
@@ -30,6 +48,18 @@ pub(super) fn default_data_dir() -> PathBuf {
     return DEFAULT_DATA_DIR.into();
 }
 
+pub(crate) fn data_dir() -> PathBuf {
+    std::env::var("NYM_VPND_DATA_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| default_data_dir())
+}
+
+// Where we keep the gateway and country lists fetched from the directory
+pub(crate) fn gateway_cache_config(data_dir: &Path) -> gateway_directory::CacheConfig {
+    gateway_directory::CacheConfig::new(data_dir.join("gateway_cache"))
+        .with_network_name(NymNetworkDetails::new_from_env().network_name)
+}
+
 pub(crate) fn default_log_dir() -> PathBuf {
     #[cfg(windows)]
     return program_data_path().join("nym-vpnd").join("log");
@@ -54,6 +84,12 @@ pub(super) enum ConfigSetupError {
         error: Box<toml::de::Error>,
     },
 
+    #[error("config file {file} has version {version}, newer than the supported {CONFIG_VERSION}")]
+    UnsupportedVersion { file: PathBuf, version: u32 },
+
+    #[error("invalid settings: {reason}")]
+    InvalidSettings { reason: String },
+
     #[error("failed to read config file {file}: {error}")]
     ReadConfig {
         file: PathBuf,
@@ -82,18 +118,123 @@ pub(super) enum ConfigSetupError {
     },
 }
 
+// The settings used when connecting, unless overridden in the connect request
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub(crate) struct VpnSettings {
+    pub(crate) dns: DnsConfig,
+    pub(crate) disable_routing: bool,
+    pub(crate) enable_two_hop: bool,
+    pub(crate) enable_poisson_rate: bool,
+    pub(crate) disable_background_cover_traffic: bool,
+    pub(crate) enable_credentials_mode: bool,
+    pub(crate) min_mixnode_performance: Option<u8>,
+    pub(crate) min_gateway_performance: Option<u8>,
+    pub(crate) enable_kill_switch: bool,
+    pub(crate) kill_switch_allow_lan: bool,
+    // Only used in two-hop mode, the library default is used when not set
+    pub(crate) wireguard_hop_count: Option<u8>,
+    // Block the domains on the blocklists of the DNS filter config
+    pub(crate) enable_dns_filter: bool,
+    // The name of a built-in or custom traffic profile, the balanced one when not set
+    pub(crate) traffic_profile: Option<String>,
+    // Resolve through the local stub over DNS over HTTPS or TLS instead of in plain text
+    pub(crate) encrypted_dns: Option<EncryptedDnsConfig>,
+}
+
+impl VpnSettings {
+    pub(crate) fn validate(&self) -> Result<(), ConfigSetupError> {
+        if self.enable_kill_switch && self.enable_two_hop {
+            return Err(ConfigSetupError::InvalidSettings {
+                reason: "the kill switch is not supported in two-hop mode".to_string(),
+            });
+        }
+        if !self.dns.split_rules.is_empty() && self.enable_two_hop {
+            return Err(ConfigSetupError::InvalidSettings {
+                reason: "split dns is not supported in two-hop mode".to_string(),
+            });
+        }
+        if self.encrypted_dns.is_some() && self.enable_two_hop {
+            return Err(ConfigSetupError::InvalidSettings {
+                reason: "encrypted dns is not supported in two-hop mode".to_string(),
+            });
+        }
+        let out_of_range = |threshold: Option<u8>| threshold.is_some_and(|t| t > 100);
+        if out_of_range(self.min_mixnode_performance) || out_of_range(self.min_gateway_performance)
+        {
+            return Err(ConfigSetupError::InvalidSettings {
+                reason: "performance thresholds must be between 0 and 100".to_string(),
+            });
+        }
+        if self.wireguard_hop_count.is_some_and(|hop_count| {
+            !(2..=nym_vpn_lib::MAX_WG_HOP_COUNT).contains(&usize::from(hop_count))
+        }) {
+            return Err(ConfigSetupError::InvalidSettings {
+                reason: format!(
+                    "the wireguard hop count must be between 2 and {}",
+                    nym_vpn_lib::MAX_WG_HOP_COUNT
+                ),
+            });
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub(super) struct NymVpnServiceConfig {
+    pub(super) version: u32,
     pub(super) entry_point: gateway_directory::EntryPoint,
     pub(super) exit_point: gateway_directory::ExitPoint,
+    #[serde(default)]
+    pub(super) settings: VpnSettings,
+    #[serde(default)]
+    pub(super) reconnect: ReconnectPolicy,
+    #[serde(default)]
+    pub(super) split_tunnel: SplitTunnelConfig,
+    #[serde(default)]
+    pub(super) dns_filter: DnsFilterConfig,
+    // Defined in addition to the built-in profiles, which they take precedence over
+    #[serde(default)]
+    pub(super) traffic_profiles: Vec<TrafficProfile>,
+}
+
+impl NymVpnServiceConfig {
+    pub(super) fn traffic_profile(
+        &self,
+        name: Option<&str>,
+    ) -> Result<TrafficProfile, ConfigSetupError> {
+        let Some(name) = name else {
+            return Ok(TrafficProfile::default());
+        };
+        let profile = self
+            .traffic_profiles
+            .iter()
+            .find(|profile| profile.name == name)
+            .cloned()
+            .or_else(|| TrafficProfile::builtin(name))
+            .ok_or_else(|| ConfigSetupError::InvalidSettings {
+                reason: format!("unknown traffic profile: {name}"),
+            })?;
+        profile
+            .validate()
+            .map_err(|reason| ConfigSetupError::InvalidSettings { reason })?;
+        Ok(profile)
+    }
 }
 
 impl fmt::Display for NymVpnServiceConfig {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "entry point: {}, exit point: {}",
-            self.entry_point, self.exit_point
+            "version: {}, entry point: {}, exit point: {}, settings: {:?}, reconnect: {}, split tunnel: {}, dns filter: {:?}, traffic profiles: {:?}",
+            self.version,
+            self.entry_point,
+            self.exit_point,
+            self.settings,
+            self.reconnect,
+            self.split_tunnel,
+            self.dns_filter,
+            self.traffic_profiles
         )
     }
 }
@@ -101,8 +242,14 @@ impl fmt::Display for NymVpnServiceConfig {
 impl Default for NymVpnServiceConfig {
     fn default() -> Self {
         Self {
+            version: CONFIG_VERSION,
             entry_point: gateway_directory::EntryPoint::Random,
             exit_point: gateway_directory::ExitPoint::Random,
+            settings: VpnSettings::default(),
+            reconnect: ReconnectPolicy::default(),
+            split_tunnel: SplitTunnelConfig::default(),
+            dns_filter: DnsFilterConfig::default(),
+            traffic_profiles: Vec::new(),
         }
     }
 }
@@ -140,10 +287,80 @@ pub(super) fn read_config_file(
             file: config_file.clone(),
             error,
         })?;
-    toml::from_str(&file_content).map_err(|error| ConfigSetupError::Parse {
+    let parse_error = |error| ConfigSetupError::Parse {
         file: config_file.clone(),
         error: Box::new(error),
-    })
+    };
+    let table: toml::Table = toml::from_str(&file_content).map_err(parse_error)?;
+    let version = config_version(&table);
+    if version > CONFIG_VERSION {
+        return Err(ConfigSetupError::UnsupportedVersion {
+            file: config_file.clone(),
+            version,
+        });
+    }
+
+    let config: NymVpnServiceConfig = toml::Value::Table(migrate_config(table))
+        .try_into()
+        .map_err(parse_error)?;
+
+    // Write back the migrated config, so that we only have to do this once
+    if version < CONFIG_VERSION {
+        info!("Migrated config file from version {version} to {CONFIG_VERSION}");
+        if let Err(err) = write_config_file(config_file, &config) {
+            warn!("Failed to write migrated config file: {err}");
+        }
+    }
+    Ok(config)
+}
+
+// Versions below the first one are treated as unversioned, since the file can be edited by hand
+fn config_version(table: &toml::Table) -> u32 {
+    table
+        .get("version")
+        .and_then(toml::Value::as_integer)
+        .and_then(|version| u32::try_from(version).ok())
+        .unwrap_or(1)
+        .max(1)
+}
+
+// Upgrade the config one version at a time, up to the current version
+fn migrate_config(mut table: toml::Table) -> toml::Table {
+    for version in config_version(&table)..CONFIG_VERSION {
+        match version {
+            1 => migrate_v1_to_v2(&mut table),
+            2 => migrate_v2_to_v3(&mut table),
+            _ => unreachable!("missing config migration from version {version}"),
+        }
+        table.insert("version".to_string(), toml::Value::from(version + 1));
+    }
+    table
+}
+
+// Version 2 adds the connection settings, which previously had to be passed with every connect
+// request. Start out with the defaults, which is what clients got when not passing any options.
+fn migrate_v1_to_v2(table: &mut toml::Table) {
+    table
+        .entry("settings")
+        .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+}
+
+// Version 3 turns the single DNS server into a list of servers, along with the split DNS rules
+fn migrate_v2_to_v3(table: &mut toml::Table) {
+    let Some(settings) = table
+        .get_mut("settings")
+        .and_then(toml::Value::as_table_mut)
+    else {
+        return;
+    };
+    if let Some(toml::Value::String(server)) = settings.remove("dns") {
+        let mut dns = toml::Table::new();
+        dns.insert(
+            "servers".to_string(),
+            toml::Value::Array(vec![toml::Value::String(server)]),
+        );
+        settings.insert("dns".to_string(), toml::Value::Table(dns));
+    }
 }
 
 pub(super) fn write_config_file(
@@ -159,6 +376,38 @@ pub(super) fn write_config_file(
     Ok(())
 }
 
+// The files of the enabled blocklists, resolved within the blocklist directory. Relative paths are
+// taken to be in it.
+pub(super) fn resolve_dns_blocklist_paths(
+    mut dns_filter: DnsFilterConfig,
+    blocklist_dir: &Path,
+) -> Result<DnsFilterConfig, String> {
+    let not_in_dir = |path: &Path| {
+        format!(
+            "dns blocklist {} is not a file in {}",
+            path.display(),
+            blocklist_dir.display()
+        )
+    };
+    let canonical_dir = fs::canonicalize(blocklist_dir).map_err(|_| not_in_dir(blocklist_dir))?;
+    for blocklist in dns_filter
+        .blocklists
+        .iter_mut()
+        .filter(|blocklist| blocklist.enabled)
+    {
+        let BlocklistSource::File { path, .. } = &mut blocklist.source else {
+            continue;
+        };
+        let resolved =
+            fs::canonicalize(canonical_dir.join(&*path)).map_err(|_| not_in_dir(path))?;
+        if !resolved.starts_with(&canonical_dir) || !resolved.is_file() {
+            return Err(not_in_dir(path));
+        }
+        *path = resolved;
+    }
+    Ok(dns_filter)
+}
+
 pub(super) fn create_data_dir(data_dir: &PathBuf) -> Result<(), ConfigSetupError> {
     fs::create_dir_all(data_dir).map_err(|error| ConfigSetupError::CreateDirectory {
         dir: data_dir.clone(),
@@ -182,3 +431,161 @@ pub(super) fn create_data_dir(data_dir: &PathBuf) -> Result<(), ConfigSetupError
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrate_unversioned_config() {
+        let table: toml::Table = toml::from_str(
+            r#"
+            entry_point = "Random"
+            exit_point = "Random"
+            "#,
+        )
+        .unwrap();
+
+        let table = migrate_config(table);
+        assert_eq!(config_version(&table), CONFIG_VERSION);
+
+        let config: NymVpnServiceConfig = toml::Value::Table(table).try_into().unwrap();
+        assert_eq!(config.settings, VpnSettings::default());
+    }
+
+    #[test]
+    fn migrate_config_below_first_version() {
+        for version in ["0", "-1"] {
+            let table: toml::Table = toml::from_str(&format!(
+                r#"
+                version = {version}
+                entry_point = "Random"
+                exit_point = "Random"
+                "#
+            ))
+            .unwrap();
+
+            let table = migrate_config(table);
+            assert_eq!(config_version(&table), CONFIG_VERSION);
+            let config: NymVpnServiceConfig = toml::Value::Table(table).try_into().unwrap();
+            assert_eq!(config.settings, VpnSettings::default());
+        }
+    }
+
+    #[test]
+    fn current_config_roundtrip() {
+        let config = NymVpnServiceConfig {
+            settings: VpnSettings {
+                enable_two_hop: true,
+                min_gateway_performance: Some(80),
+                encrypted_dns: Some(nym_vpn_lib::EncryptedDnsConfig::new(
+                    nym_vpn_lib::EncryptedDnsProtocol::Tls,
+                )),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let table: toml::Table = toml::from_str(&toml::to_string(&config).unwrap()).unwrap();
+        assert_eq!(config_version(&table), CONFIG_VERSION);
+
+        let migrated: NymVpnServiceConfig = toml::Value::Table(migrate_config(table))
+            .try_into()
+            .unwrap();
+        assert_eq!(migrated.settings, config.settings);
+    }
+
+    #[test]
+    fn custom_traffic_profiles() {
+        let config: NymVpnServiceConfig = toml::from_str(
+            r#"
+            version = 3
+            entry_point = "Random"
+            exit_point = "Random"
+
+            [[traffic_profiles]]
+            name = "low-latency"
+            poisson_rate = false
+            average_send_delay_ms = 20
+            average_packet_delay_ms = 0
+            loop_cover_traffic = true
+            loop_cover_traffic_delay_ms = 200
+            packet_buffer_timeout_ms = 1
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.traffic_profile(None).unwrap(),
+            TrafficProfile::default()
+        );
+        assert_eq!(
+            config.traffic_profile(Some("max-anonymity")).unwrap(),
+            TrafficProfile::builtin("max-anonymity").unwrap()
+        );
+        let custom = config.traffic_profile(Some("low-latency")).unwrap();
+        assert_eq!(custom.packet_buffer_timeout_ms, 1);
+        assert!(config.traffic_profile(Some("unknown")).is_err());
+    }
+
+    #[test]
+    fn migrate_single_dns_server() {
+        let table: toml::Table = toml::from_str(
+            r#"
+            version = 2
+            entry_point = "Random"
+            exit_point = "Random"
+
+            [settings]
+            dns = "9.9.9.9"
+            "#,
+        )
+        .unwrap();
+
+        let config: NymVpnServiceConfig = toml::Value::Table(migrate_config(table))
+            .try_into()
+            .unwrap();
+        assert_eq!(
+            config.settings.dns,
+            DnsConfig::new(vec!["9.9.9.9".parse().unwrap()])
+        );
+    }
+
+    #[test]
+    fn dns_blocklists_are_only_read_from_the_blocklist_dir() {
+        let config_dir = std::env::temp_dir().join(format!("nym-vpnd-test-{}", std::process::id()));
+        let blocklist_dir = config_dir.join(DNS_BLOCKLIST_DIR);
+        fs::create_dir_all(&blocklist_dir).unwrap();
+        fs::write(blocklist_dir.join("ads.txt"), "0.0.0.0 ads.example\n").unwrap();
+        fs::write(config_dir.join("secret.txt"), "").unwrap();
+
+        let dns_filter = |path: &str| DnsFilterConfig {
+            blocklists: vec![nym_vpn_lib::Blocklist {
+                name: "ads".to_string(),
+                enabled: true,
+                source: BlocklistSource::File {
+                    path: path.into(),
+                    format: nym_vpn_lib::BlocklistFormat::Hosts,
+                },
+            }],
+            ..Default::default()
+        };
+        let resolved = resolve_dns_blocklist_paths(dns_filter("ads.txt"), &blocklist_dir).unwrap();
+        let BlocklistSource::File { path, .. } = &resolved.blocklists[0].source else {
+            unreachable!();
+        };
+        assert_eq!(
+            path,
+            &fs::canonicalize(blocklist_dir.join("ads.txt")).unwrap()
+        );
+
+        assert!(resolve_dns_blocklist_paths(dns_filter("../secret.txt"), &blocklist_dir).is_err());
+        let secret = config_dir.join("secret.txt");
+        assert!(
+            resolve_dns_blocklist_paths(dns_filter(secret.to_str().unwrap()), &blocklist_dir)
+                .is_err()
+        );
+        assert!(resolve_dns_blocklist_paths(dns_filter("missing.txt"), &blocklist_dir).is_err());
+
+        fs::remove_dir_all(&config_dir).unwrap();
+    }
+}
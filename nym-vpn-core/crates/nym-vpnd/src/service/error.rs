@@ -147,6 +147,14 @@ pub enum ConnectionFailedError {
     #[error("unable to use same entry and exit gateway for location: {requested_location}")]
     SameEntryAndExitGatewayFromCountry { requested_location: String },
 
+    #[error("entry gateway {gateway_id} is the exit gateway, or runs on the same host")]
+    SameEntryAndExitGateway { gateway_id: String },
+
+    #[error(
+        "not enough gateways in distinct countries for {requested} middle hops, found {found}"
+    )]
+    NotEnoughMiddleGateways { requested: usize, found: usize },
+
     #[error("we ran out of bandwidth")]
     OutOfBandwidth,
 
@@ -161,6 +169,29 @@ pub enum ConnectionFailedError {
     },
 }
 
+impl ConnectionFailedError {
+    // Errors that are not going to go away by themselves, such as running out of bandwidth or
+    // asking for a location where there are no gateways, should not trigger an automatic
+    // reconnect.
+    pub fn is_retryable(&self) -> bool {
+        !matches!(
+            self,
+            ConnectionFailedError::InvalidCredential { .. }
+                | ConnectionFailedError::FailedToSetupMixnetStoragePaths { .. }
+                | ConnectionFailedError::FailedToCreateMixnetClientWithDefaultStorage { .. }
+                | ConnectionFailedError::FailedToSetupGatewayDirectoryClient { .. }
+                | ConnectionFailedError::FailedToSelectEntryGatewayIdNotFound { .. }
+                | ConnectionFailedError::FailedToSelectEntryGatewayLocation { .. }
+                | ConnectionFailedError::FailedToSelectExitGatewayLocation { .. }
+                | ConnectionFailedError::SameEntryAndExitGatewayFromCountry { .. }
+                | ConnectionFailedError::SameEntryAndExitGateway { .. }
+                | ConnectionFailedError::NotEnoughMiddleGateways { .. }
+                | ConnectionFailedError::OutOfBandwidth
+                | ConnectionFailedError::OutOfBandwidthWhenSettingUpTunnel
+        )
+    }
+}
+
 impl From<&nym_vpn_lib::Error> for ConnectionFailedError {
     fn from(err: &nym_vpn_lib::Error) -> Self {
         match err {
@@ -225,7 +256,8 @@ impl From<&nym_vpn_lib::Error> for ConnectionFailedError {
                 | nym_vpn_lib::SetupMixTunnelError::ConnectionMonitorError(_)
                 | nym_vpn_lib::SetupMixTunnelError::FailedToAddIpv6Route(_)
                 | nym_vpn_lib::SetupMixTunnelError::RoutingError(_)
-                | nym_vpn_lib::SetupMixTunnelError::DNSError(_) => {
+                | nym_vpn_lib::SetupMixTunnelError::DNSError(_)
+                | nym_vpn_lib::SetupMixTunnelError::KillSwitchError(_) => {
                     ConnectionFailedError::Unhandled(format!("unhandled error: {err:#?}"))
                 }
             },
@@ -261,6 +293,8 @@ impl From<&nym_vpn_lib::Error> for ConnectionFailedError {
                 | nym_vpn_lib::SetupWgTunnelError::RoutingError(_)
                 | nym_vpn_lib::SetupWgTunnelError::FailedToParseEntryGatewayIpv4(_)
                 | nym_vpn_lib::SetupWgTunnelError::AuthenticatorAddressNotFound
+                | nym_vpn_lib::SetupWgTunnelError::UnsupportedHopCount { .. }
+                | nym_vpn_lib::SetupWgTunnelError::PathMtuTooSmall { .. }
                 | nym_vpn_lib::SetupWgTunnelError::WireguardConfigError(_) => {
                     ConnectionFailedError::Unhandled(format!("unhandled error: {err:#?}"))
                 }
@@ -270,6 +304,8 @@ impl From<&nym_vpn_lib::Error> for ConnectionFailedError {
             | nym_vpn_lib::Error::DNSError(_)
             | nym_vpn_lib::Error::FirewallError(_)
             | nym_vpn_lib::Error::FailedToResetFirewallPolicy { .. }
+            | nym_vpn_lib::Error::KillSwitchNotSupportedWithWireguard
+            | nym_vpn_lib::Error::ExcludeNetworksNotSupportedWithMixnet
             | nym_vpn_lib::Error::CanceledError(_)
             | nym_vpn_lib::Error::FailedToSendWireguardShutdown
             | nym_vpn_lib::Error::DefaultInterfaceError
@@ -349,6 +385,17 @@ impl From<&nym_vpn_lib::GatewayDirectoryError> for ConnectionFailedError {
                     requested_location: requested_location.clone(),
                 }
             }
+            GatewayDirectoryError::SameEntryAndExitGateway { gateway_id } => {
+                ConnectionFailedError::SameEntryAndExitGateway {
+                    gateway_id: gateway_id.clone(),
+                }
+            }
+            GatewayDirectoryError::NotEnoughMiddleGateways { requested, found } => {
+                ConnectionFailedError::NotEnoughMiddleGateways {
+                    requested: *requested,
+                    found: *found,
+                }
+            }
         }
     }
 }
@@ -385,3 +432,24 @@ pub enum AccountError {
         source: Box<dyn std::error::Error + Send + Sync>,
     },
 }
+
+#[derive(Clone, Debug, thiserror::Error)]
+pub enum SplitTunnelError {
+    #[error("failed to read config: {reason}")]
+    ReadConfig { reason: String },
+
+    #[error("failed to write config: {reason}")]
+    WriteConfig { reason: String },
+}
+
+#[derive(Clone, Debug, thiserror::Error)]
+pub enum SettingsError {
+    #[error("invalid settings: {reason}")]
+    InvalidSettings { reason: String },
+
+    #[error("failed to read config: {reason}")]
+    ReadConfig { reason: String },
+
+    #[error("failed to write config: {reason}")]
+    WriteConfig { reason: String },
+}
@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use futures::channel::oneshot;
+use tokio::sync::mpsc::UnboundedSender;
 use tracing::{debug, error, info};
 
 use super::{
@@ -9,13 +10,27 @@ use super::{
     vpn_service::{SharedVpnState, VpnState},
 };
 
+// Sent back to the vpn service when the vpn exits with an error, so that it can decide whether to
+// reconnect or give up.
+#[derive(Debug)]
+pub(super) struct VpnServiceExitFailure {
+    pub(super) error: ConnectionFailedError,
+}
+
 pub(super) struct VpnServiceExitListener {
     shared_vpn_state: SharedVpnState,
+    exit_failure_tx: UnboundedSender<VpnServiceExitFailure>,
 }
 
 impl VpnServiceExitListener {
-    pub(super) fn new(shared_vpn_state: SharedVpnState) -> Self {
-        Self { shared_vpn_state }
+    pub(super) fn new(
+        shared_vpn_state: SharedVpnState,
+        exit_failure_tx: UnboundedSender<VpnServiceExitFailure>,
+    ) -> Self {
+        Self {
+            shared_vpn_state,
+            exit_failure_tx,
+        }
     }
 
     pub(super) async fn start(
@@ -45,8 +60,15 @@ impl VpnServiceExitListener {
                             None => ConnectionFailedError::Unhandled(err.to_string()),
                         };
 
-                        self.shared_vpn_state
-                            .set(VpnState::ConnectionFailed(connection_failed_err));
+                        // The vpn service decides if this is final or if we should try again. If
+                        // the service is gone we have nobody to hand this over to, so just set
+                        // the final state directly.
+                        if let Err(err) = self.exit_failure_tx.send(VpnServiceExitFailure {
+                            error: connection_failed_err,
+                        }) {
+                            self.shared_vpn_state
+                                .set(VpnState::ConnectionFailed(err.0.error));
+                        }
                         listener_vpn_exit_tx.send(exit_res).ok();
                     }
                 },
@@ -4,15 +4,20 @@
 mod config;
 mod error;
 mod exit_listener;
+mod reconnect;
 mod start;
 mod status_listener;
 mod vpn_service;
 
-pub(crate) use config::{default_log_dir, DEFAULT_LOG_FILE};
-pub(crate) use error::{AccountError, ConnectionFailedError, ImportCredentialError};
+pub(crate) use config::{
+    data_dir, default_log_dir, gateway_cache_config, VpnSettings, DEFAULT_LOG_FILE,
+};
+pub(crate) use error::{
+    AccountError, ConnectionFailedError, ImportCredentialError, SettingsError, SplitTunnelError,
+};
 pub(crate) use start::start_vpn_service;
 pub(crate) use vpn_service::{
     ConnectArgs, ConnectOptions, ConnectedStateDetails, VpnServiceCommand, VpnServiceConnectResult,
     VpnServiceDisconnectResult, VpnServiceInfoResult, VpnServiceStateChange,
-    VpnServiceStatusResult,
+    VpnServiceStatusResult, VpnServiceSwitchGatewayResult,
 };
@@ -0,0 +1,110 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{fmt, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 10;
+const DEFAULT_INITIAL_DELAY_SECS: u64 = 2;
+const DEFAULT_MAX_DELAY_SECS: u64 = 300;
+
+// Policy for automatically reconnecting when the tunnel goes down unexpectedly. The delay between
+// attempts grows exponentially, starting at the initial delay and capped at the max delay.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct ReconnectPolicy {
+    pub(crate) enabled: bool,
+
+    // The number of attempts before giving up. If not set, we keep trying forever.
+    pub(crate) max_attempts: Option<u32>,
+
+    pub(crate) initial_delay_secs: u64,
+
+    pub(crate) max_delay_secs: u64,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_attempts: Some(DEFAULT_MAX_ATTEMPTS),
+            initial_delay_secs: DEFAULT_INITIAL_DELAY_SECS,
+            max_delay_secs: DEFAULT_MAX_DELAY_SECS,
+        }
+    }
+}
+
+impl fmt::Display for ReconnectPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "enabled: {}, max_attempts: {}, initial_delay: {}s, max_delay: {}s",
+            self.enabled,
+            self.max_attempts
+                .map_or_else(|| "unlimited".to_string(), |n| n.to_string()),
+            self.initial_delay_secs,
+            self.max_delay_secs
+        )
+    }
+}
+
+impl ReconnectPolicy {
+    // Returns the delay to wait before the given attempt, where the first attempt is 1. Returns
+    // None if we should give up.
+    pub(crate) fn delay_for_attempt(&self, attempt: u32) -> Option<Duration> {
+        if !self.enabled || attempt == 0 {
+            return None;
+        }
+        if let Some(max_attempts) = self.max_attempts {
+            if attempt > max_attempts {
+                return None;
+            }
+        }
+        let factor = 2u64.saturating_pow(attempt - 1);
+        let delay_secs = self
+            .initial_delay_secs
+            .saturating_mul(factor)
+            .min(self.max_delay_secs);
+        Some(Duration::from_secs(delay_secs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_grows_exponentially_up_to_max() {
+        let policy = ReconnectPolicy {
+            enabled: true,
+            max_attempts: None,
+            initial_delay_secs: 2,
+            max_delay_secs: 30,
+        };
+        assert_eq!(policy.delay_for_attempt(1), Some(Duration::from_secs(2)));
+        assert_eq!(policy.delay_for_attempt(2), Some(Duration::from_secs(4)));
+        assert_eq!(policy.delay_for_attempt(4), Some(Duration::from_secs(16)));
+        assert_eq!(policy.delay_for_attempt(5), Some(Duration::from_secs(30)));
+        assert_eq!(policy.delay_for_attempt(100), Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let policy = ReconnectPolicy {
+            max_attempts: Some(3),
+            ..Default::default()
+        };
+        assert!(policy.delay_for_attempt(3).is_some());
+        assert!(policy.delay_for_attempt(4).is_none());
+    }
+
+    #[test]
+    fn disabled_never_retries() {
+        let policy = ReconnectPolicy {
+            enabled: false,
+            ..Default::default()
+        };
+        assert!(policy.delay_for_attempt(1).is_none());
+    }
+}
@@ -51,6 +51,7 @@ impl VpnServiceStatusListener {
                                 ipv6: mixnet_exit_connection_info.ips.ipv6,
                             },
                         )),
+                        mtu: mixnet_exit_connection_info.mtu,
                         since: OffsetDateTime::now_utc(),
                     };
                     self.shared_vpn_state
@@ -59,6 +60,7 @@ impl VpnServiceStatusListener {
                 NymVpnStatusMessage::WgConnectionInfo {
                     entry_connection_info,
                     exit_connection_info,
+                    ..
                 } => {
                     let connected_details = VpnConnectedStateDetails {
                         entry_gateway: entry_connection_info.gateway_id,
@@ -67,12 +69,14 @@ impl VpnServiceStatusListener {
                             entry_ipv4: entry_connection_info.private_ipv4,
                             exit_ipv4: exit_connection_info.private_ipv4,
                         }),
-
+                        mtu: exit_connection_info.mtu,
                         since: OffsetDateTime::now_utc(),
                     };
                     self.shared_vpn_state
                         .set(VpnState::Connected(Box::new(connected_details)));
                 }
+                // The connection info of the new exit follows, and updates the connected state
+                NymVpnStatusMessage::ExitFailover { .. } => {}
             }
         } else if let Some(msg) = msg.downcast_ref::<ConnectionMonitorStatus>() {
             info!("VPN connection monitor status: {msg}");
@@ -3,7 +3,7 @@
 
 use std::{
     fmt,
-    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    net::{Ipv4Addr, Ipv6Addr},
     path::PathBuf,
     sync::Arc,
 };
@@ -11,8 +11,10 @@ use std::{
 use bip39::Mnemonic;
 use futures::{
     channel::{mpsc::UnboundedSender, oneshot::Receiver as OneshotReceiver},
-    SinkExt,
+    future::BoxFuture,
+    FutureExt as _, SinkExt,
 };
+use nym_task::StatusSender;
 use nym_vpn_api_client::{
     response::{NymVpnAccountSummaryResponse, NymVpnDevice, NymVpnZkNym, NymVpnZkNymResponse},
     types::VpnApiAccount,
@@ -21,22 +23,31 @@ use nym_vpn_lib::{
     credentials::import_credential,
     gateway_directory::{self, EntryPoint, ExitPoint},
     nym_config::defaults::NymNetworkDetails,
-    GenericNymVpnConfig, MixnetClientConfig, NodeIdentity, Recipient,
+    DnsConfig, DnsFilterConfig, DnsFilterStats, DnsFilterStatsHandle, EncryptedDnsConfig,
+    GenericNymVpnConfig, KillSwitchConfig, LeakTestReport, MixnetClientConfig, NodeIdentity,
+    Recipient, SplitTunnelConfig, TrafficStatsHandle,
 };
 use nym_vpn_store::keys::KeyStore as _;
 use serde::{Deserialize, Serialize};
 use time::{format_description::well_known::Rfc3339, OffsetDateTime};
-use tokio::sync::{broadcast, mpsc::UnboundedReceiver, oneshot};
-use tracing::{debug, error, info};
+use tokio::sync::{
+    broadcast,
+    mpsc::{self as tokio_mpsc, UnboundedReceiver},
+    oneshot,
+};
+use tracing::{debug, error, info, warn};
 use url::Url;
 
 use super::{
     config::{
         self, create_config_file, create_data_dir, read_config_file, write_config_file,
-        ConfigSetupError, NymVpnServiceConfig, DEFAULT_CONFIG_FILE,
+        ConfigSetupError, NymVpnServiceConfig, VpnSettings, DEFAULT_CONFIG_FILE,
+    },
+    error::{
+        AccountError, ConnectionFailedError, ImportCredentialError, SettingsError, SplitTunnelError,
     },
-    error::{AccountError, ConnectionFailedError, ImportCredentialError},
-    exit_listener::VpnServiceExitListener,
+    exit_listener::{VpnServiceExitFailure, VpnServiceExitListener},
+    reconnect::ReconnectPolicy,
     status_listener::VpnServiceStatusListener,
 };
 
@@ -48,6 +59,10 @@ pub enum VpnState {
     Connected(Box<VpnConnectedStateDetails>),
     Disconnecting,
     ConnectionFailed(ConnectionFailedError),
+    Reconnecting {
+        attempt: u32,
+        next_retry_at: OffsetDateTime,
+    },
 }
 
 impl fmt::Display for VpnState {
@@ -58,6 +73,14 @@ impl fmt::Display for VpnState {
             VpnState::Connected(details) => write!(f, "Connected({})", details),
             VpnState::Disconnecting => write!(f, "Disconnecting"),
             VpnState::ConnectionFailed(reason) => write!(f, "ConnectionFailed({})", reason),
+            VpnState::Reconnecting {
+                attempt,
+                next_retry_at,
+            } => write!(
+                f,
+                "Reconnecting(attempt: {}, next_retry_at: {})",
+                attempt, next_retry_at
+            ),
         }
     }
 }
@@ -108,6 +131,8 @@ pub struct VpnConnectedStateDetails {
     pub entry_gateway: NodeIdentity,
     pub exit_gateway: NodeIdentity,
     pub specific_details: ConnectedStateDetails,
+    // Of the innermost tunnel
+    pub mtu: u16,
     pub since: time::OffsetDateTime,
 }
 
@@ -115,8 +140,8 @@ impl fmt::Display for VpnConnectedStateDetails {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "entry_gateway: {}, exit_gateway: {}, specific_details: {}, since: {}",
-            self.entry_gateway, self.exit_gateway, self.specific_details, self.since
+            "entry_gateway: {}, exit_gateway: {}, specific_details: {}, mtu: {}, since: {}",
+            self.entry_gateway, self.exit_gateway, self.specific_details, self.mtu, self.since
         )
     }
 }
@@ -125,7 +150,16 @@ impl fmt::Display for VpnConnectedStateDetails {
 pub enum VpnServiceCommand {
     Connect(oneshot::Sender<VpnServiceConnectResult>, ConnectArgs),
     Disconnect(oneshot::Sender<VpnServiceDisconnectResult>),
+    SwitchGateway(
+        oneshot::Sender<VpnServiceSwitchGatewayResult>,
+        Option<gateway_directory::EntryPoint>,
+        Option<gateway_directory::ExitPoint>,
+    ),
     Status(oneshot::Sender<VpnServiceStatusResult>),
+    // The counters are read by the caller, so that the service doesn't wait on them
+    GetTrafficStats(oneshot::Sender<Option<TrafficStatsHandle>>),
+    GetDnsFilterStats(oneshot::Sender<Option<DnsFilterStats>>),
+    RunLeakTest(oneshot::Sender<Option<LeakTestReport>>),
     Info(oneshot::Sender<VpnServiceInfoResult>),
     ImportCredential(
         oneshot::Sender<Result<Option<OffsetDateTime>, ImportCredentialError>>,
@@ -136,6 +170,16 @@ pub enum VpnServiceCommand {
     RegisterDevice(oneshot::Sender<Result<NymVpnDevice, AccountError>>),
     RequestZkNym(oneshot::Sender<Result<NymVpnZkNym, AccountError>>),
     GetDeviceZkNyms(oneshot::Sender<Result<NymVpnZkNymResponse, AccountError>>),
+    GetSettings(oneshot::Sender<Result<VpnSettings, SettingsError>>),
+    SetSettings(oneshot::Sender<Result<(), SettingsError>>, VpnSettings),
+    ResetSettings(oneshot::Sender<Result<VpnSettings, SettingsError>>),
+    GetSplitTunnel(oneshot::Sender<Result<SplitTunnelConfig, SplitTunnelError>>),
+    SetSplitTunnel(
+        oneshot::Sender<Result<(), SplitTunnelError>>,
+        SplitTunnelConfig,
+    ),
+    GetDnsFilter(oneshot::Sender<Result<DnsFilterConfig, SettingsError>>),
+    SetDnsFilter(oneshot::Sender<Result<(), SettingsError>>, DnsFilterConfig),
     Shutdown,
 }
 
@@ -144,7 +188,13 @@ impl fmt::Display for VpnServiceCommand {
         match self {
             VpnServiceCommand::Connect(_, args) => write!(f, "Connect {{ {args:?} }}"),
             VpnServiceCommand::Disconnect(_) => write!(f, "Disconnect"),
+            VpnServiceCommand::SwitchGateway(_, entry, exit) => {
+                write!(f, "SwitchGateway {{ entry: {entry:?}, exit: {exit:?} }}")
+            }
             VpnServiceCommand::Status(_) => write!(f, "Status"),
+            VpnServiceCommand::GetTrafficStats(_) => write!(f, "GetTrafficStats"),
+            VpnServiceCommand::GetDnsFilterStats(_) => write!(f, "GetDnsFilterStats"),
+            VpnServiceCommand::RunLeakTest(_) => write!(f, "RunLeakTest"),
             VpnServiceCommand::Info(_) => write!(f, "Info"),
             VpnServiceCommand::ImportCredential(_, _) => write!(f, "ImportCredential"),
             VpnServiceCommand::StoreAccount(_, _) => write!(f, "StoreAccount"),
@@ -152,28 +202,85 @@ impl fmt::Display for VpnServiceCommand {
             VpnServiceCommand::RegisterDevice(_) => write!(f, "RegisterDevice"),
             VpnServiceCommand::RequestZkNym(_) => write!(f, "RequestZkNym"),
             VpnServiceCommand::GetDeviceZkNyms(_) => write!(f, "GetDeviceZkNyms"),
+            VpnServiceCommand::GetSettings(_) => write!(f, "GetSettings"),
+            VpnServiceCommand::SetSettings(_, settings) => {
+                write!(f, "SetSettings {{ {settings:?} }}")
+            }
+            VpnServiceCommand::ResetSettings(_) => write!(f, "ResetSettings"),
+            VpnServiceCommand::GetSplitTunnel(_) => write!(f, "GetSplitTunnel"),
+            VpnServiceCommand::SetSplitTunnel(_, config) => {
+                write!(f, "SetSplitTunnel {{ {config} }}")
+            }
+            VpnServiceCommand::GetDnsFilter(_) => write!(f, "GetDnsFilter"),
+            VpnServiceCommand::SetDnsFilter(_, config) => {
+                write!(f, "SetDnsFilter {{ {config:?} }}")
+            }
             VpnServiceCommand::Shutdown => write!(f, "Shutdown"),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ConnectArgs {
     pub entry: Option<gateway_directory::EntryPoint>,
     pub exit: Option<gateway_directory::ExitPoint>,
     pub options: ConnectOptions,
 }
 
+// The options given with a connect request. Options that are not set fall back to the stored
+// settings.
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct ConnectOptions {
-    pub(crate) dns: Option<IpAddr>,
-    pub(crate) disable_routing: bool,
-    pub(crate) enable_two_hop: bool,
-    pub(crate) enable_poisson_rate: bool,
-    pub(crate) disable_background_cover_traffic: bool,
-    pub(crate) enable_credentials_mode: bool,
+    pub(crate) dns: Option<DnsConfig>,
+    pub(crate) disable_routing: Option<bool>,
+    pub(crate) enable_two_hop: Option<bool>,
+    pub(crate) enable_poisson_rate: Option<bool>,
+    pub(crate) disable_background_cover_traffic: Option<bool>,
+    pub(crate) enable_credentials_mode: Option<bool>,
     pub(crate) min_mixnode_performance: Option<u8>,
     pub(crate) min_gateway_performance: Option<u8>,
+    pub(crate) enable_kill_switch: Option<bool>,
+    pub(crate) kill_switch_allow_lan: Option<bool>,
+    pub(crate) wireguard_hop_count: Option<u8>,
+    pub(crate) enable_dns_filter: Option<bool>,
+    pub(crate) traffic_profile: Option<String>,
+    // Set to `Some(None)` to turn off the encrypted DNS of the stored settings
+    pub(crate) encrypted_dns: Option<Option<EncryptedDnsConfig>>,
+}
+
+impl ConnectOptions {
+    pub(crate) fn apply_to(self, settings: VpnSettings) -> VpnSettings {
+        VpnSettings {
+            dns: self.dns.unwrap_or(settings.dns),
+            disable_routing: self.disable_routing.unwrap_or(settings.disable_routing),
+            enable_two_hop: self.enable_two_hop.unwrap_or(settings.enable_two_hop),
+            enable_poisson_rate: self
+                .enable_poisson_rate
+                .unwrap_or(settings.enable_poisson_rate),
+            disable_background_cover_traffic: self
+                .disable_background_cover_traffic
+                .unwrap_or(settings.disable_background_cover_traffic),
+            enable_credentials_mode: self
+                .enable_credentials_mode
+                .unwrap_or(settings.enable_credentials_mode),
+            min_mixnode_performance: self
+                .min_mixnode_performance
+                .or(settings.min_mixnode_performance),
+            min_gateway_performance: self
+                .min_gateway_performance
+                .or(settings.min_gateway_performance),
+            enable_kill_switch: self
+                .enable_kill_switch
+                .unwrap_or(settings.enable_kill_switch),
+            kill_switch_allow_lan: self
+                .kill_switch_allow_lan
+                .unwrap_or(settings.kill_switch_allow_lan),
+            wireguard_hop_count: self.wireguard_hop_count.or(settings.wireguard_hop_count),
+            enable_dns_filter: self.enable_dns_filter.unwrap_or(settings.enable_dns_filter),
+            traffic_profile: self.traffic_profile.or(settings.traffic_profile),
+            encrypted_dns: self.encrypted_dns.unwrap_or(settings.encrypted_dns),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -188,6 +295,13 @@ impl VpnServiceConnectResult {
     }
 }
 
+// The gateways the vpn switched to, with the ones that weren't switched left out
+#[derive(Debug)]
+struct SwitchedGateways {
+    entry: Option<gateway_directory::EntryPoint>,
+    exit: Option<gateway_directory::ExitPoint>,
+}
+
 #[derive(Debug)]
 pub struct VpnServiceConnectHandle {
     pub listener_vpn_status_rx: nym_vpn_lib::StatusReceiver,
@@ -209,6 +323,20 @@ impl VpnServiceDisconnectResult {
     }
 }
 
+#[derive(Debug)]
+pub enum VpnServiceSwitchGatewayResult {
+    Success,
+    NotConnected,
+    // The entry is the gateway the mixnet client is connected to, so it stays put in mixnet mode
+    EntrySwitchNotSupported,
+}
+
+impl VpnServiceSwitchGatewayResult {
+    pub fn is_success(&self) -> bool {
+        matches!(self, VpnServiceSwitchGatewayResult::Success)
+    }
+}
+
 // Respond with the current state of the VPN service. This is currently almost the same as VpnState,
 // but it's conceptually not the same thing, so we keep them separate.
 #[derive(Clone, Debug)]
@@ -218,6 +346,10 @@ pub enum VpnServiceStatusResult {
     Connected(Box<ConnectedResultDetails>),
     Disconnecting,
     ConnectionFailed(ConnectionFailedError),
+    Reconnecting {
+        attempt: u32,
+        next_retry_at: OffsetDateTime,
+    },
 }
 
 #[derive(Clone, Debug)]
@@ -241,6 +373,14 @@ impl fmt::Display for VpnServiceStatusResult {
             VpnServiceStatusResult::ConnectionFailed(reason) => {
                 write!(f, "ConnectionFailed({})", reason)
             }
+            VpnServiceStatusResult::Reconnecting {
+                attempt,
+                next_retry_at,
+            } => write!(
+                f,
+                "Reconnecting(attempt: {}, next_retry_at: {})",
+                attempt, next_retry_at
+            ),
         }
     }
 }
@@ -250,6 +390,8 @@ pub struct ConnectedResultDetails {
     pub entry_gateway: NodeIdentity,
     pub exit_gateway: NodeIdentity,
     pub specific_details: ConnectedStateDetails,
+    // Of the innermost tunnel
+    pub mtu: u16,
     pub since: time::OffsetDateTime,
 }
 
@@ -257,8 +399,8 @@ impl fmt::Display for ConnectedResultDetails {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "entry_gateway: {}, exit_gateway: {}, specific_details: {}, since: {}",
-            self.entry_gateway, self.exit_gateway, self.specific_details, self.since
+            "entry_gateway: {}, exit_gateway: {}, specific_details: {}, mtu: {}, since: {}",
+            self.entry_gateway, self.exit_gateway, self.specific_details, self.mtu, self.since
         )
     }
 }
@@ -269,6 +411,7 @@ impl From<VpnConnectedStateDetails> for ConnectedResultDetails {
             entry_gateway: details.entry_gateway,
             exit_gateway: details.exit_gateway,
             specific_details: details.specific_details,
+            mtu: details.mtu,
             since: details.since,
         }
     }
@@ -297,6 +440,13 @@ impl From<VpnState> for VpnServiceStatusResult {
             VpnState::Connected(details) => VpnServiceStatusResult::Connected(details.into()),
             VpnState::Disconnecting => VpnServiceStatusResult::Disconnecting,
             VpnState::ConnectionFailed(reason) => VpnServiceStatusResult::ConnectionFailed(reason),
+            VpnState::Reconnecting {
+                attempt,
+                next_retry_at,
+            } => VpnServiceStatusResult::Reconnecting {
+                attempt,
+                next_retry_at,
+            },
         }
     }
 }
@@ -308,6 +458,10 @@ pub enum VpnServiceStateChange {
     Connected,
     Disconnecting,
     ConnectionFailed(ConnectionFailedError),
+    Reconnecting {
+        attempt: u32,
+        next_retry_at: OffsetDateTime,
+    },
 }
 
 impl VpnServiceStateChange {
@@ -327,6 +481,13 @@ impl From<VpnState> for VpnServiceStateChange {
             VpnState::Connected { .. } => VpnServiceStateChange::Connected,
             VpnState::Disconnecting => VpnServiceStateChange::Disconnecting,
             VpnState::ConnectionFailed(reason) => VpnServiceStateChange::ConnectionFailed(reason),
+            VpnState::Reconnecting {
+                attempt,
+                next_retry_at,
+            } => VpnServiceStateChange::Reconnecting {
+                attempt,
+                next_retry_at,
+            },
         }
     }
 }
@@ -369,6 +530,41 @@ where
     // Send commands to the actual vpn service task
     vpn_ctrl_sender: Option<UnboundedSender<nym_vpn_lib::NymVpnCtrlMessage>>,
 
+    // The traffic counters of the running vpn
+    traffic_stats: Option<TrafficStatsHandle>,
+
+    // The DNS filter counters of the running vpn, when it filters
+    dns_filter_stats: Option<DnsFilterStatsHandle>,
+
+    // The exit listener reports back here when the vpn exits with an error
+    exit_failure_tx: tokio_mpsc::UnboundedSender<VpnServiceExitFailure>,
+    exit_failure_rx: UnboundedReceiver<VpnServiceExitFailure>,
+
+    // The arguments of the last connect request from the user, reused when reconnecting
+    last_connect_args: Option<ConnectArgs>,
+
+    // The settings the current connection was started with
+    last_connect_settings: Option<VpnSettings>,
+
+    // The status channel handed out on the last connect request from the user. We keep a copy
+    // around so that status messages from reconnected tunnels reach the same listeners.
+    listener_vpn_status_tx: Option<StatusSender>,
+
+    reconnect_policy: ReconnectPolicy,
+
+    // The number of reconnect attempts made since we were last connected
+    reconnect_attempt: u32,
+
+    // When the next reconnect attempt is due, if one is scheduled
+    pending_reconnect: Option<tokio::time::Instant>,
+
+    // The gateways the vpn switched to, once it reports that the switch went through
+    switched_gateways_tx: tokio_mpsc::UnboundedSender<SwitchedGateways>,
+    switched_gateways_rx: UnboundedReceiver<SwitchedGateways>,
+
+    // Lifts the kill switch block that outlives a failed vpn
+    disable_kill_switch: fn() -> BoxFuture<'static, Result<(), nym_vpn_lib::Error>>,
+
     config_file: PathBuf,
 
     data_dir: PathBuf,
@@ -386,14 +582,27 @@ impl NymVpnService<nym_vpn_lib::storage::VpnClientOnDiskStorage> {
             .map(PathBuf::from)
             .unwrap_or_else(|_| config::default_config_dir());
         let config_file = config_dir.join(DEFAULT_CONFIG_FILE);
-        let data_dir = std::env::var("NYM_VPND_DATA_DIR")
-            .map(PathBuf::from)
-            .unwrap_or_else(|_| config::default_data_dir());
+        let data_dir = config::data_dir();
         let storage = nym_vpn_lib::storage::VpnClientOnDiskStorage::new(data_dir.clone());
+        let (exit_failure_tx, exit_failure_rx) = tokio_mpsc::unbounded_channel();
+        let (switched_gateways_tx, switched_gateways_rx) = tokio_mpsc::unbounded_channel();
         Self {
             shared_vpn_state: SharedVpnState::new(vpn_state_changes_tx),
             vpn_command_rx,
             vpn_ctrl_sender: None,
+            traffic_stats: None,
+            dns_filter_stats: None,
+            exit_failure_tx,
+            exit_failure_rx,
+            last_connect_args: None,
+            last_connect_settings: None,
+            listener_vpn_status_tx: None,
+            reconnect_policy: ReconnectPolicy::default(),
+            reconnect_attempt: 0,
+            pending_reconnect: None,
+            switched_gateways_tx,
+            switched_gateways_rx,
+            disable_kill_switch: || nym_vpn_lib::disable_kill_switch().boxed(),
             config_file,
             data_dir,
             storage,
@@ -428,14 +637,18 @@ where
     ) -> std::result::Result<NymVpnServiceConfig, ConfigSetupError> {
         // If the config file does not exit, create it
         let config = if self.config_file.exists() {
-            let mut read_config = read_config_file(&self.config_file)
-                .map_err(|err| {
+            let mut read_config = match read_config_file(&self.config_file) {
+                Ok(config) => config,
+                // Don't overwrite a config written by a newer version of the daemon
+                Err(err @ ConfigSetupError::UnsupportedVersion { .. }) => return Err(err),
+                Err(err) => {
                     error!(
                         "Failed to read config file, resetting to defaults: {:?}",
                         err
                     );
-                })
-                .unwrap_or_default();
+                    NymVpnServiceConfig::default()
+                }
+            };
             read_config.entry_point = entry.unwrap_or(read_config.entry_point);
             read_config.exit_point = exit.unwrap_or(read_config.exit_point);
             write_config_file(&self.config_file, &read_config)?;
@@ -444,6 +657,7 @@ where
             let config = NymVpnServiceConfig {
                 entry_point: entry.unwrap_or(EntryPoint::Random),
                 exit_point: exit.unwrap_or(ExitPoint::Random),
+                ..Default::default()
             };
             create_config_file(&self.config_file, config)?
         };
@@ -451,6 +665,38 @@ where
     }
 
     async fn handle_connect(&mut self, connect_args: ConnectArgs) -> VpnServiceConnectResult {
+        // A connect request from the user always starts over, cancelling any pending reconnect
+        self.pending_reconnect = None;
+        self.reconnect_attempt = 0;
+        self.last_connect_args = Some(connect_args.clone());
+
+        let (listener_vpn_status_tx, listener_vpn_status_rx) = futures::channel::mpsc::channel(16);
+        let (listener_vpn_exit_tx, listener_vpn_exit_rx) = futures::channel::oneshot::channel();
+        self.listener_vpn_status_tx = Some(listener_vpn_status_tx.clone());
+
+        if let Err(err) = self
+            .start_vpn(connect_args, listener_vpn_status_tx, listener_vpn_exit_tx)
+            .await
+        {
+            return VpnServiceConnectResult::Fail(err.to_string());
+        }
+
+        let connect_handle = VpnServiceConnectHandle {
+            listener_vpn_status_rx,
+            listener_vpn_exit_rx,
+        };
+
+        VpnServiceConnectResult::Success(connect_handle)
+    }
+
+    async fn start_vpn(
+        &mut self,
+        connect_args: ConnectArgs,
+        listener_vpn_status_tx: StatusSender,
+        listener_vpn_exit_tx: futures::channel::oneshot::Sender<
+            nym_vpn_lib::NymVpnExitStatusMessage,
+        >,
+    ) -> Result<(), ConfigSetupError> {
         self.shared_vpn_state.set(VpnState::Connecting);
 
         let ConnectArgs {
@@ -474,18 +720,39 @@ where
         );
         info!("Using options: {:?}", options);
 
-        let config = match self.try_setup_config(entry, exit) {
-            Ok(config) => config,
+        // The options in the connect request take precedence over the stored settings
+        let setup = self.try_setup_config(entry, exit).and_then(|config| {
+            let settings = options.apply_to(config.settings.clone());
+            settings.validate()?;
+            let traffic_profile = config.traffic_profile(settings.traffic_profile.as_deref())?;
+            let dns_filter = settings
+                .enable_dns_filter
+                .then(|| {
+                    config::resolve_dns_blocklist_paths(
+                        config.dns_filter.clone(),
+                        &self.dns_blocklist_dir(),
+                    )
+                    .map_err(|reason| ConfigSetupError::InvalidSettings { reason })
+                })
+                .transpose()?;
+            Ok((config, settings, traffic_profile, dns_filter))
+        });
+        let (config, options, traffic_profile, dns_filter) = match setup {
+            Ok(setup) => setup,
             Err(err) => {
                 self.shared_vpn_state.set(VpnState::NotConnected);
-                return VpnServiceConnectResult::Fail(err.to_string());
+                return Err(err);
             }
         };
 
         info!("Using config: {}", config);
+        info!("Using settings: {:?}", options);
+        self.reconnect_policy = config.reconnect.clone();
+        self.last_connect_settings = Some(options.clone());
 
         let generic_config = GenericNymVpnConfig {
             mixnet_client_config: MixnetClientConfig {
+                traffic_profile,
                 enable_poisson_rate: options.enable_poisson_rate,
                 disable_background_cover_traffic: options.disable_background_cover_traffic,
                 enable_credentials_mode: options.enable_credentials_mode,
@@ -495,20 +762,35 @@ where
             data_path: Some(self.data_dir.clone()),
             gateway_config: gateway_directory::Config::new_from_env(
                 options.min_gateway_performance,
-            ),
+            )
+            // The gateways are picked from the latest lists, the cache only covers for the
+            // directory being down, or blocked by the kill switch when reconnecting
+            .with_cache(config::gateway_cache_config(&self.data_dir).fallback_only()),
             entry_point: config.entry_point.clone(),
             exit_point: config.exit_point.clone(),
             nym_ips: None,
             nym_mtu: None,
-            dns: options.dns,
+            dns: options.dns.clone(),
+            encrypted_dns: options.encrypted_dns.clone(),
+            dns_filter,
             disable_routing: options.disable_routing,
             user_agent: Some(nym_bin_common::bin_info_local_vergen!().into()),
+            kill_switch: KillSwitchConfig {
+                enabled: options.enable_kill_switch,
+                allow_lan: options.kill_switch_allow_lan,
+            },
+            split_tunnel: config.split_tunnel.clone(),
+            connection_monitor: Default::default(),
+            exit_failover: Default::default(),
         };
 
         let nym_vpn = if options.enable_two_hop {
             let mut nym_vpn =
                 nym_vpn_lib::NymVpn::new_wireguard_vpn(config.entry_point, config.exit_point);
             nym_vpn.generic_config = generic_config;
+            if let Some(hop_count) = options.wireguard_hop_count {
+                nym_vpn.vpn_config.hop_count = hop_count.into();
+            }
             nym_vpn.into()
         } else {
             let mut nym_vpn =
@@ -523,27 +805,104 @@ where
             vpn_ctrl_tx,
             vpn_status_rx,
             vpn_exit_rx,
+            traffic_stats,
+            dns_filter_stats,
         } = handle;
 
         self.vpn_ctrl_sender = Some(vpn_ctrl_tx);
-
-        let (listener_vpn_status_tx, listener_vpn_status_rx) = futures::channel::mpsc::channel(16);
-        let (listener_vpn_exit_tx, listener_vpn_exit_rx) = futures::channel::oneshot::channel();
+        self.traffic_stats = Some(traffic_stats);
+        self.dns_filter_stats = options.enable_dns_filter.then_some(dns_filter_stats);
 
         VpnServiceStatusListener::new(self.shared_vpn_state.clone())
             .start(vpn_status_rx, listener_vpn_status_tx)
             .await;
 
-        VpnServiceExitListener::new(self.shared_vpn_state.clone())
+        VpnServiceExitListener::new(self.shared_vpn_state.clone(), self.exit_failure_tx.clone())
             .start(vpn_exit_rx, listener_vpn_exit_tx)
             .await;
 
-        let connect_handle = VpnServiceConnectHandle {
-            listener_vpn_status_rx,
-            listener_vpn_exit_rx,
+        Ok(())
+    }
+
+    fn handle_vpn_exit_failure(&mut self, failure: VpnServiceExitFailure) {
+        let VpnServiceExitFailure { error } = failure;
+        let state = self.shared_vpn_state.get();
+
+        // Once a tunnel made it all the way to connected, we start counting attempts from scratch
+        if matches!(state, VpnState::Connected(_)) {
+            self.reconnect_attempt = 0;
+        }
+
+        if matches!(state, VpnState::Disconnecting) || self.last_connect_args.is_none() {
+            self.shared_vpn_state.set(VpnState::ConnectionFailed(error));
+            return;
+        }
+
+        if !error.is_retryable() {
+            info!("Not reconnecting since the error is not retryable: {error}");
+            self.shared_vpn_state.set(VpnState::ConnectionFailed(error));
+            return;
+        }
+
+        let attempt = self.reconnect_attempt + 1;
+        match self.reconnect_policy.delay_for_attempt(attempt) {
+            Some(delay) => {
+                info!(
+                    "Scheduling reconnect attempt {attempt} in {}s",
+                    delay.as_secs()
+                );
+                self.reconnect_attempt = attempt;
+                self.pending_reconnect = Some(tokio::time::Instant::now() + delay);
+                self.shared_vpn_state.set(VpnState::Reconnecting {
+                    attempt,
+                    next_retry_at: OffsetDateTime::now_utc() + delay,
+                });
+            }
+            None => {
+                warn!(
+                    "Giving up reconnecting after {} attempts",
+                    self.reconnect_attempt
+                );
+                self.shared_vpn_state.set(VpnState::ConnectionFailed(error));
+            }
+        }
+    }
+
+    async fn handle_reconnect(&mut self) {
+        self.pending_reconnect = None;
+        let Some(connect_args) = self.last_connect_args.clone() else {
+            self.shared_vpn_state.set(VpnState::NotConnected);
+            return;
         };
 
-        VpnServiceConnectResult::Success(connect_handle)
+        info!("Reconnecting, attempt {}", self.reconnect_attempt);
+
+        // Reuse the status channel from the original connect request, so that whoever is
+        // listening keeps receiving status updates.
+        let listener_vpn_status_tx = self
+            .listener_vpn_status_tx
+            .clone()
+            .unwrap_or_else(|| futures::channel::mpsc::channel(16).0);
+        let (listener_vpn_exit_tx, listener_vpn_exit_rx) = futures::channel::oneshot::channel();
+
+        if let Err(err) = self
+            .start_vpn(connect_args, listener_vpn_status_tx, listener_vpn_exit_tx)
+            .await
+        {
+            error!("Failed to reconnect: {err}");
+            self.shared_vpn_state.set(VpnState::ConnectionFailed(
+                ConnectionFailedError::Unhandled(err.to_string()),
+            ));
+            return;
+        }
+
+        // There is no connect request to hand the exit over to, so we wait for it ourselves
+        tokio::spawn(async move {
+            match listener_vpn_exit_rx.await {
+                Ok(exit_res) => info!("Reconnected VPN exited: {exit_res:?}"),
+                Err(err) => error!("Reconnected VPN exit listener: {err}"),
+            }
+        });
     }
 
     fn is_running(&self) -> bool {
@@ -554,9 +913,22 @@ where
     }
 
     async fn handle_disconnect(&mut self) -> VpnServiceDisconnectResult {
+        // If we are waiting to reconnect there is no tunnel to stop, just cancel the reconnect
+        if self.pending_reconnect.take().is_some() {
+            info!("Cancelling pending reconnect");
+            self.lift_kill_switch().await;
+            self.shared_vpn_state.set(VpnState::NotConnected);
+            return VpnServiceDisconnectResult::Success;
+        }
+
         // To handle the mutable borrow we set the state separate from the sending the stop message
         if self.is_running() {
             self.shared_vpn_state.set(VpnState::Disconnecting);
+        } else if matches!(self.shared_vpn_state.get(), VpnState::ConnectionFailed(_)) {
+            // The kill switch keeps blocking after a failure until the user disconnects
+            self.lift_kill_switch().await;
+            self.shared_vpn_state.set(VpnState::NotConnected);
+            return VpnServiceDisconnectResult::Success;
         } else {
             return VpnServiceDisconnectResult::NotRunning;
         }
@@ -572,10 +944,117 @@ where
         }
     }
 
+    // The switch itself happens asynchronously in the vpn task, which reports the new gateways
+    // with a status update once it's done, and reports back to us whether it went through.
+    async fn handle_switch_gateway(
+        &mut self,
+        entry: Option<gateway_directory::EntryPoint>,
+        exit: Option<gateway_directory::ExitPoint>,
+    ) -> VpnServiceSwitchGatewayResult {
+        if !matches!(self.shared_vpn_state.get(), VpnState::Connected(_)) {
+            return VpnServiceSwitchGatewayResult::NotConnected;
+        }
+        let two_hop = self
+            .last_connect_settings
+            .as_ref()
+            .is_some_and(|settings| settings.enable_two_hop);
+        if entry.is_some() && !two_hop {
+            return VpnServiceSwitchGatewayResult::EntrySwitchNotSupported;
+        }
+        let Some(ref mut vpn_ctrl_sender) = self.vpn_ctrl_sender else {
+            return VpnServiceSwitchGatewayResult::NotConnected;
+        };
+
+        let (result_tx, result_rx) = oneshot::channel();
+        let switch = nym_vpn_lib::NymVpnCtrlMessage::SwitchGateways {
+            entry_point: entry.clone(),
+            exit_point: exit.clone(),
+            result_tx,
+        };
+        if let Err(err) = vpn_ctrl_sender.send(switch).await {
+            warn!("Failed to send the switch to the vpn: {err}");
+            return VpnServiceSwitchGatewayResult::NotConnected;
+        }
+
+        let switched_gateways_tx = self.switched_gateways_tx.clone();
+        tokio::spawn(async move {
+            match result_rx.await {
+                Ok(Ok(())) => {
+                    switched_gateways_tx
+                        .send(SwitchedGateways { entry, exit })
+                        .ok();
+                }
+                Ok(Err(err)) => warn!("Failed to switch gateways: {err}"),
+                Err(_) => warn!("The vpn stopped before switching gateways"),
+            }
+        });
+        VpnServiceSwitchGatewayResult::Success
+    }
+
+    // Remember the new gateways, so that we reconnect to them if the connection drops
+    fn handle_gateways_switched(&mut self, switched: SwitchedGateways) {
+        let SwitchedGateways { entry, exit } = switched;
+        if let Some(connect_args) = self.last_connect_args.as_mut() {
+            connect_args.entry = entry.or(connect_args.entry.take());
+            connect_args.exit = exit.or(connect_args.exit.take());
+        }
+    }
+
+    async fn lift_kill_switch(&self) {
+        let kill_switch_enabled = self
+            .last_connect_settings
+            .as_ref()
+            .is_some_and(|settings| settings.enable_kill_switch);
+        if kill_switch_enabled {
+            info!("Lifting kill switch");
+            if let Err(err) = (self.disable_kill_switch)().await {
+                error!("Failed to lift kill switch: {err}");
+            }
+        }
+    }
+
     async fn handle_status(&self) -> VpnServiceStatusResult {
         self.shared_vpn_state.get().into()
     }
 
+    fn handle_get_traffic_stats(&self) -> Option<TrafficStatsHandle> {
+        if !matches!(self.shared_vpn_state.get(), VpnState::Connected(_)) {
+            return None;
+        }
+        self.traffic_stats.clone()
+    }
+
+    fn handle_get_dns_filter_stats(&self) -> Option<DnsFilterStats> {
+        if !matches!(self.shared_vpn_state.get(), VpnState::Connected(_)) {
+            return None;
+        }
+        self.dns_filter_stats
+            .as_ref()
+            .map(DnsFilterStatsHandle::snapshot)
+    }
+
+    // The test runs in the vpn task and takes a few seconds, so we wait for the report on the side
+    // to not hold up other commands meanwhile
+    async fn handle_run_leak_test(&mut self, tx: oneshot::Sender<Option<LeakTestReport>>) {
+        if !matches!(self.shared_vpn_state.get(), VpnState::Connected(_)) {
+            tx.send(None).ok();
+            return;
+        }
+        let Some(ref mut vpn_ctrl_sender) = self.vpn_ctrl_sender else {
+            tx.send(None).ok();
+            return;
+        };
+        let (report_tx, report_rx) = oneshot::channel();
+        vpn_ctrl_sender
+            .send(nym_vpn_lib::NymVpnCtrlMessage::RunLeakTest(report_tx))
+            .await
+            .ok();
+        // No report when the tunnel goes down before the test finishes
+        tokio::spawn(async move {
+            tx.send(report_rx.await.ok()).ok();
+        });
+    }
+
     async fn handle_info(&self) -> VpnServiceInfoResult {
         let network = NymNetworkDetails::new_from_env();
         let bin_info = nym_bin_common::bin_info_local_vergen!();
@@ -740,12 +1219,139 @@ where
             .map_err(Into::into)
     }
 
+    fn load_config(&self) -> Result<NymVpnServiceConfig, SettingsError> {
+        if !self.config_file.exists() {
+            return Ok(NymVpnServiceConfig::default());
+        }
+        read_config_file(&self.config_file).map_err(|err| SettingsError::ReadConfig {
+            reason: err.to_string(),
+        })
+    }
+
+    // Changes to the stored config take effect on the next connect
+    fn update_config(
+        &self,
+        update: impl FnOnce(&mut NymVpnServiceConfig),
+    ) -> Result<NymVpnServiceConfig, SettingsError> {
+        let mut config = self.load_config()?;
+        update(&mut config);
+        let result = if self.config_file.exists() {
+            write_config_file(&self.config_file, &config).map(|_| config)
+        } else {
+            create_config_file(&self.config_file, config)
+        };
+        result.map_err(|err| SettingsError::WriteConfig {
+            reason: err.to_string(),
+        })
+    }
+
+    fn handle_get_settings(&self) -> Result<VpnSettings, SettingsError> {
+        self.load_config().map(|config| config.settings)
+    }
+
+    fn handle_set_settings(&self, settings: VpnSettings) -> Result<(), SettingsError> {
+        info!("Setting settings: {settings:?}");
+        settings
+            .validate()
+            .map_err(|err| SettingsError::InvalidSettings {
+                reason: err.to_string(),
+            })?;
+        // Custom traffic profiles are defined in the stored config
+        self.load_config()?
+            .traffic_profile(settings.traffic_profile.as_deref())
+            .map_err(|err| SettingsError::InvalidSettings {
+                reason: err.to_string(),
+            })?;
+        self.update_config(|config| config.settings = settings)
+            .map(|_| ())
+    }
+
+    fn handle_reset_settings(&self) -> Result<VpnSettings, SettingsError> {
+        info!("Resetting settings to defaults");
+        self.update_config(|config| config.settings = VpnSettings::default())
+            .map(|config| config.settings)
+    }
+
+    fn handle_get_split_tunnel(&self) -> Result<SplitTunnelConfig, SplitTunnelError> {
+        if !self.config_file.exists() {
+            return Ok(SplitTunnelConfig::default());
+        }
+        read_config_file(&self.config_file)
+            .map(|config| config.split_tunnel)
+            .map_err(|err| SplitTunnelError::ReadConfig {
+                reason: err.to_string(),
+            })
+    }
+
+    // The new split tunnel config is persisted and takes effect on the next connect
+    fn handle_set_split_tunnel(
+        &self,
+        split_tunnel: SplitTunnelConfig,
+    ) -> Result<(), SplitTunnelError> {
+        info!("Setting split tunnel config: {split_tunnel}");
+        if self.config_file.exists() {
+            let mut config = read_config_file(&self.config_file).map_err(|err| {
+                SplitTunnelError::ReadConfig {
+                    reason: err.to_string(),
+                }
+            })?;
+            config.split_tunnel = split_tunnel;
+            write_config_file(&self.config_file, &config)
+        } else {
+            let config = NymVpnServiceConfig {
+                split_tunnel,
+                ..Default::default()
+            };
+            create_config_file(&self.config_file, config).map(|_| ())
+        }
+        .map_err(|err| SplitTunnelError::WriteConfig {
+            reason: err.to_string(),
+        })
+    }
+
+    fn dns_blocklist_dir(&self) -> PathBuf {
+        self.config_file.with_file_name(config::DNS_BLOCKLIST_DIR)
+    }
+
+    fn handle_get_dns_filter(&self) -> Result<DnsFilterConfig, SettingsError> {
+        self.load_config().map(|config| config.dns_filter)
+    }
+
+    fn handle_set_dns_filter(&self, dns_filter: DnsFilterConfig) -> Result<(), SettingsError> {
+        info!("Setting dns filter config: {dns_filter:?}");
+        dns_filter
+            .validate()
+            .map_err(|reason| SettingsError::InvalidSettings { reason })?;
+        config::resolve_dns_blocklist_paths(dns_filter.clone(), &self.dns_blocklist_dir())
+            .map_err(|reason| SettingsError::InvalidSettings { reason })?;
+        self.update_config(|config| config.dns_filter = dns_filter)
+            .map(|_| ())
+    }
+
     pub(crate) async fn run(mut self) -> anyhow::Result<()>
     where
         <S as nym_vpn_store::mnemonic::MnemonicStorage>::StorageError: Sync + Send + 'static,
         <S as nym_vpn_store::keys::KeyStore>::StorageError: Sync + Send + 'static,
     {
-        while let Some(command) = self.vpn_command_rx.recv().await {
+        loop {
+            let command = tokio::select! {
+                command = self.vpn_command_rx.recv() => match command {
+                    Some(command) => command,
+                    None => break,
+                },
+                Some(failure) = self.exit_failure_rx.recv() => {
+                    self.handle_vpn_exit_failure(failure);
+                    continue;
+                }
+                Some(switched) = self.switched_gateways_rx.recv() => {
+                    self.handle_gateways_switched(switched);
+                    continue;
+                }
+                _ = wait_for_pending_reconnect(self.pending_reconnect) => {
+                    self.handle_reconnect().await;
+                    continue;
+                }
+            };
             debug!("VPN: Received command: {command}");
             match command {
                 VpnServiceCommand::Connect(tx, connect_args) => {
@@ -756,10 +1362,26 @@ where
                     let result = self.handle_disconnect().await;
                     tx.send(result).unwrap();
                 }
+                VpnServiceCommand::SwitchGateway(tx, entry, exit) => {
+                    let result = self.handle_switch_gateway(entry, exit).await;
+                    tx.send(result).unwrap();
+                }
                 VpnServiceCommand::Status(tx) => {
                     let result = self.handle_status().await;
                     tx.send(result).unwrap();
                 }
+                VpnServiceCommand::GetTrafficStats(tx) => {
+                    let result = self.handle_get_traffic_stats();
+                    // Polled by the stats stream, which goes away when the client disconnects
+                    tx.send(result).ok();
+                }
+                VpnServiceCommand::GetDnsFilterStats(tx) => {
+                    let result = self.handle_get_dns_filter_stats();
+                    tx.send(result).unwrap();
+                }
+                VpnServiceCommand::RunLeakTest(tx) => {
+                    self.handle_run_leak_test(tx).await;
+                }
                 VpnServiceCommand::Info(tx) => {
                     let result = self.handle_info().await;
                     tx.send(result).unwrap();
@@ -788,6 +1410,34 @@ where
                     let result = self.handle_get_device_zk_nyms().await;
                     tx.send(result).unwrap();
                 }
+                VpnServiceCommand::GetSettings(tx) => {
+                    let result = self.handle_get_settings();
+                    tx.send(result).unwrap();
+                }
+                VpnServiceCommand::SetSettings(tx, settings) => {
+                    let result = self.handle_set_settings(settings);
+                    tx.send(result).unwrap();
+                }
+                VpnServiceCommand::ResetSettings(tx) => {
+                    let result = self.handle_reset_settings();
+                    tx.send(result).unwrap();
+                }
+                VpnServiceCommand::GetSplitTunnel(tx) => {
+                    let result = self.handle_get_split_tunnel();
+                    tx.send(result).unwrap();
+                }
+                VpnServiceCommand::SetSplitTunnel(tx, split_tunnel) => {
+                    let result = self.handle_set_split_tunnel(split_tunnel);
+                    tx.send(result).unwrap();
+                }
+                VpnServiceCommand::GetDnsFilter(tx) => {
+                    let result = self.handle_get_dns_filter();
+                    tx.send(result).unwrap();
+                }
+                VpnServiceCommand::SetDnsFilter(tx, dns_filter) => {
+                    let result = self.handle_set_dns_filter(dns_filter);
+                    tx.send(result).unwrap();
+                }
                 VpnServiceCommand::Shutdown => {
                     let result = self.handle_disconnect().await;
                     info!("VPN: Shutting down: {:?}", result);
@@ -802,6 +1452,13 @@ where
     }
 }
 
+async fn wait_for_pending_reconnect(pending_reconnect: Option<tokio::time::Instant>) {
+    match pending_reconnect {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
 fn get_nym_vpn_api_url() -> Result<Url, AccountError> {
     NymNetworkDetails::new_from_env()
         .nym_vpn_api_url
@@ -809,3 +1466,173 @@ fn get_nym_vpn_api_url() -> Result<Url, AccountError> {
         .parse()
         .map_err(|_| AccountError::InvalidApiUrl)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    thread_local! {
+        static KILL_SWITCH_LIFTS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn count_kill_switch_lift() -> BoxFuture<'static, Result<(), nym_vpn_lib::Error>> {
+        KILL_SWITCH_LIFTS.with(|lifts| lifts.set(lifts.get() + 1));
+        async { Ok(()) }.boxed()
+    }
+
+    fn kill_switch_lifts() -> usize {
+        KILL_SWITCH_LIFTS.with(Cell::get)
+    }
+
+    // A service that was asked to connect, with the kill switch on, and is now connecting
+    fn connecting_service(
+        reconnect_policy: ReconnectPolicy,
+    ) -> NymVpnService<nym_vpn_lib::storage::VpnClientOnDiskStorage> {
+        let data_dir = PathBuf::from("/nonexistent/nym-vpnd");
+        let (vpn_state_changes_tx, _) = broadcast::channel(16);
+        let (_, vpn_command_rx) = tokio_mpsc::unbounded_channel();
+        let (exit_failure_tx, exit_failure_rx) = tokio_mpsc::unbounded_channel();
+        let (switched_gateways_tx, switched_gateways_rx) = tokio_mpsc::unbounded_channel();
+        let service = NymVpnService {
+            shared_vpn_state: SharedVpnState::new(vpn_state_changes_tx),
+            vpn_command_rx,
+            vpn_ctrl_sender: None,
+            traffic_stats: None,
+            dns_filter_stats: None,
+            exit_failure_tx,
+            exit_failure_rx,
+            last_connect_args: Some(ConnectArgs {
+                entry: None,
+                exit: None,
+                options: ConnectOptions::default(),
+            }),
+            last_connect_settings: Some(VpnSettings {
+                enable_kill_switch: true,
+                ..Default::default()
+            }),
+            listener_vpn_status_tx: None,
+            reconnect_policy,
+            reconnect_attempt: 0,
+            pending_reconnect: None,
+            switched_gateways_tx,
+            switched_gateways_rx,
+            disable_kill_switch: count_kill_switch_lift,
+            config_file: data_dir.join(DEFAULT_CONFIG_FILE),
+            data_dir: data_dir.clone(),
+            storage: nym_vpn_lib::storage::VpnClientOnDiskStorage::new(data_dir),
+        };
+        service.shared_vpn_state.set(VpnState::Connecting);
+        service
+    }
+
+    fn fail(service: &mut NymVpnService<nym_vpn_lib::storage::VpnClientOnDiskStorage>) {
+        service.handle_vpn_exit_failure(VpnServiceExitFailure {
+            error: ConnectionFailedError::StartMixnetTimeout(30),
+        });
+    }
+
+    fn reconnect_attempt(state: VpnState) -> Option<u32> {
+        match state {
+            VpnState::Reconnecting { attempt, .. } => Some(attempt),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn failures_schedule_reconnects_until_giving_up() {
+        let policy = ReconnectPolicy {
+            max_attempts: Some(2),
+            initial_delay_secs: 2,
+            ..Default::default()
+        };
+        let mut service = connecting_service(policy);
+
+        fail(&mut service);
+        assert_eq!(reconnect_attempt(service.shared_vpn_state.get()), Some(1));
+        let first_retry = service.pending_reconnect.unwrap();
+
+        // The reconnect is underway when it fails again, and the next one backs off further
+        service.pending_reconnect = None;
+        service.shared_vpn_state.set(VpnState::Connecting);
+        fail(&mut service);
+        assert_eq!(reconnect_attempt(service.shared_vpn_state.get()), Some(2));
+        assert!(service.pending_reconnect.unwrap() > first_retry);
+
+        service.pending_reconnect = None;
+        service.shared_vpn_state.set(VpnState::Connecting);
+        fail(&mut service);
+        assert!(matches!(
+            service.shared_vpn_state.get(),
+            VpnState::ConnectionFailed(ConnectionFailedError::StartMixnetTimeout(30))
+        ));
+        assert!(service.pending_reconnect.is_none());
+    }
+
+    #[tokio::test]
+    async fn errors_that_are_not_retryable_are_final() {
+        let mut service = connecting_service(ReconnectPolicy::default());
+        service.handle_vpn_exit_failure(VpnServiceExitFailure {
+            error: ConnectionFailedError::OutOfBandwidth,
+        });
+        assert!(matches!(
+            service.shared_vpn_state.get(),
+            VpnState::ConnectionFailed(ConnectionFailedError::OutOfBandwidth)
+        ));
+        assert!(service.pending_reconnect.is_none());
+    }
+
+    #[tokio::test]
+    async fn failures_while_disconnecting_are_final() {
+        let mut service = connecting_service(ReconnectPolicy::default());
+        service.shared_vpn_state.set(VpnState::Disconnecting);
+        fail(&mut service);
+        assert!(matches!(
+            service.shared_vpn_state.get(),
+            VpnState::ConnectionFailed(_)
+        ));
+        assert!(service.pending_reconnect.is_none());
+    }
+
+    #[tokio::test]
+    async fn disconnect_cancels_the_pending_reconnect_and_lifts_the_kill_switch() {
+        let mut service = connecting_service(ReconnectPolicy::default());
+        fail(&mut service);
+        assert!(service.pending_reconnect.is_some());
+
+        let lifts = kill_switch_lifts();
+        assert!(service.handle_disconnect().await.is_success());
+        assert!(service.pending_reconnect.is_none());
+        assert!(matches!(
+            service.shared_vpn_state.get(),
+            VpnState::NotConnected
+        ));
+        assert_eq!(kill_switch_lifts(), lifts + 1);
+    }
+
+    #[tokio::test]
+    async fn disconnect_after_giving_up_lifts_the_kill_switch() {
+        let policy = ReconnectPolicy {
+            enabled: false,
+            ..Default::default()
+        };
+        let mut service = connecting_service(policy);
+        fail(&mut service);
+        assert!(matches!(
+            service.shared_vpn_state.get(),
+            VpnState::ConnectionFailed(_)
+        ));
+
+        let lifts = kill_switch_lifts();
+        assert!(service.handle_disconnect().await.is_success());
+        assert!(matches!(
+            service.shared_vpn_state.get(),
+            VpnState::NotConnected
+        ));
+        assert_eq!(kill_switch_lifts(), lifts + 1);
+
+        // Nothing is left to disconnect from
+        assert!(!service.handle_disconnect().await.is_success());
+    }
+}
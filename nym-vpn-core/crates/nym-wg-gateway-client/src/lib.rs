@@ -96,6 +96,22 @@ impl WgGatewayClient {
         )
     }
 
+    // Hops in between the entry and the exit each get their own keys, numbered from the entry
+    pub fn new_middle(
+        data_path: &Option<PathBuf>,
+        auth_client: AuthClient,
+        auth_recipient: Recipient,
+        index: usize,
+    ) -> Self {
+        Self::new_type(
+            data_path,
+            auth_client,
+            auth_recipient,
+            &format!("private_middle_{index}_wireguard.pem"),
+            &format!("public_middle_{index}_wireguard.pem"),
+        )
+    }
+
     pub fn new_exit(
         data_path: &Option<PathBuf>,
         auth_client: AuthClient,
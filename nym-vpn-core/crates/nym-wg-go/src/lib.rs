@@ -1,11 +1,16 @@
 // Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
 // SPDX-License-Identifier: Apache-2.0
 
-#![cfg(any(target_os = "ios", target_os = "android"))]
+#![cfg(any(target_os = "ios", target_os = "android", target_os = "linux"))]
 
 mod logging;
 pub mod netstack;
+#[cfg(target_os = "linux")]
+pub mod packet_tunnel;
 pub mod uapi;
+// The desktop build of libwg exports its own flavour of wgTurnOn, so on linux only the netstack
+// and packet tunnels are available.
+#[cfg(any(target_os = "ios", target_os = "android"))]
 pub mod wireguard_go;
 
 use std::{fmt, net::SocketAddr};
@@ -23,7 +28,7 @@ pub enum Error {
     #[error("config contains nul byte")]
     ConfigContainsNulByte,
 
-    #[error("failed to start netstack tunnel (code: {})", _0)]
+    #[error("failed to start tunnel (code: {})", _0)]
     StartTunnel(i32),
 
     #[error("failed to open connection through the tunnel (code: {})", _0)]
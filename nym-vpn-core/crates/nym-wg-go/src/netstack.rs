@@ -16,6 +16,7 @@ use super::{
 
 /// Netstack interface configuration.
 pub struct InterfaceConfig {
+    pub listen_port: Option<u16>,
     pub private_key: PrivateKey,
     pub local_addrs: Vec<IpAddr>,
     pub dns_addrs: Vec<IpAddr>,
@@ -25,6 +26,7 @@ pub struct InterfaceConfig {
 impl fmt::Debug for InterfaceConfig {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("InterfaceConfig")
+            .field("listen_port", &self.listen_port)
             .field("private_key", &"(hidden)")
             .field("local_addrs", &self.local_addrs)
             .field("dns_addrs", &self.dns_addrs)
@@ -48,6 +50,10 @@ impl Config {
             self.interface.private_key.to_bytes().as_ref(),
         );
 
+        if let Some(listen_port) = self.interface.listen_port {
+            config_builder.add("listen_port", listen_port.to_string().as_str());
+        }
+
         if !self.peers.is_empty() {
             config_builder.add("replace_peers", "true");
             for peer in self.peers.iter() {
@@ -0,0 +1,251 @@
+// Copyright 2024 - Nym Technologies SA <contact@nymtech.net>
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    ffi::{c_char, c_void, CString},
+    fmt,
+    os::fd::{FromRawFd, OwnedFd},
+};
+
+use super::{uapi::UapiConfigBuilder, Error, LoggingCallback, PeerConfig, PrivateKey, Result};
+
+/// Packet tunnel interface configuration.
+pub struct InterfaceConfig {
+    pub listen_port: Option<u16>,
+    pub private_key: PrivateKey,
+    pub mtu: u16,
+}
+
+impl fmt::Debug for InterfaceConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("InterfaceConfig")
+            .field("listen_port", &self.listen_port)
+            .field("private_key", &"(hidden)")
+            .field("mtu", &self.mtu)
+            .finish()
+    }
+}
+
+/// Packet tunnel configuration.
+#[derive(Debug)]
+pub struct Config {
+    pub interface: InterfaceConfig,
+    pub peers: Vec<PeerConfig>,
+}
+
+impl Config {
+    fn as_uapi_config(&self) -> Vec<u8> {
+        let mut config_builder = UapiConfigBuilder::new();
+        config_builder.add(
+            "private_key",
+            self.interface.private_key.to_bytes().as_ref(),
+        );
+
+        if let Some(listen_port) = self.interface.listen_port {
+            config_builder.add("listen_port", listen_port.to_string().as_str());
+        }
+
+        if !self.peers.is_empty() {
+            config_builder.add("replace_peers", "true");
+            for peer in self.peers.iter() {
+                peer.append_to(&mut config_builder);
+            }
+        }
+
+        config_builder.into_bytes()
+    }
+}
+
+/// WireGuard tunnel without a tun device.
+///
+/// The IP packets going through the tunnel are exchanged over a local datagram socket instead,
+/// one packet per datagram, which makes it usable without privileges.
+#[derive(Debug)]
+pub struct Tunnel {
+    handle: i32,
+    boxed_logger_ptr: *mut Box<dyn Fn(&str)>,
+}
+
+// *mut Box is safe to send
+unsafe impl Send for Tunnel {}
+
+impl Tunnel {
+    pub fn start<F>(config: Config, logger: F) -> Result<Self>
+    where
+        F: Fn(&str) + 'static,
+    {
+        let settings =
+            CString::new(config.as_uapi_config()).map_err(|_| Error::ConfigContainsNulByte)?;
+
+        let boxed_logger_ptr = unsafe { super::logging::create_logger_callback(logger) };
+        let handle = unsafe {
+            wgPacketTurnOn(
+                i32::from(config.interface.mtu),
+                settings.as_ptr(),
+                Some(super::logging::wg_logger_callback),
+                boxed_logger_ptr as *mut _,
+            )
+        };
+
+        if handle >= 0 {
+            Ok(Self {
+                handle,
+                boxed_logger_ptr,
+            })
+        } else {
+            Err(Error::StartTunnel(handle))
+        }
+    }
+
+    /// Get the socket the IP packets of the tunnel are exchanged over.
+    ///
+    /// Every call returns a new descriptor for the same socket.
+    pub fn packet_socket(&self) -> Result<OwnedFd> {
+        let fd = unsafe { wgPacketGetSocket(self.handle) };
+        if fd >= 0 {
+            // SAFETY: the descriptor is a duplicate that nobody else owns
+            Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+        } else {
+            Err(Error::ObtainSocketFd)
+        }
+    }
+
+    /// Stop the tunnel.
+    pub fn stop(mut self) {
+        self.stop_inner();
+    }
+
+    fn stop_inner(&mut self) {
+        if self.handle >= 0 {
+            unsafe { wgPacketTurnOff(self.handle) };
+            self.handle = -1;
+        }
+        if !self.boxed_logger_ptr.is_null() {
+            self.boxed_logger_ptr = std::ptr::null_mut();
+        }
+    }
+}
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        self.stop_inner()
+    }
+}
+
+extern "C" {
+    fn wgPacketTurnOn(
+        mtu: i32,
+        settings: *const c_char,
+        logging_callback: Option<LoggingCallback>,
+        logging_context: *mut c_void,
+    ) -> i32;
+    fn wgPacketTurnOff(handle: i32);
+    fn wgPacketGetSocket(handle: i32) -> i32;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, SocketAddr, UdpSocket},
+        os::unix::net::UnixDatagram,
+        time::Duration,
+    };
+
+    use ipnetwork::IpNetwork;
+
+    use super::*;
+
+    const ADDR_A: Ipv4Addr = Ipv4Addr::new(10, 64, 0, 1);
+    const ADDR_B: Ipv4Addr = Ipv4Addr::new(10, 64, 0, 2);
+
+    fn free_port() -> u16 {
+        UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+            .and_then(|socket| socket.local_addr())
+            .map(|addr| addr.port())
+            .unwrap()
+    }
+
+    fn config(private_key: &PrivateKey, listen_port: u16, peer: PeerConfig) -> Config {
+        Config {
+            interface: InterfaceConfig {
+                listen_port: Some(listen_port),
+                private_key: private_key.clone(),
+                mtu: 1420,
+            },
+            peers: vec![peer],
+        }
+    }
+
+    // An IPv4/UDP packet, which is all wireguard-go looks at
+    fn udp_packet(source: Ipv4Addr, destination: Ipv4Addr, payload: &[u8]) -> Vec<u8> {
+        let total_len = (20 + 8 + payload.len()) as u16;
+        let mut packet = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, 17, 0, 0];
+        packet[2..4].copy_from_slice(&total_len.to_be_bytes());
+        packet.extend_from_slice(&source.octets());
+        packet.extend_from_slice(&destination.octets());
+        let checksum = !packet
+            .chunks(2)
+            .map(|word| u32::from(u16::from_be_bytes([word[0], word[1]])))
+            .fold(0u32, |sum, word| {
+                let sum = sum + word;
+                (sum & 0xffff) + (sum >> 16)
+            }) as u16;
+        packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+        packet.extend_from_slice(&5000u16.to_be_bytes());
+        packet.extend_from_slice(&5000u16.to_be_bytes());
+        packet.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    // Two tunnels peered with each other over loopback: what goes into the packet socket of one
+    // comes out of the packet socket of the other
+    #[test]
+    fn packets_go_through_the_tunnel() {
+        let key_a = PrivateKey::from([1u8; 32]);
+        let key_b = PrivateKey::from([2u8; 32]);
+        let (port_a, port_b) = (free_port(), free_port());
+
+        let tunnel_a = Tunnel::start(
+            config(
+                &key_a,
+                port_a,
+                PeerConfig {
+                    public_key: key_b.public_key(),
+                    preshared_key: None,
+                    endpoint: SocketAddr::from((Ipv4Addr::LOCALHOST, port_b)),
+                    allowed_ips: vec![IpNetwork::V4(ADDR_B.into())],
+                },
+            ),
+            |_| {},
+        )
+        .unwrap();
+        let tunnel_b = Tunnel::start(
+            config(
+                &key_b,
+                port_b,
+                PeerConfig {
+                    public_key: key_a.public_key(),
+                    preshared_key: None,
+                    endpoint: SocketAddr::from((Ipv4Addr::LOCALHOST, port_a)),
+                    allowed_ips: vec![IpNetwork::V4(ADDR_A.into())],
+                },
+            ),
+            |_| {},
+        )
+        .unwrap();
+
+        let socket_a = UnixDatagram::from(tunnel_a.packet_socket().unwrap());
+        let socket_b = UnixDatagram::from(tunnel_b.packet_socket().unwrap());
+        socket_b
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+
+        let packet = udp_packet(ADDR_A, ADDR_B, b"through the tunnel");
+        socket_a.send(&packet).unwrap();
+        let mut buf = [0u8; 1500];
+        let len = socket_b.recv(&mut buf).unwrap();
+        assert_eq!(buf[..len], packet);
+    }
+}
@@ -38,9 +38,9 @@ pub struct Cli {
     #[arg(short = 'e', long)]
     pub grpc_http_endpoint: Option<String>,
 
-    /// IP address of the DNS server to use when connected to the VPN
+    /// IP address of the DNS server to use when connected to the VPN, can be given more than once
     #[arg(short = 'D', long)]
-    pub dns: Option<String>,
+    pub dns: Vec<String>,
 
     /// Enable writing app logs to a file
     #[arg(short, long)]
@@ -83,10 +83,20 @@ pub async fn connect(
         ));
     }
 
-    let dns = app_state
-        .dns_server
-        .clone()
-        .map(|ip| nym_vpn_proto::Dns { ip });
+    let dns = (!app_state.dns_servers.is_empty() || !app_state.split_dns.is_empty()).then(|| {
+        nym_vpn_proto::Dns {
+            ip: String::new(),
+            servers: app_state.dns_servers.clone(),
+            split_rules: app_state
+                .split_dns
+                .iter()
+                .map(|rule| nym_vpn_proto::SplitDnsRule {
+                    domain: rule.domain.clone(),
+                    servers: rule.servers.clone(),
+                })
+                .collect(),
+        }
+    });
     // release the lock
     drop(app_state);
 
@@ -129,6 +129,8 @@ enum StatusUpdate {
     ConnectionOkIpv6,
     RemainingBandwidth,
     NoBandwidth,
+    ConnectionQuality,
+    ExitGatewayFailover,
 }
 
 #[derive(Clone, Serialize, TS)]
@@ -188,6 +190,8 @@ impl From<ConnectionStatusUpdate> for StatusUpdatePayload {
                 StatusType::ConnectionOkIpv6 => StatusUpdate::ConnectionOkIpv6,
                 StatusType::RemainingBandwidth => StatusUpdate::RemainingBandwidth,
                 StatusType::NoBandwidth => StatusUpdate::NoBandwidth,
+                StatusType::ConnectionQuality => StatusUpdate::ConnectionQuality,
+                StatusType::ExitGatewayFailover => StatusUpdate::ExitGatewayFailover,
                 _ => StatusUpdate::Unknown,
             },
             message: update.message.clone(),
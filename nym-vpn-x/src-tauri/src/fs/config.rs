@@ -14,4 +14,16 @@ pub struct AppConfig {
     pub grpc_http_endpoint: Option<String>,
     /// IP address of the DNS server to use when connected to the VPN
     pub dns_server: Option<String>,
+    /// IP addresses of more DNS servers, in order of preference, used after `dns_server`
+    pub dns_servers: Option<Vec<String>>,
+    /// Domains resolved by other DNS servers than the VPN ones, e.g. internal servers on the LAN
+    pub split_dns: Option<Vec<SplitDnsRule>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SplitDnsRule {
+    /// The domain, e.g. `corp.example` or `*.corp.example`, including all names below it
+    pub domain: String,
+    /// IP addresses of the DNS servers for the domain
+    pub servers: Vec<String>,
 }
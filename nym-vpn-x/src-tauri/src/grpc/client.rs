@@ -300,14 +300,20 @@ impl GrpcClient {
         let request = Request::new(ConnectRequest {
             entry: Some(entry_node),
             exit: Some(exit_node),
-            disable_routing: false,
-            enable_two_hop: two_hop_mod,
-            enable_poisson_rate: false,
-            disable_background_cover_traffic: false,
-            enable_credentials_mode: false,
+            disable_routing: None,
+            enable_two_hop: Some(two_hop_mod),
+            enable_poisson_rate: None,
+            disable_background_cover_traffic: None,
+            enable_credentials_mode: None,
             dns,
             min_mixnode_performance: None,
             min_gateway_performance: None,
+            enable_kill_switch: None,
+            kill_switch_allow_lan: None,
+            wireguard_hop_count: None,
+            enable_dns_filter: None,
+            traffic_profile: None,
+            encrypted_dns: None,
         });
         let response = vpnd.vpn_connect(request).await.map_err(|e| {
             error!("grpc vpn_connect: {}", e);
@@ -9,7 +9,7 @@ use crate::{
     cli::Cli,
     country::Country,
     db::{Db, Key},
-    fs::config::AppConfig,
+    fs::config::{AppConfig, SplitDnsRule},
     grpc::client::VpndStatus,
 };
 
@@ -41,7 +41,8 @@ pub struct AppState {
     pub state: ConnectionState,
     pub vpn_mode: VpnMode,
     pub connection_start_time: Option<OffsetDateTime>,
-    pub dns_server: Option<String>,
+    pub dns_servers: Vec<String>,
+    pub split_dns: Vec<SplitDnsRule>,
 }
 
 impl AppState {
@@ -52,12 +53,22 @@ impl AppState {
             .ok()
             .flatten()
             .unwrap_or_default();
-        let dns_server: Option<String> = cli.dns.clone().or(config.dns_server.clone());
+        let dns_servers: Vec<String> = if cli.dns.is_empty() {
+            config
+                .dns_server
+                .iter()
+                .chain(config.dns_servers.iter().flatten())
+                .cloned()
+                .collect()
+        } else {
+            cli.dns.clone()
+        };
 
         // restore any state from the saved app data (previous user session)
         AppState {
             vpn_mode,
-            dns_server,
+            dns_servers,
+            split_dns: config.split_dns.clone().unwrap_or_default(),
             ..Default::default()
         }
     }
@@ -93,6 +104,8 @@ impl From<ConnectionStatus> for ConnectionState {
             // this variant means "Not connected, but with an error"
             // so it should be treated as disconnected
             ConnectionStatus::ConnectionFailed => ConnectionState::Disconnected,
+            // the daemon is waiting to retry after the tunnel dropped
+            ConnectionStatus::Reconnecting => ConnectionState::Connecting,
         }
     }
 }
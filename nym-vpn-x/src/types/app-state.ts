@@ -117,7 +117,9 @@ export type StatusUpdate =
   | 'ConnectionOkIpv4'
   | 'ConnectionOkIpv6'
   | 'RemainingBandwidth'
-  | 'NoBandwidth';
+  | 'NoBandwidth'
+  | 'ConnectionQuality'
+  | 'ExitGatewayFailover';
 
 export type StatusUpdatePayload = {
   status: StatusUpdate;
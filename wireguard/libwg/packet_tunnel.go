@@ -0,0 +1,181 @@
+//go:build linux && !android
+
+/* SPDX-License-Identifier: MIT
+ *
+ * Copyright (C) 2024 Nym Technologies SA <contact@nymtech.net>. All Rights Reserved.
+ */
+
+package main
+
+import "C"
+
+import (
+	"os"
+	"sync"
+
+	"github.com/nymtech/nym-vpn-client/wireguard/libwg/container"
+	"github.com/nymtech/nym-vpn-client/wireguard/libwg/logging"
+	"golang.org/x/sys/unix"
+	"golang.zx2c4.com/wireguard/conn"
+	"golang.zx2c4.com/wireguard/device"
+	"golang.zx2c4.com/wireguard/tun"
+)
+
+// A tun device whose packets are exchanged with the caller over a datagram socket, one packet
+// per datagram, so that the tunnel needs neither a real tun interface nor privileges.
+type packetTUN struct {
+	file      *os.File
+	mtu       int
+	events    chan tun.Event
+	closeOnce sync.Once
+}
+
+func (t *packetTUN) File() *os.File {
+	return t.file
+}
+
+func (t *packetTUN) Read(bufs [][]byte, sizes []int, offset int) (int, error) {
+	n, err := t.file.Read(bufs[0][offset:])
+	if err != nil {
+		return 0, err
+	}
+	sizes[0] = n
+	return 1, nil
+}
+
+func (t *packetTUN) Write(bufs [][]byte, offset int) (int, error) {
+	for i, buf := range bufs {
+		if _, err := t.file.Write(buf[offset:]); err != nil {
+			return i, err
+		}
+	}
+	return len(bufs), nil
+}
+
+func (t *packetTUN) MTU() (int, error) {
+	return t.mtu, nil
+}
+
+func (t *packetTUN) Name() (string, error) {
+	return "packet", nil
+}
+
+func (t *packetTUN) Events() <-chan tun.Event {
+	return t.events
+}
+
+func (t *packetTUN) Close() error {
+	var err error
+	t.closeOnce.Do(func() {
+		err = t.file.Close()
+		close(t.events)
+	})
+	return err
+}
+
+func (t *packetTUN) BatchSize() int {
+	return 1
+}
+
+type packetTunnelHandle struct {
+	*device.Device
+	*device.Logger
+	// The caller's end of the packet socket
+	packetFd int
+}
+
+var packetTunnelHandles container.Container[packetTunnelHandle]
+
+func init() {
+	packetTunnelHandles = container.New[packetTunnelHandle]()
+}
+
+//export wgPacketTurnOn
+func wgPacketTurnOn(mtu int, settings *C.char, logSink LogSink, logContext LogContext) int32 {
+	logger := logging.NewLogger(logSink, logContext)
+
+	fds, err := unix.Socketpair(unix.AF_UNIX, unix.SOCK_DGRAM|unix.SOCK_CLOEXEC, 0)
+	if err != nil {
+		logger.Errorf("Failed to create packet socket: %v", err)
+		return ERROR_GENERAL_FAILURE
+	}
+	if err = unix.SetNonblock(fds[0], true); err != nil {
+		logger.Errorf("Failed to make packet socket non-blocking: %v", err)
+		unix.Close(fds[0])
+		unix.Close(fds[1])
+		return ERROR_GENERAL_FAILURE
+	}
+
+	packetTun := &packetTUN{
+		file:   os.NewFile(uintptr(fds[0]), "packet"),
+		mtu:    mtu,
+		events: make(chan tun.Event, 1),
+	}
+	packetTun.events <- tun.EventUp
+
+	dev := device.NewDevice(
+		packetTun,
+		conn.NewDefaultBind(),
+		logger,
+	)
+	if dev == nil {
+		logger.Errorf("Failed to create device")
+		packetTun.Close()
+		unix.Close(fds[1])
+		return ERROR_GENERAL_FAILURE
+	}
+
+	err = dev.IpcSet(C.GoString(settings))
+	if err != nil {
+		logger.Errorf("Unable to set IPC settings: %v", err)
+		dev.Close()
+		unix.Close(fds[1])
+		return ERROR_GENERAL_FAILURE
+	}
+
+	err = dev.Up()
+	if err != nil {
+		logger.Errorf("Failed to set device state to Up: %v", err)
+		dev.Close()
+		unix.Close(fds[1])
+		return ERROR_GENERAL_FAILURE
+	}
+
+	logger.Verbosef("Packet device started")
+
+	i, err := packetTunnelHandles.Insert(packetTunnelHandle{dev, logger, fds[1]})
+	if err != nil {
+		logger.Errorf("Failed to store tunnel: %v", err)
+		dev.Close()
+		unix.Close(fds[1])
+		return ERROR_GENERAL_FAILURE
+	}
+
+	return i
+}
+
+//export wgPacketTurnOff
+func wgPacketTurnOff(tunnelHandle int32) {
+	dev, err := packetTunnelHandles.Remove(tunnelHandle)
+	if err != nil {
+		return
+	}
+	dev.Close()
+	unix.Close(dev.packetFd)
+}
+
+// Returns a duplicate of the caller's end of the packet socket, which the caller owns.
+//
+//export wgPacketGetSocket
+func wgPacketGetSocket(tunnelHandle int32) int32 {
+	dev, err := packetTunnelHandles.Get(tunnelHandle)
+	if err != nil {
+		return ERROR_GENERAL_FAILURE
+	}
+	fd, err := unix.FcntlInt(uintptr(dev.packetFd), unix.F_DUPFD_CLOEXEC, 0)
+	if err != nil {
+		dev.Errorf("Failed to duplicate packet socket: %v", err)
+		return ERROR_GENERAL_FAILURE
+	}
+	return int32(fd)
+}